@@ -0,0 +1,60 @@
+//! Result conversion with and without an arena: `cargo bench --bench arena`
+//!
+//! The arena only takes the conversion of the evaluated value into a `CelEvalResult`;
+//! evaluation allocates on the global allocator in both modes, so the difference
+//! between them is the cost of that conversion. Each expression returns many strings,
+//! each its own allocation and string table entry without an arena, and `scalar`
+//! converts nothing worth arena allocation, as a baseline.
+
+use cel_lua::{
+    cel_arena_free, cel_arena_new, cel_result_free, program_execute_alloc,
+    program_execute_with_options, CelExecuteOptions, Context, Program,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::hint::black_box;
+
+const EXPRESSIONS: [(&str, &str); 3] = [
+    ("list", "names.map(n, n + '@example.com')"),
+    ("map", "{'first': names[0], 'all': names, 'upper': names.map(n, n + '!')}"),
+    ("scalar", "size(names)"),
+];
+
+fn context() -> Context {
+    let names = (0..200).map(|i| format!("user-{i}")).collect::<Vec<_>>();
+    let mut context = Context::new();
+    context.add_variable("names".to_string(), serde_json::json!(names));
+    context
+}
+
+fn bench_arena(c: &mut Criterion) {
+    let context = context();
+    let mut group = c.benchmark_group("execute");
+
+    for (name, expression) in EXPRESSIONS {
+        let mut program = Program::new();
+        program.compile(expression).unwrap();
+
+        group.bench_with_input(BenchmarkId::new("alloc", name), &program, |b, program| {
+            b.iter(|| unsafe {
+                let result = program_execute_alloc(program, &context);
+                cel_result_free(black_box(result));
+            });
+        });
+
+        let options = CelExecuteOptions {
+            arena: cel_arena_new(),
+        };
+        group.bench_with_input(BenchmarkId::new("arena", name), &program, |b, program| {
+            b.iter(|| unsafe {
+                let result = program_execute_with_options(program, &context, &options);
+                cel_result_free(black_box(result));
+            });
+        });
+        unsafe { cel_arena_free(options.arena) };
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_arena);
+criterion_main!(benches);
@@ -0,0 +1,84 @@
+//! Compile arbitrary text, with every extension enabled, and execute what compiles
+//!
+//! Execution runs under memory and cost limits so that expressions which are merely
+//! expensive, such as nested comprehensions over large ranges, are not reported.
+
+#![no_main]
+
+use cel_lua::*;
+use libfuzzer_sys::fuzz_target;
+use std::ffi::CString;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(expression) = CString::new(data) else {
+        return;
+    };
+    let options = CelCompileOptions {
+        max_expression_length: 4096,
+        max_nesting_depth: 64,
+        max_comprehensions: 8,
+        disabled_macros: std::ptr::null(),
+        disabled_macros_len: 0,
+        disable_regex: false,
+        max_regex_length: 256,
+        max_regex_size: 1 << 16,
+        string_ext: true,
+        math_ext: true,
+        list_ext: true,
+        optional_ext: true,
+        unicode_ext: true,
+        encoders_ext: true,
+        json_ext: true,
+    };
+    // Small enough that long messages are truncated
+    let mut errbuf = [0u8; 64];
+    let mut errbuf_len = errbuf.len();
+
+    unsafe {
+        let program = program_new();
+        let compiled = program_compile_with_options(
+            program,
+            expression.as_ptr(),
+            &options,
+            errbuf.as_mut_ptr(),
+            &mut errbuf_len,
+        );
+        if compiled {
+            let context = context_new();
+            bind(context);
+            context_set_max_memory(context, 1 << 20);
+            let token = cel_cancel_token_new();
+            cel_cancel_token_set_budget(token, 10_000);
+
+            cel_result_free(program_execute_cancellable(program, context, token));
+
+            cel_cancel_token_free(token);
+            context_free(context);
+        }
+        program_free(program);
+    }
+});
+
+/// Bind a few variables of different types, so expressions using them get further
+unsafe fn bind(context: *mut Context) {
+    let mut errbuf = [0u8; 64];
+    let mut errbuf_len = errbuf.len();
+    let x = CString::new("x").unwrap();
+    let int = CelValue {
+        value_type: CelValueType::Int,
+        data: CelValueData { int_val: 42 },
+    };
+    context_add_variable(context, x.as_ptr(), &int, errbuf.as_mut_ptr(), &mut errbuf_len);
+
+    let s = CString::new("s").unwrap();
+    let text = "héllo wörld";
+    errbuf_len = errbuf.len();
+    context_add_variable_string_n(
+        context,
+        s.as_ptr(),
+        text.as_ptr(),
+        text.len(),
+        errbuf.as_mut_ptr(),
+        &mut errbuf_len,
+    );
+}
@@ -0,0 +1,30 @@
+//! Restore contexts from arbitrary JSON, and serialize the ones that load
+
+#![no_main]
+
+use cel_lua::*;
+use libfuzzer_sys::fuzz_target;
+use std::ffi::CString;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(json) = CString::new(data) else {
+        return;
+    };
+    let mut errbuf = [0u8; 64];
+    let mut errbuf_len = errbuf.len();
+
+    unsafe {
+        let context = context_from_json(json.as_ptr(), errbuf.as_mut_ptr(), &mut errbuf_len);
+        if !context.is_null() {
+            let mut out = CelStringValue {
+                ptr: std::ptr::null(),
+                len: 0,
+            };
+            errbuf_len = errbuf.len();
+            if context_to_json(context, &mut out, errbuf.as_mut_ptr(), &mut errbuf_len) {
+                cel_string_release(out.ptr);
+            }
+            context_free(context);
+        }
+    }
+});
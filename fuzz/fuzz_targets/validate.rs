@@ -0,0 +1,32 @@
+//! Validate arbitrary text, and extract the variables it names even when it does
+//! not parse
+
+#![no_main]
+
+use cel_lua::*;
+use libfuzzer_sys::fuzz_target;
+use std::ffi::CString;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(expression) = std::str::from_utf8(data) {
+        let _ = fuzz_extract_variables(expression);
+    }
+    let Ok(expression) = CString::new(data) else {
+        return;
+    };
+    let mut errbuf = [0u8; 64];
+    let mut errbuf_len = errbuf.len();
+    let mut variables_len = 0;
+
+    unsafe {
+        program_validate_with_options(
+            expression.as_ptr(),
+            std::ptr::null(),
+            &mut variables_len,
+            errbuf.as_mut_ptr(),
+            &mut errbuf_len,
+        );
+        let result = program_validate_full(expression.as_ptr(), std::ptr::null());
+        cel_result_free(result);
+    }
+});
@@ -0,0 +1,202 @@
+//! Convert arbitrary C values, as a host would pass them, into a context and back
+//!
+//! Every pointer is valid for its length, as the API requires, except where a
+//! `Dangling` node asks for a null pointer with a nonzero length, which must be
+//! rejected. Strings and the text of tagged values are arbitrary bytes, so they need
+//! not be UTF-8 or well-formed numbers.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use cel_lua::*;
+use libfuzzer_sys::fuzz_target;
+use std::ffi::CString;
+use std::mem::ManuallyDrop;
+
+#[derive(Arbitrary, Debug)]
+enum Node {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Uint(u64),
+    Double(f64),
+    String(Vec<u8>),
+    Bytes(Vec<u8>),
+    List(Vec<Node>),
+    Map(Vec<(Node, Node)>),
+    Type(Vec<u8>),
+    Optional(Option<Box<Node>>),
+    Timestamp(i64, i32),
+    Duration(i64, i32),
+    BigInt(Vec<u8>),
+    Decimal(Vec<u8>),
+    /// A string, bytes, list or map with a null pointer and a nonzero length
+    Dangling(u8, u8),
+}
+
+fn string_data(ptr: *const u8, len: usize) -> CelValueData {
+    CelValueData {
+        string_val: ManuallyDrop::new(CelStringValue { ptr, len }),
+    }
+}
+
+fn bytes_data(ptr: *const u8, len: usize) -> CelValueData {
+    CelValueData {
+        bytes_val: ManuallyDrop::new(CelBytesValue { ptr, len }),
+    }
+}
+
+fn list_data(items: *const CelValue, len: usize) -> CelValueData {
+    CelValueData {
+        list_val: ManuallyDrop::new(CelListValue { items, len }),
+    }
+}
+
+fn map_data(keys: *const CelValue, values: *const CelValue, len: usize) -> CelValueData {
+    CelValueData {
+        map_val: ManuallyDrop::new(CelMapValue { keys, values, len }),
+    }
+}
+
+/// The buffers a built value points into, kept alive until it is no longer used
+#[derive(Default)]
+struct Arena {
+    bytes: Vec<Vec<u8>>,
+    values: Vec<Vec<CelValue>>,
+}
+
+impl Arena {
+    fn bytes(&mut self, bytes: &[u8]) -> (*const u8, usize) {
+        let bytes = bytes.to_vec();
+        let buffer = (bytes.as_ptr(), bytes.len());
+        self.bytes.push(bytes);
+        buffer
+    }
+
+    fn array(&mut self, values: Vec<CelValue>) -> (*const CelValue, usize) {
+        let array = (values.as_ptr(), values.len());
+        self.values.push(values);
+        array
+    }
+
+    fn build(&mut self, node: &Node) -> CelValue {
+        let (value_type, data) = match node {
+            Node::Null => (CelValueType::Null, CelValueData { int_val: 0 }),
+            Node::Bool(b) => (CelValueType::Bool, CelValueData { bool_val: *b }),
+            Node::Int(i) => (CelValueType::Int, CelValueData { int_val: *i }),
+            Node::Uint(u) => (CelValueType::Uint, CelValueData { uint_val: *u }),
+            Node::Double(f) => (CelValueType::Double, CelValueData { double_val: *f }),
+            Node::String(s) | Node::Type(s) | Node::BigInt(s) | Node::Decimal(s) => {
+                let value_type = match node {
+                    Node::Type(_) => CelValueType::Type,
+                    Node::BigInt(_) => CelValueType::BigInt,
+                    Node::Decimal(_) => CelValueType::Decimal,
+                    _ => CelValueType::String,
+                };
+                let (ptr, len) = self.bytes(s);
+                (value_type, string_data(ptr, len))
+            }
+            Node::Bytes(b) => {
+                let (ptr, len) = self.bytes(b);
+                (CelValueType::Bytes, bytes_data(ptr, len))
+            }
+            Node::List(items) => {
+                let items = items.iter().map(|item| self.build(item)).collect();
+                let (items, len) = self.array(items);
+                (CelValueType::List, list_data(items, len))
+            }
+            Node::Map(entries) => {
+                let keys = entries.iter().map(|(key, _)| self.build(key)).collect();
+                let values = entries.iter().map(|(_, value)| self.build(value)).collect();
+                let (keys, len) = self.array(keys);
+                let (values, _) = self.array(values);
+                (CelValueType::Map, map_data(keys, values, len))
+            }
+            Node::Optional(inner) => {
+                let value = match inner {
+                    Some(inner) => {
+                        let inner = self.build(inner);
+                        self.array(vec![inner]).0
+                    }
+                    None => std::ptr::null(),
+                };
+                let optional = CelOptionalValue {
+                    has_value: !value.is_null(),
+                    value,
+                };
+                let data = CelValueData {
+                    optional_val: ManuallyDrop::new(optional),
+                };
+                (CelValueType::Optional, data)
+            }
+            Node::Timestamp(seconds, nanos) => {
+                let time = CelTimeValue {
+                    seconds: *seconds,
+                    nanos: *nanos,
+                };
+                (
+                    CelValueType::Timestamp,
+                    CelValueData {
+                        timestamp_val: time,
+                    },
+                )
+            }
+            Node::Duration(seconds, nanos) => {
+                let time = CelTimeValue {
+                    seconds: *seconds,
+                    nanos: *nanos,
+                };
+                (CelValueType::Duration, CelValueData { duration_val: time })
+            }
+            Node::Dangling(kind, len) => {
+                let null: *const CelValue = std::ptr::null();
+                let len = usize::from(*len).max(1);
+                match kind % 4 {
+                    0 => (CelValueType::String, string_data(null.cast(), len)),
+                    1 => (CelValueType::Bytes, bytes_data(null.cast(), len)),
+                    2 => (CelValueType::List, list_data(null, len)),
+                    _ => (CelValueType::Map, map_data(null, null, len)),
+                }
+            }
+        };
+        CelValue { value_type, data }
+    }
+}
+
+fuzz_target!(|node: Node| {
+    let mut arena = Arena::default();
+    let value = arena.build(&node);
+    let name = CString::new("v").unwrap();
+    let mut errbuf = [0u8; 64];
+    let mut errbuf_len = errbuf.len();
+
+    unsafe {
+        let context = context_new();
+        let added = context_add_variable(
+            context,
+            name.as_ptr(),
+            &value,
+            errbuf.as_mut_ptr(),
+            &mut errbuf_len,
+        );
+        if added {
+            let mut copy = CelValue {
+                value_type: CelValueType::Null,
+                data: CelValueData { int_val: 0 },
+            };
+            errbuf_len = errbuf.len();
+            if context_get_variable(
+                context,
+                name.as_ptr(),
+                &mut copy,
+                errbuf.as_mut_ptr(),
+                &mut errbuf_len,
+            ) {
+                let mut equal = false;
+                cel_value_equals(&value, &copy, &mut equal);
+                cel_value_free(&mut copy);
+            }
+        }
+        context_free(context);
+    }
+});
@@ -0,0 +1,105 @@
+#!/bin/sh
+# Generate the LuaJIT FFI declarations of the flat C API from the C header
+#
+# Usage: hack/gen-flat-lua.sh [include/cel_lua.h] [lib/cel/flat.lua]
+set -eu
+
+header=${1:-include/cel_lua.h}
+out=${2:-lib/cel/flat.lua}
+
+abi=$(sed -n 's/^#define CEL_ABI_VERSION \([0-9][0-9]*\)$/\1/p' "$header")
+if [ -z "$abi" ]; then
+  echo "no CEL_ABI_VERSION in $header" >&2
+  exit 1
+fi
+
+{
+  cat <<EOF
+-- Generated by \`make flat-lua\` from include/cel_lua.h. Do not edit.
+--
+-- Declarations of the flat C API, whose functions take and return only scalars,
+-- C strings and handles, for hosts that use the LuaJIT FFI directly:
+--
+--   local flat = require("cel.flat")
+--   local lib = flat.load("/path/to/libcel_lua.so")
+--   local state = flat.state(lib)
+--   local program = lib.program_handle_new_in(state)
+--   local context = lib.context_handle_new_in(state)
+--   lib.cel_flat_compile(program, "n * 2")
+--   lib.cel_flat_set_int(context, "n", 21)
+--   local result = lib.cel_flat_execute(program, context)
+--   print(lib.cel_flat_result_int(result)) -- 42
+--   lib.cel_flat_result_free(result)
+
+local ffi = require("ffi")
+
+local CEL_ABI_VERSION = $abi
+
+local declarations = {
+EOF
+
+  awk '
+    /^typedef enum (CelErrorCode|CelValueType) \{$/ { print "  [[" $0; inside = 1; next }
+    inside && /^\} / { print $0 "]],"; inside = 0; next }
+    inside { print }
+  ' "$header"
+
+  functions='cel_abi_version|cel_last_error|cel_flat_[a-z_]+|cel_state_(new|free)'
+  functions="$functions|program_handle_(new|new_in|free)"
+  functions="$functions|context_handle_(new|new_in|free|reset)"
+  grep -E "^[a-zA-Z].* \\*?($functions)\\(" "$header" |
+    sed 's/.*/  [[&]],/'
+
+  cat <<'EOF'
+}
+
+for _, declaration in ipairs(declarations) do
+  -- Fails harmlessly if the cel module or another copy of this file declared it
+  pcall(ffi.cdef, declaration)
+end
+
+local _M = { CEL_ABI_VERSION = CEL_ABI_VERSION }
+
+-- The handle registry this Lua state opened in each library. Every Lua state loads
+-- its own copy of this module, so registries are never shared between Lua states,
+-- while the coroutines of one Lua state share its registry
+local states = {}
+
+-- Load the library from `path`, or by name from the system search path, refusing a
+-- build with a different ABI version
+function _M.load(path)
+  local lib = ffi.load(path or "cel_lua")
+  local abi = lib.cel_abi_version()
+  if abi ~= CEL_ABI_VERSION then
+    error("cel_lua ABI version " .. abi .. " does not match " .. CEL_ABI_VERSION, 2)
+  end
+  return lib
+end
+
+-- This Lua state's registry in `lib`, the state to pass `program_handle_new_in` and
+-- `context_handle_new_in`, opened on first use. Closing the Lua state frees it with
+-- every handle it still owns
+function _M.state(lib)
+  local state = states[lib]
+  if not state then
+    state = ffi.gc(ffi.new("uint64_t[1]", lib.cel_state_new()), function(s)
+      lib.cel_state_free(s[0])
+    end)
+    states[lib] = state
+  end
+  return state[0]
+end
+
+-- Free this Lua state's registry in `lib` now, with every handle it still owns; the
+-- next `state` call opens a new one
+function _M.close(lib)
+  local state = states[lib]
+  if state then
+    states[lib] = nil
+    lib.cel_state_free(ffi.gc(state, nil)[0])
+  end
+end
+
+return _M
+EOF
+} > "$out"
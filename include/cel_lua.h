@@ -0,0 +1,991 @@
+#ifndef CEL_LUA_H
+#define CEL_LUA_H
+
+/* Generated with cbindgen:0.29.0 */
+
+/* Warning, this file is autogenerated by cbindgen. Don't modify this manually. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Version of the C ABI exposed by this library
+#define CEL_ABI_VERSION 2
+
+// Kind of an object the library allocated for the host
+typedef enum CelAllocationKind {
+  AllocatedString,
+  AllocatedValues,
+  AllocatedResult,
+  AllocatedProgram,
+  AllocatedContext,
+} CelAllocationKind;
+
+// What a `CelAttributeHook` decides about a field selection
+typedef enum CelAttributeAccess {
+  AttributeAllow,
+  AttributeDeny,
+  AttributeReplace,
+} CelAttributeAccess;
+
+// Status codes returned by FFI entry points that report more than success/failure
+typedef enum CelErrorCode {
+  Ok,
+  InvalidHandle,
+  InvalidArgument,
+  CompileError,
+  ExecutionError,
+  NullArgument,
+  Internal,
+  Frozen,
+  Cancelled,
+  Unknown,
+  MemoryLimit,
+  UnresolvedFunction,
+  InvalidPointer,
+  IterationLimit,
+  ResultType,
+} CelErrorCode;
+
+// Severity of a log message, from most to least severe
+typedef enum CelLogLevel {
+  Error,
+  Warn,
+  Info,
+  Debug,
+} CelLogLevel;
+
+// What selecting a key a map lacks evaluates to, set by `context_set_missing_keys`
+typedef enum CelMissingKeys {
+  MissingKeyError,
+  MissingKeyNull,
+  MissingKeyUnknown,
+} CelMissingKeys;
+
+// How `program_execute_bool` turns a result into a yes or no
+typedef enum CelTruthiness {
+  TruthinessStrict,
+  TruthinessLenient,
+} CelTruthiness;
+
+// CEL value types enum
+typedef enum CelValueType {
+  Null,
+  Bool,
+  Int,
+  Uint,
+  Double,
+  String,
+  Bytes,
+  List,
+  Map,
+  // A type, as `type()` returns it, carried as its name such as `int` in `string_val`
+  Type,
+  Optional,
+  Timestamp,
+  Duration,
+  // A 128-bit signed integer, carried as decimal digits in `string_val`
+  BigInt,
+  // An exact decimal number, carried as text such as `-12.50` in `string_val`
+  Decimal,
+} CelValueType;
+
+// A compiled set of named rules loaded from a manifest
+typedef struct Bundle Bundle;
+
+// A reusable arena for evaluation results
+typedef struct CelArena CelArena;
+
+#if !defined(CEL_LUA_TARGET_WASM)
+// A reload started by `cel_bundle_reload`, collected by `cel_bundle_reload_finish`
+typedef struct CelBundleReload CelBundleReload;
+#endif
+
+// A token cancelling the evaluations it is passed to, created by `cel_cancel_token_new`
+typedef struct CelCancelToken CelCancelToken;
+
+// Evaluation result that owns every allocation reachable from its value
+typedef struct CelEvalResult CelEvalResult;
+
+#if !defined(CEL_LUA_TARGET_WASM)
+// An execution started by `program_execute_start`, collected by `cel_execution_finish`
+typedef struct CelExecution CelExecution;
+#endif
+
+// A list under construction, bound to a context by `cel_list_builder_finish`
+typedef struct CelListBuilder CelListBuilder;
+
+// A map under construction, bound to a context by `cel_map_builder_finish`
+typedef struct CelMapBuilder CelMapBuilder;
+
+// Context for storing variables for CEL evaluation
+typedef struct Context Context;
+
+// A point in a context's history that `Context::restore` can roll back to
+typedef struct ContextSnapshot ContextSnapshot;
+
+// Everything programs are compiled against: options, extensions and declarations
+typedef struct Env Env;
+
+// CEL Program for compiling and executing expressions
+typedef struct Program Program;
+
+// An ordered set of named expressions
+typedef struct RuleSet RuleSet;
+
+// An expression with `{name}` placeholders for parameter values
+typedef struct Template Template;
+
+// String value representation for CEL
+typedef struct CelStringValue {
+  const uint8_t *ptr;
+  uintptr_t len;
+} CelStringValue;
+
+// Bytes value representation for CEL
+typedef struct CelBytesValue {
+  const uint8_t *ptr;
+  uintptr_t len;
+} CelBytesValue;
+
+// List value representation for CEL
+typedef struct CelListValue {
+  const struct CelValue *items;
+  uintptr_t len;
+} CelListValue;
+
+// Map value representation for CEL, as parallel key and value arrays
+typedef struct CelMapValue {
+  const struct CelValue *keys;
+  const struct CelValue *values;
+  uintptr_t len;
+} CelMapValue;
+
+// Optional value representation for CEL
+typedef struct CelOptionalValue {
+  bool has_value;
+  const struct CelValue *value;
+} CelOptionalValue;
+
+// Timestamp or duration representation for CEL, in whole seconds and nanoseconds
+typedef struct CelTimeValue {
+  int64_t seconds;
+  int32_t nanos;
+} CelTimeValue;
+
+// Union for CEL value data
+typedef union CelValueData {
+  bool bool_val;
+  int64_t int_val;
+  uint64_t uint_val;
+  double double_val;
+  CelStringValue string_val;
+  CelBytesValue bytes_val;
+  CelListValue list_val;
+  CelMapValue map_val;
+  CelOptionalValue optional_val;
+  CelTimeValue timestamp_val;
+  CelTimeValue duration_val;
+} CelValueData;
+
+// CEL value structure
+typedef struct CelValue {
+  CelValueType value_type;
+  CelValueData data;
+} CelValue;
+
+// How many arguments a function registered with `context_add_variadic_function`
+typedef struct CelArity {
+  uintptr_t min_args;
+  uintptr_t max_args;
+  const struct CelValue *defaults;
+  uintptr_t defaults_len;
+} CelArity;
+
+// Location and details of a failed compilation
+typedef struct CelCompileError {
+  uintptr_t line;
+  uintptr_t column;
+  uintptr_t offset;
+  uintptr_t length;
+  CelStringValue message;
+  CelStringValue token;
+  CelStringValue source;
+} CelCompileError;
+
+// Limits for compiling untrusted expressions, as described for `api::CompileOptions`
+typedef struct CelCompileOptions {
+  uintptr_t max_expression_length;
+  uintptr_t max_nesting_depth;
+  uintptr_t max_comprehensions;
+  const char *const *disabled_macros;
+  uintptr_t disabled_macros_len;
+  bool disable_regex;
+  uintptr_t max_regex_length;
+  uintptr_t max_regex_size;
+  bool string_ext;
+  bool math_ext;
+  bool list_ext;
+  bool optional_ext;
+  bool unicode_ext;
+  bool encoders_ext;
+  bool json_ext;
+} CelCompileOptions;
+
+// Options for `program_execute_with_options`
+typedef struct CelExecuteOptions {
+  // Arena to build the result in, or null to allocate it as `program_execute_alloc`
+  struct CelArena *arena;
+} CelExecuteOptions;
+
+// Library objects allocated and not yet freed, as counted by `cel_memory_stats`
+typedef struct CelMemoryStats {
+  uintptr_t strings;
+  // Bytes held by those strings, not counting their NUL terminators
+  uintptr_t string_bytes;
+  uintptr_t values;
+  uintptr_t results;
+  uintptr_t programs;
+  uintptr_t contexts;
+} CelMemoryStats;
+
+// Expression node counts before and after `program_optimize`
+typedef struct CelOptimization {
+  uintptr_t nodes_before;
+  uintptr_t nodes_after;
+} CelOptimization;
+
+// Size and complexity of a compiled expression, from `program_metrics`
+typedef struct CelProgramMetrics {
+  uintptr_t nodes;
+  uintptr_t depth;
+  uintptr_t comprehensions;
+  uintptr_t calls;
+  uint64_t cost;
+} CelProgramMetrics;
+
+#if defined(CEL_LUA_STATS)
+// Execution counters of a program, built with the `stats` feature
+typedef struct CelProgramStats {
+  uint64_t executions;
+  uint64_t errors;
+  uint64_t total_time_ns;
+  CelStringValue last_error;
+} CelProgramStats;
+#endif
+
+#if defined(CEL_LUA_ALLOCATOR)
+// Allocate `size` bytes aligned like `malloc` does, or return null
+typedef void *(*CelMallocFn)(void *user_data, uintptr_t size);
+#endif
+
+#if defined(CEL_LUA_ALLOCATOR)
+// Free a block returned by the matching `CelMallocFn` or `CelReallocFn`
+typedef void (*CelFreeFn)(void *user_data, void *ptr);
+#endif
+
+#if defined(CEL_LUA_ALLOCATOR)
+// Resize a block to `size` bytes, moving it if needed, or return null and leave it as is
+typedef void *(*CelReallocFn)(void *user_data, void *ptr, uintptr_t size);
+#endif
+
+// Callback receiving the library's log messages
+typedef void (*CelLogCallback)(void *user_data, CelLogLevel level, const char *message);
+
+// Callback receiving one live object from `cel_dump_leaks`
+typedef void (*CelLeakCallback)(void *user_data, CelAllocationKind kind, const void *ptr, uintptr_t size);
+
+// Callback resolving a variable that the context does not bind
+typedef bool (*CelVariableResolver)(void *user_data, const char *name, struct CelValue *out_value);
+
+// Callback deciding each field selection an expression makes
+typedef CelAttributeAccess (*CelAttributeHook)(void *user_data, const char *path, struct CelValue *out_value);
+
+// Callback implementing a function that expressions call
+typedef bool (*CelFunctionCallback)(void *user_data, const struct CelValue *args, uintptr_t args_len, struct CelValue *out_value, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+#ifdef __cplusplus
+extern "C" {
+#endif  // __cplusplus
+
+// ABI version the library was compiled with, for load-time checks by bindings
+uint32_t cel_abi_version(void);
+
+// Register a time zone for the timestamp accessors from the bytes of a compiled `TZif` file
+bool cel_add_time_zone(const char *name, const uint8_t *data, uintptr_t len);
+
+// Bytes the arena has taken from the allocator, kept across executions for reuse;
+uintptr_t cel_arena_allocated_bytes(const struct CelArena *arena);
+
+// Free an arena
+void cel_arena_free(struct CelArena *arena);
+
+// Create an empty arena
+struct CelArena *cel_arena_new(void);
+
+// The rules that failed to compile, as a JSON object mapping names to errors
+bool cel_bundle_errors_json(const struct Bundle *bundle, struct CelStringValue *out_json);
+
+// Execute the bundle's rule named `name` into a newly allocated result
+struct CelEvalResult *cel_bundle_execute(const struct Bundle *bundle, const char *name, const struct Context *context);
+
+// Execute every rule of the bundle into a result as `ruleset_execute_all` does
+struct CelEvalResult *cel_bundle_execute_all(const struct Bundle *bundle, const struct Context *context);
+
+// Evaluate the bundle's rules into a result as `ruleset_first_match` does
+struct CelEvalResult *cel_bundle_first_match(const struct Bundle *bundle, const struct Context *context);
+
+// Free a bundle
+void cel_bundle_free(struct Bundle *bundle);
+
+// Load and compile a bundle from a manifest file or a directory of `.cel` files
+struct Bundle *cel_bundle_load(const char *path, const struct CelCompileOptions *options, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Load and compile a bundle from a manifest of `len` bytes held in memory
+struct Bundle *cel_bundle_load_bytes(const uint8_t *data, uintptr_t len, const struct CelCompileOptions *options, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+#if !defined(CEL_LUA_TARGET_WASM)
+// Reload the bundle from a manifest file or directory on a background thread
+struct CelBundleReload *cel_bundle_reload(const struct Bundle *bundle, const char *path, const struct CelCompileOptions *options);
+#endif
+
+#if !defined(CEL_LUA_TARGET_WASM)
+// Reload the bundle from a manifest of `len` bytes on a background thread
+struct CelBundleReload *cel_bundle_reload_bytes(const struct Bundle *bundle, const uint8_t *data, uintptr_t len, const struct CelCompileOptions *options);
+#endif
+
+#if !defined(CEL_LUA_TARGET_WASM)
+// Wait for the reload to finish and report whether it swapped in new rules
+bool cel_bundle_reload_finish(struct CelBundleReload *reload, uint8_t *errbuf, uintptr_t *errbuf_len);
+#endif
+
+#if !defined(CEL_LUA_TARGET_WASM)
+// Whether the reload has finished, false for a null reload
+bool cel_bundle_reload_is_done(const struct CelBundleReload *reload);
+#endif
+
+// Number of the bundle's current rules: 1 as loaded, counting up with each reload
+uint64_t cel_bundle_version(const struct Bundle *bundle);
+
+// Cancel every evaluation using `token`, including those started later
+bool cel_cancel(const struct CelCancelToken *token);
+
+// Cancel `token` once `timeout_ms` milliseconds have passed
+bool cel_cancel_after(const struct CelCancelToken *token, uint64_t timeout_ms);
+
+// Release a token
+void cel_cancel_token_free(struct CelCancelToken *token);
+
+// Create a token that has not been cancelled
+struct CelCancelToken *cel_cancel_token_new(void);
+
+// Limit the evaluations using `token` to `cost` checks in total
+bool cel_cancel_token_set_budget(const struct CelCancelToken *token, uint64_t cost);
+
+// JSON object describing this build, for feature detection by bindings
+const char *cel_capabilities_json(void);
+
+#if defined(CEL_LUA_DEBUG)
+// List every library object allocated and not yet freed, in debug builds
+uintptr_t cel_dump_leaks(CelLeakCallback callback, void *user_data);
+#endif
+
+// Free an environment
+void cel_env_free(struct Env *env);
+
+// Create a compilation environment with the given options
+struct Env *cel_env_new(const struct CelCompileOptions *options, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Render a compilation error as its message and the source line with a caret
+bool cel_error_render(const struct CelCompileError *error, struct CelStringValue *out);
+
+// Compile and execute an expression against JSON variables, returning the result as JSON
+bool cel_eval_json(const char *expression, const char *variables_json, struct CelStringValue *out_json, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+#if !defined(CEL_LUA_TARGET_WASM)
+// Wait for the execution to finish and return its result, consuming `execution`
+struct CelEvalResult *cel_execution_finish(struct CelExecution *execution);
+#endif
+
+#if !defined(CEL_LUA_TARGET_WASM)
+// Whether the execution has finished, false for a null execution
+bool cel_execution_is_done(const struct CelExecution *execution);
+#endif
+
+// Check whether two expressions are the same rule written differently
+bool cel_expressions_equivalent(const char *a, const char *b, bool simplify, bool *out_equivalent, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Compile `expression` into the program referred to by `program`
+CelErrorCode cel_flat_compile(uint64_t program, const char *expression);
+
+// Message of the calling thread's last error, as for `cel_last_error_message`
+const char *cel_flat_error_message(void);
+
+// Execute a program against a context, returning a handle to the result
+uint64_t cel_flat_execute(uint64_t program, uint64_t context);
+
+// A bool result's value, or false with `cel_last_error` set if it is not a bool
+bool cel_flat_result_bool(uint64_t result);
+
+// A double result's value, or 0 with `cel_last_error` set if it is not a double
+double cel_flat_result_double(uint64_t result);
+
+// Release a result handle
+CelErrorCode cel_flat_result_free(uint64_t result);
+
+// An int result's value, or 0 with `cel_last_error` set if it is not an int
+int64_t cel_flat_result_int(uint64_t result);
+
+// A result serialized as null-terminated JSON, as for `program_execute_json`
+const char *cel_flat_result_json(uint64_t result);
+
+// Length in bytes of a string or bytes result, or the element count of a list or map
+uintptr_t cel_flat_result_len(uint64_t result);
+
+// The bytes of a string or bytes result, `cel_flat_result_len` long
+const uint8_t *cel_flat_result_string(uint64_t result);
+
+// Type of a result's value, or `Null` for an unknown handle
+CelValueType cel_flat_result_type(uint64_t result);
+
+// A uint result's value, or 0 with `cel_last_error` set if it is not a uint
+uint64_t cel_flat_result_uint(uint64_t result);
+
+// Bind `name` to a bool in the context referred to by `context`
+CelErrorCode cel_flat_set_bool(uint64_t context, const char *name, bool value);
+
+// Bind `name` to the `len` bytes at `value`, as a CEL bytes value
+CelErrorCode cel_flat_set_bytes(uint64_t context, const char *name, const char *value, uintptr_t len);
+
+// Bind `name` to a double in the context referred to by `context`
+CelErrorCode cel_flat_set_double(uint64_t context, const char *name, double value);
+
+// Bind `name` to an int in the context referred to by `context`
+CelErrorCode cel_flat_set_int(uint64_t context, const char *name, int64_t value);
+
+// Bind `name` to the value of a JSON document, such as an object or a list
+CelErrorCode cel_flat_set_json(uint64_t context, const char *name, const char *json);
+
+// Bind `name` to null in the context referred to by `context`
+CelErrorCode cel_flat_set_null(uint64_t context, const char *name);
+
+// Bind `name` to the UTF-8 string of `len` bytes at `value`
+CelErrorCode cel_flat_set_string(uint64_t context, const char *name, const char *value, uintptr_t len);
+
+// Bind `name` to a uint in the context referred to by `context`
+CelErrorCode cel_flat_set_uint(uint64_t context, const char *name, uint64_t value);
+
+// Number of distinct identifiers in the table shared by all programs and contexts
+uintptr_t cel_intern_count(void);
+
+// Drop the shared identifiers nothing uses any more, returning how many were dropped
+uintptr_t cel_intern_purge(void);
+
+// Status of the most recent `bool`-returning call made on this thread
+CelErrorCode cel_last_error(void);
+
+// Copy the full message of the calling thread's last error into `buf`
+bool cel_last_error_message(uint8_t *buf, uintptr_t *buf_len);
+
+// Bind the list to `name` in the context, consuming the builder
+bool cel_list_builder_finish(struct CelListBuilder *builder, struct Context *context, const char *name, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Discard an unfinished list
+void cel_list_builder_free(struct CelListBuilder *builder);
+
+// Start an empty list
+struct CelListBuilder *cel_list_builder_new(void);
+
+// Append a copy of a scalar, string, bytes, timestamp or duration value
+bool cel_list_builder_push(struct CelListBuilder *builder, const struct CelValue *value, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Append a finished list, consuming `child`
+bool cel_list_builder_push_list(struct CelListBuilder *builder, struct CelListBuilder *child);
+
+// Append a finished map, consuming `child`
+bool cel_list_builder_push_map(struct CelListBuilder *builder, struct CelMapBuilder *child);
+
+// Bind the map to `name` in the context, consuming the builder
+bool cel_map_builder_finish(struct CelMapBuilder *builder, struct Context *context, const char *name, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Discard an unfinished map
+void cel_map_builder_free(struct CelMapBuilder *builder);
+
+// Insert a copy of a value under a copy of `key`, replacing any previous entry
+bool cel_map_builder_insert(struct CelMapBuilder *builder, const struct CelValue *key, const struct CelValue *value, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Insert a finished list under a copy of `key`, consuming `child`
+bool cel_map_builder_insert_list(struct CelMapBuilder *builder, const struct CelValue *key, struct CelListBuilder *child, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Insert a finished map under a copy of `key`, consuming `child`
+bool cel_map_builder_insert_map(struct CelMapBuilder *builder, const struct CelValue *key, struct CelMapBuilder *child, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Start an empty map
+struct CelMapBuilder *cel_map_builder_new(void);
+
+// Count the strings, value arrays, results, programs and contexts the library has
+struct CelMemoryStats cel_memory_stats(void);
+
+// Pointer to the error message, or null if the evaluation succeeded or `result` is null
+const struct CelStringValue *cel_result_error(const struct CelEvalResult *result);
+
+// Status code of the evaluation; `NullArgument` for a null result
+CelErrorCode cel_result_error_code(const struct CelEvalResult *result);
+
+// Free an evaluation result together with everything it owns
+void cel_result_free(struct CelEvalResult *result);
+
+// Whether the evaluation succeeded; false for a null result
+bool cel_result_is_ok(const struct CelEvalResult *result);
+
+// Pointer to the result value, or null if the evaluation failed or `result` is null
+const struct CelValue *cel_result_value(const struct CelEvalResult *result);
+
+#if defined(CEL_LUA_ALLOCATOR)
+// Allocate everything from now on with `malloc_fn`, `free_fn` and `realloc_fn`
+bool cel_set_allocator(CelMallocFn malloc_fn, CelFreeFn free_fn, CelReallocFn realloc_fn, void *user_data);
+#endif
+
+// Send the library's messages at `level` or more severe to `callback`
+void cel_set_log_callback(CelLogCallback callback, void *user_data, CelLogLevel level);
+
+// Read time zones nobody registered from the `TZif` files under the directory `dir`
+bool cel_set_tzdata_dir(const char *dir);
+
+// Simplify an expression, folding constants and removing redundant logic
+bool cel_simplify(const char *expression, struct CelStringValue *out_expression, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Free a state and every program, context and result handle it still owns
+CelErrorCode cel_state_free(uint64_t state);
+
+// Open a state owning the handles created for one host state, such as a Lua state
+uint64_t cel_state_new(void);
+
+// Free a string that was allocated by the library
+void cel_string_free(const uint8_t *ptr);
+
+// Whether the caller owns (and must release) a library string
+bool cel_string_is_owned(const uint8_t *ptr);
+
+// Kept for compatibility; does nothing
+void cel_string_pool_clear(void);
+
+// Number of library strings allocated and not yet freed, whether caller-owned or
+uintptr_t cel_string_pool_size(void);
+
+// Current reference count of a library string, or 0 for null pointers and pointers
+uintptr_t cel_string_refcount(const uint8_t *ptr);
+
+// Drop a reference to a caller-owned library string, freeing it on the last release
+bool cel_string_release(const uint8_t *ptr);
+
+// Add a reference to a caller-owned library string
+bool cel_string_retain(const uint8_t *ptr);
+
+// Compile a template such as `user.age > {min_age}` for `template_instantiate`
+struct Template *cel_template_compile(const char *source, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Free a template
+void cel_template_free(struct Template *template_);
+
+// Build a big integer value from optionally signed decimal digits
+bool cel_value_bigint_from_string(const char *text, struct CelValue *out_value, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Copy a value, including everything nested in it, into `out_value`
+bool cel_value_clone(const struct CelValue *value, struct CelValue *out_value);
+
+// Render a value as a CEL expression that evaluates to it
+bool cel_value_debug_string(const struct CelValue *value, struct CelStringValue *out);
+
+// Build an exact decimal value from text such as `19.99` or `-0.005`
+bool cel_value_decimal_from_string(const char *text, struct CelValue *out_value, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Render a value as text for people
+bool cel_value_display_string(const struct CelValue *value, struct CelStringValue *out);
+
+// Build a duration value from text such as `1h30m`, `-1.5s` or `250ms`
+bool cel_value_duration_from_string(const char *text, struct CelValue *out_value, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Compare two values under CEL equality, writing the answer to `out_equal`
+bool cel_value_equals(const struct CelValue *a, const struct CelValue *b, bool *out_equal);
+
+// Free a value the caller owns, including everything nested in it
+void cel_value_free(struct CelValue *value);
+
+// Hash a value consistently with `cel_value_equals`, writing it to `out_hash`
+bool cel_value_hash(const struct CelValue *value, uint64_t *out_hash);
+
+// Build a timestamp value from RFC 3339 text such as `2024-01-01T12:00:00Z`
+bool cel_value_timestamp_from_rfc3339(const char *text, struct CelValue *out_value, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Build a type value from its name, such as `int` or `google.protobuf.Timestamp`
+bool cel_value_type_from_name(const char *name, struct CelValue *out_value, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+#if defined(CEL_LUA_WASM)
+// `len` bytes of memory for passing a string in, released with `cel_wasm_free`
+uint8_t *cel_wasm_alloc(uintptr_t len);
+#endif
+
+#if defined(CEL_LUA_WASM)
+// Release a buffer returned by the library
+void cel_wasm_buffer_free(uint8_t *buffer);
+#endif
+
+#if defined(CEL_LUA_WASM)
+// Compile the `len` bytes at `expression` into the program referred to by `program`
+CelErrorCode cel_wasm_compile(uint64_t program, const uint8_t *expression, uintptr_t len);
+#endif
+
+#if defined(CEL_LUA_WASM)
+// Message of the calling thread's last error, as for `cel_last_error_message`
+uint8_t *cel_wasm_error_message(void);
+#endif
+
+#if defined(CEL_LUA_WASM)
+// Execute a program against a context, returning the result as a JSON buffer
+uint8_t *cel_wasm_execute_json(uint64_t program, uint64_t context);
+#endif
+
+#if defined(CEL_LUA_WASM)
+// Release memory from `cel_wasm_alloc`
+void cel_wasm_free(uint8_t *ptr, uintptr_t len);
+#endif
+
+#if defined(CEL_LUA_WASM)
+// Bind a variable to a JSON value in the context referred to by `context`
+CelErrorCode cel_wasm_set_json(uint64_t context, const uint8_t *name, uintptr_t name_len, const uint8_t *json, uintptr_t json_len);
+#endif
+
+// Register a function that expressions executed against the context can call
+bool context_add_function(struct Context *context, const char *name, CelFunctionCallback callback, void *user_data);
+
+// Register a function that expressions call on a receiver, as in
+bool context_add_member_function(struct Context *context, const char *name, CelFunctionCallback callback, void *user_data);
+
+// Register an overload of the member function `name` for a receiver and arguments
+bool context_add_member_overload(struct Context *context, const char *name, const CelValueType *arg_types, uintptr_t arg_types_len, CelValueType result_type, CelFunctionCallback callback, void *user_data);
+
+// Register an overload of `name` for arguments of the `arg_types_len` types in `arg_types`
+bool context_add_overload(struct Context *context, const char *name, const CelValueType *arg_types, uintptr_t arg_types_len, CelValueType result_type, CelFunctionCallback callback, void *user_data);
+
+#if defined(CEL_LUA_PROTO)
+// Register the message types in a serialized `google.protobuf.FileDescriptorSet`
+bool context_add_proto_descriptors(struct Context *context, const uint8_t *ptr, uintptr_t len, uint8_t *errbuf, uintptr_t *errbuf_len);
+#endif
+
+// Add a variable to the context
+bool context_add_variable(struct Context *context, const char *name, const struct CelValue *value, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Add a variable at a dotted path, building nested maps as needed
+bool context_add_variable_path(struct Context *context, const char *path, const struct CelValue *value, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Register a function taking any arguments, as many as `arity` allows
+bool context_add_variadic_function(struct Context *context, const char *name, const struct CelArity *arity, CelFunctionCallback callback, void *user_data, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Register a member function taking as many arguments as `arity` allows, counting
+bool context_add_variadic_member_function(struct Context *context, const char *name, const struct CelArity *arity, CelFunctionCallback callback, void *user_data, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+#if defined(CEL_LUA_PROTO)
+// Add a variable holding a serialized protobuf message
+bool context_add_variable_proto(struct Context *context, const char *name, const uint8_t *ptr, uintptr_t len, const char *type_name, uint8_t *errbuf, uintptr_t *errbuf_len);
+#endif
+
+// Add a string variable that refers to the caller's memory instead of copying it
+bool context_add_variable_string_borrowed(struct Context *context, const char *name, const uint8_t *ptr, uintptr_t len, uint64_t lifetime_token, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Add a string variable given as a pointer and length
+bool context_add_variable_string_n(struct Context *context, const char *name, const uint8_t *ptr, uintptr_t len, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Add a string variable given as UTF-16 code units
+bool context_add_variable_utf16(struct Context *context, const char *name, const uint16_t *ptr, uintptr_t len, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Make `now()` read the system clock again, once per execution
+bool context_clear_now(struct Context *context);
+
+// Free a context instance
+void context_free(struct Context *context);
+
+// Make the context read-only so threads can share it as a base environment
+const struct Context *context_freeze(struct Context *context);
+
+// Create a context from JSON written by `context_to_json`
+struct Context *context_from_json(const char *json, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Copy a variable's value out of the context
+bool context_get_variable(const struct Context *context, const char *name, struct CelValue *out_value, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Add a variable to the context referred to by a handle
+CelErrorCode context_handle_add_variable(uint64_t context, const char *name, const struct CelValue *value, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Free a context handle
+CelErrorCode context_handle_free(uint64_t context);
+
+// Create a new context and return its handle
+uint64_t context_handle_new(void);
+
+// Create a new context owned by a state from `cel_state_new` and return its handle
+uint64_t context_handle_new_in(uint64_t state);
+
+// Reset the context referred to by a handle, clearing all variables
+CelErrorCode context_handle_reset(uint64_t context);
+
+// Whether a variable is bound in the context
+bool context_has_variable(const struct Context *context, const char *name, CelValueType *value_type);
+
+// Unbind the variables borrowed under `lifetime_token`, returning how many there were
+uintptr_t context_invalidate_borrowed(struct Context *context, uint64_t lifetime_token);
+
+// Whether `context_freeze` has made the context read-only, false for a null context
+bool context_is_frozen(const struct Context *context);
+
+// Create a new context instance
+struct Context *context_new(void);
+
+// Unregister the functions, member functions and overloads registered under `name`
+bool context_remove_function(struct Context *context, const char *name);
+
+// Remove a single variable from the context
+bool context_remove_variable(struct Context *context, const char *name);
+
+// Reset the context, clearing all variables
+void context_reset(struct Context *context);
+
+// Roll the context's variables back to a snapshot
+bool context_restore(struct Context *context, const struct ContextSnapshot *snapshot);
+
+// Limit the nesting depth of expressions executed against the context
+// Register a hook that decides every field selection and index expressions make
+bool context_set_attribute_hook(struct Context *context, CelAttributeHook hook, void *user_data);
+
+// Keep an audit record of each of the last `capacity` executions against the context
+bool context_set_audit_log(struct Context *context, uintptr_t capacity);
+
+// Make executions against the context reproducible across runs and platforms
+bool context_set_deterministic(struct Context *context, bool enabled);
+
+bool context_set_max_depth(struct Context *context, uintptr_t max_depth);
+
+// Limit the comprehension iterations one execution against the context may run
+bool context_set_max_iterations(struct Context *context, uintptr_t max_iterations);
+
+// Limit the bytes one execution against the context may allocate
+bool context_set_max_memory(struct Context *context, uintptr_t max_bytes);
+
+// Choose what selecting a key that a map lacks evaluates to
+bool context_set_missing_keys(struct Context *context, CelMissingKeys policy);
+
+// Make operators applied to null yield null instead of failing, as in SQL
+bool context_set_null_propagation(struct Context *context, bool enabled);
+
+// Let arithmetic, comparisons and equality mix int, uint and double operands
+bool context_set_numeric_promotion(struct Context *context, bool enabled);
+
+// Fix the instant `now()` returns in expressions executed against the context
+bool context_set_now(struct Context *context, int64_t epoch_seconds, uint32_t nanos);
+
+// Make `context_add_variable` reject NaN and infinite doubles
+bool context_set_reject_non_finite(struct Context *context, bool enabled);
+
+// Register a resolver for variables the context does not bind
+bool context_set_resolver(struct Context *context, CelVariableResolver resolver, void *user_data);
+
+// Let host functions that fail evaluate to error values, which `&&`, `||` and
+bool context_set_soft_errors(struct Context *context, bool enabled);
+
+// Take a snapshot of the context's variables for `context_restore`
+struct ContextSnapshot *context_snapshot(struct Context *context);
+
+// Free a snapshot returned by `context_snapshot`
+void context_snapshot_free(struct ContextSnapshot *snapshot);
+
+// Remove the context's audit records and return them as a JSON array, oldest first
+bool context_take_audit_log(const struct Context *context, struct CelStringValue *out_json);
+
+// Serialize the context's variables as JSON, as described for `api::Context::to_json`
+bool context_to_json(const struct Context *context, struct CelStringValue *out_json, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Number of variables bound in the context, or 0 for a null context
+uintptr_t context_variable_count(const struct Context *context);
+
+// Name of the variable at `index`, for enumerating with `context_variable_count`
+bool context_variable_name_at(const struct Context *context, uintptr_t index, struct CelStringValue *out_name);
+
+// Define a constant that programs compiled in `env` afterwards see in place of a variable
+bool env_add_constant(struct Env *env, const char *name, const struct CelValue *value, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Allow programs compiled in `env` to call the function or macro `name`
+bool env_allow_function(struct Env *env, const char *name);
+
+// Compile a CEL expression into `program` within the environment
+bool env_compile(const struct Env *env, struct Program *program, const char *expression, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Declare a function the host provides, so programs compiled in `env` may call it
+bool env_declare_function(struct Env *env, const char *name);
+
+// Declare a variable that contexts will bind
+bool env_declare_variable(struct Env *env, const char *name);
+
+// Reject expressions compiled in `env` that use the macro `name`
+bool env_disable_macro(struct Env *env, const char *name);
+
+// Register an enum whose members programs compiled in `env` afterwards write as `Name.MEMBER`
+bool env_register_enum(struct Env *env, const char *name, const char *const *members, const int64_t *values, uintptr_t len);
+
+// Reject expressions that reference the variable `pattern`
+bool env_reserve_name(struct Env *env, const char *pattern);
+
+// Accept calls to functions not declared in `env`, resolving them when programs run
+bool env_set_late_bound_functions(struct Env *env, bool late_bound);
+
+// Fail executions once a comprehension ranges over more than `max` elements
+bool env_set_max_comprehension_iterations(struct Env *env, uintptr_t max);
+
+// Reject expressions with an identifier longer than `max` bytes, or none for 0
+bool env_set_max_identifier_length(struct Env *env, uintptr_t max);
+
+// Reject expressions that reference variables not declared in `env`
+bool env_set_strict_variables(struct Env *env, bool strict);
+
+// Compile a CEL expression
+bool program_compile(struct Program *program, const char *expression, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Details of the program's last compilation failure
+bool program_compile_error(const struct Program *program, struct CelCompileError *out_error);
+
+// Compile a CEL expression after checking it against `options`
+bool program_compile_with_options(struct Program *program, const char *expression, const struct CelCompileOptions *options, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// The field paths the compiled expression reads, as JSON grouped by variable
+bool program_dependencies_json(const struct Program *program, struct CelStringValue *out_json);
+
+// Execute the compiled expression
+bool program_execute(const struct Program *program, const struct Context *context, struct CelValue *result, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Execute the compiled expression into a newly allocated result
+struct CelEvalResult *program_execute_alloc(const struct Program *program, const struct Context *context);
+
+// Execute a program against `count` contexts into a newly allocated list result
+struct CelEvalResult *program_execute_batch(const struct Program *program, const struct Context *const *contexts, uintptr_t count);
+
+#if defined(CEL_LUA_PARALLEL)
+// Execute like `program_execute_batch`, with the contexts sharded across threads
+struct CelEvalResult *program_execute_batch_parallel(const struct Program *program, const struct Context *const *contexts, uintptr_t count, uintptr_t threads);
+#endif
+
+// Execute the compiled expression and write whether its result counts as true
+bool program_execute_bool(const struct Program *program, const struct Context *context, bool *out_bool, CelTruthiness truthiness, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Execute into a newly allocated result, stopping early once `token` is cancelled
+struct CelEvalResult *program_execute_cancellable(const struct Program *program, const struct Context *context, const struct CelCancelToken *token);
+
+// Execute the compiled expression and serialize the result as JSON
+bool program_execute_json(const struct Program *program, const struct Context *context, struct CelStringValue *out_json, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+#if !defined(CEL_LUA_TARGET_WASM)
+// Start executing the compiled expression on a background thread
+struct CelExecution *program_execute_start(const struct Program *program, const struct Context *context, const struct CelCancelToken *token);
+#endif
+
+// Execute the compiled expression into a newly allocated result, with a trace
+struct CelEvalResult *program_execute_traced(const struct Program *program, const struct Context *context, struct CelStringValue *out_trace_json);
+
+// Execute the compiled expression into a newly allocated result, as chosen by
+struct CelEvalResult *program_execute_with_options(const struct Program *program, const struct Context *context, const struct CelExecuteOptions *options);
+
+// Require executions of the program to return a value of `value_type`
+bool program_expect_type(struct Program *program, CelValueType value_type);
+
+// The parsed expression as JSON, with its macros expanded into comprehensions
+bool program_expanded_ast_json(const struct Program *program, struct CelStringValue *out_json);
+
+// Free a program instance
+void program_free(struct Program *program);
+
+// Compile a CEL expression into the program referred to by a handle
+CelErrorCode program_handle_compile(uint64_t program, const char *expression, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Execute the program referred to by a handle against a context handle
+CelErrorCode program_handle_execute(uint64_t program, uint64_t context, struct CelValue *result, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Free a program handle
+CelErrorCode program_handle_free(uint64_t program);
+
+// Create a new program and return its handle
+uint64_t program_handle_new(void);
+
+// Create a new program owned by a state from `cel_state_new` and return its handle
+uint64_t program_handle_new_in(uint64_t state);
+
+// Stable 64-bit fingerprint of the compiled expression's syntax tree
+bool program_hash(const struct Program *program, uint64_t *out_hash);
+
+// The program's fingerprint as 16 lowercase hex digits
+bool program_hash_hex(const struct Program *program, struct CelStringValue *out_hex);
+
+// Infer the type every successful execution of the program returns
+bool program_inferred_type(const struct Program *program, bool *out_known, CelValueType *out_type);
+
+// Measure the size and complexity of the compiled expression
+bool program_metrics(const struct Program *program, struct CelProgramMetrics *out);
+
+// Create a new program instance
+struct Program *program_new(void);
+
+// Fold constant subexpressions and prune dead conditional branches
+bool program_optimize(struct Program *program, struct CelOptimization *out);
+
+// Rename every reference to the variable `old_name` in `expression` to `new_name`
+bool program_rename_variable(const char *expression, const char *old_name, const char *new_name, struct CelStringValue *out_expression, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+#if defined(CEL_LUA_STATS)
+// Set the execution counters of a program back to zero
+bool program_reset_stats(const struct Program *program);
+#endif
+
+// Return member names of the enum `enum_name` in `env` for the int results it names
+bool program_set_result_enum(struct Program *program, const struct Env *env, const char *enum_name);
+
+#if defined(CEL_LUA_STATS)
+// Read the execution counters of a program
+bool program_stats(const struct Program *program, struct CelProgramStats *out_stats);
+#endif
+
+// Validate a CEL expression and return variables
+bool program_validate(const char *expression, const uint8_t **_variables, uintptr_t *variables_len, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Validate a CEL expression into a newly allocated list of diagnostics
+struct CelEvalResult *program_validate_full(const char *expression, const struct CelCompileOptions *options);
+
+// Validate a CEL expression against `options` and return the variable count
+bool program_validate_with_options(const char *expression, const struct CelCompileOptions *options, uintptr_t *variables_len, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Add a rule named `name`, to be compiled by the next `ruleset_compile`
+bool ruleset_add(struct RuleSet *ruleset, const char *name, const char *expression, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Compile every rule added since the last compilation
+bool ruleset_compile(struct RuleSet *ruleset, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Execute every rule into a newly allocated result mapping rule names to outcomes
+struct CelEvalResult *ruleset_execute_all(const struct RuleSet *ruleset, const struct Context *context);
+
+// Evaluate the rules in order into a result holding the name of the first that matches
+struct CelEvalResult *ruleset_first_match(const struct RuleSet *ruleset, const struct Context *context);
+
+// Free a rule set
+void ruleset_free(struct RuleSet *ruleset);
+
+// Create an empty rule set, compiled with the default options
+struct RuleSet *ruleset_new(void);
+
+// Match rules in `ruleset_first_match` with a CEL predicate over the rule's `result`
+bool ruleset_set_predicate(struct RuleSet *ruleset, const char *expression, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+// Fill the template's placeholders from a JSON object of parameters
+bool template_instantiate(const struct Template *template_, const char *params_json, struct CelStringValue *out_expression, uint8_t *errbuf, uintptr_t *errbuf_len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CEL_LUA_H */
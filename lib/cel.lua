@@ -1,4 +1,138 @@
+local ffi = require("ffi")
+local cdefs = require("cel.cdefs")
+
+local log_callback
+
+-- Log levels for `set_log_callback`, from most to least severe
+local LOG_ERROR = 0
+local LOG_WARN = 1
+local LOG_INFO = 2
+local LOG_DEBUG = 3
+
+-- Route the library's warnings to `handler(level, message)` for messages at
+-- `level` (LOG_WARN by default) or more severe; a nil handler stops logging.
+-- LuaJIT callbacks must not fire on other OS threads, so only use this where
+-- the library is called from a single thread, as in OpenResty workers.
+local function set_log_callback(handler, level)
+  local previous = log_callback
+  log_callback = handler and ffi.cast("CelLogCallback", function(_, msg_level, message)
+    handler(tonumber(msg_level), ffi.string(message))
+  end)
+  cdefs.clib.cel_set_log_callback(log_callback, nil, level or LOG_WARN)
+
+  if previous then
+    previous:free()
+  end
+end
+
+local cancel_token_mt = {
+  __index = {
+    -- Stop every evaluation using this token, including those started later
+    cancel = function(self)
+      cdefs.clib.cel_cancel(self.token)
+    end,
+  },
+}
+
+-- A token for program:execute and program:execute_async
+local function cancel_token()
+  local token = ffi.gc(cdefs.clib.cel_cancel_token_new(), cdefs.clib.cel_cancel_token_free)
+  return setmetatable({ token = token }, cancel_token_mt)
+end
+
+-- Names of the CelAllocationKind values
+local ALLOCATION_KINDS = { [0] = "string", "values", "result", "program", "context" }
+
+-- Library objects allocated and not yet freed; counts that keep growing while the
+-- workload stays the same point at a leak
+local function memory_stats()
+  local stats = cdefs.clib.cel_memory_stats()
+  return {
+    strings = tonumber(stats.strings),
+    string_bytes = tonumber(stats.string_bytes),
+    values = tonumber(stats.values),
+    results = tonumber(stats.results),
+    programs = tonumber(stats.programs),
+    contexts = tonumber(stats.contexts),
+  }
+end
+
+-- Every library object allocated and not yet freed, as { kind, address, size } tables;
+-- only debug builds of the library export cel_dump_leaks
+local dump_leaks
+if pcall(function() return cdefs.clib.cel_dump_leaks end) then
+  dump_leaks = function()
+    local leaks = {}
+    local callback = ffi.cast("CelLeakCallback", function(_, kind, ptr, size)
+      leaks[#leaks + 1] = {
+        kind = ALLOCATION_KINDS[tonumber(kind)],
+        address = tonumber(ffi.cast("uintptr_t", ptr)),
+        size = tonumber(size),
+      }
+    end)
+    cdefs.clib.cel_dump_leaks(callback, nil)
+    callback:free()
+    return leaks
+  end
+end
+
+-- Full message of the last error on this thread, however long; nil if there has been none
+local function last_error_message()
+  local len = ffi.new("uintptr_t[1]", 0)
+  cdefs.clib.cel_last_error_message(nil, len)
+  if len[0] == 0 then
+    return nil
+  end
+
+  local size = tonumber(len[0]) + 1
+  local buf = ffi.new("uint8_t[?]", size)
+  len[0] = size
+  cdefs.clib.cel_last_error_message(buf, len)
+  return ffi.string(buf, len[0])
+end
+
 return {
+  bundle = require("cel.bundle"),
   context = require("cel.context"),
+  env = require("cel.env"),
   program = require("cel.program"),
+  ruleset = require("cel.ruleset"),
+  sandbox = require("cel.sandbox").new,
+  template = require("cel.template"),
+  value = require("cel.value"),
+
+  LOG_ERROR = LOG_ERROR,
+  LOG_WARN = LOG_WARN,
+  LOG_INFO = LOG_INFO,
+  LOG_DEBUG = LOG_DEBUG,
+  set_log_callback = set_log_callback,
+  last_error_message = last_error_message,
+  set_number_policy = cdefs.set_number_policy,
+  number_policy = cdefs.number_policy,
+  cancel_token = cancel_token,
+  memory_stats = memory_stats,
+  dump_leaks = dump_leaks,
+
+  -- Distinct identifiers shared by all programs and contexts, and dropping the unused ones
+  interned_count = function()
+    return tonumber(cdefs.clib.cel_intern_count())
+  end,
+  purge_interned = function()
+    return tonumber(cdefs.clib.cel_intern_purge())
+  end,
+
+  -- Time zones for `ts.getHours('Europe/Stockholm')` and the other timestamp
+  -- accessors: TZif file contents registered under a name, or the directory read
+  -- for zones nobody registered (nil for `$TZDIR` or /usr/share/zoneinfo)
+  add_time_zone = function(name, data)
+    return cdefs.clib.cel_add_time_zone(name, data, #data)
+  end,
+  set_tzdata_dir = function(dir)
+    return cdefs.clib.cel_set_tzdata_dir(dir)
+  end,
+
+  -- JSON describing the loaded library's version, features, value types and limits
+  capabilities_json = function()
+    return ffi.string(cdefs.clib.cel_capabilities_json())
+  end,
 }
@@ -0,0 +1,145 @@
+local _M = {}
+local _MT = { __index = _M }
+
+local ffi = require("ffi")
+local cdefs = require("cel.cdefs")
+
+local ffi_gc = ffi.gc
+local ffi_new = ffi.new
+local ffi_string = ffi.string
+local setmetatable = setmetatable
+local tonumber = tonumber
+
+local ERR_BUF_MAX_LEN = cdefs.ERR_BUF_MAX_LEN
+local clib = cdefs.clib
+local bundle_free = cdefs.bundle_free
+local get_string_buf = cdefs.get_string_buf
+local get_size_ptr = cdefs.get_size_ptr
+local cel_value_to_lua_value = cdefs.cel_value_to_lua_value
+local compile_options = cdefs.compile_options
+
+-- Convert and free a result following `context`'s number policy, returning its value
+-- or nil and the error
+local function collect(result, context)
+  if not clib.cel_result_is_ok(result) then
+    local err = clib.cel_result_error(result)
+    local message = ffi_string(err.ptr, err.len)
+    clib.cel_result_free(result)
+    return nil, message
+  end
+
+  local value, err = cel_value_to_lua_value(clib.cel_result_value(result)[0],
+    context.number_policy)
+  clib.cel_result_free(result)
+  return value, err
+end
+
+-- Call `load(c_options, errbuf, errbuf_len)` and wrap the bundle it returns
+local function new(options, load)
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  local c_options, names = compile_options(options)
+  local bundle = load(c_options, errbuf, errbuf_len)
+  local _ = names -- keep the name array alive across the call
+
+  if bundle == nil then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
+
+  return setmetatable({
+    bundle = ffi_gc(bundle, bundle_free),
+  }, _MT)
+end
+
+-- Load a manifest file or a directory of `.cel` files, compiling every rule with the
+-- options table `program:compile` accepts
+function _M.load(path, options)
+  return new(options, function(c_options, errbuf, errbuf_len)
+    return clib.cel_bundle_load(path, c_options, errbuf, errbuf_len)
+  end)
+end
+
+-- Load a JSON manifest or an archive held in a string
+function _M.from_string(manifest, options)
+  return new(options, function(c_options, errbuf, errbuf_len)
+    return clib.cel_bundle_load_bytes(manifest, #manifest, c_options, errbuf, errbuf_len)
+  end)
+end
+
+-- JSON object mapping each rule that failed to compile to its error; "{}" if none did
+function _M:errors_json()
+  local out = ffi_new("CelStringValue[1]")
+  clib.cel_bundle_errors_json(self.bundle, out)
+  local json = ffi_string(out[0].ptr, out[0].len)
+  clib.cel_string_release(out[0].ptr)
+  return json
+end
+
+-- Evaluate the rule named `name`
+function _M:execute(name, context)
+  return collect(clib.cel_bundle_execute(self.bundle, name, context.context), context)
+end
+
+-- Evaluate every rule, as `ruleset:execute_all` does
+function _M:execute_all(context)
+  return collect(clib.cel_bundle_execute_all(self.bundle, context.context), context)
+end
+
+-- Return the name of the first rule that returns true, as `ruleset:first_match` does
+function _M:first_match(context)
+  return collect(clib.cel_bundle_first_match(self.bundle, context.context), context)
+end
+
+-- 1 for the rules first loaded, counting up with each reload
+function _M:version()
+  return tonumber(clib.cel_bundle_version(self.bundle))
+end
+
+-- Wait for a started reload, yielding as `program:execute_async` does in between,
+-- or blocking if should_yield is false
+local function finish(reload, should_yield)
+  if reload == nil then
+    return false, "Failed to start reload"
+  end
+
+  while should_yield ~= false and not clib.cel_bundle_reload_is_done(reload) do
+    if should_yield == nil or should_yield() then
+      if ngx and ngx.sleep then
+        ngx.sleep(0)
+      else
+        coroutine.yield()
+      end
+    end
+  end
+
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+  if not clib.cel_bundle_reload_finish(reload, errbuf, errbuf_len) then
+    return false, ffi_string(errbuf, errbuf_len[0])
+  end
+  return true
+end
+
+-- Compile the rules of a manifest file or directory on a background thread and swap
+-- them in. Evaluations keep using the current rules meanwhile, and if any new rule
+-- fails to compile the bundle keeps them. Polls as `program:execute_async` does, or
+-- blocks if should_yield is false. Returns true, or false and the error
+function _M:reload(path, options, should_yield)
+  local c_options, names = compile_options(options)
+  local reload = clib.cel_bundle_reload(self.bundle, path, c_options)
+  local _ = names -- keep the name array alive across the call
+  return finish(reload, should_yield)
+end
+
+-- Reload like `reload` from a manifest held in a string
+function _M:reload_string(manifest, options, should_yield)
+  local c_options, names = compile_options(options)
+  local reload = clib.cel_bundle_reload_bytes(self.bundle, manifest, #manifest, c_options)
+  local _ = names -- keep the name array alive across the call
+  return finish(reload, should_yield)
+end
+
+return _M
@@ -33,10 +33,64 @@ typedef enum CelValueType {
   Bytes,
   List,
   Map,
+  Type,
+  Optional,
   Timestamp,
   Duration,
+  BigInt,
+  Decimal,
 } CelValueType;
 
+typedef enum CelErrorCode {
+  Ok,
+  InvalidHandle,
+  InvalidArgument,
+  CompileError,
+  ExecutionError,
+  NullArgument,
+  Internal,
+  Frozen,
+  Cancelled,
+  Unknown,
+  MemoryLimit,
+  UnresolvedFunction,
+  InvalidPointer,
+  IterationLimit,
+  ResultType,
+} CelErrorCode;
+
+typedef enum CelLogLevel {
+  Error,
+  Warn,
+  Info,
+  Debug,
+} CelLogLevel;
+
+typedef enum CelMissingKeys {
+  MissingKeyError,
+  MissingKeyNull,
+  MissingKeyUnknown,
+} CelMissingKeys;
+
+typedef enum CelTruthiness {
+  TruthinessStrict,
+  TruthinessLenient,
+} CelTruthiness;
+
+typedef enum CelAttributeAccess {
+  AttributeAllow,
+  AttributeDeny,
+  AttributeReplace,
+} CelAttributeAccess;
+
+typedef enum CelAllocationKind {
+  AllocatedString,
+  AllocatedValues,
+  AllocatedResult,
+  AllocatedProgram,
+  AllocatedContext,
+} CelAllocationKind;
+
 typedef struct CelStringValue {
   const uint8_t *ptr;
   uintptr_t len;
@@ -47,6 +101,27 @@ typedef struct CelBytesValue {
   uintptr_t len;
 } CelBytesValue;
 
+typedef struct CelListValue {
+  const struct CelValue *items;
+  uintptr_t len;
+} CelListValue;
+
+typedef struct CelMapValue {
+  const struct CelValue *keys;
+  const struct CelValue *values;
+  uintptr_t len;
+} CelMapValue;
+
+typedef struct CelOptionalValue {
+  bool has_value;
+  const struct CelValue *value;
+} CelOptionalValue;
+
+typedef struct CelTimeValue {
+  int64_t seconds;
+  int32_t nanos;
+} CelTimeValue;
+
 typedef union CelValueData {
   bool bool_val;
   int64_t int_val;
@@ -54,6 +129,11 @@ typedef union CelValueData {
   double double_val;
   CelStringValue string_val;
   CelBytesValue bytes_val;
+  CelListValue list_val;
+  CelMapValue map_val;
+  CelOptionalValue optional_val;
+  CelTimeValue timestamp_val;
+  CelTimeValue duration_val;
 } CelValueData;
 
 typedef struct CelValue {
@@ -61,22 +141,428 @@ typedef struct CelValue {
   CelValueData data;
 } CelValue;
 
+typedef struct CelArity {
+  uintptr_t min_args;
+  uintptr_t max_args;
+  const struct CelValue *defaults;
+  uintptr_t defaults_len;
+} CelArity;
+
+typedef struct CelCompileError {
+  uintptr_t line;
+  uintptr_t column;
+  uintptr_t offset;
+  uintptr_t length;
+  CelStringValue message;
+  CelStringValue token;
+  CelStringValue source;
+} CelCompileError;
+
+typedef struct CelCompileOptions {
+  uintptr_t max_expression_length;
+  uintptr_t max_nesting_depth;
+  uintptr_t max_comprehensions;
+  const char *const *disabled_macros;
+  uintptr_t disabled_macros_len;
+  bool disable_regex;
+  uintptr_t max_regex_length;
+  uintptr_t max_regex_size;
+  bool string_ext;
+  bool math_ext;
+  bool list_ext;
+  bool optional_ext;
+  bool unicode_ext;
+  bool encoders_ext;
+  bool json_ext;
+} CelCompileOptions;
+
+typedef struct CelOptimization {
+  uintptr_t nodes_before;
+  uintptr_t nodes_after;
+} CelOptimization;
+
+typedef struct CelProgramMetrics {
+  uintptr_t nodes;
+  uintptr_t depth;
+  uintptr_t comprehensions;
+  uintptr_t calls;
+  uint64_t cost;
+} CelProgramMetrics;
+
+typedef struct CelProgramStats {
+  uint64_t executions;
+  uint64_t errors;
+  uint64_t total_time_ns;
+  CelStringValue last_error;
+} CelProgramStats;
+
+typedef struct CelMemoryStats {
+  uintptr_t strings;
+  uintptr_t string_bytes;
+  uintptr_t values;
+  uintptr_t results;
+  uintptr_t programs;
+  uintptr_t contexts;
+} CelMemoryStats;
+
+typedef void *(*CelMallocFn)(void *user_data, uintptr_t size);
+typedef void (*CelFreeFn)(void *user_data, void *ptr);
+typedef void *(*CelReallocFn)(void *user_data, void *ptr, uintptr_t size);
+
+typedef void (*CelLogCallback)(void *user_data, CelLogLevel level, const char *message);
+
+typedef void (*CelLeakCallback)(void *user_data,
+                                CelAllocationKind kind,
+                                const void *ptr,
+                                uintptr_t size);
+
+typedef bool (*CelVariableResolver)(void *user_data,
+                                    const char *name,
+                                    struct CelValue *out_value);
+
+typedef CelAttributeAccess (*CelAttributeHook)(void *user_data,
+                                               const char *path,
+                                               struct CelValue *out_value);
+
+typedef bool (*CelFunctionCallback)(void *user_data,
+                                    const struct CelValue *args,
+                                    uintptr_t args_len,
+                                    struct CelValue *out_value,
+                                    uint8_t *errbuf,
+                                    uintptr_t *errbuf_len);
+
+typedef struct CelCancelToken CelCancelToken;
+
+typedef struct CelArena CelArena;
+
+typedef struct CelExecuteOptions {
+  struct CelArena *arena;
+} CelExecuteOptions;
+
+typedef struct CelEvalResult CelEvalResult;
+
+typedef struct CelExecution CelExecution;
+
+typedef struct CelListBuilder CelListBuilder;
+
+typedef struct CelMapBuilder CelMapBuilder;
+
 typedef struct Context Context;
 
+typedef struct ContextSnapshot ContextSnapshot;
+
+typedef struct Env Env;
+
 typedef struct Program Program;
 
+typedef struct RuleSet RuleSet;
+
+typedef struct Bundle Bundle;
+
+typedef struct CelBundleReload CelBundleReload;
+
+typedef struct Template Template;
+
 struct Context *context_new(void);
 
 void context_free(struct Context *context);
 
+const struct Context *context_freeze(struct Context *context);
+
+bool context_is_frozen(const struct Context *context);
+
 bool context_add_variable(struct Context *context,
                          const char *name,
                          const struct CelValue *value,
                          uint8_t *errbuf,
                          uintptr_t *errbuf_len);
 
+bool context_add_variable_path(struct Context *context,
+                               const char *path,
+                               const struct CelValue *value,
+                               uint8_t *errbuf,
+                               uintptr_t *errbuf_len);
+
+bool context_add_variable_string_borrowed(struct Context *context,
+                                          const char *name,
+                                          const uint8_t *ptr,
+                                          uintptr_t len,
+                                          uint64_t lifetime_token,
+                                          uint8_t *errbuf,
+                                          uintptr_t *errbuf_len);
+
+uintptr_t context_invalidate_borrowed(struct Context *context,
+                                      uint64_t lifetime_token);
+
+bool context_add_variable_string_n(struct Context *context,
+                                  const char *name,
+                                  const uint8_t *ptr,
+                                  uintptr_t len,
+                                  uint8_t *errbuf,
+                                  uintptr_t *errbuf_len);
+
+bool context_add_variable_utf16(struct Context *context,
+                                const char *name,
+                                const uint16_t *ptr,
+                                uintptr_t len,
+                                uint8_t *errbuf,
+                                uintptr_t *errbuf_len);
+
+bool context_add_proto_descriptors(struct Context *context,
+                                   const uint8_t *ptr,
+                                   uintptr_t len,
+                                   uint8_t *errbuf,
+                                   uintptr_t *errbuf_len);
+
+bool context_add_variable_proto(struct Context *context,
+                                const char *name,
+                                const uint8_t *ptr,
+                                uintptr_t len,
+                                const char *type_name,
+                                uint8_t *errbuf,
+                                uintptr_t *errbuf_len);
+
+bool context_get_variable(const struct Context *context,
+                          const char *name,
+                          struct CelValue *out_value,
+                          uint8_t *errbuf,
+                          uintptr_t *errbuf_len);
+
+void cel_value_free(struct CelValue *value);
+
+bool cel_value_clone(const struct CelValue *value, struct CelValue *out_value);
+
+bool cel_value_timestamp_from_rfc3339(const char *text,
+                                      struct CelValue *out_value,
+                                      uint8_t *errbuf,
+                                      uintptr_t *errbuf_len);
+
+bool cel_value_duration_from_string(const char *text,
+                                    struct CelValue *out_value,
+                                    uint8_t *errbuf,
+                                    uintptr_t *errbuf_len);
+
+bool cel_value_bigint_from_string(const char *text,
+                                  struct CelValue *out_value,
+                                  uint8_t *errbuf,
+                                  uintptr_t *errbuf_len);
+
+bool cel_value_decimal_from_string(const char *text,
+                                   struct CelValue *out_value,
+                                   uint8_t *errbuf,
+                                   uintptr_t *errbuf_len);
+
+bool cel_value_type_from_name(const char *name,
+                              struct CelValue *out_value,
+                              uint8_t *errbuf,
+                              uintptr_t *errbuf_len);
+
+bool cel_value_debug_string(const struct CelValue *value, struct CelStringValue *out);
+
+bool cel_value_display_string(const struct CelValue *value, struct CelStringValue *out);
+
+bool cel_value_equals(const struct CelValue *a, const struct CelValue *b, bool *out_equal);
+
+bool cel_value_hash(const struct CelValue *value, uint64_t *out_hash);
+
+bool context_remove_variable(struct Context *context, const char *name);
+
+bool context_has_variable(const struct Context *context,
+                          const char *name,
+                          CelValueType *value_type);
+
+bool context_set_max_depth(struct Context *context, uintptr_t max_depth);
+
+bool context_set_max_memory(struct Context *context, uintptr_t max_bytes);
+
+bool context_set_max_iterations(struct Context *context, uintptr_t max_iterations);
+
+bool context_set_audit_log(struct Context *context, uintptr_t capacity);
+
+bool context_set_deterministic(struct Context *context, bool enabled);
+
+bool context_take_audit_log(const struct Context *context, struct CelStringValue *out_json);
+
+bool context_set_missing_keys(struct Context *context, CelMissingKeys policy);
+
+bool context_set_null_propagation(struct Context *context, bool enabled);
+
+bool context_set_numeric_promotion(struct Context *context, bool enabled);
+
+bool context_set_soft_errors(struct Context *context, bool enabled);
+
+bool context_set_reject_non_finite(struct Context *context, bool enabled);
+
+bool context_set_now(struct Context *context, int64_t epoch_seconds, uint32_t nanos);
+
+bool context_clear_now(struct Context *context);
+
+bool context_set_resolver(struct Context *context,
+                          CelVariableResolver resolver,
+                          void *user_data);
+
+bool context_set_attribute_hook(struct Context *context,
+                                CelAttributeHook hook,
+                                void *user_data);
+
+bool context_add_function(struct Context *context,
+                          const char *name,
+                          CelFunctionCallback callback,
+                          void *user_data);
+
+bool context_add_overload(struct Context *context,
+                          const char *name,
+                          const CelValueType *arg_types,
+                          uintptr_t arg_types_len,
+                          CelValueType result_type,
+                          CelFunctionCallback callback,
+                          void *user_data);
+
+bool context_add_member_function(struct Context *context,
+                                 const char *name,
+                                 CelFunctionCallback callback,
+                                 void *user_data);
+
+bool context_add_member_overload(struct Context *context,
+                                 const char *name,
+                                 const CelValueType *arg_types,
+                                 uintptr_t arg_types_len,
+                                 CelValueType result_type,
+                                 CelFunctionCallback callback,
+                                 void *user_data);
+
+bool context_add_variadic_function(struct Context *context,
+                                   const char *name,
+                                   const struct CelArity *arity,
+                                   CelFunctionCallback callback,
+                                   void *user_data,
+                                   uint8_t *errbuf,
+                                   uintptr_t *errbuf_len);
+
+bool context_add_variadic_member_function(struct Context *context,
+                                          const char *name,
+                                          const struct CelArity *arity,
+                                          CelFunctionCallback callback,
+                                          void *user_data,
+                                          uint8_t *errbuf,
+                                          uintptr_t *errbuf_len);
+
+bool context_remove_function(struct Context *context, const char *name);
+
+uintptr_t context_variable_count(const struct Context *context);
+
+bool context_variable_name_at(const struct Context *context,
+                              uintptr_t index,
+                              struct CelStringValue *out_name);
+
 void context_reset(struct Context *context);
 
+struct ContextSnapshot *context_snapshot(struct Context *context);
+
+bool context_restore(struct Context *context, const struct ContextSnapshot *snapshot);
+
+void context_snapshot_free(struct ContextSnapshot *snapshot);
+
+bool context_to_json(const struct Context *context,
+                     struct CelStringValue *out_json,
+                     uint8_t *errbuf,
+                     uintptr_t *errbuf_len);
+
+struct Context *context_from_json(const char *json,
+                                  uint8_t *errbuf,
+                                  uintptr_t *errbuf_len);
+
+struct CelListBuilder *cel_list_builder_new(void);
+
+bool cel_list_builder_push(struct CelListBuilder *builder,
+                           const struct CelValue *value,
+                           uint8_t *errbuf,
+                           uintptr_t *errbuf_len);
+
+bool cel_list_builder_push_list(struct CelListBuilder *builder,
+                                struct CelListBuilder *child);
+
+bool cel_list_builder_push_map(struct CelListBuilder *builder,
+                               struct CelMapBuilder *child);
+
+bool cel_list_builder_finish(struct CelListBuilder *builder,
+                             struct Context *context,
+                             const char *name,
+                             uint8_t *errbuf,
+                             uintptr_t *errbuf_len);
+
+void cel_list_builder_free(struct CelListBuilder *builder);
+
+struct CelMapBuilder *cel_map_builder_new(void);
+
+bool cel_map_builder_insert(struct CelMapBuilder *builder,
+                            const struct CelValue *key,
+                            const struct CelValue *value,
+                            uint8_t *errbuf,
+                            uintptr_t *errbuf_len);
+
+bool cel_map_builder_insert_list(struct CelMapBuilder *builder,
+                                 const struct CelValue *key,
+                                 struct CelListBuilder *child,
+                                 uint8_t *errbuf,
+                                 uintptr_t *errbuf_len);
+
+bool cel_map_builder_insert_map(struct CelMapBuilder *builder,
+                                const struct CelValue *key,
+                                struct CelMapBuilder *child,
+                                uint8_t *errbuf,
+                                uintptr_t *errbuf_len);
+
+bool cel_map_builder_finish(struct CelMapBuilder *builder,
+                            struct Context *context,
+                            const char *name,
+                            uint8_t *errbuf,
+                            uintptr_t *errbuf_len);
+
+void cel_map_builder_free(struct CelMapBuilder *builder);
+
+struct Env *cel_env_new(const struct CelCompileOptions *options,
+                        uint8_t *errbuf,
+                        uintptr_t *errbuf_len);
+
+void cel_env_free(struct Env *env);
+
+bool env_declare_variable(struct Env *env, const char *name);
+
+bool env_declare_function(struct Env *env, const char *name);
+
+bool env_allow_function(struct Env *env, const char *name);
+
+bool env_add_constant(struct Env *env,
+                      const char *name,
+                      const struct CelValue *value,
+                      uint8_t *errbuf,
+                      uintptr_t *errbuf_len);
+
+bool env_register_enum(struct Env *env,
+                       const char *name,
+                       const char *const *members,
+                       const int64_t *values,
+                       uintptr_t len);
+
+bool env_set_strict_variables(struct Env *env, bool strict);
+
+bool env_set_late_bound_functions(struct Env *env, bool late_bound);
+
+bool env_reserve_name(struct Env *env, const char *pattern);
+
+bool env_set_max_identifier_length(struct Env *env, uintptr_t max);
+
+bool env_disable_macro(struct Env *env, const char *name);
+
+bool env_set_max_comprehension_iterations(struct Env *env, uintptr_t max);
+
+bool env_compile(const struct Env *env,
+                 struct Program *program,
+                 const char *expression,
+                 uint8_t *errbuf,
+                 uintptr_t *errbuf_len);
+
 struct Program *program_new(void);
 
 void program_free(struct Program *program);
@@ -86,25 +572,307 @@ bool program_compile(struct Program *program,
                     uint8_t *errbuf,
                     uintptr_t *errbuf_len);
 
+bool program_compile_with_options(struct Program *program,
+                                  const char *expression,
+                                  const struct CelCompileOptions *options,
+                                  uint8_t *errbuf,
+                                  uintptr_t *errbuf_len);
+
+bool program_compile_error(const struct Program *program,
+                          struct CelCompileError *out_error);
+
+bool cel_error_render(const struct CelCompileError *error, struct CelStringValue *out);
+
+bool program_optimize(struct Program *program, struct CelOptimization *out);
+
+bool program_expect_type(struct Program *program, CelValueType value_type);
+
+bool program_inferred_type(const struct Program *program,
+                           bool *out_known,
+                           CelValueType *out_type);
+
+bool program_set_result_enum(struct Program *program,
+                             const struct Env *env,
+                             const char *enum_name);
+
+bool program_hash(const struct Program *program, uint64_t *out_hash);
+
+bool program_hash_hex(const struct Program *program, struct CelStringValue *out_hex);
+
+bool program_metrics(const struct Program *program, struct CelProgramMetrics *out);
+
+bool program_dependencies_json(const struct Program *program, struct CelStringValue *out_json);
+
+bool program_expanded_ast_json(const struct Program *program, struct CelStringValue *out_json);
+
+bool program_rename_variable(const char *expression,
+                             const char *old_name,
+                             const char *new_name,
+                             struct CelStringValue *out_expression,
+                             uint8_t *errbuf,
+                             uintptr_t *errbuf_len);
+
+bool cel_simplify(const char *expression,
+                  struct CelStringValue *out_expression,
+                  uint8_t *errbuf,
+                  uintptr_t *errbuf_len);
+
+bool cel_expressions_equivalent(const char *a,
+                                const char *b,
+                                bool simplify,
+                                bool *out_equivalent,
+                                uint8_t *errbuf,
+                                uintptr_t *errbuf_len);
+
+bool program_stats(const struct Program *program, struct CelProgramStats *out_stats);
+
+bool program_reset_stats(const struct Program *program);
+
 bool program_execute(const struct Program *program,
                     const struct Context *context,
                     struct CelValue *result,
                     uint8_t *errbuf,
                     uintptr_t *errbuf_len);
 
+struct CelEvalResult *program_execute_alloc(const struct Program *program,
+                                           const struct Context *context);
+
+struct CelEvalResult *program_execute_traced(const struct Program *program,
+                                            const struct Context *context,
+                                            struct CelStringValue *out_trace_json);
+
+struct CelArena *cel_arena_new(void);
+
+void cel_arena_free(struct CelArena *arena);
+
+uintptr_t cel_arena_allocated_bytes(const struct CelArena *arena);
+
+struct CelEvalResult *program_execute_with_options(const struct Program *program,
+                                                  const struct Context *context,
+                                                  const struct CelExecuteOptions *options);
+
+struct CelExecution *program_execute_start(const struct Program *program,
+                                          const struct Context *context,
+                                          const struct CelCancelToken *token);
+
+bool cel_execution_is_done(const struct CelExecution *execution);
+
+struct CelEvalResult *cel_execution_finish(struct CelExecution *execution);
+
+struct CelCancelToken *cel_cancel_token_new(void);
+
+bool cel_cancel(const struct CelCancelToken *token);
+
+bool cel_cancel_after(const struct CelCancelToken *token, uint64_t timeout_ms);
+
+bool cel_cancel_token_set_budget(const struct CelCancelToken *token, uint64_t cost);
+
+void cel_cancel_token_free(struct CelCancelToken *token);
+
+struct CelEvalResult *program_execute_cancellable(const struct Program *program,
+                                                 const struct Context *context,
+                                                 const struct CelCancelToken *token);
+
+struct CelEvalResult *program_execute_batch(const struct Program *program,
+                                           const struct Context *const *contexts,
+                                           uintptr_t count);
+
+struct RuleSet *ruleset_new(void);
+
+void ruleset_free(struct RuleSet *ruleset);
+
+bool ruleset_add(struct RuleSet *ruleset,
+                 const char *name,
+                 const char *expression,
+                 uint8_t *errbuf,
+                 uintptr_t *errbuf_len);
+
+bool ruleset_compile(struct RuleSet *ruleset,
+                     uint8_t *errbuf,
+                     uintptr_t *errbuf_len);
+
+struct CelEvalResult *ruleset_execute_all(const struct RuleSet *ruleset,
+                                         const struct Context *context);
+
+bool ruleset_set_predicate(struct RuleSet *ruleset,
+                           const char *expression,
+                           uint8_t *errbuf,
+                           uintptr_t *errbuf_len);
+
+struct CelEvalResult *ruleset_first_match(const struct RuleSet *ruleset,
+                                         const struct Context *context);
+
+struct Bundle *cel_bundle_load(const char *path,
+                               const struct CelCompileOptions *options,
+                               uint8_t *errbuf,
+                               uintptr_t *errbuf_len);
+
+struct Bundle *cel_bundle_load_bytes(const uint8_t *data,
+                                     uintptr_t len,
+                                     const struct CelCompileOptions *options,
+                                     uint8_t *errbuf,
+                                     uintptr_t *errbuf_len);
+
+void cel_bundle_free(struct Bundle *bundle);
+
+struct CelEvalResult *cel_bundle_execute_all(const struct Bundle *bundle,
+                                             const struct Context *context);
+
+struct CelEvalResult *cel_bundle_first_match(const struct Bundle *bundle,
+                                             const struct Context *context);
+
+uint64_t cel_bundle_version(const struct Bundle *bundle);
+
+struct CelBundleReload *cel_bundle_reload(const struct Bundle *bundle,
+                                          const char *path,
+                                          const struct CelCompileOptions *options);
+
+struct CelBundleReload *cel_bundle_reload_bytes(const struct Bundle *bundle,
+                                                const uint8_t *data,
+                                                uintptr_t len,
+                                                const struct CelCompileOptions *options);
+
+bool cel_bundle_reload_is_done(const struct CelBundleReload *reload);
+
+bool cel_bundle_reload_finish(struct CelBundleReload *reload,
+                              uint8_t *errbuf,
+                              uintptr_t *errbuf_len);
+
+bool cel_bundle_errors_json(const struct Bundle *bundle, struct CelStringValue *out_json);
+
+struct CelEvalResult *cel_bundle_execute(const struct Bundle *bundle,
+                                         const char *name,
+                                         const struct Context *context);
+
+struct Template *cel_template_compile(const char *source,
+                                      uint8_t *errbuf,
+                                      uintptr_t *errbuf_len);
+
+void cel_template_free(struct Template *template);
+
+bool template_instantiate(const struct Template *template,
+                          const char *params_json,
+                          struct CelStringValue *out_expression,
+                          uint8_t *errbuf,
+                          uintptr_t *errbuf_len);
+
+bool program_execute_json(const struct Program *program,
+                         const struct Context *context,
+                         struct CelStringValue *out_json,
+                         uint8_t *errbuf,
+                         uintptr_t *errbuf_len);
+
+bool program_execute_bool(const struct Program *program,
+                         const struct Context *context,
+                         bool *out_bool,
+                         CelTruthiness truthiness,
+                         uint8_t *errbuf,
+                         uintptr_t *errbuf_len);
+
+bool cel_eval_json(const char *expression,
+                  const char *variables_json,
+                  struct CelStringValue *out_json,
+                  uint8_t *errbuf,
+                  uintptr_t *errbuf_len);
+
+bool cel_result_is_ok(const struct CelEvalResult *result);
+
+CelErrorCode cel_result_error_code(const struct CelEvalResult *result);
+
+const struct CelValue *cel_result_value(const struct CelEvalResult *result);
+
+const struct CelStringValue *cel_result_error(const struct CelEvalResult *result);
+
+void cel_result_free(struct CelEvalResult *result);
+
 bool program_validate(const char *expression,
                      const uint8_t **variables,
                      uintptr_t *variables_len,
                      uint8_t *errbuf,
                      uintptr_t *errbuf_len);
 
+bool program_validate_with_options(const char *expression,
+                                  const struct CelCompileOptions *options,
+                                  uintptr_t *variables_len,
+                                  uint8_t *errbuf,
+                                  uintptr_t *errbuf_len);
+
+struct CelEvalResult *program_validate_full(const char *expression,
+                                            const struct CelCompileOptions *options);
+
+uint32_t cel_abi_version(void);
+
+const char *cel_capabilities_json(void);
+
+uintptr_t cel_intern_count(void);
+
+uintptr_t cel_intern_purge(void);
+
+bool cel_add_time_zone(const char *name, const uint8_t *data, uintptr_t len);
+
+bool cel_set_tzdata_dir(const char *dir);
+
+CelErrorCode cel_last_error(void);
+
+bool cel_last_error_message(uint8_t *buf,
+                            uintptr_t *buf_len);
+
+void cel_set_log_callback(CelLogCallback callback, void *user_data, CelLogLevel level);
+
+struct CelMemoryStats cel_memory_stats(void);
+
+bool cel_set_allocator(CelMallocFn malloc_fn,
+                       CelFreeFn free_fn,
+                       CelReallocFn realloc_fn,
+                       void *user_data);
+
+uintptr_t cel_dump_leaks(CelLeakCallback callback, void *user_data);
+
+uint64_t program_handle_new(void);
+
+uint64_t program_handle_new_in(uint64_t state);
+
+CelErrorCode program_handle_free(uint64_t program);
+
+CelErrorCode program_handle_compile(uint64_t program,
+                                    const char *expression,
+                                    uint8_t *errbuf,
+                                    uintptr_t *errbuf_len);
+
+CelErrorCode program_handle_execute(uint64_t program,
+                                    uint64_t context,
+                                    struct CelValue *result,
+                                    uint8_t *errbuf,
+                                    uintptr_t *errbuf_len);
+
+uint64_t context_handle_new(void);
+
+uint64_t context_handle_new_in(uint64_t state);
+
+CelErrorCode context_handle_free(uint64_t context);
+
+CelErrorCode context_handle_add_variable(uint64_t context,
+                                         const char *name,
+                                         const struct CelValue *value,
+                                         uint8_t *errbuf,
+                                         uintptr_t *errbuf_len);
+
+CelErrorCode context_handle_reset(uint64_t context);
+
 // String memory management functions
+bool cel_string_retain(const uint8_t *ptr);
+bool cel_string_release(const uint8_t *ptr);
+bool cel_string_is_owned(const uint8_t *ptr);
+uintptr_t cel_string_refcount(const uint8_t *ptr);
 void cel_string_free(const uint8_t *ptr);
 void cel_string_pool_clear(void);
 ]])
 
 local ERR_BUF_MAX_LEN = 4096
 
+-- Must match CEL_ABI_VERSION in include/cel_lua.h
+local CEL_ABI_VERSION = 2
+
 -- Library loading with fallback strategy
 local function load_library()
   local lib_name = ffi.os == "OSX" and "libcel_lua.dylib" or "libcel_lua.so"
@@ -165,24 +933,222 @@ end
 -- Load the library
 local clib = load_library()
 
+local abi_version = tonumber(clib.cel_abi_version())
+if abi_version ~= CEL_ABI_VERSION then
+  error(
+    ("libcel_lua ABI version mismatch: bindings expect %d, library provides %d"):format(
+      CEL_ABI_VERSION,
+      abi_version
+    ),
+    2
+  )
+end
+
 -- Create unified module
 local module = {
   clib = clib,
   ERR_BUF_MAX_LEN = ERR_BUF_MAX_LEN,
+  ABI_VERSION = CEL_ABI_VERSION,
   get_string_buf = get_string_buf,
   get_size_ptr = get_size_ptr,
   use_resty_core = use_resty_core,
 }
 
+-- How integers outside the 53 bits a Lua number holds exactly are returned:
+-- "force-float" rounds them to the nearest number, "preserve-int" returns them as
+-- int64_t/uint64_t cdata and "string-for-large-ints" as decimal strings
+local NUMBER_POLICIES = {
+  ["force-float"] = true,
+  ["preserve-int"] = true,
+  ["string-for-large-ints"] = true,
+}
+local MAX_SAFE_INTEGER = 2 ^ 53 - 1
+local number_policy = "force-float"
+
+function module.check_number_policy(policy)
+  if not NUMBER_POLICIES[policy] then
+    return nil, "Unknown number policy: " .. tostring(policy)
+  end
+  return true
+end
+
+-- The default for contexts and programs that do not set their own policy
+function module.set_number_policy(policy)
+  local ok, err = module.check_number_policy(policy)
+  if not ok then
+    return nil, err
+  end
+
+  number_policy = policy
+  return true
+end
+
+function module.number_policy()
+  return number_policy
+end
+
+-- An int64_t or uint64_t cdata as a Lua value, following `policy`. The bounds are
+-- compared as 64-bit integers, so -MAX_SAFE_INTEGER only applies to int64_t
+local function integer_to_lua_value(int, unsigned, policy)
+  local exact = int <= MAX_SAFE_INTEGER and (unsigned or int >= -MAX_SAFE_INTEGER)
+  if exact or policy == "force-float" then
+    return tonumber(int)
+  elseif policy == "preserve-int" then
+    return int
+  end
+  return (tostring(int):match("^-?%d+"))
+end
+
+-- Helper function to convert CelValue to Lua value
+-- Copies everything out; the memory behind `cel_val` stays owned by its producer.
+-- Large integers follow `policy`, or the module default when it is nil
+local function cel_value_to_lua_value(cel_val, policy)
+  policy = policy or number_policy
+  if cel_val.value_type == module.Null then
+    return nil
+  elseif cel_val.value_type == module.Bool then
+    return cel_val.data.bool_val
+  elseif cel_val.value_type == module.Int then
+    return integer_to_lua_value(cel_val.data.int_val, false, policy)
+  elseif cel_val.value_type == module.Uint then
+    return integer_to_lua_value(cel_val.data.uint_val, true, policy)
+  elseif cel_val.value_type == module.Double then
+    return cel_val.data.double_val
+  elseif cel_val.value_type == module.String then
+    local string_val = cel_val.data.string_val
+    return ffi.string(string_val.ptr, string_val.len)
+  elseif cel_val.value_type == module.Bytes then
+    local bytes_val = cel_val.data.bytes_val
+    return ffi.string(bytes_val.ptr, bytes_val.len)
+  elseif cel_val.value_type == module.List then
+    local list_val = cel_val.data.list_val
+    local result = {}
+    for i = 0, tonumber(list_val.len) - 1 do
+      local item, err = cel_value_to_lua_value(list_val.items[i], policy)
+      if err then
+        return nil, err
+      end
+      result[i + 1] = item
+    end
+    return result
+  elseif cel_val.value_type == module.Map then
+    local map_val = cel_val.data.map_val
+    local result = {}
+    for i = 0, tonumber(map_val.len) - 1 do
+      local key, err = cel_value_to_lua_value(map_val.keys[i], policy)
+      if err then
+        return nil, err
+      end
+      local value
+      value, err = cel_value_to_lua_value(map_val.values[i], policy)
+      if err then
+        return nil, err
+      end
+      result[key] = value
+    end
+    return result
+  elseif cel_val.value_type == module.Optional then
+    -- An empty optional reads as nil, like a null value
+    local optional_val = cel_val.data.optional_val
+    if not optional_val.has_value then
+      return nil
+    end
+    return cel_value_to_lua_value(optional_val.value[0], policy)
+  elseif cel_val.value_type == module.Timestamp then
+    -- Seconds since the Unix epoch, as os.time() counts them
+    local time_val = cel_val.data.timestamp_val
+    return tonumber(time_val.seconds) + time_val.nanos / 1e9
+  elseif cel_val.value_type == module.Duration then
+    local time_val = cel_val.data.duration_val
+    return tonumber(time_val.seconds) + time_val.nanos / 1e9
+  elseif cel_val.value_type == module.BigInt then
+    -- A number when that is exact or floats are forced, and the digits otherwise
+    local string_val = cel_val.data.string_val
+    local digits = ffi.string(string_val.ptr, string_val.len)
+    local n = tonumber(digits)
+    if math.abs(n) <= MAX_SAFE_INTEGER or policy == "force-float" then
+      return n
+    end
+    return digits
+  elseif cel_val.value_type == module.Decimal then
+    -- Always the text, such as "19.99", which a Lua number could not hold exactly
+    local string_val = cel_val.data.string_val
+    return ffi.string(string_val.ptr, string_val.len)
+  elseif cel_val.value_type == module.Type then
+    -- The type's name, such as "int" or "google.protobuf.Timestamp"
+    local string_val = cel_val.data.string_val
+    return ffi.string(string_val.ptr, string_val.len)
+  else
+    return nil, "Unsupported value type"
+  end
+end
+
+module.cel_value_to_lua_value = cel_value_to_lua_value
+
+-- Build a CelCompileOptions from a Lua table; the returned name array must stay
+-- referenced until the call that uses the options returns
+local function compile_options(options)
+  if not options then
+    return nil
+  end
+
+  local macros = options.disabled_macros or {}
+  local names = ffi.new("const char *[?]", #macros)
+  for i = 1, #macros do
+    names[i - 1] = macros[i]
+  end
+
+  local c_options = ffi.new("CelCompileOptions", {
+    max_expression_length = options.max_expression_length or 0,
+    max_nesting_depth = options.max_nesting_depth or 0,
+    max_comprehensions = options.max_comprehensions or 0,
+    disabled_macros = names,
+    disabled_macros_len = #macros,
+    disable_regex = options.disable_regex or false,
+    max_regex_length = options.max_regex_length or 0,
+    max_regex_size = options.max_regex_size or 0,
+    string_ext = options.string_ext or false,
+    math_ext = options.math_ext or false,
+    list_ext = options.list_ext or false,
+    optional_ext = options.optional_ext or false,
+    unicode_ext = options.unicode_ext or false,
+    encoders_ext = options.encoders_ext or false,
+    json_ext = options.json_ext or false,
+  })
+  return c_options, names
+end
+
+module.compile_options = compile_options
+
 -- Add cleanup functions
 module.context_free = function(c)
   clib.context_free(c)
 end
 
+module.context_snapshot_free = function(s)
+  clib.context_snapshot_free(s)
+end
+
+module.env_free = function(e)
+  clib.cel_env_free(e)
+end
+
 module.program_free = function(p)
   clib.program_free(p)
 end
 
+module.ruleset_free = function(r)
+  clib.ruleset_free(r)
+end
+
+module.bundle_free = function(b)
+  clib.cel_bundle_free(b)
+end
+
+module.template_free = function(t)
+  clib.cel_template_free(t)
+end
+
 -- Add CEL value type constants
 if use_resty_core then
   -- OpenResty environment: use dynamic constants from the loaded library
@@ -195,8 +1161,12 @@ if use_resty_core then
   module.Bytes = clib.Bytes
   module.List = clib.List
   module.Map = clib.Map
+  module.Type = clib.Type
+  module.Optional = clib.Optional
   module.Timestamp = clib.Timestamp
   module.Duration = clib.Duration
+  module.BigInt = clib.BigInt
+  module.Decimal = clib.Decimal
 else
   -- Non-OpenResty environment: use hardcoded constants
   module.Null = 0
@@ -208,8 +1178,12 @@ else
   module.Bytes = 6
   module.List = 7
   module.Map = 8
-  module.Timestamp = 9
-  module.Duration = 10
+  module.Type = 9
+  module.Optional = 10
+  module.Timestamp = 11
+  module.Duration = 12
+  module.BigInt = 13
+  module.Decimal = 14
 end
 
 return module
@@ -12,8 +12,10 @@ local setmetatable = setmetatable
 local ERR_BUF_MAX_LEN = cdefs.ERR_BUF_MAX_LEN
 local clib = cdefs.clib
 local context_free = cdefs.context_free
+local context_snapshot_free = cdefs.context_snapshot_free
 local get_string_buf = cdefs.get_string_buf
 local get_size_ptr = cdefs.get_size_ptr
+local cel_value_to_lua_value = cdefs.cel_value_to_lua_value
 
 -- Helper function to convert Lua values to CelValue
 local function lua_value_to_cel_value(lua_val, cel_val)
@@ -27,7 +29,8 @@ local function lua_value_to_cel_value(lua_val, cel_val)
     cel_val.data.bool_val = lua_val
     return true
   elseif val_type == "number" then
-    if lua_val == math.floor(lua_val) then
+    -- Infinities equal their floor but have no integer form
+    if lua_val == math.floor(lua_val) and lua_val ~= math.huge and lua_val ~= -math.huge then
       cel_val.value_type = cdefs.Int
       cel_val.data.int_val = lua_val
     else
@@ -40,11 +43,117 @@ local function lua_value_to_cel_value(lua_val, cel_val)
     cel_val.data.string_val.ptr = lua_val
     cel_val.data.string_val.len = #lua_val
     return true
+  elseif val_type == "cdata" and ffi.istype("int64_t", lua_val) then
+    -- 64-bit integers, as the "preserve-int" number policy returns them
+    cel_val.value_type = cdefs.Int
+    cel_val.data.int_val = lua_val
+    return true
+  elseif val_type == "cdata" and ffi.istype("uint64_t", lua_val) then
+    cel_val.value_type = cdefs.Uint
+    cel_val.data.uint_val = lua_val
+    return true
+  elseif val_type == "cdata" and ffi.istype("CelValue", lua_val) then
+    -- Built by cel.value; a plain copy borrows any text, which the library copies
+    ffi.copy(cel_val, lua_val, ffi.sizeof("CelValue"))
+    return true
   else
     return false
   end
 end
 
+-- A table is a list when every key is an index in 1..#t, so {} is an empty list
+local function is_list(t)
+  local n = #t
+  for k in pairs(t) do
+    if type(k) ~= "number" or k < 1 or k > n or k ~= math.floor(k) then
+      return false
+    end
+  end
+  return true
+end
+
+-- Build a table into a list or map builder, one element at a time. Returns the
+-- builder and whether it is a map, or nil and an error message
+local build_table
+
+local function build_list(t, errbuf, errbuf_len)
+  local builder = ffi_gc(clib.cel_list_builder_new(), clib.cel_list_builder_free)
+  local cel_value = ffi_new("CelValue[1]")
+
+  for i = 1, #t do
+    local item = t[i]
+    if type(item) == "table" then
+      local child, is_map = build_table(item, errbuf, errbuf_len)
+      if not child then
+        return nil, is_map
+      end
+      -- Pushing consumes the child, so it must no longer be collected
+      ffi_gc(child, nil)
+      if is_map then
+        clib.cel_list_builder_push_map(builder, child)
+      else
+        clib.cel_list_builder_push_list(builder, child)
+      end
+    else
+      if not lua_value_to_cel_value(item, cel_value[0]) then
+        return nil, "Failed to convert value"
+      end
+      errbuf_len[0] = ERR_BUF_MAX_LEN
+      if not clib.cel_list_builder_push(builder, cel_value, errbuf, errbuf_len) then
+        return nil, ffi_string(errbuf, errbuf_len[0])
+      end
+    end
+  end
+
+  return builder, false
+end
+
+local function build_map(t, errbuf, errbuf_len)
+  local builder = ffi_gc(clib.cel_map_builder_new(), clib.cel_map_builder_free)
+  local cel_key = ffi_new("CelValue[1]")
+  local cel_value = ffi_new("CelValue[1]")
+
+  for k, v in pairs(t) do
+    if not lua_value_to_cel_value(k, cel_key[0]) then
+      return nil, "Failed to convert key"
+    end
+
+    local ok
+    errbuf_len[0] = ERR_BUF_MAX_LEN
+    if type(v) == "table" then
+      local child, is_map = build_table(v, errbuf, errbuf_len)
+      if not child then
+        return nil, is_map
+      end
+      ffi_gc(child, nil)
+      errbuf_len[0] = ERR_BUF_MAX_LEN
+      if is_map then
+        ok = clib.cel_map_builder_insert_map(builder, cel_key, child, errbuf, errbuf_len)
+      else
+        ok = clib.cel_map_builder_insert_list(builder, cel_key, child, errbuf, errbuf_len)
+      end
+    else
+      if not lua_value_to_cel_value(v, cel_value[0]) then
+        return nil, "Failed to convert value"
+      end
+      ok = clib.cel_map_builder_insert(builder, cel_key, cel_value, errbuf, errbuf_len)
+    end
+
+    if not ok then
+      return nil, ffi_string(errbuf, errbuf_len[0])
+    end
+  end
+
+  return builder, true
+end
+
+build_table = function(t, errbuf, errbuf_len)
+  if is_list(t) then
+    return build_list(t, errbuf, errbuf_len)
+  end
+  return build_map(t, errbuf, errbuf_len)
+end
+
 function _M.new()
   local context = clib.context_new()
   local c = setmetatable({
@@ -54,11 +163,60 @@ function _M.new()
   return c
 end
 
+-- Free the native context and its variables now rather than when it is garbage
+-- collected. Later calls fail, and closing again does nothing. Refused while an
+-- `execute_async` call is reading the context
+function _M:close()
+  if (self.running or 0) > 0 then
+    return nil, "Context is in use by an async execution"
+  end
+
+  local context = self.context
+  if context ~= nil then
+    self.context = nil
+    context_free(ffi_gc(context, nil))
+  end
+  return true
+end
+
 function _M:add_variable(name, value)
   local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
   local errbuf_len = get_size_ptr()
   errbuf_len[0] = ERR_BUF_MAX_LEN
 
+  local ok
+  if type(value) == "string" then
+    -- Length-based path: keeps interior NULs and passes binary data as bytes
+    ok = clib.context_add_variable_string_n(self.context, name, value, #value,
+                                            errbuf, errbuf_len)
+    if not ok then
+      return nil, ffi_string(errbuf, errbuf_len[0])
+    end
+
+    return true
+  end
+
+  if type(value) == "table" then
+    -- Fed through builders element by element rather than as one JSON document
+    local builder, is_map = build_table(value, errbuf, errbuf_len)
+    if not builder then
+      return nil, is_map
+    end
+
+    ffi_gc(builder, nil)
+    errbuf_len[0] = ERR_BUF_MAX_LEN
+    if is_map then
+      ok = clib.cel_map_builder_finish(builder, self.context, name, errbuf, errbuf_len)
+    else
+      ok = clib.cel_list_builder_finish(builder, self.context, name, errbuf, errbuf_len)
+    end
+    if not ok then
+      return nil, ffi_string(errbuf, errbuf_len[0])
+    end
+
+    return true
+  end
+
   -- Convert Lua value to CelValue
   local cel_value = ffi_new("CelValue[1]")
   local success = lua_value_to_cel_value(value, cel_value[0])
@@ -67,7 +225,7 @@ function _M:add_variable(name, value)
     return nil, "Failed to convert value"
   end
 
-  local ok = clib.context_add_variable(self.context, name, cel_value, errbuf, errbuf_len)
+  ok = clib.context_add_variable(self.context, name, cel_value, errbuf, errbuf_len)
 
   if not ok then
     return nil, ffi_string(errbuf, errbuf_len[0])
@@ -76,8 +234,339 @@ function _M:add_variable(name, value)
   return true
 end
 
+-- Bind a string without copying it into the context. The context keeps the Lua
+-- string alive until invalidate_borrowed(token) releases it
+function _M:add_variable_borrowed(name, value, token)
+  token = token or 0
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  if type(value) ~= "string" then
+    return nil, "Borrowed value must be a string"
+  end
+  if not clib.context_add_variable_string_borrowed(self.context, name, value, #value, token,
+                                                   errbuf, errbuf_len) then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
+
+  self.borrowed = self.borrowed or {}
+  local strings = self.borrowed[token] or {}
+  strings[#strings + 1] = value
+  self.borrowed[token] = strings
+  return true
+end
+
+function _M:invalidate_borrowed(token)
+  token = token or 0
+  if clib.context_is_frozen(self.context) then
+    return nil, "Context is frozen"
+  end
+  local unbound = tonumber(clib.context_invalidate_borrowed(self.context, token))
+
+  if self.borrowed then
+    self.borrowed[token] = nil
+  end
+  return unbound
+end
+
+-- Bind a value at a dotted path such as "request.headers.authorization",
+-- creating or extending the maps along the way
+function _M:add_variable_path(path, value)
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  local cel_value = ffi_new("CelValue[1]")
+  if not lua_value_to_cel_value(value, cel_value[0]) then
+    return nil, "Failed to convert value"
+  end
+
+  if not clib.context_add_variable_path(self.context, path, cel_value, errbuf, errbuf_len) then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
+
+  return true
+end
+
+function _M:remove_variable(name)
+  return clib.context_remove_variable(self.context, name)
+end
+
+-- Returns whether `name` is bound, and if so its CelValueType (see cel.cdefs)
+function _M:has_variable(name)
+  local value_type = ffi_new("CelValueType[1]")
+  if not clib.context_has_variable(self.context, name, value_type) then
+    return false
+  end
+
+  return true, tonumber(value_type[0])
+end
+
+function _M:get_variable(name)
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  local cel_value = ffi_new("CelValue[1]")
+  if not clib.context_get_variable(self.context, name, cel_value, errbuf, errbuf_len) then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
+
+  local value, err = cel_value_to_lua_value(cel_value[0], self.number_policy)
+  clib.cel_value_free(cel_value)
+  return value, err
+end
+
+-- Returns the sorted names of all bound variables
+function _M:list_variables()
+  local names = {}
+  local name = ffi_new("CelStringValue[1]")
+  for i = 0, tonumber(clib.context_variable_count(self.context)) - 1 do
+    if clib.context_variable_name_at(self.context, i, name) then
+      names[#names + 1] = ffi_string(name[0].ptr, name[0].len)
+    end
+  end
+  table.sort(names)
+  return names
+end
+
+-- Only available when the library was built with the `proto` feature
+if pcall(function() return clib.context_add_variable_proto end) then
+  -- Register the message types in a serialized FileDescriptorSet, as written by
+  -- `protoc --descriptor_set_out`
+  function _M:add_proto_descriptors(descriptor_set)
+    local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+    local errbuf_len = get_size_ptr()
+    errbuf_len[0] = ERR_BUF_MAX_LEN
+
+    if not clib.context_add_proto_descriptors(self.context, descriptor_set, #descriptor_set,
+                                              errbuf, errbuf_len) then
+      return nil, ffi_string(errbuf, errbuf_len[0])
+    end
+
+    return true
+  end
+
+  -- Bind a serialized message of a registered type, e.g. "acme.Request"
+  function _M:add_variable_proto(name, message, type_name)
+    local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+    local errbuf_len = get_size_ptr()
+    errbuf_len[0] = ERR_BUF_MAX_LEN
+
+    if not clib.context_add_variable_proto(self.context, name, message, #message, type_name,
+                                           errbuf, errbuf_len) then
+      return nil, ffi_string(errbuf, errbuf_len[0])
+    end
+
+    return true
+  end
+end
+
 function _M:reset()
   clib.context_reset(self.context)
 end
 
+-- Make the context read-only, for sharing one base environment between many
+-- coroutines; changing it afterwards fails with "Context is frozen"
+function _M:freeze()
+  clib.context_freeze(self.context)
+  return self
+end
+
+function _M:is_frozen()
+  return clib.context_is_frozen(self.context)
+end
+
+-- Deeper expressions fail with "expression too deep" instead of overflowing the
+-- stack; 0 removes the limit
+function _M:set_max_depth(max_depth)
+  clib.context_set_max_depth(self.context, max_depth)
+end
+
+-- What selecting a key that a map lacks evaluates to: "error" (the default) fails
+-- the execution, "null" gives null, and "unknown" fails it with "Result is unknown"
+-- and the missing keys, unless the result does not depend on them
+local MISSING_KEYS = {
+  error = "MissingKeyError",
+  null = "MissingKeyNull",
+  unknown = "MissingKeyUnknown",
+}
+
+function _M:set_missing_keys(policy)
+  local missing_keys = MISSING_KEYS[policy]
+  if not missing_keys then
+    return nil, "Unknown missing key policy: " .. tostring(policy)
+  end
+
+  if not clib.context_set_missing_keys(self.context, missing_keys) then
+    return nil, "Context is frozen"
+  end
+
+  return true
+end
+
+-- How integers beyond 2^53 come back from `get_variable` and from programs executed
+-- against this context, overriding the `cel.set_number_policy` default
+function _M:set_number_policy(policy)
+  local ok, err = cdefs.check_number_policy(policy)
+  if not ok then
+    return nil, err
+  end
+
+  self.number_policy = policy
+  return true
+end
+
+-- With `enabled`, operators applied to nil (null) give nil instead of failing, as
+-- in SQL, so `row.price * row.qty` is nil for a row without a price
+function _M:set_null_propagation(enabled)
+  if not clib.context_set_null_propagation(self.context, enabled and true or false) then
+    return nil, "Context is frozen"
+  end
+
+  return true
+end
+
+-- With `enabled`, arithmetic, comparisons and equality mix int, uint and double
+-- operands, so `count < 1.5` works when `count` is an integer
+function _M:set_numeric_promotion(enabled)
+  if not clib.context_set_numeric_promotion(self.context, enabled and true or false) then
+    return nil, "Context is frozen"
+  end
+
+  return true
+end
+
+-- With `enabled`, `add_variable` fails for NaN and infinite numbers instead of
+-- binding them; NaN is otherwise unequal to everything and unordered, as CEL specifies
+function _M:set_reject_non_finite(enabled)
+  if not clib.context_set_reject_non_finite(self.context, enabled and true or false) then
+    return nil, "Context is frozen"
+  end
+
+  return true
+end
+
+-- Limit the bytes one execution may allocate for the strings, bytes, lists and
+-- maps it builds; executions past the limit fail with "exceeded its memory limit",
+-- and 0 removes the limit
+function _M:set_max_memory(max_bytes)
+  if not clib.context_set_max_memory(self.context, max_bytes) then
+    return nil, "Context is frozen"
+  end
+
+  return true
+end
+
+-- Limit the comprehension iterations one execution may run, counted across all of its
+-- comprehensions; executions past the limit fail with "comprehension iterations", and
+-- 0 removes the limit
+function _M:set_max_iterations(max_iterations)
+  if not clib.context_set_max_iterations(self.context, max_iterations) then
+    return nil, "Context is frozen"
+  end
+
+  return true
+end
+
+-- Make executions reproducible across runs and platforms: `now()` fails unless
+-- `set_now` fixed the instant, and comprehensions over a map visit its keys in
+-- sorted order
+function _M:set_deterministic(enabled)
+  if not clib.context_set_deterministic(self.context, enabled) then
+    return nil, "Context is frozen"
+  end
+
+  return true
+end
+
+-- Keep an audit record of each of the last `capacity` executions against the
+-- context, for `take_audit_log`; 0 stops recording, and any call discards the records
+-- kept
+function _M:set_audit_log(capacity)
+  if not clib.context_set_audit_log(self.context, capacity) then
+    return nil, "Context is frozen"
+  end
+
+  return true
+end
+
+-- Remove the audit records kept and return them as a JSON array, oldest first: each
+-- holds the program's fingerprint, the bound variable names, the result or error and
+-- the duration in nanoseconds
+function _M:take_audit_log()
+  local out = ffi_new("CelStringValue[1]")
+  clib.context_take_audit_log(self.context, out)
+  local json = ffi_string(out[0].ptr, out[0].len)
+  clib.cel_string_release(out[0].ptr)
+  return json
+end
+
+-- Fix the instant `now()` returns, so that expressions comparing against the
+-- current time are reproducible; `nanos` defaults to 0
+function _M:set_now(seconds, nanos)
+  if not clib.context_set_now(self.context, seconds, nanos or 0) then
+    return nil, "Timestamp out of range"
+  end
+
+  return true
+end
+
+-- Go back to reading the system clock once per evaluation
+function _M:clear_now()
+  clib.context_clear_now(self.context)
+end
+
+-- The variables as JSON that `context.from_json` rebuilds, keeping bytes,
+-- timestamps and other non-JSON types intact
+function _M:to_json()
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  local out = ffi_new("CelStringValue[1]")
+  if not clib.context_to_json(self.context, out, errbuf, errbuf_len) then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
+
+  local json = ffi_string(out[0].ptr, out[0].len)
+  clib.cel_string_release(out[0].ptr)
+  return json
+end
+
+function _M.from_json(json)
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  local context = clib.context_from_json(json, errbuf, errbuf_len)
+  if context == nil then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
+
+  return setmetatable({
+    context = ffi_gc(context, context_free),
+  }, _MT)
+end
+
+-- Take a snapshot that `restore` can roll the variables back to. No variables
+-- are copied, so snapshot once and restore after each evaluation:
+--
+--   local snap = ctx:snapshot()
+--   ctx:add_variable("request_id", id)
+--   program:execute(ctx)
+--   ctx:restore(snap)
+function _M:snapshot()
+  return ffi_gc(clib.context_snapshot(self.context), context_snapshot_free)
+end
+
+function _M:restore(snapshot)
+  if not clib.context_restore(self.context, snapshot) then
+    return nil, "Invalid snapshot"
+  end
+
+  return true
+end
+
 return _M
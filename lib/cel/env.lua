@@ -0,0 +1,154 @@
+local _M = {}
+local _MT = { __index = _M }
+
+local ffi = require("ffi")
+local cdefs = require("cel.cdefs")
+local program = require("cel.program")
+local context = require("cel.context")
+
+local ffi_gc = ffi.gc
+local ffi_new = ffi.new
+local ffi_string = ffi.string
+local ipairs = ipairs
+local pairs = pairs
+local setmetatable = setmetatable
+local table_sort = table.sort
+
+local ERR_BUF_MAX_LEN = cdefs.ERR_BUF_MAX_LEN
+local clib = cdefs.clib
+local env_free = cdefs.env_free
+local get_string_buf = cdefs.get_string_buf
+local get_size_ptr = cdefs.get_size_ptr
+local compile_options = cdefs.compile_options
+
+-- Create an environment from the same options table `program:compile` accepts
+function _M.new(options)
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  local c_options, names = compile_options(options)
+  local env = clib.cel_env_new(c_options, errbuf, errbuf_len)
+  local _ = names -- keep the name array alive across the call
+
+  if env == nil then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
+
+  return setmetatable({
+    env = ffi_gc(env, env_free),
+  }, _MT)
+end
+
+function _M:declare_variable(name)
+  return clib.env_declare_variable(self.env, name)
+end
+
+-- Allow programs compiled in this environment to call a host-provided function
+function _M:declare_function(name)
+  return clib.env_declare_function(self.env, name)
+end
+
+-- Restrict programs compiled in this environment to the functions allowed so far
+function _M:allow_function(name)
+  return clib.env_allow_function(self.env, name)
+end
+
+-- Define a constant that programs compiled from now on see in place of the variable
+-- `name`, folded into them as they compile. Values convert as for
+-- `context:add_variable`, but timestamps, durations and NaN have no literal form
+function _M:add_constant(name, value)
+  -- Converted through a scratch context, so tables become lists and maps as usual
+  local scratch = context.new()
+  local ok, err = scratch:add_variable(name, value)
+  if not ok then
+    return nil, err
+  end
+
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  local cel_value = ffi_new("CelValue[1]")
+  if not clib.context_get_variable(scratch.context, name, cel_value, errbuf, errbuf_len) then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+  ok = clib.env_add_constant(self.env, name, cel_value, errbuf, errbuf_len)
+  clib.cel_value_free(cel_value)
+  if not ok then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
+
+  return true
+end
+
+-- Register an enum from a table of member names to ints, so programs compiled from
+-- now on can write `Level.INFO` for the member's int
+function _M:register_enum(name, members)
+  local names = {}
+  for member in pairs(members) do
+    names[#names + 1] = member
+  end
+  table_sort(names)
+
+  local c_names = ffi_new("const char *[?]", #names)
+  local c_values = ffi_new("int64_t[?]", #names)
+  for i, member in ipairs(names) do
+    c_names[i - 1] = member
+    c_values[i - 1] = members[member]
+  end
+
+  if not clib.env_register_enum(self.env, name, c_names, c_values, #names) then
+    return nil, "Invalid enum name or member name"
+  end
+  return true
+end
+
+-- Reject expressions that reference variables not declared with declare_variable
+function _M:set_strict_variables(strict)
+  return clib.env_set_strict_variables(self.env, strict and true or false)
+end
+
+-- Accept calls to undeclared functions, resolving them against the context on execution
+function _M:set_late_bound_functions(enabled)
+  return clib.env_set_late_bound_functions(self.env, enabled and true or false)
+end
+
+-- Reject expressions that reference `pattern`; a trailing `*` reserves a prefix
+function _M:reserve_name(pattern)
+  return clib.env_reserve_name(self.env, pattern)
+end
+
+-- Reject expressions with an identifier longer than `max` bytes, or none for 0
+function _M:set_max_identifier_length(max)
+  return clib.env_set_max_identifier_length(self.env, max)
+end
+
+-- Reject expressions that use the macro `name`, such as "map" or "filter"
+function _M:disable_macro(name)
+  return clib.env_disable_macro(self.env, name)
+end
+
+-- Fail executions once a comprehension ranges over more than `max` elements, or never
+-- for 0; applies to programs compiled from now on
+function _M:set_max_comprehension_iterations(max)
+  return clib.env_set_max_comprehension_iterations(self.env, max)
+end
+
+-- Compile into a new program, or return nil, err and the compile error details
+function _M:compile(expression)
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  local prog = program.new()
+  if not clib.env_compile(self.env, prog.program, expression, errbuf, errbuf_len) then
+    return nil, ffi_string(errbuf, errbuf_len[0]), prog:compile_error()
+  end
+
+  prog.compiled = true
+  return prog
+end
+
+return _M
@@ -0,0 +1,139 @@
+-- Generated by `make flat-lua` from include/cel_lua.h. Do not edit.
+--
+-- Declarations of the flat C API, whose functions take and return only scalars,
+-- C strings and handles, for hosts that use the LuaJIT FFI directly:
+--
+--   local flat = require("cel.flat")
+--   local lib = flat.load("/path/to/libcel_lua.so")
+--   local state = flat.state(lib)
+--   local program = lib.program_handle_new_in(state)
+--   local context = lib.context_handle_new_in(state)
+--   lib.cel_flat_compile(program, "n * 2")
+--   lib.cel_flat_set_int(context, "n", 21)
+--   local result = lib.cel_flat_execute(program, context)
+--   print(lib.cel_flat_result_int(result)) -- 42
+--   lib.cel_flat_result_free(result)
+
+local ffi = require("ffi")
+
+local CEL_ABI_VERSION = 2
+
+local declarations = {
+  [[typedef enum CelErrorCode {
+  Ok,
+  InvalidHandle,
+  InvalidArgument,
+  CompileError,
+  ExecutionError,
+  NullArgument,
+  Internal,
+  Frozen,
+  Cancelled,
+  Unknown,
+  MemoryLimit,
+  UnresolvedFunction,
+  InvalidPointer,
+  IterationLimit,
+  ResultType,
+} CelErrorCode;]],
+  [[typedef enum CelValueType {
+  Null,
+  Bool,
+  Int,
+  Uint,
+  Double,
+  String,
+  Bytes,
+  List,
+  Map,
+  // A type, as `type()` returns it, carried as its name such as `int` in `string_val`
+  Type,
+  Optional,
+  Timestamp,
+  Duration,
+  // A 128-bit signed integer, carried as decimal digits in `string_val`
+  BigInt,
+  // An exact decimal number, carried as text such as `-12.50` in `string_val`
+  Decimal,
+} CelValueType;]],
+  [[uint32_t cel_abi_version(void);]],
+  [[CelErrorCode cel_flat_compile(uint64_t program, const char *expression);]],
+  [[const char *cel_flat_error_message(void);]],
+  [[uint64_t cel_flat_execute(uint64_t program, uint64_t context);]],
+  [[bool cel_flat_result_bool(uint64_t result);]],
+  [[double cel_flat_result_double(uint64_t result);]],
+  [[CelErrorCode cel_flat_result_free(uint64_t result);]],
+  [[int64_t cel_flat_result_int(uint64_t result);]],
+  [[const char *cel_flat_result_json(uint64_t result);]],
+  [[uintptr_t cel_flat_result_len(uint64_t result);]],
+  [[const uint8_t *cel_flat_result_string(uint64_t result);]],
+  [[CelValueType cel_flat_result_type(uint64_t result);]],
+  [[uint64_t cel_flat_result_uint(uint64_t result);]],
+  [[CelErrorCode cel_flat_set_bool(uint64_t context, const char *name, bool value);]],
+  [[CelErrorCode cel_flat_set_bytes(uint64_t context, const char *name, const char *value, uintptr_t len);]],
+  [[CelErrorCode cel_flat_set_double(uint64_t context, const char *name, double value);]],
+  [[CelErrorCode cel_flat_set_int(uint64_t context, const char *name, int64_t value);]],
+  [[CelErrorCode cel_flat_set_json(uint64_t context, const char *name, const char *json);]],
+  [[CelErrorCode cel_flat_set_null(uint64_t context, const char *name);]],
+  [[CelErrorCode cel_flat_set_string(uint64_t context, const char *name, const char *value, uintptr_t len);]],
+  [[CelErrorCode cel_flat_set_uint(uint64_t context, const char *name, uint64_t value);]],
+  [[CelErrorCode cel_last_error(void);]],
+  [[CelErrorCode cel_state_free(uint64_t state);]],
+  [[uint64_t cel_state_new(void);]],
+  [[CelErrorCode context_handle_free(uint64_t context);]],
+  [[uint64_t context_handle_new(void);]],
+  [[uint64_t context_handle_new_in(uint64_t state);]],
+  [[CelErrorCode context_handle_reset(uint64_t context);]],
+  [[CelErrorCode program_handle_free(uint64_t program);]],
+  [[uint64_t program_handle_new(void);]],
+  [[uint64_t program_handle_new_in(uint64_t state);]],
+}
+
+for _, declaration in ipairs(declarations) do
+  -- Fails harmlessly if the cel module or another copy of this file declared it
+  pcall(ffi.cdef, declaration)
+end
+
+local _M = { CEL_ABI_VERSION = CEL_ABI_VERSION }
+
+-- The handle registry this Lua state opened in each library. Every Lua state loads
+-- its own copy of this module, so registries are never shared between Lua states,
+-- while the coroutines of one Lua state share its registry
+local states = {}
+
+-- Load the library from `path`, or by name from the system search path, refusing a
+-- build with a different ABI version
+function _M.load(path)
+  local lib = ffi.load(path or "cel_lua")
+  local abi = lib.cel_abi_version()
+  if abi ~= CEL_ABI_VERSION then
+    error("cel_lua ABI version " .. abi .. " does not match " .. CEL_ABI_VERSION, 2)
+  end
+  return lib
+end
+
+-- This Lua state's registry in `lib`, the state to pass `program_handle_new_in` and
+-- `context_handle_new_in`, opened on first use. Closing the Lua state frees it with
+-- every handle it still owns
+function _M.state(lib)
+  local state = states[lib]
+  if not state then
+    state = ffi.gc(ffi.new("uint64_t[1]", lib.cel_state_new()), function(s)
+      lib.cel_state_free(s[0])
+    end)
+    states[lib] = state
+  end
+  return state[0]
+end
+
+-- Free this Lua state's registry in `lib` now, with every handle it still owns; the
+-- next `state` call opens a new one
+function _M.close(lib)
+  local state = states[lib]
+  if state then
+    states[lib] = nil
+    lib.cel_state_free(ffi.gc(state, nil)[0])
+  end
+end
+
+return _M
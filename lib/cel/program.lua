@@ -16,35 +16,8 @@ local program_free = cdefs.program_free
 local get_string_buf = cdefs.get_string_buf
 local get_size_ptr = cdefs.get_size_ptr
 local use_resty_core = cdefs.use_resty_core
-
--- Helper function to convert CelValue to Lua value
-local function cel_value_to_lua_value(cel_val)
-  if cel_val.value_type == cdefs.Null then
-    return nil
-  elseif cel_val.value_type == cdefs.Bool then
-    return cel_val.data.bool_val
-  elseif cel_val.value_type == cdefs.Int then
-    return tonumber(cel_val.data.int_val)
-  elseif cel_val.value_type == cdefs.Uint then
-    return tonumber(cel_val.data.uint_val)
-  elseif cel_val.value_type == cdefs.Double then
-    return cel_val.data.double_val
-  elseif cel_val.value_type == cdefs.String then
-    local string_val = cel_val.data.string_val
-    local result = ffi_string(string_val.ptr, string_val.len)
-    -- Clean up the string memory
-    if not use_resty_core and clib.cel_string_free then
-      clib.cel_string_free(string_val.ptr)
-    end
-    return result
-  elseif cel_val.value_type == cdefs.List then
-    return nil, "List values not yet supported"
-  elseif cel_val.value_type == cdefs.Map then
-    return nil, "Map values not yet supported"
-  else
-    return nil, "Unsupported value type"
-  end
-end
+local cel_value_to_lua_value = cdefs.cel_value_to_lua_value
+local compile_options = cdefs.compile_options
 
 function _M.new()
   local program = clib.program_new()
@@ -56,22 +29,377 @@ function _M.new()
   return p
 end
 
-function _M:compile(expression)
+-- Free the native program now rather than when it is garbage collected. Later calls
+-- fail as for a program that was never compiled, and closing again does nothing.
+-- Refused while an `execute_async` call is using the program
+function _M:close()
+  if (self.running or 0) > 0 then
+    return nil, "Program is in use by an async execution"
+  end
+
+  local program = self.program
+  if program ~= nil then
+    self.program = nil
+    self.compiled = false
+    program_free(ffi_gc(program, nil))
+  end
+  return true
+end
+
+function _M:compile(expression, options)
   local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
   local errbuf_len = get_size_ptr()
   errbuf_len[0] = ERR_BUF_MAX_LEN
 
-  local ok = clib.program_compile(self.program, expression, errbuf, errbuf_len)
+  local c_options, names = compile_options(options)
+  local ok = clib.program_compile_with_options(self.program, expression, c_options, errbuf,
+    errbuf_len)
+  local _ = names -- keep the name array alive across the call
 
   if not ok then
-    return false, ffi_string(errbuf, errbuf_len[0])
+    return false, ffi_string(errbuf, errbuf_len[0]), self:compile_error()
   end
 
   self.compiled = true
   return true
 end
 
-function _M:execute(context)
+-- Location of the last compilation failure, or nil if the last compile parsed
+function _M:compile_error()
+  local details = ffi_new("CelCompileError[1]")
+  if not clib.program_compile_error(self.program, details) then
+    return nil
+  end
+
+  local err = details[0]
+  local info = {
+    line = tonumber(err.line),
+    column = tonumber(err.column),
+    offset = tonumber(err.offset),
+    token = ffi_string(err.token.ptr, err.token.len),
+    message = ffi_string(err.message.ptr, err.message.len),
+  }
+
+  local rendered = ffi_new("CelStringValue[1]")
+  if clib.cel_error_render(details, rendered) then
+    info.rendered = ffi_string(rendered[0].ptr, rendered[0].len)
+    clib.cel_string_release(rendered[0].ptr)
+  end
+
+  return info
+end
+
+-- Fold constant subexpressions, returning the node counts before and after
+function _M:optimize()
+  if not self.compiled then
+    return nil, "Program not compiled"
+  end
+
+  local out = ffi_new("CelOptimization[1]")
+  if not clib.program_optimize(self.program, out) then
+    return nil, "Optimization failed"
+  end
+
+  return {
+    nodes_before = tonumber(out[0].nodes_before),
+    nodes_after = tonumber(out[0].nodes_after),
+  }
+end
+
+-- Require executions to return a value of `value_type`, a `cel.cdefs` constant such
+-- as `cdefs.Bool`; executions returning another type fail
+function _M:expect_type(value_type)
+  if not self.compiled then
+    return nil, "Program not compiled"
+  end
+
+  return clib.program_expect_type(self.program, value_type)
+end
+
+-- Return member names of the enum `name` registered in `env` for the int results
+-- it names, or ints again for a nil `name`
+function _M:set_result_enum(env, name)
+  if not self.compiled then
+    return nil, "Program not compiled"
+  end
+
+  if not clib.program_set_result_enum(self.program, env.env, name) then
+    return nil, "Unknown enum: " .. tostring(name)
+  end
+  return true
+end
+
+-- How integers beyond 2^53 come back from this program's executions, overriding the
+-- policy of the context and the `cel.set_number_policy` default
+function _M:set_number_policy(policy)
+  local ok, err = cdefs.check_number_policy(policy)
+  if not ok then
+    return nil, err
+  end
+
+  self.number_policy = policy
+  return true
+end
+
+-- The type every successful execution returns as a `cel.cdefs` constant such as
+-- `cdefs.Bool`, or nil if the shape of the expression does not determine it
+function _M:inferred_type()
+  if not self.compiled then
+    return nil, "Program not compiled"
+  end
+
+  local known = ffi_new("bool[1]")
+  local value_type = ffi_new("CelValueType[1]")
+  if not clib.program_inferred_type(self.program, known, value_type) then
+    return nil, "Program not compiled"
+  end
+
+  if not known[0] then
+    return nil
+  end
+  return tonumber(value_type[0])
+end
+
+-- Size and complexity of the compiled expression: node count, deepest nesting,
+-- comprehensions, function calls and an estimate of the nodes one evaluation visits
+function _M:metrics()
+  if not self.compiled then
+    return nil, "Program not compiled"
+  end
+
+  local out = ffi_new("CelProgramMetrics[1]")
+  if not clib.program_metrics(self.program, out) then
+    return nil, "Program not compiled"
+  end
+
+  return {
+    nodes = tonumber(out[0].nodes),
+    depth = tonumber(out[0].depth),
+    comprehensions = tonumber(out[0].comprehensions),
+    calls = tonumber(out[0].calls),
+    cost = tonumber(out[0].cost),
+  }
+end
+
+-- Fingerprint of the compiled expression as 16 hex digits; expressions differing
+-- only in whitespace, comments or quoting share it
+function _M:hash()
+  if not self.compiled then
+    return nil, "Program not compiled"
+  end
+
+  local out = ffi_new("CelStringValue[1]")
+  if not clib.program_hash_hex(self.program, out) then
+    return nil, "Program not compiled"
+  end
+
+  local hex = ffi_string(out[0].ptr, out[0].len)
+  clib.cel_string_release(out[0].ptr)
+  return hex
+end
+
+-- JSON object mapping each variable to the field paths the expression reads from it,
+-- such as {"user":["user.roles[*]"]}
+function _M:dependencies_json()
+  if not self.compiled then
+    return nil, "Program not compiled"
+  end
+
+  local out = ffi_new("CelStringValue[1]")
+  if not clib.program_dependencies_json(self.program, out) then
+    return nil, "Program not compiled"
+  end
+
+  local json = ffi_string(out[0].ptr, out[0].len)
+  clib.cel_string_release(out[0].ptr)
+  return json
+end
+
+-- The parsed expression as CEL Expr JSON, with macros such as all() and map() expanded
+-- into comprehensions
+function _M:expanded_ast_json()
+  if not self.compiled then
+    return nil, "Program not compiled"
+  end
+
+  local out = ffi_new("CelStringValue[1]")
+  if not clib.program_expanded_ast_json(self.program, out) then
+    return nil, "Program not compiled"
+  end
+
+  local json = ffi_string(out[0].ptr, out[0].len)
+  clib.cel_string_release(out[0].ptr)
+  return json
+end
+
+-- With `enabled`, `execute` without a token builds results in an arena the program
+-- reuses for every execution, instead of allocating each string and array apart
+function _M:set_arena(enabled)
+  if not enabled then
+    self.execute_options, self.arena = nil, nil
+  elseif not self.arena then
+    self.arena = ffi_gc(clib.cel_arena_new(), clib.cel_arena_free)
+    self.execute_options = ffi_new("CelExecuteOptions[1]")
+    self.execute_options[0].arena = self.arena
+  end
+  return true
+end
+
+-- With a token from cel.cancel_token(), cancelling it from another thread stops the
+-- evaluation with an "Execution cancelled" error
+function _M:execute(context, token)
+  if not self.compiled then
+    return nil, "Program not compiled"
+  end
+
+  local result
+  if token then
+    result = clib.program_execute_cancellable(self.program, context.context, token.token)
+  elseif self.execute_options then
+    result = clib.program_execute_with_options(self.program, context.context,
+      self.execute_options)
+  else
+    result = clib.program_execute_alloc(self.program, context.context)
+  end
+
+  if not clib.cel_result_is_ok(result) then
+    local err = clib.cel_result_error(result)
+    local message = ffi_string(err.ptr, err.len)
+    clib.cel_result_free(result)
+    return nil, message
+  end
+
+  local value, err = cel_value_to_lua_value(clib.cel_result_value(result)[0],
+    self.number_policy or context.number_policy)
+  clib.cel_result_free(result)
+  return value, err
+end
+
+-- Execute on a background thread so a long evaluation doesn't block the event
+-- loop. Between polls, yields to ngx.sleep(0) in OpenResty or coroutine.yield
+-- otherwise, whenever should_yield() returns true (always, if it is nil). The
+-- context must not be modified until this returns. Cancelling the optional token
+-- stops the evaluation
+function _M:execute_async(context, should_yield, token)
+  if not self.compiled then
+    return nil, "Program not compiled"
+  end
+
+  local execution = clib.program_execute_start(self.program, context.context,
+    token and token.token)
+  if execution == nil then
+    return nil, "Failed to start execution"
+  end
+  -- Neither may be closed while the thread reads them
+  self.running = (self.running or 0) + 1
+  context.running = (context.running or 0) + 1
+  -- If the coroutine is abandoned, collecting the execution joins the thread; the
+  -- finalizer keeps the program and context alive until then
+  execution = ffi_gc(execution, function(e)
+    local _ = self.program and context.context
+    clib.cel_result_free(clib.cel_execution_finish(e))
+  end)
+
+  local ok, err = pcall(function()
+    while not clib.cel_execution_is_done(execution) do
+      if should_yield == nil or should_yield() then
+        if ngx and ngx.sleep then
+          ngx.sleep(0)
+        else
+          coroutine.yield()
+        end
+      end
+    end
+  end)
+
+  -- Always collected, even if yielding raised, since the thread reads the context
+  local result = clib.cel_execution_finish(ffi_gc(execution, nil))
+  self.running = self.running - 1
+  context.running = context.running - 1
+  if not ok then
+    clib.cel_result_free(result)
+    error(err, 0)
+  end
+
+  if not clib.cel_result_is_ok(result) then
+    local cel_err = clib.cel_result_error(result)
+    local message = ffi_string(cel_err.ptr, cel_err.len)
+    clib.cel_result_free(result)
+    return nil, message
+  end
+
+  local value, conv_err = cel_value_to_lua_value(clib.cel_result_value(result)[0],
+    self.number_policy or context.number_policy)
+  clib.cel_result_free(result)
+  return value, conv_err
+end
+
+-- Execute like `execute`, also returning the evaluation trace as a JSON string
+function _M:execute_traced(context)
+  if not self.compiled then
+    return nil, "Program not compiled"
+  end
+
+  local out = ffi_new("CelStringValue[1]")
+  local result = clib.program_execute_traced(self.program, context.context, out)
+
+  local trace
+  if out[0].ptr ~= nil then
+    trace = ffi_string(out[0].ptr, out[0].len)
+    clib.cel_string_release(out[0].ptr)
+  end
+
+  if not clib.cel_result_is_ok(result) then
+    local err = clib.cel_result_error(result)
+    local message = ffi_string(err.ptr, err.len)
+    clib.cel_result_free(result)
+    return nil, message, trace
+  end
+
+  local value, err = cel_value_to_lua_value(clib.cel_result_value(result)[0],
+    self.number_policy or context.number_policy)
+  clib.cel_result_free(result)
+  return value, err, trace
+end
+
+-- Execute against each context in the array, returning an array of `{ value = ... }`
+-- or `{ error = "..." }` in the same order
+function _M:execute_batch(contexts)
+  if not self.compiled then
+    return nil, "Program not compiled"
+  end
+
+  local count = #contexts
+  local array = ffi_new("const Context *[?]", count)
+  for i = 1, count do
+    array[i - 1] = contexts[i].context
+  end
+
+  local result = clib.program_execute_batch(self.program, array, count)
+  if not clib.cel_result_is_ok(result) then
+    local err = clib.cel_result_error(result)
+    local message = ffi_string(err.ptr, err.len)
+    clib.cel_result_free(result)
+    return nil, message
+  end
+
+  -- Each outcome follows the policy of the context it was evaluated against
+  local outcomes_val = clib.cel_result_value(result)[0].data.list_val
+  local outcomes = {}
+  for i = 0, tonumber(outcomes_val.len) - 1 do
+    local outcome, err = cel_value_to_lua_value(outcomes_val.items[i],
+      self.number_policy or contexts[i + 1].number_policy)
+    if err then
+      clib.cel_result_free(result)
+      return nil, err
+    end
+    outcomes[i + 1] = outcome
+  end
+  clib.cel_result_free(result)
+  return outcomes
+end
+
+function _M:execute_json(context)
   if not self.compiled then
     return nil, "Program not compiled"
   end
@@ -80,23 +408,80 @@ function _M:execute(context)
   local errbuf_len = get_size_ptr()
   errbuf_len[0] = ERR_BUF_MAX_LEN
 
-  local result = ffi_new("CelValue[1]")
-  local ok = clib.program_execute(self.program, context.context, result, errbuf, errbuf_len)
+  local out = ffi_new("CelStringValue[1]")
+  if not clib.program_execute_json(self.program, context.context, out, errbuf, errbuf_len) then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
 
-  if not ok then
+  local json = ffi_string(out[0].ptr, out[0].len)
+  clib.cel_string_release(out[0].ptr)
+  return json
+end
+
+-- Execute and return whether the result counts as true. Only bools are accepted
+-- unless `lenient`, under which nil, zero and empty values are false and any other
+-- value true
+function _M:execute_bool(context, lenient)
+  if not self.compiled then
+    return nil, "Program not compiled"
+  end
+
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  local out = ffi_new("bool[1]")
+  local truthiness = lenient and "TruthinessLenient" or "TruthinessStrict"
+  if not clib.program_execute_bool(self.program, context.context, out, truthiness, errbuf,
+      errbuf_len) then
     return nil, ffi_string(errbuf, errbuf_len[0])
   end
 
-  return cel_value_to_lua_value(result[0])
+  return out[0]
+end
+
+-- Only available when the library was built with the `stats` feature
+if pcall(function() return clib.program_stats end) then
+  -- Execution counters since the program was compiled or its counters were reset
+  function _M:stats()
+    if not self.compiled then
+      return nil, "Program not compiled"
+    end
+
+    local out = ffi_new("CelProgramStats[1]")
+    if not clib.program_stats(self.program, out) then
+      return nil, "Program not compiled"
+    end
+
+    local stats = out[0]
+    local result = {
+      executions = tonumber(stats.executions),
+      errors = tonumber(stats.errors),
+      total_time_ns = tonumber(stats.total_time_ns),
+    }
+    if stats.last_error.ptr ~= nil then
+      result.last_error = ffi_string(stats.last_error.ptr, stats.last_error.len)
+      clib.cel_string_release(stats.last_error.ptr)
+    end
+
+    return result
+  end
+
+  function _M:reset_stats()
+    return clib.program_reset_stats(self.program)
+  end
 end
 
-function _M.validate(expression)
+function _M.validate(expression, options)
   local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
   local errbuf_len = get_size_ptr()
   errbuf_len[0] = ERR_BUF_MAX_LEN
 
   local variables_len = ffi_new("uintptr_t[1]")
-  local ok = clib.program_validate(expression, nil, variables_len, errbuf, errbuf_len)
+  local c_options, names = compile_options(options)
+  local ok = clib.program_validate_with_options(expression, c_options, variables_len, errbuf,
+    errbuf_len)
+  local _ = names -- keep the name array alive across the call
 
   if not ok then
     return nil, ffi_string(errbuf, errbuf_len[0])
@@ -108,6 +493,89 @@ function _M.validate(expression)
   }
 end
 
+-- Every error and warning for `expression`, as an array of tables with `severity`,
+-- `message`, `line`, `column`, `offset` and `length`
+function _M.validate_full(expression, options)
+  local c_options, names = compile_options(options)
+  local result = clib.program_validate_full(expression, c_options)
+  local _ = names -- keep the name array alive across the call
+
+  if not clib.cel_result_is_ok(result) then
+    local err = clib.cel_result_error(result)
+    local message = ffi_string(err.ptr, err.len)
+    clib.cel_result_free(result)
+    return nil, message
+  end
+
+  local diagnostics, err = cel_value_to_lua_value(clib.cel_result_value(result)[0])
+  clib.cel_result_free(result)
+  return diagnostics, err
+end
+
+-- Rename references to the variable `old_name` in `expression`, keeping the rest of
+-- the source as written
+function _M.rename_variable(expression, old_name, new_name)
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  local out = ffi_new("CelStringValue[1]")
+  if not clib.program_rename_variable(expression, old_name, new_name, out, errbuf,
+    errbuf_len) then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
+
+  local renamed = ffi_string(out[0].ptr, out[0].len)
+  clib.cel_string_release(out[0].ptr)
+  return renamed
+end
+
+-- Fold constants and remove redundant logic, such as `x && true` to `x`
+function _M.simplify(expression)
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  local out = ffi_new("CelStringValue[1]")
+  if not clib.cel_simplify(expression, out, errbuf, errbuf_len) then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
+
+  local simplified = ffi_string(out[0].ptr, out[0].len)
+  clib.cel_string_release(out[0].ptr)
+  return simplified
+end
+
+-- Whether two expressions are the same rule written differently; with `simplify`,
+-- both are simplified first
+function _M.equivalent(a, b, simplify)
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  local out = ffi_new("bool[1]")
+  if not clib.cel_expressions_equivalent(a, b, simplify == true, out, errbuf, errbuf_len) then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
+  return out[0]
+end
+
+-- One-shot evaluation: `variables_json` is an optional JSON object of variables
+function _M.eval_json(expression, variables_json)
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  local out = ffi_new("CelStringValue[1]")
+  if not clib.cel_eval_json(expression, variables_json, out, errbuf, errbuf_len) then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
+
+  local json = ffi_string(out[0].ptr, out[0].len)
+  clib.cel_string_release(out[0].ptr)
+  return json
+end
+
 -- Clean up string pool
 if not use_resty_core and clib.cel_string_pool_clear then
   function _M.cleanup()
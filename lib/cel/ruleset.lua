@@ -0,0 +1,89 @@
+local _M = {}
+local _MT = { __index = _M }
+
+local ffi = require("ffi")
+local cdefs = require("cel.cdefs")
+
+local ffi_gc = ffi.gc
+local ffi_string = ffi.string
+local setmetatable = setmetatable
+
+local ERR_BUF_MAX_LEN = cdefs.ERR_BUF_MAX_LEN
+local clib = cdefs.clib
+local ruleset_free = cdefs.ruleset_free
+local get_string_buf = cdefs.get_string_buf
+local get_size_ptr = cdefs.get_size_ptr
+local cel_value_to_lua_value = cdefs.cel_value_to_lua_value
+
+-- Convert and free a result following `context`'s number policy, returning its value
+-- or nil and the error
+local function collect(result, context)
+  if not clib.cel_result_is_ok(result) then
+    local err = clib.cel_result_error(result)
+    local message = ffi_string(err.ptr, err.len)
+    clib.cel_result_free(result)
+    return nil, message
+  end
+
+  local value, err = cel_value_to_lua_value(clib.cel_result_value(result)[0],
+    context.number_policy)
+  clib.cel_result_free(result)
+  return value, err
+end
+
+function _M.new()
+  return setmetatable({
+    ruleset = ffi_gc(clib.ruleset_new(), ruleset_free),
+  }, _MT)
+end
+
+-- Add a named rule, compiled by the next `compile`
+function _M:add(name, expression)
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  if not clib.ruleset_add(self.ruleset, name, expression, errbuf, errbuf_len) then
+    return false, ffi_string(errbuf, errbuf_len[0])
+  end
+  return true
+end
+
+-- Compile the rules added since the last call; the error lists each failing rule
+function _M:compile()
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  if not clib.ruleset_compile(self.ruleset, errbuf, errbuf_len) then
+    return false, ffi_string(errbuf, errbuf_len[0])
+  end
+  return true
+end
+
+-- Match rules in `first_match` when the CEL `expression` over the rule's `result` is
+-- true, such as "result == 'deny'"; nil matches rules that return true
+function _M:set_predicate(expression)
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  if not clib.ruleset_set_predicate(self.ruleset, expression, errbuf, errbuf_len) then
+    return false, ffi_string(errbuf, errbuf_len[0])
+  end
+  return true
+end
+
+-- Evaluate every rule, returning a table from rule name to `{ value = ... }` or
+-- `{ error = "..." }`
+function _M:execute_all(context)
+  return collect(clib.ruleset_execute_all(self.ruleset, context.context), context)
+end
+
+-- Evaluate the rules in order, returning the name of the first that matches, or nil
+-- if none did; later rules are not evaluated
+function _M:first_match(context)
+  return collect(clib.ruleset_first_match(self.ruleset, context.context), context)
+end
+
+return _M
@@ -0,0 +1,93 @@
+local _M = {}
+local _MT = { __index = _M }
+
+local ffi = require("ffi")
+local cdefs = require("cel.cdefs")
+local context = require("cel.context")
+local env = require("cel.env")
+
+local ffi_gc = ffi.gc
+local ipairs = ipairs
+local pairs = pairs
+local setmetatable = setmetatable
+
+local clib = cdefs.clib
+
+-- Limits applied unless the options set them; 0 disables one
+local DEFAULTS = {
+  max_expression_length = 4096,
+  max_nesting_depth = 32,
+  max_comprehensions = 4,
+  max_regex_length = 256,
+  max_regex_size = 1048576,
+  max_cost = 10000,
+  timeout_ms = 100,
+  max_depth = 64,
+}
+
+-- Create a sandbox from the options table `program:compile` accepts, plus
+-- `max_cost`, `timeout_ms`, `max_depth`, `allowed_functions` and `variables`.
+-- Limits the options leave out take their defaults above, and extensions stay off
+function _M.new(options)
+  options = options or {}
+  local settings = {}
+  for name, value in pairs(DEFAULTS) do
+    settings[name] = value
+  end
+  for name, value in pairs(options) do
+    settings[name] = value
+  end
+
+  local sandbox_env, err = env.new(settings)
+  if not sandbox_env then
+    return nil, err
+  end
+
+  for _, name in ipairs(options.allowed_functions or {}) do
+    sandbox_env:allow_function(name)
+  end
+  if options.variables then
+    for _, name in ipairs(options.variables) do
+      sandbox_env:declare_variable(name)
+    end
+    sandbox_env:set_strict_variables(true)
+  end
+
+  return setmetatable({
+    env = sandbox_env,
+    max_cost = settings.max_cost,
+    timeout_ms = settings.timeout_ms,
+    max_depth = settings.max_depth,
+  }, _MT)
+end
+
+-- Compile an untrusted expression, or return nil, err and the compile error details
+function _M:compile(expression)
+  return self.env:compile(expression)
+end
+
+-- A new context limited to the sandbox's nesting depth
+function _M:context()
+  local ctx = context.new()
+  ctx:set_max_depth(self.max_depth)
+  return ctx
+end
+
+-- Execute a program compiled by this sandbox within its cost and time limits
+function _M:execute(program, ctx)
+  local token = ffi_gc(clib.cel_cancel_token_new(), clib.cel_cancel_token_free)
+  if self.max_cost > 0 then
+    clib.cel_cancel_token_set_budget(token, self.max_cost)
+  end
+  if self.timeout_ms > 0 then
+    clib.cel_cancel_after(token, self.timeout_ms)
+  end
+
+  local value, err = program:execute(ctx, { token = token })
+  if err == "Execution cancelled" then
+    return nil, "Execution timed out after " .. self.timeout_ms .. " ms"
+  end
+  return value, err
+end
+
+return _M
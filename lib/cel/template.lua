@@ -0,0 +1,51 @@
+local _M = {}
+local _MT = { __index = _M }
+
+local ffi = require("ffi")
+local cdefs = require("cel.cdefs")
+
+local ffi_gc = ffi.gc
+local ffi_new = ffi.new
+local ffi_string = ffi.string
+local setmetatable = setmetatable
+
+local ERR_BUF_MAX_LEN = cdefs.ERR_BUF_MAX_LEN
+local clib = cdefs.clib
+local template_free = cdefs.template_free
+local get_string_buf = cdefs.get_string_buf
+local get_size_ptr = cdefs.get_size_ptr
+
+-- Compile an expression with `{name}` placeholders, or return nil, err
+function _M.compile(source)
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  local template = clib.cel_template_compile(source, errbuf, errbuf_len)
+  if template == nil then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
+
+  return setmetatable({
+    template = ffi_gc(template, template_free),
+  }, _MT)
+end
+
+-- The expression with each placeholder replaced by the literal of its parameter in
+-- the JSON object `params_json`
+function _M:instantiate(params_json)
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  local out = ffi_new("CelStringValue[1]")
+  if not clib.template_instantiate(self.template, params_json, out, errbuf, errbuf_len) then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
+
+  local expression = ffi_string(out[0].ptr, out[0].len)
+  clib.cel_string_release(out[0].ptr)
+  return expression
+end
+
+return _M
@@ -0,0 +1,122 @@
+local _M = {}
+
+local bit = require("bit")
+local ffi = require("ffi")
+local cdefs = require("cel.cdefs")
+
+local ffi_gc = ffi.gc
+local ffi_new = ffi.new
+local ffi_string = ffi.string
+
+local ERR_BUF_MAX_LEN = cdefs.ERR_BUF_MAX_LEN
+local clib = cdefs.clib
+local get_string_buf = cdefs.get_string_buf
+local get_size_ptr = cdefs.get_size_ptr
+
+local function parse(constructor, text)
+  local errbuf = get_string_buf(ERR_BUF_MAX_LEN)
+  local errbuf_len = get_size_ptr()
+  errbuf_len[0] = ERR_BUF_MAX_LEN
+
+  local value = ffi_new("CelValue")
+  if not constructor(text, value, errbuf, errbuf_len) then
+    return nil, ffi_string(errbuf, errbuf_len[0])
+  end
+
+  return value
+end
+
+-- A timestamp from RFC 3339 text such as "2024-01-01T12:00:00Z", for
+-- `context:add_variable`
+function _M.timestamp(text)
+  return parse(clib.cel_value_timestamp_from_rfc3339, text)
+end
+
+-- A duration from text such as "1h30m", "-1.5s" or "250ms", for
+-- `context:add_variable`
+function _M.duration(text)
+  return parse(clib.cel_value_duration_from_string, text)
+end
+
+-- A 128-bit integer from decimal digits such as "170141183460469231731687303715884105727",
+-- for `context:add_variable`. Arithmetic on it needs `context:set_numeric_promotion(true)`
+function _M.bigint(text)
+  local value, err = parse(clib.cel_value_bigint_from_string, text)
+  if not value then
+    return nil, err
+  end
+  return ffi_gc(value, clib.cel_value_free)
+end
+
+-- An exact decimal from text such as "19.99", for `context:add_variable`. Arithmetic
+-- on it needs `context:set_numeric_promotion(true)`
+function _M.decimal(text)
+  local value, err = parse(clib.cel_value_decimal_from_string, text)
+  if not value then
+    return nil, err
+  end
+  return ffi_gc(value, clib.cel_value_free)
+end
+
+-- A type from its name, such as "int" or "google.protobuf.Timestamp", which compares
+-- equal to what `type()` returns for values of that type
+function _M.type(name)
+  local value, err = parse(clib.cel_value_type_from_name, name)
+  if not value then
+    return nil, err
+  end
+  return ffi_gc(value, clib.cel_value_free)
+end
+
+-- A copy of a value built by `cel.value` that shares nothing with it, freed when
+-- collected
+function _M.clone(value)
+  local copy = ffi_new("CelValue")
+  if not clib.cel_value_clone(value, copy) then
+    return nil, "Value cannot be copied"
+  end
+  return ffi_gc(copy, clib.cel_value_free)
+end
+
+-- Whether two values built by `cel.value` are equal under CEL equality, where `1`, `1u`
+-- and `1.0` are equal
+function _M.equals(a, b)
+  local out = ffi_new("bool[1]")
+  if not clib.cel_value_equals(a, b, out) then
+    return nil, "Values cannot be compared"
+  end
+  return out[0]
+end
+
+-- A hash of `value` as 16 hex digits, shared by values `equals` finds equal
+function _M.hash(value)
+  local out = ffi_new("uint64_t[1]")
+  if not clib.cel_value_hash(value, out) then
+    return nil, "Value cannot be hashed"
+  end
+  return bit.tohex(out[0], 16)
+end
+
+local function render(renderer, value)
+  local out = ffi_new("CelStringValue[1]")
+  if not renderer(value, out) then
+    return nil, "Value cannot be rendered"
+  end
+
+  local text = ffi_string(out[0].ptr, out[0].len)
+  clib.cel_string_release(out[0].ptr)
+  return text
+end
+
+-- `value` as a CEL expression that evaluates to it, such as `[1u, b"\x00"]`, for logs
+function _M.debug_string(value)
+  return render(clib.cel_value_debug_string, value)
+end
+
+-- `value` as text for people: strings as their contents, timestamps in RFC 3339, and
+-- lists and maps as by `debug_string`
+function _M.display_string(value)
+  return render(clib.cel_value_display_string, value)
+end
+
+return _M
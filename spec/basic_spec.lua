@@ -24,6 +24,87 @@ describe("CEL Basic Functionality", function()
       local prog = cel.program.new()
       assert.is_not_nil(prog)
     end)
+
+    it("should route library messages to a log callback", function()
+      local messages = {}
+      cel.set_log_callback(function(level, message)
+        messages[#messages + 1] = { level = level, message = message }
+      end, cel.LOG_INFO)
+
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("raw", "\255"))
+      cel.set_log_callback(nil)
+
+      assert.equals(1, #messages)
+      assert.equals(cel.LOG_INFO, messages[1].level)
+      assert.matches("not valid UTF%-8", messages[1].message)
+    end)
+
+    it("should describe the library's capabilities", function()
+      local json = cel.capabilities_json()
+      assert.matches('"version":"%d+%.%d+%.%d+"', json)
+      assert.matches('"timestamp"', json)
+    end)
+
+    it("should keep the full message of the last error", function()
+      local prog = cel.program.new()
+      local ok, err = prog:compile("1 + + 2")
+      assert.is_false(ok)
+      assert.equals(err, cel.last_error_message())
+    end)
+
+    it("should count the library objects still alive", function()
+      local prog = cel.program.new()
+      local stats = cel.memory_stats()
+      assert.is_true(stats.programs >= 1)
+      assert.is_true(stats.strings >= 0)
+
+      if cel.dump_leaks then
+        local kinds = {}
+        for _, leak in ipairs(cel.dump_leaks()) do
+          kinds[leak.kind] = true
+        end
+        assert.is_true(kinds.program)
+      end
+      assert.is_not_nil(prog)
+    end)
+
+    it("should share variable names between programs", function()
+      local before = cel.interned_count()
+      for _ = 1, 3 do
+        local prog = cel.program.new()
+        assert.is_true(prog:compile("interned_spec_name > 0"))
+      end
+      assert.is_true(cel.interned_count() <= before + 1)
+      collectgarbage()
+      assert.is_true(cel.purge_interned() >= 0)
+    end)
+
+    it("should read timestamps in registered time zones", function()
+      local function u32(n)
+        local bytes = {}
+        for i = 4, 1, -1 do
+          bytes[i] = n % 256
+          n = math.floor(n / 256)
+        end
+        return string.char(unpack(bytes))
+      end
+      -- A TZif file with no transitions, whose offsets come from the rule in its footer
+      local block = "TZif2" .. string.rep("\0", 15) .. u32(0):rep(4) .. u32(1) .. u32(4)
+        .. u32(3600) .. "\0\0CET\0"
+      local tzif = block .. block .. "\nCET-1CEST,M3.5.0,M10.5.0/3\n"
+      assert.is_true(cel.add_time_zone("Spec/Stockholm", tzif))
+      assert.is_false(cel.add_time_zone("Spec/Invalid", "not tzif"))
+
+      local result, err = test_helper.eval_expression(cel,
+        "timestamp('2024-07-01T12:00:00Z').getHours('Spec/Stockholm')")
+      assert.is_nil(err)
+      assert.equals(14, result)
+      result, err = test_helper.eval_expression(cel,
+        "getDayOfWeek(timestamp('2024-01-15T23:30:00Z'), '+05:30')")
+      assert.is_nil(err)
+      assert.equals(2, result)
+    end)
   end)
 
   describe("Basic Arithmetic", function()
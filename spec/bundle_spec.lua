@@ -0,0 +1,107 @@
+local test_helper = require("spec.test_helper")
+test_helper.setup_lua_path()
+
+describe("CEL Bundle", function()
+  local cel
+
+  before_each(function()
+    cel = require("cel")
+  end)
+
+  local ARCHIVE = [[
+=== admin
+'admin' in user.roles
+=== adult
+user.age >= 18
+=== broken
+user.age >=
+]]
+
+  it("should compile an archive and report the rules that fail", function()
+    local policy = assert(cel.bundle.from_string(ARCHIVE))
+    assert.matches('^{"broken":"Compilation error', policy:errors_json())
+
+    local ctx = cel.context.new()
+    ctx:add_variable("user", { roles = { "admin" }, age = 17 })
+    assert.is_true(policy:execute("admin", ctx))
+    assert.is_false(policy:execute("adult", ctx))
+    assert.equals("admin", policy:first_match(ctx))
+
+    local results = assert(policy:execute_all(ctx))
+    assert.is_true(results.admin.value)
+    assert.is_nil(results.broken)
+
+    local value, err = policy:execute("broken", ctx)
+    assert.is_nil(value)
+    assert.matches("failed to compile", err)
+    value, err = policy:execute("missing", ctx)
+    assert.is_nil(value)
+    assert.matches("no rule named 'missing'", err)
+  end)
+
+  it("should load JSON manifests", function()
+    local policy = assert(cel.bundle.from_string(
+      '[{"name": "sum", "expression": "1 + 2"}, {"name": "long", "expression": "1 + 2 + 3"}]',
+      { max_expression_length = 6 }))
+    assert.equals(3, policy:execute("sum", cel.context.new()))
+    assert.matches('"long"', policy:errors_json())
+    assert.equals("{}", assert(cel.bundle.from_string('{"a": "1"}')):errors_json())
+
+    local bundle, err = cel.bundle.from_string('{"a": 1}')
+    assert.is_nil(bundle)
+    assert.matches("not a string", err)
+    bundle, err = cel.bundle.from_string("=== a\n1\n=== a\n2")
+    assert.is_nil(bundle)
+    assert.matches("already defined", err)
+  end)
+
+  it("should load a directory of rule files", function()
+    local dir = os.tmpname()
+    os.remove(dir)
+    assert(os.execute("mkdir " .. dir))
+    local file = assert(io.open(dir .. "/adult.cel", "w"))
+    file:write("user.age >= 18")
+    file:close()
+
+    local policy = assert(cel.bundle.load(dir))
+    local ctx = cel.context.new()
+    ctx:add_variable("user", { age = 30 })
+    assert.is_true(policy:execute("adult", ctx))
+
+    local bundle, err = cel.bundle.load(dir .. "/missing.json")
+    assert.is_nil(bundle)
+    assert.matches("cannot read", err)
+
+    os.remove(dir .. "/adult.cel")
+    os.remove(dir)
+  end)
+
+  it("should reload rules in place", function()
+    local routes = assert(cel.bundle.from_string("=== route\n'old'"))
+    local ctx = cel.context.new()
+    assert.equals(1, routes:version())
+
+    assert.is_true(routes:reload_string("=== route\n'new'", nil, false))
+    assert.equals(2, routes:version())
+    assert.equals("new", routes:execute("route", ctx))
+
+    local ok, err = routes:reload_string("=== route\n1 +", nil, false)
+    assert.is_false(ok)
+    assert.matches("^rule 'route': ", err)
+    ok, err = routes:reload("/nonexistent/bundle.json", nil, false)
+    assert.is_false(ok)
+    assert.matches("cannot read", err)
+    assert.equals(2, routes:version())
+    assert.equals("new", routes:execute("route", ctx))
+
+    local co = coroutine.wrap(function()
+      return routes:reload_string("=== route\n'newer'")
+    end)
+    local done = co()
+    while done == nil do
+      done = co()
+    end
+    assert.is_true(done)
+    assert.equals("newer", routes:execute("route", ctx))
+  end)
+end)
@@ -0,0 +1,153 @@
+local test_helper = require("spec.test_helper")
+test_helper.setup_lua_path()
+
+describe("CEL Env", function()
+  local cel
+
+  before_each(function()
+    cel = require("cel")
+  end)
+
+  it("should compile programs with the environment's options", function()
+    local env = assert(cel.env.new({ string_ext = true }))
+    local prog = assert(env:compile("'ADMIN'.lowerAscii() == role"))
+
+    local ctx = cel.context.new()
+    ctx:add_variable("role", "admin")
+    assert.is_true(prog:execute(ctx))
+
+    local limited = assert(cel.env.new({ max_expression_length = 4 }))
+    local result, err = limited:compile("1 + 2")
+    assert.is_nil(result)
+    assert.equals("Compilation error: expression is longer than 4 bytes", err)
+  end)
+
+  it("should reject functions the environment does not provide", function()
+    local env = assert(cel.env.new())
+    local prog, err = env:compile("lookup('id')")
+    assert.is_nil(prog)
+    assert.equals("Compilation error: function 'lookup' is not declared", err)
+
+    assert.is_nil(env:compile("'A'.lowerAscii()"))
+
+    assert.is_true(env:declare_function("lookup"))
+    assert.is_not_nil(env:compile("lookup('id')"))
+    assert.is_true(env:declare_variable("request"))
+  end)
+
+  it("should reject undeclared variables when strict", function()
+    local env = assert(cel.env.new())
+    env:declare_variable("request")
+    assert.is_not_nil(env:compile("reqest.path == '/'"))
+
+    assert.is_true(env:set_strict_variables(true))
+    assert.is_not_nil(env:compile("request.path == '/' && [1].all(x, x > 0)"))
+
+    local prog, err, details = env:compile("reqest.path == '/'")
+    assert.is_nil(prog)
+    assert.equals(
+      "Compilation error: undeclared reference to 'reqest', did you mean 'request'? at line 1, column 1",
+      err
+    )
+    assert.equals("reqest", details.token)
+  end)
+
+  it("should leave late-bound functions to be resolved at execution", function()
+    local env = assert(cel.env.new())
+    assert.is_true(env:set_late_bound_functions(true))
+    local prog = assert(env:compile("fetch('id') == 1"))
+
+    local value, err = prog:execute(cel.context.new())
+    assert.is_nil(value)
+    assert.equals("Execution error: function 'fetch' is not registered", err)
+
+    env:allow_function("size")
+    local _, compile_err = env:compile("fetch('id')")
+    assert.equals("Compilation error: function 'fetch' is not allowed", compile_err)
+  end)
+
+  it("should enforce reserved names and identifier lengths", function()
+    local env = assert(cel.env.new())
+    assert.is_true(env:reserve_name("__*"))
+    assert.is_not_nil(env:compile("request.__meta == '__x'"))
+
+    local prog, err, details = env:compile("a || __tenant == 'x'")
+    assert.is_nil(prog)
+    assert.equals(
+      "Compilation error: reference to reserved variable '__tenant' at line 1, column 6",
+      err
+    )
+    assert.equals("__tenant", details.token)
+
+    assert.is_true(env:set_max_identifier_length(8))
+    assert.is_not_nil(env:compile("request.path"))
+    prog, err = env:compile("request.path_prefix")
+    assert.is_nil(prog)
+    assert.equals(
+      "Compilation error: identifier 'path_prefix' is longer than 8 bytes at line 1, column 9",
+      err
+    )
+  end)
+
+  it("should disable macros and cap comprehension iterations", function()
+    local env = assert(cel.env.new())
+    assert.is_true(env:disable_macro("filter"))
+    assert.is_false(env:disable_macro("sort"))
+    local prog, err = env:compile("[1].filter(x, x > 0)")
+    assert.is_nil(prog)
+    assert.equals("Compilation error: macro 'filter' is disabled", err)
+
+    assert.is_true(env:set_max_comprehension_iterations(2))
+    prog = assert(env:compile("items.all(x, x > 0)"))
+    local ctx = cel.context.new()
+    ctx:add_variable("items", { 1, 2 })
+    assert.is_true(prog:execute(ctx))
+    ctx:add_variable("items", { 1, 2, 3 })
+    local value, exec_err = prog:execute(ctx)
+    assert.is_nil(value)
+    assert.truthy(exec_err:find("exceeds the limit of 2 iterations", 1, true))
+  end)
+
+  it("should fold constants into programs", function()
+    local env = assert(cel.env.new())
+    assert.is_true(env:add_constant("threshold", 100))
+    assert.is_true(env:add_constant("tiers", { free = 1, pro = 10 }))
+    env:declare_variable("amount")
+    env:set_strict_variables(true)
+
+    local prog = assert(env:compile("amount > threshold * tiers['pro']"))
+    local ctx = cel.context.new()
+    ctx:add_variable("amount", 1001)
+    ctx:add_variable("threshold", 5000)
+    assert.is_true(prog:execute(ctx))
+
+    local ok, err = env:add_constant("started", cel.value.timestamp("2024-01-01T00:00:00Z"))
+    assert.is_nil(ok)
+    assert.truthy(err:find("has no literal form", 1, true))
+  end)
+
+  it("should compile enum members and return member names", function()
+    local env = cel.env.new()
+    assert.is_true(env:register_enum("Level", { DEBUG = 0, INFO = 1, WARN = 2 }))
+
+    local prog = assert(env:compile("level >= Level.INFO ? level : Level.DEBUG"))
+    local ctx = cel.context.new()
+    ctx:add_variable("level", 2)
+    assert.equals(2, prog:execute(ctx))
+    assert.is_true(prog:set_result_enum(env, "Level"))
+    assert.equals("WARN", prog:execute(ctx))
+    assert.is_true(prog:set_result_enum(env, nil))
+    assert.equals(2, prog:execute(ctx))
+
+    local _, err = env:compile("level == Level.TRACE")
+    assert.truthy(err:find("enum 'Level' has no member 'TRACE'", 1, true))
+    assert.is_nil(prog:set_result_enum(env, "Severity"))
+    assert.is_nil(env:register_enum("Level", { ["not valid"] = 1 }))
+  end)
+
+  it("should reject invalid options", function()
+    local env, err = cel.env.new({ disabled_macros = { "sort" } })
+    assert.is_nil(env)
+    assert.equals("Unknown macro 'sort'", err)
+  end)
+end)
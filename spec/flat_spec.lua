@@ -0,0 +1,97 @@
+local test_helper = require("spec.test_helper")
+test_helper.setup_lua_path()
+
+describe("CEL flat API", function()
+  local ffi = require("ffi")
+  local clib, flat
+
+  before_each(function()
+    clib = require("cel.cdefs").clib
+    flat = require("cel.flat")
+  end)
+
+  it("should declare the flat functions for the loaded library", function()
+    assert.equals(flat.CEL_ABI_VERSION, tonumber(clib.cel_abi_version()))
+
+    local program, context = clib.program_handle_new(), clib.context_handle_new()
+    assert.equals(clib.Ok, clib.cel_flat_compile(program, "greeting + ', ' + name"))
+    assert.equals(clib.Ok, clib.cel_flat_set_string(context, "greeting", "hello", 5))
+    assert.equals(clib.Ok, clib.cel_flat_set_json(context, "name", '"world"'))
+
+    local result = clib.cel_flat_execute(program, context)
+    assert.is_true(result ~= 0)
+    assert.equals(clib.String, clib.cel_flat_result_type(result))
+    local len = tonumber(clib.cel_flat_result_len(result))
+    assert.equals("hello, world", ffi.string(clib.cel_flat_result_string(result), len))
+    assert.equals('"hello, world"', ffi.string(clib.cel_flat_result_json(result)))
+    assert.equals(clib.Ok, clib.cel_flat_result_free(result))
+    assert.equals(clib.InvalidHandle, clib.cel_flat_result_free(result))
+
+    assert.equals(clib.CompileError, clib.cel_flat_compile(program, "1 +"))
+    assert.is_true(#ffi.string(clib.cel_flat_error_message()) > 0)
+
+    assert.equals(clib.Ok, clib.context_handle_free(context))
+    assert.equals(clib.Ok, clib.program_handle_free(program))
+  end)
+
+  it("should give each Lua state its own handle registry", function()
+    -- A fresh copy of the module stands in for another Lua state's
+    package.loaded["cel.flat"] = nil
+    local other = require("cel.flat")
+    package.loaded["cel.flat"] = flat
+
+    local state = flat.state(clib)
+    assert.is_true(state ~= 0)
+    assert.is_true(flat.state(clib) == state)
+    assert.is_true(other.state(clib) ~= state)
+
+    local program = clib.program_handle_new_in(state)
+    local other_program = clib.program_handle_new_in(other.state(clib))
+    local other_context = clib.context_handle_new_in(other.state(clib))
+    assert.equals(clib.Ok, clib.cel_flat_compile(other_program, "1 + 1"))
+    local other_result = clib.cel_flat_execute(other_program, other_context)
+    assert.equals(2, tonumber(clib.cel_flat_result_int(other_result)))
+
+    -- Closing the other state frees its handles and leaves this one's alone
+    other.close(clib)
+    assert.equals(clib.InvalidHandle, clib.program_handle_free(other_program))
+    assert.equals(clib.InvalidHandle, clib.context_handle_free(other_context))
+    assert.equals(clib.InvalidHandle, clib.cel_flat_result_free(other_result))
+    assert.equals(clib.Ok, clib.cel_flat_compile(program, "2 + 2"))
+    assert.is_true(clib.program_handle_new_in(0) == 0)
+    assert.equals(clib.InvalidHandle, clib.cel_last_error())
+
+    flat.close(clib)
+    assert.equals(clib.InvalidHandle, clib.program_handle_free(program))
+    assert.is_true(flat.state(clib) ~= state)
+  end)
+
+  it("should share the Lua state's registry between coroutines", function()
+    local state = flat.state(clib)
+    local workers = {}
+    for n = 1, 4 do
+      workers[n] = coroutine.wrap(function()
+        local program = clib.program_handle_new_in(state)
+        local context = clib.context_handle_new_in(state)
+        assert.equals(clib.Ok, clib.cel_flat_compile(program, "n * 10"))
+        coroutine.yield()
+        assert.equals(clib.Ok, clib.cel_flat_set_int(context, "n", n))
+        coroutine.yield()
+        local result = clib.cel_flat_execute(program, context)
+        local value = tonumber(clib.cel_flat_result_int(result))
+        assert.equals(clib.Ok, clib.cel_flat_result_free(result))
+        return value
+      end)
+    end
+
+    -- Interleave the coroutines step by step
+    local values = {}
+    for _ = 1, 3 do
+      for n = 1, 4 do
+        values[n] = workers[n]()
+      end
+    end
+    assert.same({ 10, 20, 30, 40 }, values)
+    flat.close(clib)
+  end)
+end)
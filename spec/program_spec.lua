@@ -24,6 +24,169 @@ describe("CEL Program Compilation and Validation", function()
       assert.is_string(err)
     end)
 
+    it("should report where compilation failed", function()
+      local prog = cel.program.new()
+      local ok, err, details = prog:compile("1 + + 2")
+      assert.is_false(ok)
+      assert.equals("Compilation error: unrecognized token: '+' at line 1, column 5", err)
+      assert.equals(1, details.line)
+      assert.equals(5, details.column)
+      assert.equals(4, details.offset)
+      assert.equals("+", details.token)
+      assert.equals("ERROR: <input>:1:5: unrecognized token: '+'\n | 1 + + 2\n | ....^", details.rendered)
+
+      assert.is_true(prog:compile("1 + 2"))
+      assert.is_nil(prog:compile_error())
+    end)
+
+    it("should fail instead of crashing on literals the parser cannot hold", function()
+      local prog = cel.program.new()
+      local ok, err = prog:compile("99999999999999999999")
+      assert.is_false(ok)
+      assert.matches("^Internal error: ", err)
+
+      assert.is_true(prog:compile("1 + 2"))
+    end)
+
+    it("should enforce compile options", function()
+      local prog = cel.program.new()
+      local ok, err = prog:compile("[[[1]]]", { max_nesting_depth = 2 })
+      assert.is_false(ok)
+      assert.equals("Compilation error: expression is nested deeper than 2 levels at line 1, column 3", err)
+
+      ok, err = prog:compile("[1].map(x, x)", { disabled_macros = { "map" } })
+      assert.is_false(ok)
+      assert.equals("Compilation error: macro 'map' is disabled", err)
+
+      assert.is_true(prog:compile("[1].map(x, x)", { max_comprehensions = 1 }))
+      assert.is_nil(cel.program.validate("12345", { max_expression_length = 4 }))
+    end)
+
+    it("should apply regex options", function()
+      local prog = cel.program.new()
+      local ok, err = prog:compile("'abc'.matches('^a')", { disable_regex = true })
+      assert.is_false(ok)
+      assert.equals("Compilation error: function 'matches' is disabled", err)
+
+      ok, err = prog:compile("'abc'.matches('^abc$')", { max_regex_length = 3 })
+      assert.is_false(ok)
+      assert.equals("Compilation error: regex '^abc$' is longer than 3 bytes", err)
+
+      assert.is_true(prog:compile("'abc'.matches('^a')"))
+      assert.is_true(prog:execute(cel.context.new()))
+    end)
+
+    it("should enable the strings extension", function()
+      local prog = cel.program.new()
+      local ctx = cel.context.new()
+      assert.is_true(prog:compile("' Hello World '.trim().lowerAscii().split(' ').join('-')",
+        { string_ext = true }))
+      assert.equals("hello-world", prog:execute(ctx))
+
+      assert.is_true(prog:compile("'héllo'.substring(1, 3) + 'abc'.replace('b', 'x')",
+        { string_ext = true }))
+      assert.equals("élaxc", prog:execute(ctx))
+
+      assert.is_true(prog:compile("'abc'.upperAscii()"))
+      local value, err = prog:execute(ctx)
+      assert.is_nil(value)
+      assert.is_not_nil(err)
+    end)
+
+    it("should enable the math extension", function()
+      local prog = cel.program.new()
+      local ctx = cel.context.new()
+      ctx:add_variable("score", 140)
+      assert.is_true(prog:compile("math.least(math.greatest(score, 0), 100)", { math_ext = true }))
+      assert.equals(100, prog:execute(ctx))
+
+      assert.is_true(prog:compile("math.round(2.5) + math.abs(-1.5)", { math_ext = true }))
+      assert.equals(4.5, prog:execute(ctx))
+
+      assert.is_true(prog:compile("math.abs(-1)"))
+      assert.is_nil(prog:execute(ctx))
+    end)
+
+    it("should enable the lists extension", function()
+      local prog = cel.program.new()
+      local ctx = cel.context.new()
+      assert.is_true(prog:compile("[[3, 1], [1, 2]].flatten().distinct().sort().slice(0, 2)",
+        { list_ext = true }))
+      assert.same({ 1, 2 }, prog:execute(ctx))
+
+      assert.is_true(prog:compile("[2, 1].sort()"))
+      assert.is_nil(prog:execute(ctx))
+    end)
+
+    it("should enable optional values", function()
+      local prog = cel.program.new()
+      local ctx = cel.context.new()
+      ctx:add_variable_path("request.headers.host", "example.com")
+
+      assert.is_true(prog:compile("request.?headers.?user_agent.orValue('unknown')",
+        { optional_ext = true }))
+      assert.equals("unknown", prog:execute(ctx))
+
+      assert.is_true(prog:compile("request.?headers.host", { optional_ext = true }))
+      assert.equals("example.com", prog:execute(ctx))
+
+      assert.is_true(prog:compile("optional.none()", { optional_ext = true }))
+      assert.is_nil(prog:execute(ctx))
+
+      assert.is_false(prog:compile("request.?headers"))
+    end)
+
+    it("should enable the unicode string functions", function()
+      local prog = cel.program.new()
+      local ctx = cel.context.new()
+      ctx:add_variable("city", "München")
+
+      assert.is_true(prog:compile("city.equalsIgnoreCase('MÜNCHEN')", { unicode_ext = true }))
+      assert.is_true(prog:execute(ctx))
+
+      assert.is_true(prog:compile("['b', 'Ä', 'a'].sortCollated()", { unicode_ext = true }))
+      assert.same({ "a", "Ä", "b" }, prog:execute(ctx))
+
+      assert.is_true(prog:compile("'ﬁ'.normalize('NFKC')", { unicode_ext = true }))
+      assert.equals("fi", prog:execute(ctx))
+    end)
+
+    it("should enable the encoding functions", function()
+      local prog = cel.program.new()
+      local ctx = cel.context.new()
+      ctx:add_variable("token", "dXNlcjpwYXNz")
+
+      assert.is_true(prog:compile("string(base64.decode(token))", { encoders_ext = true }))
+      assert.equals("user:pass", prog:execute(ctx))
+
+      assert.is_true(prog:compile("hex.encode(b'\\x01\\xab')", { encoders_ext = true }))
+      assert.equals("01ab", prog:execute(ctx))
+
+      assert.is_true(prog:compile("url.encode('a b/c')", { encoders_ext = true }))
+      assert.equals("a%20b%2Fc", prog:execute(ctx))
+
+      assert.is_true(prog:compile("hex.encode(b'a')"))
+      assert.is_nil(prog:execute(ctx))
+    end)
+
+    it("should enable the JSON functions", function()
+      local prog = cel.program.new()
+      local ctx = cel.context.new()
+      ctx:add_variable("body", '{"user":{"id":7},"tags":["a","b"]}')
+
+      assert.is_true(prog:compile("json.parse(body).user.id == 7", { json_ext = true }))
+      assert.is_true(prog:execute(ctx))
+
+      assert.is_true(prog:compile("json.stringify({'b': [1], 'a': 'x'})", { json_ext = true }))
+      assert.equals('{"a":"x","b":[1]}', prog:execute(ctx))
+
+      assert.is_true(prog:compile("json.parse('{')", { json_ext = true }))
+      assert.is_nil(prog:execute(ctx))
+
+      assert.is_true(prog:compile("parse(body)", { json_ext = true }))
+      assert.is_nil(prog:execute(ctx))
+    end)
+
     it("should handle empty expressions", function()
       local prog = cel.program.new()
       local ok, err = prog:compile("")
@@ -63,6 +226,41 @@ describe("CEL Program Compilation and Validation", function()
       assert.equals(42, result)
     end)
 
+    it("should execute to JSON", function()
+      local prog = cel.program.new()
+      local ctx = cel.context.new()
+      ctx:add_variable("name", "cel")
+
+      assert.is_true(prog:compile("{'name': name, 'ids': [1, 2]}"))
+      local json, err = prog:execute_json(ctx)
+      assert.is_nil(err)
+      assert.equals('{"ids":[1,2],"name":"cel"}', json)
+    end)
+
+    it("should execute to a yes or no", function()
+      local prog = cel.program.new()
+      local ctx = cel.context.new()
+      ctx:add_variable("roles", {})
+
+      assert.is_true(prog:compile("roles"))
+      assert.is_false(prog:execute_bool(ctx, true))
+      local answer, err = prog:execute_bool(ctx)
+      assert.is_nil(answer)
+      assert.matches("expected bool", err, 1, true)
+
+      assert.is_true(prog:compile("size(roles) == 0"))
+      assert.is_true(prog:execute_bool(ctx))
+    end)
+
+    it("should evaluate JSON in one call", function()
+      assert.equals("[\"ada\"]", cel.program.eval_json("[user.name]", '{"user": {"name": "ada"}}'))
+      assert.equals("3", cel.program.eval_json("1 + 2"))
+
+      local json, err = cel.program.eval_json("x", "[1]")
+      assert.is_nil(json)
+      assert.equals("Variables must be a JSON object", err)
+    end)
+
     it("should handle multiple executions", function()
       local prog = cel.program.new()
       local ctx1 = cel.context.new()
@@ -82,6 +280,109 @@ describe("CEL Program Compilation and Validation", function()
       assert.is_nil(err2)
       assert.equals(40, result2)
     end)
+
+    it("should return lists as Lua tables", function()
+      local prog = cel.program.new()
+      local ctx = cel.context.new()
+
+      assert.is_true(prog:compile("[1, 'two', [3]]"))
+
+      local result, err = prog:execute(ctx)
+      assert.is_nil(err)
+      assert.same({ 1, "two", { 3 } }, result)
+    end)
+
+    it("should return maps as Lua tables", function()
+      local prog = cel.program.new()
+      local ctx = cel.context.new()
+
+      assert.is_true(prog:compile("{'name': 'cel', 'size': 3}"))
+
+      local result, err = prog:execute(ctx)
+      assert.is_nil(err)
+      assert.same({ name = "cel", size = 3 }, result)
+    end)
+
+    it("should report execution errors", function()
+      local prog = cel.program.new()
+      local ctx = cel.context.new()
+
+      assert.is_true(prog:compile("missing_variable + 1"))
+
+      local result, err = prog:execute(ctx)
+      assert.is_nil(result)
+      assert.is_string(err)
+    end)
+
+    it("should execute in the background, yielding while it waits", function()
+      local prog = cel.program.new()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("n", 3))
+      assert.is_true(prog:compile("[1, 2, n].map(x, x * x)"))
+
+      local co = coroutine.create(function()
+        return prog:execute_async(ctx)
+      end)
+      local ok, result
+      repeat
+        ok, result = coroutine.resume(co)
+        assert.is_true(ok)
+      until coroutine.status(co) == "dead"
+      assert.same({ 1, 4, 9 }, result)
+
+      assert.is_true(prog:compile("missing_variable + 1"))
+      local value, err = prog:execute_async(ctx, function() return false end)
+      assert.is_nil(value)
+      assert.matches("missing_variable", err)
+    end)
+
+    it("should stop evaluations whose token is cancelled", function()
+      local prog = cel.program.new()
+      local ctx = cel.context.new()
+      assert.is_true(prog:compile("[1, 2, 3].all(x, x > 0)"))
+
+      local token = cel.cancel_token()
+      assert.is_true(prog:execute(ctx, token))
+      token:cancel()
+      local value, err = prog:execute(ctx, token)
+      assert.is_nil(value)
+      assert.matches("cancelled", err)
+
+      value, err = prog:execute_async(ctx, function() return false end, token)
+      assert.is_nil(value)
+      assert.matches("cancelled", err)
+      assert.is_true(prog:execute(ctx))
+    end)
+
+    it("should release handles on close, but not while executing in the background", function()
+      local prog = cel.program.new()
+      local ctx = cel.context.new()
+      assert.is_true(prog:compile("[1, 2, 3].map(x, x * 2)"))
+
+      local co = coroutine.create(function()
+        return prog:execute_async(ctx)
+      end)
+      assert.is_true(coroutine.resume(co))
+      if coroutine.status(co) ~= "dead" then
+        local ok, err = prog:close()
+        assert.is_nil(ok)
+        assert.matches("in use", err)
+        assert.is_nil(ctx:close())
+        repeat
+          assert.is_true(coroutine.resume(co))
+        until coroutine.status(co) == "dead"
+      end
+
+      assert.is_true(prog:close())
+      assert.is_true(prog:close())
+      local value, err = prog:execute(ctx)
+      assert.is_nil(value)
+      assert.is_string(err)
+
+      assert.is_true(ctx:close())
+      assert.is_true(ctx:close())
+      assert.is_nil(ctx:add_variable("a", 1))
+    end)
   end)
 
   describe("Program Validation", function()
@@ -112,6 +413,31 @@ describe("CEL Program Compilation and Validation", function()
       assert.is_nil(err)
       assert.is_number(result.variable_count)
     end)
+
+    it("should report every error and warning", function()
+      local diagnostics = cel.program.validate_full("x == x && xs.all(x, x > 0)")
+      assert.are.equal(2, #diagnostics)
+      assert.are.equal("warning", diagnostics[1].severity)
+      assert.are.equal("comparison is always true", diagnostics[1].message)
+      assert.are.equal(3, diagnostics[1].column)
+      assert.are.equal(2, diagnostics[1].length)
+      assert.are.equal(
+        "comprehension variable 'x' shadows a variable of the same name",
+        diagnostics[2].message
+      )
+      assert.are.equal(18, diagnostics[2].column)
+
+      diagnostics = cel.program.validate_full("1 + + 2")
+      assert.are.equal(1, #diagnostics)
+      assert.are.equal("error", diagnostics[1].severity)
+      assert.are.equal(1, diagnostics[1].line)
+      assert.are.equal(5, diagnostics[1].column)
+
+      assert.are.same({}, cel.program.validate_full("a + 1"))
+      local result, err = cel.program.validate_full("a", { disabled_macros = { "nope" } })
+      assert.is_nil(result)
+      assert.is_not_nil(err)
+    end)
   end)
 
   describe("Program Lifecycle", function()
@@ -135,6 +461,184 @@ describe("CEL Program Compilation and Validation", function()
       assert.equals(12, result)
     end)
 
+    it("should measure expression complexity", function()
+      local prog = cel.program.new()
+      local metrics, err = prog:metrics()
+      assert.is_nil(metrics)
+      assert.equals("Program not compiled", err)
+
+      assert.is_true(prog:compile("size(xs) > 1 && xs.all(x, x > 0)"))
+      assert.same({ nodes = 11, depth = 4, comprehensions = 1, calls = 1, cost = 407 },
+                  prog:metrics())
+    end)
+
+    it("should optimize constant subexpressions", function()
+      local prog = cel.program.new()
+      local stats, err = prog:optimize()
+      assert.is_nil(stats)
+      assert.equals("Program not compiled", err)
+
+      assert.is_true(prog:compile("x > 1 + 2 ? 'a' + 'b' : 'c'"))
+      stats = assert(prog:optimize())
+      assert.equals(10, stats.nodes_before)
+      assert.equals(6, stats.nodes_after)
+
+      local ctx = cel.context.new()
+      ctx:add_variable("x", 4)
+      assert.equals("ab", prog:execute(ctx))
+    end)
+
+    it("should fail executions returning another type than expected", function()
+      local cdefs = require("cel.cdefs")
+      local prog = cel.program.new()
+      local ok, err = prog:expect_type(cdefs.Bool)
+      assert.is_nil(ok)
+      assert.equals("Program not compiled", err)
+
+      assert.is_true(prog:compile("verdict"))
+      assert.is_true(prog:expect_type(cdefs.Bool))
+      local ctx = cel.context.new()
+      ctx:add_variable("verdict", "allow")
+      local result
+      result, err = prog:execute(ctx)
+      assert.is_nil(result)
+      assert.matches("result has type string, expected bool", err, 1, true)
+
+      ctx:add_variable("verdict", true)
+      assert.is_true(prog:execute(ctx))
+    end)
+
+    it("should infer the result type of expressions whose shape determines it", function()
+      local cdefs = require("cel.cdefs")
+      local prog = cel.program.new()
+      local ok, err = prog:inferred_type()
+      assert.is_nil(ok)
+      assert.equals("Program not compiled", err)
+
+      assert.is_true(prog:compile("size(name) > 3 || admin"))
+      assert.equals(cdefs.Bool, prog:inferred_type())
+      assert.is_true(prog:compile("'id-' + string(id)"))
+      assert.equals(cdefs.String, prog:inferred_type())
+      assert.is_true(prog:compile("user.name"))
+      assert.is_nil(prog:inferred_type())
+    end)
+
+    it("should build results in an arena when asked", function()
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("{'greeting': 'hello ' + name, 'tags': tags}"))
+      local ctx = cel.context.new()
+      ctx:add_variable("name", "ada")
+      ctx:add_variable("tags", { "a", "b" })
+
+      local expected = { greeting = "hello ada", tags = { "a", "b" } }
+      assert.is_true(prog:set_arena(true))
+      for _ = 1, 3 do
+        assert.same(expected, prog:execute(ctx))
+      end
+      local clib = require("cel.cdefs").clib
+      assert.is_true(tonumber(clib.cel_arena_allocated_bytes(prog.arena)) > 0)
+
+      ctx:add_variable("name", "grace")
+      local value, err = prog:execute(ctx)
+      assert.is_nil(err)
+      assert.equals("hello grace", value.greeting)
+
+      assert.is_true(prog:set_arena(false))
+      assert.is_nil(prog.arena)
+      ctx:add_variable("name", "ada")
+      assert.same(expected, prog:execute(ctx))
+    end)
+
+    it("should execute a batch of contexts with an outcome for each", function()
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("age >= 18"))
+
+      local adult, child, unknown = cel.context.new(), cel.context.new(), cel.context.new()
+      adult:add_variable("age", 30)
+      child:add_variable("age", 12)
+      local outcomes = assert(prog:execute_batch({ adult, child, unknown }))
+      assert.equals(3, #outcomes)
+      assert.is_true(outcomes[1].value)
+      assert.is_false(outcomes[2].value)
+      assert.is_nil(outcomes[3].value)
+      assert.is_string(outcomes[3].error)
+      assert.same({}, prog:execute_batch({}))
+
+      local _, err = cel.program.new():execute_batch({ adult })
+      assert.equals("Program not compiled", err)
+    end)
+
+    it("should trace the clauses that decide a result", function()
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("role == 'admin' || age >= 18"))
+
+      local ctx = cel.context.new()
+      ctx:add_variable("role", "user")
+      ctx:add_variable("age", 16)
+      local result, err, trace = prog:execute_traced(ctx)
+      assert.is_false(result)
+      assert.is_nil(err)
+      assert.is_string(trace)
+      assert.is_truthy(trace:find('"expression":"age >= 18","value":false', 1, true))
+    end)
+
+    it("should fingerprint equivalent expressions alike", function()
+      local a, b, c = cel.program.new(), cel.program.new(), cel.program.new()
+      assert.is_true(a:compile("a+b == 'x' // legacy rule"))
+      assert.is_true(b:compile('(a + b) == "x"'))
+      assert.is_true(c:compile("a + b == 'y'"))
+
+      assert.equals("692607cc7f502f92", a:hash())
+      assert.equals(a:hash(), b:hash())
+      assert.are_not.equals(a:hash(), c:hash())
+      assert.is_nil(cel.program.new():hash())
+    end)
+
+    it("should list the field paths an expression reads", function()
+      local prog = cel.program.new()
+      assert.is_true(prog:compile(
+        "request.headers['x-id'] != '' && user.roles.exists(r, r.name == 'admin')"))
+      assert.equals(
+        '{"request":["request.headers[\\"x-id\\"]"],"user":["user.roles[*].name"]}',
+        prog:dependencies_json())
+      assert.is_nil(cel.program.new():dependencies_json())
+    end)
+
+    it("should expose the macro-expanded AST", function()
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("xs.all(x, x > 0)"))
+      local json = prog:expanded_ast_json()
+      assert.truthy(json:find('"comprehensionExpr"', 1, true))
+      assert.truthy(json:find('"accuVar":"__result__"', 1, true))
+      assert.truthy(json:find('"function":"@not_strictly_false"', 1, true))
+      assert.is_nil(cel.program.new():expanded_ast_json())
+    end)
+
+    it("should rename variable references in source", function()
+      local renamed = cel.program.rename_variable(
+        "user.name == 'user' && xs.all(user, user > 0) // user", "user", "account")
+      assert.equals("account.name == 'user' && xs.all(user, user > 0) // user", renamed)
+
+      local result, err = cel.program.rename_variable("xs.all(x, x > n)", "n", "x")
+      assert.is_nil(result)
+      assert.is_not_nil(err)
+      assert.is_nil(cel.program.rename_variable("a +", "a", "b"))
+    end)
+
+    it("should simplify expressions and detect equivalent ones", function()
+      assert.equals("x && y", cel.program.simplify("x && (y || false) && x"))
+      assert.equals("!c", cel.program.simplify("c ? false : true"))
+      assert.is_nil(cel.program.simplify("x &&"))
+
+      assert.is_true(cel.program.equivalent("a && b == 'x' // rule", "\"x\" == b && a"))
+      assert.is_true(cel.program.equivalent("xs.all(x, x > n)", "xs.all(y, n < y)"))
+      assert.is_false(cel.program.equivalent("x && true", "x"))
+      assert.is_true(cel.program.equivalent("x && true", "x", true))
+      local result, err = cel.program.equivalent("a +", "a")
+      assert.is_nil(result)
+      assert.is_not_nil(err)
+    end)
+
     it("should handle compilation errors gracefully", function()
       local prog = cel.program.new()
 
@@ -0,0 +1,79 @@
+local test_helper = require("spec.test_helper")
+test_helper.setup_lua_path()
+
+describe("CEL RuleSet", function()
+  local cel
+
+  before_each(function()
+    cel = require("cel")
+  end)
+
+  it("should evaluate every rule against one context", function()
+    local rules = cel.ruleset.new()
+    assert.is_true(rules:add("admin", "'admin' in user.roles"))
+    assert.is_true(rules:add("adult", "user.age >= 18"))
+    assert.is_true(rules:add("broken", "user.missing + 1"))
+    local ok, err = rules:add("admin", "true")
+    assert.is_false(ok)
+    assert.matches("already defined", err)
+
+    local ctx = cel.context.new()
+    ctx:add_variable("user", { roles = { "admin" }, age = 17 })
+    local results
+    results, err = rules:execute_all(ctx)
+    assert.is_nil(results)
+    assert.matches("not compiled", err)
+
+    assert.is_true(rules:compile())
+    results = assert(rules:execute_all(ctx))
+    assert.is_true(results.admin.value)
+    assert.is_false(results.adult.value)
+    assert.is_string(results.broken.error)
+  end)
+
+  it("should name the rules that fail to compile", function()
+    local rules = cel.ruleset.new()
+    assert.is_true(rules:add("good", "1 + 1"))
+    assert.is_true(rules:add("bad", "1 + + "))
+    local ok, err = rules:compile()
+    assert.is_false(ok)
+    assert.matches("^rule 'bad': ", err)
+  end)
+
+  it("should stop at the first matching rule", function()
+    local rules = cel.ruleset.new()
+    assert.is_true(rules:add("blocked", "ip in blocklist"))
+    assert.is_true(rules:add("internal", "ip.startsWith('10.')"))
+    assert.is_true(rules:add("unreachable", "missing"))
+    assert.is_true(rules:compile())
+
+    local ctx = cel.context.new()
+    ctx:add_variable("ip", "10.0.0.1")
+    ctx:add_variable("blocklist", { "1.2.3.4" })
+    assert.equals("internal", rules:first_match(ctx))
+
+    ctx:add_variable("ip", "192.168.0.1")
+    local name, err = rules:first_match(ctx)
+    assert.is_nil(name)
+    assert.matches("rule 'unreachable'", err)
+  end)
+
+  it("should match with a predicate over the rule's result", function()
+    local rules = cel.ruleset.new()
+    assert.is_true(rules:add("allow", "'allow'"))
+    assert.is_true(rules:add("deny", "'deny'"))
+    assert.is_true(rules:compile())
+
+    local ctx = cel.context.new()
+    local name, err = rules:first_match(ctx)
+    assert.is_nil(name)
+    assert.is_nil(err)
+
+    assert.is_true(rules:set_predicate("result == 'deny'"))
+    assert.equals("deny", rules:first_match(ctx))
+    local ok
+    ok, err = rules:set_predicate("result ==")
+    assert.is_false(ok)
+    assert.is_string(err)
+  end)
+end)
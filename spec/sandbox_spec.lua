@@ -0,0 +1,56 @@
+local test_helper = require("spec.test_helper")
+test_helper.setup_lua_path()
+
+describe("CEL sandbox", function()
+  local cel
+
+  before_each(function()
+    cel = require("cel")
+  end)
+
+  it("should apply safe limits by default", function()
+    local sandbox = assert(cel.sandbox())
+    local _, err = sandbox:compile(string.rep("x + ", 2000) .. "x")
+    assert.matches("length", err)
+    _, err = sandbox:compile("'a'.lowerAscii()")
+    assert.matches("not declared", err)
+
+    local prog = assert(sandbox:compile("xs.all(x, x > 0)"))
+    local ctx = sandbox:context()
+    ctx:add_variable("xs", { 1, 2, 3 })
+    assert.is_true(sandbox:execute(prog, ctx))
+  end)
+
+  it("should enforce allowed functions and variables", function()
+    local sandbox = assert(cel.sandbox({
+      allowed_functions = { "size" },
+      variables = { "name" },
+      string_ext = true,
+    }))
+    assert(sandbox:compile("size(name) > 2"))
+    local _, err = sandbox:compile("name.lowerAscii() == 'a'")
+    assert.matches("function 'lowerAscii' is not allowed", err)
+    _, err = sandbox:compile("size(nmae) > 2")
+    assert.matches("undeclared reference to 'nmae'", err)
+  end)
+
+  it("should stop executions over their cost or time", function()
+    local ctx = cel.context.new()
+    ctx:add_variable("xs", { 1, 2, 3, 4, 5 })
+
+    local cheap = assert(cel.sandbox({ max_cost = 3 }))
+    local value, err = cheap:execute(assert(cheap:compile("xs.all(x, x > 0)")), ctx)
+    assert.is_nil(value)
+    assert.matches("cost budget", err)
+
+    local slow = assert(cel.sandbox({ timeout_ms = 1, max_cost = 0 }))
+    local items = {}
+    for i = 1, 3000 do
+      items[i] = i
+    end
+    ctx:add_variable("xs", items)
+    value, err = slow:execute(assert(slow:compile("xs.all(x, xs.exists(y, y == x + 1))")), ctx)
+    assert.is_nil(value)
+    assert.equals("Execution timed out after 1 ms", err)
+  end)
+end)
@@ -0,0 +1,44 @@
+local test_helper = require("spec.test_helper")
+test_helper.setup_lua_path()
+
+describe("CEL Template", function()
+  local cel
+
+  before_each(function()
+    cel = require("cel")
+  end)
+
+  it("should substitute parameters as typed literals", function()
+    local template = assert(cel.template.compile("user.age > {min_age} && user.name != {name}"))
+    local expression = assert(template:instantiate('{"min_age": 21, "name": "bob"}'))
+    assert.equals('user.age > 21 && user.name != "bob"', expression)
+
+    local prog = cel.program.new()
+    assert.is_true(prog:compile(expression))
+    local ctx = cel.context.new()
+    ctx:add_variable("user", { age = 30, name = "alice" })
+    assert.is_true(prog:execute(ctx))
+  end)
+
+  it("should keep parameters from changing the expression", function()
+    local template = assert(cel.template.compile("role == {role}"))
+    local expression = assert(template:instantiate([[{"role": "x' || true || '"}]]))
+    assert.equals([[role == "x' || true || '"]], expression)
+  end)
+
+  it("should report bad templates and parameters", function()
+    local template, err = cel.template.compile("a.{field} == 1")
+    assert.is_nil(template)
+    assert.matches("can only stand for a value", err)
+
+    template = assert(cel.template.compile("a == {b}"))
+    local expression
+    expression, err = template:instantiate("{}")
+    assert.is_nil(expression)
+    assert.equals("missing template parameter 'b'", err)
+
+    expression, err = template:instantiate('{"b": 1, "c": 2}')
+    assert.is_nil(expression)
+    assert.equals("unknown template parameter 'c'", err)
+  end)
+end)
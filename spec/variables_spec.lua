@@ -8,6 +8,11 @@ describe("CEL Variables and Context", function()
     cel = require("cel")
   end)
 
+  after_each(function()
+    -- The default is module state, so a failing test must not leak its policy
+    cel.set_number_policy("force-float")
+  end)
+
   describe("Context Management", function()
     it("should create and manage context", function()
       local ctx = cel.context.new()
@@ -21,8 +26,420 @@ describe("CEL Variables and Context", function()
       assert.is_nil(err)
 
       ctx:reset()
-      -- After reset, the context should be empty
-      -- Note: We can't directly test this without executing an expression
+      assert.is_false(ctx:has_variable("test"))
+    end)
+
+    it("should remove single variables", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("a", 1))
+      assert.is_true(ctx:add_variable("b", "two"))
+
+      assert.is_true(ctx:remove_variable("a"))
+      assert.is_false(ctx:remove_variable("a"))
+
+      local bound, value_type = ctx:has_variable("b")
+      assert.is_true(bound)
+      assert.equals(require("cel.cdefs").String, value_type)
+      assert.is_false(ctx:has_variable("a"))
+    end)
+
+    it("should read variables back", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("name", "cel"))
+      assert.is_true(ctx:add_variable("count", 3))
+
+      assert.equals("cel", ctx:get_variable("name"))
+      assert.equals(3, ctx:get_variable("count"))
+
+      local value, err = ctx:get_variable("missing")
+      assert.is_nil(value)
+      assert.matches("not bound", err)
+    end)
+
+    it("should add variables at dotted paths", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable_path("request.headers.authorization", "Bearer x"))
+      assert.is_true(ctx:add_variable_path("request.method", "GET"))
+
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("request.headers.authorization + ' ' + request.method"))
+      assert.equals("Bearer x GET", prog:execute(ctx))
+
+      local ok, err = ctx:add_variable_path("request.method.name", 1)
+      assert.is_nil(ok)
+      assert.matches("not a map", err)
+    end)
+
+    it("should add tables as lists and maps", function()
+      local ctx = cel.context.new()
+      local prog = cel.program.new()
+      assert.is_true(ctx:add_variable("items", { 1, "two", { 3 } }))
+      assert.is_true(ctx:add_variable("m", { k = "v", nested = { n = 2 }, empty = {} }))
+
+      assert.is_true(prog:compile("size(items) == 3 && items[2][0] == 3"))
+      assert.is_true(prog:execute(ctx))
+      assert.is_true(prog:compile("m.k == 'v' && m.nested.n == 2 && size(m.empty) == 0"))
+      assert.is_true(prog:execute(ctx))
+
+      local ok, err = ctx:add_variable("bad", { [1.5] = true })
+      assert.is_nil(ok)
+      assert.matches("cannot be map keys", err)
+    end)
+
+    it("should bind borrowed strings until invalidated", function()
+      local ctx = cel.context.new()
+      local prog = cel.program.new()
+      assert.is_true(ctx:add_variable_borrowed("body", string.rep("x", 100000) .. "needle", 5))
+      assert.is_true(prog:compile("body.contains('needle')"))
+      assert.is_true(prog:execute(ctx))
+
+      assert.equals(0, ctx:invalidate_borrowed(6))
+      assert.equals(1, ctx:invalidate_borrowed(5))
+      assert.is_false(ctx:has_variable("body"))
+    end)
+
+    it("should list variable names", function()
+      local ctx = cel.context.new()
+      assert.same({}, ctx:list_variables())
+
+      assert.is_true(ctx:add_variable("b", 1))
+      assert.is_true(ctx:add_variable("a", "x"))
+      assert.same({ "a", "b" }, ctx:list_variables())
+    end)
+
+    it("should reject expressions deeper than the limit", function()
+      local ctx = cel.context.new()
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("[[[1]]]"))
+
+      ctx:set_max_depth(3)
+      local result, err = prog:execute(ctx)
+      assert.is_nil(result)
+      assert.matches("expression too deep", err)
+
+      ctx:set_max_depth(0)
+      assert.is_not_nil(prog:execute(ctx))
+    end)
+
+    it("should evaluate now() at an injected instant", function()
+      local ctx = cel.context.new()
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("now() == timestamp('2024-01-01T00:00:00Z')"))
+
+      assert.is_true(ctx:set_now(1704067200))
+      assert.is_true(prog:execute(ctx))
+
+      local ok, err = ctx:set_now(0, 1000000000)
+      assert.is_nil(ok)
+      assert.matches("out of range", err)
+
+      ctx:clear_now()
+      assert.is_false(prog:execute(ctx))
+    end)
+
+    it("should apply the missing key policy", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("user", { name = "a" }))
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("user.age"))
+
+      local result, err = prog:execute(ctx)
+      assert.is_nil(result)
+      assert.matches("No such key", err)
+
+      assert.is_true(ctx:set_missing_keys("null"))
+      assert.is_nil(prog:execute(ctx))
+      assert.is_true(prog:compile("has(user.age)"))
+      assert.is_false(prog:execute(ctx))
+
+      assert.is_true(ctx:set_missing_keys("unknown"))
+      result, err = prog:execute(ctx)
+      assert.is_nil(result)
+      assert.equals("Result is unknown: missing user.age", err)
+      assert.is_true(prog:compile("has(user.name) || user.age > 1"))
+      assert.is_true(prog:execute(ctx))
+
+      local ok
+      ok, err = ctx:set_missing_keys("ignore")
+      assert.is_nil(ok)
+      assert.matches("Unknown missing key policy", err)
+    end)
+
+    it("should propagate null through operators", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("row", { qty = 2 }))
+      assert.is_true(ctx:add_variable("price", nil))
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("price * row.qty > 10"))
+
+      local result, err = prog:execute(ctx)
+      assert.is_nil(result)
+      assert.is_not_nil(err)
+
+      assert.is_true(ctx:set_null_propagation(true))
+      result, err = prog:execute(ctx)
+      assert.is_nil(result)
+      assert.is_nil(err)
+
+      assert.is_true(prog:compile("price == null && row.qty * 3 == 6"))
+      assert.is_true(prog:execute(ctx))
+    end)
+
+    it("should promote mixed numeric operands", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("count", 1))
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("count < 1.5 && count + 0.5 == 1.5 && 2u > count"))
+
+      local result, err = prog:execute(ctx)
+      assert.is_nil(result)
+      assert.is_not_nil(err)
+
+      assert.is_true(ctx:set_numeric_promotion(true))
+      assert.is_true(prog:execute(ctx))
+
+      assert.is_true(prog:compile("count - 2u"))
+      assert.equals(-1, prog:execute(ctx))
+    end)
+
+    it("should bind NaN and infinities unless told to reject them", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("nan", 0 / 0))
+      assert.is_true(ctx:add_variable("inf", math.huge))
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("nan != nan && !(nan < 1.0) && inf > 1.0"))
+      assert.is_true(prog:execute(ctx))
+
+      assert.is_true(ctx:set_reject_non_finite(true))
+      local ok, err = ctx:add_variable("x", -math.huge)
+      assert.is_nil(ok)
+      assert.matches("rejects", err)
+    end)
+
+    it("should stop executions that pass the memory limit", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("s", string.rep("x", 100)))
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("[s, s].map(x, x + x + x + x + x).size()"))
+
+      assert.is_true(ctx:set_max_memory(1000))
+      local result, err = prog:execute(ctx)
+      assert.is_nil(result)
+      assert.matches("exceeded its memory limit of 1000 bytes", err)
+
+      assert.is_true(ctx:set_max_memory(0))
+      assert.equals(2, prog:execute(ctx))
+    end)
+
+    it("should stop executions that pass the iteration limit", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("xs", { 1, 2, 3 }))
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("xs.all(x, xs.all(y, y > 0))"))
+
+      assert.is_true(ctx:set_max_iterations(12))
+      assert.is_true(prog:execute(ctx))
+      assert.is_true(ctx:set_max_iterations(11))
+      local result, err = prog:execute(ctx)
+      assert.is_nil(result)
+      assert.matches("exceeded its limit of 11 comprehension iterations", err)
+
+      assert.is_true(ctx:set_max_iterations(0))
+      assert.is_true(prog:execute(ctx))
+    end)
+
+    it("should evaluate deterministically", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("m", { b = 2, a = 1, c = 3 }))
+      local prog = cel.program.new()
+      assert.is_true(
+        prog:compile("m.map(k, k) == ['a', 'b', 'c'] && now() > timestamp('2000-01-01T00:00:00Z')")
+      )
+
+      assert.is_true(ctx:set_deterministic(true))
+      local result, err = prog:execute(ctx)
+      assert.is_nil(result)
+      assert.matches("set the time on the context", err)
+
+      assert.is_true(ctx:set_now(1704067200))
+      assert.is_true(prog:execute(ctx))
+    end)
+
+    it("should keep an audit log of recent executions", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("n", 0))
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("10 / n"))
+      assert.equals("[]", ctx:take_audit_log())
+
+      assert.is_true(ctx:set_audit_log(1))
+      assert.is_nil(prog:execute(ctx))
+      assert.is_true(ctx:add_variable("n", 5))
+      assert.equals(2, prog:execute(ctx))
+
+      local log = ctx:take_audit_log()
+      assert.matches('"variables":%["n"%],"result":2', log)
+      assert.is_nil(log:find('"error"', 1, true))
+      assert.equals("[]", ctx:take_audit_log())
+    end)
+
+    it("should reject changes to a frozen context", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("limit", 10))
+      assert.is_false(ctx:is_frozen())
+      assert.equals(ctx, ctx:freeze())
+      assert.is_true(ctx:is_frozen())
+
+      local ok, err = ctx:add_variable("limit", 20)
+      assert.is_nil(ok)
+      assert.equals("Context is frozen", err)
+      ctx:reset()
+
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("limit * 2"))
+      assert.equals(20, prog:execute(ctx))
+    end)
+
+    it("should round-trip variables through JSON", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("name", "ada"))
+      assert.is_true(ctx:add_variable("created", cel.value.timestamp("2024-01-01T00:00:00Z")))
+
+      local json = ctx:to_json()
+      assert.matches('"timestamp":"2024%-01%-01T00:00:00Z"', json)
+
+      local restored = cel.context.from_json(json)
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("name == 'ada' && created == timestamp('2024-01-01T00:00:00Z')"))
+      assert.is_true(prog:execute(restored))
+
+      local missing, err = cel.context.from_json('{"values": {"x": 1}}')
+      assert.is_nil(missing)
+      assert.matches("Invalid tagged value", err)
+    end)
+
+    it("should bind timestamp and duration values", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("created", cel.value.timestamp("2024-01-01T00:00:00Z")))
+      assert.is_true(ctx:add_variable("ttl", cel.value.duration("1h30m")))
+
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("created + ttl == timestamp('2024-01-01T01:30:00Z')"))
+      assert.is_true(prog:execute(ctx))
+
+      assert.is_true(prog:compile("ttl"))
+      assert.equals(5400, prog:execute(ctx))
+
+      local value, err = cel.value.duration("90 minutes")
+      assert.is_nil(value)
+      assert.matches("invalid duration", err)
+    end)
+
+    it("should bind and compute big integers", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("total", cel.value.bigint("18446744073709551616")))
+      assert.is_true(ctx:set_numeric_promotion(true))
+
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("total * 2 > 18446744073709551615u"))
+      assert.is_true(prog:execute(ctx))
+
+      assert(ctx:set_number_policy("string-for-large-ints"))
+      assert.is_true(prog:compile("total * 1000 + 1"))
+      assert.equals("18446744073709551616001", prog:execute(ctx))
+      assert.is_true(prog:compile("bigint(41) + 1"))
+      assert.equals(42, prog:execute(ctx))
+
+      local value, err = cel.value.bigint("1.5")
+      assert.is_nil(value)
+      assert.matches("invalid big integer", err)
+    end)
+
+    it("should compute exactly with decimals", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("price", cel.value.decimal("19.99")))
+      assert.is_true(ctx:set_numeric_promotion(true))
+
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("price * 3 + decimal('0.03')"))
+      assert.equals("60.00", prog:execute(ctx))
+      assert.is_true(prog:compile("decimal('0.1') + decimal('0.2') == decimal('0.3')"))
+      assert.is_true(prog:execute(ctx))
+      assert.is_true(prog:compile("price > 19 && price < 20u"))
+      assert.is_true(prog:execute(ctx))
+
+      local value, err = cel.value.decimal("1e3")
+      assert.is_nil(value)
+      assert.matches("invalid decimal", err)
+    end)
+
+    it("should return types by name", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("user", { age = 30 }))
+      assert.is_true(ctx:add_variable("int_type", cel.value.type("int")))
+
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("type(user.age)"))
+      assert.equals("int", prog:execute(ctx))
+      assert.is_true(prog:compile("[type(user), type(duration('1s'))]"))
+      assert.same({ "map", "google.protobuf.Duration" }, prog:execute(ctx))
+      assert.is_true(prog:compile("type(user.age) == int_type"))
+      assert.is_true(prog:execute(ctx))
+
+      local value, err = cel.value.type("")
+      assert.is_nil(value)
+      assert.matches("invalid type name", err)
+    end)
+
+    it("should render values as text", function()
+      local price = cel.value.decimal("19.90")
+      assert.equals('decimal("19.90")', cel.value.debug_string(price))
+      assert.equals("19.90", cel.value.display_string(price))
+
+      local elapsed = cel.value.duration("1m30s")
+      assert.equals('duration("90s")', cel.value.debug_string(elapsed))
+      assert.equals("90s", cel.value.display_string(elapsed))
+      assert.equals("int", cel.value.display_string(cel.value.type("int")))
+    end)
+
+    it("should compare and hash values under CEL equality", function()
+      local exact, whole = cel.value.decimal("2.00"), cel.value.bigint("2")
+      assert.is_true(cel.value.equals(exact, whole))
+      assert.equals(cel.value.hash(exact), cel.value.hash(whole))
+      assert.matches("^%x+$", cel.value.hash(exact))
+      assert.equals(16, #cel.value.hash(exact))
+      assert.is_false(cel.value.equals(exact, cel.value.decimal("2.01")))
+    end)
+
+    it("should copy values", function()
+      local copy = cel.value.clone(cel.value.decimal("2.50"))
+      collectgarbage()
+      assert.equals('decimal("2.50")', cel.value.debug_string(copy))
+
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("price", copy))
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("price"))
+      assert.equals("2.50", prog:execute(ctx))
+    end)
+
+    it("should restore snapshots", function()
+      local ctx = cel.context.new()
+      assert.is_true(ctx:add_variable("base", 1))
+      local snap = ctx:snapshot()
+
+      local prog = cel.program.new()
+      assert.is_true(prog:compile("base + extra"))
+      for i = 1, 3 do
+        assert.is_true(ctx:add_variable("extra", i))
+        assert.equals(1 + i, prog:execute(ctx))
+        assert.is_true(ctx:restore(snap))
+        assert.same({ "base" }, ctx:list_variables())
+      end
+
+      local ok, err = cel.context.new():restore(snap)
+      assert.is_nil(ok)
+      assert.matches("Invalid snapshot", err)
     end)
   end)
 
@@ -60,6 +477,75 @@ describe("CEL Variables and Context", function()
       assert.is_nil(err)
       assert.is_nil(result)
     end)
+
+    it("should keep interior NUL bytes in string variables", function()
+      local result, err = test_helper.eval_expression(cel, "size(s)", { s = "a\0b" })
+      assert.is_nil(err)
+      assert.equals(3, result)
+    end)
+
+    it("should pass non-UTF-8 strings as bytes", function()
+      local result, err = test_helper.eval_expression(cel, "data == b'\\xff\\xfe'",
+                                                      { data = "\255\254" })
+      assert.is_nil(err)
+      assert.is_true(result)
+    end)
+
+    it("should return large integers as the number policy says", function()
+      local ctx = cel.context.new()
+      ctx:add_variable("id", 9007199254740993LL)
+      ctx:add_variable("max", 18446744073709551615ULL)
+      local prog = cel.program.new()
+      assert(prog:compile("[id + 1, max, 7]"))
+
+      assert.equals("force-float", cel.number_policy())
+      local result = assert(prog:execute(ctx))
+      assert.equals(2 ^ 53 + 2, result[1])
+
+      assert(cel.set_number_policy("preserve-int"))
+      result = assert(prog:execute(ctx))
+      assert.is_true(result[1] == 9007199254740994LL)
+      assert.is_true(result[2] == 18446744073709551615ULL)
+      assert.equals("number", type(result[3]))
+
+      assert(cel.set_number_policy("string-for-large-ints"))
+      result = assert(prog:execute(ctx))
+      assert.same({ "9007199254740994", "18446744073709551615", 7 }, result)
+
+      local ok, err = cel.set_number_policy("bigint")
+      assert.is_nil(ok)
+      assert.matches("Unknown number policy", err)
+    end)
+
+    it("should prefer the program's and context's number policy to the default", function()
+      local ctx = cel.context.new()
+      ctx:add_variable("max", 18446744073709551615ULL)
+      local other = cel.context.new()
+      other:add_variable("max", 18446744073709551615ULL)
+      local prog = cel.program.new()
+      assert(prog:compile("[max]"))
+
+      assert(ctx:set_number_policy("string-for-large-ints"))
+      assert.same({ "18446744073709551615" }, prog:execute(ctx))
+      assert.equals("18446744073709551615", ctx:get_variable("max"))
+      assert.equals(2 ^ 64, prog:execute(other)[1])
+
+      local outcomes = assert(prog:execute_batch({ ctx, other }))
+      assert.same({ "18446744073709551615" }, outcomes[1].value)
+      assert.equals(2 ^ 64, outcomes[2].value[1])
+
+      assert(prog:set_number_policy("preserve-int"))
+      assert.is_true(prog:execute(ctx)[1] == 18446744073709551615ULL)
+      assert.is_true(prog:execute(other)[1] == 18446744073709551615ULL)
+      assert.equals("force-float", cel.number_policy())
+
+      local ok, err = ctx:set_number_policy("bigint")
+      assert.is_nil(ok)
+      assert.matches("Unknown number policy", err)
+      ok, err = prog:set_number_policy(nil)
+      assert.is_nil(ok)
+      assert.matches("Unknown number policy", err)
+    end)
   end)
 
   describe("Variable Operations", function()
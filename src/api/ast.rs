@@ -0,0 +1,335 @@
+//! The parsed expression as JSON, with macros expanded into comprehensions
+//!
+//! The JSON follows the protobuf JSON mapping of CEL's `google.api.expr.v1alpha1.Expr`,
+//! so tools that read parsed expressions from other CEL implementations can read it.
+//! Every node has a unique `id` and one of `constExpr`, `identExpr`, `selectExpr`,
+//! `callExpr`, `listExpr`, `structExpr` or `comprehensionExpr`. Operators are calls to
+//! their CEL function names, such as `_+_`, `_[_]`, `_?_:_` and `@in`; `has(a.b)` is a
+//! test-only select; and `all`, `exists`, `exists_one`, `map` and `filter` expand into
+//! comprehensions over the accumulator `__result__`, as the CEL specification defines
+//! them. The interpreter evaluates macros directly, so the expansion shows what a macro
+//! means rather than how it runs.
+
+use super::optimize::{arithmetic_symbol, relation_symbol, unparse};
+use super::options::COMPREHENSIONS;
+use super::value::base64_encode;
+use cel_parser::{Atom, Expression, Member, RelationOp, UnaryOp};
+use serde_json::{json, Value as Json};
+
+/// The accumulator of expanded comprehensions
+const RESULT: &str = "__result__";
+
+/// `expression` as `Expr` JSON, with its macros expanded
+pub(crate) fn expanded_ast(expression: &Expression) -> Json {
+    Expander { next_id: 0 }.expand(expression)
+}
+
+struct Expander {
+    next_id: u64,
+}
+
+impl Expander {
+    const fn id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn expand(&mut self, expression: &Expression) -> Json {
+        let id = self.id();
+        match expression {
+            Expression::Arithmetic(left, op, right) => {
+                self.call(id, None, &format!("_{}_", arithmetic_symbol(op)), [&**left, &**right])
+            }
+            Expression::Relation(left, RelationOp::In, right) => {
+                self.call(id, None, "@in", [&**left, &**right])
+            }
+            Expression::Relation(left, op, right) => {
+                self.call(id, None, &format!("_{}_", relation_symbol(op)), [&**left, &**right])
+            }
+            Expression::Ternary(condition, if_true, if_false) => {
+                self.call(id, None, "_?_:_", [&**condition, &**if_true, &**if_false])
+            }
+            Expression::Or(left, right) => self.call(id, None, "_||_", [&**left, &**right]),
+            Expression::And(left, right) => self.call(id, None, "_&&_", [&**left, &**right]),
+            Expression::Unary(op, operand) => {
+                let (function, twice) = match op {
+                    UnaryOp::Not => ("!_", false),
+                    UnaryOp::DoubleNot => ("!_", true),
+                    UnaryOp::Minus => ("-_", false),
+                    UnaryOp::DoubleMinus => ("-_", true),
+                };
+                if !twice {
+                    return self.call(id, None, function, [&**operand]);
+                }
+                let inner_id = self.id();
+                let inner = self.call(inner_id, None, function, [&**operand]);
+                node(id, "callExpr", json!({ "function": function, "args": [inner] }))
+            }
+            Expression::Member(operand, member) => match member.as_ref() {
+                Member::Attribute(field) => self.select(id, operand, field, false),
+                Member::Index(index) => self.call(id, None, "_[_]", [&**operand, &**index]),
+                Member::Fields(fields) => {
+                    let entries = fields
+                        .iter()
+                        .map(|(field, value)| {
+                            let entry_id = self.id();
+                            json!({
+                                "id": entry_id,
+                                "fieldKey": field.as_str(),
+                                "value": self.expand(value),
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    node(
+                        id,
+                        "structExpr",
+                        json!({ "messageName": unparse(operand), "entries": entries }),
+                    )
+                }
+            },
+            Expression::FunctionCall(function, target, args) => {
+                let Expression::Ident(name) = function.as_ref() else {
+                    return self.call(id, target.as_deref(), &unparse(function), args);
+                };
+                if let ("has", None, [Expression::Member(operand, member)]) =
+                    (name.as_str(), target, args.as_slice())
+                {
+                    if let Member::Attribute(field) = member.as_ref() {
+                        return self.select(id, operand, field, true);
+                    }
+                }
+                if let (Some(range), [Expression::Ident(var), body @ ..]) =
+                    (target, args.as_slice())
+                {
+                    let arity = if name.as_str() == "map" { 1..=2 } else { 1..=1 };
+                    if COMPREHENSIONS.contains(&name.as_str()) && arity.contains(&body.len()) {
+                        return self.comprehension(id, name, var, range, body);
+                    }
+                }
+                self.call(id, target.as_deref(), name, args)
+            }
+            Expression::List(items) => {
+                let elements = items.iter().map(|item| self.expand(item)).collect::<Vec<_>>();
+                node(id, "listExpr", json!({ "elements": elements }))
+            }
+            Expression::Map(entries) => {
+                let entries = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        let entry_id = self.id();
+                        json!({
+                            "id": entry_id,
+                            "mapKey": self.expand(key),
+                            "value": self.expand(value),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                node(id, "structExpr", json!({ "entries": entries }))
+            }
+            Expression::Atom(atom) => node(id, "constExpr", constant(atom)),
+            Expression::Ident(name) => node(id, "identExpr", json!({ "name": name.as_str() })),
+        }
+    }
+
+    fn call<'e>(
+        &mut self,
+        id: u64,
+        target: Option<&Expression>,
+        function: &str,
+        args: impl IntoIterator<Item = &'e Expression>,
+    ) -> Json {
+        let mut call = json!({ "function": function });
+        if let Some(target) = target {
+            call["target"] = self.expand(target);
+        }
+        call["args"] = args.into_iter().map(|arg| self.expand(arg)).collect();
+        node(id, "callExpr", call)
+    }
+
+    fn select(&mut self, id: u64, operand: &Expression, field: &str, test_only: bool) -> Json {
+        let mut select = json!({ "operand": self.expand(operand), "field": field });
+        if test_only {
+            select["testOnly"] = json!(true);
+        }
+        node(id, "selectExpr", select)
+    }
+
+    /// The comprehension the macro `name` expands to
+    fn comprehension(
+        &mut self,
+        id: u64,
+        name: &str,
+        var: &str,
+        range: &Expression,
+        body: &[Expression],
+    ) -> Json {
+        let iter_range = self.expand(range);
+        let predicate = self.expand(&body[0]);
+        let (accu_init, loop_condition, loop_step, result) = match (name, body) {
+            ("all" | "exists", _) => {
+                let all = name == "all";
+                let accu_init = self.constant(json!({ "boolValue": all }));
+                let result = self.ident(RESULT);
+                let condition = if all { result } else { self.synthetic("!_", vec![result]) };
+                let loop_condition = self.synthetic("@not_strictly_false", vec![condition]);
+                let step = if all { "_&&_" } else { "_||_" };
+                let result = self.ident(RESULT);
+                let loop_step = self.synthetic(step, vec![result, predicate]);
+                (accu_init, loop_condition, loop_step, self.ident(RESULT))
+            }
+            ("exists_one", _) => {
+                let accu_init = self.constant(json!({ "int64Value": "0" }));
+                let loop_condition = self.constant(json!({ "boolValue": true }));
+                let (result, one) =
+                    (self.ident(RESULT), self.constant(json!({ "int64Value": "1" })));
+                let counted = self.synthetic("_+_", vec![result, one]);
+                let result = self.ident(RESULT);
+                let loop_step = self.synthetic("_?_:_", vec![predicate, counted, result]);
+                let (result, one) =
+                    (self.ident(RESULT), self.constant(json!({ "int64Value": "1" })));
+                (accu_init, loop_condition, loop_step, self.synthetic("_==_", vec![result, one]))
+            }
+            (_, [_, transform]) => {
+                let transform = self.expand(transform);
+                let appended = self.appended(transform);
+                let result = self.ident(RESULT);
+                let loop_step = self.synthetic("_?_:_", vec![predicate, appended, result]);
+                self.collected(loop_step)
+            }
+            ("map", _) => {
+                let loop_step = self.appended(predicate);
+                self.collected(loop_step)
+            }
+            _ => {
+                let element = self.ident(var);
+                let appended = self.appended(element);
+                let result = self.ident(RESULT);
+                let loop_step = self.synthetic("_?_:_", vec![predicate, appended, result]);
+                self.collected(loop_step)
+            }
+        };
+
+        node(
+            id,
+            "comprehensionExpr",
+            json!({
+                "iterVar": var,
+                "iterRange": iter_range,
+                "accuVar": RESULT,
+                "accuInit": accu_init,
+                "loopCondition": loop_condition,
+                "loopStep": loop_step,
+                "result": result,
+            }),
+        )
+    }
+
+    /// `__result__ + [element]`
+    fn appended(&mut self, element: Json) -> Json {
+        let result = self.ident(RESULT);
+        let list_id = self.id();
+        let list = node(list_id, "listExpr", json!({ "elements": Json::Array(vec![element]) }));
+        self.synthetic("_+_", vec![result, list])
+    }
+
+    /// The parts of a comprehension that collects a list with `loop_step`
+    fn collected(&mut self, loop_step: Json) -> (Json, Json, Json, Json) {
+        let list_id = self.id();
+        let accu_init = node(list_id, "listExpr", json!({ "elements": [] }));
+        let loop_condition = self.constant(json!({ "boolValue": true }));
+        (accu_init, loop_condition, loop_step, self.ident(RESULT))
+    }
+
+    fn synthetic(&mut self, function: &str, args: Vec<Json>) -> Json {
+        let id = self.id();
+        node(id, "callExpr", json!({ "function": function, "args": Json::Array(args) }))
+    }
+
+    fn ident(&mut self, name: &str) -> Json {
+        let id = self.id();
+        node(id, "identExpr", json!({ "name": name }))
+    }
+
+    fn constant(&mut self, value: Json) -> Json {
+        let id = self.id();
+        node(id, "constExpr", value)
+    }
+}
+
+fn node(id: u64, kind: &str, body: Json) -> Json {
+    let mut node = serde_json::Map::new();
+    node.insert("id".to_string(), json!(id));
+    node.insert(kind.to_string(), body);
+    Json::Object(node)
+}
+
+/// A literal as a `Constant`, with 64-bit integers as strings like protobuf JSON
+fn constant(atom: &Atom) -> Json {
+    match atom {
+        Atom::Int(i) => json!({ "int64Value": i.to_string() }),
+        Atom::UInt(u) => json!({ "uint64Value": u.to_string() }),
+        Atom::Float(f) => json!({ "doubleValue": f }),
+        Atom::String(s) => json!({ "stringValue": s.as_str() }),
+        Atom::Bytes(bytes) => json!({ "bytesValue": base64_encode(bytes) }),
+        Atom::Bool(b) => json!({ "boolValue": b }),
+        Atom::Null => json!({ "nullValue": "NULL_VALUE" }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expanded(expression: &str) -> Json {
+        expanded_ast(&cel_parser::parse(expression).unwrap())
+    }
+
+    #[test]
+    fn test_operators_are_calls() {
+        assert_eq!(
+            expanded("a.b + 1 in c"),
+            json!({"id": 1, "callExpr": {"function": "@in", "args": [
+                {"id": 2, "callExpr": {"function": "_+_", "args": [
+                    {"id": 3, "selectExpr": {
+                        "operand": {"id": 4, "identExpr": {"name": "a"}},
+                        "field": "b",
+                    }},
+                    {"id": 5, "constExpr": {"int64Value": "1"}},
+                ]}},
+                {"id": 6, "identExpr": {"name": "c"}},
+            ]}})
+        );
+        assert_eq!(
+            expanded("has(a.b)"),
+            json!({"id": 1, "selectExpr": {
+                "operand": {"id": 2, "identExpr": {"name": "a"}},
+                "field": "b",
+                "testOnly": true,
+            }})
+        );
+    }
+
+    #[test]
+    fn test_macros_expand_into_comprehensions() {
+        let all = expanded("xs.all(x, x > 0)");
+        let comprehension = &all["comprehensionExpr"];
+        assert_eq!(comprehension["iterVar"], "x");
+        assert_eq!(comprehension["iterRange"]["identExpr"]["name"], "xs");
+        assert_eq!(comprehension["accuVar"], RESULT);
+        assert_eq!(comprehension["accuInit"]["constExpr"], json!({"boolValue": true}));
+        assert_eq!(comprehension["loopCondition"]["callExpr"]["function"], "@not_strictly_false");
+        assert_eq!(comprehension["loopStep"]["callExpr"]["function"], "_&&_");
+
+        let filter = expanded("xs.filter(x, x > 0)");
+        let step = &filter["comprehensionExpr"]["loopStep"]["callExpr"];
+        assert_eq!(step["function"], "_?_:_");
+        assert_eq!(step["args"][1]["callExpr"]["function"], "_+_");
+
+        let exists_one = expanded("xs.exists_one(x, x > 0)");
+        let result = &exists_one["comprehensionExpr"]["result"]["callExpr"];
+        assert_eq!(result["function"], "_==_");
+
+        // Calls that only share a macro's name are left as calls
+        assert_eq!(expanded("all(xs)")["callExpr"]["function"], "all");
+    }
+}
@@ -0,0 +1,449 @@
+//! Host interception of field selections and indexes
+//!
+//! When a context has an attribute hook the program is rewritten so the operand of
+//! every field selection goes through a function that asks the hook first: `a.b`
+//! becomes `_attribute(a, "a.b", "b").b`, with the path of the selection passed to
+//! the hook. The function passes the operand through if the hook allows the selection,
+//! fails the evaluation if it denies it, and otherwise stands in a map holding only the
+//! replacement value, so the selection that follows evaluates to it.
+//!
+//! Indexes become `_attribute_index(a, k, "a", "a[k]")`, which asks the hook once `k`
+//! is evaluated and then indexes `a` itself, so `k` is evaluated once. A string key
+//! stands for the selection of that field, so `a["b"]` and `a[k]` with `k` holding
+//! `"b"` are both `a.b` to the hook, and cannot get around a decision about `a.b`;
+//! other keys are asked about as `a[1]`. Paths through string literal keys are written
+//! the same way, so `a["b"].c` is `a.b.c`, while selections below a key that is not a
+//! literal keep it as written, as in `a[k].c`.
+//!
+//! Optional selections are asked about like the others, `a.?b` as `a.b`: their operand
+//! goes through `_attribute`, or through `_attribute_key(a, k, "a")` for `a[?k]`, which
+//! stands in a map under the evaluated key for a replacement. That evaluates `k` twice.
+//! The names of called functions are not intercepted.
+
+use super::ext::{OPT_INDEX, OPT_SELECT};
+use super::missing::{map_key, Missing, HAS, INDEX, SELECT};
+use super::optimize::unparse;
+use super::{Context, Value};
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::objects::{Key, Map};
+use cel_interpreter::{Context as CelContext, ExecutionError};
+use cel_parser::{Atom, Expression, Member};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const ATTRIBUTE: &str = "_attribute";
+const ATTRIBUTE_INDEX: &str = "_attribute_index";
+const ATTRIBUTE_KEY: &str = "_attribute_key";
+
+/// What an attribute hook decides about one field selection
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeAccess {
+    /// The field is selected as usual
+    Allow,
+    /// The evaluation fails with the given message
+    Deny(String),
+    /// The selection evaluates to the given value, whether or not the field exists
+    Replace(Value),
+}
+
+/// Add the functions of `with_attribute_hooks` to `ctx`, if `context` has a hook,
+/// indexing under its missing-key policy and recording missing keys in `missing`
+pub(crate) fn register(ctx: &mut CelContext, context: &Context, missing: &Missing) {
+    let Some(hook) = context.attribute_hook() else {
+        return;
+    };
+
+    let indexed = Arc::clone(&hook);
+    let (policy, null_safe, missing) =
+        (context.missing_keys(), context.null_propagation(), missing.clone());
+    ctx.add_function(ATTRIBUTE_INDEX, move |Arguments(args): Arguments| {
+        let [operand, index, Value::String(prefix), Value::String(path)] = args.as_slice() else {
+            return Err(ExecutionError::function_error(ATTRIBUTE_INDEX, "invalid arguments"));
+        };
+        // Not a key anything can be read with, so indexing fails or yields null
+        let Some(key_path) = key_path(prefix, index) else {
+            return missing.index(policy, null_safe, operand, index, path);
+        };
+        match indexed(key_path.as_str()) {
+            AttributeAccess::Allow => missing.index(policy, null_safe, operand, index, path),
+            AttributeAccess::Deny(message) => Err(ExecutionError::function_error("index", message)),
+            AttributeAccess::Replace(value) => Ok(value),
+        }
+    });
+
+    let keyed = Arc::clone(&hook);
+    ctx.add_function(ATTRIBUTE_KEY, move |Arguments(args): Arguments| {
+        let [operand, key, Value::String(prefix)] = args.as_slice() else {
+            return Err(ExecutionError::function_error(ATTRIBUTE_KEY, "invalid arguments"));
+        };
+        let (Some(key_path), Some(key)) = (key_path(prefix, key), map_key(key)) else {
+            return Ok(operand.clone());
+        };
+        match keyed(key_path.as_str()) {
+            AttributeAccess::Allow => Ok(operand.clone()),
+            AttributeAccess::Deny(message) => Err(ExecutionError::function_error("index", message)),
+            AttributeAccess::Replace(value) => Ok(Value::Map(Map {
+                map: Arc::new(HashMap::from([(key, value)])),
+            })),
+        }
+    });
+
+    ctx.add_function(ATTRIBUTE, move |Arguments(args): Arguments| {
+        let [operand, Value::String(path), Value::String(field)] = args.as_slice() else {
+            return Err(ExecutionError::function_error(ATTRIBUTE, "invalid arguments"));
+        };
+        match hook(path.as_str()) {
+            AttributeAccess::Allow => Ok(operand.clone()),
+            AttributeAccess::Deny(message) => {
+                Err(ExecutionError::function_error("select", message))
+            }
+            AttributeAccess::Replace(value) => {
+                let map = HashMap::from([(Key::String(Arc::clone(field)), value)]);
+                Ok(Value::Map(Map { map: Arc::new(map) }))
+            }
+        }
+    });
+}
+
+/// The path the hook is asked about for indexing what `prefix` names with `key`, or
+/// `None` if nothing can be read with such a key
+fn key_path(prefix: &str, key: &Value) -> Option<String> {
+    match key {
+        Value::String(key) => Some(format!("{prefix}.{key}")),
+        Value::Int(i) => Some(format!("{prefix}[{i}]")),
+        Value::UInt(u) => Some(format!("{prefix}[{u}u]")),
+        Value::Bool(b) => Some(format!("{prefix}[{b}]")),
+        _ => None,
+    }
+}
+
+/// `expression` with the operand of every field selection, and every index, including
+/// those already rewritten into lookup functions, passed through the hook functions
+pub(crate) fn with_attribute_hooks(expression: &Expression) -> Expression {
+    let recurse = |expression: &Expression| with_attribute_hooks(expression);
+    let boxed = |expression: &Expression| Box::new(recurse(expression));
+    let text = |text: &str| Expression::Atom(Atom::String(Arc::new(text.to_string())));
+    let hooked = |operand: &Expression, path: &str, field: &str| {
+        Expression::FunctionCall(
+            Expression::Ident(Arc::new(ATTRIBUTE.to_string())).into(),
+            None,
+            vec![recurse(operand), text(path), text(field)],
+        )
+    };
+    let hooked_index = |operand: &Expression, index: &Expression, prefix: &str, path: &str| {
+        Expression::FunctionCall(
+            Expression::Ident(Arc::new(ATTRIBUTE_INDEX.to_string())).into(),
+            None,
+            vec![recurse(operand), recurse(index), text(prefix), text(path)],
+        )
+    };
+    let hooked_key = |operand: &Expression, key: &Expression, prefix: &str| {
+        Expression::FunctionCall(
+            Expression::Ident(Arc::new(ATTRIBUTE_KEY.to_string())).into(),
+            None,
+            vec![recurse(operand), recurse(key), text(prefix)],
+        )
+    };
+
+    match expression {
+        Expression::Member(operand, member) => match member.as_ref() {
+            Member::Attribute(name) => Expression::Member(
+                Box::new(hooked(operand, &hook_path(expression), name)),
+                member.clone(),
+            ),
+            Member::Index(index) => {
+                hooked_index(operand, index, &hook_path(operand), &unparse(expression))
+            }
+            Member::Fields(fields) => Expression::Member(
+                operand.clone(),
+                Member::Fields(
+                    fields.iter().map(|(name, value)| (name.clone(), recurse(value))).collect(),
+                )
+                .into(),
+            ),
+        },
+        Expression::FunctionCall(function, None, args) if is_lookup(function) => {
+            let [operand, field, path] = args.as_slice() else {
+                return expression.clone();
+            };
+            let (Expression::Atom(Atom::String(name)), Expression::Atom(Atom::String(written))) =
+                (field, path)
+            else {
+                return expression.clone();
+            };
+            let args = vec![
+                hooked(operand, &written_path(written), name),
+                field.clone(),
+                path.clone(),
+            ];
+            Expression::FunctionCall(function.clone(), None, args)
+        }
+        Expression::FunctionCall(function, None, args) if is_index(function) => {
+            let [operand, index, Expression::Atom(Atom::String(path))] = args.as_slice() else {
+                return expression.clone();
+            };
+            hooked_index(operand, index, &lookup_path(operand), path)
+        }
+        Expression::FunctionCall(function, Some(target), args) if is_optional(function) => {
+            let [key] = args.as_slice() else {
+                return expression.clone();
+            };
+            let operand = match key {
+                Expression::Atom(Atom::String(field)) => {
+                    hooked(target, &hook_path(expression), field)
+                }
+                key => hooked_key(target, key, &hook_path(target)),
+            };
+            Expression::FunctionCall(function.clone(), Some(Box::new(operand)), vec![recurse(key)])
+        }
+        Expression::FunctionCall(function, target, args) => Expression::FunctionCall(
+            function.clone(),
+            target.as_deref().map(boxed),
+            args.iter().map(recurse).collect(),
+        ),
+        Expression::Arithmetic(left, op, right) => {
+            Expression::Arithmetic(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Relation(left, op, right) => {
+            Expression::Relation(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Ternary(condition, if_true, if_false) => {
+            Expression::Ternary(boxed(condition), boxed(if_true), boxed(if_false))
+        }
+        Expression::Or(left, right) => Expression::Or(boxed(left), boxed(right)),
+        Expression::And(left, right) => Expression::And(boxed(left), boxed(right)),
+        Expression::Unary(op, operand) => Expression::Unary(op.clone(), boxed(operand)),
+        Expression::List(items) => Expression::List(items.iter().map(recurse).collect()),
+        Expression::Map(entries) => Expression::Map(
+            entries.iter().map(|(key, value)| (recurse(key), recurse(value))).collect(),
+        ),
+        Expression::Atom(_) | Expression::Ident(_) => expression.clone(),
+    }
+}
+
+/// Whether `function` is one of the lookup functions standing in for a selection
+fn is_lookup(function: &Expression) -> bool {
+    matches!(function, Expression::Ident(name) if [SELECT, HAS].contains(&name.as_str()))
+}
+
+/// Whether `function` is the lookup function standing in for an index
+fn is_index(function: &Expression) -> bool {
+    matches!(function, Expression::Ident(name) if name.as_str() == INDEX)
+}
+
+/// Whether `function` is one of the optional selections of the optional extension
+fn is_optional(function: &Expression) -> bool {
+    matches!(function, Expression::Ident(name) if [OPT_SELECT, OPT_INDEX].contains(&name.as_str()))
+}
+
+/// The path the hook is asked about for `expression`, with string literal keys and
+/// optional selections written as the selections they stand for
+fn hook_path(expression: &Expression) -> String {
+    let (operand, key) = match expression {
+        Expression::Member(operand, member) => match member.as_ref() {
+            Member::Attribute(name) => return format!("{}.{name}", prefix(operand)),
+            Member::Index(index) => (operand.as_ref(), index.as_ref()),
+            Member::Fields(_) => return unparse(expression),
+        },
+        Expression::FunctionCall(function, Some(target), args) if is_optional(function) => {
+            let [key] = args.as_slice() else {
+                return unparse(expression);
+            };
+            (target.as_ref(), key)
+        }
+        _ => return unparse(expression),
+    };
+    match key {
+        Expression::Atom(Atom::String(key)) => format!("{}.{key}", prefix(operand)),
+        key => format!("{}[{}]", prefix(operand), unparse(key)),
+    }
+}
+
+/// The path of `operand` as the start of a longer one
+fn prefix(operand: &Expression) -> String {
+    match operand {
+        Expression::Arithmetic(..)
+        | Expression::Relation(..)
+        | Expression::Ternary(..)
+        | Expression::Or(..)
+        | Expression::And(..)
+        | Expression::Unary(..) => format!("({})", unparse(operand)),
+        operand => hook_path(operand),
+    }
+}
+
+/// The path the hook is asked about for a selection or index the lookup functions
+/// stand in for, given the text it was written as
+fn written_path(written: &str) -> String {
+    cel_parser::parse(written).map_or_else(|_| written.to_string(), |parsed| hook_path(&parsed))
+}
+
+/// The path the hook is asked about for `operand`, which the lookup functions may
+/// already stand in for
+fn lookup_path(operand: &Expression) -> String {
+    match operand {
+        Expression::FunctionCall(function, None, args)
+            if is_lookup(function) || is_index(function) =>
+        {
+            match args.last() {
+                Some(Expression::Atom(Atom::String(written))) => written_path(written),
+                _ => unparse(operand),
+            }
+        }
+        _ => hook_path(operand),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{CompileOptions, Error, MissingKeys, Program};
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    fn context() -> Context {
+        let mut context = Context::new();
+        context.add_variable(
+            "user".to_string(),
+            json!({"name": "a", "ssn": "123", "tier": "free", "tags": {"x": 1}}),
+        );
+        context
+    }
+
+    #[test]
+    fn test_attribute_hook_rewrite() {
+        let parsed = cel_parser::parse("a.b.c > 1 && has(a.d) && f(a)[0]").unwrap();
+        assert_eq!(
+            unparse(&with_attribute_hooks(&parsed)),
+            unparse(
+                &cel_parser::parse(
+                    "_attribute(_attribute(a, 'a.b', 'b').b, 'a.b.c', 'c').c > 1 \
+                     && has(_attribute(a, 'a.d', 'd').d) \
+                     && _attribute_index(f(a), 0, 'f(a)', 'f(a)[0]')"
+                )
+                .unwrap()
+            )
+        );
+
+        // Indexes the lookup rewrite stood in for are asked about as written
+        let looked_up =
+            crate::api::missing::with_lookups(&cel_parser::parse("a.b[k]").unwrap(), false);
+        assert_eq!(
+            unparse(&with_attribute_hooks(&looked_up)),
+            unparse(
+                &cel_parser::parse(
+                    "_attribute_index(_select(_attribute(a, 'a.b', 'b'), 'b', 'a.b'), k, \
+                     'a.b', 'a.b[k]')"
+                )
+                .unwrap()
+            )
+        );
+
+        let parsed = cel_parser::parse("a['b'].c + a._opt_select('b')._opt_index(k)").unwrap();
+        assert_eq!(
+            unparse(&with_attribute_hooks(&parsed)),
+            unparse(
+                &cel_parser::parse(
+                    "_attribute(_attribute_index(a, 'b', 'a', 'a[\"b\"]'), 'a.b.c', 'c').c \
+                     + _attribute_key(_attribute(a, 'a.b', 'b')._opt_select('b'), k, 'a.b')\
+                     ._opt_index(k)"
+                )
+                .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_attribute_hook_decides_selections() {
+        let read = Arc::new(Mutex::new(Vec::new()));
+        let mut context = context();
+        let log = Arc::clone(&read);
+        context.set_attribute_hook(move |path| {
+            log.lock().unwrap().push(path.to_string());
+            match path {
+                "user.ssn" => AttributeAccess::Deny("ssn is restricted".to_string()),
+                "user.tier" => AttributeAccess::Replace(Value::String("gold".to_string().into())),
+                _ => AttributeAccess::Allow,
+            }
+        });
+        let execute = |expression: &str| Program::compile(expression).unwrap().execute(&context);
+
+        assert_eq!(
+            execute("user.name == 'a' && user.tier == 'gold' && user.tags.x == 1"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(*read.lock().unwrap(), ["user.name", "user.tier", "user.tags", "user.tags.x"]);
+        assert!(matches!(
+            execute("user.ssn != ''"),
+            Err(Error::Execution(message)) if message.contains("ssn is restricted")
+        ));
+        assert!(execute("has(user.ssn)").is_err());
+
+        context.set_missing_keys(MissingKeys::Null);
+        let program = Program::compile("user.age == null && user.tier == 'gold'").unwrap();
+        assert_eq!(program.execute(&context), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn test_attribute_hook_decides_indexes() {
+        let read = Arc::new(Mutex::new(Vec::new()));
+        let mut context = context();
+        context.add_variable("field".to_string(), json!("ssn"));
+        context.add_variable("xs".to_string(), json!([10, 20]));
+        let log = Arc::clone(&read);
+        context.set_attribute_hook(move |path| {
+            log.lock().unwrap().push(path.to_string());
+            match path {
+                "user.ssn" | "xs[1]" => AttributeAccess::Deny("restricted".to_string()),
+                "user.tier" => AttributeAccess::Replace(Value::String("gold".to_string().into())),
+                _ => AttributeAccess::Allow,
+            }
+        });
+
+        for policy in [MissingKeys::Error, MissingKeys::Null] {
+            context.set_missing_keys(policy);
+            let execute =
+                |expression: &str| Program::compile(expression).unwrap().execute(&context);
+            for expression in [
+                "user['ssn'] == '123'",
+                "user[field] == '123'",
+                "xs[1] == 20",
+            ] {
+                assert!(
+                    matches!(
+                        execute(expression),
+                        Err(Error::Execution(message)) if message.contains("restricted")
+                    ),
+                    "{expression} under {policy:?}"
+                );
+            }
+            assert_eq!(
+                execute("user['tier'] == 'gold' && user['tags'].x == 1 && xs[0] == 10"),
+                Ok(Value::Bool(true))
+            );
+        }
+        let first = [
+            "user.ssn",
+            "user.ssn",
+            "xs[1]",
+            "user.tier",
+            "user.tags",
+            "user.tags.x",
+        ];
+        assert_eq!(read.lock().unwrap()[..first.len()], first);
+
+        let options = CompileOptions {
+            optional_ext: true,
+            ..CompileOptions::default()
+        };
+        let execute = |expression: &str| {
+            Program::compile_with_options(expression, &options).unwrap().execute(&context)
+        };
+        assert!(execute("user.?ssn.hasValue()").is_err());
+        assert!(execute("user[?field].hasValue()").is_err());
+        assert_eq!(
+            execute("user.?tier.value() == 'gold' && xs[?0].value() == 10"),
+            Ok(Value::Bool(true))
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! Audit records of executions, kept by contexts that opt in
+//!
+//! Each record identifies the program by its fingerprint and names the variables the
+//! context bound, along with the result or error and how long the execution took, so
+//! a decision can be reconstructed later from the program and the logged inputs. A
+//! context keeps a fixed number of the most recent records, dropping the oldest.
+
+use super::trace::timed;
+use super::{Context, Error, OwnedValue, Value};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Mutex, PoisonError};
+
+/// One execution recorded in a context's audit log
+///
+/// Serializes to JSON with `result` and `error` left out when absent.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditRecord {
+    /// `Program::fingerprint_hex` of the executed program
+    pub expression_hash: String,
+    /// The variables bound in the context, sorted; resolved variables are left out
+    pub variables: Vec<String>,
+    /// The result, absent if execution failed or the value has no JSON form
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<OwnedValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Time the execution took; 0 on targets without a clock
+    pub duration_ns: u64,
+}
+
+/// The most recent audit records of a context, up to its capacity
+///
+/// Records are added from shared references, since programs execute through `&Context`.
+#[derive(Debug, Default)]
+pub(crate) struct AuditLog {
+    capacity: usize,
+    records: Mutex<VecDeque<AuditRecord>>,
+}
+
+impl AuditLog {
+    /// A log keeping the last `capacity` records, where 0 keeps none
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Mutex::default(),
+        }
+    }
+
+    /// Remove the records kept, oldest first
+    pub(crate) fn take(&self) -> Vec<AuditRecord> {
+        std::mem::take(&mut *self.records.lock().unwrap_or_else(PoisonError::into_inner)).into()
+    }
+
+    fn push(&self, record: AuditRecord) {
+        let mut records = self.records.lock().unwrap_or_else(PoisonError::into_inner);
+        if records.len() >= self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+}
+
+/// Run `execute`, recording it in the audit log of `context` if it keeps one
+pub(crate) fn audited(
+    context: &Context,
+    fingerprint: u64,
+    execute: impl FnOnce() -> Result<Value, Error>,
+) -> Result<Value, Error> {
+    let log = context.audit_log();
+    if log.capacity == 0 {
+        return execute();
+    }
+
+    let (result, duration_ns) = timed(execute);
+    let mut variables: Vec<String> = context.variable_names().map(str::to_string).collect();
+    variables.sort_unstable();
+    let (value, error) = match &result {
+        Ok(value) => (OwnedValue::try_from(value).ok(), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    log.push(AuditRecord {
+        expression_hash: format!("{fingerprint:016x}"),
+        variables,
+        result: value,
+        error,
+        duration_ns,
+    });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Program;
+    use serde_json::json;
+
+    #[test]
+    fn test_audit_log_keeps_recent_executions() {
+        let program = Program::compile("10 / n").unwrap();
+        let mut context = Context::new();
+        context.add_variable("n".to_string(), json!(2));
+        context.add_variable("actor".to_string(), json!("alice"));
+        program.execute(&context).unwrap();
+        assert!(context.take_audit_records().is_empty());
+
+        context.set_audit_log(2);
+        program.execute(&context).unwrap();
+        context.add_variable("n".to_string(), json!(0));
+        program.execute(&context).unwrap_err();
+        let records = context.take_audit_records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].expression_hash, program.fingerprint_hex());
+        assert_eq!(records[0].variables, ["actor", "n"]);
+        assert_eq!(records[0].result, Some(OwnedValue::Int(5)));
+        assert_eq!(records[1].result, None);
+        assert!(records[1].error.is_some());
+        assert!(context.take_audit_records().is_empty());
+
+        for _ in 0..3 {
+            program.execute(&context).unwrap_err();
+        }
+        assert_eq!(context.take_audit_records().len(), 2);
+
+        context.set_audit_log(0);
+        program.execute(&context).unwrap_err();
+        assert!(context.take_audit_records().is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+//! Evaluating one program against many contexts
+
+use super::{Context, Error, Program, Value};
+#[cfg(feature = "parallel")]
+use std::collections::BTreeMap;
+#[cfg(feature = "parallel")]
+use std::sync::{Arc, Mutex, PoisonError};
+
+impl Program {
+    /// Evaluate the expression against each context in turn, with one outcome per
+    /// context, so one failing context does not hide the others
+    #[must_use]
+    pub fn execute_batch(&self, contexts: &[&Context]) -> Vec<Result<Value, Error>> {
+        contexts.iter().map(|context| self.execute(context)).collect()
+    }
+
+    /// Evaluate like `execute_batch`, sharding the contexts across `threads` threads,
+    /// or one per CPU for 0
+    ///
+    /// Pools are started on first use and kept for later batches with the same
+    /// thread count. Resolvers set on the contexts are called from the pool's threads.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Execution` if the threads cannot be started.
+    #[cfg(feature = "parallel")]
+    pub fn execute_batch_parallel(
+        &self,
+        contexts: &[&Context],
+        threads: usize,
+    ) -> Result<Vec<Result<Value, Error>>, Error> {
+        use rayon::prelude::*;
+
+        let execute = || contexts.par_iter().map(|context| self.execute(context)).collect();
+        if threads == 0 {
+            return Ok(execute());
+        }
+        Ok(pool(threads)?.install(execute))
+    }
+}
+
+/// The pool with `threads` threads, started on first use
+#[cfg(feature = "parallel")]
+fn pool(threads: usize) -> Result<Arc<rayon::ThreadPool>, Error> {
+    static POOLS: Mutex<BTreeMap<usize, Arc<rayon::ThreadPool>>> = Mutex::new(BTreeMap::new());
+
+    let mut pools = POOLS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(pool) = pools.get(&threads) {
+        return Ok(Arc::clone(pool));
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("cel-batch-{i}"))
+        .build()
+        .map_err(|e| Error::Execution(format!("cannot start {threads} threads: {e}")))?;
+    Ok(Arc::clone(pools.entry(threads).or_insert_with(|| Arc::new(pool))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contexts() -> Vec<Context> {
+        (0..20)
+            .map(|n| {
+                let mut context = Context::new();
+                if n != 7 {
+                    context.add_variable("n".to_string(), serde_json::json!(n));
+                }
+                context
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_execute_batch() {
+        let program = Program::compile("n * 2").unwrap();
+        let contexts = contexts();
+        let contexts: Vec<&Context> = contexts.iter().collect();
+        let outcomes = program.execute_batch(&contexts);
+        assert_eq!(outcomes.len(), 20);
+        assert_eq!(outcomes[3], Ok(Value::Int(6)));
+        assert!(matches!(outcomes[7], Err(Error::Execution(_))));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_execute_batch_parallel() {
+        let program = Program::compile("n * 2").unwrap();
+        let contexts = contexts();
+        let contexts: Vec<&Context> = contexts.iter().collect();
+        let sequential = program.execute_batch(&contexts);
+        for threads in [0, 1, 4, 4] {
+            assert_eq!(program.execute_batch_parallel(&contexts, threads).unwrap(), sequential);
+        }
+    }
+}
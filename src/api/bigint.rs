@@ -0,0 +1,99 @@
+//! Big integers: 128-bit signed integers for counters beyond `int` and `uint`
+//!
+//! Expressions build big integers with `bigint()`, and hosts pass them as
+//! `CelValueType::BigInt`. The interpreter's operators only know its own numeric
+//! types, so arithmetic and comparisons on big integers go through the functions of
+//! `numeric` and need `Context::set_numeric_promotion`. Integer arithmetic on big
+//! integers fails on overflow instead of wrapping or rounding to a double.
+
+use super::value::{bigint_parts, bigint_value, parse_bigint, value_type_of};
+use super::Value;
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::{Context as CelContext, ExecutionError};
+
+const BIGINT: &str = "bigint";
+
+/// Add `bigint()`, converting an int, uint, decimal string or big integer, to `ctx`
+pub(crate) fn register(ctx: &mut CelContext) {
+    ctx.add_function(BIGINT, |Arguments(args): Arguments| match args.as_slice() {
+        [Value::Int(i)] => Ok(bigint_value(i128::from(*i))),
+        [Value::UInt(u)] => Ok(bigint_value(i128::from(*u))),
+        [Value::String(text)] => {
+            parse_bigint(text).map_err(|e| ExecutionError::function_error(BIGINT, e))
+        }
+        [value] if bigint_parts(value).is_some() => Ok(value.clone()),
+        [value] => Err(ExecutionError::function_error(
+            BIGINT,
+            format!("cannot convert {:?} to bigint", value_type_of(value)),
+        )),
+        _ => Err(ExecutionError::function_error(BIGINT, "expected one argument")),
+    });
+}
+
+/// The integer an int, uint or big integer holds, or `None` for any other value
+pub(crate) fn as_i128(value: &Value) -> Option<i128> {
+    match value {
+        Value::Int(i) => Some(i128::from(*i)),
+        Value::UInt(u) => Some(i128::from(*u)),
+        value => bigint_parts(value),
+    }
+}
+
+/// `left op right` as a big integer, for the arithmetic operator written `op`
+pub(crate) fn arithmetic(op: &str, left: i128, right: i128) -> Result<Value, ExecutionError> {
+    let result = match op {
+        "+" => left.checked_add(right),
+        "-" => left.checked_sub(right),
+        "*" => left.checked_mul(right),
+        "/" => left.checked_div(right),
+        "%" => left.checked_rem(right),
+        op => return Err(ExecutionError::function_error(BIGINT, format!("unknown operator {op}"))),
+    };
+    result.map(bigint_value).ok_or_else(|| {
+        let reason = if right == 0 && matches!(op, "/" | "%") {
+            "division by zero"
+        } else {
+            "overflow"
+        };
+        ExecutionError::function_error(BIGINT, format!("{reason} in {left} {op} {right}"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::value::bigint_value;
+    use crate::api::{Context, Error, Program, Value};
+    use serde_json::json;
+
+    fn execute(expression: &str) -> Result<Value, Error> {
+        let mut context = Context::new();
+        context.add_variable("count".to_string(), json!(u64::MAX));
+        context.set_numeric_promotion(true);
+        Program::compile(expression).unwrap().execute(&context)
+    }
+
+    #[test]
+    fn test_bigint_arithmetic() {
+        let expected = bigint_value(i128::from(u64::MAX) * 4 + 4);
+        assert_eq!(execute("bigint(count) * 4u + 4"), Ok(expected));
+        assert_eq!(execute("bigint('-7') / 2"), Ok(bigint_value(-3)));
+        assert_eq!(execute("bigint(3) + 0.5"), Ok(Value::Float(3.5)));
+        assert!(matches!(
+            execute("bigint('170141183460469231731687303715884105727') + 1"),
+            Err(Error::Execution(_))
+        ));
+        assert!(matches!(execute("bigint(1) % 0"), Err(Error::Execution(_))));
+        assert!(matches!(execute("bigint(1.5)"), Err(Error::Execution(_))));
+    }
+
+    #[test]
+    fn test_bigint_comparisons() {
+        assert_eq!(execute("bigint(count) + 1 > count"), Ok(Value::Bool(true)));
+        assert_eq!(
+            execute("bigint(count) == count && count == bigint(count)"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(execute("bigint('-1') < 0u && bigint(2) >= 1.5"), Ok(Value::Bool(true)));
+        assert_eq!(execute("bigint(1) != 'a'"), Ok(Value::Bool(true)));
+    }
+}
@@ -0,0 +1,428 @@
+//! Rule bundles: named expressions loaded and compiled from a manifest
+//!
+//! A bundle is the unit a policy engine deploys. It is read from one of:
+//!
+//! - a JSON object mapping rule names to expressions, or a JSON array of
+//!   `{"name": ..., "expression": ...}` objects, which keeps the rules in order
+//! - an archive: text in which each rule starts with a `=== name` line, followed by
+//!   the lines of its expression
+//! - a directory of `.cel` files, each a rule named after the file without its
+//!   extension, in file name order
+//!
+//! Every rule is compiled on load. Rules that fail to compile are left out, and their
+//! errors kept, so one broken rule does not take down the rest of the bundle.
+//!
+//! A bundle can be reloaded while it is in use. The new rules are compiled without
+//! holding up evaluations, then swapped in at once; evaluations already running keep
+//! the version they started with, which is freed when the last of them finishes.
+
+use super::{CompileOptions, Context, Error, Program, RuleSet, Value};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// Line starting each rule of an archive, followed by the rule's name
+const ARCHIVE_HEADER: &str = "===";
+
+/// A compiled set of named rules loaded from a manifest, which can be reloaded
+#[derive(Debug)]
+pub struct Bundle {
+    /// Shared with background reloads, which may outlive the bundle
+    current: Arc<RwLock<Arc<BundleVersion>>>,
+}
+
+/// One loaded version of a bundle's rules
+#[derive(Debug)]
+pub struct BundleVersion {
+    number: u64,
+    rules: RuleSet,
+    errors: Vec<(Arc<str>, Error)>,
+}
+
+/// Where a bundle's manifest is read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BundleSource {
+    /// A manifest file or a directory of `.cel` files
+    Path(PathBuf),
+    /// A JSON manifest or an archive
+    Bytes(Vec<u8>),
+}
+
+impl BundleSource {
+    /// The named expressions of the manifest, in manifest order
+    fn rules(&self) -> Result<Vec<(String, String)>, Error> {
+        match self {
+            Self::Path(path) => path_rules(path),
+            Self::Bytes(bytes) => bytes_rules(bytes),
+        }
+    }
+}
+
+impl Bundle {
+    /// Load a manifest file or a directory of `.cel` files, compiled with `options`
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if the path cannot be read, the manifest is
+    /// malformed or two rules share a name. Rules that fail to compile are not errors
+    /// here; see `BundleVersion::errors`.
+    pub fn load(path: &Path, options: CompileOptions) -> Result<Self, Error> {
+        Self::compile(path_rules(path)?, options)
+    }
+
+    /// Load a JSON manifest or an archive held in memory, compiled with `options`
+    ///
+    /// Text starting with `{` or `[` is read as JSON, anything else as an archive.
+    ///
+    /// # Errors
+    ///
+    /// Fails as `load` does for a manifest file.
+    pub fn from_bytes(bytes: &[u8], options: CompileOptions) -> Result<Self, Error> {
+        Self::compile(bytes_rules(bytes)?, options)
+    }
+
+    fn compile(rules: Vec<(String, String)>, options: CompileOptions) -> Result<Self, Error> {
+        let version = BundleVersion::compile(1, rules, options)?;
+        Ok(Self {
+            current: Arc::new(RwLock::new(Arc::new(version))),
+        })
+    }
+
+    /// The rules in use, which stay valid however long they are held, even after a
+    /// reload replaces them
+    #[must_use]
+    pub fn current(&self) -> Arc<BundleVersion> {
+        Arc::clone(&self.current.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Evaluate the current version's rule named `name` against `context`
+    ///
+    /// # Errors
+    ///
+    /// Fails as `BundleVersion::execute` does.
+    pub fn execute(&self, name: &str, context: &Context) -> Result<Value, Error> {
+        self.current().execute(name, context)
+    }
+
+    /// Compile the rules of `source` with `options` and swap them in, returning the
+    /// new version's number
+    ///
+    /// Evaluations keep using the previous rules while the new ones compile. Unlike a
+    /// first load, a reload is all or nothing: if any rule fails to compile, the
+    /// bundle keeps its previous rules, so a bad update cannot silently drop a rule.
+    ///
+    /// # Errors
+    ///
+    /// Fails as `load` does, and with `Error::InvalidArgument` describing each rule
+    /// that failed to compile on its own line as `rule 'name': error`.
+    pub fn reload(&self, source: &BundleSource, options: CompileOptions) -> Result<u64, Error> {
+        swap(&self.current, source, options)
+    }
+
+    /// Reload like `reload` on a new thread, whose result is the new version's number
+    ///
+    /// The thread holds its own reference to the bundle's rules, so the bundle may be
+    /// dropped before it finishes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the thread cannot be started.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn reload_in_background(
+        &self,
+        source: BundleSource,
+        options: CompileOptions,
+    ) -> std::io::Result<std::thread::JoinHandle<Result<u64, Error>>> {
+        let current = Arc::clone(&self.current);
+        std::thread::Builder::new()
+            .name("cel-bundle-reload".to_string())
+            .spawn(move || swap(&current, &source, options))
+    }
+}
+
+/// Compile the rules of `source` and make them the current version if all compile
+fn swap(
+    current: &RwLock<Arc<BundleVersion>>,
+    source: &BundleSource,
+    options: CompileOptions,
+) -> Result<u64, Error> {
+    let version = BundleVersion::compile(0, source.rules()?, options)?;
+    if !version.errors.is_empty() {
+        let error = version
+            .errors
+            .iter()
+            .map(|(name, e)| format!("rule '{name}': {e}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        return Err(Error::InvalidArgument(error));
+    }
+
+    // Numbered under the lock, so concurrent reloads install in the order numbered
+    let mut current = current.write().unwrap_or_else(PoisonError::into_inner);
+    let number = current.number + 1;
+    *current = Arc::new(BundleVersion { number, ..version });
+    Ok(number)
+}
+
+impl BundleVersion {
+    fn compile(
+        number: u64,
+        rules: Vec<(String, String)>,
+        options: CompileOptions,
+    ) -> Result<Self, Error> {
+        let mut set = RuleSet::new(options);
+        for (name, expression) in rules {
+            set.add(&name, expression)?;
+        }
+        let errors = set.compile().err().unwrap_or_default();
+        for (name, _) in &errors {
+            set.remove(name);
+        }
+        Ok(Self {
+            number,
+            rules: set,
+            errors,
+        })
+    }
+
+    /// 1 for the rules first loaded, counting up with each reload
+    #[must_use]
+    pub const fn number(&self) -> u64 {
+        self.number
+    }
+
+    /// The rules that compiled, in manifest order
+    #[must_use]
+    pub const fn rules(&self) -> &RuleSet {
+        &self.rules
+    }
+
+    /// The name and compile error of each rule left out, in manifest order
+    #[must_use]
+    pub fn errors(&self) -> &[(Arc<str>, Error)] {
+        &self.errors
+    }
+
+    /// The program of the rule named `name`, or `None` if there is no such rule or it
+    /// failed to compile
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&Program> {
+        self.rules.get(name)
+    }
+
+    /// Evaluate the rule named `name` against `context`
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if there is no such compiled rule, or fails as
+    /// `Program::execute` does.
+    pub fn execute(&self, name: &str, context: &Context) -> Result<Value, Error> {
+        let Some(program) = self.get(name) else {
+            return Err(match self.errors.iter().find(|(failed, _)| &**failed == name) {
+                Some(_) => Error::InvalidArgument(format!("rule '{name}' failed to compile")),
+                None => Error::InvalidArgument(format!("no rule named '{name}'")),
+            });
+        };
+        program.execute(context)
+    }
+}
+
+/// Rules of a manifest file, or of the `.cel` files of a directory
+fn path_rules(path: &Path) -> Result<Vec<(String, String)>, Error> {
+    let unreadable =
+        |e: std::io::Error| Error::InvalidArgument(format!("cannot read {}: {e}", path.display()));
+    if !path.is_dir() {
+        return bytes_rules(&std::fs::read(path).map_err(unreadable)?);
+    }
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(path).map_err(unreadable)? {
+        let file = entry.map_err(unreadable)?.path();
+        if file.is_file() && file.extension().is_some_and(|extension| extension == "cel") {
+            files.push(file);
+        }
+    }
+    files.sort();
+
+    let mut rules = Vec::with_capacity(files.len());
+    for file in files {
+        let name = file.file_stem().and_then(|stem| stem.to_str()).ok_or_else(|| {
+            Error::InvalidArgument(format!("rule file name {} is not UTF-8", file.display()))
+        })?;
+        let expression = std::fs::read_to_string(&file)
+            .map_err(|e| Error::InvalidArgument(format!("cannot read {}: {e}", file.display())))?;
+        rules.push((name.to_string(), expression));
+    }
+    Ok(rules)
+}
+
+/// Rules of a JSON manifest or an archive
+fn bytes_rules(bytes: &[u8]) -> Result<Vec<(String, String)>, Error> {
+    let text = std::str::from_utf8(bytes)
+        .map_err(|e| Error::InvalidArgument(format!("bundle is not UTF-8: {e}")))?;
+    match text.trim_start().chars().next() {
+        Some('{' | '[') => json_rules(text),
+        _ => archive_rules(text),
+    }
+}
+
+/// Rules of a JSON manifest, as an object of expressions or an array of rules
+fn json_rules(text: &str) -> Result<Vec<(String, String)>, Error> {
+    let invalid = |message: String| Error::InvalidArgument(format!("invalid bundle: {message}"));
+    let manifest: serde_json::Value =
+        serde_json::from_str(text).map_err(|e| invalid(e.to_string()))?;
+    match manifest {
+        serde_json::Value::Object(rules) => rules
+            .into_iter()
+            .map(|(name, expression)| match expression {
+                serde_json::Value::String(expression) => Ok((name, expression)),
+                _ => Err(invalid(format!("rule '{name}' is not a string"))),
+            })
+            .collect(),
+        serde_json::Value::Array(rules) => rules
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| {
+                let field = |key: &str| rule.get(key).and_then(serde_json::Value::as_str);
+                match (field("name"), field("expression")) {
+                    (Some(name), Some(expression)) => {
+                        Ok((name.to_string(), expression.to_string()))
+                    }
+                    _ => Err(invalid(format!("rule {index} needs a name and an expression"))),
+                }
+            })
+            .collect(),
+        _ => Err(invalid("expected an object or an array".to_string())),
+    }
+}
+
+/// Rules of an archive, each a `=== name` line and the expression lines after it
+fn archive_rules(text: &str) -> Result<Vec<(String, String)>, Error> {
+    let mut rules: Vec<(String, String)> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if let Some(name) = line.strip_prefix(ARCHIVE_HEADER) {
+            let name = name.trim();
+            if name.is_empty() {
+                return Err(Error::InvalidArgument(format!(
+                    "invalid bundle: line {} names no rule",
+                    number + 1
+                )));
+            }
+            rules.push((name.to_string(), String::new()));
+        } else if let Some((_, expression)) = rules.last_mut() {
+            expression.push_str(line);
+            expression.push('\n');
+        } else if !line.trim().is_empty() {
+            return Err(Error::InvalidArgument(format!(
+                "invalid bundle: line {} is outside a rule",
+                number + 1
+            )));
+        }
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARCHIVE: &str = "\
+=== adult
+user.age >= 18
+=== admin
+'admin' in
+  user.roles
+=== broken
+user.age >=
+";
+
+    fn user() -> Context {
+        let mut context = Context::new();
+        context.add_variable("user".to_string(), serde_json::json!({"age": 30, "roles": []}));
+        context
+    }
+
+    #[test]
+    fn test_bundle_archive() {
+        let bundle = Bundle::from_bytes(ARCHIVE.as_bytes(), CompileOptions::default()).unwrap();
+        assert_eq!(bundle.current().rules().names().collect::<Vec<_>>(), ["adult", "admin"]);
+        assert_eq!(bundle.current().errors().len(), 1);
+        assert_eq!(&*bundle.current().errors()[0].0, "broken");
+        assert!(matches!(bundle.current().errors()[0].1, Error::Compile(_)));
+
+        assert_eq!(bundle.execute("adult", &user()), Ok(Value::Bool(true)));
+        assert_eq!(bundle.execute("admin", &user()), Ok(Value::Bool(false)));
+        let error = bundle.execute("broken", &user()).unwrap_err().to_string();
+        assert!(error.contains("failed to compile"), "{error}");
+        let error = bundle.execute("missing", &user()).unwrap_err().to_string();
+        assert!(error.contains("no rule named"), "{error}");
+        assert!(bundle.current().rules().execute_all(&user()).is_ok());
+
+        for invalid in ["1 + 1\n=== late\n2", "===\n1", "=== twice\n1\n=== twice\n2"] {
+            let result = Bundle::from_bytes(invalid.as_bytes(), CompileOptions::default());
+            assert!(matches!(result, Err(Error::InvalidArgument(_))), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_bundle_json() {
+        let options = CompileOptions::default();
+        let bundle = Bundle::from_bytes(br#"{"b": "2", "a": "1"}"#, options.clone()).unwrap();
+        assert_eq!(bundle.current().rules().names().collect::<Vec<_>>(), ["a", "b"]);
+
+        let ordered = br#"[{"name": "b", "expression": "2"}, {"name": "a", "expression": "1"}]"#;
+        let bundle = Bundle::from_bytes(ordered, options.clone()).unwrap();
+        assert_eq!(bundle.current().rules().names().collect::<Vec<_>>(), ["b", "a"]);
+        assert_eq!(bundle.execute("a", &Context::new()), Ok(Value::Int(1)));
+
+        for invalid in [&br#"{"a": 1}"#[..], br#"[{"name": "a"}]"#, b"[1", b"\xff"] {
+            let result = Bundle::from_bytes(invalid, options.clone());
+            assert!(matches!(result, Err(Error::InvalidArgument(_))));
+        }
+    }
+
+    #[test]
+    fn test_bundle_directory() {
+        let dir = std::env::temp_dir().join(format!("cel-bundle-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("b_adult.cel"), "user.age >= 18").unwrap();
+        std::fs::write(dir.join("a_old.cel"), "user.age > 65").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a rule").unwrap();
+        std::fs::write(dir.join("bundle.json"), r#"{"one": "1"}"#).unwrap();
+
+        let bundle = Bundle::load(&dir, CompileOptions::default()).unwrap();
+        assert_eq!(bundle.current().rules().names().collect::<Vec<_>>(), ["a_old", "b_adult"]);
+        assert_eq!(bundle.execute("a_old", &user()), Ok(Value::Bool(false)));
+
+        let manifest = Bundle::load(&dir.join("bundle.json"), CompileOptions::default()).unwrap();
+        assert_eq!(manifest.execute("one", &user()), Ok(Value::Int(1)));
+        let missing = Bundle::load(&dir.join("missing.json"), CompileOptions::default());
+        assert!(matches!(missing, Err(Error::InvalidArgument(_))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bundle_reload() {
+        let bundle = Bundle::from_bytes(b"=== limit\n10", CompileOptions::default()).unwrap();
+        let before = bundle.current();
+        assert_eq!(before.number(), 1);
+
+        let update = BundleSource::Bytes(b"=== limit\n20\n=== extra\ntrue".to_vec());
+        assert_eq!(bundle.reload(&update, CompileOptions::default()), Ok(2));
+        assert_eq!(bundle.execute("limit", &Context::new()), Ok(Value::Int(20)));
+        assert_eq!(before.execute("limit", &Context::new()), Ok(Value::Int(10)));
+        assert!(before.get("extra").is_none());
+
+        let broken = BundleSource::Bytes(b"=== limit\n30\n=== extra\n1 +".to_vec());
+        let error = bundle.reload(&broken, CompileOptions::default()).unwrap_err();
+        assert!(error.to_string().starts_with("rule 'extra': "), "{error}");
+        let missing = BundleSource::Path(PathBuf::from("/nonexistent/bundle.json"));
+        assert!(bundle.reload(&missing, CompileOptions::default()).is_err());
+        assert_eq!(bundle.current().number(), 2);
+
+        let update = BundleSource::Bytes(b"=== limit\n40".to_vec());
+        let reload = bundle.reload_in_background(update, CompileOptions::default()).unwrap();
+        drop(bundle);
+        assert_eq!(reload.join().unwrap(), Ok(3));
+    }
+}
@@ -0,0 +1,135 @@
+//! Cancelling evaluations from another thread
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// A flag `Program::execute_cancellable` checks while it evaluates
+///
+/// Clones share the flag, so one can be handed to the evaluating thread and another
+/// kept to cancel it. Once cancelled, a token stays cancelled. A token can also
+/// cancel itself at a deadline, and bound the cost of the evaluations using it.
+#[derive(Debug, Clone)]
+pub struct CancelToken {
+    state: Arc<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    cancelled: AtomicBool,
+    /// When the first deadline was set; left unread until then, so tokens work on
+    /// targets without a clock
+    created: OnceLock<Instant>,
+    /// Nanoseconds after `created` at which the token counts as cancelled
+    deadline: AtomicU64,
+    /// Checks left before evaluations fail, or `u64::MAX` for no limit
+    budget: AtomicU64,
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(State {
+                cancelled: AtomicBool::new(false),
+                created: OnceLock::new(),
+                deadline: AtomicU64::new(u64::MAX),
+                budget: AtomicU64::new(u64::MAX),
+            }),
+        }
+    }
+}
+
+impl CancelToken {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make every evaluation checking this token fail with `Error::Cancelled`
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Cancel the token once `timeout` has passed, replacing any earlier deadline
+    ///
+    /// The deadline is noticed at the same checks as `cancel`, so an evaluation
+    /// without comprehensions runs to completion once started.
+    ///
+    /// # Panics
+    ///
+    /// Panics on targets without a clock, such as `wasm32-unknown-unknown`.
+    pub fn cancel_after(&self, timeout: Duration) {
+        let created = self.state.created.get_or_init(Instant::now);
+        let deadline = created.elapsed().saturating_add(timeout);
+        let nanos = u64::try_from(deadline.as_nanos()).unwrap_or(u64::MAX);
+        self.state.deadline.store(nanos, Ordering::Relaxed);
+    }
+
+    /// Fail evaluations using this token once they have made `cost` checks in total
+    ///
+    /// Each evaluation of a comprehension's predicate or transform costs one, so this
+    /// bounds the work of an expression over large lists. The budget is shared by all
+    /// evaluations using the token and is not refilled.
+    pub fn set_budget(&self, cost: u64) {
+        self.state.budget.store(cost, Ordering::Relaxed);
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        if self.state.cancelled.load(Ordering::Relaxed) {
+            return true;
+        }
+        let deadline = self.state.deadline.load(Ordering::Relaxed);
+        deadline != u64::MAX
+            && self.state.created.get().is_some_and(|created| {
+                u64::try_from(created.elapsed().as_nanos()).unwrap_or(u64::MAX) >= deadline
+            })
+    }
+
+    /// Whether the budget set with `set_budget` has been spent
+    #[must_use]
+    pub fn is_exhausted(&self) -> bool {
+        self.state.budget.load(Ordering::Relaxed) == 0
+    }
+
+    /// Spend one unit of the budget, returning false if none was left
+    pub(crate) fn spend(&self) -> bool {
+        self.state
+            .budget
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| match left {
+                0 => None,
+                u64::MAX => Some(u64::MAX),
+                left => Some(left - 1),
+            })
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_after() {
+        let token = CancelToken::new();
+        token.cancel_after(Duration::from_secs(3600));
+        assert!(!token.is_cancelled());
+        token.cancel_after(Duration::ZERO);
+        assert!(token.is_cancelled());
+        assert!(token.clone().is_cancelled());
+    }
+
+    #[test]
+    fn test_budget() {
+        let token = CancelToken::new();
+        assert!(token.spend());
+        assert!(!token.is_exhausted());
+
+        token.set_budget(2);
+        assert!(token.spend());
+        assert!(token.spend());
+        assert!(token.is_exhausted());
+        assert!(!token.spend());
+        assert!(!token.is_cancelled());
+    }
+}
@@ -0,0 +1,126 @@
+//! What this build of the library supports, for bindings that run against several
+//! releases of it
+
+use super::value::{MAX_DURATION_SECONDS, MAX_TIMESTAMP_SECONDS, MIN_TIMESTAMP_SECONDS};
+use super::{BUILTIN_FUNCTIONS, DEFAULT_MAX_DEPTH, MACROS};
+use crate::ffi::CEL_ABI_VERSION;
+use serde::Serialize;
+
+/// `CelValueType` names, in discriminant order
+const VALUE_TYPES: [&str; 15] = [
+    "null",
+    "bool",
+    "int",
+    "uint",
+    "double",
+    "string",
+    "bytes",
+    "list",
+    "map",
+    "type",
+    "optional",
+    "timestamp",
+    "duration",
+    "bigint",
+    "decimal",
+];
+
+/// The version, features, value types and limits of this build
+///
+/// Bindings check for a capability here rather than comparing versions, so they keep
+/// working against libraries built with a different set of features.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Capabilities {
+    /// The library version, such as `0.1.0`
+    pub version: &'static str,
+    pub abi_version: u32,
+    /// Optional Cargo features compiled in, such as `proto`, `stats` and `wasm`
+    pub features: Vec<&'static str>,
+    /// Extensions programs can enable, named by their `CompileOptions` field
+    pub extensions: Vec<&'static str>,
+    /// Value types results and variables can carry, named as in `CelValueType`
+    pub value_types: Vec<&'static str>,
+    pub functions: Vec<&'static str>,
+    pub macros: Vec<&'static str>,
+    pub limits: Limits,
+}
+
+/// Fixed limits of this build
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Limits {
+    /// Nesting depth a context allows until `Context::set_max_depth` changes it
+    pub default_max_depth: usize,
+    /// Earliest timestamp, in seconds since the Unix epoch
+    pub min_timestamp_seconds: i64,
+    /// Latest timestamp, in seconds since the Unix epoch
+    pub max_timestamp_seconds: i64,
+    /// Longest duration either side of zero, in seconds
+    pub max_duration_seconds: u64,
+}
+
+impl Capabilities {
+    /// The capabilities as a JSON object
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// The capabilities of this build
+#[must_use]
+pub fn capabilities() -> Capabilities {
+    let features = [
+        ("allocator", cfg!(feature = "allocator")),
+        ("parallel", cfg!(feature = "parallel")),
+        ("proto", cfg!(feature = "proto")),
+        ("stats", cfg!(feature = "stats")),
+        ("wasm", cfg!(feature = "wasm")),
+    ];
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        abi_version: CEL_ABI_VERSION,
+        features: features
+            .into_iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name)
+            .collect(),
+        extensions: vec![
+            "string_ext",
+            "math_ext",
+            "list_ext",
+            "optional_ext",
+            "unicode_ext",
+            "encoders_ext",
+            "json_ext",
+        ],
+        value_types: VALUE_TYPES.to_vec(),
+        functions: BUILTIN_FUNCTIONS.to_vec(),
+        macros: MACROS.to_vec(),
+        limits: Limits {
+            default_max_depth: DEFAULT_MAX_DEPTH,
+            min_timestamp_seconds: MIN_TIMESTAMP_SECONDS,
+            max_timestamp_seconds: MAX_TIMESTAMP_SECONDS,
+            max_duration_seconds: MAX_DURATION_SECONDS,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::CelValueType;
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities = capabilities();
+        assert_eq!(capabilities.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(capabilities.features.contains(&"proto"), cfg!(feature = "proto"));
+        assert_eq!(VALUE_TYPES.len(), CelValueType::Decimal as usize + 1);
+        assert_eq!(VALUE_TYPES[CelValueType::Optional as usize], "optional");
+
+        let json: serde_json::Value = serde_json::from_str(&capabilities.to_json()).unwrap();
+        assert_eq!(json["abi_version"], serde_json::json!(CEL_ABI_VERSION));
+        assert_eq!(json["limits"]["default_max_depth"], serde_json::json!(256));
+        assert!(json["functions"].as_array().unwrap().contains(&serde_json::json!("now")));
+    }
+}
@@ -0,0 +1,107 @@
+//! Named constants of an environment, inlined into programs as they compile
+//!
+//! Every reference to a constant is replaced by the literal for its value before the
+//! program is folded, so `amount > threshold` compiles to `amount > 100` and
+//! `tiers[plan]` with both names constant folds down to a single literal. Field
+//! selections such as `x.threshold`, function names, message type names and
+//! comprehension variables that shadow a constant are left alone.
+
+use super::options::COMPREHENSIONS;
+use cel_parser::{Expression, Member};
+use std::collections::HashMap;
+
+/// `expression` with every reference to one of `constants` replaced by its literal
+pub(crate) fn inline(expression: &Expression, constants: &HashMap<&str, Expression>) -> Expression {
+    let recurse = |expression: &Expression| inline(expression, constants);
+    let boxed = |expression: &Expression| Box::new(recurse(expression));
+
+    match expression {
+        Expression::Ident(name) => {
+            constants.get(name.as_str()).cloned().unwrap_or_else(|| expression.clone())
+        }
+        Expression::FunctionCall(function, Some(range), args)
+            if matches!(function.as_ref(), Expression::Ident(name)
+                if COMPREHENSIONS.contains(&name.as_str())) =>
+        {
+            let mut scoped = constants.clone();
+            if let Some(Expression::Ident(variable)) = args.first() {
+                scoped.remove(variable.as_str());
+            }
+            Expression::FunctionCall(
+                function.clone(),
+                Some(boxed(range)),
+                args.iter().map(|arg| inline(arg, &scoped)).collect(),
+            )
+        }
+        Expression::FunctionCall(function, target, args) => Expression::FunctionCall(
+            function.clone(),
+            target.as_deref().map(boxed),
+            args.iter().map(recurse).collect(),
+        ),
+        Expression::Member(operand, member) => match member.as_ref() {
+            Member::Attribute(_) => Expression::Member(boxed(operand), member.clone()),
+            Member::Index(index) => {
+                Expression::Member(boxed(operand), Member::Index(boxed(index)).into())
+            }
+            Member::Fields(fields) => Expression::Member(
+                operand.clone(),
+                Member::Fields(
+                    fields.iter().map(|(name, value)| (name.clone(), recurse(value))).collect(),
+                )
+                .into(),
+            ),
+        },
+        Expression::Arithmetic(left, op, right) => {
+            Expression::Arithmetic(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Relation(left, op, right) => {
+            Expression::Relation(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Ternary(condition, if_true, if_false) => {
+            Expression::Ternary(boxed(condition), boxed(if_true), boxed(if_false))
+        }
+        Expression::Or(left, right) => Expression::Or(boxed(left), boxed(right)),
+        Expression::And(left, right) => Expression::And(boxed(left), boxed(right)),
+        Expression::Unary(op, operand) => Expression::Unary(op.clone(), boxed(operand)),
+        Expression::List(items) => Expression::List(items.iter().map(recurse).collect()),
+        Expression::Map(entries) => Expression::Map(
+            entries.iter().map(|(key, value)| (recurse(key), recurse(value))).collect(),
+        ),
+        Expression::Atom(_) => expression.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{Context, Env, Error, Value};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_constants_are_folded() {
+        let mut env = Env::default();
+        env.add_constant("threshold", &Value::Int(100)).unwrap();
+        let tiers = serde_json::json!({"free": 1, "pro": 10});
+        env.add_constant("tiers", &crate::api::value::json_to_cel_value(&tiers).unwrap())
+            .unwrap();
+        env.add_constant("plan", &Value::String(Arc::new("pro".to_string()))).unwrap();
+        env.set_strict_variables(true);
+        env.declare_variable("amount");
+
+        let program = env.compile("amount > threshold * tiers[plan]").unwrap();
+        assert_eq!(program.variables(), ["amount"]);
+        let mut context = Context::new();
+        context.add_variable("amount".to_string(), serde_json::json!(1001));
+        assert_eq!(program.execute(&context), Ok(Value::Bool(true)));
+        // Bindings in the context cannot override a constant
+        context.add_variable("threshold".to_string(), serde_json::json!(1000));
+        assert_eq!(program.execute(&context), Ok(Value::Bool(true)));
+
+        let program = env.compile("[1, 2].map(threshold, threshold * 2)").unwrap();
+        assert_eq!(program.execute(&context), env.compile("[2, 4]").unwrap().execute(&context));
+
+        let timestamp = env.compile("timestamp('2024-01-01T00:00:00Z')").unwrap();
+        let now = timestamp.execute(&context).unwrap();
+        assert!(matches!(env.add_constant("epoch", &now), Err(Error::InvalidArgument(_))));
+        assert!(matches!(env.add_constant("a b", &Value::Null), Err(Error::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,1686 @@
+use super::audit::AuditLog;
+use super::functions::Overloads;
+use super::intern::intern;
+use super::value::{
+    json_to_cel_value, timestamp_value, value_from_tagged_json, value_to_tagged_json, value_type_of,
+};
+use super::{AttributeAccess, AuditRecord, Error, MissingKeys, Value};
+use crate::ffi::CelValueType;
+use cel_interpreter::objects::{Key, Map};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Closure supplying variables that a context does not bind
+///
+/// It returns `Ok(None)` to leave the variable unbound, and an error message if the
+/// value could not be produced.
+pub type VariableResolver = dyn Fn(&str) -> Result<Option<Value>, String> + Send + Sync;
+
+struct Resolver(Box<VariableResolver>);
+
+impl fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Resolver")
+    }
+}
+
+/// Closure deciding each field selection an expression makes, given the selection as
+/// written, such as `request.user.email`
+pub type AttributeHook = dyn Fn(&str) -> AttributeAccess + Send + Sync;
+
+struct Hook(Arc<AttributeHook>);
+
+impl fmt::Debug for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Hook")
+    }
+}
+
+/// Closure implementing a function that expressions call, given its arguments
+///
+/// It returns the result, or an error message that fails the evaluation.
+pub type HostFunction = dyn Fn(&[Value]) -> Result<Value, String> + Send + Sync;
+
+/// How many arguments a host function takes, and the values of those a call leaves out
+///
+/// A call passes between `min` and `max` arguments, any number from `min` on if `max`
+/// is `None`. `defaults` fills in the arguments after the first `min`, in order, so a
+/// function taking 2 to 3 arguments with one default always gets 3.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+    pub defaults: Vec<Value>,
+}
+
+impl Arity {
+    /// Exactly `count` arguments
+    #[must_use]
+    pub const fn exactly(count: usize) -> Self {
+        Self::between(count, count)
+    }
+
+    /// `min` arguments or more
+    #[must_use]
+    pub const fn at_least(min: usize) -> Self {
+        Self {
+            min,
+            max: None,
+            defaults: Vec::new(),
+        }
+    }
+
+    /// From `min` to `max` arguments
+    #[must_use]
+    pub const fn between(min: usize, max: usize) -> Self {
+        Self {
+            min,
+            max: Some(max),
+            defaults: Vec::new(),
+        }
+    }
+
+    /// This arity, filling in the optional arguments a call leaves out with `defaults`
+    #[must_use]
+    pub fn with_defaults(mut self, defaults: Vec<Value>) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        let Some(max) = self.max else {
+            return Ok(());
+        };
+        if max < self.min {
+            return Err(Error::InvalidArgument(format!(
+                "maximum of {max} arguments is below the minimum of {}",
+                self.min
+            )));
+        }
+        if self.defaults.len() > max - self.min {
+            return Err(Error::InvalidArgument(format!(
+                "more defaults ({}) than optional arguments ({})",
+                self.defaults.len(),
+                max - self.min
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let noun = |count: usize| if count == 1 { "argument" } else { "arguments" };
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{max} {}", noun(max)),
+            Some(max) => write!(f, "{} to {max} arguments", self.min),
+            None => write!(f, "at least {} {}", self.min, noun(self.min)),
+        }
+    }
+}
+
+/// A string variable that refers to memory owned by the caller
+#[derive(Debug, Clone, Copy)]
+struct BorrowedStr {
+    ptr: *const u8,
+    len: usize,
+    token: u64,
+}
+
+// SAFETY: the string is only ever read, and `add_borrowed_string_variable` requires
+// it to stay valid and unchanged until its token is invalidated
+unsafe impl Send for BorrowedStr {}
+unsafe impl Sync for BorrowedStr {}
+
+impl BorrowedStr {
+    fn as_str(&self) -> &str {
+        // SAFETY: built from a `&str` that the caller keeps alive until invalidation
+        unsafe { std::str::from_utf8_unchecked(std::slice::from_raw_parts(self.ptr, self.len)) }
+    }
+}
+
+/// A variable binding as recorded in the undo journal
+#[derive(Debug)]
+enum Binding {
+    Json(serde_json::Value),
+    Bytes(Vec<u8>),
+    Value(Value),
+    Borrowed(BorrowedStr),
+}
+
+/// How a variable was bound before a change, so `Context::restore` can undo it
+#[derive(Debug)]
+struct Undo {
+    serial: u64,
+    name: Arc<str>,
+    previous: Option<Binding>,
+}
+
+/// A point in a context's history that `Context::restore` can roll back to
+///
+/// Taking a snapshot copies no variables. While any snapshot of a context is alive,
+/// the context journals the previous binding of each variable it changes, so
+/// restoring costs time proportional to the changes made since the snapshot rather
+/// than to the size of the context.
+#[derive(Debug)]
+pub struct ContextSnapshot {
+    owner: Arc<()>,
+    position: usize,
+    last_serial: Option<u64>,
+}
+
+/// Deepest expression `Program::execute` evaluates unless `Context::set_max_depth`
+/// says otherwise
+///
+/// Evaluation recurses once per level of nesting, so this keeps the deepest accepted
+/// expression well inside a 2 MiB thread stack.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// Context for storing variables for CEL evaluation
+#[derive(Debug)]
+pub struct Context {
+    variables: HashMap<Arc<str>, serde_json::Value>,
+    bytes_variables: HashMap<Arc<str>, Vec<u8>>,
+    value_variables: HashMap<Arc<str>, Value>,
+    borrowed_variables: HashMap<Arc<str>, BorrowedStr>,
+    resolver: Option<Resolver>,
+    attribute_hook: Option<Hook>,
+    audit: AuditLog,
+    functions: HashMap<Arc<str>, Arc<Overloads>>,
+    max_depth: usize,
+    now: Option<(i64, u32)>,
+    missing_keys: MissingKeys,
+    null_propagation: bool,
+    numeric_promotion: bool,
+    soft_errors: bool,
+    reject_non_finite: bool,
+    max_memory: usize,
+    max_iterations: usize,
+    deterministic: bool,
+    frozen: bool,
+    journal: Vec<Undo>,
+    next_serial: u64,
+    snapshots: Arc<()>,
+    #[cfg(feature = "proto")]
+    descriptors: prost_reflect::DescriptorPool,
+}
+
+impl Context {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            variables: HashMap::new(),
+            bytes_variables: HashMap::new(),
+            value_variables: HashMap::new(),
+            borrowed_variables: HashMap::new(),
+            resolver: None,
+            attribute_hook: None,
+            audit: AuditLog::default(),
+            functions: HashMap::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            now: None,
+            missing_keys: MissingKeys::Error,
+            null_propagation: false,
+            numeric_promotion: false,
+            soft_errors: false,
+            reject_non_finite: false,
+            max_memory: 0,
+            max_iterations: 0,
+            deterministic: false,
+            frozen: false,
+            journal: Vec::new(),
+            next_serial: 0,
+            snapshots: Arc::new(()),
+            #[cfg(feature = "proto")]
+            descriptors: prost_reflect::DescriptorPool::new(),
+        }
+    }
+
+    pub fn add_variable(&mut self, name: String, value: serde_json::Value) {
+        let name = intern(&name);
+        self.record_change(&name);
+        self.bytes_variables.remove(&name);
+        self.value_variables.remove(&name);
+        self.borrowed_variables.remove(&name);
+        self.variables.insert(name, value);
+    }
+
+    /// Add a variable holding raw bytes, which JSON values cannot represent
+    pub fn add_bytes_variable(&mut self, name: String, value: Vec<u8>) {
+        let name = intern(&name);
+        self.record_change(&name);
+        self.variables.remove(&name);
+        self.value_variables.remove(&name);
+        self.borrowed_variables.remove(&name);
+        self.bytes_variables.insert(name, value);
+    }
+
+    /// Add a variable holding an already converted CEL value
+    ///
+    /// Used for values JSON cannot represent faithfully, such as decoded protobuf
+    /// messages with bytes or unsigned fields.
+    pub fn add_value_variable(&mut self, name: String, value: Value) {
+        let name = intern(&name);
+        self.record_change(&name);
+        self.variables.remove(&name);
+        self.bytes_variables.remove(&name);
+        self.borrowed_variables.remove(&name);
+        self.value_variables.insert(name, value);
+    }
+
+    /// Add a string variable that refers to `value` instead of copying it
+    ///
+    /// The context keeps only the pointer and length. Executing a program still copies
+    /// the string into the interpreter, which owns every value it evaluates, but that
+    /// copy is released as soon as the execution ends. `invalidate_borrowed` unbinds
+    /// every variable added with the same `token`.
+    ///
+    /// # Safety
+    ///
+    /// `value` must stay valid and unchanged until `invalidate_borrowed(token)` is
+    /// called or the context is dropped, even if the variable is replaced or removed
+    /// first, since restoring a snapshot can bind it again.
+    pub unsafe fn add_borrowed_string_variable(&mut self, name: String, value: &str, token: u64) {
+        let name = intern(&name);
+        self.record_change(&name);
+        self.variables.remove(&name);
+        self.bytes_variables.remove(&name);
+        self.value_variables.remove(&name);
+        self.borrowed_variables.insert(
+            name,
+            BorrowedStr {
+                ptr: value.as_ptr(),
+                len: value.len(),
+                token,
+            },
+        );
+    }
+
+    /// Unbind every variable added by `add_borrowed_string_variable` with `token`,
+    /// returning how many were bound
+    ///
+    /// Snapshots forget them too: restoring one leaves these variables unbound
+    /// rather than pointing at memory the caller may be about to free.
+    pub fn invalidate_borrowed(&mut self, token: u64) -> usize {
+        let before = self.borrowed_variables.len();
+        self.borrowed_variables.retain(|_, borrowed| borrowed.token != token);
+        for undo in &mut self.journal {
+            if matches!(undo.previous, Some(Binding::Borrowed(borrowed)) if borrowed.token == token)
+            {
+                undo.previous = None;
+            }
+        }
+        before - self.borrowed_variables.len()
+    }
+
+    /// Bind `value` at a dotted path such as `request.headers.authorization`
+    ///
+    /// Missing intermediate maps are created and existing ones are extended, so
+    /// several paths can share a root variable.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the path has an empty segment or if a prefix of it is
+    /// already bound to something other than a map.
+    pub fn add_variable_path(&mut self, path: &str, value: serde_json::Value) -> Result<(), Error> {
+        let segments = path_segments(path)?;
+        let Some((leaf, parents)) = segments.split_last() else {
+            return Err(Error::InvalidArgument(format!("Invalid variable path '{path}'")));
+        };
+        let Some((root, intermediate)) = parents.split_first() else {
+            self.add_variable((*leaf).to_string(), value);
+            return Ok(());
+        };
+
+        // A root holding values JSON cannot represent is extended as a value
+        if self.value_variables.contains_key(*root) {
+            let value = json_to_cel_value(&value).map_err(Error::InvalidArgument)?;
+            return self.add_value_variable_path(path, value);
+        }
+        if self.bytes_variables.contains_key(*root) || self.borrowed_variables.contains_key(*root) {
+            return Err(Error::InvalidArgument(format!("Variable '{root}' is not a map")));
+        }
+        self.record_change(root);
+        let mut node = self
+            .variables
+            .entry(intern(root))
+            .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+        let mut prefix = (*root).to_string();
+
+        for segment in intermediate {
+            let Some(map) = node.as_object_mut() else {
+                return Err(Error::InvalidArgument(format!("Variable '{prefix}' is not a map")));
+            };
+            node = map
+                .entry(*segment)
+                .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+            prefix.push('.');
+            prefix.push_str(segment);
+        }
+
+        let Some(map) = node.as_object_mut() else {
+            return Err(Error::InvalidArgument(format!("Variable '{prefix}' is not a map")));
+        };
+        map.insert((*leaf).to_string(), value);
+        Ok(())
+    }
+
+    /// Bind an already converted value at a dotted path, like `add_variable_path`
+    ///
+    /// Used for values JSON cannot represent faithfully, such as a `uint` that also
+    /// fits an `int`. The root variable then holds a converted value itself, so maps
+    /// bound at its other paths are converted too.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the path has an empty segment or if a prefix of it is
+    /// already bound to something other than a map.
+    pub fn add_value_variable_path(&mut self, path: &str, value: Value) -> Result<(), Error> {
+        let segments = path_segments(path)?;
+        let Some((root, rest)) = segments.split_first() else {
+            return Err(Error::InvalidArgument(format!("Invalid variable path '{path}'")));
+        };
+        if rest.is_empty() {
+            self.add_value_variable((*root).to_string(), value);
+            return Ok(());
+        }
+
+        if self.bytes_variables.contains_key(*root) || self.borrowed_variables.contains_key(*root) {
+            return Err(Error::InvalidArgument(format!("Variable '{root}' is not a map")));
+        }
+        let mut node = match (self.value_variables.get(*root), self.variables.get(*root)) {
+            (Some(bound), _) => bound.clone(),
+            (None, Some(json)) => json_to_cel_value(json).map_err(Error::InvalidArgument)?,
+            (None, None) => empty_map(),
+        };
+        insert_at_path(&mut node, root, rest, value)?;
+        self.add_value_variable((*root).to_string(), node);
+        Ok(())
+    }
+
+    /// Remove a variable, returning whether it was bound
+    pub fn remove_variable(&mut self, name: &str) -> bool {
+        self.record_change(name);
+        self.variables.remove(name).is_some()
+            || self.bytes_variables.remove(name).is_some()
+            || self.value_variables.remove(name).is_some()
+            || self.borrowed_variables.remove(name).is_some()
+    }
+
+    /// The CEL type a bound variable evaluates to, or `None` if it is not bound
+    #[must_use]
+    pub fn variable_type(&self, name: &str) -> Option<CelValueType> {
+        if self.bytes_variables.contains_key(name) {
+            return Some(CelValueType::Bytes);
+        }
+        if self.borrowed_variables.contains_key(name) {
+            return Some(CelValueType::String);
+        }
+        if let Some(value) = self.value_variables.get(name) {
+            return Some(value_type_of(value));
+        }
+
+        self.variables.get(name).map(|value| match value {
+            serde_json::Value::Null => CelValueType::Null,
+            serde_json::Value::Bool(_) => CelValueType::Bool,
+            serde_json::Value::Number(n) if n.is_i64() => CelValueType::Int,
+            serde_json::Value::Number(n) if n.is_u64() => CelValueType::Uint,
+            serde_json::Value::Number(_) => CelValueType::Double,
+            serde_json::Value::String(_) => CelValueType::String,
+            serde_json::Value::Array(_) => CelValueType::List,
+            serde_json::Value::Object(_) => CelValueType::Map,
+        })
+    }
+
+    /// Names of all bound variables, in an unspecified order that is stable until the
+    /// context is modified
+    pub fn variable_names(&self) -> impl Iterator<Item = &str> {
+        self.variables
+            .keys()
+            .chain(self.bytes_variables.keys())
+            .chain(self.value_variables.keys())
+            .chain(self.borrowed_variables.keys())
+            .map(|name| &**name)
+    }
+
+    #[must_use]
+    pub fn variable_count(&self) -> usize {
+        self.variables.len()
+            + self.bytes_variables.len()
+            + self.value_variables.len()
+            + self.borrowed_variables.len()
+    }
+
+    #[must_use]
+    pub const fn get_variables(&self) -> &HashMap<Arc<str>, serde_json::Value> {
+        &self.variables
+    }
+
+    #[must_use]
+    pub const fn get_bytes_variables(&self) -> &HashMap<Arc<str>, Vec<u8>> {
+        &self.bytes_variables
+    }
+
+    #[must_use]
+    pub const fn get_value_variables(&self) -> &HashMap<Arc<str>, Value> {
+        &self.value_variables
+    }
+
+    /// The string a variable added by `add_borrowed_string_variable` refers to
+    #[must_use]
+    pub fn get_borrowed_variable(&self, name: &str) -> Option<&str> {
+        self.borrowed_variables.get(name).map(BorrowedStr::as_str)
+    }
+
+    /// The variables added by `add_borrowed_string_variable`, with their strings
+    pub fn get_borrowed_variables(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.borrowed_variables
+            .iter()
+            .map(|(name, borrowed)| (&**name, borrowed.as_str()))
+    }
+
+    /// The bound variables as JSON, for `from_json` to rebuild them
+    ///
+    /// Variables bound from JSON are kept verbatim under `variables`; all others are
+    /// under `values` in the type-preserving form described in the README, so bytes,
+    /// unsigned integers, timestamps, durations and optionals survive the round trip.
+    /// The resolver, depth limit, injected clock and protobuf descriptors are left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if a variable holds a value with no JSON form.
+    pub fn to_json(&self) -> Result<String, Error> {
+        let mut values = self
+            .bytes_variables
+            .iter()
+            .map(|(name, bytes)| (name, Value::Bytes(Arc::new(bytes.clone()))))
+            .collect::<Vec<_>>();
+        values.extend(self.value_variables.iter().map(|(name, value)| (name, value.clone())));
+        let values = values
+            .into_iter()
+            .map(|(name, value)| {
+                value_to_tagged_json(&value).map(|json| (name.to_string(), json)).map_err(|e| {
+                    Error::InvalidArgument(format!("Variable '{name}' cannot be serialized: {e}"))
+                })
+            })
+            .collect::<Result<serde_json::Map<_, _>, _>>()?;
+        let variables =
+            self.variables.iter().map(|(name, value)| (name.to_string(), value.clone()));
+        let borrowed = self
+            .get_borrowed_variables()
+            .map(|(name, value)| (name.to_string(), serde_json::Value::String(value.to_string())));
+        let variables = variables.chain(borrowed);
+
+        Ok(serde_json::json!({
+            "variables": variables.collect::<serde_json::Map<_, _>>(),
+            "values": values,
+        })
+        .to_string())
+    }
+
+    /// Build a context from the output of `to_json`
+    ///
+    /// Either member may be left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if `json` is not an object with only
+    /// `variables` and `values` objects, or a value cannot be read.
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let json = serde_json::from_str::<serde_json::Value>(json)
+            .map_err(|e| Error::InvalidArgument(format!("Invalid context JSON: {e}")))?;
+        let serde_json::Value::Object(members) = json else {
+            return Err(Error::InvalidArgument("Context JSON must be an object".to_string()));
+        };
+
+        let mut context = Self::new();
+        for (member, entries) in members {
+            if member != "variables" && member != "values" {
+                return Err(Error::InvalidArgument(format!("Unknown context member '{member}'")));
+            }
+            let serde_json::Value::Object(entries) = entries else {
+                return Err(Error::InvalidArgument(format!(
+                    "Context member '{member}' must be an object"
+                )));
+            };
+            for (name, value) in entries {
+                if member == "variables" {
+                    context.add_variable(name, value);
+                    continue;
+                }
+                match value_from_tagged_json(&value) {
+                    Ok(Value::Bytes(bytes)) => context.add_bytes_variable(name, bytes.to_vec()),
+                    Ok(value) => context.add_value_variable(name, value),
+                    Err(e) => {
+                        return Err(Error::InvalidArgument(format!("Variable '{name}': {e}")))
+                    }
+                }
+            }
+        }
+        Ok(context)
+    }
+
+    /// Register the message types in a serialized `google.protobuf.FileDescriptorSet`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the set cannot be decoded or refers to unknown types.
+    #[cfg(feature = "proto")]
+    pub fn add_proto_descriptors(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.descriptors
+            .decode_file_descriptor_set(bytes)
+            .map_err(|e| Error::InvalidArgument(format!("Invalid descriptor set: {e}")))
+    }
+
+    /// Add a variable holding a serialized protobuf message of a registered type
+    ///
+    /// The enum values the message can reference are bound at their fully qualified
+    /// names too, except where that would conflict with an existing variable.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `type_name` is not registered or the message cannot be
+    /// decoded.
+    #[cfg(feature = "proto")]
+    pub fn add_proto_variable(
+        &mut self,
+        name: String,
+        bytes: &[u8],
+        type_name: &str,
+    ) -> Result<(), Error> {
+        let descriptor = self
+            .descriptors
+            .get_message_by_name(type_name)
+            .ok_or_else(|| Error::InvalidArgument(format!("Unknown message type '{type_name}'")))?;
+        let message = prost_reflect::DynamicMessage::decode(descriptor, bytes)
+            .map_err(|e| Error::InvalidArgument(format!("Invalid '{type_name}' message: {e}")))?;
+
+        for (path, number) in super::proto::enum_constants(&message.descriptor()) {
+            if path.split('.').next().is_some_and(|root| root == name) {
+                continue;
+            }
+            // A conflicting binding keeps its value; the constant just isn't available
+            let _ = self.add_variable_path(&path, serde_json::Value::from(number));
+        }
+        self.add_value_variable(name, super::proto::message_to_cel_value(&message));
+        Ok(())
+    }
+
+    /// Register a closure for variables the context does not bind
+    ///
+    /// Bound variables always take precedence, and resolved values are not cached in
+    /// the context. Each execution asks for every free variable of the program before
+    /// it starts, including those only read in a branch it will not take, but never
+    /// for the variables of comprehensions such as `x` in `xs.all(x, x > 0)`. The
+    /// resolver survives `reset` and is not part of snapshots.
+    pub fn set_resolver<F>(&mut self, resolver: F)
+    where
+        F: Fn(&str) -> Result<Option<Value>, String> + Send + Sync + 'static,
+    {
+        self.resolver = Some(Resolver(Box::new(resolver)));
+    }
+
+    pub fn clear_resolver(&mut self) {
+        self.resolver = None;
+    }
+
+    #[must_use]
+    pub const fn has_resolver(&self) -> bool {
+        self.resolver.is_some()
+    }
+
+    /// Register a closure that decides every field selection and index expressions
+    /// make against this context
+    ///
+    /// The closure sees the path of each selection, so `a.b.c` asks about `a.b` and
+    /// then `a.b.c`, and can allow it, deny it to fail the evaluation, or replace the
+    /// value it evaluates to. That makes it suitable for row-level security, and for
+    /// recording which data a rule actually read. Selections inside `has()` are asked
+    /// about too. Indexes are asked about once the key is evaluated: a string key as
+    /// the selection it stands for, so `a["b"]` and `a[k]` with `k` holding `"b"` are
+    /// both `a.b`, and other keys as in `a[1]`. The hook survives `reset` and is not
+    /// part of snapshots.
+    pub fn set_attribute_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&str) -> AttributeAccess + Send + Sync + 'static,
+    {
+        self.attribute_hook = Some(Hook(Arc::new(hook)));
+    }
+
+    pub fn clear_attribute_hook(&mut self) {
+        self.attribute_hook = None;
+    }
+
+    #[must_use]
+    pub const fn has_attribute_hook(&self) -> bool {
+        self.attribute_hook.is_some()
+    }
+
+    /// The attribute hook, shared with the interpreter functions that call it
+    pub(crate) fn attribute_hook(&self) -> Option<Arc<AttributeHook>> {
+        self.attribute_hook.as_ref().map(|hook| Arc::clone(&hook.0))
+    }
+
+    /// Keep an audit record of each of the last `capacity` executions against this
+    /// context
+    ///
+    /// Each record holds the program's fingerprint, the names of the variables bound,
+    /// the result or error and the duration, enough to reconstruct a decision from the
+    /// program and the host's own copy of the inputs. Once `capacity` records are kept
+    /// the oldest is dropped for each new one. 0, the default, stops recording, and any
+    /// change discards the records kept. Traced executions are not recorded.
+    pub fn set_audit_log(&mut self, capacity: usize) {
+        self.audit = AuditLog::new(capacity);
+    }
+
+    /// Remove and return the audit records kept, oldest first
+    #[must_use]
+    pub fn take_audit_records(&self) -> Vec<AuditRecord> {
+        self.audit.take()
+    }
+
+    pub(crate) const fn audit_log(&self) -> &AuditLog {
+        &self.audit
+    }
+
+    /// Register a function that expressions executed against this context can call
+    ///
+    /// It takes any arguments, and replaces the function of this kind registered
+    /// under `name` before; overloads added with `add_overload` are tried first. One
+    /// named like a built-in function, such as `size`, is shadowed by it. Functions
+    /// survive `reset` and are not part of snapshots, so hosts can compile programs
+    /// once and bind the functions of each request to its context.
+    pub fn add_function<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.overloads(name).set_untyped(false, Arity::default(), Arc::new(function));
+    }
+
+    /// Register a function taking any arguments, as many as `arity` allows
+    ///
+    /// Calls passing too few or too many arguments fail the evaluation, and `function`
+    /// gets the defaults of `arity` in place of the optional arguments a call leaves
+    /// out, so a helper such as `coalesce(a, b, ...)` is registered once for all its
+    /// arities.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if `arity` allows fewer arguments than it
+    /// requires or has more defaults than optional arguments.
+    pub fn add_variadic_function<F>(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        function: F,
+    ) -> Result<(), Error>
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        arity.check()?;
+        self.overloads(name).set_untyped(false, arity, Arc::new(function));
+        Ok(())
+    }
+
+    /// Register a function that expressions call on a receiver, as in
+    /// `user.hasRole('admin')`
+    ///
+    /// `function` gets the receiver followed by the arguments. Member functions are
+    /// kept apart from the functions `add_function` registers under the same name,
+    /// which only global calls such as `hasRole(user, 'admin')` reach.
+    pub fn add_member_function<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.overloads(name).set_untyped(true, Arity::default(), Arc::new(function));
+    }
+
+    /// Register a member function taking as many arguments as `arity` allows, counting
+    /// the receiver
+    ///
+    /// # Errors
+    ///
+    /// Fails as `add_variadic_function` does.
+    pub fn add_variadic_member_function<F>(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        function: F,
+    ) -> Result<(), Error>
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        arity.check()?;
+        self.overloads(name).set_untyped(true, arity, Arc::new(function));
+        Ok(())
+    }
+
+    /// Register an overload of `name` for arguments of the types in `params`
+    ///
+    /// A call whose arguments have exactly these types goes to `function`, leaving
+    /// the library to dispatch between the overloads of a name rather than the host.
+    /// The overload replaces one registered for the same types, and fails the
+    /// evaluation if it returns a value of a type other than `result`. Calls that no
+    /// overload matches go to the function `add_function` registered, or fail.
+    pub fn add_overload<F>(
+        &mut self,
+        name: &str,
+        params: &[CelValueType],
+        result: CelValueType,
+        function: F,
+    ) where
+        F: Fn(&[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.overloads(name).add(false, params, result, Arc::new(function));
+    }
+
+    /// Register an overload of the member function `name` for a receiver and
+    /// arguments of the types in `params`, the receiver's first
+    ///
+    /// Member calls with no matching overload go to the function
+    /// `add_member_function` registered, or fail.
+    pub fn add_member_overload<F>(
+        &mut self,
+        name: &str,
+        params: &[CelValueType],
+        result: CelValueType,
+        function: F,
+    ) where
+        F: Fn(&[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.overloads(name).add(true, params, result, Arc::new(function));
+    }
+
+    fn overloads(&mut self, name: &str) -> &mut Overloads {
+        Arc::make_mut(self.functions.entry(intern(name)).or_default())
+    }
+
+    /// Unregister the functions, member functions and overloads registered under
+    /// `name`; returns false if there were none
+    pub fn remove_function(&mut self, name: &str) -> bool {
+        self.functions.remove(name).is_some()
+    }
+
+    #[must_use]
+    pub fn has_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// The registered functions, in no particular order
+    pub(crate) fn functions(&self) -> impl Iterator<Item = (&str, &Arc<Overloads>)> {
+        self.functions.iter().map(|(name, overloads)| (&**name, overloads))
+    }
+
+    /// Limit the nesting depth of expressions evaluated against this context
+    ///
+    /// Deeper expressions fail with an "expression too deep" execution error instead of
+    /// overflowing the stack. 0 removes the limit.
+    pub const fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    #[must_use]
+    pub const fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Fix the instant `now()` returns, in seconds and nanoseconds since the Unix epoch
+    ///
+    /// Without one, each evaluation reads the system clock once, so every call to
+    /// `now()` within it sees the same instant. `reset` keeps the injected instant.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if `nanos` is a second or more, or the instant
+    /// falls outside the years 0001 to 9999 that CEL timestamps cover.
+    pub fn set_now(&mut self, seconds: i64, nanos: u32) -> Result<(), Error> {
+        timestamp_value(seconds, nanos)?;
+        self.now = Some((seconds, nanos));
+        Ok(())
+    }
+
+    /// Go back to reading the system clock for `now()`
+    pub const fn clear_now(&mut self) {
+        self.now = None;
+    }
+
+    /// The instant set by `set_now`, as seconds and nanoseconds since the Unix epoch
+    #[must_use]
+    pub const fn now(&self) -> Option<(i64, u32)> {
+        self.now
+    }
+
+    /// Choose what selecting a field or key that a map lacks evaluates to in
+    /// expressions executed against this context
+    ///
+    /// Programs keep one rewritten copy per policy, compiled the first time it is used.
+    pub const fn set_missing_keys(&mut self, policy: MissingKeys) {
+        self.missing_keys = policy;
+    }
+
+    #[must_use]
+    pub const fn missing_keys(&self) -> MissingKeys {
+        self.missing_keys
+    }
+
+    /// Make operators applied to null yield null in expressions executed against this
+    /// context, as in SQL, instead of failing
+    ///
+    /// Arithmetic, negation, `!`, ordering comparisons, field selection and indexing
+    /// with a null operand then evaluate to null, and `has()` on a field of null is
+    /// false, so `row.price * row.qty` is null for a row without a price. Equality,
+    /// `in` and the logical operators are unchanged, so `x == null` still tests for
+    /// null. Like `set_missing_keys`, this runs a rewritten copy of the program.
+    pub const fn set_null_propagation(&mut self, enabled: bool) {
+        self.null_propagation = enabled;
+    }
+
+    #[must_use]
+    pub const fn null_propagation(&self) -> bool {
+        self.null_propagation
+    }
+
+    /// Let arithmetic, comparisons and equality mix int, uint and double operands in
+    /// expressions executed against this context
+    ///
+    /// Comparisons between int and uint are exact, and with a double operand are done
+    /// in double, so `count < 1.5` works for an int `count`. Arithmetic with a double
+    /// operand is done in double, and between int and uint in int, failing if a value
+    /// falls outside the int range. Like `set_missing_keys`, this runs a rewritten copy
+    /// of the program.
+    pub const fn set_numeric_promotion(&mut self, enabled: bool) {
+        self.numeric_promotion = enabled;
+    }
+
+    #[must_use]
+    pub const fn numeric_promotion(&self) -> bool {
+        self.numeric_promotion
+    }
+
+    /// Let host functions that fail evaluate to error values in expressions executed
+    /// against this context, which `&&`, `||` and comprehensions can absorb
+    ///
+    /// As CEL specifies, `lookup(id) && false` is then false like `false && lookup(id)`
+    /// when `lookup` fails, and `lookup(id) || true` is true. Other operators and
+    /// functions given an error evaluate to it, and an evaluation that results in an
+    /// error fails with its message, as it would without soft errors. Errors the
+    /// library raises, such as division by zero, still fail the evaluation. Like
+    /// `set_missing_keys`, this runs a rewritten copy of the program.
+    pub const fn set_soft_errors(&mut self, enabled: bool) {
+        self.soft_errors = enabled;
+    }
+
+    #[must_use]
+    pub const fn soft_errors(&self) -> bool {
+        self.soft_errors
+    }
+
+    /// Refuse NaN and infinite doubles as variables of this context
+    ///
+    /// By default they are bound like any other double: NaN equals nothing, not even
+    /// itself, and ordering comparisons with it are false, as CEL specifies. Hosts that
+    /// treat a non-finite input as a bug can have the C API reject it instead.
+    pub const fn set_reject_non_finite(&mut self, enabled: bool) {
+        self.reject_non_finite = enabled;
+    }
+
+    #[must_use]
+    pub const fn reject_non_finite(&self) -> bool {
+        self.reject_non_finite
+    }
+
+    /// Whether a variable holds a NaN double, at any depth
+    pub(crate) fn binds_nan(&self) -> bool {
+        fn has_nan(value: &Value) -> bool {
+            match value {
+                Value::Float(f) => f.is_nan(),
+                Value::List(items) => items.iter().any(has_nan),
+                Value::Map(map) => map.map.values().any(has_nan),
+                _ => false,
+            }
+        }
+        self.value_variables.values().any(has_nan)
+    }
+
+    /// Limit the bytes one evaluation against this context may allocate
+    ///
+    /// Strings, bytes, lists and maps the expression builds are charged as they are
+    /// made, so `string(bytes(s) + bytes(s))` in a comprehension fails with
+    /// `Error::MemoryLimit` once the total passes `bytes`, which cost budgets alone do
+    /// not catch. Values taken from the context are not charged. 0, the default,
+    /// removes the limit. Like `set_missing_keys`, this runs a rewritten copy of the
+    /// program; if that copy does not compile, evaluation fails with `Error::Execution`
+    /// rather than running unmetered.
+    pub const fn set_max_memory(&mut self, bytes: usize) {
+        self.max_memory = bytes;
+    }
+
+    #[must_use]
+    pub const fn max_memory(&self) -> usize {
+        self.max_memory
+    }
+
+    /// Limit the comprehension iterations one evaluation against this context may run
+    ///
+    /// Iterations are counted across every `all`, `exists`, `exists_one`, `map` and
+    /// `filter` the evaluation runs, nested ones included, so `xs.all(x, ys.all(y, p))`
+    /// over 1000 elements each spends a million. Evaluation fails with
+    /// `Error::IterationLimit` once the count passes `iterations`. Comprehensions that
+    /// stop early, such as an `exists` that finds a match, only spend the iterations
+    /// they run. 0, the default, removes the limit. Like `set_max_memory`, this runs a
+    /// rewritten copy of the program and fails evaluation if the copy does not compile,
+    /// so comprehensions never run uncounted.
+    pub const fn set_max_iterations(&mut self, iterations: usize) {
+        self.max_iterations = iterations;
+    }
+
+    #[must_use]
+    pub const fn max_iterations(&self) -> usize {
+        self.max_iterations
+    }
+
+    /// Make evaluations against this context reproducible across runs and platforms
+    ///
+    /// `now()` then fails unless `set_now` injected the instant, instead of reading the
+    /// system clock, and comprehensions over a map visit its keys in sorted order
+    /// rather than the map's hash order. The rest of evaluation is deterministic
+    /// already: the library has no source of randomness, doubles format the same way
+    /// everywhere, and maps handed to the C API list their entries sorted by key. Host
+    /// functions and resolvers have to be deterministic themselves. Like
+    /// `set_max_memory`, this runs a rewritten copy of the program.
+    pub const fn set_deterministic(&mut self, enabled: bool) {
+        self.deterministic = enabled;
+    }
+
+    #[must_use]
+    pub const fn deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Mark the context read-only for the C API, which then rejects every change to it
+    ///
+    /// Rust code needs no flag to share a context immutably: `&Context` and
+    /// `Arc<Context>` already prevent changes. There is no way to unfreeze.
+    pub const fn freeze(&mut self) {
+        self.frozen = true;
+    }
+
+    #[must_use]
+    pub const fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Ask the resolver for a variable, returning `None` if it leaves it unbound
+    ///
+    /// # Errors
+    ///
+    /// Returns the resolver's error message if it could not produce the value.
+    pub fn resolve_variable(&self, name: &str) -> Result<Option<Value>, String> {
+        self.resolver.as_ref().map_or(Ok(None), |resolver| (resolver.0)(name))
+    }
+
+    pub fn reset(&mut self) {
+        if !self.is_journaling() {
+            self.variables.clear();
+            self.bytes_variables.clear();
+            self.value_variables.clear();
+            self.borrowed_variables.clear();
+            return;
+        }
+
+        let variables = self.variables.drain().map(|(name, value)| (name, Binding::Json(value)));
+        let bytes_variables =
+            self.bytes_variables.drain().map(|(name, value)| (name, Binding::Bytes(value)));
+        let value_variables =
+            self.value_variables.drain().map(|(name, value)| (name, Binding::Value(value)));
+        let borrowed_variables = self
+            .borrowed_variables
+            .drain()
+            .map(|(name, value)| (name, Binding::Borrowed(value)));
+        for (name, previous) in variables
+            .chain(bytes_variables)
+            .chain(value_variables)
+            .chain(borrowed_variables)
+        {
+            self.journal.push(Undo {
+                serial: self.next_serial,
+                name,
+                previous: Some(previous),
+            });
+            self.next_serial += 1;
+        }
+    }
+
+    /// Take a snapshot that `restore` can later roll the variables back to
+    ///
+    /// The resolver and host functions are not part of the snapshot.
+    #[must_use]
+    pub fn snapshot(&mut self) -> ContextSnapshot {
+        if !self.is_journaling() {
+            self.journal.clear();
+        }
+
+        ContextSnapshot {
+            owner: Arc::clone(&self.snapshots),
+            position: self.journal.len(),
+            last_serial: self.journal.last().map(|undo| undo.serial),
+        }
+    }
+
+    /// Roll the variables back to how they were when `snapshot` was taken
+    ///
+    /// The snapshot stays usable, so the same one can be restored after every
+    /// evaluation. Restoring an earlier snapshot invalidates the later ones.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot was taken from another context or has been
+    /// invalidated.
+    pub fn restore(&mut self, snapshot: &ContextSnapshot) -> Result<(), Error> {
+        if !Arc::ptr_eq(&self.snapshots, &snapshot.owner) {
+            return Err(Error::InvalidArgument(
+                "Snapshot was taken from a different context".to_string(),
+            ));
+        }
+        // A later restore may have truncated the journal below this snapshot and new
+        // changes refilled it, so the entry just before it must be the same one
+        let intact = snapshot.position.checked_sub(1).is_none_or(|last| {
+            self.journal.get(last).map(|undo| undo.serial) == snapshot.last_serial
+        });
+        if !intact || snapshot.position > self.journal.len() {
+            return Err(Error::InvalidArgument(
+                "Snapshot was invalidated by restoring an earlier snapshot".to_string(),
+            ));
+        }
+
+        for undo in self.journal.drain(snapshot.position..).rev() {
+            self.variables.remove(&undo.name);
+            self.bytes_variables.remove(&undo.name);
+            self.value_variables.remove(&undo.name);
+            self.borrowed_variables.remove(&undo.name);
+            match undo.previous {
+                Some(Binding::Json(value)) => {
+                    self.variables.insert(undo.name, value);
+                }
+                Some(Binding::Bytes(value)) => {
+                    self.bytes_variables.insert(undo.name, value);
+                }
+                Some(Binding::Value(value)) => {
+                    self.value_variables.insert(undo.name, value);
+                }
+                Some(Binding::Borrowed(value)) => {
+                    self.borrowed_variables.insert(undo.name, value);
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a live snapshot needs changes to be journaled
+    fn is_journaling(&self) -> bool {
+        Arc::strong_count(&self.snapshots) > 1
+    }
+
+    /// Journal the current binding of `name` before it changes
+    fn record_change(&mut self, name: &str) {
+        if !self.is_journaling() {
+            self.journal.clear();
+            return;
+        }
+
+        let previous = self
+            .variables
+            .get(name)
+            .cloned()
+            .map(Binding::Json)
+            .or_else(|| self.bytes_variables.get(name).cloned().map(Binding::Bytes))
+            .or_else(|| self.value_variables.get(name).cloned().map(Binding::Value))
+            .or_else(|| self.borrowed_variables.get(name).copied().map(Binding::Borrowed));
+        self.journal.push(Undo {
+            serial: self.next_serial,
+            name: intern(name),
+            previous,
+        });
+        self.next_serial += 1;
+    }
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The segments of a dotted variable path, none of which may be empty
+fn path_segments(path: &str) -> Result<Vec<&str>, Error> {
+    let segments = path.split('.').collect::<Vec<_>>();
+    if segments.iter().any(|segment| segment.is_empty()) {
+        return Err(Error::InvalidArgument(format!("Invalid variable path '{path}'")));
+    }
+    Ok(segments)
+}
+
+fn empty_map() -> Value {
+    Value::Map(Map {
+        map: Arc::new(HashMap::new()),
+    })
+}
+
+/// Insert `value` into the map `node`, bound at `prefix`, under the keys `segments`,
+/// creating the maps between them
+fn insert_at_path(
+    node: &mut Value,
+    prefix: &str,
+    segments: &[&str],
+    value: Value,
+) -> Result<(), Error> {
+    let Value::Map(map) = node else {
+        return Err(Error::InvalidArgument(format!("Variable '{prefix}' is not a map")));
+    };
+    let Some((segment, rest)) = segments.split_first() else {
+        return Ok(());
+    };
+    let entries = Arc::make_mut(&mut map.map);
+    let key = Key::String(Arc::new((*segment).to_string()));
+    if rest.is_empty() {
+        entries.insert(key, value);
+        return Ok(());
+    }
+    let child = entries.entry(key).or_insert_with(empty_map);
+    insert_at_path(child, &format!("{prefix}.{segment}"), rest, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Program;
+
+    #[test]
+    fn test_context_new_and_default() {
+        let ctx1 = Context::new();
+        let ctx2 = Context::default();
+
+        assert_eq!(ctx1.variables.len(), 0);
+        assert_eq!(ctx2.variables.len(), 0);
+    }
+
+    #[test]
+    fn test_context_add_variable_direct() {
+        let mut context = Context::new();
+        assert_eq!(context.variables.len(), 0);
+
+        context.add_variable("test".to_string(), serde_json::Value::Bool(true));
+        assert_eq!(context.variables.len(), 1);
+        assert_eq!(context.variables.get("test"), Some(&serde_json::Value::Bool(true)));
+    }
+
+    #[test]
+    fn test_context_reset_direct() {
+        let mut context = Context::new();
+        context.add_variable("test1".to_string(), serde_json::Value::Bool(true));
+        context.add_variable("test2".to_string(), serde_json::Value::String("hello".to_string()));
+        assert_eq!(context.variables.len(), 2);
+
+        context.reset();
+        assert_eq!(context.variables.len(), 0);
+    }
+
+    #[test]
+    fn test_context_get_variables() {
+        let mut context = Context::new();
+        context
+            .add_variable("x".to_string(), serde_json::Value::Number(serde_json::Number::from(42)));
+
+        let vars = context.get_variables();
+        assert_eq!(vars.len(), 1);
+        assert!(vars.contains_key("x"));
+    }
+
+    #[test]
+    fn test_context_add_variable_string() {
+        let mut context = Context::new();
+        let test_string = "Hello, CEL!";
+
+        // Test adding string variable
+        let json_value = serde_json::Value::String(test_string.to_string());
+        context.add_variable("greeting".to_string(), json_value);
+
+        let variables = context.get_variables();
+        assert_eq!(variables.len(), 1);
+        assert!(variables.contains_key("greeting"));
+
+        if let Some(value) = variables.get("greeting") {
+            assert_eq!(value.as_str().unwrap(), test_string);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::indexing_slicing)]
+    fn test_context_add_variable_array() {
+        let mut context = Context::new();
+        let array_value = serde_json::json!([1, 2, 3, "four", true]);
+
+        context.add_variable("items".to_string(), array_value);
+
+        let variables = context.get_variables();
+        assert_eq!(variables.len(), 1);
+        assert!(variables.contains_key("items"));
+
+        if let Some(value) = variables.get("items") {
+            assert!(value.is_array());
+            let arr = value.as_array().unwrap();
+            assert_eq!(arr.len(), 5);
+            assert_eq!(arr[0], 1);
+            assert_eq!(arr[3], "four");
+        }
+    }
+
+    #[test]
+    fn test_context_add_variable_object() {
+        let mut context = Context::new();
+        let object_value = serde_json::json!({
+            "name": "John Doe",
+            "age": 30,
+            "active": true,
+            "score": 95.5
+        });
+
+        context.add_variable("user".to_string(), object_value);
+
+        let variables = context.get_variables();
+        assert_eq!(variables.len(), 1);
+        assert!(variables.contains_key("user"));
+
+        if let Some(value) = variables.get("user") {
+            assert!(value.is_object());
+            let obj = value.as_object().unwrap();
+            assert_eq!(obj["name"], "John Doe");
+            assert_eq!(obj["age"], 30);
+            assert_eq!(obj["active"], true);
+            assert_eq!(obj["score"], 95.5);
+        }
+    }
+
+    #[test]
+    fn test_context_add_variable_nested_object() {
+        let mut context = Context::new();
+        let nested_object = serde_json::json!({
+            "user": {
+                "profile": {
+                    "name": "Alice",
+                    "preferences": {
+                        "theme": "dark",
+                        "notifications": true
+                    }
+                },
+                "permissions": ["read", "write"]
+            }
+        });
+
+        context.add_variable("data".to_string(), nested_object);
+
+        let variables = context.get_variables();
+        assert_eq!(variables.len(), 1);
+
+        if let Some(value) = variables.get("data") {
+            assert!(
+                value.get("user").and_then(|u| u.get("profile")).and_then(|p| p.get("name"))
+                    == Some(&serde_json::Value::String("Alice".to_string()))
+            );
+            assert!(
+                value
+                    .get("user")
+                    .and_then(|u| u.get("profile"))
+                    .and_then(|p| p.get("preferences"))
+                    .and_then(|pref| pref.get("theme"))
+                    == Some(&serde_json::Value::String("dark".to_string()))
+            );
+            assert!(value
+                .get("user")
+                .and_then(|u| u.get("permissions"))
+                .is_some_and(serde_json::Value::is_array));
+        }
+    }
+
+    #[test]
+    fn test_context_variable_types_edge_cases() {
+        let mut context = Context::new(); // Test various numeric edge cases
+        context.add_variable(
+            "max_i64".to_string(),
+            serde_json::Value::Number(serde_json::Number::from(i64::MAX)),
+        );
+        context.add_variable(
+            "min_i64".to_string(),
+            serde_json::Value::Number(serde_json::Number::from(i64::MIN)),
+        );
+        context.add_variable(
+            "zero".to_string(),
+            serde_json::Value::Number(serde_json::Number::from(0)),
+        );
+        context.add_variable("float_val".to_string(), serde_json::json!(f64::MAX)); // Close to f64::MAX
+        context.add_variable(
+            "small_float".to_string(),
+            serde_json::json!(2.225_073_858_507_201_4e-308),
+        ); // Close to f64::MIN_POSITIVE
+
+        // Test empty collections
+        context.add_variable("empty_array".to_string(), serde_json::json!([]));
+        context.add_variable("empty_object".to_string(), serde_json::json!({}));
+
+        // Test null value
+        context.add_variable("null_value".to_string(), serde_json::Value::Null);
+
+        let variables = context.get_variables();
+        assert_eq!(variables.len(), 8);
+
+        assert_eq!(
+            variables.get("max_i64").unwrap(),
+            &serde_json::Value::Number(serde_json::Number::from(i64::MAX))
+        );
+        assert_eq!(
+            variables.get("min_i64").unwrap(),
+            &serde_json::Value::Number(serde_json::Number::from(i64::MIN))
+        );
+        assert_eq!(
+            variables.get("zero").unwrap(),
+            &serde_json::Value::Number(serde_json::Number::from(0))
+        );
+        assert!(variables.get("empty_array").unwrap().as_array().unwrap().is_empty());
+        assert!(variables.get("empty_object").unwrap().as_object().unwrap().is_empty());
+        assert!(variables.get("null_value").unwrap().is_null());
+    }
+
+    #[test]
+    fn test_context_unicode_variable_names() {
+        let mut context = Context::new();
+
+        // Test Unicode variable names
+        context.add_variable("变量".to_string(), serde_json::json!(42));
+        context.add_variable("переменная".to_string(), serde_json::json!("hello"));
+        context.add_variable("変数".to_string(), serde_json::json!(true));
+        context.add_variable("متغير".to_string(), serde_json::json!(std::f64::consts::PI));
+
+        let variables = context.get_variables();
+        assert_eq!(variables.len(), 4);
+
+        assert_eq!(
+            variables.get("变量").unwrap(),
+            &serde_json::Value::Number(serde_json::Number::from(42))
+        );
+        assert_eq!(
+            variables.get("переменная").unwrap(),
+            &serde_json::Value::String("hello".to_string())
+        );
+        assert_eq!(variables.get("変数").unwrap(), &serde_json::Value::Bool(true));
+        assert_eq!(
+            variables.get("متغير").unwrap(),
+            &serde_json::Value::Number(serde_json::Number::from_f64(std::f64::consts::PI).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_context_large_number_of_variables() {
+        let mut context = Context::new();
+
+        // Add many variables
+        for i in 0..1000 {
+            let name = format!("var_{i}");
+            let value = serde_json::json!({
+                "index": i,
+                "even": i % 2 == 0,
+                "name": format!("Variable number {}", i)
+            });
+            context.add_variable(name, value);
+        }
+
+        let variables = context.get_variables();
+        assert_eq!(variables.len(), 1000);
+
+        // Verify some random variables
+        assert_eq!(
+            variables.get("var_0").unwrap().get("index").unwrap(),
+            &serde_json::Value::Number(serde_json::Number::from(0))
+        );
+        assert_eq!(
+            variables.get("var_0").unwrap().get("even").unwrap(),
+            &serde_json::Value::Bool(true)
+        );
+        assert_eq!(
+            variables.get("var_500").unwrap().get("index").unwrap(),
+            &serde_json::Value::Number(serde_json::Number::from(500))
+        );
+        assert_eq!(
+            variables.get("var_500").unwrap().get("even").unwrap(),
+            &serde_json::Value::Bool(true)
+        );
+        assert_eq!(
+            variables.get("var_999").unwrap().get("index").unwrap(),
+            &serde_json::Value::Number(serde_json::Number::from(999))
+        );
+        assert_eq!(
+            variables.get("var_999").unwrap().get("even").unwrap(),
+            &serde_json::Value::Bool(false)
+        );
+    }
+
+    #[test]
+    fn test_context_add_variable_path_builds_maps() {
+        let mut context = Context::new();
+        context
+            .add_variable_path("request.headers.authorization", serde_json::json!("Bearer x"))
+            .unwrap();
+        context.add_variable_path("request.method", serde_json::json!("GET")).unwrap();
+        context.add_variable_path("top", serde_json::json!(1)).unwrap();
+
+        assert_eq!(
+            context.get_variables().get("request"),
+            Some(&serde_json::json!({
+                "headers": {"authorization": "Bearer x"},
+                "method": "GET"
+            }))
+        );
+        assert_eq!(context.get_variables().get("top"), Some(&serde_json::json!(1)));
+
+        let program = Program::compile(
+            "request.headers.authorization.startsWith('Bearer') && request.method == 'GET'",
+        )
+        .unwrap();
+        assert_eq!(program.execute(&context).unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn test_context_add_value_variable_path_keeps_uints() {
+        let mut context = Context::new();
+        context.add_variable_path("limits.name", serde_json::json!("quota")).unwrap();
+        context.add_value_variable_path("limits.max", Value::UInt(u64::MAX)).unwrap();
+        context.add_value_variable_path("limits.nested.one", Value::UInt(1)).unwrap();
+        context.add_variable_path("limits.nested.two", serde_json::json!(2)).unwrap();
+        context.add_variable("json".to_string(), serde_json::json!([u64::MAX]));
+        assert_eq!(context.variable_type("limits"), Some(CelValueType::Map));
+
+        let program = Program::compile(
+            "limits.name == 'quota' && limits.max == 18446744073709551615u \
+             && type(limits.nested.one) == uint && limits.nested.two == 2 \
+             && json[0] == 18446744073709551615u",
+        )
+        .unwrap();
+        assert_eq!(program.execute(&context).unwrap(), Value::Bool(true));
+
+        let error = context.add_value_variable_path("limits.max.x", Value::UInt(1));
+        assert_eq!(error.unwrap_err().to_string(), "Variable 'limits.max' is not a map");
+        context.add_bytes_variable("raw".to_string(), vec![1]);
+        assert!(context.add_value_variable_path("raw.x", Value::UInt(1)).is_err());
+        assert!(context.add_value_variable_path("limits..x", Value::UInt(1)).is_err());
+    }
+
+    #[test]
+    fn test_context_add_variable_path_conflicts() {
+        let mut context = Context::new();
+        context.add_variable("request".to_string(), serde_json::json!({"method": "GET"}));
+
+        let error = context.add_variable_path("request.method.name", serde_json::json!(1));
+        assert_eq!(error.unwrap_err().to_string(), "Variable 'request.method' is not a map");
+        assert!(context.add_variable_path("request..x", serde_json::json!(1)).is_err());
+        assert!(context.add_variable_path(".x", serde_json::json!(1)).is_err());
+
+        // Nothing was changed by the failed calls
+        assert_eq!(
+            context.get_variables().get("request"),
+            Some(&serde_json::json!({"method": "GET"}))
+        );
+    }
+
+    #[test]
+    fn test_context_snapshot_restore() {
+        let mut context = Context::new();
+        context.add_variable("base".to_string(), serde_json::json!(1));
+        context.add_variable_path("request.method", serde_json::json!("GET")).unwrap();
+        context.add_bytes_variable("raw".to_string(), vec![1]);
+
+        let snapshot = context.snapshot();
+        for round in 0..3 {
+            context.add_variable("base".to_string(), serde_json::json!(round + 10));
+            context.add_variable("extra".to_string(), serde_json::json!(round));
+            context.add_variable_path("request.path", serde_json::json!("/x")).unwrap();
+            context.add_variable("raw".to_string(), serde_json::json!("now a string"));
+            assert!(context.remove_variable("base"));
+            context.restore(&snapshot).unwrap();
+
+            assert_eq!(context.get_variables().get("base"), Some(&serde_json::json!(1)));
+            assert_eq!(
+                context.get_variables().get("request"),
+                Some(&serde_json::json!({"method": "GET"}))
+            );
+            assert_eq!(context.get_bytes_variables().get("raw"), Some(&vec![1]));
+            assert_eq!(context.variable_count(), 3);
+        }
+
+        context.reset();
+        assert_eq!(context.variable_count(), 0);
+        context.restore(&snapshot).unwrap();
+        assert_eq!(context.variable_count(), 3);
+    }
+
+    #[test]
+    fn test_context_snapshot_invalidation() {
+        let mut context = Context::new();
+        let first = context.snapshot();
+        context.add_variable("a".to_string(), serde_json::json!(1));
+        let second = context.snapshot();
+        context.add_variable("b".to_string(), serde_json::json!(2));
+
+        context.restore(&first).unwrap();
+        assert_eq!(context.variable_count(), 0);
+
+        // Refill the journal past the second snapshot's position
+        context.add_variable("c".to_string(), serde_json::json!(3));
+        context.add_variable("d".to_string(), serde_json::json!(4));
+        assert!(context.restore(&second).is_err());
+
+        let other = Context::new().snapshot();
+        assert_eq!(
+            context.restore(&other).unwrap_err().to_string(),
+            "Snapshot was taken from a different context"
+        );
+    }
+
+    #[test]
+    fn test_context_journal_only_while_snapshots_live() {
+        let mut context = Context::new();
+        context.add_variable("a".to_string(), serde_json::json!(1));
+        assert!(context.journal.is_empty());
+
+        let snapshot = context.snapshot();
+        context.add_variable("a".to_string(), serde_json::json!(2));
+        assert_eq!(context.journal.len(), 1);
+
+        drop(snapshot);
+        context.add_variable("a".to_string(), serde_json::json!(3));
+        assert!(context.journal.is_empty());
+    }
+
+    #[test]
+    fn test_context_value_variable() {
+        let mut context = Context::new();
+        let snapshot = context.snapshot();
+        context.add_value_variable("big".to_string(), Value::UInt(u64::MAX));
+        assert_eq!(context.variable_type("big"), Some(CelValueType::Uint));
+        assert_eq!(context.variable_names().collect::<Vec<_>>(), ["big"]);
+
+        let program = Program::compile("big == 18446744073709551615u").unwrap();
+        assert_eq!(program.execute(&context).unwrap(), Value::Bool(true));
+
+        assert!(context.add_variable_path("big.x", serde_json::json!(1)).is_err());
+        context.restore(&snapshot).unwrap();
+        assert_eq!(context.variable_count(), 0);
+    }
+
+    #[test]
+    fn test_context_json_round_trip() {
+        let mut context = Context::new();
+        context.add_variable("user".to_string(), serde_json::json!({"name": "ada", "age": 36}));
+        context.add_bytes_variable("raw".to_string(), vec![0, 0xff]);
+        context.add_value_variable("big".to_string(), Value::UInt(u64::MAX));
+        context.add_value_variable("at".to_string(), timestamp_value(1_700_000_000, 5).unwrap());
+
+        let json = context.to_json().unwrap();
+        let restored = Context::from_json(&json).unwrap();
+        assert_eq!(restored.to_json().unwrap(), json);
+        assert_eq!(restored.variable_type("raw"), Some(CelValueType::Bytes));
+        assert_eq!(restored.variable_type("big"), Some(CelValueType::Uint));
+        assert_eq!(restored.variable_type("at"), Some(CelValueType::Timestamp));
+
+        let program = Program::compile("user.age > 30 && big > 0u && size(raw) == 2").unwrap();
+        assert_eq!(program.execute(&restored).unwrap(), Value::Bool(true));
+
+        assert_eq!(Context::from_json("{}").unwrap().variable_count(), 0);
+        for invalid in [
+            "[]",
+            "{\"variables\": 1}",
+            "{\"other\": {}}",
+            "{\"values\": {\"x\": 1}}",
+        ] {
+            assert!(Context::from_json(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_context_json_rejects_functions() {
+        let mut context = Context::new();
+        context.add_value_variable(
+            "f".to_string(),
+            Value::Function(Arc::new("size".to_string()), None),
+        );
+        let error = context.to_json().unwrap_err().to_string();
+        assert!(error.contains("Variable 'f' cannot be serialized"), "{error}");
+    }
+
+    #[test]
+    fn test_context_borrowed_string_variable() {
+        let body = "x".repeat(1 << 20) + "needle";
+        let mut context = Context::new();
+        unsafe {
+            context.add_borrowed_string_variable("body".to_string(), &body, 7);
+            context.add_borrowed_string_variable("other".to_string(), "static", 8);
+        }
+        assert_eq!(context.variable_type("body"), Some(CelValueType::String));
+        assert_eq!(context.get_borrowed_variable("body").map(str::len), Some(body.len()));
+        assert_eq!(context.variable_count(), 2);
+
+        let program = Program::compile("body.contains('needle') && other == 'static'").unwrap();
+        assert_eq!(program.execute(&context).unwrap(), Value::Bool(true));
+        assert!(context.to_json().unwrap().contains(r#""other":"static""#));
+
+        let snapshot = context.snapshot();
+        context.reset();
+        assert_eq!(context.invalidate_borrowed(7), 0);
+        context.restore(&snapshot).unwrap();
+        assert_eq!(context.get_borrowed_variable("other"), Some("static"));
+        assert!(context.get_borrowed_variable("body").is_none());
+
+        assert_eq!(context.invalidate_borrowed(8), 1);
+        assert_eq!(context.variable_count(), 0);
+    }
+}
@@ -0,0 +1,330 @@
+//! Decimals: exact base-10 numbers for money and other amounts doubles would round
+//!
+//! A decimal is a 128-bit integer scaled down by a power of ten, with up to 28 decimal
+//! places, and keeps the places it was written with: `decimal('1.10')` prints as
+//! `1.10`. Like big integers, decimals are carried as tagged function values holding
+//! their text, built in expressions with `decimal()` and by hosts as
+//! `CelValueType::Decimal`. Arithmetic and comparisons on them go through the
+//! functions of `numeric` and need `Context::set_numeric_promotion`.
+
+use super::bigint::as_i128;
+use super::value::{decimal_parts, decimal_value, parse_decimal, value_type_of};
+use super::Value;
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::{Context as CelContext, ExecutionError};
+use std::cmp::Ordering;
+use std::fmt;
+
+const DECIMAL: &str = "decimal";
+
+/// The most decimal places a decimal holds
+pub(crate) const MAX_SCALE: u32 = 28;
+
+/// `mantissa / 10^scale`, with `scale` at most `MAX_SCALE`
+///
+/// Equal numbers written with different places, such as `1.1` and `1.10`, are
+/// distinct values that `compare` orders as equal.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+impl Decimal {
+    pub(crate) const fn integer(n: i128) -> Self {
+        Self {
+            mantissa: n,
+            scale: 0,
+        }
+    }
+
+    /// Parse an optionally signed number in plain decimal notation, such as `-12.50`
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        let trimmed = text.trim();
+        let (negative, unsigned) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+        };
+        let (whole, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+        if (whole.is_empty() && fraction.is_empty())
+            || !whole.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit())
+        {
+            return Err(format!("invalid decimal '{text}'"));
+        }
+        let scale = u32::try_from(fraction.len())
+            .ok()
+            .filter(|scale| *scale <= MAX_SCALE)
+            .ok_or_else(|| format!("decimal '{text}' has more than {MAX_SCALE} places"))?;
+        let sign = if negative { "-" } else { "" };
+        let mantissa = format!("{sign}{whole}{fraction}")
+            .parse()
+            .map_err(|_| format!("decimal '{text}' is out of range"))?;
+        Ok(Self { mantissa, scale })
+    }
+
+    /// The same number with `scale` places, which must be at least the current ones
+    fn with_scale(self, scale: u32) -> Option<Self> {
+        let factor = 10_i128.checked_pow(scale.checked_sub(self.scale)?)?;
+        Some(Self {
+            mantissa: self.mantissa.checked_mul(factor)?,
+            scale,
+        })
+    }
+
+    /// Both numbers with the places of whichever has more
+    fn aligned(self, other: Self) -> Option<(i128, i128, u32)> {
+        let scale = self.scale.max(other.scale);
+        Some((self.with_scale(scale)?.mantissa, other.with_scale(scale)?.mantissa, scale))
+    }
+
+    fn checked_add(self, other: Self) -> Option<Self> {
+        let (left, right, scale) = self.aligned(other)?;
+        Some(Self {
+            mantissa: left.checked_add(right)?,
+            scale,
+        })
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        let (left, right, scale) = self.aligned(other)?;
+        Some(Self {
+            mantissa: left.checked_sub(right)?,
+            scale,
+        })
+    }
+
+    /// The product, rounded half to even when it has more than `MAX_SCALE` places
+    fn checked_mul(self, other: Self) -> Option<Self> {
+        let product = Self {
+            mantissa: self.mantissa.checked_mul(other.mantissa)?,
+            scale: self.scale + other.scale,
+        };
+        if product.scale <= MAX_SCALE {
+            return Some(product);
+        }
+        let divisor = 10_u128.pow(product.scale - MAX_SCALE);
+        let magnitude = product.mantissa.unsigned_abs();
+        let rounded = round_half_even(magnitude / divisor, magnitude % divisor, divisor)?;
+        Self::signed(rounded, product.mantissa < 0, MAX_SCALE)
+    }
+
+    /// The quotient to as many places as it needs, up to `MAX_SCALE` places rounded
+    /// half to even, without trailing zeros
+    fn checked_div(self, other: Self) -> Option<Self> {
+        if other.mantissa == 0 {
+            return None;
+        }
+        let divisor = other.mantissa.unsigned_abs();
+        let dividend = self.mantissa.unsigned_abs();
+        let (mut quotient, mut remainder) = (dividend / divisor, dividend % divisor);
+        let mut scale = i64::from(self.scale) - i64::from(other.scale);
+        while scale < 0 || (remainder != 0 && scale < i64::from(MAX_SCALE)) {
+            remainder = remainder.checked_mul(10)?;
+            quotient = quotient.checked_mul(10)?.checked_add(remainder / divisor)?;
+            remainder %= divisor;
+            scale += 1;
+        }
+        let quotient = round_half_even(quotient, remainder, divisor)?;
+        let negative = (self.mantissa < 0) != (other.mantissa < 0);
+        Self::signed(quotient, negative, u32::try_from(scale).ok()?).map(Self::trimmed)
+    }
+
+    fn checked_rem(self, other: Self) -> Option<Self> {
+        let (left, right, scale) = self.aligned(other)?;
+        Some(Self {
+            mantissa: left.checked_rem(right)?,
+            scale,
+        })
+    }
+
+    fn signed(magnitude: u128, negative: bool, scale: u32) -> Option<Self> {
+        let mantissa = i128::try_from(magnitude).ok()?;
+        Some(Self {
+            mantissa: if negative { -mantissa } else { mantissa },
+            scale,
+        })
+    }
+
+    /// The same number without trailing zeros after the point
+    fn trimmed(mut self) -> Self {
+        while self.scale > 0 && self.mantissa % 10 == 0 {
+            self.mantissa /= 10;
+            self.scale -= 1;
+        }
+        self
+    }
+
+    /// The numeric order of two decimals, whatever their places
+    pub(crate) fn compare(self, other: Self) -> Ordering {
+        // Whole parts first, then fractions scaled to the same places, so nothing
+        // overflows
+        let split = |d: Self| {
+            let unit = 10_i128.pow(d.scale);
+            (d.mantissa.div_euclid(unit), d.mantissa.rem_euclid(unit))
+        };
+        let ((left_whole, left_fraction), (right_whole, right_fraction)) =
+            (split(self), split(other));
+        let scale = self.scale.max(other.scale);
+        left_whole.cmp(&right_whole).then_with(|| {
+            let left_fraction = left_fraction * 10_i128.pow(scale - self.scale);
+            left_fraction.cmp(&(right_fraction * 10_i128.pow(scale - other.scale)))
+        })
+    }
+
+    /// The nearest double
+    pub(crate) fn to_f64(self) -> f64 {
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    /// The integer this is, whatever its places, or `None` if it has a fraction
+    pub(crate) fn to_integer(self) -> Option<i128> {
+        let trimmed = self.trimmed();
+        (trimmed.scale == 0).then_some(trimmed.mantissa)
+    }
+}
+
+/// `quotient` rounded half to even, given the `remainder` left over from `divisor`
+fn round_half_even(quotient: u128, remainder: u128, divisor: u128) -> Option<u128> {
+    let round_up = match (remainder * 2).cmp(&divisor) {
+        Ordering::Greater => true,
+        Ordering::Equal => quotient % 2 == 1,
+        Ordering::Less => false,
+    };
+    if round_up {
+        quotient.checked_add(1)
+    } else {
+        Some(quotient)
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        let magnitude = self.mantissa.unsigned_abs();
+        if self.scale == 0 {
+            return write!(f, "{sign}{magnitude}");
+        }
+        let unit = 10_u128.pow(self.scale);
+        let places = self.scale as usize;
+        write!(f, "{sign}{}.{:0places$}", magnitude / unit, magnitude % unit)
+    }
+}
+
+/// Add `decimal()`, converting an int, uint, big integer, decimal string or decimal,
+/// to `ctx`
+pub(crate) fn register(ctx: &mut CelContext) {
+    ctx.add_function(DECIMAL, |Arguments(args): Arguments| match args.as_slice() {
+        [Value::String(text)] => {
+            parse_decimal(text).map_err(|e| ExecutionError::function_error(DECIMAL, e))
+        }
+        [value] => as_decimal(value).map(decimal_value).ok_or_else(|| {
+            ExecutionError::function_error(
+                DECIMAL,
+                format!("cannot convert {:?} to decimal", value_type_of(value)),
+            )
+        }),
+        _ => Err(ExecutionError::function_error(DECIMAL, "expected one argument")),
+    });
+}
+
+/// The decimal an int, uint, big integer or decimal holds, or `None` for any other
+/// value
+pub(crate) fn as_decimal(value: &Value) -> Option<Decimal> {
+    decimal_parts(value).or_else(|| as_i128(value).map(Decimal::integer))
+}
+
+/// `left op right` as a decimal, for the arithmetic operator written `op`
+pub(crate) fn arithmetic(op: &str, left: Decimal, right: Decimal) -> Result<Value, ExecutionError> {
+    let result = match op {
+        "+" => left.checked_add(right),
+        "-" => left.checked_sub(right),
+        "*" => left.checked_mul(right),
+        "/" => left.checked_div(right),
+        "%" => left.checked_rem(right),
+        op => {
+            return Err(ExecutionError::function_error(DECIMAL, format!("unknown operator {op}")))
+        }
+    };
+    result.map(decimal_value).ok_or_else(|| {
+        let reason = if right.mantissa == 0 && matches!(op, "/" | "%") {
+            "division by zero"
+        } else {
+            "overflow"
+        };
+        ExecutionError::function_error(DECIMAL, format!("{reason} in {left} {op} {right}"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Context, Error, Program};
+
+    fn decimal(text: &str) -> Decimal {
+        Decimal::parse(text).unwrap()
+    }
+
+    fn execute(expression: &str) -> Result<Value, Error> {
+        let mut context = Context::new();
+        context.set_numeric_promotion(true);
+        Program::compile(expression).unwrap().execute(&context)
+    }
+
+    #[test]
+    fn test_decimal_parse_and_display() {
+        for text in [
+            "0",
+            "1.10",
+            "-0.05",
+            "123456789.000000001",
+            "-170141183460469231731687303715884105728",
+        ] {
+            assert_eq!(decimal(text).to_string(), text);
+        }
+        assert_eq!(decimal("+.5").to_string(), "0.5");
+        assert_eq!(decimal("7.").to_string(), "7");
+        for text in [
+            "",
+            ".",
+            "1e3",
+            "1.2.3",
+            "--1",
+            "0.00000000000000000000000000001",
+        ] {
+            assert!(Decimal::parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn test_decimal_operations() {
+        let text = |result: Option<Decimal>| result.map(|d| d.to_string());
+        assert_eq!(text(decimal("0.1").checked_add(decimal("0.2"))), Some("0.3".to_string()));
+        assert_eq!(text(decimal("1.10").checked_sub(decimal("2"))), Some("-0.90".to_string()));
+        assert_eq!(text(decimal("19.99").checked_mul(decimal("3"))), Some("59.97".to_string()));
+        assert_eq!(text(decimal("1").checked_div(decimal("4"))), Some("0.25".to_string()));
+        assert_eq!(
+            text(decimal("-2").checked_div(decimal("3"))),
+            Some("-0.6666666666666666666666666667".to_string())
+        );
+        assert_eq!(text(decimal("100").checked_div(decimal("0.5"))), Some("200".to_string()));
+        assert_eq!(text(decimal("7.5").checked_rem(decimal("2"))), Some("1.5".to_string()));
+        assert_eq!(text(decimal("1").checked_div(decimal("0"))), None);
+
+        assert_eq!(decimal("1.10").compare(decimal("1.1")), Ordering::Equal);
+        assert_eq!(decimal("-0.5").compare(decimal("-1")), Ordering::Greater);
+        assert_eq!(decimal("2.05").compare(decimal("2.5")), Ordering::Less);
+    }
+
+    #[test]
+    fn test_decimal_expressions() {
+        assert_eq!(
+            execute("decimal('0.1') + decimal('0.2') == decimal('0.3')"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(execute("decimal('19.99') * 3"), Ok(decimal_value(decimal("59.97"))));
+        assert_eq!(execute("decimal('2.50') > 2u && decimal(2) == 2"), Ok(Value::Bool(true)));
+        assert_eq!(execute("decimal('0.5') + 0.25"), Ok(Value::Float(0.75)));
+        assert!(matches!(execute("decimal('1') / 0"), Err(Error::Execution(_))));
+        assert!(matches!(execute("decimal(true)"), Err(Error::Execution(_))));
+    }
+}
@@ -0,0 +1,195 @@
+//! Field paths an expression reads, for hosts that prefetch only the data rules touch
+//!
+//! A path starts at a variable and follows field selections, such as
+//! `request.headers.authorization`, or `request.headers["x-id"]` for keys that are not
+//! identifiers. Indexing with anything but a string literal
+//! appears as `[*]`, as does iterating a list in a comprehension, so
+//! `user.roles.exists(r, r.name == 'admin')` reads `user.roles[*].name`.
+
+use super::options::COMPREHENSIONS;
+use cel_parser::{Atom, Expression, Member};
+use std::collections::BTreeSet;
+
+/// Every path `expression` reads, sorted
+pub(crate) fn dependency_paths(expression: &Expression) -> BTreeSet<String> {
+    let mut walker = Walker::default();
+    walker.visit(expression);
+    walker.paths
+}
+
+/// A comprehension variable and the path of the elements it iterates, if known
+struct Binding<'a> {
+    name: &'a str,
+    elements: Option<String>,
+    used: bool,
+}
+
+#[derive(Default)]
+struct Walker<'a> {
+    scopes: Vec<Binding<'a>>,
+    paths: BTreeSet<String>,
+}
+
+impl<'a> Walker<'a> {
+    fn visit(&mut self, expression: &'a Expression) {
+        if let Some((path, indexes)) = self.path(expression) {
+            self.paths.insert(path);
+            indexes.into_iter().for_each(|index| self.visit(index));
+            return;
+        }
+
+        match expression {
+            Expression::Arithmetic(left, _, right)
+            | Expression::Relation(left, _, right)
+            | Expression::Or(left, right)
+            | Expression::And(left, right) => {
+                self.visit(left);
+                self.visit(right);
+            }
+            Expression::Ternary(condition, then, otherwise) => {
+                self.visit(condition);
+                self.visit(then);
+                self.visit(otherwise);
+            }
+            Expression::Unary(_, operand) => self.visit(operand),
+            Expression::Member(target, member) => match member.as_ref() {
+                Member::Attribute(_) => self.visit(target),
+                Member::Index(index) => {
+                    self.visit(target);
+                    self.visit(index);
+                }
+                Member::Fields(fields) => fields.iter().for_each(|(_, value)| self.visit(value)),
+            },
+            Expression::FunctionCall(function, target, args) => {
+                let comprehension = match (function.as_ref(), target, args.first()) {
+                    (Expression::Ident(name), Some(target), Some(Expression::Ident(var)))
+                        if COMPREHENSIONS.contains(&name.as_str()) =>
+                    {
+                        Some((target, var))
+                    }
+                    _ => None,
+                };
+                match comprehension {
+                    Some((target, var)) => self.comprehension(target, var, &args[1..]),
+                    None => {
+                        if let Some(target) = target {
+                            self.visit(target);
+                        }
+                        args.iter().for_each(|arg| self.visit(arg));
+                    }
+                }
+            }
+            Expression::List(items) => items.iter().for_each(|item| self.visit(item)),
+            Expression::Map(entries) => entries.iter().for_each(|(key, value)| {
+                self.visit(key);
+                self.visit(value);
+            }),
+            Expression::Ident(_) | Expression::Atom(_) => {}
+        }
+    }
+
+    /// Visit a comprehension over `range` whose `body` binds `var` to each element
+    fn comprehension(&mut self, range: &'a Expression, var: &'a str, body: &'a [Expression]) {
+        let range_path = self.path(range).map(|(path, indexes)| {
+            indexes.into_iter().for_each(|index| self.visit(index));
+            path
+        });
+        if range_path.is_none() {
+            self.visit(range);
+        }
+
+        self.scopes.push(Binding {
+            name: var,
+            elements: range_path.as_ref().map(|path| format!("{path}[*]")),
+            used: false,
+        });
+        body.iter().for_each(|expression| self.visit(expression));
+        let binding = self.scopes.pop().expect("pushed above");
+
+        // A range whose elements are never read is still read as a whole
+        if let (Some(path), false) = (range_path, binding.used) {
+            self.paths.insert(path);
+        }
+    }
+
+    /// The path `expression` reads if it is a chain of selections from a variable,
+    /// with the index expressions the chain evaluates along the way
+    fn path(&mut self, expression: &'a Expression) -> Option<(String, Vec<&'a Expression>)> {
+        match expression {
+            Expression::Ident(name) => {
+                let binding = self.scopes.iter_mut().rev().find(|b| b.name == name.as_str());
+                match binding {
+                    Some(binding) => {
+                        binding.used = true;
+                        binding.elements.clone().map(|path| (path, Vec::new()))
+                    }
+                    None => Some((name.to_string(), Vec::new())),
+                }
+            }
+            Expression::Member(target, member) => match member.as_ref() {
+                Member::Attribute(field) => {
+                    let (path, indexes) = self.path(target)?;
+                    Some((format!("{path}.{field}"), indexes))
+                }
+                Member::Index(index) => {
+                    let (path, mut indexes) = self.path(target)?;
+                    if let Expression::Atom(Atom::String(key)) = index.as_ref() {
+                        return Some((select(&path, key), indexes));
+                    }
+                    indexes.push(index);
+                    Some((format!("{path}[*]"), indexes))
+                }
+                // A message construction, whose target is a type name
+                Member::Fields(_) => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// `path` with `key` selected, in brackets unless the key is an identifier
+fn select(path: &str, key: &str) -> String {
+    let identifier = key.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if identifier {
+        format!("{path}.{key}")
+    } else {
+        format!("{path}[{key:?}]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(expression: &str) -> Vec<String> {
+        dependency_paths(&cel_parser::parse(expression).unwrap()).into_iter().collect()
+    }
+
+    #[test]
+    fn test_field_paths() {
+        assert_eq!(
+            paths("request.headers.authorization != '' && user.age >= limits['adult']"),
+            ["limits.adult", "request.headers.authorization", "user.age"]
+        );
+        assert_eq!(paths("a.items[i].price > 1 && size(b)"), ["a.items[*].price", "b", "i"]);
+        assert_eq!(paths("has(user.email) ? user.email : 'none'"), ["user.email"]);
+        assert_eq!(paths("Point{x: p.x}.x"), ["p.x"]);
+        assert_eq!(paths("headers['x-id'] + headers['_a1']"), ["headers._a1", "headers[\"x-id\"]"]);
+    }
+
+    #[test]
+    fn test_comprehension_paths() {
+        assert_eq!(
+            paths("user.roles.exists(r, r.name == 'admin' && r.scope in allowed)"),
+            ["allowed", "user.roles[*].name", "user.roles[*].scope"]
+        );
+        assert_eq!(paths("xs.all(x, x > 0)"), ["xs[*]"]);
+        assert_eq!(paths("xs.map(x, 1)"), ["xs"]);
+        assert_eq!(paths("[1, 2].all(x, x.y > n)"), ["n"]);
+        assert_eq!(
+            paths("groups.all(g, g.members.exists(m, m.id == me.id))"),
+            ["groups[*].members[*].id", "me.id"]
+        );
+    }
+}
@@ -0,0 +1,123 @@
+//! Map iteration in a fixed order, for deterministic evaluation
+//!
+//! Maps are hash maps, so a comprehension over one visits its keys in an order that
+//! changes from run to run, and `m.map(k, k)` or the error an `all` stops at changes
+//! with it. When a context asks for deterministic evaluation the program is rewritten
+//! so the range of every `all`, `exists`, `exists_one`, `map` and `filter` goes through
+//! a function that turns a map into the list of its keys in `sorted_entries` order,
+//! and passes anything else through unchanged.
+
+use super::options::COMPREHENSIONS;
+use super::value::{key_value, sorted_entries};
+use super::{Context, Value};
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::{Context as CelContext, ExecutionError};
+use cel_parser::{Expression, Member};
+use std::sync::Arc;
+
+const SORTED: &str = "_sorted_range";
+
+/// Add the function of `with_sorted_ranges` to `ctx`, if `context` asks for
+/// deterministic evaluation
+pub(crate) fn register(ctx: &mut CelContext, context: &Context) {
+    if !context.deterministic() {
+        return;
+    }
+    ctx.add_function(SORTED, |Arguments(args): Arguments| {
+        let [range] = args.as_slice() else {
+            return Err(ExecutionError::function_error(SORTED, "expected one argument"));
+        };
+        Ok(match range {
+            Value::Map(map) => Value::List(Arc::new(
+                sorted_entries(map).into_iter().map(|(key, _)| key_value(key)).collect(),
+            )),
+            _ => range.clone(),
+        })
+    });
+}
+
+/// `expression` with the range of every comprehension iterated in a fixed order
+pub(crate) fn with_sorted_ranges(expression: &Expression) -> Expression {
+    let recurse = |expression: &Expression| with_sorted_ranges(expression);
+    let boxed = |expression: &Expression| Box::new(recurse(expression));
+
+    match expression {
+        Expression::FunctionCall(function, Some(range), args)
+            if matches!(function.as_ref(), Expression::Ident(name)
+                if COMPREHENSIONS.contains(&name.as_str())) =>
+        {
+            let range = Expression::FunctionCall(
+                Expression::Ident(Arc::new(SORTED.to_string())).into(),
+                None,
+                vec![recurse(range)],
+            );
+            Expression::FunctionCall(
+                function.clone(),
+                Some(Box::new(range)),
+                args.iter().map(recurse).collect(),
+            )
+        }
+        Expression::Arithmetic(left, op, right) => {
+            Expression::Arithmetic(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Relation(left, op, right) => {
+            Expression::Relation(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Ternary(condition, if_true, if_false) => {
+            Expression::Ternary(boxed(condition), boxed(if_true), boxed(if_false))
+        }
+        Expression::Or(left, right) => Expression::Or(boxed(left), boxed(right)),
+        Expression::And(left, right) => Expression::And(boxed(left), boxed(right)),
+        Expression::Unary(op, operand) => Expression::Unary(op.clone(), boxed(operand)),
+        Expression::Member(operand, member) => {
+            let member = match member.as_ref() {
+                Member::Attribute(name) => Member::Attribute(name.clone()),
+                Member::Index(index) => Member::Index(boxed(index)),
+                Member::Fields(fields) => Member::Fields(
+                    fields.iter().map(|(name, value)| (name.clone(), recurse(value))).collect(),
+                ),
+            };
+            Expression::Member(boxed(operand), member.into())
+        }
+        Expression::FunctionCall(function, target, args) => Expression::FunctionCall(
+            function.clone(),
+            target.as_deref().map(boxed),
+            args.iter().map(recurse).collect(),
+        ),
+        Expression::List(items) => Expression::List(items.iter().map(recurse).collect()),
+        Expression::Map(entries) => Expression::Map(
+            entries.iter().map(|(key, value)| (recurse(key), recurse(value))).collect(),
+        ),
+        Expression::Atom(_) | Expression::Ident(_) => expression.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{Context, Error, Program, Value};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_deterministic_evaluation() {
+        let mut context = Context::new();
+        let keys: serde_json::Map<_, _> = (0..32).map(|i| (format!("k{i:02}"), json!(i))).collect();
+        context.add_variable("m".to_string(), serde_json::Value::Object(keys));
+        context.set_deterministic(true);
+        let execute = |expression: &str| Program::compile(expression).unwrap().execute(&context);
+
+        let expected: Vec<Value> =
+            (0..32).map(|i| Value::String(Arc::new(format!("k{i:02}")))).collect();
+        assert_eq!(execute("m.map(k, k)"), Ok(Value::List(Arc::new(expected))));
+        assert_eq!(execute("m.filter(k, m[k] > 29)"), execute("['k30', 'k31']"));
+        assert_eq!(execute("[3, 1].map(x, x * 2)"), execute("[6, 2]"));
+
+        assert!(matches!(
+            execute("now() > timestamp('2000-01-01T00:00:00Z')"),
+            Err(Error::Execution(_))
+        ));
+        context.set_now(1_700_000_000, 0).unwrap();
+        let execute = |expression: &str| Program::compile(expression).unwrap().execute(&context);
+        assert_eq!(execute("now() == timestamp('2023-11-14T22:13:20Z')"), Ok(Value::Bool(true)));
+    }
+}
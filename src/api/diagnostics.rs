@@ -0,0 +1,339 @@
+//! Validation that reports every problem with an expression at once
+//!
+//! Compiling stops at the first error. `validate_full` reports that error alongside
+//! warnings about expressions that compile but probably do not mean what they say,
+//! such as a comparison whose outcome is fixed.
+
+use super::env::identifier_offset;
+use super::optimize;
+use super::options::ExpressionStats;
+use super::rename::Scanner;
+use super::{CompileError, CompileOptions, Error, Program};
+use cel_parser::{Atom, Expression, Member, RelationOp};
+
+/// Non-standard built-ins, with the extension function that replaces each
+const DEPRECATED_FUNCTIONS: [(&str, &str); 2] = [("max", "math.greatest"), ("min", "math.least")];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The expression cannot be compiled
+    Error,
+    /// The expression compiles but is probably wrong
+    Warning,
+}
+
+impl Severity {
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+/// A problem `validate_full` found, located as a `CompileError` is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
+    pub length: usize,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, error: CompileError) -> Self {
+        Self {
+            severity,
+            message: error.message,
+            line: error.line,
+            column: error.column,
+            offset: error.offset,
+            length: error.length,
+        }
+    }
+}
+
+/// Every error and warning for `expression` compiled with `options`
+///
+/// The compile error, if any, comes first, followed by the warnings in source order:
+/// comparisons that are always true or always false, calls to deprecated functions and
+/// comprehension variables that shadow another variable. Warnings are only reported
+/// for expressions that parse.
+#[must_use]
+pub fn validate_full(expression: &str, options: &CompileOptions) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    if let Err(e) = Program::compile_with_options(expression, options) {
+        let error = match e {
+            Error::Compile(error) => error,
+            e => CompileError::unlocated(expression, e.to_string()),
+        };
+        diagnostics.push(Diagnostic::new(Severity::Error, error));
+    }
+    let Ok(parsed) = cel_parser::parse(expression) else {
+        return diagnostics;
+    };
+
+    let mut warnings = Vec::new();
+    constant_comparisons(expression, &parsed, options, &mut warnings);
+    deprecated_functions(expression, &parsed, &mut warnings);
+    shadowed_variables(expression, &parsed, &mut warnings);
+    warnings.sort_by_key(|warning| (warning.line == 0, warning.offset));
+    diagnostics.extend(warnings.into_iter().map(|w| Diagnostic::new(Severity::Warning, w)));
+    diagnostics
+}
+
+/// Comparisons of identical operands or of literals, whose outcome never changes
+fn constant_comparisons(
+    expression: &str,
+    parsed: &Expression,
+    options: &CompileOptions,
+    warnings: &mut Vec<CompileError>,
+) {
+    let mut relations = Vec::new();
+    relations_in_order(parsed, &mut relations);
+    // The parser keeps no locations, so match the operators by their order in the source
+    let operators = relation_operators(expression);
+    let located = operators.len() == relations.len();
+
+    for (i, relation) in relations.into_iter().enumerate() {
+        let Expression::Relation(left, op, right) = relation else {
+            continue;
+        };
+        let outcome = if left == right {
+            match op {
+                RelationOp::Equals | RelationOp::LessThanEq | RelationOp::GreaterThanEq => {
+                    Some(true)
+                }
+                RelationOp::NotEquals | RelationOp::LessThan | RelationOp::GreaterThan => {
+                    Some(false)
+                }
+                RelationOp::In => None,
+            }
+        } else if optimize::is_literal(left) && optimize::is_literal(right) {
+            match optimize::fold(relation, options.extensions()) {
+                Expression::Atom(Atom::Bool(outcome)) => Some(outcome),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        if let Some(outcome) = outcome {
+            let message = format!("comparison is always {outcome}");
+            warnings.push(if located {
+                let (offset, length) = operators[i];
+                CompileError::at(expression, offset, length, message)
+            } else {
+                CompileError::unlocated(expression, message)
+            });
+        }
+    }
+}
+
+/// Calls to the functions in `DEPRECATED_FUNCTIONS`, one warning per function
+fn deprecated_functions(expression: &str, parsed: &Expression, warnings: &mut Vec<CompileError>) {
+    let mut calls = Vec::new();
+    nodes_in_order(parsed, &mut calls);
+    for (name, replacement) in DEPRECATED_FUNCTIONS {
+        let called = calls.iter().any(|node| {
+            matches!(node, Expression::FunctionCall(function, None, _)
+                if matches!(function.as_ref(), Expression::Ident(ident) if ident.as_str() == name))
+        });
+        if called {
+            let message = format!("function '{name}' is deprecated; use {replacement}");
+            warnings.push(match identifier_offset(expression, name) {
+                Some(offset) => CompileError::at(expression, offset, name.len(), message),
+                None => CompileError::unlocated(expression, message),
+            });
+        }
+    }
+}
+
+/// Comprehension variables named like an enclosing comprehension's or a free variable
+fn shadowed_variables(expression: &str, parsed: &Expression, warnings: &mut Vec<CompileError>) {
+    let free = ExpressionStats::of(parsed, &[]).variables;
+    for (offset, name, nested) in Scanner::new(expression).comprehension_variables() {
+        let shadowed = if nested {
+            "an enclosing comprehension variable"
+        } else if free.contains(name) {
+            "a variable of the same name"
+        } else {
+            continue;
+        };
+        let message = format!("comprehension variable '{name}' shadows {shadowed}");
+        warnings.push(CompileError::at(expression, offset, name.len(), message));
+    }
+}
+
+/// The comparisons in `expression`, in the order their operators appear in the source
+fn relations_in_order<'a>(expression: &'a Expression, found: &mut Vec<&'a Expression>) {
+    let mut nodes = Vec::new();
+    nodes_in_order(expression, &mut nodes);
+    found.extend(nodes.into_iter().filter(|node| matches!(node, Expression::Relation(..))));
+}
+
+/// Every node of `expression`, with binary operators between their operands so the
+/// order follows the source
+fn nodes_in_order<'a>(expression: &'a Expression, found: &mut Vec<&'a Expression>) {
+    match expression {
+        Expression::Arithmetic(left, _, right)
+        | Expression::Relation(left, _, right)
+        | Expression::And(left, right)
+        | Expression::Or(left, right) => {
+            nodes_in_order(left, found);
+            found.push(expression);
+            nodes_in_order(right, found);
+        }
+        Expression::Ternary(condition, if_true, if_false) => {
+            nodes_in_order(condition, found);
+            found.push(expression);
+            nodes_in_order(if_true, found);
+            nodes_in_order(if_false, found);
+        }
+        Expression::Unary(_, operand) => {
+            found.push(expression);
+            nodes_in_order(operand, found);
+        }
+        Expression::Member(operand, member) => {
+            nodes_in_order(operand, found);
+            found.push(expression);
+            match member.as_ref() {
+                Member::Attribute(_) => {}
+                Member::Index(index) => nodes_in_order(index, found),
+                Member::Fields(fields) => {
+                    for (_, value) in fields {
+                        nodes_in_order(value, found);
+                    }
+                }
+            }
+        }
+        Expression::FunctionCall(_, target, args) => {
+            if let Some(target) = target {
+                nodes_in_order(target, found);
+            }
+            found.push(expression);
+            for arg in args {
+                nodes_in_order(arg, found);
+            }
+        }
+        Expression::List(items) => {
+            found.push(expression);
+            for item in items {
+                nodes_in_order(item, found);
+            }
+        }
+        Expression::Map(entries) => {
+            found.push(expression);
+            for (key, value) in entries {
+                nodes_in_order(key, found);
+                nodes_in_order(value, found);
+            }
+        }
+        Expression::Atom(_) | Expression::Ident(_) => found.push(expression),
+    }
+}
+
+/// Offsets and lengths of the comparison operators in `source`, skipping string
+/// literals and comments
+fn relation_operators(source: &str) -> Vec<(usize, usize)> {
+    let scanner = Scanner::new(source);
+    let bytes = source.as_bytes();
+    let mut operators = Vec::new();
+
+    let mut i = scanner.skip_trivia(0);
+    while i < bytes.len() {
+        if let Some(end) = scanner.string_end(i) {
+            i = scanner.skip_trivia(end);
+            continue;
+        }
+        let end = scanner.identifier_end(i);
+        if end > i {
+            if &source[i..end] == "in" {
+                operators.push((i, 2));
+            }
+            i = scanner.skip_trivia(end);
+            continue;
+        }
+
+        let length = match (bytes[i], bytes.get(i + 1)) {
+            (b'=' | b'!' | b'<' | b'>', Some(b'=')) => 2,
+            (b'<' | b'>', _) => 1,
+            _ => 0,
+        };
+        if length > 0 {
+            operators.push((i, length));
+        }
+        i = scanner.skip_trivia(i + length.max(1));
+    }
+    operators
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warnings(expression: &str) -> Vec<(String, usize)> {
+        validate_full(expression, &CompileOptions::default())
+            .into_iter()
+            .map(|d| {
+                assert_eq!(d.severity, Severity::Warning);
+                (d.message, d.column)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_full_constant_comparisons() {
+        assert_eq!(
+            warnings("x > 1 && a.b == a.b || 'a<b' != x || 1 < 2"),
+            [
+                ("comparison is always true".to_string(), 14),
+                ("comparison is always true".to_string(), 40)
+            ]
+        );
+        assert_eq!(warnings("x != x"), [("comparison is always false".to_string(), 3)]);
+        assert_eq!(warnings("x == y && 1 in [1]").len(), 1);
+    }
+
+    #[test]
+    fn test_validate_full_deprecated_and_shadowed() {
+        assert_eq!(
+            warnings("max(a, 1) > 0"),
+            [("function 'max' is deprecated; use math.greatest".to_string(), 1)]
+        );
+        assert_eq!(
+            warnings("xs.all(x, ys.exists(x, x > 0))"),
+            [(
+                "comprehension variable 'x' shadows an enclosing comprehension variable"
+                    .to_string(),
+                21
+            )]
+        );
+        assert_eq!(
+            warnings("x > 0 && xs.all(x, x > 1)"),
+            [("comprehension variable 'x' shadows a variable of the same name".to_string(), 17)]
+        );
+        assert!(warnings("xs.all(x, x > 0) && ys.all(x, x < 0)").is_empty());
+    }
+
+    #[test]
+    fn test_validate_full_errors() {
+        let diagnostics = validate_full("1 + + 2", &CompileOptions::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (1, 5));
+
+        let options = CompileOptions {
+            max_expression_length: 5,
+            ..CompileOptions::default()
+        };
+        let diagnostics = validate_full("x == x", &options);
+        assert_eq!(
+            diagnostics.iter().map(|d| d.severity).collect::<Vec<_>>(),
+            [Severity::Error, Severity::Warning]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Enumerations: symbolic names for ints, registered on an environment
+//!
+//! An enum registered as `Level` is a constant map from member names to ints, so
+//! `level >= Level.INFO` compiles to `level >= 1` and costs nothing at run time.
+//! Selecting a member the enum lacks is a compile error rather than a missing key.
+//! A program told its results are of an enum returns the member name for an int the
+//! enum names, for hosts that want `"INFO"` back rather than `1`.
+
+use super::Value;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// An enumeration registered with `Env::register_enum`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnumType {
+    name: String,
+    members: BTreeMap<String, i64>,
+}
+
+impl EnumType {
+    pub(crate) const fn new(name: String, members: BTreeMap<String, i64>) -> Self {
+        Self { name, members }
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The value of the member `name`, if the enum has one
+    #[must_use]
+    pub fn value(&self, name: &str) -> Option<i64> {
+        self.members.get(name).copied()
+    }
+
+    /// The name of the member with `value`, the first in name order if several share it
+    #[must_use]
+    pub fn member(&self, value: i64) -> Option<&str> {
+        self.members.iter().find(|(_, v)| **v == value).map(|(name, _)| name.as_str())
+    }
+
+    /// Member names and values, in name order
+    pub fn members(&self) -> impl Iterator<Item = (&str, i64)> {
+        self.members.iter().map(|(name, value)| (name.as_str(), *value))
+    }
+
+    /// `value` as the name of a member if it is an int the enum names, and unchanged
+    /// otherwise
+    pub(crate) fn named(&self, value: Value) -> Value {
+        match value {
+            Value::Int(i) => match self.member(i) {
+                Some(name) => Value::String(Arc::new(name.to_string())),
+                None => value,
+            },
+            _ => value,
+        }
+    }
+
+    /// The member a dependency path such as `Level.INFO` selects from the enum, if it
+    /// starts at the enum
+    pub(crate) fn selected<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let rest = path.strip_prefix(self.name.as_str())?.strip_prefix('.')?;
+        Some(rest.split(['.', '[']).next().unwrap_or(rest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{Context, Env, Error, Value};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_enums() {
+        let mut env = Env::default();
+        let levels = [("DEBUG", 0), ("INFO", 1), ("WARN", 2)];
+        env.register_enum("Level", levels.map(|(name, value)| (name.to_string(), value)))
+            .unwrap();
+        let level = env.enum_type("Level").unwrap();
+        assert_eq!(level.value("INFO"), Some(1));
+        assert_eq!(level.member(2), Some("WARN"));
+
+        let mut context = Context::new();
+        context.add_variable("level".to_string(), serde_json::json!(2));
+        let program = env.compile("level >= Level.INFO").unwrap();
+        assert_eq!(program.variables(), ["level"]);
+        assert_eq!(program.execute(&context), Ok(Value::Bool(true)));
+
+        let error = env.compile("level == Level.TRACE").unwrap_err().to_string();
+        assert!(error.contains("enum 'Level' has no member 'TRACE'"), "{error}");
+
+        let mut program = env.compile("level > 1 ? level : Level.DEBUG").unwrap();
+        program.set_result_enum(Some(level.clone()));
+        assert_eq!(program.execute(&context), Ok(Value::String(Arc::new("WARN".to_string()))));
+        context.add_variable("level".to_string(), serde_json::json!(7));
+        assert_eq!(program.execute(&context), Ok(Value::Int(7)));
+
+        let invalid = [("not valid".to_string(), 1)];
+        assert!(matches!(env.register_enum("Level", invalid), Err(Error::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,636 @@
+use super::optimize::{literal, unparse};
+use super::rename::{is_identifier, Scanner};
+use super::value::type_name_of;
+use super::{CompileError, CompileOptions, EnumType, Error, Program, Value};
+use cel_interpreter::objects::{Key, Map};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+/// Functions and macros every program can call
+pub const BUILTIN_FUNCTIONS: [&str; 34] = [
+    "all",
+    "bigint",
+    "bytes",
+    "contains",
+    "decimal",
+    "double",
+    "duration",
+    "endsWith",
+    "exists",
+    "exists_one",
+    "filter",
+    "getDate",
+    "getDayOfMonth",
+    "getDayOfWeek",
+    "getDayOfYear",
+    "getFullYear",
+    "getHours",
+    "getMilliseconds",
+    "getMinutes",
+    "getMonth",
+    "getSeconds",
+    "has",
+    "int",
+    "map",
+    "matches",
+    "max",
+    "min",
+    "now",
+    "size",
+    "startsWith",
+    "string",
+    "timestamp",
+    "type",
+    "uint",
+];
+
+/// Everything programs are compiled against: options, extensions and declarations
+///
+/// Compiling through an environment rejects calls to functions that are neither
+/// built in, provided by an enabled extension nor declared, instead of leaving them
+/// to fail when the program runs. With strict variables it likewise rejects
+/// references to variables that are not declared, catching typos such as
+/// `reqest.path` before they reach production. Reserved names and an identifier length
+/// limit keep expressions away from internal variables and unwieldy names. With
+/// late-bound functions, calls to undeclared functions are accepted and resolved
+/// against the functions of the context each execution runs with. Disabled macros and
+/// an iteration cap keep comprehensions over untrusted lists from running away.
+/// Constants and enums are inlined into programs as they compile, so configuration
+/// shared by every request costs nothing when the program runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Env {
+    options: CompileOptions,
+    variables: BTreeSet<String>,
+    /// Constant names and the source of the literal for their values
+    constants: BTreeMap<String, String>,
+    enums: BTreeMap<String, EnumType>,
+    functions: BTreeSet<String>,
+    /// Once set, the only functions programs may call, of those otherwise available
+    allowed_functions: Option<BTreeSet<String>>,
+    strict_variables: bool,
+    late_bound_functions: bool,
+    /// Variable names expressions may not reference; `prefix*` reserves a prefix
+    reserved_names: BTreeSet<String>,
+    max_identifier_length: usize,
+    max_comprehension_iterations: usize,
+}
+
+impl Env {
+    #[must_use]
+    pub const fn new(options: CompileOptions) -> Self {
+        Self {
+            options,
+            variables: BTreeSet::new(),
+            constants: BTreeMap::new(),
+            enums: BTreeMap::new(),
+            functions: BTreeSet::new(),
+            allowed_functions: None,
+            strict_variables: false,
+            late_bound_functions: false,
+            reserved_names: BTreeSet::new(),
+            max_identifier_length: 0,
+            max_comprehension_iterations: 0,
+        }
+    }
+
+    #[must_use]
+    pub const fn options(&self) -> &CompileOptions {
+        &self.options
+    }
+
+    /// Declare a variable that contexts will bind; returns false if it already was
+    pub fn declare_variable(&mut self, name: impl Into<String>) -> bool {
+        self.variables.insert(name.into())
+    }
+
+    /// Define a constant that programs compiled from now on see in place of the
+    /// variable `name`, replacing any earlier value
+    ///
+    /// References are replaced by the value when a program compiles and the
+    /// subexpressions that become constant are folded, so `amount > threshold * 2`
+    /// compiles to `amount > 200` for a threshold of 100. A context binding `name`
+    /// has no effect on such programs, and constants count as declared under strict
+    /// variables.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if `name` is not an identifier or `value` has
+    /// no literal form: only null, bools, numbers other than NaN and the infinities,
+    /// strings, bytes, and lists and maps of those can be constants.
+    pub fn add_constant(&mut self, name: impl Into<String>, value: &Value) -> Result<(), Error> {
+        let name = name.into();
+        if !is_identifier(&name) {
+            return Err(Error::InvalidArgument(format!("invalid constant name '{name}'")));
+        }
+        let Some(expression) = literal(value) else {
+            let message = format!("constant '{name}' has no literal form: {}", type_name_of(value));
+            return Err(Error::InvalidArgument(message));
+        };
+        self.enums.remove(&name);
+        self.constants.insert(name, unparse(&expression));
+        Ok(())
+    }
+
+    /// Register the enum `name` with the given members, replacing any earlier enum or
+    /// constant of that name
+    ///
+    /// Expressions compiled from now on write members as `Level.INFO`, which compiles
+    /// to the member's int, and selecting a member the enum lacks fails to compile.
+    /// The enum is a constant map from member names to values, so `Level` alone is
+    /// that map. Pass the enum from `enum_type` to `Program::set_result_enum` to get
+    /// member names back from a program.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if `name` or a member name is not an identifier.
+    pub fn register_enum(
+        &mut self,
+        name: impl Into<String>,
+        members: impl IntoIterator<Item = (String, i64)>,
+    ) -> Result<(), Error> {
+        let name = name.into();
+        let members: BTreeMap<String, i64> = members.into_iter().collect();
+        if let Some(member) = members.keys().find(|member| !is_identifier(member)) {
+            return Err(Error::InvalidArgument(format!("invalid enum member name '{member}'")));
+        }
+        let map = members
+            .iter()
+            .map(|(member, value)| (Key::String(Arc::new(member.clone())), Value::Int(*value)))
+            .collect();
+        self.add_constant(name.clone(), &Value::Map(Map { map: Arc::new(map) }))?;
+        self.enums.insert(name.clone(), EnumType::new(name, members));
+        Ok(())
+    }
+
+    /// The enum registered as `name`, if any
+    #[must_use]
+    pub fn enum_type(&self, name: &str) -> Option<&EnumType> {
+        self.enums.get(name)
+    }
+
+    /// Declare a function the host provides; returns false if it already was
+    pub fn declare_function(&mut self, name: impl Into<String>) -> bool {
+        self.functions.insert(name.into())
+    }
+
+    /// Allow programs to call `name`; returns false if it already was
+    ///
+    /// The first call restricts the environment to the functions allowed this way,
+    /// including built-in functions and macros such as `size` and `all`, for hosts
+    /// evaluating untrusted expressions. A function must still be built in, provided
+    /// by an enabled extension or declared to be called.
+    pub fn allow_function(&mut self, name: impl Into<String>) -> bool {
+        self.allowed_functions.get_or_insert_with(BTreeSet::new).insert(name.into())
+    }
+
+    /// Reject expressions that reference undeclared variables
+    pub const fn set_strict_variables(&mut self, strict: bool) {
+        self.strict_variables = strict;
+    }
+
+    #[must_use]
+    pub const fn strict_variables(&self) -> bool {
+        self.strict_variables
+    }
+
+    /// Accept calls to functions that are not declared, leaving them to be resolved
+    /// when the program runs
+    ///
+    /// For hosts that register functions on each request's context after compiling
+    /// expressions at startup. A call that the context does not register by then
+    /// fails the execution with `Error::UnresolvedFunction`. Allowed functions still
+    /// apply, so a restricted environment only accepts late-bound calls it allows.
+    pub const fn set_late_bound_functions(&mut self, late_bound: bool) {
+        self.late_bound_functions = late_bound;
+    }
+
+    #[must_use]
+    pub const fn late_bound_functions(&self) -> bool {
+        self.late_bound_functions
+    }
+
+    /// Reject expressions that reference the variable `pattern`; returns false if it
+    /// already was reserved
+    ///
+    /// A pattern ending in `*` reserves every name starting with the rest of it, so
+    /// `__*` keeps expressions away from internal variables such as `__tenant`.
+    pub fn reserve_name(&mut self, pattern: impl Into<String>) -> bool {
+        self.reserved_names.insert(pattern.into())
+    }
+
+    /// Whether expressions compiled in this environment may not reference `name`
+    #[must_use]
+    pub fn is_reserved(&self, name: &str) -> bool {
+        self.reserved_names.iter().any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == pattern.as_str(),
+        })
+    }
+
+    /// Reject expressions with an identifier longer than `max` bytes, or none for 0
+    ///
+    /// Variables, fields, functions and comprehension variables all count.
+    pub const fn set_max_identifier_length(&mut self, max: usize) {
+        self.max_identifier_length = max;
+    }
+
+    /// Reject expressions that use the macro `name`, one of
+    /// [`MACROS`](super::options::MACROS); returns false if it already was disabled
+    ///
+    /// Adds to the macros the environment's options disable, so disabling `map`,
+    /// `filter`, `exists`, `exists_one` and `all` rules out comprehensions entirely.
+    pub fn disable_macro(&mut self, name: impl Into<String>) -> bool {
+        let name = name.into();
+        if self.options.disabled_macros.contains(&name) {
+            return false;
+        }
+        self.options.disabled_macros.push(name);
+        true
+    }
+
+    /// Fail executions of programs compiled from now on once a comprehension ranges
+    /// over more than `max` elements, or never for 0
+    ///
+    /// The size of the list or map is checked before the first iteration, and each
+    /// nested comprehension is checked on its own range.
+    pub const fn set_max_comprehension_iterations(&mut self, max: usize) {
+        self.max_comprehension_iterations = max;
+    }
+
+    #[must_use]
+    pub const fn max_comprehension_iterations(&self) -> usize {
+        self.max_comprehension_iterations
+    }
+
+    /// Declared variable names, in order
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.variables.iter().map(String::as_str)
+    }
+
+    /// Constant names, in order
+    pub fn constants(&self) -> impl Iterator<Item = &str> {
+        self.constants.keys().map(String::as_str)
+    }
+
+    /// Declared function names, in order
+    pub fn functions(&self) -> impl Iterator<Item = &str> {
+        self.functions.iter().map(String::as_str)
+    }
+
+    /// Whether programs compiled in this environment can call `name`
+    #[must_use]
+    pub fn has_function(&self, name: &str) -> bool {
+        self.provides(name) && self.is_allowed(name)
+    }
+
+    fn is_allowed(&self, name: &str) -> bool {
+        self.allowed_functions.as_ref().is_none_or(|allowed| allowed.contains(name))
+    }
+
+    /// Whether `name` is built in, provided by an enabled extension or declared
+    fn provides(&self, name: &str) -> bool {
+        BUILTIN_FUNCTIONS.contains(&name)
+            || self.options.extensions().provides(name)
+            || self.functions.contains(name)
+    }
+
+    /// Compile `expression` with this environment's options and declarations
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Compile` if the expression exceeds a limit, has a longer
+    /// identifier than allowed, cannot be parsed, calls a function that is not
+    /// available or, with late-bound functions, not allowed, references a reserved
+    /// variable or, with strict variables, references a variable that is not declared.
+    pub fn compile(&self, expression: &str) -> Result<Program, Error> {
+        let max = self.max_identifier_length;
+        if let Some((offset, name)) =
+            identifiers(expression).find(|(_, name)| max > 0 && name.len() > max)
+        {
+            let message = format!("identifier '{name}' is longer than {max} bytes");
+            return Err(Error::Compile(CompileError::at(expression, offset, name.len(), message)));
+        }
+
+        let mut program = Program::compile_with_options(expression, &self.options)?;
+        let unavailable = |name: &String| {
+            !self.is_allowed(name) || !(self.provides(name) || self.late_bound_functions)
+        };
+        if let Some(name) = program.functions().into_iter().find(unavailable) {
+            let message = if self.provides(&name) || self.late_bound_functions {
+                format!("function '{name}' is not allowed")
+            } else {
+                format!("function '{name}' is not declared")
+            };
+            return Err(Error::Compile(CompileError::unlocated(expression, message)));
+        }
+
+        if let Some(name) = program.variables().into_iter().find(|name| self.is_reserved(name)) {
+            let message = format!("reference to reserved variable '{name}'");
+            return Err(Error::Compile(reference_error(expression, &name, message)));
+        }
+
+        let declared =
+            |name: &String| self.variables.contains(name) || self.constants.contains_key(name);
+        let undeclared = program.variables().into_iter().find(|name| !declared(name));
+        if let Some(name) = undeclared.filter(|_| self.strict_variables) {
+            return Err(Error::Compile(self.undeclared_variable(expression, &name)));
+        }
+        self.check_enum_members(expression, &program)?;
+        let constants: HashMap<&str, _> = self
+            .constants
+            .iter()
+            .filter_map(|(name, source)| Some((name.as_str(), cel_parser::parse(source).ok()?)))
+            .collect();
+        program.inline_constants(&constants)?;
+        if self.late_bound_functions {
+            program.set_late_bound();
+        }
+        program.set_max_range(self.max_comprehension_iterations);
+        Ok(program)
+    }
+
+    /// Fail if `program` selects a member that one of the enums lacks
+    fn check_enum_members(&self, expression: &str, program: &Program) -> Result<(), Error> {
+        for path in program.dependencies() {
+            for enum_type in self.enums.values() {
+                let Some(member) = enum_type.selected(&path) else {
+                    continue;
+                };
+                if enum_type.value(member).is_none() {
+                    let name = enum_type.name();
+                    let message = format!("enum '{name}' has no member '{member}'");
+                    return Err(Error::Compile(reference_error(expression, name, message)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// An error at the first reference to `name`, suggesting a declared variable with
+    /// a similar name
+    fn undeclared_variable(&self, expression: &str, name: &str) -> CompileError {
+        let mut message = format!("undeclared reference to '{name}'");
+        let closest = self
+            .variables
+            .iter()
+            .map(|declared| (edit_distance(name, declared), declared))
+            .filter(|&(distance, _)| distance <= 2 && distance < name.chars().count())
+            .min();
+        if let Some((_, suggestion)) = closest {
+            message.push_str(&format!(", did you mean '{suggestion}'?"));
+        }
+        reference_error(expression, name, message)
+    }
+}
+
+/// An error at the first reference to the variable `name`
+fn reference_error(expression: &str, name: &str, message: String) -> CompileError {
+    match identifier_offset(expression, name) {
+        Some(offset) => CompileError::at(expression, offset, name.len(), message),
+        None => CompileError::unlocated(expression, message),
+    }
+}
+
+/// Offsets and names of the identifiers in `expression`, outside string literals and
+/// comments
+fn identifiers(expression: &str) -> impl Iterator<Item = (usize, &str)> {
+    let scanner = Scanner::new(expression);
+    let bytes = expression.as_bytes();
+    let mut found = Vec::new();
+
+    let mut i = scanner.skip_trivia(0);
+    while i < bytes.len() {
+        if let Some(end) = scanner.string_end(i) {
+            i = scanner.skip_trivia(end);
+            continue;
+        }
+        // Numbers end like identifiers, and are skipped whole
+        let end = scanner.identifier_end(i).max(i + 1);
+        if bytes[i].is_ascii_alphabetic() || bytes[i] == b'_' {
+            found.push((i, &expression[i..end]));
+        }
+        i = scanner.skip_trivia(end);
+    }
+    found.into_iter()
+}
+
+/// Offset of the first standalone use of identifier `name` outside string literals,
+/// skipping field names such as the `b` in `a.b`
+pub(crate) fn identifier_offset(expression: &str, name: &str) -> Option<usize> {
+    let bytes = expression.as_bytes();
+    let is_ident = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'_';
+    let mut quote = None;
+    let mut escaped = false;
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let byte = bytes[offset];
+        if let Some(open) = quote {
+            if escaped {
+                escaped = false;
+            } else if byte == b'\\' {
+                escaped = true;
+            } else if byte == open {
+                quote = None;
+            }
+            offset += 1;
+            continue;
+        }
+        if byte == b'\'' || byte == b'"' {
+            quote = Some(byte);
+            offset += 1;
+            continue;
+        }
+        if !is_ident(byte) {
+            offset += 1;
+            continue;
+        }
+
+        let end = offset + bytes[offset..].iter().take_while(|&&byte| is_ident(byte)).count();
+        let selected = bytes[..offset].iter().rev().find(|byte| !byte.is_ascii_whitespace());
+        if &expression[offset..end] == name && selected != Some(&b'.') {
+            return Some(offset);
+        }
+        offset = end;
+    }
+
+    None
+}
+
+/// Levenshtein distance between two identifiers
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Context, Value};
+
+    #[test]
+    fn test_env_compile() {
+        let env = Env::default();
+        let program = env.compile("size([1, 2]) == 2 && 'ab'.startsWith('a')").unwrap();
+        assert_eq!(program.execute(&Context::new()), Ok(Value::Bool(true)));
+
+        let Err(Error::Compile(error)) = env.compile("'AB'.lowerAscii()") else {
+            panic!("expected a compile error");
+        };
+        assert_eq!(error.message, "function 'lowerAscii' is not declared");
+    }
+
+    #[test]
+    fn test_env_extensions_and_declarations() {
+        let mut env = Env::new(CompileOptions {
+            string_ext: true,
+            ..CompileOptions::default()
+        });
+        assert!(env.compile("'AB'.lowerAscii()").is_ok());
+        assert!(env.compile("lookup('id')").is_err());
+
+        assert!(env.declare_function("lookup"));
+        assert!(!env.declare_function("lookup"));
+        assert!(env.compile("lookup('id')").is_ok());
+
+        assert!(env.declare_variable("request"));
+        assert_eq!(env.variables().collect::<Vec<_>>(), ["request"]);
+        assert_eq!(env.functions().collect::<Vec<_>>(), ["lookup"]);
+    }
+
+    #[test]
+    fn test_env_allowed_functions() {
+        let mut env = Env::default();
+        env.declare_function("lookup");
+        assert!(env.compile("size(lookup('id')) > 0").is_ok());
+
+        assert!(env.allow_function("size"));
+        assert!(!env.allow_function("size"));
+        assert!(env.allow_function("all"));
+        assert!(env.allow_function("lowerAscii"));
+        assert!(env.compile("size([1]) > 0 && [1].all(x, x > 0)").is_ok());
+
+        let Err(Error::Compile(error)) = env.compile("size(lookup('id')) > 0") else {
+            panic!("expected a compile error");
+        };
+        assert_eq!(error.message, "function 'lookup' is not allowed");
+        let Err(Error::Compile(error)) = env.compile("'AB'.lowerAscii()") else {
+            panic!("expected a compile error");
+        };
+        assert_eq!(error.message, "function 'lowerAscii' is not declared");
+    }
+
+    #[test]
+    fn test_env_strict_variables() {
+        let mut env = Env::default();
+        env.declare_variable("request");
+        assert!(env.compile("reqest.path == '/'").is_ok());
+
+        env.set_strict_variables(true);
+        assert!(env.compile("request.path == '/' && [1].all(x, x > 0)").is_ok());
+
+        let Err(Error::Compile(error)) = env.compile("'reqest' != reqest.path") else {
+            panic!("expected a compile error");
+        };
+        assert_eq!(error.message, "undeclared reference to 'reqest', did you mean 'request'?");
+        assert_eq!((error.line, error.column, error.token.as_str()), (1, 13, "reqest"));
+
+        let Err(Error::Compile(error)) = env.compile("request.path == other") else {
+            panic!("expected a compile error");
+        };
+        assert_eq!(error.message, "undeclared reference to 'other'");
+    }
+
+    #[test]
+    fn test_env_late_bound_functions() {
+        let mut env = Env::default();
+        env.set_late_bound_functions(true);
+        let program = env.compile("lookup(id) + '!'").unwrap();
+
+        let mut context = Context::new();
+        context.add_variable("id".to_string(), serde_json::json!("a"));
+        assert_eq!(program.execute(&context), Err(Error::UnresolvedFunction("lookup".to_string())));
+
+        context.add_function("lookup", |args| match args {
+            [Value::String(id)] => Ok(Value::String(format!("user-{id}").into())),
+            _ => Err("expected a string".to_string()),
+        });
+        assert_eq!(program.execute(&context), Ok(Value::String("user-a!".to_string().into())));
+        assert!(context.remove_function("lookup"));
+        assert!(matches!(program.execute(&context), Err(Error::UnresolvedFunction(_))));
+
+        env.allow_function("size");
+        let Err(Error::Compile(error)) = env.compile("lookup(id)") else {
+            panic!("expected a compile error");
+        };
+        assert_eq!(error.message, "function 'lookup' is not allowed");
+    }
+
+    #[test]
+    fn test_env_identifier_policy() {
+        let mut env = Env::default();
+        assert!(env.reserve_name("__*"));
+        assert!(!env.reserve_name("__*"));
+        assert!(env.reserve_name("internal"));
+        assert!(env.is_reserved("__tenant") && env.is_reserved("internal"));
+        assert!(!env.is_reserved("internal_id"));
+        assert!(env.compile("user.__meta == 1 && '__x' != internal_id").is_ok());
+
+        let Err(Error::Compile(error)) = env.compile("a || __tenant.id == 'x'") else {
+            panic!("expected a compile error");
+        };
+        assert_eq!(error.message, "reference to reserved variable '__tenant'");
+        assert_eq!((error.column, error.length), (6, 8));
+        assert!(env.compile("[1].exists(internal, internal > 0)").is_ok());
+        assert!(env.compile("internal").is_err());
+
+        env.set_max_identifier_length(6);
+        assert!(env.compile("abcdef.x + 1234567 + size('abcdefg')").is_ok());
+        let Err(Error::Compile(error)) = env.compile("a.abcdefg // abcdefgh") else {
+            panic!("expected a compile error");
+        };
+        assert_eq!(error.message, "identifier 'abcdefg' is longer than 6 bytes");
+        assert_eq!(error.column, 3);
+    }
+
+    #[test]
+    fn test_env_disabled_macros() {
+        let mut env = Env::default();
+        assert!(env.disable_macro("map"));
+        assert!(!env.disable_macro("map"));
+        assert!(env.disable_macro("filter"));
+        assert!(env.compile("[1].all(x, x > 0)").is_ok());
+
+        let Err(Error::Compile(error)) = env.compile("[1].filter(x, x > 0)") else {
+            panic!("expected a compile error");
+        };
+        assert_eq!(error.message, "macro 'filter' is disabled");
+        assert_eq!(env.options().disabled_macros, ["map", "filter"]);
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("reqest", "request"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_env_applies_options() {
+        let env = Env::new(CompileOptions {
+            max_expression_length: 4,
+            ..CompileOptions::default()
+        });
+        assert!(env.compile("1 + 2").is_err());
+        assert!(env.compile("1+2").is_ok());
+    }
+}
@@ -0,0 +1,273 @@
+//! CEL equality and hashing of values, for hosts comparing and caching results
+//!
+//! Equality follows CEL rather than the shape of the values: numbers are equal when
+//! they are the same number, whatever their types, so `1`, `1u`, `1.0`, `bigint('1')`
+//! and `decimal('1.00')` are all equal, and NaN equals nothing. Lists are equal element
+//! by element, maps when they have the same keys with equal values, and optionals when
+//! both are empty or hold equal values. Strings never equal bytes, and values of other
+//! types are equal when they are the same value.
+//!
+//! `value_hash` agrees with `values_equal`: equal values hash the same. Like program
+//! fingerprints, it is FNV-1a over a canonical encoding, so it is the same across
+//! releases and platforms and can key caches shared between processes.
+
+use super::decimal::{as_decimal, Decimal};
+use super::value::{key_value, optional_parts, type_parts};
+use super::Value;
+use cel_interpreter::objects::{Key, Map};
+use std::cmp::Ordering;
+
+/// A number in the form equality compares it in
+#[derive(Debug, Clone, Copy)]
+enum Number {
+    /// An int, uint, big integer, decimal or whole double
+    Exact(Decimal),
+    /// A double with a fraction, an infinity or NaN
+    Float(f64),
+}
+
+/// Whether `left == right` holds under CEL equality
+#[must_use]
+pub fn values_equal(left: &Value, right: &Value) -> bool {
+    if let (Some(left), Some(right)) = (number(left), number(right)) {
+        return numbers_equal(left, right);
+    }
+    if let (Some(left), Some(right)) = (optional_parts(left), optional_parts(right)) {
+        return match (left, right) {
+            (Some(left), Some(right)) => values_equal(left, right),
+            (left, right) => left.is_none() && right.is_none(),
+        };
+    }
+
+    match (left, right) {
+        (Value::List(left), Value::List(right)) => {
+            left.len() == right.len()
+                && left.iter().zip(right.iter()).all(|(left, right)| values_equal(left, right))
+        }
+        (Value::Map(left), Value::Map(right)) => {
+            left.map.len() == right.map.len()
+                && left.map.iter().all(|(key, value)| {
+                    lookup(right, key).is_some_and(|other| values_equal(value, other))
+                })
+        }
+        (left, right) => left == right,
+    }
+}
+
+/// A 64-bit hash of `value` that equal values share
+#[must_use]
+pub fn value_hash(value: &Value) -> u64 {
+    let mut hasher = Fnv::new();
+    hasher.value(value);
+    hasher.0
+}
+
+fn number(value: &Value) -> Option<Number> {
+    match value {
+        Value::Float(f) => {
+            Some(whole_double(*f).map_or(Number::Float(*f), |n| Number::Exact(Decimal::integer(n))))
+        }
+        value => as_decimal(value).map(Number::Exact),
+    }
+}
+
+/// The integer a whole double is, or `None` if it has a fraction or is beyond 128 bits
+#[allow(clippy::cast_possible_truncation)]
+fn whole_double(f: f64) -> Option<i128> {
+    const LIMIT: f64 = 170_141_183_460_469_231_731_687_303_715_884_105_728.0;
+    (f.fract() == 0.0 && f.abs() < LIMIT).then(|| f as i128)
+}
+
+/// Exact numbers compare exactly, and a decimal with a fraction equals the double
+/// nearest to it, as under numeric promotion
+fn numbers_equal(left: Number, right: Number) -> bool {
+    match (left, right) {
+        (Number::Exact(left), Number::Exact(right)) => left.compare(right) == Ordering::Equal,
+        (Number::Float(left), Number::Float(right)) => {
+            left.partial_cmp(&right) == Some(Ordering::Equal)
+        }
+        (Number::Exact(exact), Number::Float(f)) | (Number::Float(f), Number::Exact(exact)) => {
+            exact.to_integer().is_none() && exact.to_f64().partial_cmp(&f) == Some(Ordering::Equal)
+        }
+    }
+}
+
+/// The value `map` holds for `key`, matching int and uint keys by number
+fn lookup<'a>(map: &'a Map, key: &Key) -> Option<&'a Value> {
+    map.map.get(key).or_else(|| match key {
+        Key::Int(i) => u64::try_from(*i).ok().and_then(|u| map.map.get(&Key::Uint(u))),
+        Key::Uint(u) => i64::try_from(*u).ok().and_then(|i| map.map.get(&Key::Int(i))),
+        _ => None,
+    })
+}
+
+/// FNV-1a, fed each value as a kind byte followed by its contents in little-endian
+struct Fnv(u64);
+
+impl Fnv {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    const fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn kind(&mut self, kind: u8) {
+        self.write(&[kind]);
+    }
+
+    fn length(&mut self, len: usize) {
+        self.write(&(len as u64).to_le_bytes());
+    }
+
+    fn value(&mut self, value: &Value) {
+        if let Some(number) = number(value) {
+            match number {
+                Number::Exact(exact) => match exact.to_integer() {
+                    Some(n) => {
+                        self.kind(1);
+                        self.write(&n.to_le_bytes());
+                    }
+                    None => self.double(exact.to_f64()),
+                },
+                Number::Float(f) => self.double(f),
+            }
+            return;
+        }
+        if let Some(inner) = optional_parts(value) {
+            self.kind(2);
+            if let Some(inner) = inner {
+                self.value(inner);
+            }
+            return;
+        }
+        if let Some(name) = type_parts(value) {
+            self.kind(3);
+            self.write(name.as_bytes());
+            return;
+        }
+
+        match value {
+            Value::Null => self.kind(4),
+            Value::Bool(b) => self.write(&[5, u8::from(*b)]),
+            Value::String(s) => {
+                self.kind(6);
+                self.length(s.len());
+                self.write(s.as_bytes());
+            }
+            Value::Bytes(b) => {
+                self.kind(7);
+                self.length(b.len());
+                self.write(b);
+            }
+            Value::List(items) => {
+                self.kind(8);
+                self.length(items.len());
+                for item in items.iter() {
+                    self.value(item);
+                }
+            }
+            Value::Map(map) => {
+                // Entries are hashed on their own and summed, so the order they are
+                // stored in does not matter
+                let sum = map.map.iter().fold(0_u64, |sum, (key, value)| {
+                    let mut entry = Self::new();
+                    entry.value(&key_value(key));
+                    entry.value(value);
+                    sum.wrapping_add(entry.0)
+                });
+                self.kind(9);
+                self.length(map.map.len());
+                self.write(&sum.to_le_bytes());
+            }
+            Value::Timestamp(time) => {
+                self.kind(10);
+                self.write(&time.timestamp().to_le_bytes());
+                self.write(&time.timestamp_subsec_nanos().to_le_bytes());
+            }
+            Value::Duration(duration) => {
+                self.kind(11);
+                self.write(&duration.num_seconds().to_le_bytes());
+                self.write(&duration.subsec_nanos().to_le_bytes());
+            }
+            Value::Function(name, _) => {
+                self.kind(12);
+                self.write(name.as_bytes());
+            }
+            _ => self.kind(0),
+        }
+    }
+
+    fn double(&mut self, f: f64) {
+        self.kind(13);
+        self.write(&f.to_bits().to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::value::{bigint_value, optional_value, parse_decimal, parse_timestamp};
+    use crate::api::{Context, Program};
+
+    fn execute(expression: &str) -> Value {
+        Program::compile(expression).unwrap().execute(&Context::new()).unwrap()
+    }
+
+    fn assert_equal(left: &Value, right: &Value) {
+        assert!(values_equal(left, right), "{left:?} != {right:?}");
+        assert!(values_equal(right, left), "{right:?} != {left:?}");
+        assert_eq!(value_hash(left), value_hash(right), "{left:?} and {right:?}");
+    }
+
+    #[test]
+    fn test_values_equal() {
+        let one = [
+            Value::Int(1),
+            Value::UInt(1),
+            Value::Float(1.0),
+            bigint_value(1),
+            parse_decimal("1.00").unwrap(),
+        ];
+        for left in &one {
+            for right in &one {
+                assert_equal(left, right);
+            }
+        }
+        assert_equal(&Value::Float(0.5), &parse_decimal("0.5").unwrap());
+        assert_equal(&Value::Float(-0.0), &Value::Int(0));
+        assert_equal(
+            &execute("{1: [1u, 'a'], 'b': null}"),
+            &execute("{'b': null, 1u: [1.0, 'a']}"),
+        );
+        assert_equal(&optional_value(Some(Value::Int(2))), &optional_value(Some(Value::UInt(2))));
+        assert_equal(
+            &parse_timestamp("2024-01-01T01:00:00+01:00").unwrap(),
+            &parse_timestamp("2024-01-01T00:00:00Z").unwrap(),
+        );
+        assert_equal(&execute("type(1)"), &execute("type(-5)"));
+
+        let nan = Value::Float(f64::NAN);
+        assert!(!values_equal(&nan, &nan));
+        assert!(!values_equal(&Value::Float(1.5), &Value::Int(1)));
+        assert!(!values_equal(&execute("'a'"), &execute("b'a'")));
+        assert!(!values_equal(&execute("[1, 2]"), &execute("[2, 1]")));
+        assert!(!values_equal(&execute("{'a': 1}"), &execute("{'a': 1, 'b': 2}")));
+        assert!(!values_equal(&optional_value(None), &Value::Null));
+        assert!(!values_equal(&Value::Int(-1), &Value::UInt(u64::MAX)));
+        assert!(!values_equal(&execute("type(1)"), &execute("type(1u)")));
+    }
+
+    #[test]
+    fn test_value_hash_is_stable() {
+        assert_eq!(value_hash(&Value::Null), 0xaf63_b94c_8601_b113);
+        assert_ne!(value_hash(&execute("[1, 2]")), value_hash(&execute("[2, 1]")));
+        assert_ne!(value_hash(&execute("'a'")), value_hash(&execute("b'a'")));
+    }
+}
@@ -0,0 +1,208 @@
+use std::fmt;
+
+/// Error returned by the safe API
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The expression could not be parsed
+    Compile(CompileError),
+    /// A variable could not be converted or resolved before evaluation
+    Variable(String),
+    /// Evaluation failed
+    Execution(String),
+    /// An argument was rejected, such as a conflicting variable path
+    InvalidArgument(String),
+    /// Evaluation stopped because its `CancelToken` was cancelled
+    Cancelled,
+    /// Evaluation needed the listed keys, which the data does not have, under
+    /// `MissingKeys::Unknown`
+    Unknown(Vec<String>),
+    /// Evaluation allocated more than the `limit` bytes of `Context::set_max_memory`,
+    /// having reached `allocated` bytes when it stopped
+    MemoryLimit { limit: usize, allocated: usize },
+    /// Evaluation ran more than the `limit` comprehension iterations of
+    /// `Context::set_max_iterations`
+    IterationLimit { limit: usize },
+    /// Evaluation called a late-bound function that the context does not register
+    UnresolvedFunction(String),
+    /// Evaluation returned a value of type `actual` from a program that
+    /// `Program::expect_type` requires to return `expected`
+    ResultType {
+        expected: &'static str,
+        actual: &'static str,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Compile(error) => write!(f, "Compilation error: {error}"),
+            Self::Execution(message) => write!(f, "Execution error: {message}"),
+            Self::Variable(message) | Self::InvalidArgument(message) => f.write_str(message),
+            Self::Cancelled => f.write_str("Execution cancelled"),
+            Self::Unknown(keys) => write!(f, "Result is unknown: missing {}", keys.join(", ")),
+            Self::MemoryLimit { limit, allocated } => write!(
+                f,
+                "Execution exceeded its memory limit of {limit} bytes, allocating {allocated}"
+            ),
+            Self::IterationLimit { limit } => {
+                write!(f, "Execution exceeded its limit of {limit} comprehension iterations")
+            }
+            Self::UnresolvedFunction(name) => {
+                write!(f, "Execution error: function '{name}' is not registered")
+            }
+            Self::ResultType { expected, actual } => {
+                write!(f, "Execution error: result has type {actual}, expected {expected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Where and why an expression failed to parse
+///
+/// `line` and `column` are 1-based, with the column counted in characters; both are 0
+/// when the parser reported no location. `offset` and `length` are in bytes and cover
+/// the offending token, which is empty at the end of input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
+    pub length: usize,
+    pub token: String,
+    pub expected: Vec<String>,
+    pub source: String,
+}
+
+impl CompileError {
+    pub(crate) fn from_parse_error(source: &str, error: &cel_parser::ParseError) -> Self {
+        let start = error.span.start.as_ref().or(error.span.end.as_ref());
+        let Some(start) = start.filter(|start| source.is_char_boundary(start.absolute)) else {
+            return Self::unlocated(source, error.msg.clone());
+        };
+
+        let end = error.span.end.as_ref().map_or(start.absolute, |end| end.absolute);
+        let token = source.get(start.absolute..end.max(start.absolute)).unwrap_or_default();
+        Self {
+            expected: error.expected.clone(),
+            ..Self::at(source, start.absolute, token.len(), error.msg.clone())
+        }
+    }
+
+    /// An error covering `length` bytes of `source` from `offset`, which must be a
+    /// character boundary
+    pub(crate) fn at(source: &str, offset: usize, length: usize, message: String) -> Self {
+        let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let token = source.get(offset..offset + length).unwrap_or_default();
+
+        Self {
+            message,
+            line: source[..line_start].matches('\n').count() + 1,
+            column: source[line_start..offset].chars().count() + 1,
+            offset,
+            length: token.len(),
+            token: token.to_string(),
+            expected: Vec::new(),
+            source: source.to_string(),
+        }
+    }
+
+    /// An error that applies to `source` as a whole
+    pub(crate) fn unlocated(source: &str, message: String) -> Self {
+        Self {
+            message,
+            line: 0,
+            column: 0,
+            offset: 0,
+            length: 0,
+            token: String::new(),
+            expected: Vec::new(),
+            source: source.to_string(),
+        }
+    }
+
+    /// The message followed by the offending source line with the token underlined
+    ///
+    /// ```text
+    /// ERROR: <input>:1:5: unrecognized token: '+'
+    ///  | 1 + + 2
+    ///  | ....^
+    /// ```
+    #[must_use]
+    pub fn render(&self) -> String {
+        let split = self.source.get(..self.offset).zip(self.source.get(self.offset..));
+        let Some((before, after)) = split.filter(|_| self.line > 0) else {
+            return format!("ERROR: <input>: {}", self.message);
+        };
+
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line = &before[line_start..];
+        let rest = after.split('\n').next().unwrap_or_default();
+        let underline = self.token.lines().next().map_or(1, |token| token.chars().count().max(1));
+
+        format!(
+            "ERROR: <input>:{}:{}: {}\n | {line}{rest}\n | {}{}",
+            self.line,
+            self.column,
+            self.message,
+            ".".repeat(line.chars().count()),
+            "^".repeat(underline)
+        )
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            f.write_str(&self.message)
+        } else {
+            write!(f, "{} at line {}, column {}", self.message, self.line, self.column)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile_error(expression: &str) -> CompileError {
+        match crate::api::Program::compile(expression) {
+            Err(Error::Compile(error)) => error,
+            other => panic!("expected a compile error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_error_display() {
+        assert_eq!(Error::Execution("bad".to_string()).to_string(), "Execution error: bad");
+        assert_eq!(Error::InvalidArgument("bad".to_string()).to_string(), "bad");
+        assert_eq!(
+            Error::Compile(compile_error("1 + + 2")).to_string(),
+            "Compilation error: unrecognized token: '+' at line 1, column 5"
+        );
+    }
+
+    #[test]
+    fn test_compile_error_location() {
+        let error = compile_error("a &&\n  'é' + + b");
+
+        assert_eq!((error.line, error.column), (2, 9));
+        assert_eq!(error.offset, "a &&\n  'é' + ".len());
+        assert_eq!((error.token.as_str(), error.length), ("+", 1));
+        assert_eq!(
+            error.render(),
+            "ERROR: <input>:2:9: unrecognized token: '+'\n |   'é' + + b\n | ........^"
+        );
+    }
+
+    #[test]
+    fn test_compile_error_at_end_of_input() {
+        let error = compile_error("1 +");
+
+        assert_eq!((error.line, error.column, error.offset), (1, 4, 3));
+        assert!(error.token.is_empty());
+        assert!(error.render().ends_with("\n | 1 +\n | ...^"));
+    }
+}
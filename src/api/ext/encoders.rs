@@ -0,0 +1,203 @@
+//! The encoders extension: base64, hex and URL encoding and decoding
+//!
+//! Functions are called through the `base64`, `hex` and `url` namespaces, as in
+//! `base64.decode(token)`. The namespaces share the function names `encode` and
+//! `decode`, and the interpreter passes a call only the value of its target, so each
+//! namespace is bound to a map naming its encoding for the functions to dispatch on.
+
+use super::{arg, check_arity, type_error};
+use crate::api::value::{base64_decode, base64_encode};
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::objects::{Key, Map};
+use cel_interpreter::{Context as CelContext, ExecutionError, FunctionContext, Value};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+pub(super) const FUNCTIONS: [&str; 2] = ["encode", "decode"];
+
+pub(super) const NAMESPACES: [&str; 3] = ["base64", "hex", "url"];
+
+/// Key of the namespace maps under which they name their encoding
+const ENCODING_KEY: &str = "encoding";
+
+pub(super) fn register(ctx: &mut CelContext) {
+    for namespace in NAMESPACES {
+        let map = HashMap::from([(
+            Key::String(Arc::new(ENCODING_KEY.to_string())),
+            Value::String(Arc::new(namespace.to_string())),
+        )]);
+        ctx.add_variable_from_value(namespace, Value::Map(Map { map: Arc::new(map) }));
+    }
+    ctx.add_function("encode", |ftx: &FunctionContext, Arguments(args): Arguments| {
+        let (function, encoding) = namespace("encode", ftx.this.as_ref())?;
+        check_arity(&function, &args, 1)?;
+        let bytes = match arg(&function, &args, 0)? {
+            Value::Bytes(b) => b.as_slice(),
+            Value::String(s) => s.as_bytes(),
+            other => return Err(type_error(&function, "bytes or string", other)),
+        };
+        let encoded = match encoding {
+            "base64" => base64_encode(bytes),
+            "hex" => hex_encode(bytes),
+            _ => url_encode(bytes),
+        };
+        Ok(Value::String(Arc::new(encoded)))
+    });
+    ctx.add_function("decode", |ftx: &FunctionContext, Arguments(args): Arguments| {
+        let (function, encoding) = namespace("decode", ftx.this.as_ref())?;
+        check_arity(&function, &args, 1)?;
+        let text = match arg(&function, &args, 0)? {
+            Value::String(s) => s.as_str(),
+            other => return Err(type_error(&function, "string", other)),
+        };
+        let invalid = || ExecutionError::function_error(&function, format!("invalid {encoding}"));
+        match encoding {
+            "base64" => base64_decode(&pad_base64(text))
+                .map(|b| Value::Bytes(Arc::new(b)))
+                .ok_or_else(invalid),
+            "hex" => hex_decode(text).map(|b| Value::Bytes(Arc::new(b))).ok_or_else(invalid),
+            _ => url_decode(text).map(|s| Value::String(Arc::new(s))).ok_or_else(invalid),
+        }
+    });
+}
+
+/// The qualified name of `function` and the encoding of the namespace it was called
+/// through, which must be one of those this extension binds
+fn namespace(
+    function: &str,
+    this: Option<&Value>,
+) -> Result<(String, &'static str), ExecutionError> {
+    let encoding = match this {
+        Some(Value::Map(map)) => match map.map.get(&Key::String(Arc::new(ENCODING_KEY.into()))) {
+            Some(Value::String(name)) => NAMESPACES.iter().find(|n| **n == name.as_str()),
+            _ => None,
+        },
+        _ => None,
+    };
+    encoding
+        .map(|encoding| (format!("{encoding}.{function}"), *encoding))
+        .ok_or_else(|| {
+            let message = format!("call as base64.{function}, hex.{function} or url.{function}");
+            ExecutionError::function_error(function, message)
+        })
+}
+
+/// Restore the padding base64 encoders commonly leave off, such as in JWTs
+fn pad_base64(text: &str) -> String {
+    let mut padded = text.to_string();
+    while padded.len() % 4 > 1 {
+        padded.push('=');
+    }
+    padded
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// Decode hex digits of either case, which must come in pairs
+fn hex_decode(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => hex_byte(*high, *low),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The byte written as the hex digits `high` and `low`
+fn hex_byte(high: u8, low: u8) -> Option<u8> {
+    let digit = |c: u8| char::from(c).to_digit(16);
+    u8::try_from((digit(high)? << 4) | digit(low)?).ok()
+}
+
+/// Percent-encode every byte outside the URI unreserved characters, so the result is
+/// safe as a path segment or query component
+fn url_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len());
+    for byte in bytes {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(byte) {
+            encoded.push(char::from(*byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+/// Decode `%XX` escapes and `+` as a space, as in form-encoded query strings, into
+/// text that must be valid UTF-8
+fn url_decode(text: &str) -> Option<String> {
+    let mut decoded = Vec::with_capacity(text.len());
+    let mut bytes = text.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'%' => {
+                decoded.push(hex_byte(bytes.next()?, bytes.next()?)?);
+            }
+            b'+' => decoded.push(b' '),
+            _ => decoded.push(byte),
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{CompileOptions, Context, Program, Value};
+    use std::sync::Arc;
+
+    fn eval(expression: &str) -> Result<Value, String> {
+        let options = CompileOptions {
+            encoders_ext: true,
+            ..CompileOptions::default()
+        };
+        let program = Program::compile_with_options(expression, &options).unwrap();
+        program.execute(&Context::new()).map_err(|e| e.to_string())
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(Arc::new(s.to_string()))
+    }
+
+    fn bytes(b: &[u8]) -> Value {
+        Value::Bytes(Arc::new(b.to_vec()))
+    }
+
+    #[test]
+    fn test_encoders_ext() {
+        assert_eq!(eval("base64.encode(b'hello')"), Ok(string("aGVsbG8=")));
+        assert_eq!(eval("base64.encode('user:pass')"), Ok(string("dXNlcjpwYXNz")));
+        assert_eq!(eval("base64.decode('aGVsbG8=')"), Ok(bytes(b"hello")));
+        assert_eq!(eval("base64.decode('aGVsbG8')"), Ok(bytes(b"hello")));
+        assert!(eval("base64.decode('a$==')").unwrap_err().contains("invalid base64"));
+
+        assert_eq!(eval("hex.encode(b'\\x00\\xff')"), Ok(string("00ff")));
+        assert_eq!(eval("hex.decode('00FF')"), Ok(bytes(&[0, 255])));
+        assert!(eval("hex.decode('abc')").unwrap_err().contains("invalid hex"));
+
+        assert_eq!(eval("url.encode('a b&c=é')"), Ok(string("a%20b%26c%3D%C3%A9")));
+        assert_eq!(eval("url.decode('a%20b+c%C3%A9')"), Ok(string("a b cé")));
+        assert!(eval("url.decode('%zz')").unwrap_err().contains("invalid url"));
+        assert!(eval("url.decode('%ff')").is_err());
+        assert!(eval("url.decode('%+f')").is_err());
+
+        assert_eq!(
+            eval("string(base64.decode(base64.encode('round trip')))"),
+            Ok(string("round trip"))
+        );
+        assert!(eval("hex.encode(1)").unwrap_err().contains("hex.encode"));
+        assert!(eval("{}.encode('a')").unwrap_err().contains("base64.encode"));
+
+        let disabled = Program::compile("hex.encode(b'a')").unwrap().execute(&Context::new());
+        assert!(disabled.is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! The JSON extension: parsing and serializing JSON text inside expressions
+//!
+//! Functions are called through the `json` namespace, as in
+//! `json.parse(request.headers['x-claims']).sub`. The interpreter looks functions up
+//! by their bare name, so the namespace is bound to a map marking it as this
+//! extension's, and a call through any other target, or none, is rejected rather than
+//! parsing JSON under a name a host function may expect to own.
+
+use super::{arg, check_arity, string_arg};
+use crate::api::{json_to_cel_value, OwnedValue};
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::objects::{Key, Map};
+use cel_interpreter::{Context as CelContext, ExecutionError, FunctionContext, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub(super) const FUNCTIONS: [&str; 2] = ["parse", "stringify"];
+
+pub(super) const NAMESPACE: &str = "json";
+
+/// Key of the namespace map marking it as the one this extension binds
+const MARKER_KEY: &str = "namespace";
+
+pub(super) fn register(ctx: &mut CelContext) {
+    let map = HashMap::from([(
+        Key::String(Arc::new(MARKER_KEY.to_string())),
+        Value::String(Arc::new(NAMESPACE.to_string())),
+    )]);
+    ctx.add_variable_from_value(NAMESPACE, Value::Map(Map { map: Arc::new(map) }));
+    ctx.add_function("parse", parse);
+    ctx.add_function("stringify", stringify);
+}
+
+/// Reject a call of `function` other than through the `json` namespace
+fn check_namespace(function: &str, this: Option<&Value>) -> Result<(), ExecutionError> {
+    let marker = Key::String(Arc::new(MARKER_KEY.to_string()));
+    let marked = match this {
+        Some(Value::Map(map)) => {
+            matches!(map.map.get(&marker), Some(Value::String(name)) if name.as_str() == NAMESPACE)
+        }
+        _ => false,
+    };
+    if marked {
+        Ok(())
+    } else {
+        Err(ExecutionError::function_error(function, format!("call as json.{function}")))
+    }
+}
+
+/// `json.parse(s)` converts the JSON text `s` to the value it describes, with objects
+/// as maps with string keys and numbers as ints, uints or doubles as they fit
+fn parse(ftx: &FunctionContext, Arguments(args): Arguments) -> Result<Value, ExecutionError> {
+    check_namespace("parse", ftx.this.as_ref())?;
+    check_arity("json.parse", &args, 1)?;
+    let text = string_arg("json.parse", &args, 0)?;
+    let json: serde_json::Value = serde_json::from_str(text)
+        .map_err(|e| ExecutionError::function_error("json.parse", e.to_string()))?;
+    json_to_cel_value(&json).map_err(|e| ExecutionError::function_error("json.parse", e))
+}
+
+/// `json.stringify(v)` writes `v` as compact JSON text, converting it the way results
+/// are converted to JSON, so map keys become strings and bytes become base64
+fn stringify(ftx: &FunctionContext, Arguments(args): Arguments) -> Result<Value, ExecutionError> {
+    check_namespace("stringify", ftx.this.as_ref())?;
+    check_arity("json.stringify", &args, 1)?;
+    let value = OwnedValue::try_from(arg("json.stringify", &args, 0)?)
+        .map_err(|e| ExecutionError::function_error("json.stringify", e))?;
+    let text = serde_json::to_string(&value)
+        .map_err(|e| ExecutionError::function_error("json.stringify", e.to_string()))?;
+    Ok(Value::String(Arc::new(text)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{CompileOptions, Context, Program, Value};
+    use std::sync::Arc;
+
+    fn eval(expression: &str) -> Result<Value, String> {
+        let options = CompileOptions {
+            json_ext: true,
+            ..CompileOptions::default()
+        };
+        let program = Program::compile_with_options(expression, &options).unwrap();
+        let mut context = Context::new();
+        context.add_variable(
+            "claims".to_string(),
+            r#"{"sub":"u1","roles":["admin"],"exp":17}"#.into(),
+        );
+        program.execute(&context).map_err(|e| e.to_string())
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(Arc::new(s.to_string()))
+    }
+
+    #[test]
+    fn test_json_ext() {
+        assert_eq!(eval("json.parse(claims).sub"), Ok(string("u1")));
+        assert_eq!(eval("'admin' in json.parse(claims).roles"), Ok(Value::Bool(true)));
+        assert_eq!(eval("json.parse(claims).exp + 1"), Ok(Value::Int(18)));
+        assert_eq!(eval("json.parse('[1.5, null, true]')[0]"), Ok(Value::Float(1.5)));
+        assert!(eval("json.parse('{')").unwrap_err().contains("json.parse"));
+        assert!(eval("json.parse(1)").unwrap_err().contains("string"));
+
+        assert_eq!(
+            eval("json.stringify({'b': [1, 2.5, null], 'a': b'hi'})"),
+            Ok(string(r#"{"a":"aGk=","b":[1,2.5,null]}"#))
+        );
+        assert_eq!(eval("json.stringify('quote \"')"), Ok(string(r#""quote \"""#)));
+        assert_eq!(
+            eval("json.stringify(json.parse(claims))"),
+            Ok(string(r#"{"exp":17,"roles":["admin"],"sub":"u1"}"#))
+        );
+        assert!(eval("json.stringify(duration('1s'))").is_err());
+
+        assert!(eval("parse('1')").unwrap_err().contains("call as json.parse"));
+        assert!(eval("{'a': 1}.stringify(1)").unwrap_err().contains("call as json.stringify"));
+        assert!(eval("claims.parse()").is_err());
+
+        let disabled = Program::compile("json.parse('1')").unwrap().execute(&Context::new());
+        assert!(disabled.is_err());
+    }
+}
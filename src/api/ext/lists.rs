@@ -0,0 +1,168 @@
+//! The CEL lists extension
+
+use super::{check_arity, int_arg, type_error};
+use cel_interpreter::extractors::{Arguments, This};
+use cel_interpreter::{Context as CelContext, ExecutionError, Value};
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+pub(super) const FUNCTIONS: [&str; 4] = ["flatten", "distinct", "sort", "slice"];
+
+pub(super) fn register(ctx: &mut CelContext) {
+    ctx.add_function("flatten", flatten);
+    ctx.add_function("distinct", distinct);
+    ctx.add_function("sort", sort);
+    ctx.add_function("slice", slice);
+}
+
+fn list_target<'a>(function: &str, this: &'a Value) -> Result<&'a Arc<Vec<Value>>, ExecutionError> {
+    match this {
+        Value::List(list) => Ok(list),
+        other => Err(type_error(function, "list", other)),
+    }
+}
+
+/// `list.flatten()` flattens one level of nested lists, `list.flatten(depth)` up to
+/// `depth` levels
+fn flatten(This(this): This<Value>, Arguments(args): Arguments) -> Result<Value, ExecutionError> {
+    check_arity("flatten", &args, 1)?;
+    let list = list_target("flatten", &this)?;
+    let depth = if args.is_empty() { 1 } else { int_arg("flatten", &args, 0)? };
+    let depth = usize::try_from(depth).map_err(|_| {
+        ExecutionError::function_error("flatten", format!("depth must be non-negative: {depth}"))
+    })?;
+
+    let mut flat = Vec::with_capacity(list.len());
+    flatten_into(&mut flat, list, depth);
+    Ok(Value::List(Arc::new(flat)))
+}
+
+fn flatten_into(flat: &mut Vec<Value>, list: &[Value], depth: usize) {
+    for item in list {
+        match item {
+            Value::List(inner) if depth > 0 => flatten_into(flat, inner, depth - 1),
+            other => flat.push(other.clone()),
+        }
+    }
+}
+
+/// `list.distinct()` keeps the first of each group of equal elements
+fn distinct(This(this): This<Value>) -> Result<Value, ExecutionError> {
+    let list = list_target("distinct", &this)?;
+    let mut unique: Vec<Value> = Vec::with_capacity(list.len());
+    for item in list.iter() {
+        if !unique.contains(item) {
+            unique.push(item.clone());
+        }
+    }
+    Ok(Value::List(Arc::new(unique)))
+}
+
+/// `list.sort()` sorts a list of ints, uints, doubles, strings, bools or bytes,
+/// all of the same type
+fn sort(This(this): This<Value>) -> Result<Value, ExecutionError> {
+    let list = list_target("sort", &this)?;
+    let mut sorted = list.to_vec();
+    let mut failure = None;
+    sorted.sort_by(|a, b| {
+        compare(a, b).unwrap_or_else(|| {
+            if failure.is_none() {
+                failure = Some((a.clone(), b.clone()));
+            }
+            Ordering::Equal
+        })
+    });
+
+    if let Some((a, b)) = failure {
+        return Err(ExecutionError::function_error(
+            "sort",
+            format!("cannot compare {a:?} with {b:?}"),
+        ));
+    }
+    Ok(Value::List(Arc::new(sorted)))
+}
+
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::UInt(a), Value::UInt(b)) => Some(a.cmp(b)),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Bytes(a), Value::Bytes(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// `list.slice(start, end)` is the elements from index `start` up to `end`
+fn slice(This(this): This<Value>, start: i64, end: i64) -> Result<Value, ExecutionError> {
+    let list = list_target("slice", &this)?;
+    let range_error = |message: String| Err(ExecutionError::function_error("slice", message));
+
+    let (Ok(from), Ok(to)) = (usize::try_from(start), usize::try_from(end)) else {
+        return range_error(format!("negative index: {start}..{end}"));
+    };
+    if from > to {
+        return range_error(format!("start index {start} is after end index {end}"));
+    }
+    if to > list.len() {
+        return range_error(format!("index {end} out of range for list of size {}", list.len()));
+    }
+    Ok(Value::List(Arc::new(list[from..to].to_vec())))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{CompileOptions, Context, Program, Value};
+
+    fn eval(expression: &str) -> Result<Value, String> {
+        let options = CompileOptions {
+            list_ext: true,
+            ..CompileOptions::default()
+        };
+        let program = Program::compile_with_options(expression, &options).unwrap();
+        program.execute(&Context::new()).map_err(|e| e.to_string())
+    }
+
+    fn holds(expression: &str) {
+        assert_eq!(eval(expression), Ok(Value::Bool(true)), "{expression}");
+    }
+
+    #[test]
+    fn test_flatten() {
+        holds("[1, [2, [3, [4]]]].flatten() == [1, 2, [3, [4]]]");
+        holds("[1, [2, [3, [4]]]].flatten(2) == [1, 2, 3, [4]]");
+        holds("[1, [2]].flatten(0) == [1, [2]]");
+        holds("[].flatten() == []");
+        assert!(eval("[1].flatten(-1)").is_err());
+    }
+
+    #[test]
+    fn test_distinct() {
+        holds("[1, 2, 1, 3, 2].distinct() == [1, 2, 3]");
+        holds("['b', 'a', 'b'].distinct() == ['b', 'a']");
+    }
+
+    #[test]
+    fn test_sort() {
+        holds("[3, 1, 2].sort() == [1, 2, 3]");
+        holds("['b', 'c', 'a'].sort() == ['a', 'b', 'c']");
+        holds("[2.5, -1.0].sort() == [-1.0, 2.5]");
+        assert!(eval("[1, 'a'].sort()").is_err());
+    }
+
+    #[test]
+    fn test_slice() {
+        holds("[1, 2, 3, 4].slice(1, 3) == [2, 3]");
+        holds("[1, 2].slice(2, 2) == []");
+        assert!(eval("[1, 2].slice(1, 3)").is_err());
+        assert!(eval("[1, 2].slice(2, 1)").is_err());
+        assert!(eval("[1, 2].slice(-1, 1)").is_err());
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        let program = Program::compile("[2, 1].sort()").unwrap();
+        assert!(program.execute(&Context::new()).is_err());
+    }
+}
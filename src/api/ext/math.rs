@@ -0,0 +1,154 @@
+//! The CEL math extension
+//!
+//! Functions are called through the `math` namespace, as in `math.greatest(a, b)`.
+
+use super::{arg, bind_namespace, type_error};
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::{Context as CelContext, ExecutionError, Value};
+use std::cmp::Ordering;
+
+pub(super) const FUNCTIONS: [&str; 6] = ["greatest", "least", "abs", "ceil", "floor", "round"];
+
+pub(super) fn register(ctx: &mut CelContext) {
+    bind_namespace(ctx, "math");
+    ctx.add_function("greatest", |Arguments(args): Arguments| {
+        extreme("math.greatest", &args, Ordering::Greater)
+    });
+    ctx.add_function("least", |Arguments(args): Arguments| {
+        extreme("math.least", &args, Ordering::Less)
+    });
+    ctx.add_function("abs", |Arguments(args): Arguments| match arg("math.abs", &args, 0)? {
+        Value::Int(i) => i
+            .checked_abs()
+            .map(Value::Int)
+            .ok_or_else(|| ExecutionError::function_error("math.abs", "integer overflow")),
+        Value::UInt(u) => Ok(Value::UInt(*u)),
+        Value::Float(f) => Ok(Value::Float(f.abs())),
+        other => Err(type_error("math.abs", "numeric", other)),
+    });
+    ctx.add_function("ceil", |Arguments(args): Arguments| rounding("math.ceil", &args, f64::ceil));
+    ctx.add_function("floor", |Arguments(args): Arguments| {
+        rounding("math.floor", &args, f64::floor)
+    });
+    ctx.add_function("round", |Arguments(args): Arguments| {
+        rounding("math.round", &args, f64::round)
+    });
+}
+
+/// The argument that compares as `wanted` against all others, from either the
+/// arguments themselves or a single list argument
+fn extreme(function: &str, args: &[Value], wanted: Ordering) -> Result<Value, ExecutionError> {
+    let values = match args {
+        [Value::List(list)] => list.as_slice(),
+        _ => args,
+    };
+    let Some((first, rest)) = values.split_first() else {
+        return Err(ExecutionError::function_error(function, "no arguments"));
+    };
+
+    let mut best = numeric(function, first)?;
+    for value in rest {
+        let value = numeric(function, value)?;
+        if compare(value, best) == Some(wanted) {
+            best = value;
+        }
+    }
+    Ok(best.clone())
+}
+
+fn numeric<'a>(function: &str, value: &'a Value) -> Result<&'a Value, ExecutionError> {
+    match value {
+        Value::Int(_) | Value::UInt(_) | Value::Float(_) => Ok(value),
+        other => Err(type_error(function, "numeric", other)),
+    }
+}
+
+/// Numeric comparison across int, uint and double, exact for the integer types
+pub(crate) fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::UInt(a), Value::UInt(b)) => Some(a.cmp(b)),
+        (Value::Int(a), Value::UInt(b)) => Some(i128::from(*a).cmp(&i128::from(*b))),
+        (Value::UInt(a), Value::Int(b)) => Some(i128::from(*a).cmp(&i128::from(*b))),
+        _ => as_f64(a).partial_cmp(&as_f64(b)),
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+pub(crate) const fn as_f64(value: &Value) -> f64 {
+    match value {
+        Value::Int(i) => *i as f64,
+        Value::UInt(u) => *u as f64,
+        Value::Float(f) => *f,
+        _ => f64::NAN,
+    }
+}
+
+fn rounding(
+    function: &str,
+    args: &[Value],
+    round: fn(f64) -> f64,
+) -> Result<Value, ExecutionError> {
+    match arg(function, args, 0)? {
+        Value::Float(f) => Ok(Value::Float(round(*f))),
+        other => Err(type_error(function, "double", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{CompileOptions, Context, Program, Value};
+
+    fn eval(expression: &str) -> Result<Value, String> {
+        let options = CompileOptions {
+            math_ext: true,
+            ..CompileOptions::default()
+        };
+        let program = Program::compile_with_options(expression, &options).unwrap();
+        program.execute(&Context::new()).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_greatest_and_least() {
+        assert_eq!(eval("math.greatest(1, 3, 2)"), Ok(Value::Int(3)));
+        assert_eq!(eval("math.greatest([1, 3, 2])"), Ok(Value::Int(3)));
+        assert_eq!(eval("math.greatest(1, 2.5, 2u)"), Ok(Value::Float(2.5)));
+        assert_eq!(eval("math.least(-1, 2u)"), Ok(Value::Int(-1)));
+        assert_eq!(eval("math.least([7])"), Ok(Value::Int(7)));
+        assert_eq!(eval("math.least(5)"), Ok(Value::Int(5)));
+        assert!(eval("math.least([])").is_err());
+        assert!(eval("math.greatest(1, 'a')").is_err());
+    }
+
+    #[test]
+    fn test_abs_and_rounding() {
+        assert_eq!(eval("math.abs(-4)"), Ok(Value::Int(4)));
+        assert_eq!(eval("math.abs(-4.5)"), Ok(Value::Float(4.5)));
+        assert_eq!(eval("math.abs(4u)"), Ok(Value::UInt(4)));
+        assert!(eval("math.abs(-9223372036854775807 - 1)").is_err());
+
+        assert_eq!(eval("math.ceil(1.2)"), Ok(Value::Float(2.0)));
+        assert_eq!(eval("math.floor(-1.2)"), Ok(Value::Float(-2.0)));
+        assert_eq!(eval("math.round(2.5)"), Ok(Value::Float(3.0)));
+        assert_eq!(eval("math.round(-2.5)"), Ok(Value::Float(-3.0)));
+        assert!(eval("math.ceil(1)").is_err());
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        let program = Program::compile("math.abs(-1)").unwrap();
+        assert!(program.execute(&Context::new()).is_err());
+    }
+
+    #[test]
+    fn test_variable_shadows_namespace() {
+        let options = CompileOptions {
+            math_ext: true,
+            ..CompileOptions::default()
+        };
+        let program = Program::compile_with_options("math.pi", &options).unwrap();
+        let mut context = Context::new();
+        context.add_variable("math".to_string(), serde_json::json!({"pi": 3}));
+        assert_eq!(program.execute(&context), Ok(Value::Int(3)));
+    }
+}
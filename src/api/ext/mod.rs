@@ -0,0 +1,129 @@
+//! Optional CEL extension libraries, enabled per program through `CompileOptions`
+
+mod encoders;
+mod json;
+mod lists;
+mod math;
+mod optional;
+mod strings;
+mod unicode;
+
+pub(crate) use math::{as_f64, compare as compare_numbers};
+pub(crate) use optional::{Desugared, INDEX as OPT_INDEX, SELECT as OPT_SELECT};
+
+use super::value_type_of;
+use cel_interpreter::objects::Map;
+use cel_interpreter::{Context as CelContext, ExecutionError, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Extension libraries a program registers on every execution context
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Extensions {
+    pub strings: bool,
+    pub math: bool,
+    pub lists: bool,
+    pub optional: bool,
+    pub unicode: bool,
+    pub encoders: bool,
+    pub json: bool,
+}
+
+impl Extensions {
+    pub fn register(self, ctx: &mut CelContext) {
+        if self.strings {
+            strings::register(ctx);
+        }
+        if self.math {
+            math::register(ctx);
+        }
+        if self.lists {
+            lists::register(ctx);
+        }
+        if self.optional {
+            optional::register(ctx);
+        }
+        if self.unicode {
+            unicode::register(ctx);
+        }
+        if self.encoders {
+            encoders::register(ctx);
+        }
+        if self.json {
+            json::register(ctx);
+        }
+    }
+
+    /// Whether `name` is a function these extensions register
+    pub fn provides(self, name: &str) -> bool {
+        (self.strings && strings::FUNCTIONS.contains(&name))
+            || (self.math && math::FUNCTIONS.contains(&name))
+            || (self.lists && lists::FUNCTIONS.contains(&name))
+            || (self.optional && optional::FUNCTIONS.contains(&name))
+            || (self.unicode && unicode::FUNCTIONS.contains(&name))
+            || (self.encoders && encoders::FUNCTIONS.contains(&name))
+            || (self.json && json::FUNCTIONS.contains(&name))
+    }
+
+    /// Whether `name` is a namespace these extensions bind, rather than a variable
+    pub fn binds(self, name: &str) -> bool {
+        (self.math && name == "math")
+            || (self.optional && name == "optional")
+            || (self.encoders && encoders::NAMESPACES.contains(&name))
+            || (self.json && name == json::NAMESPACE)
+    }
+}
+
+/// Bind `name` as a function namespace
+///
+/// The parser reads `ns.f(x)` as a call of `f` with `ns` as its target, which the
+/// interpreter evaluates, so the namespace is bound to an empty map that the
+/// functions ignore. Variables bound afterwards under the same name shadow it.
+fn bind_namespace(ctx: &mut CelContext, name: &str) {
+    let namespace = Map {
+        map: HashMap::new().into(),
+    };
+    ctx.add_variable_from_value(name, Value::Map(namespace));
+}
+
+/// The `index`th argument of `function`, which must be present
+fn arg<'a>(function: &str, args: &'a [Value], index: usize) -> Result<&'a Value, ExecutionError> {
+    args.get(index)
+        .ok_or_else(|| ExecutionError::function_error(function, "missing argument"))
+}
+
+fn string_arg<'a>(
+    function: &str,
+    args: &'a [Value],
+    index: usize,
+) -> Result<&'a Arc<String>, ExecutionError> {
+    match arg(function, args, index)? {
+        Value::String(s) => Ok(s),
+        other => Err(type_error(function, "string", other)),
+    }
+}
+
+fn int_arg(function: &str, args: &[Value], index: usize) -> Result<i64, ExecutionError> {
+    match arg(function, args, index)? {
+        Value::Int(i) => Ok(*i),
+        other => Err(type_error(function, "int", other)),
+    }
+}
+
+/// Reject calls with more than `max` arguments
+fn check_arity(function: &str, args: &[Value], max: usize) -> Result<(), ExecutionError> {
+    if args.len() > max {
+        return Err(ExecutionError::function_error(
+            function,
+            format!("expected at most {max} arguments, got {}", args.len()),
+        ));
+    }
+    Ok(())
+}
+
+fn type_error(function: &str, expected: &str, actual: &Value) -> ExecutionError {
+    ExecutionError::function_error(
+        function,
+        format!("expected {expected} argument, got {:?}", value_type_of(actual)),
+    )
+}
@@ -0,0 +1,375 @@
+//! CEL optional values
+//!
+//! `optional.of(x)`, `optional.ofNonZeroValue(x)` and `optional.none()` build
+//! optionals, which `hasValue()`, `value()`, `orValue(default)` and `or(other)` take
+//! apart. `a.?b` selects a field and `a[?k]` an element as an optional that is empty
+//! when the field or element is absent; selections chained after either propagate the
+//! optional. The parser has no syntax for these, so [`Desugared`] rewrites them into
+//! calls before the source is parsed.
+
+use super::{arg, bind_namespace, type_error};
+use crate::api::{optional_parts, optional_value, CompileError};
+use cel_interpreter::extractors::{Arguments, This};
+use cel_interpreter::objects::Key;
+use cel_interpreter::{Context as CelContext, ExecutionError, Value};
+use std::sync::Arc;
+
+pub(crate) const SELECT: &str = "_opt_select";
+pub(crate) const INDEX: &str = "_opt_index";
+
+pub(super) const FUNCTIONS: [&str; 9] = [
+    "of",
+    "ofNonZeroValue",
+    "none",
+    "hasValue",
+    "value",
+    "orValue",
+    "or",
+    SELECT,
+    INDEX,
+];
+
+pub(super) fn register(ctx: &mut CelContext) {
+    bind_namespace(ctx, "optional");
+    ctx.add_function("of", |Arguments(args): Arguments| {
+        Ok::<_, ExecutionError>(optional_value(Some(arg("optional.of", &args, 0)?.clone())))
+    });
+    ctx.add_function("ofNonZeroValue", |Arguments(args): Arguments| {
+        let value = arg("optional.ofNonZeroValue", &args, 0)?;
+        Ok::<_, ExecutionError>(optional_value((!is_zero(value)).then(|| value.clone())))
+    });
+    ctx.add_function("none", |Arguments(_): Arguments| optional_value(None));
+    ctx.add_function("hasValue", |This(this): This<Value>| {
+        Ok::<_, ExecutionError>(contents("hasValue", &this)?.is_some())
+    });
+    ctx.add_function("value", |This(this): This<Value>| {
+        contents("value", &this)?
+            .cloned()
+            .ok_or_else(|| ExecutionError::function_error("value", "optional.none() dereference"))
+    });
+    ctx.add_function("orValue", |This(this): This<Value>, default: Value| {
+        Ok::<_, ExecutionError>(contents("orValue", &this)?.cloned().unwrap_or(default))
+    });
+    ctx.add_function(
+        "or",
+        |This(this): This<Value>, other: Value| -> Result<Value, ExecutionError> {
+            if contents("or", &this)?.is_some() {
+                return Ok(this);
+            }
+            contents("or", &other)?;
+            Ok(other)
+        },
+    );
+    ctx.add_function(SELECT, |This(this): This<Value>, field: Arc<String>| {
+        select(&this, &Value::String(field))
+    });
+    ctx.add_function(INDEX, |This(this): This<Value>, key: Value| select(&this, &key));
+}
+
+/// The contents of the optional `value`
+fn contents<'a>(function: &str, value: &'a Value) -> Result<Option<&'a Value>, ExecutionError> {
+    optional_parts(value).ok_or_else(|| type_error(function, "optional", value))
+}
+
+/// `target[key]` as an optional, empty when `target` is an empty optional or has no
+/// such key or index
+fn select(target: &Value, key: &Value) -> Result<Value, ExecutionError> {
+    let target = match optional_parts(target) {
+        Some(Some(inner)) => inner,
+        Some(None) => return Ok(optional_value(None)),
+        None => target,
+    };
+
+    let found = match (target, key) {
+        (Value::Map(map), _) => map_key(key).and_then(|key| map.map.get(&key)),
+        (Value::List(items), Value::Int(i)) => usize::try_from(*i).ok().and_then(|i| items.get(i)),
+        (Value::List(items), Value::UInt(u)) => usize::try_from(*u).ok().and_then(|u| items.get(u)),
+        (Value::List(_), other) => return Err(type_error(INDEX, "int index", other)),
+        (other, _) => return Err(type_error(SELECT, "map or list", other)),
+    };
+    Ok(optional_value(found.cloned()))
+}
+
+fn map_key(key: &Value) -> Option<Key> {
+    match key {
+        Value::String(s) => Some(Key::String(Arc::clone(s))),
+        Value::Int(i) => Some(Key::Int(*i)),
+        Value::UInt(u) => Some(Key::Uint(*u)),
+        Value::Bool(b) => Some(Key::Bool(*b)),
+        _ => None,
+    }
+}
+
+fn is_zero(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::Bool(b) => !b,
+        Value::Int(i) => *i == 0,
+        Value::UInt(u) => *u == 0,
+        Value::Float(f) => *f == 0.0,
+        Value::String(s) => s.is_empty(),
+        Value::Bytes(b) => b.is_empty(),
+        Value::List(items) => items.is_empty(),
+        Value::Map(map) => map.map.is_empty(),
+        _ => false,
+    }
+}
+
+/// Source with optional selections rewritten into calls the parser accepts
+///
+/// `a.?b` becomes `a._opt_select('b')` and `a[?k]` becomes `a._opt_index(k)`, and
+/// plain selections chained after them are rewritten the same way. String literals
+/// are left alone.
+pub(crate) struct Desugared {
+    pub source: String,
+    /// `(rewritten offset, original offset, copied verbatim)` for each segment
+    segments: Vec<(usize, usize, bool)>,
+}
+
+impl Desugared {
+    pub fn of(source: &str) -> Self {
+        let mut rewriter = Rewriter {
+            source,
+            bytes: source.as_bytes(),
+            out: String::with_capacity(source.len()),
+            copied: 0,
+            segments: vec![(0, 0, true)],
+        };
+        rewriter.run();
+        rewriter.out.push_str(&source[rewriter.copied..]);
+
+        Self {
+            source: rewriter.out,
+            segments: rewriter.segments,
+        }
+    }
+
+    /// Offset in the original source of `offset` in the rewritten one; offsets inside
+    /// a rewritten selection map to its start
+    fn original_offset(&self, offset: usize) -> usize {
+        let index = self.segments.partition_point(|&(start, _, _)| start <= offset);
+        let (start, original, verbatim) = self.segments[index.saturating_sub(1)];
+        if verbatim {
+            original + (offset - start)
+        } else {
+            original
+        }
+    }
+
+    /// `error`, reported against the rewritten source, moved onto `original`
+    pub fn locate(&self, original: &str, error: CompileError) -> CompileError {
+        if error.line == 0 {
+            return CompileError::unlocated(original, error.message);
+        }
+        let start = self.original_offset(error.offset);
+        let end = self.original_offset(error.offset + error.length).max(start);
+        CompileError {
+            expected: error.expected,
+            ..CompileError::at(original, start, end - start, error.message)
+        }
+    }
+}
+
+struct Rewriter<'a> {
+    source: &'a str,
+    bytes: &'a [u8],
+    out: String,
+    /// Bytes of `source` already copied or replaced
+    copied: usize,
+    segments: Vec<(usize, usize, bool)>,
+}
+
+impl Rewriter<'_> {
+    fn run(&mut self) {
+        let mut quote = None;
+        let mut escaped = false;
+        // Whether each open `[` was rewritten to an optional index, and whether the
+        // selection chain was optional before it
+        let mut brackets: Vec<(bool, bool)> = Vec::new();
+        let mut chain = false;
+        let mut i = 0;
+
+        while i < self.bytes.len() {
+            let byte = self.bytes[i];
+            if let Some(open) = quote {
+                if escaped {
+                    escaped = false;
+                } else if byte == b'\\' {
+                    escaped = true;
+                } else if byte == open {
+                    quote = None;
+                }
+                i += 1;
+                continue;
+            }
+
+            match byte {
+                b'\'' | b'"' => {
+                    quote = Some(byte);
+                    chain = false;
+                }
+                b'.' if self.follows_operand(i) => {
+                    let optional = self.bytes.get(i + 1) == Some(&b'?');
+                    let name_start = self.skip_whitespace(if optional { i + 2 } else { i + 1 });
+                    let name_end = self.identifier_end(name_start);
+                    let is_call = self.bytes.get(self.skip_whitespace(name_end)) == Some(&b'(');
+                    if name_end > name_start && (optional || (chain && !is_call)) {
+                        let name = &self.source[name_start..name_end];
+                        self.replace(i, name_end, &format!(".{SELECT}('{name}')"));
+                        chain = true;
+                        i = name_end;
+                        continue;
+                    }
+                    chain = false;
+                }
+                b'[' => {
+                    let optional = self.bytes.get(i + 1) == Some(&b'?') && self.follows_operand(i);
+                    if optional || (chain && self.follows_operand(i)) {
+                        let end = if optional { i + 2 } else { i + 1 };
+                        self.replace(i, end, &format!(".{INDEX}("));
+                        brackets.push((true, chain));
+                        chain = false;
+                        i = end;
+                        continue;
+                    }
+                    brackets.push((false, chain));
+                    chain = false;
+                }
+                b']' => {
+                    let (optional, _) = brackets.pop().unwrap_or_default();
+                    if optional {
+                        self.replace(i, i + 1, ")");
+                    }
+                    chain = optional;
+                }
+                byte if byte.is_ascii_whitespace() => {}
+                _ => chain = false,
+            }
+            i += 1;
+        }
+    }
+
+    /// Replace `source[start..end]` with `replacement`
+    fn replace(&mut self, start: usize, end: usize, replacement: &str) {
+        self.out.push_str(&self.source[self.copied..start]);
+        self.segments.push((self.out.len(), start, false));
+        self.out.push_str(replacement);
+        self.segments.push((self.out.len(), end, true));
+        self.copied = end;
+    }
+
+    /// Whether the last token before `offset` ends an operand that can be selected from
+    fn follows_operand(&self, offset: usize) -> bool {
+        self.bytes[..offset]
+            .iter()
+            .rev()
+            .find(|byte| !byte.is_ascii_whitespace())
+            .is_some_and(|&byte| {
+                byte.is_ascii_alphanumeric()
+                    || matches!(byte, b'_' | b')' | b']' | b'}' | b'\'' | b'"')
+            })
+    }
+
+    fn skip_whitespace(&self, mut offset: usize) -> usize {
+        while self.bytes.get(offset).is_some_and(u8::is_ascii_whitespace) {
+            offset += 1;
+        }
+        offset
+    }
+
+    fn identifier_end(&self, start: usize) -> usize {
+        let starts_identifier = |byte: &u8| byte.is_ascii_alphabetic() || *byte == b'_';
+        if !self.bytes.get(start).is_some_and(starts_identifier) {
+            return start;
+        }
+        let mut end = start + 1;
+        while self
+            .bytes
+            .get(end)
+            .is_some_and(|byte| byte.is_ascii_alphanumeric() || *byte == b'_')
+        {
+            end += 1;
+        }
+        end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{CompileOptions, Context, Error, Program};
+
+    fn eval_with(expression: &str, context: &Context) -> Result<Value, String> {
+        let options = CompileOptions {
+            optional_ext: true,
+            ..CompileOptions::default()
+        };
+        let program = Program::compile_with_options(expression, &options).unwrap();
+        program.execute(context).map_err(|e| e.to_string())
+    }
+
+    fn eval(expression: &str) -> Result<Value, String> {
+        eval_with(expression, &Context::new())
+    }
+
+    #[test]
+    fn test_desugar() {
+        assert_eq!(Desugared::of("a.?b").source, "a._opt_select('b')");
+        assert_eq!(Desugared::of("a.?b.c.d()").source, "a._opt_select('b')._opt_select('c').d()");
+        assert_eq!(Desugared::of("a[?0][1]").source, "a._opt_index(0)._opt_index(1)");
+        assert_eq!(Desugared::of("a.?b[x.y]").source, "a._opt_select('b')._opt_index(x.y)");
+        assert_eq!(Desugared::of("a.?b == c.d").source, "a._opt_select('b') == c.d");
+        assert_eq!(Desugared::of("'a.?b' + a.b").source, "'a.?b' + a.b");
+        assert_eq!(Desugared::of("x ? [1][0] : 2").source, "x ? [1][0] : 2");
+    }
+
+    #[test]
+    fn test_optional_functions() {
+        assert_eq!(eval("optional.of(1).value()"), Ok(Value::Int(1)));
+        assert_eq!(eval("optional.of(1).hasValue()"), Ok(Value::Bool(true)));
+        assert_eq!(eval("optional.none().hasValue()"), Ok(Value::Bool(false)));
+        assert_eq!(eval("optional.none().orValue(2)"), Ok(Value::Int(2)));
+        assert_eq!(eval("optional.ofNonZeroValue('').orValue('x')"), eval("'x'"));
+        assert_eq!(eval("optional.none().or(optional.of(3)).value()"), Ok(Value::Int(3)));
+        assert!(eval("optional.none().value()").unwrap_err().contains("dereference"));
+        assert!(eval("1.orValue(2)").is_err());
+    }
+
+    #[test]
+    fn test_optional_selection() {
+        let mut context = Context::new();
+        context.add_variable(
+            "req".to_string(),
+            serde_json::json!({"headers": {"host": "a"}, "tags": ["x"]}),
+        );
+        let eval = |expression| eval_with(expression, &context);
+
+        assert_eq!(eval("req.?headers.host.orValue('none')"), eval("'a'"));
+        assert_eq!(eval("req.?headers.?agent.orValue('none')"), eval("'none'"));
+        assert_eq!(eval("req.?missing.host.hasValue()"), Ok(Value::Bool(false)));
+        assert_eq!(eval("req.tags[?0].orValue('')"), eval("'x'"));
+        assert_eq!(eval("req.tags[?5].hasValue()"), Ok(Value::Bool(false)));
+        assert_eq!(eval("req.?tags[0].value()"), eval("'x'"));
+    }
+
+    #[test]
+    fn test_error_location_in_original_source() {
+        let options = CompileOptions {
+            optional_ext: true,
+            ..CompileOptions::default()
+        };
+        let Err(Error::Compile(error)) = Program::compile_with_options("a.?b + + c", &options)
+        else {
+            panic!("expected a compile error");
+        };
+        assert_eq!((error.line, error.column, error.offset), (1, 8, 7));
+        assert_eq!(error.source, "a.?b + + c");
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        assert!(Program::compile("a.?b").is_err());
+        let program = Program::compile("optional.none()").unwrap();
+        assert!(program.execute(&Context::new()).is_err());
+    }
+}
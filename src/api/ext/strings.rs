@@ -0,0 +1,221 @@
+//! The CEL strings extension
+//!
+//! Indices and lengths count Unicode code points, as in other CEL implementations.
+
+use super::{check_arity, int_arg, string_arg, type_error};
+use cel_interpreter::extractors::{Arguments, This};
+use cel_interpreter::{Context as CelContext, ExecutionError, Value};
+use std::sync::Arc;
+
+pub(super) const FUNCTIONS: [&str; 8] = [
+    "lowerAscii",
+    "upperAscii",
+    "trim",
+    "replace",
+    "split",
+    "join",
+    "indexOf",
+    "substring",
+];
+
+pub(super) fn register(ctx: &mut CelContext) {
+    ctx.add_function("lowerAscii", |This(this): This<Arc<String>>| {
+        Ok::<_, ExecutionError>(Value::String(Arc::new(this.to_ascii_lowercase())))
+    });
+    ctx.add_function("upperAscii", |This(this): This<Arc<String>>| {
+        Ok::<_, ExecutionError>(Value::String(Arc::new(this.to_ascii_uppercase())))
+    });
+    ctx.add_function("trim", |This(this): This<Arc<String>>| {
+        Ok::<_, ExecutionError>(Value::String(Arc::new(this.trim().to_string())))
+    });
+    ctx.add_function("replace", replace);
+    ctx.add_function("split", split);
+    ctx.add_function("join", join);
+    ctx.add_function("indexOf", index_of);
+    ctx.add_function("substring", substring);
+}
+
+/// `s.replace(old, new)` replaces every occurrence, `s.replace(old, new, n)` the
+/// first `n`, or all of them when `n` is negative
+fn replace(
+    This(this): This<Arc<String>>,
+    Arguments(args): Arguments,
+) -> Result<Value, ExecutionError> {
+    check_arity("replace", &args, 3)?;
+    let from = string_arg("replace", &args, 0)?;
+    let to = string_arg("replace", &args, 1)?;
+    let limit = if args.len() > 2 { int_arg("replace", &args, 2)? } else { -1 };
+
+    let replaced = match usize::try_from(limit) {
+        Ok(limit) => this.replacen(from.as_str(), to, limit),
+        Err(_) => this.replace(from.as_str(), to),
+    };
+    Ok(Value::String(Arc::new(replaced)))
+}
+
+/// `s.split(sep)` splits at every separator, `s.split(sep, n)` into at most `n`
+/// parts, or at every separator when `n` is negative; an empty separator splits
+/// between code points
+fn split(
+    This(this): This<Arc<String>>,
+    Arguments(args): Arguments,
+) -> Result<Value, ExecutionError> {
+    check_arity("split", &args, 2)?;
+    let separator = string_arg("split", &args, 0)?;
+    let limit = if args.len() > 1 { int_arg("split", &args, 1)? } else { -1 };
+    let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+
+    let parts: Vec<String> = if limit == 0 {
+        Vec::new()
+    } else if separator.is_empty() {
+        let mut chars = this.chars();
+        let mut parts: Vec<String> = chars.by_ref().take(limit - 1).map(String::from).collect();
+        let rest: String = chars.collect();
+        if !rest.is_empty() {
+            parts.push(rest);
+        }
+        parts
+    } else {
+        this.splitn(limit, separator.as_str()).map(str::to_string).collect()
+    };
+    Ok(Value::List(Arc::new(
+        parts.into_iter().map(|part| Value::String(Arc::new(part))).collect(),
+    )))
+}
+
+/// `list.join()` concatenates a list of strings, `list.join(sep)` with `sep` between
+fn join(This(this): This<Value>, Arguments(args): Arguments) -> Result<Value, ExecutionError> {
+    check_arity("join", &args, 1)?;
+    let Value::List(items) = this else {
+        return Err(type_error("join", "list", &this));
+    };
+    let separator = if args.is_empty() { "" } else { string_arg("join", &args, 0)?.as_str() };
+
+    let parts = items
+        .iter()
+        .map(|item| match item {
+            Value::String(s) => Ok(s.as_str()),
+            other => Err(type_error("join", "list of strings", other)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::String(Arc::new(parts.join(separator))))
+}
+
+/// `s.indexOf(sub)` is the code point index of the first occurrence of `sub`, or -1;
+/// `s.indexOf(sub, start)` searches from code point `start`
+fn index_of(
+    This(this): This<Arc<String>>,
+    Arguments(args): Arguments,
+) -> Result<Value, ExecutionError> {
+    check_arity("indexOf", &args, 2)?;
+    let needle = string_arg("indexOf", &args, 0)?;
+    let start = if args.len() > 1 { int_arg("indexOf", &args, 1)? } else { 0 };
+    let start_byte = char_to_byte("indexOf", &this, start)?;
+
+    let index = this[start_byte..].find(needle.as_str()).map_or(-1, |offset| {
+        let chars = this[..start_byte + offset].chars().count();
+        i64::try_from(chars).unwrap_or(i64::MAX)
+    });
+    Ok(Value::Int(index))
+}
+
+/// `s.substring(start)` and `s.substring(start, end)`, in code points
+fn substring(
+    This(this): This<Arc<String>>,
+    Arguments(args): Arguments,
+) -> Result<Value, ExecutionError> {
+    check_arity("substring", &args, 2)?;
+    let start = int_arg("substring", &args, 0)?;
+    let start_byte = char_to_byte("substring", &this, start)?;
+    let end_byte = if args.len() > 1 {
+        let end = int_arg("substring", &args, 1)?;
+        if end < start {
+            return Err(ExecutionError::function_error(
+                "substring",
+                format!("invalid range: start {start} is after end {end}"),
+            ));
+        }
+        char_to_byte("substring", &this, end)?
+    } else {
+        this.len()
+    };
+
+    Ok(Value::String(Arc::new(this[start_byte..end_byte].to_string())))
+}
+
+/// Byte offset of code point `index` in `s`, which may be one past the last
+fn char_to_byte(function: &str, s: &str, index: i64) -> Result<usize, ExecutionError> {
+    usize::try_from(index)
+        .ok()
+        .and_then(|index| s.char_indices().map(|(i, _)| i).chain([s.len()]).nth(index))
+        .ok_or_else(|| {
+            ExecutionError::function_error(function, format!("index out of range: {index}"))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{CompileOptions, Context, Program, Value};
+    use std::sync::Arc;
+
+    fn eval(expression: &str) -> Result<Value, String> {
+        let options = CompileOptions {
+            string_ext: true,
+            ..CompileOptions::default()
+        };
+        let program = Program::compile_with_options(expression, &options).unwrap();
+        program.execute(&Context::new()).map_err(|e| e.to_string())
+    }
+
+    fn string(s: &str) -> Result<Value, String> {
+        Ok(Value::String(Arc::new(s.to_string())))
+    }
+
+    #[test]
+    fn test_case_and_trim() {
+        assert_eq!(eval("'TacoCat'.lowerAscii()"), string("tacocat"));
+        assert_eq!(eval("'TacoCÆt'.upperAscii()"), string("TACOCÆT"));
+        assert_eq!(eval("' \\t trim me \\n'.trim()"), string("trim me"));
+    }
+
+    #[test]
+    fn test_replace() {
+        assert_eq!(eval("'hello hello'.replace('he', 'we')"), string("wello wello"));
+        assert_eq!(eval("'hello hello'.replace('he', 'we', 1)"), string("wello hello"));
+        assert_eq!(eval("'hello hello'.replace('he', 'we', -1)"), string("wello wello"));
+        assert_eq!(eval("'hello'.replace('he', 'we', 0)"), string("hello"));
+    }
+
+    #[test]
+    fn test_split_and_join() {
+        assert_eq!(eval("'a,b,c'.split(',') == ['a', 'b', 'c']"), Ok(Value::Bool(true)));
+        assert_eq!(eval("'a,b,c'.split(',', 2) == ['a', 'b,c']"), Ok(Value::Bool(true)));
+        assert_eq!(eval("'a,b,c'.split(',', 0) == []"), Ok(Value::Bool(true)));
+        assert_eq!(eval("'héj'.split('') == ['h', 'é', 'j']"), Ok(Value::Bool(true)));
+        assert_eq!(eval("'héj'.split('', 2) == ['h', 'éj']"), Ok(Value::Bool(true)));
+        assert_eq!(eval("['a', 'b'].join()"), string("ab"));
+        assert_eq!(eval("['a', 'b'].join(', ')"), string("a, b"));
+        assert!(eval("['a', 1].join()").is_err());
+    }
+
+    #[test]
+    fn test_index_of_and_substring() {
+        assert_eq!(eval("'héllo'.indexOf('l')"), Ok(Value::Int(2)));
+        assert_eq!(eval("'héllo'.indexOf('l', 3)"), Ok(Value::Int(3)));
+        assert_eq!(eval("'héllo'.indexOf('z')"), Ok(Value::Int(-1)));
+        assert_eq!(eval("'héllo'.indexOf('', 5)"), Ok(Value::Int(5)));
+        assert!(eval("'héllo'.indexOf('l', 6)").is_err());
+
+        assert_eq!(eval("'héllo'.substring(1)"), string("éllo"));
+        assert_eq!(eval("'héllo'.substring(1, 3)"), string("él"));
+        assert_eq!(eval("'héllo'.substring(5)"), string(""));
+        assert!(eval("'héllo'.substring(3, 1)").is_err());
+        assert!(eval("'héllo'.substring(-1)").is_err());
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        let program = Program::compile("'A'.lowerAscii()").unwrap();
+        assert!(program.execute(&Context::new()).is_err());
+    }
+}
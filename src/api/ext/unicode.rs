@@ -0,0 +1,153 @@
+//! The unicode extension: comparisons that treat text as people read it
+//!
+//! Byte-wise comparison tells `"Straße"` from `"STRASSE"`, and `"é"` typed as one code
+//! point from `"é"` typed as `e` and a combining accent. These functions normalize and
+//! case-fold first. Collation follows the root order rather than the tailoring of a
+//! particular language, since the library carries no locale data: letters sort
+//! together regardless of accents and case, then unaccented before accented, then
+//! lowercase before uppercase.
+
+use super::{check_arity, string_arg, type_error};
+use cel_interpreter::extractors::{Arguments, This};
+use cel_interpreter::{Context as CelContext, ExecutionError, Value};
+use std::cmp::Ordering;
+use std::sync::Arc;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+pub(super) const FUNCTIONS: [&str; 5] = [
+    "foldCase",
+    "equalsIgnoreCase",
+    "normalize",
+    "compareCollated",
+    "sortCollated",
+];
+
+pub(super) fn register(ctx: &mut CelContext) {
+    ctx.add_function("foldCase", |This(this): This<Arc<String>>| {
+        Ok::<_, ExecutionError>(Value::String(Arc::new(fold_case(&this))))
+    });
+    ctx.add_function("equalsIgnoreCase", equals_ignore_case);
+    ctx.add_function("normalize", normalize);
+    ctx.add_function("compareCollated", compare_collated);
+    ctx.add_function("sortCollated", sort_collated);
+}
+
+/// `s` in NFC with its case folded, approximating Unicode full case folding by
+/// uppercasing and then lowercasing, so `ß` folds to `ss` like `SS` does
+fn fold_case(s: &str) -> String {
+    s.nfc().collect::<String>().to_uppercase().to_lowercase()
+}
+
+/// `a.equalsIgnoreCase(b)` compares `a` and `b` after normalizing and folding case
+fn equals_ignore_case(
+    This(this): This<Arc<String>>,
+    Arguments(args): Arguments,
+) -> Result<Value, ExecutionError> {
+    check_arity("equalsIgnoreCase", &args, 1)?;
+    let other = string_arg("equalsIgnoreCase", &args, 0)?;
+    Ok(Value::Bool(fold_case(&this) == fold_case(other)))
+}
+
+/// `s.normalize(form)` converts `s` to the normalization form `NFC`, `NFD`, `NFKC` or
+/// `NFKD`
+fn normalize(
+    This(this): This<Arc<String>>,
+    Arguments(args): Arguments,
+) -> Result<Value, ExecutionError> {
+    check_arity("normalize", &args, 1)?;
+    let normalized = match string_arg("normalize", &args, 0)?.as_str() {
+        "NFC" => this.nfc().collect(),
+        "NFD" => this.nfd().collect(),
+        "NFKC" => this.nfkc().collect(),
+        "NFKD" => this.nfkd().collect(),
+        form => {
+            let message = format!("unknown normalization form '{form}'");
+            return Err(ExecutionError::function_error("normalize", message));
+        }
+    };
+    Ok(Value::String(Arc::new(normalized)))
+}
+
+/// `a.compareCollated(b)` is -1, 0 or 1 as `a` sorts before, with or after `b`
+fn compare_collated(
+    This(this): This<Arc<String>>,
+    Arguments(args): Arguments,
+) -> Result<Value, ExecutionError> {
+    check_arity("compareCollated", &args, 1)?;
+    let other = string_arg("compareCollated", &args, 0)?;
+    Ok(Value::Int(match collation_key(&this).cmp(&collation_key(other)) {
+        Ordering::Less => -1,
+        Ordering::Equal => 0,
+        Ordering::Greater => 1,
+    }))
+}
+
+/// `list.sortCollated()` sorts a list of strings in collation order
+fn sort_collated(This(this): This<Value>) -> Result<Value, ExecutionError> {
+    let Value::List(list) = &this else {
+        return Err(type_error("sortCollated", "list", &this));
+    };
+    let mut sorted = Vec::with_capacity(list.len());
+    for item in list.iter() {
+        match item {
+            Value::String(s) => sorted.push(Arc::clone(s)),
+            other => return Err(type_error("sortCollated", "list of strings", other)),
+        }
+    }
+    sorted.sort_by_cached_key(|s| collation_key(s));
+    Ok(Value::List(Arc::new(sorted.into_iter().map(Value::String).collect())))
+}
+
+/// Sort key for `s`: its letters without accents or case, then its accents, then its
+/// case with lowercase first, then its code points, so only equal strings tie
+fn collation_key(s: &str) -> (String, String, Vec<(bool, char)>, String) {
+    let decomposed: String = s.nfkd().collect();
+    let folded = decomposed.to_uppercase().to_lowercase();
+    let base = folded.chars().filter(|c| !is_combining_mark(*c)).collect();
+    let case = decomposed.chars().map(|c| (c.is_uppercase(), c)).collect();
+    (base, folded, case, s.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{CompileOptions, Context, Program, Value};
+    use std::sync::Arc;
+
+    fn eval(expression: &str) -> Result<Value, String> {
+        let options = CompileOptions {
+            unicode_ext: true,
+            ..CompileOptions::default()
+        };
+        let program = Program::compile_with_options(expression, &options).unwrap();
+        program.execute(&Context::new()).map_err(|e| e.to_string())
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(Arc::new(s.to_string()))
+    }
+
+    #[test]
+    fn test_unicode_ext() {
+        assert_eq!(eval("'Straße'.equalsIgnoreCase('STRASSE')"), Ok(Value::Bool(true)));
+        assert_eq!(eval("'Café'.equalsIgnoreCase('CAFE\u{301}')"), Ok(Value::Bool(true)));
+        assert_eq!(eval("'Café'.equalsIgnoreCase('cafe')"), Ok(Value::Bool(false)));
+        assert_eq!(eval("'ÖL'.foldCase()"), Ok(string("öl")));
+
+        assert_eq!(eval("'e\u{301}'.normalize('NFC')"), Ok(string("é")));
+        assert_eq!(eval("'é'.normalize('NFD')"), Ok(string("e\u{301}")));
+        assert_eq!(eval("'ﬁ'.normalize('NFKC')"), Ok(string("fi")));
+        assert!(eval("'a'.normalize('nfc')").unwrap_err().contains("unknown normalization"));
+
+        assert_eq!(eval("'äpple'.compareCollated('Zebra')"), Ok(Value::Int(-1)));
+        assert_eq!(eval("'a'.compareCollated('A')"), Ok(Value::Int(-1)));
+        assert_eq!(eval("'b'.compareCollated('b')"), Ok(Value::Int(0)));
+        let sorted = eval("['b', 'Ä', 'z', 'é', 'a', 'E'].sortCollated()");
+        let expected = ["a", "Ä", "b", "E", "é", "z"].map(string);
+        assert_eq!(sorted, Ok(Value::List(Arc::new(expected.to_vec()))));
+        assert!(eval("['a', 1].sortCollated()").is_err());
+
+        let disabled = Program::compile("'a'.foldCase()").unwrap().execute(&Context::new());
+        assert!(disabled.is_err());
+    }
+}
@@ -0,0 +1,251 @@
+//! Host functions: functions a context binds for the programs executed against it
+//!
+//! Host functions are added to the interpreter context of each evaluation next to the
+//! library's own, and never replace a built-in function or one of an enabled
+//! extension. A name can have typed overloads, each for arguments of given types, and
+//! an untyped implementation taking any arguments; a call goes to the first overload
+//! its arguments match, and to the untyped implementation if none does. Global calls
+//! such as `hasRole(user, 'admin')` and member calls such as `user.hasRole('admin')`
+//! have implementations of their own, and a member call passes its receiver first.
+//! The untyped implementation can limit how many arguments it takes, filling in
+//! defaults for those a call leaves out.
+//!
+//! Under `Context::set_soft_errors` a failing host function evaluates to an error
+//! value instead of failing the evaluation; see `soft_errors`.
+//!
+//! A program compiled with late-bound functions also gets a stub for each function it
+//! calls that nothing provides, so the call fails the evaluation with
+//! `Error::UnresolvedFunction` rather than an error naming an undeclared reference.
+
+use super::ext::Extensions;
+use super::value::{error_value, type_name_of, value_type_name, value_type_of};
+use super::{Arity, Context, Error, HostFunction, Value, BUILTIN_FUNCTIONS};
+use crate::ffi::CelValueType;
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::{Context as CelContext, ExecutionError, FunctionContext};
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// An implementation of a host function for arguments of the types in `params`
+#[derive(Clone)]
+struct Overload {
+    params: Vec<CelValueType>,
+    result: CelValueType,
+    function: Arc<HostFunction>,
+}
+
+impl fmt::Debug for Overload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Overload")
+            .field("params", &self.params)
+            .field("result", &self.result)
+            .finish_non_exhaustive()
+    }
+}
+
+/// An implementation of a host function for arguments of any types, in number within
+/// `arity`
+#[derive(Clone)]
+struct Untyped {
+    arity: Arity,
+    function: Arc<HostFunction>,
+}
+
+impl Untyped {
+    fn call(&self, args: &[Value]) -> Result<Value, String> {
+        let Arity { min, max, defaults } = &self.arity;
+        if args.len() < *min || max.is_some_and(|max| args.len() > max) {
+            return Err(format!("takes {}, got {}", self.arity, args.len()));
+        }
+        let missing = defaults.get(args.len() - min..).unwrap_or_default();
+        let args = if missing.is_empty() {
+            Cow::Borrowed(args)
+        } else {
+            Cow::Owned(args.iter().chain(missing).cloned().collect())
+        };
+        (self.function)(&args)
+    }
+}
+
+/// The implementations of one function name for global or for member calls
+#[derive(Clone, Default)]
+struct Implementations {
+    typed: Vec<Overload>,
+    untyped: Option<Untyped>,
+}
+
+impl fmt::Debug for Implementations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Implementations")
+            .field("typed", &self.typed)
+            .field("untyped", &self.untyped.as_ref().map(|untyped| &untyped.arity))
+            .finish()
+    }
+}
+
+impl Implementations {
+    const fn is_empty(&self) -> bool {
+        self.typed.is_empty() && self.untyped.is_none()
+    }
+
+    /// Add an overload, replacing any for the same argument types
+    fn add(&mut self, params: &[CelValueType], result: CelValueType, function: Arc<HostFunction>) {
+        let overload = Overload {
+            params: params.to_vec(),
+            result,
+            function,
+        };
+        match self.typed.iter_mut().find(|existing| existing.params == params) {
+            Some(existing) => *existing = overload,
+            None => self.typed.push(overload),
+        }
+    }
+
+    /// Call the overload `args` match, or else the untyped implementation
+    fn call(&self, args: &[Value]) -> Result<Value, String> {
+        let matches = |overload: &&Overload| {
+            overload.params.len() == args.len()
+                && overload
+                    .params
+                    .iter()
+                    .zip(args)
+                    .all(|(param, arg)| *param == value_type_of(arg))
+        };
+        if let Some(overload) = self.typed.iter().find(matches) {
+            let value = (overload.function)(args)?;
+            if value_type_of(&value) != overload.result {
+                return Err(format!(
+                    "returned {} instead of {}",
+                    type_name_of(&value),
+                    value_type_name(overload.result)
+                ));
+            }
+            return Ok(value);
+        }
+
+        match &self.untyped {
+            Some(untyped) => untyped.call(args),
+            None => {
+                let types: Vec<&str> = args.iter().map(type_name_of).collect();
+                Err(format!("no overload takes ({})", types.join(", ")))
+            }
+        }
+    }
+}
+
+/// The implementations registered under one function name
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Overloads {
+    global: Implementations,
+    member: Implementations,
+}
+
+impl Overloads {
+    const fn implementations(&mut self, member: bool) -> &mut Implementations {
+        if member {
+            &mut self.member
+        } else {
+            &mut self.global
+        }
+    }
+
+    /// Set the implementation for arguments of any types, of member calls if `member`
+    pub(crate) fn set_untyped(&mut self, member: bool, arity: Arity, function: Arc<HostFunction>) {
+        self.implementations(member).untyped = Some(Untyped { arity, function });
+    }
+
+    /// Add an overload, of member calls taking the receiver's type first if `member`
+    pub(crate) fn add(
+        &mut self,
+        member: bool,
+        params: &[CelValueType],
+        result: CelValueType,
+        function: Arc<HostFunction>,
+    ) {
+        self.implementations(member).add(params, result, function);
+    }
+
+    /// Call the implementation for `args`, passing `receiver` first for a member call
+    fn call(&self, receiver: Option<&Value>, args: &[Value]) -> Result<Value, String> {
+        let Some(receiver) = receiver else {
+            if self.global.is_empty() {
+                return Err("can only be called as a method".to_string());
+            }
+            return self.global.call(args);
+        };
+        if self.member.is_empty() {
+            return Err("cannot be called as a method".to_string());
+        }
+
+        let mut all = Vec::with_capacity(args.len() + 1);
+        all.push(receiver.clone());
+        all.extend_from_slice(args);
+        self.member.call(&all)
+    }
+}
+
+/// The late-bound function one evaluation called without a host function bound to it
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Unresolved(Arc<Mutex<Option<String>>>);
+
+impl Unresolved {
+    /// `result`, turned into `Error::UnresolvedFunction` if it failed after calling an
+    /// unbound function
+    pub(crate) fn resolve(&self, result: Result<Value, Error>) -> Result<Value, Error> {
+        let called = self.0.lock().unwrap_or_else(PoisonError::into_inner).take();
+        match (result, called) {
+            (Err(Error::Execution(_)), Some(name)) => Err(Error::UnresolvedFunction(name)),
+            (result, _) => result,
+        }
+    }
+}
+
+/// Whether `name` is a built-in function or one of an enabled extension
+fn is_library_function(name: &str, extensions: Extensions) -> bool {
+    BUILTIN_FUNCTIONS.contains(&name) || extensions.provides(name)
+}
+
+/// Add the host functions of `context` to `ctx`
+pub(crate) fn register(ctx: &mut CelContext, context: &Context, extensions: Extensions) {
+    for (name, overloads) in context.functions() {
+        if is_library_function(name, extensions) {
+            continue;
+        }
+        let overloads = Arc::clone(overloads);
+        let owned = name.to_string();
+        let soft = context.soft_errors();
+        ctx.add_function(name, move |ftx: &FunctionContext, Arguments(args): Arguments| {
+            overloads.call(ftx.this.as_ref(), &args).or_else(|e| {
+                let error = ExecutionError::function_error(&owned, e);
+                if soft {
+                    Ok(error_value(error.to_string()))
+                } else {
+                    Err(error)
+                }
+            })
+        });
+    }
+}
+
+/// Add a stub to `ctx` for each of `called` that neither the library nor `context`
+/// provides, recording the call in `unresolved`
+pub(crate) fn register_stubs<'a>(
+    ctx: &mut CelContext,
+    context: &Context,
+    extensions: Extensions,
+    called: impl IntoIterator<Item = &'a str>,
+    unresolved: &Unresolved,
+) {
+    for name in called {
+        if is_library_function(name, extensions) || context.has_function(name) {
+            continue;
+        }
+        let slot = Arc::clone(&unresolved.0);
+        let owned = name.to_string();
+        ctx.add_function(name, move |Arguments(_): Arguments| -> Result<Value, ExecutionError> {
+            *slot.lock().unwrap_or_else(PoisonError::into_inner) = Some(owned.clone());
+            Err(ExecutionError::function_error(&owned, "function is not registered"))
+        });
+    }
+}
@@ -0,0 +1,120 @@
+//! Conservative result types, inferred from the shape of an expression
+//!
+//! The library has no type checker, since variables carry no declared types, so only
+//! expressions whose root settles its own type are inferred: literals, comparisons,
+//! `&&` and `||`, operators on operands of one inferred type, conditionals whose
+//! branches agree, and built-in functions with a fixed result type such as `size` and
+//! `string`. Everything else, such as a variable or a host function call, has no
+//! inferred type. An inferred type holds for every execution that succeeds, whatever
+//! the context's null propagation, numeric promotion or missing-key policy.
+
+use crate::ffi::CelValueType;
+use cel_parser::{ArithmeticOp, Atom, Expression, RelationOp, UnaryOp};
+
+/// The type every successful evaluation of `expression` returns, if its shape alone
+/// determines it
+pub(crate) fn inferred_type(expression: &Expression) -> Option<CelValueType> {
+    match expression {
+        Expression::Atom(atom) => Some(atom_type(atom)),
+        Expression::List(_) => Some(CelValueType::List),
+        Expression::Map(_) => Some(CelValueType::Map),
+        Expression::And(..) | Expression::Or(..) => Some(CelValueType::Bool),
+        // Null propagation makes ordering comparisons with a null operand null
+        Expression::Relation(_, RelationOp::Equals | RelationOp::NotEquals | RelationOp::In, _) => {
+            Some(CelValueType::Bool)
+        }
+        Expression::Relation(left, _, right) => {
+            inferred_type(left)?;
+            inferred_type(right).map(|_| CelValueType::Bool)
+        }
+        Expression::Arithmetic(left, op, right) => {
+            let operands = same_type(left, right)?;
+            match (operands, op) {
+                (CelValueType::Int | CelValueType::Uint | CelValueType::Double, _)
+                | (
+                    CelValueType::String | CelValueType::Bytes | CelValueType::List,
+                    ArithmeticOp::Add,
+                ) => Some(operands),
+                _ => None,
+            }
+        }
+        Expression::Unary(UnaryOp::Not | UnaryOp::DoubleNot, operand) => {
+            inferred_type(operand).filter(|t| *t == CelValueType::Bool)
+        }
+        Expression::Unary(UnaryOp::Minus | UnaryOp::DoubleMinus, operand) => {
+            inferred_type(operand).filter(|t| matches!(t, CelValueType::Int | CelValueType::Double))
+        }
+        Expression::Ternary(_, if_true, if_false) => same_type(if_true, if_false),
+        Expression::FunctionCall(function, ..) => match function.as_ref() {
+            Expression::Ident(name) => function_type(name),
+            _ => None,
+        },
+        Expression::Member(..) | Expression::Ident(_) => None,
+    }
+}
+
+/// The type `left` and `right` both have, if they are inferred to have the same
+fn same_type(left: &Expression, right: &Expression) -> Option<CelValueType> {
+    let left = inferred_type(left)?;
+    (inferred_type(right)? == left).then_some(left)
+}
+
+const fn atom_type(atom: &Atom) -> CelValueType {
+    match atom {
+        Atom::Int(_) => CelValueType::Int,
+        Atom::UInt(_) => CelValueType::Uint,
+        Atom::Float(_) => CelValueType::Double,
+        Atom::String(_) => CelValueType::String,
+        Atom::Bytes(_) => CelValueType::Bytes,
+        Atom::Bool(_) => CelValueType::Bool,
+        Atom::Null => CelValueType::Null,
+    }
+}
+
+/// The result type of the built-in function `name`, which host functions and
+/// extensions cannot replace, if it has only one
+fn function_type(name: &str) -> Option<CelValueType> {
+    match name {
+        "all" | "exists" | "exists_one" | "has" | "contains" | "startsWith" | "endsWith"
+        | "matches" => Some(CelValueType::Bool),
+        "map" | "filter" => Some(CelValueType::List),
+        "size" | "int" => Some(CelValueType::Int),
+        "uint" => Some(CelValueType::Uint),
+        "double" => Some(CelValueType::Double),
+        "string" => Some(CelValueType::String),
+        "bytes" => Some(CelValueType::Bytes),
+        "timestamp" => Some(CelValueType::Timestamp),
+        "duration" => Some(CelValueType::Duration),
+        "type" => Some(CelValueType::Type),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn infer(expression: &str) -> Option<CelValueType> {
+        inferred_type(&cel_parser::parse(expression).unwrap())
+    }
+
+    #[test]
+    fn test_inferred_type() {
+        assert_eq!(infer("1"), Some(CelValueType::Int));
+        assert_eq!(infer("'a' + 'b'"), Some(CelValueType::String));
+        assert_eq!(infer("1 + 2.0"), None);
+        assert_eq!(infer("-(2.0 * 3.0)"), Some(CelValueType::Double));
+        assert_eq!(infer("a == b && c"), Some(CelValueType::Bool));
+        assert_eq!(infer("a < 1"), None);
+        assert_eq!(infer("size(a) < 1"), Some(CelValueType::Bool));
+        assert_eq!(infer("x ? 1u : 2u"), Some(CelValueType::Uint));
+        assert_eq!(infer("x ? 1 : 'one'"), None);
+        assert_eq!(infer("xs.all(x, x > 0)"), Some(CelValueType::Bool));
+        assert_eq!(infer("xs.map(x, x * 2)"), Some(CelValueType::List));
+        assert_eq!(infer("{'a': 1}"), Some(CelValueType::Map));
+        assert_eq!(infer("!a"), None);
+        assert_eq!(infer("a"), None);
+        assert_eq!(infer("a.b"), None);
+        assert_eq!(infer("hasRole(user)"), None);
+    }
+}
@@ -0,0 +1,76 @@
+//! Identifier storage shared by every program and context in the process
+//!
+//! Rule sets repeat the same variable names across thousands of expressions, so
+//! each distinct name is stored once and handed out as a reference-counted `Arc<str>`.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+
+fn table() -> &'static Mutex<HashSet<Arc<str>>> {
+    static TABLE: OnceLock<Mutex<HashSet<Arc<str>>>> = OnceLock::new();
+    TABLE.get_or_init(|| Mutex::new(HashSet::new()))
+}
+
+/// The shared copy of `name`, adding it to the table if it is new
+#[must_use]
+pub fn intern(name: &str) -> Arc<str> {
+    let mut table = table().lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(interned) = table.get(name) {
+        return Arc::clone(interned);
+    }
+    let interned: Arc<str> = Arc::from(name);
+    table.insert(Arc::clone(&interned));
+    interned
+}
+
+/// Number of distinct names in the table
+#[must_use]
+pub fn interned_count() -> usize {
+    table().lock().unwrap_or_else(PoisonError::into_inner).len()
+}
+
+/// Drop the names no program or context uses any more, returning how many were dropped
+///
+/// Names stay in the table after their last user is freed until this is called, so
+/// long-running hosts that churn through distinct names should call it periodically.
+pub fn purge_interned() -> usize {
+    let mut table = table().lock().unwrap_or_else(PoisonError::into_inner);
+    let before = table.len();
+    table.retain(|name| Arc::strong_count(name) > 1);
+    before - table.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Context, Program};
+
+    #[test]
+    fn test_intern_shares_storage() {
+        let first = intern("intern_test_request");
+        let second = intern(&String::from("intern_test_request"));
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(!Arc::ptr_eq(&first, &intern("intern_test_response")));
+        assert!(interned_count() >= 2);
+
+        drop((first, second));
+        purge_interned();
+        let table = table().lock().unwrap_or_else(PoisonError::into_inner);
+        assert!(!table.contains("intern_test_request"));
+        assert!(!table.contains("intern_test_response"));
+    }
+
+    #[test]
+    fn test_programs_and_contexts_share_names() {
+        let program = Program::compile("intern_test_shared > 1").unwrap();
+        let mut context = Context::new();
+        context.add_variable("intern_test_shared".to_string(), serde_json::json!(2));
+        let key = context.get_variables().keys().next().unwrap();
+
+        // The table, the program, the context and `shared` itself
+        let shared = intern("intern_test_shared");
+        assert!(Arc::ptr_eq(key, &shared));
+        assert_eq!(Arc::strong_count(&shared), 4);
+        assert_eq!(program.variables(), ["intern_test_shared"]);
+    }
+}
@@ -0,0 +1,206 @@
+//! Limits on comprehension iterations: a cap on the elements one comprehension may
+//! iterate over, and a budget on the iterations one evaluation may run
+//!
+//! Under a cap the program is rewritten so the range of every `all`, `exists`,
+//! `exists_one`, `map` and `filter` goes through `_iteration_limit`, which passes a list
+//! or map through unchanged and fails the evaluation once it has more elements than
+//! the cap. The check runs before the first iteration, so a comprehension over an
+//! oversized list costs nothing beyond its size. Under a budget the first argument
+//! after the iteration variable, which the interpreter evaluates once per iteration,
+//! goes through `_iteration`, which charges one iteration and fails the evaluation
+//! once the budget is spent.
+
+use super::options::COMPREHENSIONS;
+use super::{Error, Value};
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::{Context as CelContext, ExecutionError};
+use cel_parser::{Atom, Expression, Member};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const LIMIT: &str = "_iteration_limit";
+const ITERATION: &str = "_iteration";
+
+/// The comprehension iterations one evaluation has run, against the budget of its
+/// context
+#[derive(Debug, Clone)]
+pub(crate) struct Budget {
+    spent: Arc<AtomicUsize>,
+    limit: usize,
+}
+
+impl Budget {
+    /// A budget of `limit` iterations, where 0 is no limit
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            spent: Arc::default(),
+            limit,
+        }
+    }
+
+    /// `result`, turned into `Error::IterationLimit` if the evaluation spent more than
+    /// the budget
+    ///
+    /// This holds even if the evaluation went on to succeed, as it can when soft
+    /// errors turned the failed iterations into values.
+    pub(crate) fn resolve(&self, result: Result<Value, Error>) -> Result<Value, Error> {
+        if self.limit > 0 && self.spent.load(Ordering::Relaxed) > self.limit {
+            return Err(Error::IterationLimit { limit: self.limit });
+        }
+        result
+    }
+}
+
+/// Add the functions of `with_iteration_checks` to `ctx`: the range check for ranges
+/// capped at `max_range` elements, and the iteration count charging `budget`
+pub(crate) fn register(ctx: &mut CelContext, max_range: usize, budget: &Budget) {
+    if max_range > 0 {
+        ctx.add_function(LIMIT, |Arguments(args): Arguments| {
+            let [range, Value::Int(max)] = args.as_slice() else {
+                return Err(ExecutionError::function_error(LIMIT, "invalid arguments"));
+            };
+            let len = match range {
+                Value::List(items) => items.len(),
+                Value::Map(map) => map.map.len(),
+                _ => 0,
+            };
+            if i64::try_from(len).unwrap_or(i64::MAX) > *max {
+                return Err(ExecutionError::function_error(
+                    "comprehension",
+                    format!("range of {len} elements exceeds the limit of {max} iterations"),
+                ));
+            }
+            Ok(range.clone())
+        });
+    }
+
+    if budget.limit > 0 {
+        let budget = budget.clone();
+        ctx.add_function(ITERATION, move |Arguments(args): Arguments| {
+            let [value] = args.as_slice() else {
+                return Err(ExecutionError::function_error(ITERATION, "expected one argument"));
+            };
+            let spent = budget.spent.fetch_add(1, Ordering::Relaxed).saturating_add(1);
+            if spent > budget.limit {
+                return Err(ExecutionError::function_error(
+                    "comprehension",
+                    format!("evaluation exceeded its budget of {} iterations", budget.limit),
+                ));
+            }
+            Ok(value.clone())
+        });
+    }
+}
+
+/// `expression` with the range of every comprehension checked against `max_range`,
+/// unless it is 0, and with every iteration counted if `counted`
+pub(crate) fn with_iteration_checks(
+    expression: &Expression,
+    max_range: usize,
+    counted: bool,
+) -> Expression {
+    let recurse = |expression: &Expression| with_iteration_checks(expression, max_range, counted);
+    let boxed = |expression: &Expression| Box::new(recurse(expression));
+    let call = |function: &str, args: Vec<Expression>| {
+        Expression::FunctionCall(
+            Expression::Ident(Arc::new(function.to_string())).into(),
+            None,
+            args,
+        )
+    };
+
+    match expression {
+        Expression::FunctionCall(function, Some(range), args)
+            if matches!(function.as_ref(), Expression::Ident(name)
+                if COMPREHENSIONS.contains(&name.as_str())) =>
+        {
+            let limit = i64::try_from(max_range).unwrap_or(i64::MAX);
+            let range = match max_range {
+                0 => recurse(range),
+                _ => call(LIMIT, vec![recurse(range), Expression::Atom(Atom::Int(limit))]),
+            };
+            let args = args
+                .iter()
+                .enumerate()
+                .map(|(i, arg)| match (counted, i) {
+                    (true, 1) => call(ITERATION, vec![recurse(arg)]),
+                    _ => recurse(arg),
+                })
+                .collect();
+            Expression::FunctionCall(function.clone(), Some(Box::new(range)), args)
+        }
+        Expression::Arithmetic(left, op, right) => {
+            Expression::Arithmetic(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Relation(left, op, right) => {
+            Expression::Relation(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Ternary(condition, if_true, if_false) => {
+            Expression::Ternary(boxed(condition), boxed(if_true), boxed(if_false))
+        }
+        Expression::Or(left, right) => Expression::Or(boxed(left), boxed(right)),
+        Expression::And(left, right) => Expression::And(boxed(left), boxed(right)),
+        Expression::Unary(op, operand) => Expression::Unary(op.clone(), boxed(operand)),
+        Expression::Member(operand, member) => {
+            let member = match member.as_ref() {
+                Member::Attribute(name) => Member::Attribute(name.clone()),
+                Member::Index(index) => Member::Index(boxed(index)),
+                Member::Fields(fields) => Member::Fields(
+                    fields.iter().map(|(name, value)| (name.clone(), recurse(value))).collect(),
+                ),
+            };
+            Expression::Member(boxed(operand), member.into())
+        }
+        Expression::FunctionCall(function, target, args) => Expression::FunctionCall(
+            function.clone(),
+            target.as_deref().map(boxed),
+            args.iter().map(recurse).collect(),
+        ),
+        Expression::List(items) => Expression::List(items.iter().map(recurse).collect()),
+        Expression::Map(entries) => Expression::Map(
+            entries.iter().map(|(key, value)| (recurse(key), recurse(value))).collect(),
+        ),
+        Expression::Atom(_) | Expression::Ident(_) => expression.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{Context, Env, Error, Program, Value};
+
+    #[test]
+    fn test_comprehension_ranges_are_capped() {
+        let mut env = Env::default();
+        env.set_max_comprehension_iterations(3);
+        let context = Context::new();
+        let execute = |expression: &str| env.compile(expression).unwrap().execute(&context);
+
+        assert_eq!(execute("[1, 2, 3].all(x, x > 0)"), Ok(Value::Bool(true)));
+        assert_eq!(execute("{'a': 1}.exists(k, k == 'a')"), Ok(Value::Bool(true)));
+        assert!(execute("[1, 2, 3, 4].map(x, x * 2) == []").is_err());
+        assert!(execute("[[1, 2, 3, 4]].all(xs, xs.exists(x, x > 3))").is_err());
+        assert!(execute("size([1, 2, 3, 4]) == 4").is_ok());
+    }
+
+    #[test]
+    fn test_iteration_budget_spans_the_evaluation() {
+        let mut context = Context::new();
+        context.set_max_iterations(6);
+        let execute = |expression: &str| Program::compile(expression).unwrap().execute(&context);
+
+        assert_eq!(
+            execute("[1, 2, 3].all(x, x > 0) && [4, 5, 6].all(x, x > 0)"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            execute("[1, 2, 3, 4].all(x, [1].all(y, y > 0))"),
+            Err(Error::IterationLimit { limit: 6 })
+        );
+        // `exists` stops at the first match, and only the iterations run are charged
+        assert_eq!(execute("[1, 2, 3, 4, 5, 6, 7, 8].exists(x, x == 1)"), Ok(Value::Bool(true)));
+        assert_eq!(
+            execute("[1, 2, 3, 4, 5, 6, 7].map(x, x * 2) == []"),
+            Err(Error::IterationLimit { limit: 6 })
+        );
+    }
+}
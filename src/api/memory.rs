@@ -0,0 +1,182 @@
+//! Memory limits: a budget on the bytes one evaluation allocates
+//!
+//! The interpreter does not report its allocations, so under a limit the program is
+//! rewritten to pass every value it builds through `_alloc`: list and map literals,
+//! the results of `+` and the results of function calls, which covers conversions
+//! such as `string(bytes(s))` and the lists `map()` and `filter()` produce. `_alloc`
+//! charges the value's own size, the bytes of a string or bytes value and a slot for
+//! each list element or map entry, and fails the evaluation once the total passes the
+//! limit. Values are shared rather than copied when put into lists and maps, so their
+//! contents are only charged where they were built.
+
+use super::{Error, Value};
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::objects::Key;
+use cel_interpreter::{Context as CelContext, ExecutionError};
+use cel_parser::{ArithmeticOp, Expression, Member};
+use std::mem::size_of;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const ALLOC: &str = "_alloc";
+
+/// The bytes one evaluation has allocated, against the limit of its context
+#[derive(Debug, Clone)]
+pub(crate) struct Meter {
+    allocated: Arc<AtomicUsize>,
+    limit: usize,
+}
+
+impl Meter {
+    /// A meter for `limit` bytes, where 0 is no limit
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            allocated: Arc::default(),
+            limit,
+        }
+    }
+
+    /// `result`, turned into `Error::MemoryLimit` if it failed after passing the limit
+    pub(crate) fn resolve(&self, result: Result<Value, Error>) -> Result<Value, Error> {
+        let allocated = self.allocated.load(Ordering::Relaxed);
+        match result {
+            Err(Error::Execution(_)) if self.limit > 0 && allocated > self.limit => {
+                Err(Error::MemoryLimit {
+                    limit: self.limit,
+                    allocated,
+                })
+            }
+            result => result,
+        }
+    }
+}
+
+/// Add the `_alloc` function of `with_metering` to `ctx`, charging `meter`
+pub(crate) fn register(ctx: &mut CelContext, meter: &Meter) {
+    let meter = meter.clone();
+    ctx.add_function(ALLOC, move |Arguments(args): Arguments| {
+        let [value] = args.as_slice() else {
+            return Err(ExecutionError::function_error("alloc", "expected one argument"));
+        };
+        let size = shallow_size(value);
+        let allocated = meter.allocated.fetch_add(size, Ordering::Relaxed).saturating_add(size);
+        if allocated > meter.limit {
+            return Err(ExecutionError::function_error(
+                "alloc",
+                format!("evaluation exceeded its memory limit of {} bytes", meter.limit),
+            ));
+        }
+        Ok(value.clone())
+    });
+}
+
+/// The bytes `value` holds itself, leaving out the values it shares with others
+fn shallow_size(value: &Value) -> usize {
+    const SLOT: usize = size_of::<Value>();
+    match value {
+        Value::String(text) => text.len(),
+        Value::Bytes(bytes) => bytes.len(),
+        Value::List(items) => items.len() * SLOT,
+        Value::Map(map) => map.map.len() * (size_of::<Key>() + SLOT),
+        _ => 0,
+    }
+}
+
+/// `expression` with the values it builds passed through `_alloc`
+///
+/// Message constructions and the names of called functions are left as they are.
+pub(crate) fn with_metering(expression: &Expression) -> Expression {
+    let recurse = |expression: &Expression| with_metering(expression);
+    let boxed = |expression: &Expression| Box::new(recurse(expression));
+    let metered = |expression: Expression| {
+        Expression::FunctionCall(
+            Expression::Ident(Arc::new(ALLOC.to_string())).into(),
+            None,
+            vec![expression],
+        )
+    };
+
+    match expression {
+        Expression::List(items) => metered(Expression::List(items.iter().map(recurse).collect())),
+        Expression::Map(entries) => metered(Expression::Map(
+            entries.iter().map(|(key, value)| (recurse(key), recurse(value))).collect(),
+        )),
+        Expression::Arithmetic(left, op @ ArithmeticOp::Add, right) => {
+            metered(Expression::Arithmetic(boxed(left), op.clone(), boxed(right)))
+        }
+        Expression::Arithmetic(left, op, right) => {
+            Expression::Arithmetic(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::FunctionCall(function, target, args) => metered(Expression::FunctionCall(
+            function.clone(),
+            target.as_deref().map(boxed),
+            args.iter().map(recurse).collect(),
+        )),
+        Expression::Member(operand, member) => match member.as_ref() {
+            Member::Attribute(_) => Expression::Member(boxed(operand), member.clone()),
+            Member::Index(index) => {
+                Expression::Member(boxed(operand), Member::Index(boxed(index)).into())
+            }
+            Member::Fields(fields) => Expression::Member(
+                operand.clone(),
+                Member::Fields(
+                    fields.iter().map(|(name, value)| (name.clone(), recurse(value))).collect(),
+                )
+                .into(),
+            ),
+        },
+        Expression::Relation(left, op, right) => {
+            Expression::Relation(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Ternary(condition, if_true, if_false) => {
+            Expression::Ternary(boxed(condition), boxed(if_true), boxed(if_false))
+        }
+        Expression::Or(left, right) => Expression::Or(boxed(left), boxed(right)),
+        Expression::And(left, right) => Expression::And(boxed(left), boxed(right)),
+        Expression::Unary(op, operand) => Expression::Unary(op.clone(), boxed(operand)),
+        Expression::Atom(_) | Expression::Ident(_) => expression.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::optimize::unparse;
+    use crate::api::{Context, Program};
+    use serde_json::json;
+
+    fn execute(expression: &str, max_memory: usize) -> Result<Value, Error> {
+        let mut context = Context::new();
+        context.add_variable("s".to_string(), json!("x".repeat(100)));
+        context.set_max_memory(max_memory);
+        Program::compile(expression).unwrap().execute(&context)
+    }
+
+    #[test]
+    fn test_memory_rewrite() {
+        let parsed = cel_parser::parse("[a, b + c].size() > a * 2").unwrap();
+        assert_eq!(
+            unparse(&with_metering(&parsed)),
+            unparse(
+                &cel_parser::parse("_alloc(_alloc([a, _alloc(b + c)]).size()) > a * 2").unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        assert_eq!(execute("size(s + s)", 1_000), Ok(Value::Int(200)));
+        assert_eq!(execute("size(string(bytes(s)))", 1_000), Ok(Value::Int(100)));
+
+        let expression = "[s, s].map(x, x + x + x + x + x + x).size()";
+        assert_eq!(execute(expression, 0), Ok(Value::Int(2)));
+        assert!(matches!(
+            execute(expression, 1_000),
+            Err(Error::MemoryLimit { limit: 1_000, allocated }) if allocated > 1_000
+        ));
+        assert!(matches!(
+            execute("s + 1", 1_000),
+            Err(Error::Execution(message)) if !message.contains("memory")
+        ));
+    }
+}
@@ -0,0 +1,306 @@
+//! Policies for fields and keys a map does not have
+//!
+//! The interpreter fails when an expression selects a key its map lacks. Under the
+//! other policies the program is rewritten so every selection and index goes through
+//! a function that looks the key up itself: `a.b` becomes `_select(a, "b", "a.b")`,
+//! with the original text kept to name the key when it is missing. Null propagation
+//! uses the same functions, which then also let a null operand through as null.
+
+use super::optimize::unparse;
+use super::{Error, Value};
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::objects::Key;
+use cel_interpreter::{Context as CelContext, ExecutionError};
+use cel_parser::{Atom, Expression, Member};
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex, PoisonError};
+
+pub(crate) const SELECT: &str = "_select";
+pub(crate) const INDEX: &str = "_index";
+pub(crate) const HAS: &str = "_has";
+
+/// What selecting a field or key that a map does not have evaluates to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum MissingKeys {
+    /// Evaluation fails with a "no such key" error, and `has()` is false
+    #[default]
+    Error,
+    /// The selection is null, and `has()` is still false
+    Null,
+    /// The selection is unknown, and so is `has()` on it: an evaluation that needs the
+    /// key fails with `Error::Unknown`, naming every missing key it reached
+    Unknown,
+}
+
+/// The keys one evaluation found missing under `MissingKeys::Unknown`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Missing(Arc<Mutex<BTreeSet<String>>>);
+
+impl Missing {
+    /// `result`, turned into `Error::Unknown` if it failed after reaching a missing key
+    pub(crate) fn resolve(&self, result: Result<Value, Error>) -> Result<Value, Error> {
+        let missing = std::mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner));
+        match result {
+            Err(Error::Execution(_)) if !missing.is_empty() => {
+                Err(Error::Unknown(missing.into_iter().collect()))
+            }
+            result => result,
+        }
+    }
+
+    /// The outcome of selecting the missing key written as `path`
+    fn reached(&self, policy: MissingKeys, path: &str) -> Result<Value, ExecutionError> {
+        match policy {
+            MissingKeys::Null => return Ok(Value::Null),
+            MissingKeys::Unknown => {
+                self.0.lock().unwrap_or_else(PoisonError::into_inner).insert(path.to_string());
+            }
+            MissingKeys::Error => {}
+        }
+        Err(ExecutionError::function_error("select", format!("no such key: {path}")))
+    }
+
+    /// The outcome of indexing `operand` with `index`, written as `path`, under `policy`
+    pub(crate) fn index(
+        &self,
+        policy: MissingKeys,
+        null_safe: bool,
+        operand: &Value,
+        index: &Value,
+        path: &str,
+    ) -> Result<Value, ExecutionError> {
+        match (operand, index) {
+            (Value::Map(map), index) => {
+                // Not a key the map could lack, so no policy applies, as in the interpreter
+                let key = map_key(index).ok_or_else(|| {
+                    ExecutionError::function_error("index", "unsupported key type")
+                })?;
+                match map.map.get(&key) {
+                    Some(value) => Ok(value.clone()),
+                    None => self.reached(policy, path),
+                }
+            }
+            (Value::Null, _) if null_safe => Ok(Value::Null),
+            (Value::List(items), Value::Int(i)) => list_item(items, usize::try_from(*i).ok(), path),
+            (Value::List(items), Value::UInt(i)) => {
+                list_item(items, usize::try_from(*i).ok(), path)
+            }
+            _ => Err(ExecutionError::function_error("index", format!("cannot index {path}"))),
+        }
+    }
+}
+
+/// Add the lookup functions of `with_lookups` to `ctx`, recording missing keys in
+/// `missing`, and letting null operands through as null if `null_safe`
+pub(crate) fn register(
+    ctx: &mut CelContext,
+    policy: MissingKeys,
+    null_safe: bool,
+    missing: &Missing,
+) {
+    let selected = missing.clone();
+    ctx.add_function(SELECT, move |Arguments(args): Arguments| {
+        let [operand, Value::String(field), Value::String(path)] = args.as_slice() else {
+            return Err(ExecutionError::function_error("select", "invalid arguments"));
+        };
+        match operand {
+            Value::Map(map) => match map.map.get(&Key::String(Arc::clone(field))) {
+                Some(value) => Ok(value.clone()),
+                None => selected.reached(policy, path),
+            },
+            Value::Null if null_safe => Ok(Value::Null),
+            _ => Err(ExecutionError::function_error("select", format!("no such key: {path}"))),
+        }
+    });
+
+    let indexed = missing.clone();
+    ctx.add_function(INDEX, move |Arguments(args): Arguments| {
+        let [operand, index, Value::String(path)] = args.as_slice() else {
+            return Err(ExecutionError::function_error("index", "invalid arguments"));
+        };
+        indexed.index(policy, null_safe, operand, index, path)
+    });
+
+    let tested = missing.clone();
+    ctx.add_function(HAS, move |Arguments(args): Arguments| {
+        let [operand, Value::String(field), Value::String(path)] = args.as_slice() else {
+            return Err(ExecutionError::function_error("has", "invalid arguments"));
+        };
+        match operand {
+            Value::Map(map) if map.map.contains_key(&Key::String(Arc::clone(field))) => {
+                Ok(Value::Bool(true))
+            }
+            Value::Map(_) if policy == MissingKeys::Unknown => tested.reached(policy, path),
+            Value::Map(_) => Ok(Value::Bool(false)),
+            Value::Null if null_safe => Ok(Value::Bool(false)),
+            _ => Err(ExecutionError::function_error("has", format!("no such key: {path}"))),
+        }
+    });
+}
+
+pub(crate) fn map_key(index: &Value) -> Option<Key> {
+    match index {
+        Value::Int(i) => Some(Key::Int(*i)),
+        Value::UInt(u) => Some(Key::Uint(*u)),
+        Value::Bool(b) => Some(Key::Bool(*b)),
+        Value::String(s) => Some(Key::String(Arc::clone(s))),
+        _ => None,
+    }
+}
+
+fn list_item(items: &[Value], index: Option<usize>, path: &str) -> Result<Value, ExecutionError> {
+    index.and_then(|i| items.get(i)).cloned().ok_or_else(|| {
+        ExecutionError::function_error("index", format!("index out of range: {path}"))
+    })
+}
+
+/// `expression` with field selections and indexes replaced by calls to the lookup
+/// functions, and with `has()` too if `lookup_has`
+///
+/// Message constructions and the names of called functions are left as they are.
+pub(crate) fn with_lookups(expression: &Expression, lookup_has: bool) -> Expression {
+    let recurse = |expression: &Expression| with_lookups(expression, lookup_has);
+    let boxed = |expression: &Expression| Box::new(recurse(expression));
+    let call = |function: &str, args: Vec<Expression>| {
+        Expression::FunctionCall(
+            Expression::Ident(Arc::new(function.to_string())).into(),
+            None,
+            args,
+        )
+    };
+    let text = |text: String| Expression::Atom(Atom::String(Arc::new(text)));
+
+    match expression {
+        Expression::Member(operand, member) => match member.as_ref() {
+            Member::Attribute(name) => call(
+                SELECT,
+                vec![
+                    recurse(operand),
+                    text(name.to_string()),
+                    text(unparse(expression)),
+                ],
+            ),
+            Member::Index(index) => {
+                call(INDEX, vec![recurse(operand), recurse(index), text(unparse(expression))])
+            }
+            Member::Fields(fields) => Expression::Member(
+                operand.clone(),
+                Member::Fields(
+                    fields.iter().map(|(name, value)| (name.clone(), recurse(value))).collect(),
+                )
+                .into(),
+            ),
+        },
+        Expression::FunctionCall(function, None, args) if is_has(function) => {
+            let [Expression::Member(operand, member)] = args.as_slice() else {
+                return expression.clone();
+            };
+            let Member::Attribute(name) = member.as_ref() else {
+                return expression.clone();
+            };
+            if lookup_has {
+                let path = unparse(&args[0]);
+                call(HAS, vec![recurse(operand), text(name.to_string()), text(path)])
+            } else {
+                let selection = Expression::Member(boxed(operand), member.clone());
+                Expression::FunctionCall(function.clone(), None, vec![selection])
+            }
+        }
+        Expression::FunctionCall(function, target, args) => Expression::FunctionCall(
+            function.clone(),
+            target.as_deref().map(boxed),
+            args.iter().map(recurse).collect(),
+        ),
+        Expression::Arithmetic(left, op, right) => {
+            Expression::Arithmetic(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Relation(left, op, right) => {
+            Expression::Relation(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Ternary(condition, if_true, if_false) => {
+            Expression::Ternary(boxed(condition), boxed(if_true), boxed(if_false))
+        }
+        Expression::Or(left, right) => Expression::Or(boxed(left), boxed(right)),
+        Expression::And(left, right) => Expression::And(boxed(left), boxed(right)),
+        Expression::Unary(op, operand) => Expression::Unary(op.clone(), boxed(operand)),
+        Expression::List(items) => Expression::List(items.iter().map(recurse).collect()),
+        Expression::Map(entries) => Expression::Map(
+            entries.iter().map(|(key, value)| (recurse(key), recurse(value))).collect(),
+        ),
+        Expression::Atom(_) | Expression::Ident(_) => expression.clone(),
+    }
+}
+
+fn is_has(function: &Expression) -> bool {
+    matches!(function, Expression::Ident(name) if name.as_str() == "has")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Context, Program};
+    use serde_json::json;
+
+    fn execute(expression: &str, policy: MissingKeys) -> Result<Value, Error> {
+        let mut context = Context::new();
+        context.add_variable("user".to_string(), json!({"name": "a", "tags": {"x": 1}}));
+        context.set_missing_keys(policy);
+        Program::compile(expression).unwrap().execute(&context)
+    }
+
+    #[test]
+    fn test_missing_keys_rewrite() {
+        let parsed = cel_parser::parse("has(a.b) && a.b[0] == 1 && math.least(1, 2) > 0").unwrap();
+        assert_eq!(
+            unparse(&with_lookups(&parsed, false)),
+            unparse(
+                &cel_parser::parse(
+                    "has(a.b) && _index(_select(a, 'b', 'a.b'), 0, 'a.b[0]') == 1 \
+                     && math.least(1, 2) > 0"
+                )
+                .unwrap()
+            )
+        );
+        assert!(unparse(&with_lookups(&parsed, true)).contains("_has(a, \"b\", \"a.b\")"));
+    }
+
+    #[test]
+    fn test_missing_keys_policies() {
+        assert!(matches!(execute("user.age > 1", MissingKeys::Error), Err(Error::Execution(_))));
+        assert_eq!(execute("has(user.age)", MissingKeys::Error), Ok(Value::Bool(false)));
+
+        assert_eq!(execute("user.age", MissingKeys::Null), Ok(Value::Null));
+        assert_eq!(execute("user.tags['y'] == null", MissingKeys::Null), Ok(Value::Bool(true)));
+        assert_eq!(execute("has(user.age)", MissingKeys::Null), Ok(Value::Bool(false)));
+        assert_eq!(
+            execute("user.name + '!'", MissingKeys::Null),
+            Ok(Value::String("a!".to_string().into()))
+        );
+
+        assert_eq!(
+            execute("user.age > 1 || user.tags['y'] == 2", MissingKeys::Unknown),
+            Err(Error::Unknown(vec!["user.age".to_string()]))
+        );
+        assert_eq!(
+            execute("has(user.age)", MissingKeys::Unknown),
+            Err(Error::Unknown(vec!["user.age".to_string()]))
+        );
+        assert_eq!(
+            execute("has(user.name) && user.tags.x == 1", MissingKeys::Unknown),
+            Ok(Value::Bool(true))
+        );
+        assert!(matches!(execute("[1][3]", MissingKeys::Unknown), Err(Error::Execution(_))));
+    }
+
+    #[test]
+    fn test_missing_keys_unsupported_key_type() {
+        let failed = execute("user.tags[1.5]", MissingKeys::Error);
+        assert!(matches!(failed, Err(Error::Execution(_))), "{failed:?}");
+        for policy in [MissingKeys::Null, MissingKeys::Unknown] {
+            let Err(Error::Execution(message)) = execute("user.tags[1.5]", policy) else {
+                panic!("expected a double key to fail under {policy:?}");
+            };
+            assert!(message.contains("unsupported key type"), "{message}");
+        }
+    }
+}
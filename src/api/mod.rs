@@ -0,0 +1,81 @@
+//! Safe Rust API for compiling and evaluating CEL expressions
+//!
+//! The C API in [`crate::ffi`] is a thin layer over these types.
+
+mod ast;
+mod attributes;
+pub mod audit;
+pub mod batch;
+mod bigint;
+pub mod bundle;
+pub mod cancel;
+pub mod capabilities;
+mod constants;
+pub mod context;
+mod decimal;
+mod dependencies;
+mod deterministic;
+pub mod diagnostics;
+pub mod enums;
+pub mod env;
+pub mod equality;
+pub mod error;
+mod ext;
+mod functions;
+mod infer;
+pub mod intern;
+mod iterations;
+mod memory;
+pub mod missing;
+mod nan;
+mod nulls;
+mod numeric;
+pub mod optimize;
+pub mod options;
+mod patterns;
+pub mod program;
+#[cfg(feature = "proto")]
+pub mod proto;
+pub mod rename;
+pub mod render;
+pub mod ruleset;
+pub mod simplify;
+mod soft_errors;
+#[cfg(feature = "stats")]
+pub mod stats;
+pub mod template;
+mod timezones;
+pub mod trace;
+pub mod truthiness;
+pub mod value;
+
+pub use attributes::AttributeAccess;
+pub use audit::*;
+pub use batch::*;
+pub use bundle::*;
+pub use cancel::*;
+pub use capabilities::*;
+pub use context::*;
+pub use diagnostics::*;
+pub use enums::*;
+pub use env::*;
+pub use equality::*;
+pub use error::*;
+pub use intern::*;
+pub use missing::*;
+pub use optimize::*;
+pub use options::*;
+pub use program::*;
+#[cfg(feature = "proto")]
+pub use proto::*;
+pub use rename::*;
+pub use render::*;
+pub use ruleset::*;
+pub use simplify::*;
+#[cfg(feature = "stats")]
+pub use stats::*;
+pub use template::*;
+pub use timezones::{add_time_zone, set_tzdata_dir};
+pub use trace::*;
+pub use truthiness::*;
+pub use value::*;
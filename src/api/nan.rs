@@ -0,0 +1,104 @@
+//! Ordering comparisons with NaN, which CEL defines as false
+//!
+//! The interpreter fails `x < 1.0` when `x` is NaN, since NaN has no order. When a
+//! context binds a NaN double the program is rewritten so ordering comparisons go
+//! through a function that is false for a NaN operand and otherwise compares as
+//! usual. Equality needs no rewrite: NaN already equals nothing, not even itself.
+
+use super::nulls::ordered;
+use super::optimize::relation_symbol;
+use super::Value;
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::{Context as CelContext, ExecutionError};
+use cel_parser::{Atom, Expression, RelationOp};
+use std::sync::Arc;
+
+const COMPARE: &str = "_nan_compare";
+
+/// Add the function of `with_nan_comparisons` to `ctx`
+pub(crate) fn register(ctx: &mut CelContext) {
+    ctx.add_function(COMPARE, |Arguments(args): Arguments| {
+        let [left, right, Value::String(op)] = args.as_slice() else {
+            return Err(ExecutionError::function_error(COMPARE, "invalid arguments"));
+        };
+        ordered(COMPARE, op, left, right, left.partial_cmp(right))
+    });
+}
+
+/// `expression` with ordering comparisons replaced by calls to the NaN-aware function
+pub(crate) fn with_nan_comparisons(expression: &Expression) -> Expression {
+    let recurse = |expression: &Expression| with_nan_comparisons(expression);
+    let boxed = |expression: &Expression| Box::new(recurse(expression));
+
+    match expression {
+        Expression::Relation(
+            left,
+            op @ (RelationOp::LessThan
+            | RelationOp::LessThanEq
+            | RelationOp::GreaterThan
+            | RelationOp::GreaterThanEq),
+            right,
+        ) => Expression::FunctionCall(
+            Expression::Ident(Arc::new(COMPARE.to_string())).into(),
+            None,
+            vec![
+                recurse(left),
+                recurse(right),
+                Expression::Atom(Atom::String(Arc::new(relation_symbol(op).to_string()))),
+            ],
+        ),
+        Expression::Relation(left, op, right) => {
+            Expression::Relation(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Arithmetic(left, op, right) => {
+            Expression::Arithmetic(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Ternary(condition, if_true, if_false) => {
+            Expression::Ternary(boxed(condition), boxed(if_true), boxed(if_false))
+        }
+        Expression::Or(left, right) => Expression::Or(boxed(left), boxed(right)),
+        Expression::And(left, right) => Expression::And(boxed(left), boxed(right)),
+        Expression::Unary(op, operand) => Expression::Unary(op.clone(), boxed(operand)),
+        Expression::Member(operand, member) => Expression::Member(boxed(operand), member.clone()),
+        Expression::FunctionCall(function, target, args) => Expression::FunctionCall(
+            function.clone(),
+            target.as_deref().map(boxed),
+            args.iter().map(recurse).collect(),
+        ),
+        Expression::List(items) => Expression::List(items.iter().map(recurse).collect()),
+        Expression::Map(entries) => Expression::Map(
+            entries.iter().map(|(key, value)| (recurse(key), recurse(value))).collect(),
+        ),
+        Expression::Atom(_) | Expression::Ident(_) => expression.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{Context, Error, Program, Value};
+
+    fn execute(expression: &str) -> Result<Value, Error> {
+        let mut context = Context::new();
+        context.add_value_variable("nan".to_string(), Value::Float(f64::NAN));
+        context.add_value_variable("inf".to_string(), Value::Float(f64::INFINITY));
+        Program::compile(expression).unwrap().execute(&context)
+    }
+
+    #[test]
+    fn test_nan_comparisons_are_false() {
+        assert_eq!(execute("nan < 1.0 || nan >= 1.0 || 1.0 > nan"), Ok(Value::Bool(false)));
+        assert_eq!(execute("nan == nan || nan == 1.0"), Ok(Value::Bool(false)));
+        assert_eq!(execute("nan != nan && !([nan][0] <= 0.0)"), Ok(Value::Bool(true)));
+        assert_eq!(execute("inf > 1.0 && -inf < 0.0 && inf == inf"), Ok(Value::Bool(true)));
+        assert_eq!(execute("'a' < 'b' && 1 <= 2"), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn test_nan_comparisons_with_numeric_promotion() {
+        let mut context = Context::new();
+        context.add_value_variable("nan".to_string(), Value::Float(f64::NAN));
+        context.set_numeric_promotion(true);
+        let program = Program::compile("nan < 1 || 1u > nan || nan == 1").unwrap();
+        assert_eq!(program.execute(&context), Ok(Value::Bool(false)));
+    }
+}
@@ -0,0 +1,207 @@
+//! Null propagation: operators applied to null yield null instead of failing
+//!
+//! Under `Context::set_null_propagation` the program is rewritten so arithmetic,
+//! negation, `!` and ordering comparisons go through functions that return null when
+//! an operand is null and otherwise apply the operator, so `null + 1` is null as in
+//! SQL. Selecting from or indexing null goes through the lookup functions of
+//! `missing`, which return null for a null operand in this mode.
+
+use super::optimize::{arithmetic_symbol, relation_symbol};
+use super::Value;
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::{Context as CelContext, ExecutionError};
+use cel_parser::{Atom, Expression, RelationOp, UnaryOp};
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+const ARITHMETIC: &str = "_null_arithmetic";
+const COMPARE: &str = "_null_compare";
+const NEGATE: &str = "_null_negate";
+const NOT: &str = "_null_not";
+
+/// Add the functions of `with_null_propagation` to `ctx`
+pub(crate) fn register(ctx: &mut CelContext) {
+    ctx.add_function(ARITHMETIC, |Arguments(args): Arguments| {
+        let [left, right, Value::String(op)] = args.as_slice() else {
+            return Err(ExecutionError::function_error(ARITHMETIC, "invalid arguments"));
+        };
+        if matches!(left, Value::Null) || matches!(right, Value::Null) {
+            return Ok(Value::Null);
+        }
+        arithmetic(ARITHMETIC, op, left.clone(), right.clone())
+    });
+
+    ctx.add_function(COMPARE, |Arguments(args): Arguments| {
+        let [left, right, Value::String(op)] = args.as_slice() else {
+            return Err(ExecutionError::function_error(COMPARE, "invalid arguments"));
+        };
+        if matches!(left, Value::Null) || matches!(right, Value::Null) {
+            return Ok(Value::Null);
+        }
+        ordered(COMPARE, op, left, right, left.partial_cmp(right))
+    });
+
+    ctx.add_function(NEGATE, |Arguments(args): Arguments| match args.as_slice() {
+        [Value::Null] => Ok(Value::Null),
+        [Value::Int(i)] => i.checked_neg().map(Value::Int).ok_or_else(|| {
+            ExecutionError::function_error(NEGATE, format!("integer overflow negating {i}"))
+        }),
+        [Value::Float(f)] => Ok(Value::Float(-f)),
+        [value] => Err(ExecutionError::function_error(NEGATE, format!("cannot negate {value:?}"))),
+        _ => Err(ExecutionError::function_error(NEGATE, "invalid arguments")),
+    });
+
+    ctx.add_function(NOT, |Arguments(args): Arguments| match args.as_slice() {
+        [Value::Null] => Ok(Value::Null),
+        [Value::Bool(b)] => Ok(Value::Bool(!b)),
+        [value] => Err(ExecutionError::function_error(NOT, format!("cannot negate {value:?}"))),
+        _ => Err(ExecutionError::function_error(NOT, "invalid arguments")),
+    });
+}
+
+/// `left op right` for the arithmetic operator written `op`, on behalf of `function`
+pub(crate) fn arithmetic(
+    function: &str,
+    op: &str,
+    left: Value,
+    right: Value,
+) -> Result<Value, ExecutionError> {
+    match op {
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        "/" => left / right,
+        "%" => left % right,
+        op => Err(ExecutionError::function_error(function, format!("unknown operator {op}"))),
+    }
+}
+
+/// Whether `ordering` of `left` and `right` satisfies the comparison written `op`,
+/// with `None` for values that have no order
+///
+/// As CEL specifies, every ordering comparison with a NaN operand is false.
+pub(crate) fn ordered(
+    function: &str,
+    op: &str,
+    left: &Value,
+    right: &Value,
+    ordering: Option<Ordering>,
+) -> Result<Value, ExecutionError> {
+    let is_nan = |value: &Value| matches!(value, Value::Float(f) if f.is_nan());
+    if ordering.is_none() && (is_nan(left) || is_nan(right)) {
+        return Ok(Value::Bool(false));
+    }
+    let Some(ordering) = ordering else {
+        return Err(ExecutionError::function_error(
+            function,
+            format!("cannot compare {left:?} {op} {right:?}"),
+        ));
+    };
+    match op {
+        "<" => Ok(Value::Bool(ordering.is_lt())),
+        "<=" => Ok(Value::Bool(ordering.is_le())),
+        ">" => Ok(Value::Bool(ordering.is_gt())),
+        ">=" => Ok(Value::Bool(ordering.is_ge())),
+        op => Err(ExecutionError::function_error(function, format!("unknown operator {op}"))),
+    }
+}
+
+/// `expression` with arithmetic, negation, `!` and ordering comparisons replaced by
+/// calls to the null-propagating functions
+///
+/// Equality, `in`, `&&`, `||` and the ternary operator keep their usual meaning, so
+/// `x == null` still tests for null.
+pub(crate) fn with_null_propagation(expression: &Expression) -> Expression {
+    let recurse = |expression: &Expression| with_null_propagation(expression);
+    let boxed = |expression: &Expression| Box::new(recurse(expression));
+    let call = |function: &str, args: Vec<Expression>| {
+        Expression::FunctionCall(
+            Expression::Ident(Arc::new(function.to_string())).into(),
+            None,
+            args,
+        )
+    };
+    let symbol = |op: &str| Expression::Atom(Atom::String(Arc::new(op.to_string())));
+
+    match expression {
+        Expression::Arithmetic(left, op, right) => {
+            call(ARITHMETIC, vec![recurse(left), recurse(right), symbol(arithmetic_symbol(op))])
+        }
+        Expression::Relation(
+            left,
+            op @ (RelationOp::LessThan
+            | RelationOp::LessThanEq
+            | RelationOp::GreaterThan
+            | RelationOp::GreaterThanEq),
+            right,
+        ) => call(COMPARE, vec![recurse(left), recurse(right), symbol(relation_symbol(op))]),
+        Expression::Relation(left, op, right) => {
+            Expression::Relation(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Unary(op, operand) => match op {
+            UnaryOp::Minus => call(NEGATE, vec![recurse(operand)]),
+            UnaryOp::DoubleMinus => call(NEGATE, vec![call(NEGATE, vec![recurse(operand)])]),
+            UnaryOp::Not => call(NOT, vec![recurse(operand)]),
+            UnaryOp::DoubleNot => call(NOT, vec![call(NOT, vec![recurse(operand)])]),
+        },
+        Expression::Ternary(condition, if_true, if_false) => {
+            Expression::Ternary(boxed(condition), boxed(if_true), boxed(if_false))
+        }
+        Expression::Or(left, right) => Expression::Or(boxed(left), boxed(right)),
+        Expression::And(left, right) => Expression::And(boxed(left), boxed(right)),
+        Expression::Member(operand, member) => Expression::Member(boxed(operand), member.clone()),
+        Expression::FunctionCall(function, target, args) => Expression::FunctionCall(
+            function.clone(),
+            target.as_deref().map(boxed),
+            args.iter().map(recurse).collect(),
+        ),
+        Expression::List(items) => Expression::List(items.iter().map(recurse).collect()),
+        Expression::Map(entries) => Expression::Map(
+            entries.iter().map(|(key, value)| (recurse(key), recurse(value))).collect(),
+        ),
+        Expression::Atom(_) | Expression::Ident(_) => expression.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{Context, Error, MissingKeys, Program, Value};
+    use serde_json::json;
+
+    fn execute(expression: &str) -> Result<Value, Error> {
+        let mut context = Context::new();
+        context.add_variable("row".to_string(), json!({"price": null, "qty": 2, "tags": null}));
+        context.set_null_propagation(true);
+        Program::compile(expression).unwrap().execute(&context)
+    }
+
+    #[test]
+    fn test_null_propagation() {
+        assert_eq!(execute("null + 1"), Ok(Value::Null));
+        assert_eq!(execute("row.price * row.qty"), Ok(Value::Null));
+        assert_eq!(execute("-row.price"), Ok(Value::Null));
+        assert_eq!(execute("row.price > 10"), Ok(Value::Null));
+        assert_eq!(execute("row.tags.first"), Ok(Value::Null));
+        assert_eq!(execute("row.tags[0] + 1"), Ok(Value::Null));
+        assert_eq!(execute("row.price == null"), Ok(Value::Bool(true)));
+        assert_eq!(execute("has(row.tags.first)"), Ok(Value::Bool(false)));
+
+        assert_eq!(execute("row.qty * 3 - 1"), Ok(Value::Int(5)));
+        assert_eq!(execute("-row.qty < 0 && !(row.qty >= 3)"), Ok(Value::Bool(true)));
+        assert!(matches!(execute("row.discount + 1"), Err(Error::Execution(_))));
+        assert!(matches!(execute("row.qty + 'a'"), Err(Error::Execution(_))));
+    }
+
+    #[test]
+    fn test_null_propagation_with_missing_keys() {
+        let mut context = Context::new();
+        context.add_variable("row".to_string(), json!({"price": null}));
+        context.set_null_propagation(true);
+        context.set_missing_keys(MissingKeys::Null);
+        let program = Program::compile("row.discount * row.price").unwrap();
+        assert_eq!(program.execute(&context), Ok(Value::Null));
+
+        context.set_null_propagation(false);
+        assert!(matches!(program.execute(&context), Err(Error::Execution(_))));
+    }
+}
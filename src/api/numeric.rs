@@ -0,0 +1,205 @@
+//! Heterogeneous numeric operators: int, uint and double mixed without conversions
+//!
+//! CEL only applies arithmetic and ordering to operands of the same numeric type, so
+//! `count < 1.5` fails for an int `count`. Under `Context::set_numeric_promotion` the
+//! program is rewritten so arithmetic, comparisons and equality go through functions
+//! that promote mixed operands first:
+//!
+//! - Comparisons and equality between int and uint are exact, and between a double and
+//!   either integer type are done in double, so `1 == 1.0`, `2u > -1` and `1 < 1.5`.
+//! - Arithmetic with a double operand converts the other operand to double.
+//! - Arithmetic between int and uint is done in int, failing if the uint or the result
+//!   falls outside the int range.
+//! - Big integers compare exactly with ints, uints and each other. Arithmetic between a
+//!   big integer and an int, uint or big integer is done in 128 bits, giving a big
+//!   integer and failing on overflow.
+//! - Decimals compare exactly with ints, uints, big integers and each other, and
+//!   arithmetic between them is exact, giving a decimal. With a double, both are
+//!   converted to double.
+//!
+//! Operands of the same type, and non-numeric operands, behave as usual.
+
+use super::bigint::{self, as_i128};
+use super::decimal::{self, as_decimal};
+use super::ext::{as_f64, compare_numbers};
+use super::nulls::{arithmetic, ordered};
+use super::optimize::{arithmetic_symbol, relation_symbol};
+use super::value::{bigint_parts, decimal_parts};
+use super::Value;
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::{Context as CelContext, ExecutionError};
+use cel_parser::{Atom, Expression, RelationOp};
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+const ARITHMETIC: &str = "_numeric_arithmetic";
+const COMPARE: &str = "_numeric_compare";
+
+/// Add the functions of `with_numeric_promotion` to `ctx`, letting null operands of
+/// arithmetic and ordering through as null if `null_safe`
+pub(crate) fn register(ctx: &mut CelContext, null_safe: bool) {
+    ctx.add_function(ARITHMETIC, move |Arguments(args): Arguments| {
+        let [left, right, Value::String(op)] = args.as_slice() else {
+            return Err(ExecutionError::function_error(ARITHMETIC, "invalid arguments"));
+        };
+        if null_safe && (matches!(left, Value::Null) || matches!(right, Value::Null)) {
+            return Ok(Value::Null);
+        }
+        if decimal_parts(left).is_some() || decimal_parts(right).is_some() {
+            if let (Some(left), Some(right)) = (as_decimal(left), as_decimal(right)) {
+                return decimal::arithmetic(op, left, right);
+            }
+        }
+        if bigint_parts(left).is_some() || bigint_parts(right).is_some() {
+            if let (Some(left), Some(right)) = (as_i128(left), as_i128(right)) {
+                return bigint::arithmetic(op, left, right);
+            }
+        }
+        let (left, right) = promote(left, right)?;
+        arithmetic(ARITHMETIC, op, left, right)
+    });
+
+    ctx.add_function(COMPARE, move |Arguments(args): Arguments| {
+        let [left, right, Value::String(op)] = args.as_slice() else {
+            return Err(ExecutionError::function_error(COMPARE, "invalid arguments"));
+        };
+        let equality = matches!(op.as_str(), "==" | "!=");
+        if !is_number(left) || !is_number(right) {
+            if equality {
+                return Ok(Value::Bool((left == right) == (op.as_str() == "==")));
+            }
+            if null_safe && (matches!(left, Value::Null) || matches!(right, Value::Null)) {
+                return Ok(Value::Null);
+            }
+            return ordered(COMPARE, op, left, right, left.partial_cmp(right));
+        }
+
+        let ordering = match (as_decimal(left), as_decimal(right)) {
+            (Some(left), Some(right)) => Some(left.compare(right)),
+            _ => compare_numbers(&as_double(left), &as_double(right)),
+        };
+        match op.as_str() {
+            "==" => Ok(Value::Bool(ordering == Some(Ordering::Equal))),
+            "!=" => Ok(Value::Bool(ordering != Some(Ordering::Equal))),
+            op => ordered(COMPARE, op, left, right, ordering),
+        }
+    });
+}
+
+fn is_number(value: &Value) -> bool {
+    matches!(value, Value::Float(_)) || as_decimal(value).is_some()
+}
+
+/// `value` with a big integer or decimal converted to double, and anything else
+/// unchanged
+#[allow(clippy::cast_precision_loss)]
+fn as_double(value: &Value) -> Value {
+    if let Some(decimal) = decimal_parts(value) {
+        return Value::Float(decimal.to_f64());
+    }
+    bigint_parts(value).map_or_else(|| value.clone(), |n| Value::Float(n as f64))
+}
+
+/// `left` and `right` converted to a common numeric type for arithmetic
+fn promote(left: &Value, right: &Value) -> Result<(Value, Value), ExecutionError> {
+    let int = |u: u64| {
+        i64::try_from(u).map(Value::Int).map_err(|_| {
+            ExecutionError::function_error(ARITHMETIC, format!("{u}u is out of the int range"))
+        })
+    };
+    let (left, right) = (&as_double(left), &as_double(right));
+    Ok(match (left, right) {
+        (Value::Float(_), Value::Int(_) | Value::UInt(_))
+        | (Value::Int(_) | Value::UInt(_), Value::Float(_)) => {
+            (Value::Float(as_f64(left)), Value::Float(as_f64(right)))
+        }
+        (Value::Int(_), Value::UInt(u)) => (left.clone(), int(*u)?),
+        (Value::UInt(u), Value::Int(_)) => (int(*u)?, right.clone()),
+        _ => (left.clone(), right.clone()),
+    })
+}
+
+/// `expression` with arithmetic, comparisons and equality replaced by calls to the
+/// promoting functions
+pub(crate) fn with_numeric_promotion(expression: &Expression) -> Expression {
+    let recurse = |expression: &Expression| with_numeric_promotion(expression);
+    let boxed = |expression: &Expression| Box::new(recurse(expression));
+    let call = |function: &str, args: Vec<Expression>| {
+        Expression::FunctionCall(
+            Expression::Ident(Arc::new(function.to_string())).into(),
+            None,
+            args,
+        )
+    };
+    let symbol = |op: &str| Expression::Atom(Atom::String(Arc::new(op.to_string())));
+
+    match expression {
+        Expression::Arithmetic(left, op, right) => {
+            call(ARITHMETIC, vec![recurse(left), recurse(right), symbol(arithmetic_symbol(op))])
+        }
+        Expression::Relation(left, RelationOp::In, right) => {
+            Expression::Relation(boxed(left), RelationOp::In, boxed(right))
+        }
+        Expression::Relation(left, op, right) => {
+            call(COMPARE, vec![recurse(left), recurse(right), symbol(relation_symbol(op))])
+        }
+        Expression::Ternary(condition, if_true, if_false) => {
+            Expression::Ternary(boxed(condition), boxed(if_true), boxed(if_false))
+        }
+        Expression::Or(left, right) => Expression::Or(boxed(left), boxed(right)),
+        Expression::And(left, right) => Expression::And(boxed(left), boxed(right)),
+        Expression::Unary(op, operand) => Expression::Unary(op.clone(), boxed(operand)),
+        Expression::Member(operand, member) => Expression::Member(boxed(operand), member.clone()),
+        Expression::FunctionCall(function, target, args) => Expression::FunctionCall(
+            function.clone(),
+            target.as_deref().map(boxed),
+            args.iter().map(recurse).collect(),
+        ),
+        Expression::List(items) => Expression::List(items.iter().map(recurse).collect()),
+        Expression::Map(entries) => Expression::Map(
+            entries.iter().map(|(key, value)| (recurse(key), recurse(value))).collect(),
+        ),
+        Expression::Atom(_) | Expression::Ident(_) => expression.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{Context, Error, Program, Value};
+    use serde_json::json;
+
+    fn execute(expression: &str) -> Result<Value, Error> {
+        let mut context = Context::new();
+        context.add_variable("count".to_string(), json!(1));
+        context.add_variable("ratio".to_string(), json!(0.5));
+        context.set_numeric_promotion(true);
+        Program::compile(expression).unwrap().execute(&context)
+    }
+
+    #[test]
+    fn test_numeric_promotion_comparisons() {
+        assert_eq!(execute("count < 1.5"), Ok(Value::Bool(true)));
+        assert_eq!(execute("count == 1.0 && 1u == count"), Ok(Value::Bool(true)));
+        assert_eq!(execute("2u > -1 && -1 < 0u"), Ok(Value::Bool(true)));
+        assert_eq!(execute("18446744073709551615u > 9223372036854775807"), Ok(Value::Bool(true)));
+        assert_eq!(execute("count != 1.5 && 'a' < 'b' && 'a' != 1"), Ok(Value::Bool(true)));
+        assert!(matches!(execute("'a' < 1"), Err(Error::Execution(_))));
+    }
+
+    #[test]
+    fn test_numeric_promotion_arithmetic() {
+        assert_eq!(execute("count + ratio"), Ok(Value::Float(1.5)));
+        assert_eq!(execute("3u * ratio"), Ok(Value::Float(1.5)));
+        assert_eq!(execute("count - 2u"), Ok(Value::Int(-1)));
+        assert_eq!(execute("7 / 2"), Ok(Value::Int(3)));
+        assert!(matches!(execute("count + 18446744073709551615u"), Err(Error::Execution(_))));
+
+        let mut context = Context::new();
+        context.set_numeric_promotion(true);
+        context.set_null_propagation(true);
+        for expression in ["null + 1.5", "null < 2u"] {
+            let program = Program::compile(expression).unwrap();
+            assert_eq!(program.execute(&context), Ok(Value::Null));
+        }
+    }
+}
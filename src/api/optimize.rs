@@ -0,0 +1,599 @@
+//! Constant folding for compiled programs
+//!
+//! Subexpressions whose operands are all literals are evaluated once, when the
+//! program is optimized, and replaced by their result. Conditionals with a literal
+//! condition keep only the branch taken. Anything that fails to evaluate is left
+//! in place so the error still surfaces when the program runs.
+
+use super::ext::Extensions;
+use super::options::COMPREHENSIONS;
+use cel_interpreter::objects::Key;
+use cel_interpreter::{Context as CelContext, Program as CelProgram, Value};
+use cel_parser::{ArithmeticOp, Atom, Expression, Member, RelationOp, UnaryOp};
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// Built-in functions without side effects, safe to evaluate on literal arguments
+const PURE_FUNCTIONS: [&str; 11] = [
+    "bytes",
+    "contains",
+    "double",
+    "endsWith",
+    "int",
+    "max",
+    "min",
+    "size",
+    "startsWith",
+    "string",
+    "uint",
+];
+
+/// Size of a program before and after `Program::optimize`, in expression nodes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Optimization {
+    pub nodes_before: usize,
+    pub nodes_after: usize,
+}
+
+/// `expression` with its constant subexpressions folded, evaluating them with the
+/// functions of `extensions` available
+pub(crate) fn fold(expression: &Expression, extensions: Extensions) -> Expression {
+    let mut ctx = CelContext::default();
+    extensions.register(&mut ctx);
+    let evaluate = |expression: &Expression| {
+        let program = CelProgram::compile(&unparse(expression)).ok()?;
+        program.execute(&ctx).ok()
+    };
+    Folder {
+        evaluate: &evaluate,
+        extensions,
+    }
+    .fold(expression)
+}
+
+struct Folder<'a> {
+    evaluate: &'a dyn Fn(&Expression) -> Option<Value>,
+    extensions: Extensions,
+}
+
+impl Folder<'_> {
+    fn fold(&self, expression: &Expression) -> Expression {
+        match expression {
+            Expression::Ternary(condition, if_true, if_false) => match self.fold(condition) {
+                Expression::Atom(Atom::Bool(true)) => self.fold(if_true),
+                Expression::Atom(Atom::Bool(false)) => self.fold(if_false),
+                condition => Expression::Ternary(
+                    condition.into(),
+                    self.fold(if_true).into(),
+                    self.fold(if_false).into(),
+                ),
+            },
+            // The interpreter evaluates the left operand first, so only a literal on
+            // the left can short-circuit without hiding an error on the right
+            Expression::And(left, right) => match self.fold(left) {
+                short @ Expression::Atom(Atom::Bool(false)) => short,
+                left => self.evaluate(Expression::And(left.into(), self.fold(right).into())),
+            },
+            Expression::Or(left, right) => match self.fold(left) {
+                short @ Expression::Atom(Atom::Bool(true)) => short,
+                left => self.evaluate(Expression::Or(left.into(), self.fold(right).into())),
+            },
+            Expression::Arithmetic(left, op, right) => self.evaluate(Expression::Arithmetic(
+                self.fold(left).into(),
+                op.clone(),
+                self.fold(right).into(),
+            )),
+            Expression::Relation(left, op, right) => self.evaluate(Expression::Relation(
+                self.fold(left).into(),
+                op.clone(),
+                self.fold(right).into(),
+            )),
+            Expression::Unary(op, operand) => {
+                self.evaluate(Expression::Unary(op.clone(), self.fold(operand).into()))
+            }
+            Expression::Member(operand, member) => {
+                let member = match member.as_ref() {
+                    Member::Attribute(name) => Member::Attribute(name.clone()),
+                    Member::Index(index) => Member::Index(self.fold(index).into()),
+                    Member::Fields(fields) => Member::Fields(
+                        fields
+                            .iter()
+                            .map(|(name, value)| (name.clone(), self.fold(value)))
+                            .collect(),
+                    ),
+                };
+                self.evaluate(Expression::Member(self.fold(operand).into(), member.into()))
+            }
+            Expression::FunctionCall(function, target, args) => {
+                let name = match function.as_ref() {
+                    Expression::Ident(name) => name.as_str(),
+                    _ => "",
+                };
+                let target = target.as_ref().map(|target| Box::new(self.fold(target)));
+                // `has` inspects the shape of its argument rather than its value
+                let args = match name {
+                    "has" => args.clone(),
+                    _ => args.iter().map(|arg| self.fold(arg)).collect(),
+                };
+
+                let folded = Expression::FunctionCall(function.clone(), target, args);
+                if PURE_FUNCTIONS.contains(&name) || self.extensions.provides(name) {
+                    self.evaluate(folded)
+                } else {
+                    folded
+                }
+            }
+            Expression::List(items) => {
+                Expression::List(items.iter().map(|item| self.fold(item)).collect())
+            }
+            Expression::Map(entries) => Expression::Map(
+                entries.iter().map(|(key, value)| (self.fold(key), self.fold(value))).collect(),
+            ),
+            Expression::Atom(_) | Expression::Ident(_) => expression.clone(),
+        }
+    }
+
+    /// `expression` replaced by its value if its operands are literals and it
+    /// evaluates to something a literal can express
+    fn evaluate(&self, expression: Expression) -> Expression {
+        if !operands_literal(&expression) {
+            return expression;
+        }
+
+        (self.evaluate)(&expression)
+            .and_then(|value| literal(&value))
+            .unwrap_or(expression)
+    }
+}
+
+/// Whether every operand of `expression` is a literal
+fn operands_literal(expression: &Expression) -> bool {
+    match expression {
+        Expression::Arithmetic(left, _, right)
+        | Expression::Relation(left, _, right)
+        | Expression::And(left, right)
+        | Expression::Or(left, right) => is_literal(left) && is_literal(right),
+        Expression::Unary(_, operand) => is_literal(operand),
+        Expression::Member(..) => member_literals(expression),
+        Expression::FunctionCall(_, target, args) => {
+            target.as_deref().is_none_or(is_literal) && args.iter().all(is_literal)
+        }
+        _ => false,
+    }
+}
+
+/// Whether `expression` selects a field or index of a literal by a literal
+fn member_literals(expression: &Expression) -> bool {
+    let Expression::Member(operand, member) = expression else {
+        return false;
+    };
+    is_literal(operand)
+        && match member.as_ref() {
+            Member::Attribute(_) => true,
+            Member::Index(index) => is_literal(index),
+            Member::Fields(_) => false,
+        }
+}
+
+/// Whether `expression` is a literal, or a list or map of literals
+pub(crate) fn is_literal(expression: &Expression) -> bool {
+    match expression {
+        Expression::Atom(_) => true,
+        Expression::List(items) => items.iter().all(is_literal),
+        Expression::Map(entries) => {
+            entries.iter().all(|(key, value)| is_literal(key) && is_literal(value))
+        }
+        _ => false,
+    }
+}
+
+/// The literal expression for `value`, if it has one
+pub(crate) fn literal(value: &Value) -> Option<Expression> {
+    let atom = match value {
+        Value::Int(i) => Atom::Int(*i),
+        Value::UInt(u) => Atom::UInt(*u),
+        Value::Float(f) if f.is_finite() => Atom::Float(*f),
+        Value::String(s) => Atom::String(s.clone()),
+        Value::Bytes(b) => Atom::Bytes(b.clone()),
+        Value::Bool(b) => Atom::Bool(*b),
+        Value::Null => Atom::Null,
+        Value::List(items) => {
+            return items.iter().map(literal).collect::<Option<_>>().map(Expression::List);
+        }
+        Value::Map(map) => {
+            let mut entries = map
+                .map
+                .iter()
+                .map(|(key, value)| Some((Expression::Atom(key_atom(key)), literal(value)?)))
+                .collect::<Option<Vec<_>>>()?;
+            // Map iteration order varies, so sort for a stable optimized source
+            entries.sort_by_cached_key(|(key, _)| unparse(key));
+            return Some(Expression::Map(entries));
+        }
+        _ => return None,
+    };
+    Some(Expression::Atom(atom))
+}
+
+fn key_atom(key: &Key) -> Atom {
+    match key {
+        Key::Int(i) => Atom::Int(*i),
+        Key::Uint(u) => Atom::UInt(*u),
+        Key::Bool(b) => Atom::Bool(*b),
+        Key::String(s) => Atom::String(s.clone()),
+    }
+}
+
+/// Number of expression nodes in `expression`
+pub(crate) fn node_count(expression: &Expression) -> usize {
+    let children = match expression {
+        Expression::Arithmetic(left, _, right)
+        | Expression::Relation(left, _, right)
+        | Expression::And(left, right)
+        | Expression::Or(left, right) => node_count(left) + node_count(right),
+        Expression::Ternary(condition, if_true, if_false) => {
+            node_count(condition) + node_count(if_true) + node_count(if_false)
+        }
+        Expression::Unary(_, operand) => node_count(operand),
+        Expression::Member(operand, member) => {
+            node_count(operand)
+                + match member.as_ref() {
+                    Member::Attribute(_) => 0,
+                    Member::Index(index) => node_count(index),
+                    Member::Fields(fields) => {
+                        fields.iter().map(|(_, value)| node_count(value)).sum()
+                    }
+                }
+        }
+        Expression::FunctionCall(_, target, args) => {
+            target.as_deref().map_or(0, node_count) + args.iter().map(node_count).sum::<usize>()
+        }
+        Expression::List(items) => items.iter().map(node_count).sum(),
+        Expression::Map(entries) => {
+            entries.iter().map(|(key, value)| node_count(key) + node_count(value)).sum()
+        }
+        Expression::Atom(_) | Expression::Ident(_) => 0,
+    };
+    children + 1
+}
+
+/// `expression` with the body of every comprehension wrapped in a call to
+/// `checkpoint`, which returns its argument, so the function runs once per iteration
+pub(crate) fn with_checkpoints(expression: &Expression, checkpoint: &str) -> Expression {
+    let recurse = |expression: &Expression| with_checkpoints(expression, checkpoint);
+    let boxed = |expression: &Expression| Box::new(recurse(expression));
+    match expression {
+        Expression::Arithmetic(left, op, right) => {
+            Expression::Arithmetic(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Relation(left, op, right) => {
+            Expression::Relation(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Ternary(condition, if_true, if_false) => {
+            Expression::Ternary(boxed(condition), boxed(if_true), boxed(if_false))
+        }
+        Expression::Or(left, right) => Expression::Or(boxed(left), boxed(right)),
+        Expression::And(left, right) => Expression::And(boxed(left), boxed(right)),
+        Expression::Unary(op, operand) => Expression::Unary(op.clone(), boxed(operand)),
+        Expression::Member(operand, member) => {
+            let member = match member.as_ref() {
+                Member::Attribute(name) => Member::Attribute(name.clone()),
+                Member::Index(index) => Member::Index(boxed(index)),
+                Member::Fields(fields) => Member::Fields(
+                    fields.iter().map(|(name, value)| (name.clone(), recurse(value))).collect(),
+                ),
+            };
+            Expression::Member(boxed(operand), member.into())
+        }
+        Expression::FunctionCall(function, target, args) => {
+            let comprehension = target.is_some()
+                && matches!(function.as_ref(), Expression::Ident(name)
+                    if COMPREHENSIONS.contains(&name.as_str()));
+            // The first argument names the iteration variable; the rest are evaluated
+            // once per element
+            let args = args
+                .iter()
+                .enumerate()
+                .map(|(i, arg)| match (comprehension, i) {
+                    (true, 1..) => Expression::FunctionCall(
+                        Expression::Ident(Arc::new(checkpoint.to_string())).into(),
+                        None,
+                        vec![recurse(arg)],
+                    ),
+                    _ => recurse(arg),
+                })
+                .collect();
+            Expression::FunctionCall(function.clone(), target.as_deref().map(boxed), args)
+        }
+        Expression::List(items) => Expression::List(items.iter().map(recurse).collect()),
+        Expression::Map(entries) => Expression::Map(
+            entries.iter().map(|(key, value)| (recurse(key), recurse(value))).collect(),
+        ),
+        Expression::Atom(_) | Expression::Ident(_) => expression.clone(),
+    }
+}
+
+/// CEL source that parses back to `expression`
+///
+/// Operands are parenthesized wherever precedence could matter, so the output is
+/// meant for the parser rather than for people.
+pub(crate) fn unparse(expression: &Expression) -> String {
+    let mut out = String::new();
+    write_expression(&mut out, expression);
+    out
+}
+
+fn write_expression(out: &mut String, expression: &Expression) {
+    match expression {
+        Expression::Arithmetic(left, op, right) => {
+            write_binary(out, left, arithmetic_symbol(op), right);
+        }
+        Expression::Relation(left, op, right) => {
+            write_binary(out, left, relation_symbol(op), right);
+        }
+        Expression::Ternary(condition, if_true, if_false) => {
+            write_operand(out, condition);
+            out.push_str(" ? ");
+            write_operand(out, if_true);
+            out.push_str(" : ");
+            write_operand(out, if_false);
+        }
+        Expression::Or(left, right) => write_binary(out, left, "||", right),
+        Expression::And(left, right) => write_binary(out, left, "&&", right),
+        Expression::Unary(op, operand) => {
+            out.push_str(match op {
+                UnaryOp::Not => "!",
+                UnaryOp::DoubleNot => "!!",
+                UnaryOp::Minus => "-",
+                UnaryOp::DoubleMinus => "--",
+            });
+            write_operand(out, operand);
+        }
+        Expression::Member(operand, member) => {
+            write_operand(out, operand);
+            match member.as_ref() {
+                Member::Attribute(name) => {
+                    out.push('.');
+                    out.push_str(name);
+                }
+                Member::Index(index) => {
+                    out.push('[');
+                    write_expression(out, index);
+                    out.push(']');
+                }
+                Member::Fields(fields) => {
+                    out.push('{');
+                    for (i, (name, value)) in fields.iter().enumerate() {
+                        if i > 0 {
+                            out.push_str(", ");
+                        }
+                        out.push_str(name);
+                        out.push_str(": ");
+                        write_expression(out, value);
+                    }
+                    out.push('}');
+                }
+            }
+        }
+        Expression::FunctionCall(function, target, args) => {
+            if let Some(target) = target {
+                write_operand(out, target);
+                out.push('.');
+            }
+            write_expression(out, function);
+            out.push('(');
+            write_list(out, args);
+            out.push(')');
+        }
+        Expression::List(items) => {
+            out.push('[');
+            write_list(out, items);
+            out.push(']');
+        }
+        Expression::Map(entries) => {
+            out.push('{');
+            for (i, (key, value)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_expression(out, key);
+                out.push_str(": ");
+                write_expression(out, value);
+            }
+            out.push('}');
+        }
+        Expression::Atom(atom) => write_atom(out, atom),
+        Expression::Ident(name) => out.push_str(name),
+    }
+}
+
+/// The operator as it is written in CEL source
+pub(crate) const fn arithmetic_symbol(op: &ArithmeticOp) -> &'static str {
+    match op {
+        ArithmeticOp::Add => "+",
+        ArithmeticOp::Subtract => "-",
+        ArithmeticOp::Divide => "/",
+        ArithmeticOp::Multiply => "*",
+        ArithmeticOp::Modulus => "%",
+    }
+}
+
+/// The operator as it is written in CEL source
+pub(crate) const fn relation_symbol(op: &RelationOp) -> &'static str {
+    match op {
+        RelationOp::LessThan => "<",
+        RelationOp::LessThanEq => "<=",
+        RelationOp::GreaterThan => ">",
+        RelationOp::GreaterThanEq => ">=",
+        RelationOp::Equals => "==",
+        RelationOp::NotEquals => "!=",
+        RelationOp::In => "in",
+    }
+}
+
+fn write_binary(out: &mut String, left: &Expression, op: &str, right: &Expression) {
+    write_operand(out, left);
+    out.push(' ');
+    out.push_str(op);
+    out.push(' ');
+    write_operand(out, right);
+}
+
+fn write_list(out: &mut String, items: &[Expression]) {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        write_expression(out, item);
+    }
+}
+
+/// `expression` as an operand, parenthesized unless it is a primary expression
+fn write_operand(out: &mut String, expression: &Expression) {
+    let primary = match expression {
+        Expression::Atom(Atom::Int(i)) => *i >= 0,
+        Expression::Atom(Atom::Float(f)) => f.is_sign_positive(),
+        Expression::Atom(_)
+        | Expression::Ident(_)
+        | Expression::List(_)
+        | Expression::Map(_)
+        | Expression::Member(..)
+        | Expression::FunctionCall(..) => true,
+        _ => false,
+    };
+    if primary {
+        write_expression(out, expression);
+    } else {
+        out.push('(');
+        write_expression(out, expression);
+        out.push(')');
+    }
+}
+
+fn write_atom(out: &mut String, atom: &Atom) {
+    match atom {
+        // The literal 9223372036854775808 is out of range, so the minimum cannot be
+        // written as its negation
+        Atom::Int(i64::MIN) => out.push_str("(-9223372036854775807 - 1)"),
+        Atom::Int(i) => {
+            let _ = write!(out, "{i}");
+        }
+        Atom::UInt(u) => {
+            let _ = write!(out, "{u}u");
+        }
+        // Debug formatting is the shortest that round-trips and always includes a
+        // fraction or an exponent, as CEL float literals require
+        Atom::Float(f) => {
+            let _ = write!(out, "{f:?}");
+        }
+        Atom::String(s) => {
+            out.push('"');
+            for c in s.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    '\r' => out.push_str("\\r"),
+                    '\t' => out.push_str("\\t"),
+                    c if c.is_control() => {
+                        let _ = write!(out, "\\u{:04x}", u32::from(c));
+                    }
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+        }
+        Atom::Bytes(bytes) => {
+            out.push_str("b\"");
+            for &byte in bytes.iter() {
+                if (byte.is_ascii_graphic() && byte != b'"' && byte != b'\\') || byte == b' ' {
+                    out.push(char::from(byte));
+                } else {
+                    let _ = write!(out, "\\x{byte:02x}");
+                }
+            }
+            out.push('"');
+        }
+        Atom::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Atom::Null => out.push_str("null"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn optimize(expression: &str) -> String {
+        let parsed = cel_parser::parse(expression).unwrap();
+        unparse(&fold(&parsed, Extensions::default()))
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        assert_eq!(optimize("1 + 2 * 3"), "7");
+        assert_eq!(optimize("'a' + 'b' == x"), "\"ab\" == x");
+        assert_eq!(optimize("size('xyz') > n"), "3 > n");
+        assert_eq!(optimize("[1, 2] + [3]"), "[1, 2, 3]");
+        assert_eq!(optimize("{'a': 1 + 1}.a"), "2");
+        assert_eq!(optimize("-(2 - 5) * x"), "3 * x");
+    }
+
+    #[test]
+    fn test_prune_branches() {
+        assert_eq!(optimize("1 < 2 ? a : b"), "a");
+        assert_eq!(optimize("false ? a : b + (1 + 1)"), "b + 2");
+        assert_eq!(optimize("false && a"), "false");
+        assert_eq!(optimize("true || a"), "true");
+        assert_eq!(optimize("a && false"), "a && false");
+        assert_eq!(optimize("c ? 1 + 1 : 3"), "c ? 2 : 3");
+    }
+
+    #[test]
+    fn test_keep_failures_and_macros() {
+        assert_eq!(optimize("1 / 0"), "1 / 0");
+        assert_eq!(optimize("has({'a': 1}.a)"), "has({\"a\": 1}.a)");
+        assert_eq!(optimize("[1, 2].all(x, x > 0 + 0)"), "[1, 2].all(x, x > 0)");
+        assert_eq!(optimize("now(1 + 1)"), "now(2)");
+    }
+
+    #[test]
+    fn test_unparse_round_trips() {
+        for expression in [
+            "a.b[c] in {1: [-1, 2u, -2.5, 1e+300]}",
+            "!(a || b) && -x < --y ? f(a, b) : m.g(1).h",
+            "\"quote\\\" \\\\ \\n\\u0001\" + b\"\\x00\\xff\\x22\"",
+            "Msg{name: 'a', n: 1} == a",
+        ] {
+            let parsed = cel_parser::parse(expression).unwrap();
+            assert_eq!(cel_parser::parse(&unparse(&parsed)).unwrap(), parsed, "{expression}");
+        }
+    }
+
+    #[test]
+    fn test_unparse_min_int() {
+        let folded = optimize("-9223372036854775807 - 1 == x");
+        assert_eq!(folded, "(-9223372036854775807 - 1) == x");
+        assert_eq!(optimize(&folded), folded);
+
+        let program = cel_interpreter::Program::compile(&optimize("-9223372036854775807 - 1"));
+        let value = program.unwrap().execute(&cel_interpreter::Context::default());
+        assert_eq!(value.unwrap(), cel_interpreter::Value::Int(i64::MIN));
+    }
+
+    #[test]
+    fn test_node_count() {
+        let parsed = cel_parser::parse("a + size('xyz') > 1").unwrap();
+        assert_eq!(node_count(&parsed), 6);
+    }
+
+    #[test]
+    fn test_with_checkpoints() {
+        let parsed = cel_parser::parse("xs.all(x, x > 0) && size(xs.map(y, y + 1)) > 1").unwrap();
+        assert_eq!(
+            unparse(&with_checkpoints(&parsed, "check")),
+            "xs.all(x, check(x > 0)) && size(xs.map(y, check(y + 1))) > 1"
+        );
+        let parsed = cel_parser::parse("all(x, y) + f(x)").unwrap();
+        assert_eq!(unparse(&with_checkpoints(&parsed, "check")), "all(x, y) + f(x)");
+    }
+}
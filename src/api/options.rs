@@ -0,0 +1,444 @@
+use super::ext::{Desugared, Extensions};
+use super::patterns::{PatternCache, PatternLimits};
+use super::{CompileError, Error};
+use cel_parser::{Atom, Expression, Member};
+use std::collections::BTreeSet;
+
+/// Macros that `CompileOptions::disabled_macros` can name
+pub const MACROS: [&str; 6] = ["has", "all", "exists", "exists_one", "map", "filter"];
+
+/// Comprehension macros, which iterate over a list or map
+pub(crate) const COMPREHENSIONS: [&str; 5] = ["all", "exists", "exists_one", "map", "filter"];
+
+/// Limits checked before an expression is compiled, for rejecting untrusted input
+/// early, and the extension libraries the compiled program can call
+///
+/// A limit of 0 disables that check. The length and bracket nesting are checked on
+/// the source text before it is parsed, so pathological input never reaches the
+/// parser; the remaining limits are checked on the parsed expression.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompileOptions {
+    /// Longest accepted expression, in bytes
+    pub max_expression_length: usize,
+    /// Deepest accepted nesting of brackets or subexpressions
+    pub max_nesting_depth: usize,
+    /// Most comprehension macros (`all`, `exists`, `exists_one`, `map`, `filter`)
+    pub max_comprehensions: usize,
+    /// Macros the expression may not use, from [`MACROS`]
+    pub disabled_macros: Vec<String>,
+    /// Reject expressions that call `matches()`
+    pub disable_regex: bool,
+    /// Longest accepted `matches()` pattern, in bytes
+    pub max_regex_length: usize,
+    /// Largest accepted compiled `matches()` pattern, in bytes of the regex engine's
+    /// size limit
+    pub max_regex_size: usize,
+    /// Enable the strings extension: `lowerAscii`, `upperAscii`, `replace`, `split`,
+    /// `join`, `indexOf`, `substring` and `trim`
+    pub string_ext: bool,
+    /// Enable the math extension: `math.greatest`, `math.least`, `math.abs`,
+    /// `math.ceil`, `math.floor` and `math.round`
+    pub math_ext: bool,
+    /// Enable the lists extension: `flatten`, `distinct`, `sort` and `slice`
+    pub list_ext: bool,
+    /// Enable optional values: `optional.of`, `optional.none`, `optional.ofNonZeroValue`,
+    /// `hasValue`, `value`, `orValue`, `or`, and `a.?b` and `a[?k]` selection
+    pub optional_ext: bool,
+    /// Enable the unicode extension: `foldCase`, `equalsIgnoreCase`, `normalize`,
+    /// `compareCollated` and `sortCollated`
+    pub unicode_ext: bool,
+    /// Enable the encoders extension: `base64.encode`, `base64.decode`, `hex.encode`,
+    /// `hex.decode`, `url.encode` and `url.decode`
+    pub encoders_ext: bool,
+    /// Enable the JSON extension: `json.parse` and `json.stringify`
+    pub json_ext: bool,
+}
+
+impl CompileOptions {
+    /// # Errors
+    ///
+    /// Returns `Error::Compile` for the first limit the expression exceeds.
+    pub fn check(&self, expression: &str) -> Result<(), Error> {
+        self.check_source(expression)?;
+
+        // Parse errors are reported by the compile that follows
+        let desugared = self.desugar(expression);
+        let source = desugared.as_ref().map_or(expression, |d| d.source.as_str());
+        let Ok(parsed) = cel_parser::parse(source) else {
+            return Ok(());
+        };
+        let stats = ExpressionStats::of(&parsed, &self.disabled_macros);
+        self.check_stats(expression, &stats)?;
+        PatternCache::new(self.pattern_limits(), &stats.regex_literals)
+            .map(drop)
+            .map_err(|e| violation(expression, e))
+    }
+
+    /// Check the limits that apply to the source text, before it is parsed
+    pub(crate) fn check_source(&self, expression: &str) -> Result<(), Error> {
+        if self.max_expression_length > 0 && expression.len() > self.max_expression_length {
+            return Err(violation(
+                expression,
+                format!("expression is longer than {} bytes", self.max_expression_length),
+            ));
+        }
+        if self.max_nesting_depth > 0 {
+            if let Some(offset) = bracket_overflow(expression, self.max_nesting_depth) {
+                return Err(Error::Compile(CompileError::at(
+                    expression,
+                    offset,
+                    1,
+                    self.depth_message(),
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check the limits that apply to the parsed expression
+    pub(crate) fn check_stats(
+        &self,
+        expression: &str,
+        stats: &ExpressionStats,
+    ) -> Result<(), Error> {
+        if self.max_nesting_depth > 0 && stats.depth > self.max_nesting_depth {
+            return Err(violation(expression, self.depth_message()));
+        }
+        if self.max_comprehensions > 0 && stats.comprehensions > self.max_comprehensions {
+            return Err(violation(
+                expression,
+                format!("expression uses more than {} comprehensions", self.max_comprehensions),
+            ));
+        }
+        if let Some(name) = &stats.disabled_macro {
+            return Err(violation(expression, format!("macro '{name}' is disabled")));
+        }
+        if self.disable_regex && stats.uses_regex {
+            return Err(violation(expression, "function 'matches' is disabled".to_string()));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) const fn pattern_limits(&self) -> PatternLimits {
+        PatternLimits {
+            max_length: self.max_regex_length,
+            max_size: self.max_regex_size,
+        }
+    }
+
+    /// `expression` with optional selections rewritten, when optional values are enabled
+    pub(crate) fn desugar(&self, expression: &str) -> Option<Desugared> {
+        self.optional_ext.then(|| Desugared::of(expression))
+    }
+
+    pub(crate) const fn extensions(&self) -> Extensions {
+        Extensions {
+            strings: self.string_ext,
+            math: self.math_ext,
+            lists: self.list_ext,
+            optional: self.optional_ext,
+            unicode: self.unicode_ext,
+            encoders: self.encoders_ext,
+            json: self.json_ext,
+        }
+    }
+
+    fn depth_message(&self) -> String {
+        format!("expression is nested deeper than {} levels", self.max_nesting_depth)
+    }
+}
+
+fn violation(expression: &str, message: String) -> Error {
+    Error::Compile(CompileError::unlocated(expression, message))
+}
+
+/// Byte offset of the first bracket that opens deeper than `max_depth`, ignoring
+/// string literals
+fn bracket_overflow(expression: &str, max_depth: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (offset, byte) in expression.bytes().enumerate() {
+        if let Some(open) = quote {
+            if escaped {
+                escaped = false;
+            } else if byte == b'\\' {
+                escaped = true;
+            } else if byte == open {
+                quote = None;
+            }
+            continue;
+        }
+
+        match byte {
+            b'\'' | b'"' => quote = Some(byte),
+            b'(' | b'[' | b'{' => {
+                depth += 1;
+                if depth > max_depth {
+                    return Some(offset);
+                }
+            }
+            b')' | b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Iterations a comprehension is assumed to make when estimating static cost, since
+/// the size of the list or map it ranges over is only known when it runs
+pub const ASSUMED_ITERATIONS: u64 = 100;
+
+/// Size and complexity of a compiled expression, from `Program::metrics`
+///
+/// `cost` estimates the nodes one evaluation visits: each node counts once, and the
+/// nodes inside a comprehension count `ASSUMED_ITERATIONS` times per enclosing
+/// comprehension, saturating at `u64::MAX`. Evaluation short-circuits and branches,
+/// so it is an upper bound on expressions without comprehensions and a guide for
+/// those with them, meant for rejecting pathological rules rather than predicting
+/// running time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProgramMetrics {
+    /// Nodes in the syntax tree
+    pub nodes: usize,
+    /// Deepest nesting of subexpressions, 1 for a single literal or variable
+    pub depth: usize,
+    /// Comprehension macros: `all`, `exists`, `exists_one`, `map` and `filter`
+    pub comprehensions: usize,
+    /// Calls to functions and the `has` macro, including methods such as `size`
+    pub calls: usize,
+    pub cost: u64,
+}
+
+/// Shape of a parsed expression, as measured against `CompileOptions`
+pub(crate) struct ExpressionStats {
+    pub nodes: usize,
+    pub depth: usize,
+    pub comprehensions: usize,
+    pub calls: usize,
+    /// Static cost estimate, as described for `ProgramMetrics`
+    pub cost: u64,
+    pub disabled_macro: Option<String>,
+    pub uses_regex: bool,
+    /// `matches()` patterns written as string literals
+    pub regex_literals: Vec<String>,
+    /// Identifiers the expression reads that no comprehension binds
+    pub variables: BTreeSet<String>,
+}
+
+impl ExpressionStats {
+    /// Walks the expression with an explicit stack, so deep input cannot overflow it
+    pub fn of<'a>(expression: &'a Expression, disabled_macros: &[String]) -> Self {
+        let mut stats = Self {
+            nodes: 0,
+            depth: 0,
+            comprehensions: 0,
+            calls: 0,
+            cost: 0,
+            disabled_macro: None,
+            uses_regex: false,
+            regex_literals: Vec::new(),
+            variables: BTreeSet::new(),
+        };
+        // Comprehension variables as `(enclosing scope, name)`, with 0 the outermost
+        // scope, which binds nothing
+        let mut scopes: Vec<(usize, &str)> = vec![(0, "")];
+        // Each node carries its depth, scope and the times it is evaluated for the cost
+        let mut pending = vec![(expression, 1, 0, 1u64)];
+
+        while let Some((expression, depth, scope, weight)) = pending.pop() {
+            stats.nodes += 1;
+            stats.depth = stats.depth.max(depth);
+            stats.cost = stats.cost.saturating_add(weight);
+            let mut push = |child: &'a Expression| pending.push((child, depth + 1, scope, weight));
+            match expression {
+                Expression::Arithmetic(left, _, right)
+                | Expression::Relation(left, _, right)
+                | Expression::Or(left, right)
+                | Expression::And(left, right) => {
+                    push(left);
+                    push(right);
+                }
+                Expression::Ternary(condition, then, otherwise) => {
+                    push(condition);
+                    push(then);
+                    push(otherwise);
+                }
+                Expression::Unary(_, operand) => push(operand),
+                Expression::Member(target, member) => {
+                    push(target);
+                    match member.as_ref() {
+                        Member::Attribute(_) => {}
+                        Member::Index(index) => push(index),
+                        Member::Fields(fields) => fields.iter().for_each(|(_, value)| push(value)),
+                    }
+                }
+                Expression::FunctionCall(function, target, args) => {
+                    let mut args_scope = scope;
+                    let mut args_weight = weight;
+                    let mut call = true;
+                    if let Expression::Ident(name) = function.as_ref() {
+                        if COMPREHENSIONS.contains(&name.as_str()) {
+                            stats.comprehensions += 1;
+                            args_weight = weight.saturating_mul(ASSUMED_ITERATIONS);
+                            call = false;
+                            // The first argument names the variable the others iterate with
+                            if let Some(Expression::Ident(var)) = args.first() {
+                                scopes.push((scope, var.as_str()));
+                                args_scope = scopes.len() - 1;
+                            }
+                        }
+                        if name.as_str() == "matches" {
+                            stats.uses_regex = true;
+                            let pattern = if target.is_some() { args.first() } else { args.get(1) };
+                            if let Some(Expression::Atom(Atom::String(pattern))) = pattern {
+                                stats.regex_literals.push(pattern.to_string());
+                            }
+                        }
+                        if stats.disabled_macro.is_none()
+                            && disabled_macros.iter().any(|disabled| disabled == name.as_str())
+                        {
+                            stats.disabled_macro = Some(name.to_string());
+                        }
+                    }
+                    stats.calls += usize::from(call);
+                    // The function name is a leaf, not a variable read
+                    stats.depth = stats.depth.max(depth + 1);
+                    if let Some(target) = target {
+                        push(target);
+                    }
+                    args.iter()
+                        .for_each(|arg| pending.push((arg, depth + 1, args_scope, args_weight)));
+                }
+                Expression::List(items) => items.iter().for_each(&mut push),
+                Expression::Map(entries) => entries.iter().for_each(|(key, value)| {
+                    push(key);
+                    push(value);
+                }),
+                Expression::Ident(name) => {
+                    let mut bound = scope;
+                    while bound != 0 && scopes[bound].1 != name.as_str() {
+                        bound = scopes[bound].0;
+                    }
+                    if bound == 0 {
+                        stats.variables.insert(name.to_string());
+                    }
+                }
+                Expression::Atom(_) => {}
+            }
+        }
+
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Program;
+
+    fn message(options: &CompileOptions, expression: &str) -> Option<String> {
+        match Program::compile_with_options(expression, options) {
+            Err(Error::Compile(error)) => Some(error.message),
+            Err(other) => panic!("unexpected error {other:?}"),
+            Ok(_) => None,
+        }
+    }
+
+    #[test]
+    fn test_default_options_accept_everything() {
+        let nested = format!("{}1{}", "(".repeat(64), ")".repeat(64));
+        assert_eq!(message(&CompileOptions::default(), &nested), None);
+    }
+
+    #[test]
+    fn test_max_expression_length() {
+        let options = CompileOptions {
+            max_expression_length: 5,
+            ..CompileOptions::default()
+        };
+
+        assert_eq!(message(&options, "1 + 2"), None);
+        assert_eq!(message(&options, "1 + 23"), Some("expression is longer than 5 bytes".into()));
+    }
+
+    #[test]
+    fn test_max_nesting_depth() {
+        let options = CompileOptions {
+            max_nesting_depth: 3,
+            ..CompileOptions::default()
+        };
+
+        assert_eq!(message(&options, "[[1]]"), None);
+        assert_eq!(message(&options, "'(((((' + 'x'"), None);
+
+        let error = Program::compile_with_options("[[[[1]]]]", &options).unwrap_err();
+        let Error::Compile(error) = error else {
+            panic!("{error:?}")
+        };
+        assert_eq!(error.message, "expression is nested deeper than 3 levels");
+        assert_eq!((error.line, error.column, error.offset), (1, 4, 3));
+
+        // Operators nest without brackets
+        assert!(message(&options, "1 + 2 + 3 + 4").is_some());
+        assert!(message(&options, "a ? 1 : b ? 2 : c ? 3 : 4").is_some());
+    }
+
+    #[test]
+    fn test_max_comprehensions_and_disabled_macros() {
+        let options = CompileOptions {
+            max_comprehensions: 1,
+            disabled_macros: vec!["has".to_string()],
+            ..CompileOptions::default()
+        };
+
+        assert_eq!(message(&options, "[1, 2].all(x, x > 0)"), None);
+        assert_eq!(
+            message(&options, "[1].all(x, [2].exists(y, y == x))"),
+            Some("expression uses more than 1 comprehensions".into())
+        );
+        assert_eq!(message(&options, "has(a.b)"), Some("macro 'has' is disabled".into()));
+    }
+
+    #[test]
+    fn test_expression_variables() {
+        let variables = |expression| {
+            let parsed = cel_parser::parse(expression).unwrap();
+            ExpressionStats::of(&parsed, &[]).variables.into_iter().collect::<Vec<_>>()
+        };
+
+        assert_eq!(variables("a.b + c[d] - f(e)"), ["a", "c", "d", "e"]);
+        assert_eq!(variables("items.map(x, x * k).exists(y, y > x)"), ["items", "k", "x"]);
+        assert_eq!(variables("[1].all(x, [2].all(y, x < y))"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_regex_options() {
+        let options = CompileOptions {
+            disable_regex: true,
+            ..CompileOptions::default()
+        };
+        let disabled = Some("function 'matches' is disabled".to_string());
+        assert_eq!(message(&options, "name.matches('^a')"), disabled);
+        assert_eq!(message(&options, "matches(name, '^a')"), disabled);
+        assert_eq!(message(&options, "name == 'matches'"), None);
+
+        let options = CompileOptions {
+            max_regex_length: 4,
+            ..CompileOptions::default()
+        };
+        assert_eq!(
+            message(&options, "name.matches('^abcd')"),
+            Some("regex '^abcd' is longer than 4 bytes".into())
+        );
+        assert_eq!(message(&options, "name.matches(pattern)"), None);
+        assert_eq!(
+            message(&CompileOptions::default(), "name.matches('(')").unwrap().lines().next(),
+            Some("invalid regex '(': regex parse error:")
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use crate::ffi::{log, CelLogLevel};
+use regex::{Regex, RegexBuilder};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Most patterns built at run time that a program keeps compiled
+const DYNAMIC_CAPACITY: usize = 64;
+
+/// Limits on the patterns `matches()` accepts
+///
+/// The regex engine runs in linear time, so the cost of a hostile pattern is in
+/// compiling it; these bound that cost. A limit of 0 keeps the engine's default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct PatternLimits {
+    pub max_length: usize,
+    pub max_size: usize,
+}
+
+/// Compiled `matches()` patterns shared by every execution of a program
+///
+/// Patterns written as string literals are compiled with the program, so a bad one
+/// is a compile error. Patterns computed at run time are compiled on first use and
+/// kept, up to `DYNAMIC_CAPACITY` of them.
+#[derive(Debug)]
+pub(crate) struct PatternCache {
+    limits: PatternLimits,
+    literals: HashMap<String, Regex>,
+    dynamic: Mutex<HashMap<String, Regex>>,
+}
+
+impl PatternCache {
+    /// # Errors
+    ///
+    /// Returns the first literal pattern that cannot be compiled, with the reason.
+    pub fn new(limits: PatternLimits, literals: &[String]) -> Result<Self, String> {
+        let literals = literals
+            .iter()
+            .map(|pattern| Ok((pattern.clone(), compile(pattern, limits)?)))
+            .collect::<Result<_, String>>()?;
+
+        Ok(Self {
+            limits,
+            literals,
+            dynamic: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// # Errors
+    ///
+    /// Returns the reason `pattern` cannot be compiled.
+    pub fn get(&self, pattern: &str) -> Result<Regex, String> {
+        if let Some(regex) = self.literals.get(pattern) {
+            return Ok(regex.clone());
+        }
+
+        let mut dynamic = self.dynamic.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        if let Some(regex) = dynamic.get(pattern) {
+            return Ok(regex.clone());
+        }
+        let regex = compile(pattern, self.limits)?;
+        if dynamic.len() >= DYNAMIC_CAPACITY {
+            log(CelLogLevel::Info, || {
+                format!("regex cache full, evicting {} run-time patterns", dynamic.len())
+            });
+            dynamic.clear();
+        }
+        dynamic.insert(pattern.to_string(), regex.clone());
+        Ok(regex)
+    }
+}
+
+fn compile(pattern: &str, limits: PatternLimits) -> Result<Regex, String> {
+    if limits.max_length > 0 && pattern.len() > limits.max_length {
+        return Err(format!("regex '{pattern}' is longer than {} bytes", limits.max_length));
+    }
+
+    let mut builder = RegexBuilder::new(pattern);
+    if limits.max_size > 0 {
+        builder.size_limit(limits.max_size).dfa_size_limit(limits.max_size);
+    }
+    builder.build().map_err(|e| format!("invalid regex '{pattern}': {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literals_are_compiled_up_front() {
+        let limits = PatternLimits::default();
+        let cache = PatternCache::new(limits, &["^a+$".to_string()]).unwrap();
+
+        assert!(cache.get("^a+$").unwrap().is_match("aaa"));
+        assert!(cache.dynamic.lock().unwrap().is_empty());
+
+        let error = PatternCache::new(limits, &["(".to_string()]).unwrap_err();
+        assert!(error.starts_with("invalid regex '('"), "{error}");
+    }
+
+    #[test]
+    fn test_dynamic_patterns_are_cached_and_bounded() {
+        let cache = PatternCache::new(PatternLimits::default(), &[]).unwrap();
+
+        for i in 0..DYNAMIC_CAPACITY {
+            cache.get(&format!("^{i}$")).unwrap();
+        }
+        assert_eq!(cache.dynamic.lock().unwrap().len(), DYNAMIC_CAPACITY);
+        cache.get("^0$").unwrap();
+        assert_eq!(cache.dynamic.lock().unwrap().len(), DYNAMIC_CAPACITY);
+
+        cache.get("^new$").unwrap();
+        assert_eq!(cache.dynamic.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = PatternLimits {
+            max_length: 8,
+            max_size: 1024,
+        };
+        let cache = PatternCache::new(limits, &[]).unwrap();
+
+        assert_eq!(
+            cache.get("^abcdefgh$").unwrap_err(),
+            "regex '^abcdefgh$' is longer than 8 bytes"
+        );
+        assert!(cache.get("a{1000}").is_err());
+        assert!(cache.get("a+").is_ok());
+    }
+}
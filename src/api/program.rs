@@ -0,0 +1,1301 @@
+use super::ast::expanded_ast;
+use super::attributes;
+use super::audit::audited;
+use super::bigint;
+use super::constants;
+use super::decimal;
+use super::dependencies::dependency_paths;
+use super::deterministic;
+use super::ext::Extensions;
+use super::functions::{self, Unresolved};
+use super::infer;
+use super::intern::intern;
+use super::iterations::{self, Budget};
+use super::memory::{self, Meter};
+use super::missing::{self, Missing, MissingKeys};
+use super::nan;
+use super::nulls;
+use super::numeric;
+use super::optimize::{self, Optimization};
+use super::options::{ExpressionStats, ProgramMetrics};
+use super::patterns::PatternCache;
+use super::soft_errors;
+#[cfg(feature = "stats")]
+use super::stats::{ProgramStats, StatsRecorder};
+use super::timezones;
+use super::trace::{trace, TraceNode};
+use super::value::{json_to_cel_value, type_name_of, type_value, value_type_name, value_type_of};
+use super::{
+    CancelToken, CompileError, CompileOptions, Context, EnumType, Error, Truthiness, Value,
+};
+use crate::ffi::CelValueType;
+use cel_interpreter::extractors::{Arguments, This};
+use cel_interpreter::{Context as CelContext, ExecutionError, Program as CelProgram};
+use chrono::DateTime;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// The function cancellable executions call on every comprehension iteration
+const CHECKPOINT: &str = "_cancel_check";
+
+/// A compiled CEL expression
+#[derive(Debug)]
+pub struct Program {
+    program: CelProgram,
+    /// The source `program` was compiled from, after desugaring
+    source: String,
+    fingerprint: u64,
+    depth: usize,
+    patterns: Arc<PatternCache>,
+    extensions: Extensions,
+    variables: Vec<Arc<str>>,
+    /// Whether calls to functions nothing provides are resolved when the program runs
+    late_bound: bool,
+    /// Most elements a comprehension may iterate over, or 0 for no limit
+    max_range: usize,
+    /// The type every execution has to return, if any
+    expected_type: Option<CelValueType>,
+    /// The enum whose member names stand in for int results, if any
+    result_enum: Option<EnumType>,
+    /// `program` rewritten for cancellation checkpoints or a missing-key policy,
+    /// compiled on first use, or the error the rewrite failed to compile with
+    variants: Mutex<HashMap<Rewrites, Result<Arc<CelProgram>, Error>>>,
+    #[cfg(feature = "stats")]
+    stats: StatsRecorder,
+}
+
+impl Program {
+    /// # Errors
+    ///
+    /// Returns `Error::Compile` if the expression cannot be parsed.
+    pub fn compile(expression: &str) -> Result<Self, Error> {
+        Self::compile_with_options(expression, &CompileOptions::default())
+    }
+
+    /// Compile `expression` after checking it against `options`
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Compile` if the expression exceeds a limit or cannot be parsed.
+    pub fn compile_with_options(expression: &str, options: &CompileOptions) -> Result<Self, Error> {
+        let desugared = options.desugar(expression);
+        let source = desugared.as_ref().map_or(expression, |d| d.source.as_str());
+        let compile_error = |e| {
+            let error = CompileError::from_parse_error(source, &e);
+            Error::Compile(match &desugared {
+                Some(desugared) => desugared.locate(expression, error),
+                None => error,
+            })
+        };
+
+        options.check_source(expression)?;
+        let parsed = cel_parser::parse(source).map_err(compile_error)?;
+        let stats = ExpressionStats::of(&parsed, &options.disabled_macros);
+        options.check_stats(expression, &stats)?;
+
+        let program = CelProgram::compile(source).map_err(compile_error)?;
+        let patterns = PatternCache::new(options.pattern_limits(), &stats.regex_literals)
+            .map_err(|e| Error::Compile(CompileError::unlocated(expression, e)))?;
+        let extensions = options.extensions();
+        Ok(Self {
+            program,
+            source: source.to_string(),
+            fingerprint: fingerprint(&parsed),
+            depth: stats.depth,
+            patterns: Arc::new(patterns),
+            extensions,
+            variables: free_variables(stats, extensions),
+            late_bound: false,
+            max_range: 0,
+            expected_type: None,
+            result_enum: None,
+            variants: Mutex::default(),
+            #[cfg(feature = "stats")]
+            stats: StatsRecorder::default(),
+        })
+    }
+
+    /// Fold constant subexpressions and prune conditional branches that can never
+    /// be taken, so the program evaluates fewer nodes on every execution
+    ///
+    /// `1 + 2` becomes `3`, `size('xyz')` becomes `3` and `true ? a : b` becomes `a`.
+    /// Subexpressions that fail to evaluate are kept, so the program fails as before
+    /// when it runs. Optimizing again has no further effect.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Compile` if the folded expression cannot be compiled, leaving
+    /// the program unchanged.
+    pub fn optimize(&mut self) -> Result<Optimization, Error> {
+        let compile_error =
+            |source: &str, e| Error::Compile(CompileError::from_parse_error(source, &e));
+        let parsed = cel_parser::parse(&self.source).map_err(|e| compile_error(&self.source, e))?;
+        let folded = optimize::fold(&parsed, self.extensions);
+        let source = optimize::unparse(&folded);
+        let program = CelProgram::compile(&source).map_err(|e| compile_error(&source, e))?;
+
+        let stats = ExpressionStats::of(&folded, &[]);
+        self.program = program;
+        self.source = source;
+        self.depth = stats.depth;
+        self.variables = free_variables(stats, self.extensions);
+        self.variants = Mutex::default();
+        Ok(Optimization {
+            nodes_before: optimize::node_count(&parsed),
+            nodes_after: optimize::node_count(&folded),
+        })
+    }
+
+    /// Replace references to `constants` with their literals and fold the
+    /// subexpressions that become constant
+    ///
+    /// The fingerprint covers the inlined values, so programs compiled from the same
+    /// source against different constants tell apart.
+    pub(crate) fn inline_constants(
+        &mut self,
+        constants: &HashMap<&str, cel_parser::Expression>,
+    ) -> Result<(), Error> {
+        if !self.variables.iter().any(|name| constants.contains_key(name.as_ref())) {
+            return Ok(());
+        }
+        let compile_error =
+            |source: &str, e| Error::Compile(CompileError::from_parse_error(source, &e));
+        let parsed = cel_parser::parse(&self.source).map_err(|e| compile_error(&self.source, e))?;
+        let inlined = constants::inline(&parsed, constants);
+        let folded = optimize::fold(&inlined, self.extensions);
+        let source = optimize::unparse(&folded);
+        let program = CelProgram::compile(&source).map_err(|e| compile_error(&source, e))?;
+
+        let stats = ExpressionStats::of(&folded, &[]);
+        self.program = program;
+        self.source = source;
+        self.fingerprint = fingerprint(&inlined);
+        self.depth = stats.depth;
+        self.variables = free_variables(stats, self.extensions);
+        self.variants = Mutex::default();
+        Ok(())
+    }
+
+    /// Evaluate the expression against the variables bound in `context`
+    ///
+    /// Variables the expression references but `context` does not bind are passed
+    /// to its resolver, if it has one, and the functions `context` registers can be
+    /// called alongside the built-in ones.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Variable` if a variable cannot be converted or resolved,
+    /// `Error::Execution` if evaluation fails or the expression is nested deeper than
+    /// `context.max_depth()`, `Error::MemoryLimit` if evaluation allocates more
+    /// than `context.max_memory()` bytes, `Error::IterationLimit` if it runs more than
+    /// `context.max_iterations()` comprehension iterations, and
+    /// `Error::UnresolvedFunction` if a program compiled with late-bound functions
+    /// calls one `context` does not register. Returns `Error::ResultType` if the
+    /// result does not have the type set by `expect_type`.
+    pub fn execute(&self, context: &Context) -> Result<Value, Error> {
+        #[cfg(feature = "stats")]
+        let start = std::time::Instant::now();
+        let result = audited(context, self.fingerprint, || {
+            bindings(context).and_then(|bindings| self.evaluate(context, &bindings))
+        });
+        #[cfg(feature = "stats")]
+        self.stats.record(start.elapsed(), &result);
+        result
+    }
+
+    /// `execute` with the variables of `context` already converted by `bindings`
+    pub(crate) fn execute_with(
+        &self,
+        context: &Context,
+        bindings: &[(&str, Value)],
+    ) -> Result<Value, Error> {
+        #[cfg(feature = "stats")]
+        let start = std::time::Instant::now();
+        let result = audited(context, self.fingerprint, || self.evaluate(context, bindings));
+        #[cfg(feature = "stats")]
+        self.stats.record(start.elapsed(), &result);
+        result
+    }
+
+    fn evaluate(&self, context: &Context, bindings: &[(&str, Value)]) -> Result<Value, Error> {
+        let variant = self.variant(self.rewrites(context, false))?;
+        let program = variant.as_deref().unwrap_or(&self.program);
+        let missing = Missing::default();
+        let meter = Meter::new(context.max_memory());
+        let budget = Budget::new(context.max_iterations());
+        let unresolved = Unresolved::default();
+        let result = self
+            .cel_context_with(context, bindings, &missing, &meter, &budget, &unresolved)
+            .and_then(|cel_ctx| {
+                program.execute(&cel_ctx).map_err(|e| Error::Execution(e.to_string()))
+            });
+        let result = budget.resolve(unresolved.resolve(soft_errors::resolve(result)));
+        self.check_type(missing.resolve(meter.resolve(result)))
+    }
+
+    /// Evaluate the expression like `execute` and turn the result into a yes or no
+    /// under `truthiness`
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::ResultType` if the result is not a bool under
+    /// `Truthiness::Strict`, otherwise fails as `execute` does.
+    pub fn execute_bool(&self, context: &Context, truthiness: Truthiness) -> Result<bool, Error> {
+        self.execute(context).and_then(|value| truthiness.truthy(&value))
+    }
+
+    /// Evaluate the expression like `execute`, stopping early once `token` is cancelled
+    ///
+    /// The token is checked before evaluation starts and on every iteration of a
+    /// comprehension, so another thread can abort an evaluation over a large list.
+    /// Expressions without comprehensions are only checked before they start. Each
+    /// check costs a function call, and the first cancellable execution compiles a
+    /// checked copy of the program.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Cancelled` if `token` is cancelled or reaches its deadline before
+    /// evaluation finishes, and `Error::Execution` if it spends the token's budget or
+    /// the checkpoints cannot be added to the program, otherwise fails as `execute`
+    /// does.
+    pub fn execute_cancellable(
+        &self,
+        context: &Context,
+        token: &CancelToken,
+    ) -> Result<Value, Error> {
+        if token.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        #[cfg(feature = "stats")]
+        let start = std::time::Instant::now();
+        let result =
+            audited(context, self.fingerprint, || self.evaluate_cancellable(context, token));
+        #[cfg(feature = "stats")]
+        self.stats.record(start.elapsed(), &result);
+        result
+    }
+
+    fn evaluate_cancellable(&self, context: &Context, token: &CancelToken) -> Result<Value, Error> {
+        let variant = self.variant(self.rewrites(context, true))?;
+        let program = variant.as_deref().unwrap_or(&self.program);
+        let missing = Missing::default();
+        let meter = Meter::new(context.max_memory());
+        let budget = Budget::new(context.max_iterations());
+        let unresolved = Unresolved::default();
+        let result = self.cel_context(context, &missing, &meter, &budget, &unresolved).and_then(
+            |mut cel_ctx| {
+                let checked = token.clone();
+                cel_ctx.add_function(CHECKPOINT, move |Arguments(args): Arguments| {
+                    if checked.is_cancelled() || !checked.spend() {
+                        return Err(ExecutionError::function_error(CHECKPOINT, "cancelled"));
+                    }
+                    Ok(args.first().cloned().unwrap_or(Value::Null))
+                });
+                program.execute(&cel_ctx).map_err(|e| {
+                    if token.is_cancelled() {
+                        Error::Cancelled
+                    } else if token.is_exhausted() {
+                        Error::Execution("evaluation exceeded its cost budget".to_string())
+                    } else {
+                        Error::Execution(e.to_string())
+                    }
+                })
+            },
+        );
+        let result = budget.resolve(unresolved.resolve(soft_errors::resolve(result)));
+        self.check_type(missing.resolve(meter.resolve(result)))
+    }
+
+    /// Counters for the executions since the program was compiled or its counters
+    /// were reset
+    #[cfg(feature = "stats")]
+    #[must_use]
+    pub fn stats(&self) -> ProgramStats {
+        self.stats.snapshot()
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+    }
+
+    /// Evaluate the expression like `execute`, also recording the value and timing of
+    /// each subexpression evaluated
+    ///
+    /// The trace shows which clause decided the result, such as the condition that
+    /// made a policy deny a request. Operands are evaluated separately for the trace,
+    /// so tracing costs more than executing and is meant for diagnosis. An attribute
+    /// hook is asked about the selections of each operand too, and so more than once.
+    /// The trace is `None` if execution fails before evaluation starts, for example
+    /// because a variable cannot be converted.
+    #[must_use]
+    pub fn execute_traced(&self, context: &Context) -> (Result<Value, Error>, Option<TraceNode>) {
+        let missing = Missing::default();
+        let meter = Meter::new(context.max_memory());
+        let budget = Budget::new(context.max_iterations());
+        let unresolved = Unresolved::default();
+        let cel_ctx = match self.cel_context(context, &missing, &meter, &budget, &unresolved) {
+            Ok(cel_ctx) => cel_ctx,
+            Err(e) => return (Err(e), None),
+        };
+        let variant = match self.variant(self.rewrites(context, false)) {
+            Ok(variant) => variant,
+            Err(e) => return (Err(e), None),
+        };
+        let program = variant.as_deref().unwrap_or(&self.program);
+        let hooked = context.has_attribute_hook();
+        let trace = cel_parser::parse(&self.source)
+            .ok()
+            .map(|parsed| trace(&parsed, &cel_ctx, hooked));
+        let result = program.execute(&cel_ctx).map_err(|e| Error::Execution(e.to_string()));
+        let result = budget.resolve(unresolved.resolve(soft_errors::resolve(result)));
+        (self.check_type(missing.resolve(meter.resolve(result))), trace)
+    }
+
+    /// The rewrites executing against `context` needs
+    fn rewrites(&self, context: &Context, checkpoints: bool) -> Rewrites {
+        Rewrites {
+            max_range: self.max_range,
+            ..Rewrites::for_context(context, checkpoints)
+        }
+    }
+
+    /// The program with `rewrites` applied, compiled on first use, or `None` to run
+    /// the program as compiled
+    ///
+    /// A rewrite that does not compile fails every execution that needs it rather
+    /// than running the program as compiled, which would skip the cancellation
+    /// checkpoints, limits and hooks the rewrite installs.
+    fn variant(&self, rewrites: Rewrites) -> Result<Option<Arc<CelProgram>>, Error> {
+        if rewrites == Rewrites::default() {
+            return Ok(None);
+        }
+        let mut variants = self.variants.lock().unwrap_or_else(PoisonError::into_inner);
+        let variant = variants.entry(rewrites).or_insert_with(|| {
+            let rewrite_error = |source: &str, e| {
+                let error = CompileError::from_parse_error(source, &e);
+                Error::Execution(format!("cannot rewrite the program for this context: {error}"))
+            };
+            let mut parsed =
+                cel_parser::parse(&self.source).map_err(|e| rewrite_error(&self.source, e))?;
+            if rewrites.soft_errors {
+                parsed = soft_errors::with_soft_errors(&parsed, self.extensions);
+            }
+            if rewrites.memory_limit {
+                parsed = memory::with_metering(&parsed);
+            }
+            if rewrites.missing_keys != MissingKeys::Error || rewrites.null_propagation {
+                let lookup_has =
+                    rewrites.missing_keys == MissingKeys::Unknown || rewrites.null_propagation;
+                parsed = missing::with_lookups(&parsed, lookup_has);
+            }
+            if rewrites.numeric_promotion {
+                parsed = numeric::with_numeric_promotion(&parsed);
+            }
+            if rewrites.null_propagation {
+                parsed = nulls::with_null_propagation(&parsed);
+            }
+            if rewrites.nan_comparisons {
+                parsed = nan::with_nan_comparisons(&parsed);
+            }
+            if rewrites.attribute_hook {
+                parsed = attributes::with_attribute_hooks(&parsed);
+            }
+            if rewrites.sorted_ranges {
+                parsed = deterministic::with_sorted_ranges(&parsed);
+            }
+            if rewrites.max_range > 0 || rewrites.iteration_budget {
+                let (max_range, counted) = (rewrites.max_range, rewrites.iteration_budget);
+                parsed = iterations::with_iteration_checks(&parsed, max_range, counted);
+            }
+            if rewrites.checkpoints {
+                parsed = optimize::with_checkpoints(&parsed, CHECKPOINT);
+            }
+            let source = optimize::unparse(&parsed);
+            CelProgram::compile(&source)
+                .map(Arc::new)
+                .map_err(|e| rewrite_error(&source, e))
+        });
+        variant.clone().map(Some)
+    }
+
+    /// An interpreter context binding the variables of `context`, after checking the
+    /// program against its limits
+    fn cel_context(
+        &self,
+        context: &Context,
+        missing: &Missing,
+        meter: &Meter,
+        budget: &Budget,
+        unresolved: &Unresolved,
+    ) -> Result<CelContext, Error> {
+        self.cel_context_with(context, &bindings(context)?, missing, meter, budget, unresolved)
+    }
+
+    /// Like `cel_context`, with the variables of `context` already converted, so
+    /// programs run against the same context can share the conversion
+    fn cel_context_with(
+        &self,
+        context: &Context,
+        bindings: &[(&str, Value)],
+        missing: &Missing,
+        meter: &Meter,
+        budget: &Budget,
+        unresolved: &Unresolved,
+    ) -> Result<CelContext, Error> {
+        if context.max_depth() > 0 && self.depth > context.max_depth() {
+            return Err(Error::Execution(format!(
+                "expression too deep: nesting depth {} exceeds the limit of {}",
+                self.depth,
+                context.max_depth()
+            )));
+        }
+
+        let mut cel_ctx = CelContext::default();
+        let patterns = Arc::clone(&self.patterns);
+        cel_ctx.add_function(
+            "matches",
+            move |This(this): This<Arc<String>>, pattern: Arc<String>| {
+                let regex = patterns
+                    .get(&pattern)
+                    .map_err(|e| ExecutionError::function_error("matches", e))?;
+                Ok::<_, ExecutionError>(regex.is_match(&this))
+            },
+        );
+        bigint::register(&mut cel_ctx);
+        decimal::register(&mut cel_ctx);
+        timezones::register(&mut cel_ctx);
+        cel_ctx.add_function("type", |Arguments(args): Arguments| match args.as_slice() {
+            [value] => Ok(type_value(type_name_of(value))),
+            _ => Err(ExecutionError::function_error("type", "expected one argument")),
+        });
+        self.extensions.register(&mut cel_ctx);
+        if context.missing_keys() != MissingKeys::Error || context.null_propagation() {
+            let null_safe = context.null_propagation();
+            missing::register(&mut cel_ctx, context.missing_keys(), null_safe, missing);
+        }
+        if context.null_propagation() {
+            nulls::register(&mut cel_ctx);
+        }
+        if context.numeric_promotion() {
+            numeric::register(&mut cel_ctx, context.null_propagation());
+        }
+        if nan_comparisons(context) {
+            nan::register(&mut cel_ctx);
+        }
+        if context.max_memory() > 0 {
+            memory::register(&mut cel_ctx, meter);
+        }
+        if context.soft_errors() {
+            soft_errors::register(&mut cel_ctx);
+        }
+        iterations::register(&mut cel_ctx, self.max_range, budget);
+        attributes::register(&mut cel_ctx, context, missing);
+        deterministic::register(&mut cel_ctx, context);
+        let now = current_time(context.now(), !context.deterministic());
+        cel_ctx.add_function("now", move || {
+            now.clone().ok_or_else(|| {
+                ExecutionError::function_error(
+                    "now",
+                    "no system clock; set the time on the context",
+                )
+            })
+        });
+
+        functions::register(&mut cel_ctx, context, self.extensions);
+        if self.late_bound {
+            let references = self.program.references();
+            let called = references.functions();
+            functions::register_stubs(&mut cel_ctx, context, self.extensions, called, unresolved);
+        }
+
+        for (name, value) in bindings {
+            cel_ctx.add_variable_from_value(*name, value.clone());
+        }
+
+        // Only free variables are resolved: the interpreter's references include the
+        // variables comprehensions bind, which the resolver knows nothing about
+        if context.has_resolver() {
+            for name in self.variables.iter().map(|name| &**name) {
+                if context.variable_type(name).is_some() {
+                    continue;
+                }
+                let resolved = context.resolve_variable(name).map_err(|e| {
+                    Error::Variable(format!("Error resolving variable '{name}': {e}"))
+                })?;
+                if let Some(value) = resolved {
+                    cel_ctx.add_variable_from_value(name, value);
+                }
+            }
+        }
+
+        Ok(cel_ctx)
+    }
+
+    /// A stable 64-bit hash of the expression's syntax tree
+    ///
+    /// Expressions that differ only in whitespace, comments, quoting or redundant
+    /// parentheses hash the same, so hosts can deduplicate rules and key external
+    /// caches. The hash is computed at compilation and `optimize` leaves it unchanged.
+    #[must_use]
+    pub const fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// `fingerprint` as 16 lowercase hex digits
+    #[must_use]
+    pub fn fingerprint_hex(&self) -> String {
+        format!("{:016x}", self.fingerprint)
+    }
+
+    /// Size and complexity of the expression, for rejecting pathological rules before
+    /// they run
+    ///
+    /// The metrics describe the expression as it is now, so they shrink after
+    /// `optimize`. They are measured on each call rather than kept with the program.
+    #[must_use]
+    pub fn metrics(&self) -> ProgramMetrics {
+        cel_parser::parse(&self.source).map_or_else(
+            |_| ProgramMetrics::default(),
+            |parsed| {
+                let stats = ExpressionStats::of(&parsed, &[]);
+                ProgramMetrics {
+                    nodes: stats.nodes,
+                    depth: stats.depth,
+                    comprehensions: stats.comprehensions,
+                    calls: stats.calls,
+                    cost: stats.cost,
+                }
+            },
+        )
+    }
+
+    /// Names of the functions and macros the expression calls
+    #[must_use]
+    pub fn functions(&self) -> Vec<String> {
+        self.program.references().functions().into_iter().map(str::to_string).collect()
+    }
+
+    /// Resolve calls to functions nothing provides when the program runs, against the
+    /// functions of the context it runs with
+    pub(crate) const fn set_late_bound(&mut self) {
+        self.late_bound = true;
+    }
+
+    /// Require every execution to return a value of `value_type`, or of any type if
+    /// `None`
+    ///
+    /// An execution whose result has another type fails with `Error::ResultType`
+    /// instead, so a host expecting a policy verdict never receives a string. Ints,
+    /// uints and doubles are distinct types, and null matches only `Null`.
+    pub const fn expect_type(&mut self, value_type: Option<CelValueType>) {
+        self.expected_type = value_type;
+    }
+
+    #[must_use]
+    pub const fn expected_type(&self) -> Option<CelValueType> {
+        self.expected_type
+    }
+
+    /// The type every successful execution returns, if the shape of the expression
+    /// alone determines it
+    ///
+    /// There is no type checker, so only literals, comparisons, logical operators,
+    /// operators on operands of one inferred type, conditionals whose branches agree
+    /// and built-in functions with a fixed result type are inferred; anything
+    /// depending on a variable or a host function is `None`. A program naming its
+    /// int results with `set_result_enum` infers no type for them.
+    #[must_use]
+    pub fn inferred_type(&self) -> Option<CelValueType> {
+        let parsed = cel_parser::parse(&self.source).ok()?;
+        infer::inferred_type(&parsed)
+            .filter(|t| !(self.result_enum.is_some() && *t == CelValueType::Int))
+    }
+
+    /// Return the name of the member for int results that `result_enum` names, and
+    /// other results unchanged
+    ///
+    /// Names replace results before `expect_type` checks them, so a program returning
+    /// enum names expects `CelValueType::String`.
+    pub fn set_result_enum(&mut self, result_enum: Option<EnumType>) {
+        self.result_enum = result_enum;
+    }
+
+    #[must_use]
+    pub const fn result_enum(&self) -> Option<&EnumType> {
+        self.result_enum.as_ref()
+    }
+
+    /// `result` with `result_enum` applied, turned into `Error::ResultType` if it is a
+    /// value of another type than `expect_type` asked for
+    fn check_type(&self, result: Result<Value, Error>) -> Result<Value, Error> {
+        let result = match &self.result_enum {
+            Some(result_enum) => result.map(|value| result_enum.named(value)),
+            None => result,
+        };
+        match (self.expected_type, &result) {
+            (Some(expected), Ok(value)) if value_type_of(value) != expected => {
+                Err(Error::ResultType {
+                    expected: value_type_name(expected),
+                    actual: type_name_of(value),
+                })
+            }
+            _ => result,
+        }
+    }
+
+    /// Fail executions once a comprehension ranges over more than `max` elements
+    pub(crate) const fn set_max_range(&mut self, max: usize) {
+        self.max_range = max;
+    }
+
+    /// Names of the variables the expression references, in order
+    ///
+    /// Comprehension variables and the namespaces of enabled extensions, such as
+    /// `math`, are left out.
+    #[must_use]
+    pub fn variables(&self) -> Vec<String> {
+        self.variables.iter().map(ToString::to_string).collect()
+    }
+
+    /// The field paths the expression reads from its variables, in order
+    ///
+    /// Each path starts at a variable from `variables` and follows the fields
+    /// selected from it, as in `request.headers.authorization`. Dynamic indexes and the
+    /// elements a comprehension iterates appear as `[*]`, as in `user.roles[*]`. A
+    /// variable used as a whole, such as the argument of `size(items)`, appears on
+    /// its own.
+    #[must_use]
+    pub fn dependencies(&self) -> Vec<String> {
+        let Ok(parsed) = cel_parser::parse(&self.source) else {
+            return Vec::new();
+        };
+        dependency_paths(&parsed)
+            .into_iter()
+            .filter(|path| {
+                let root = path.split(['.', '[']).next().unwrap_or_default();
+                self.variables.iter().any(|variable| &**variable == root)
+            })
+            .collect()
+    }
+
+    /// The parsed expression as JSON, with its macros expanded into comprehensions
+    ///
+    /// The JSON is CEL's `Expr` message in the protobuf JSON mapping, so tools built for
+    /// other CEL implementations can read it. Operators appear as calls to their CEL
+    /// names, such as `_+_`, and each node has a unique `id`. Like `metrics`, it
+    /// describes the expression as it is now.
+    #[must_use]
+    pub fn expanded_ast_json(&self) -> String {
+        cel_parser::parse(&self.source)
+            .map_or_else(|_| "null".to_string(), |parsed| expanded_ast(&parsed).to_string())
+    }
+}
+
+/// The source rewrites an execution needs, each giving a variant of the program
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+struct Rewrites {
+    checkpoints: bool,
+    missing_keys: MissingKeys,
+    null_propagation: bool,
+    numeric_promotion: bool,
+    memory_limit: bool,
+    soft_errors: bool,
+    nan_comparisons: bool,
+    iteration_budget: bool,
+    attribute_hook: bool,
+    sorted_ranges: bool,
+    max_range: usize,
+}
+
+impl Rewrites {
+    fn for_context(context: &Context, checkpoints: bool) -> Self {
+        Self {
+            checkpoints,
+            missing_keys: context.missing_keys(),
+            null_propagation: context.null_propagation(),
+            numeric_promotion: context.numeric_promotion(),
+            memory_limit: context.max_memory() > 0,
+            soft_errors: context.soft_errors(),
+            nan_comparisons: nan_comparisons(context),
+            iteration_budget: context.max_iterations() > 0,
+            attribute_hook: context.has_attribute_hook(),
+            sorted_ranges: context.deterministic(),
+            max_range: 0,
+        }
+    }
+}
+
+/// Whether ordering comparisons need the NaN-aware function of `nan`, which the
+/// promoting and null-propagating comparisons already are
+fn nan_comparisons(context: &Context) -> bool {
+    !context.numeric_promotion() && !context.null_propagation() && context.binds_nan()
+}
+
+/// The variables bound in `context`, converted to interpreter values
+pub(crate) fn bindings(context: &Context) -> Result<Vec<(&str, Value)>, Error> {
+    let mut bindings = Vec::new();
+    for (name, value) in context.get_variables() {
+        let cel_value = json_to_cel_value(value)
+            .map_err(|e| Error::Variable(format!("Error converting variable '{name}': {e}")))?;
+        bindings.push((&**name, cel_value));
+    }
+
+    for (name, bytes) in context.get_bytes_variables() {
+        bindings.push((&**name, Value::Bytes(Arc::new(bytes.clone()))));
+    }
+
+    for (name, value) in context.get_value_variables() {
+        bindings.push((&**name, value.clone()));
+    }
+
+    for (name, value) in context.get_borrowed_variables() {
+        bindings.push((name, Value::String(Arc::new(value.to_string()))));
+    }
+
+    Ok(bindings)
+}
+
+/// FNV-1a over the canonical source of `expression`, which is independent of the
+/// Rust version and platform, unlike the standard library hashers
+fn fingerprint(expression: &cel_parser::Expression) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    optimize::unparse(expression)
+        .bytes()
+        .fold(OFFSET_BASIS, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(PRIME))
+}
+
+/// The timestamp `now()` returns: the injected instant, or else the system clock if
+/// `system_clock` allows it and the target has one
+fn current_time(injected: Option<(i64, u32)>, system_clock: bool) -> Option<Value> {
+    let (seconds, nanos) = injected.or_else(|| system_clock.then(system_time).flatten())?;
+    let time = DateTime::from_timestamp(seconds, nanos).unwrap_or(DateTime::UNIX_EPOCH);
+    Some(Value::Timestamp(time.fixed_offset()))
+}
+
+#[cfg(not(all(target_family = "wasm", target_os = "unknown")))]
+fn system_time() -> Option<(i64, u32)> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    Some((i64::try_from(elapsed.as_secs()).unwrap_or(i64::MAX), elapsed.subsec_nanos()))
+}
+
+/// `wasm32-unknown-unknown` has no clock, and reading `SystemTime` there panics
+#[cfg(all(target_family = "wasm", target_os = "unknown"))]
+const fn system_time() -> Option<(i64, u32)> {
+    None
+}
+
+/// The variables `stats` found, leaving out the namespaces `extensions` bind
+fn free_variables(stats: ExpressionStats, extensions: Extensions) -> Vec<Arc<str>> {
+    stats
+        .variables
+        .into_iter()
+        .filter(|name| !extensions.binds(name))
+        .map(|name| intern(&name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_program_compile_and_execute() {
+        let program = Program::compile("greeting + ', ' + name").unwrap();
+        let mut context = Context::new();
+        context.add_variable("greeting".to_string(), serde_json::json!("hello"));
+        context.add_variable("name".to_string(), serde_json::json!("cel"));
+
+        let mut variables = program.variables();
+        variables.sort_unstable();
+        assert_eq!(variables, ["greeting", "name"]);
+        assert_eq!(
+            program.execute(&context).unwrap(),
+            Value::String(Arc::new("hello, cel".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_program_errors() {
+        assert!(matches!(Program::compile("1 + + 2"), Err(Error::Compile(_))));
+
+        let program = Program::compile("missing + 1").unwrap();
+        let error = program.execute(&Context::new()).unwrap_err();
+        assert!(matches!(error, Error::Execution(_)));
+        assert!(error.to_string().starts_with("Execution error: "));
+    }
+
+    #[test]
+    fn test_program_resolver() {
+        let mut context = Context::new();
+        context.set_resolver(|name| match name {
+            "lazy" => Ok(Some(Value::Int(41))),
+            "broken" => Err("backend unavailable".to_string()),
+            _ => Ok(None),
+        });
+
+        let program = Program::compile("lazy + 1").unwrap();
+        assert_eq!(program.execute(&context).unwrap(), Value::Int(42));
+
+        let program = Program::compile("broken").unwrap();
+        assert_eq!(
+            program.execute(&context).unwrap_err().to_string(),
+            "Error resolving variable 'broken': backend unavailable"
+        );
+
+        context.clear_resolver();
+        let program = Program::compile("lazy").unwrap();
+        assert!(program.execute(&context).is_err());
+    }
+
+    #[test]
+    fn test_program_resolver_comprehensions() {
+        let asked = Arc::new(Mutex::new(Vec::new()));
+        let mut context = Context::new();
+        let log = Arc::clone(&asked);
+        context.set_resolver(move |name| {
+            log.lock().unwrap().push(name.to_string());
+            match name {
+                "xs" => Ok(Some(Value::List(Arc::new(vec![Value::Int(1), Value::Int(2)])))),
+                "limit" => Ok(Some(Value::Int(2))),
+                _ => Err(format!("unknown variable {name}")),
+            }
+        });
+
+        let program = Program::compile("xs.all(x, x > 0) && xs.exists(y, y == limit)").unwrap();
+        assert_eq!(program.execute(&context), Ok(Value::Bool(true)));
+        let mut asked = asked.lock().unwrap().clone();
+        asked.sort_unstable();
+        assert_eq!(asked, ["limit", "xs"]);
+
+        let program = Program::compile("[1].map(x, x + 1).exists(y, y == x)").unwrap();
+        assert_eq!(
+            program.execute(&context).unwrap_err().to_string(),
+            "Error resolving variable 'x': unknown variable x"
+        );
+    }
+
+    #[test]
+    fn test_program_host_functions() {
+        let mut context = Context::new();
+        context.add_function("twice", |args| match args {
+            [Value::Int(n)] => Ok(Value::Int(n * 2)),
+            _ => Err("expected an int".to_string()),
+        });
+        context.add_function("size", |_| Ok(Value::Int(-1)));
+        assert!(context.has_function("twice"));
+
+        let program = Program::compile("twice(21) + size([1])").unwrap();
+        assert_eq!(program.execute(&context), Ok(Value::Int(43)));
+
+        let program = Program::compile("twice('a')").unwrap();
+        let message = program.execute(&context).unwrap_err().to_string();
+        assert!(message.contains("expected an int"), "{message}");
+
+        context.reset();
+        assert!(context.has_function("twice"));
+        assert!(context.remove_function("twice"));
+        assert!(!context.remove_function("twice"));
+        assert!(program.execute(&context).is_err());
+    }
+
+    #[test]
+    fn test_program_host_member_functions() {
+        use crate::ffi::CelValueType;
+        use cel_interpreter::objects::Key;
+
+        let mut context = Context::new();
+        context.add_variable("user".to_string(), serde_json::json!({"roles": ["admin"]}));
+        context.add_member_overload(
+            "hasRole",
+            &[CelValueType::Map, CelValueType::String],
+            CelValueType::Bool,
+            |args| {
+                let [Value::Map(user), role] = args else {
+                    return Err("expected a user and a role".to_string());
+                };
+                let roles = user.map.get(&Key::String(Arc::new("roles".to_string())));
+                Ok(Value::Bool(matches!(roles, Some(Value::List(roles)) if roles.contains(role))))
+            },
+        );
+
+        let execute = |expression: &str| Program::compile(expression)?.execute(&context);
+        assert_eq!(
+            execute("user.hasRole('admin') && !user.hasRole('guest')"),
+            Ok(Value::Bool(true))
+        );
+        let message = execute("hasRole(user, 'admin')").unwrap_err().to_string();
+        assert!(message.contains("can only be called as a method"), "{message}");
+        let message = execute("'x'.hasRole('admin')").unwrap_err().to_string();
+        assert!(message.contains("no overload takes (string, string)"), "{message}");
+
+        context.add_function("hasRole", |args| Ok(Value::Bool(args.len() == 2)));
+        assert_eq!(
+            Program::compile("hasRole(user, 'admin')").unwrap().execute(&context),
+            Ok(Value::Bool(true))
+        );
+        context.add_function("twice", |_| Ok(Value::Null));
+        let program = Program::compile("'a'.twice()").unwrap();
+        let message = program.execute(&context).unwrap_err().to_string();
+        assert!(message.contains("cannot be called as a method"), "{message}");
+    }
+
+    #[test]
+    fn test_program_host_overloads() {
+        use crate::ffi::CelValueType;
+
+        let mut context = Context::new();
+        context.add_overload("describe", &[CelValueType::Int], CelValueType::String, |_| {
+            Ok(Value::String(Arc::new("int".to_string())))
+        });
+        context.add_overload(
+            "describe",
+            &[CelValueType::String, CelValueType::Int],
+            CelValueType::String,
+            |_| Ok(Value::String(Arc::new("string, int".to_string()))),
+        );
+        context.add_overload("describe", &[CelValueType::Bool], CelValueType::String, |_| {
+            Ok(Value::Int(1))
+        });
+
+        let execute = |expression: &str| Program::compile(expression)?.execute(&context);
+        assert_eq!(
+            execute("describe(1) + ', ' + describe('a', 2)"),
+            Ok(Value::String(Arc::new("int, string, int".to_string())))
+        );
+        let message = execute("describe(1u)").unwrap_err().to_string();
+        assert!(message.contains("no overload takes (uint)"), "{message}");
+        let message = execute("describe(true)").unwrap_err().to_string();
+        assert!(message.contains("returned int instead of string"), "{message}");
+
+        context.add_function("describe", |_| Ok(Value::Null));
+        assert_eq!(Program::compile("describe(1u)").unwrap().execute(&context), Ok(Value::Null));
+        assert_eq!(
+            Program::compile("describe(2)").unwrap().execute(&context),
+            Ok(Value::String(Arc::new("int".to_string())))
+        );
+    }
+
+    #[test]
+    fn test_program_host_variadic_functions() {
+        use crate::api::Arity;
+
+        let mut context = Context::new();
+        context
+            .add_variadic_function("coalesce", Arity::at_least(1), |args| {
+                Ok(args.iter().find(|arg| **arg != Value::Null).cloned().unwrap_or(Value::Null))
+            })
+            .unwrap();
+        let fill = Value::String(Arc::new("*".to_string()));
+        context
+            .add_variadic_function("pad", Arity::between(2, 3).with_defaults(vec![fill]), |args| {
+                let [Value::String(text), Value::Int(width), Value::String(fill)] = args else {
+                    return Err("expected a string, a width and a fill".to_string());
+                };
+                let padding = usize::try_from(*width).unwrap_or(0).saturating_sub(text.len());
+                Ok(Value::String(Arc::new(format!("{text}{}", fill.repeat(padding)))))
+            })
+            .unwrap();
+
+        let execute = |expression: &str| Program::compile(expression)?.execute(&context);
+        assert_eq!(
+            execute("coalesce(null, null, 3) == 3 && coalesce(1) == 1"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            execute("pad('a', 3) + pad('b', 2, '-')"),
+            Ok(Value::String(Arc::new("a**b-".to_string())))
+        );
+        let message = execute("coalesce()").unwrap_err().to_string();
+        assert!(message.contains("takes at least 1 argument, got 0"), "{message}");
+        let message = execute("pad('a')").unwrap_err().to_string();
+        assert!(message.contains("takes 2 to 3 arguments, got 1"), "{message}");
+
+        let arity = Arity::between(1, 2).with_defaults(vec![Value::Null, Value::Null]);
+        assert!(matches!(
+            context.add_variadic_function("f", arity, |_| Ok(Value::Null)),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(!context.has_function("f"));
+    }
+
+    #[test]
+    fn test_program_depth_limit() {
+        let expression = format!("{}0", "1 + ".repeat(150));
+        let program = Program::compile(&expression).unwrap();
+        let mut context = Context::new();
+        assert_eq!(context.max_depth(), crate::api::DEFAULT_MAX_DEPTH);
+        assert_eq!(program.execute(&context).unwrap(), Value::Int(150));
+
+        context.set_max_depth(100);
+        assert_eq!(
+            program.execute(&context).unwrap_err().to_string(),
+            "Execution error: expression too deep: nesting depth 151 exceeds the limit of 100"
+        );
+        assert_eq!(Program::compile("1 + 2").unwrap().execute(&context).unwrap(), Value::Int(3));
+
+        context.set_max_depth(0);
+        assert_eq!(program.execute(&context).unwrap(), Value::Int(150));
+    }
+
+    #[test]
+    fn test_program_matches() {
+        let program =
+            Program::compile("name.matches('^c[a-z]+$') && matches(name, pattern)").unwrap();
+        let mut context = Context::new();
+        context.add_variable("name".to_string(), serde_json::json!("cel"));
+        context.add_variable("pattern".to_string(), serde_json::json!("e"));
+        assert_eq!(program.execute(&context).unwrap(), Value::Bool(true));
+
+        context.add_variable("pattern".to_string(), serde_json::json!("("));
+        let error = program.execute(&context).unwrap_err().to_string();
+        assert!(error.contains("invalid regex '('"), "{error}");
+    }
+
+    #[test]
+    fn test_program_type() {
+        let mut context = Context::new();
+        context.add_variable("user".to_string(), serde_json::json!({"age": 30}));
+        let execute = |expression: &str| Program::compile(expression).unwrap().execute(&context);
+
+        assert_eq!(execute("type(user.age)"), Ok(type_value("int")));
+        assert_eq!(execute("type(type(1))"), Ok(type_value("type")));
+        assert_eq!(execute("type(duration('1s'))"), Ok(type_value("google.protobuf.Duration")));
+        assert_eq!(
+            execute("type(user.age) == type(1) && type(user) != type([])"),
+            Ok(Value::Bool(true))
+        );
+    }
+
+    #[test]
+    fn test_program_optimize() {
+        let mut program =
+            Program::compile("size('xyz') + 1 > n && (debug ? n / 0 > 1 : true)").unwrap();
+        let mut context = Context::new();
+        context.add_variable("n".to_string(), serde_json::json!(3));
+        context.add_variable("debug".to_string(), serde_json::json!(false));
+
+        let optimization = program.optimize().unwrap();
+        assert_eq!(optimization.nodes_before, 15);
+        assert_eq!(optimization.nodes_after, 12);
+        assert_eq!(program.execute(&context).unwrap(), Value::Bool(true));
+        assert_eq!(program.optimize().unwrap().nodes_before, 12);
+
+        let mut program = Program::compile("(1 < 2 ? a : b) + 0").unwrap();
+        program.optimize().unwrap();
+        assert_eq!(program.variables(), ["a"]);
+    }
+
+    #[test]
+    fn test_program_expect_type() {
+        use crate::ffi::CelValueType;
+
+        let mut program = Program::compile("n").unwrap();
+        program.expect_type(Some(CelValueType::Int));
+        let mut context = Context::new();
+        context.add_variable("n".to_string(), serde_json::json!(3));
+        assert_eq!(program.execute(&context), Ok(Value::Int(3)));
+        assert_eq!(program.expected_type(), Some(CelValueType::Int));
+
+        context.add_variable("n".to_string(), serde_json::json!(3.5));
+        assert_eq!(
+            program.execute(&context),
+            Err(Error::ResultType {
+                expected: "int",
+                actual: "double"
+            })
+        );
+        let (result, _) = program.execute_traced(&context);
+        assert!(matches!(result, Err(Error::ResultType { .. })));
+
+        program.expect_type(None);
+        assert_eq!(program.execute(&context), Ok(Value::Float(3.5)));
+    }
+
+    #[test]
+    fn test_program_metrics() {
+        use crate::api::ASSUMED_ITERATIONS;
+
+        let program = Program::compile("size(xs) > 1 && xs.all(x, x > 0)").unwrap();
+        assert_eq!(
+            program.metrics(),
+            ProgramMetrics {
+                nodes: 11,
+                depth: 4,
+                comprehensions: 1,
+                calls: 1,
+                cost: 7 + 4 * ASSUMED_ITERATIONS,
+            }
+        );
+
+        let nested = Program::compile("xs.all(x, xs.all(y, x != y))").unwrap();
+        assert_eq!(nested.metrics().comprehensions, 2);
+        assert!(nested.metrics().cost > ASSUMED_ITERATIONS * ASSUMED_ITERATIONS);
+    }
+
+    #[test]
+    fn test_program_fingerprint() {
+        let program = Program::compile("a+b  == 'x' // admins").unwrap();
+        let same = Program::compile("(a + b) == \"x\"").unwrap();
+        let different = Program::compile("a + b == 'y'").unwrap();
+
+        assert_eq!(program.fingerprint(), same.fingerprint());
+        assert_ne!(program.fingerprint(), different.fingerprint());
+        // Fixed so that a change to the canonical form is noticed: it invalidates
+        // fingerprints hosts have stored
+        assert_eq!(program.fingerprint_hex(), "692607cc7f502f92");
+
+        let mut optimized = Program::compile("1 + 1 == a").unwrap();
+        let fingerprint = optimized.fingerprint();
+        optimized.optimize().unwrap();
+        assert_eq!(optimized.fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn test_program_injected_now() {
+        let program =
+            Program::compile("now() == timestamp('2024-01-01T00:00:00.5Z') && now() == now()")
+                .unwrap();
+        let mut context = Context::new();
+        context.set_now(1_704_067_200, 500_000_000).unwrap();
+        assert_eq!(program.execute(&context).unwrap(), Value::Bool(true));
+
+        context.clear_now();
+        assert_eq!(program.execute(&context).unwrap(), Value::Bool(false));
+        let program = Program::compile("now() > timestamp('2024-01-01T00:00:00Z')").unwrap();
+        assert_eq!(program.execute(&context).unwrap(), Value::Bool(true));
+
+        assert!(context.set_now(0, 1_000_000_000).is_err());
+        assert!(context.set_now(i64::MAX, 0).is_err());
+        assert_eq!(context.now(), None);
+    }
+
+    #[test]
+    fn test_program_execute_cancellable() {
+        let program = Program::compile("xs.all(x, xs.exists(y, y == x + 1))").unwrap();
+        let mut context = Context::new();
+        context.add_variable("xs".to_string(), serde_json::json!([1, 2, 3]));
+        let token = CancelToken::new();
+        assert_eq!(program.execute_cancellable(&context, &token).unwrap(), Value::Bool(false));
+
+        token.cancel();
+        assert!(matches!(program.execute_cancellable(&context, &token), Err(Error::Cancelled)));
+
+        // Long enough to still be running when the other thread cancels it
+        let xs: Vec<i64> = (0..5_000).collect();
+        context.add_variable("xs".to_string(), serde_json::json!(xs));
+        let token = CancelToken::new();
+        let canceller = token.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            canceller.cancel();
+        });
+        assert!(matches!(program.execute_cancellable(&context, &token), Err(Error::Cancelled)));
+        thread.join().unwrap();
+    }
+
+    /// A program whose rewrites cannot compile, as if `unparse` wrote something the
+    /// parser rejects
+    fn unrewritable(expression: &str) -> Program {
+        let mut program = Program::compile(expression).unwrap();
+        program.source = "xs.all(x,".to_string();
+        program
+    }
+
+    #[test]
+    fn test_program_execute_cancellable_unrewritable() {
+        let program = unrewritable("xs.all(x, x > 0)");
+        let mut context = Context::new();
+        context.add_variable("xs".to_string(), serde_json::json!([1, 2, 3]));
+        assert_eq!(program.execute(&context).unwrap(), Value::Bool(true));
+
+        let token = CancelToken::new();
+        let Err(Error::Execution(message)) = program.execute_cancellable(&context, &token) else {
+            panic!("expected the checkpoints to fail to install");
+        };
+        assert!(message.starts_with("cannot rewrite the program for this context"), "{message}");
+    }
+
+    #[test]
+    fn test_program_max_memory_unrewritable() {
+        let program = unrewritable("xs.map(x, string(x) + '!')");
+        let mut context = Context::new();
+        context.add_variable("xs".to_string(), serde_json::json!([1, 2, 3]));
+        context.set_max_memory(1 << 20);
+        let Err(Error::Execution(message)) = program.execute(&context) else {
+            panic!("expected the metering to fail to install");
+        };
+        assert!(message.starts_with("cannot rewrite the program for this context"), "{message}");
+    }
+
+    #[test]
+    fn test_program_max_iterations_unrewritable() {
+        let program = unrewritable("xs.all(x, xs.all(y, x + y > 0))");
+        let mut context = Context::new();
+        context.add_variable("xs".to_string(), serde_json::json!([1, 2, 3]));
+        context.set_max_iterations(4);
+        let Err(Error::Execution(message)) = program.execute(&context) else {
+            panic!("expected the iteration checks to fail to install");
+        };
+        assert!(message.starts_with("cannot rewrite the program for this context"), "{message}");
+    }
+
+    #[test]
+    fn test_program_attribute_hook_unrewritable() {
+        let program = unrewritable("user.ssn == '123'");
+        let mut context = Context::new();
+        context.add_variable("user".to_string(), serde_json::json!({"ssn": "123"}));
+        assert_eq!(program.execute(&context).unwrap(), Value::Bool(true));
+
+        context.set_attribute_hook(|_| crate::api::AttributeAccess::Deny("restricted".into()));
+        let Err(Error::Execution(message)) = program.execute(&context) else {
+            panic!("expected the hook to fail to install");
+        };
+        assert!(message.starts_with("cannot rewrite the program for this context"), "{message}");
+        assert!(program.execute_traced(&context).0.is_err());
+    }
+
+    #[test]
+    fn test_program_execute_limits() {
+        let program = Program::compile("xs.all(x, x > 0)").unwrap();
+        let mut context = Context::new();
+        context.add_variable("xs".to_string(), serde_json::json!([1, 2, 3]));
+
+        let token = CancelToken::new();
+        token.set_budget(3);
+        assert_eq!(program.execute_cancellable(&context, &token).unwrap(), Value::Bool(true));
+        let Err(Error::Execution(message)) = program.execute_cancellable(&context, &token) else {
+            panic!("expected the budget to be spent");
+        };
+        assert_eq!(message, "evaluation exceeded its cost budget");
+
+        let token = CancelToken::new();
+        token.cancel_after(std::time::Duration::ZERO);
+        assert!(matches!(program.execute_cancellable(&context, &token), Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn test_program_dependencies() {
+        let options = CompileOptions {
+            math_ext: true,
+            ..CompileOptions::default()
+        };
+        let program = Program::compile_with_options(
+            "math.greatest(user.age, 18) > 20 && request.headers['x-id'] in user.ids",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(program.dependencies(), ["request.headers[\"x-id\"]", "user.age", "user.ids"]);
+    }
+
+    #[test]
+    fn test_program_expanded_ast_json() {
+        let program = Program::compile("xs.exists(x, x == 1)").unwrap();
+        let ast: serde_json::Value = serde_json::from_str(&program.expanded_ast_json()).unwrap();
+        assert_eq!(ast["comprehensionExpr"]["loopStep"]["callExpr"]["function"], "_||_");
+    }
+}
@@ -0,0 +1,125 @@
+use super::Value;
+use cel_interpreter::objects::{Key, Map};
+use prost_reflect::{DynamicMessage, Kind, MapKey, MessageDescriptor, Value as ProtoValue};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Convert a decoded message into a CEL map keyed by field name
+///
+/// Fields with explicit presence (message fields, `optional` and `oneof` members and
+/// proto2 fields) are only included when set, so `has()` reports their presence.
+/// Other fields are always included with their default value, since field access on
+/// a map cannot fall back to a default. Enums evaluate to their number, and the
+/// wrapper, `Struct`, `Value` and `ListValue` well-known types to the value they wrap.
+#[must_use]
+pub fn message_to_cel_value(message: &DynamicMessage) -> Value {
+    if let Some(value) = well_known_to_cel_value(message) {
+        return value;
+    }
+
+    let map = message
+        .descriptor()
+        .fields()
+        .filter(|field| !field.supports_presence() || message.has_field(field))
+        .map(|field| {
+            let value = proto_to_cel_value(&message.get_field(&field));
+            (Key::String(Arc::new(field.name().to_string())), value)
+        })
+        .collect::<HashMap<_, _>>();
+    Value::Map(Map { map: Arc::new(map) })
+}
+
+fn well_known_to_cel_value(message: &DynamicMessage) -> Option<Value> {
+    let field = match message.descriptor().full_name() {
+        "google.protobuf.BoolValue"
+        | "google.protobuf.BytesValue"
+        | "google.protobuf.DoubleValue"
+        | "google.protobuf.FloatValue"
+        | "google.protobuf.Int32Value"
+        | "google.protobuf.Int64Value"
+        | "google.protobuf.StringValue"
+        | "google.protobuf.UInt32Value"
+        | "google.protobuf.UInt64Value" => "value",
+        "google.protobuf.Struct" => "fields",
+        "google.protobuf.ListValue" => "values",
+        "google.protobuf.Value" => {
+            let kind = message.descriptor().fields().find(|field| message.has_field(field));
+            return Some(match kind {
+                Some(field) if field.name() != "null_value" => {
+                    proto_to_cel_value(&message.get_field(&field))
+                }
+                _ => Value::Null,
+            });
+        }
+        _ => return None,
+    };
+
+    message.get_field_by_name(field).map(|value| proto_to_cel_value(&value))
+}
+
+fn proto_to_cel_value(value: &ProtoValue) -> Value {
+    match value {
+        ProtoValue::Bool(b) => Value::Bool(*b),
+        ProtoValue::I32(i) => Value::Int(i64::from(*i)),
+        ProtoValue::I64(i) => Value::Int(*i),
+        ProtoValue::U32(u) => Value::UInt(u64::from(*u)),
+        ProtoValue::U64(u) => Value::UInt(*u),
+        ProtoValue::F32(f) => Value::Float(f64::from(*f)),
+        ProtoValue::F64(f) => Value::Float(*f),
+        ProtoValue::String(s) => Value::String(Arc::new(s.clone())),
+        ProtoValue::Bytes(b) => Value::Bytes(Arc::new(b.to_vec())),
+        ProtoValue::EnumNumber(n) => Value::Int(i64::from(*n)),
+        ProtoValue::Message(message) => message_to_cel_value(message),
+        ProtoValue::List(items) => {
+            Value::List(Arc::new(items.iter().map(proto_to_cel_value).collect()))
+        }
+        ProtoValue::Map(entries) => {
+            let map = entries
+                .iter()
+                .map(|(key, value)| (map_key(key), proto_to_cel_value(value)))
+                .collect::<HashMap<_, _>>();
+            Value::Map(Map { map: Arc::new(map) })
+        }
+    }
+}
+
+fn map_key(key: &MapKey) -> Key {
+    match key {
+        MapKey::Bool(b) => Key::Bool(*b),
+        MapKey::I32(i) => Key::Int(i64::from(*i)),
+        MapKey::I64(i) => Key::Int(*i),
+        MapKey::U32(u) => Key::Uint(u64::from(*u)),
+        MapKey::U64(u) => Key::Uint(*u),
+        MapKey::String(s) => Key::String(Arc::new(s.clone())),
+    }
+}
+
+/// Fully qualified names and numbers of every enum value a message can reference
+///
+/// Binding these as dotted paths lets expressions compare enum fields against
+/// constants such as `acme.Status.ACTIVE`.
+#[must_use]
+pub fn enum_constants(descriptor: &MessageDescriptor) -> Vec<(String, i32)> {
+    let mut seen = HashSet::new();
+    let mut pending = vec![descriptor.clone()];
+    let mut constants = Vec::new();
+
+    while let Some(message) = pending.pop() {
+        if !seen.insert(message.full_name().to_string()) {
+            continue;
+        }
+        for field in message.fields() {
+            match field.kind() {
+                Kind::Message(nested) => pending.push(nested),
+                Kind::Enum(enum_type) => constants.extend(enum_type.values().map(|value| {
+                    (format!("{}.{}", enum_type.full_name(), value.name()), value.number())
+                })),
+                _ => {}
+            }
+        }
+    }
+
+    constants.sort();
+    constants.dedup();
+    constants
+}
@@ -0,0 +1,324 @@
+//! Renaming variables in stored expressions, for when a schema field is renamed
+//!
+//! The rename rewrites the source text rather than re-rendering the syntax tree, so
+//! comments, quoting and layout survive. Only references to the variable change:
+//! string contents, field selections such as `x.old`, function names, message field
+//! names and comprehension variables that shadow it are left alone.
+
+use super::dependencies::dependency_paths;
+use super::options::COMPREHENSIONS;
+use super::{CompileError, Error};
+use std::collections::BTreeSet;
+
+/// Words that cannot name a variable
+const RESERVED: &[&str] = &[
+    "as",
+    "break",
+    "const",
+    "continue",
+    "else",
+    "false",
+    "for",
+    "function",
+    "if",
+    "import",
+    "in",
+    "let",
+    "loop",
+    "namespace",
+    "null",
+    "package",
+    "return",
+    "true",
+    "var",
+    "void",
+    "while",
+];
+
+/// `expression` with every reference to the variable `old` renamed to `new`
+///
+/// # Errors
+///
+/// Returns `Error::Compile` if `expression` cannot be parsed, and
+/// `Error::InvalidArgument` if either name is not a valid variable name or a
+/// comprehension variable named `new` would capture a renamed reference.
+pub fn rename_variable(expression: &str, old: &str, new: &str) -> Result<String, Error> {
+    for name in [old, new] {
+        if !is_identifier(name) || RESERVED.contains(&name) {
+            return Err(Error::InvalidArgument(format!("'{name}' is not a valid variable name")));
+        }
+    }
+    let before = variables(expression)?;
+    if old == new || !before.contains(old) {
+        return Ok(expression.to_string());
+    }
+
+    let offsets = Scanner::new(expression).references(old, new)?;
+    let mut renamed = String::with_capacity(expression.len());
+    let mut copied = 0;
+    for offset in offsets {
+        renamed.push_str(&expression[copied..offset]);
+        renamed.push_str(new);
+        copied = offset + old.len();
+    }
+    renamed.push_str(&expression[copied..]);
+
+    // The scan is lexical, so check it found exactly the references the parser sees
+    let expected: BTreeSet<String> = before
+        .into_iter()
+        .map(|name| if name == old { new.to_string() } else { name })
+        .collect();
+    if variables(&renamed)? != expected {
+        return Err(Error::InvalidArgument(format!("cannot rename '{old}' in this expression")));
+    }
+    Ok(renamed)
+}
+
+/// The variables `expression` references
+fn variables(expression: &str) -> Result<BTreeSet<String>, Error> {
+    let parsed = cel_parser::parse(expression)
+        .map_err(|e| Error::Compile(CompileError::from_parse_error(expression, &e)))?;
+    Ok(dependency_paths(&parsed)
+        .into_iter()
+        .map(|path| match path.find(['.', '[']) {
+            Some(end) => path[..end].to_string(),
+            None => path,
+        })
+        .collect())
+}
+
+pub(crate) fn is_identifier(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// An open bracket and what its contents mean for identifiers inside it
+enum Frame<'a> {
+    /// Comprehension arguments, after the first, see `binds` instead of a variable
+    Comprehension {
+        binds: &'a str,
+    },
+    /// A message construction, whose keys are field names
+    Message,
+    Other,
+}
+
+pub(crate) struct Scanner<'a> {
+    source: &'a str,
+    bytes: &'a [u8],
+    frames: Vec<Frame<'a>>,
+    /// Comprehension variables seen, with whether an enclosing comprehension binds
+    /// the same name
+    declarations: Vec<(usize, &'a str, bool)>,
+}
+
+impl<'a> Scanner<'a> {
+    pub(crate) fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            bytes: source.as_bytes(),
+            frames: Vec::new(),
+            declarations: Vec::new(),
+        }
+    }
+
+    /// Offsets and names of the comprehension variables, with whether each shadows a
+    /// variable of an enclosing comprehension
+    pub(crate) fn comprehension_variables(mut self) -> Vec<(usize, &'a str, bool)> {
+        // No identifier is empty, so this scan only records the declarations
+        let _ = self.references("", "");
+        self.declarations
+    }
+
+    /// Offsets of the identifiers referring to the variable `old`
+    fn references(&mut self, old: &str, new: &str) -> Result<Vec<usize>, Error> {
+        let source = self.source;
+        let mut offsets = Vec::new();
+        // The last token, as `b'a'` for identifiers and literals, and the identifier
+        let mut previous = (0u8, "");
+        // A comprehension macro was just selected, so the next `(` opens its arguments
+        let mut comprehension = false;
+        // The offset of a comprehension's variable, which is not a reference
+        let mut declaration = None;
+
+        let mut i = self.skip_trivia(0);
+        while i < self.bytes.len() {
+            let byte = self.bytes[i];
+            if let Some(end) = self.string_end(i) {
+                previous = (b'a', "");
+                i = self.skip_trivia(end);
+                continue;
+            }
+            if byte.is_ascii_digit() {
+                let mut end = i;
+                while end < self.bytes.len()
+                    && (self.bytes[end].is_ascii_alphanumeric()
+                        || (self.bytes[end] == b'.'
+                            && self.bytes.get(end + 1).is_some_and(u8::is_ascii_digit)))
+                {
+                    end += 1;
+                }
+                previous = (b'a', "");
+                i = self.skip_trivia(end);
+                continue;
+            }
+            if byte.is_ascii_alphabetic() || byte == b'_' {
+                let end = self.identifier_end(i);
+                let word = &source[i..end];
+                let next = self.bytes.get(self.skip_trivia(end)).copied();
+                if previous.0 == b'.' {
+                    comprehension = next == Some(b'(') && COMPREHENSIONS.contains(&word);
+                } else if declaration == Some(i) || next == Some(b'(') {
+                    // A comprehension variable or a function name
+                } else if next == Some(b':')
+                    && matches!(previous.0, b'{' | b',')
+                    && matches!(self.frames.last(), Some(Frame::Message))
+                {
+                    // A field name in a message construction
+                } else if word == old && !self.binds(old) {
+                    if self.binds(new) {
+                        return Err(Error::InvalidArgument(format!(
+                            "renaming '{old}' to '{new}' would refer to a comprehension variable"
+                        )));
+                    }
+                    offsets.push(i);
+                }
+                previous = (b'a', word);
+                i = self.skip_trivia(end);
+                continue;
+            }
+
+            match byte {
+                b'(' => {
+                    let frame = if std::mem::take(&mut comprehension) {
+                        let start = self.skip_trivia(i + 1);
+                        let end = self.identifier_end(start);
+                        let after = self.bytes.get(self.skip_trivia(end)).copied();
+                        if end > start && after == Some(b',') {
+                            let binds = &source[start..end];
+                            declaration = Some(start);
+                            self.declarations.push((start, binds, self.binds(binds)));
+                            Frame::Comprehension { binds }
+                        } else {
+                            Frame::Other
+                        }
+                    } else {
+                        Frame::Other
+                    };
+                    self.frames.push(frame);
+                }
+                b'{' => {
+                    let message =
+                        previous.0 == b'a' && !previous.1.is_empty() && previous.1 != "in";
+                    self.frames.push(if message { Frame::Message } else { Frame::Other });
+                }
+                b'[' => self.frames.push(Frame::Other),
+                b')' | b'}' | b']' => {
+                    self.frames.pop();
+                }
+                _ => {}
+            }
+            previous = (byte, "");
+            i = self.skip_trivia(i + 1);
+        }
+        Ok(offsets)
+    }
+
+    /// Whether an enclosing comprehension binds `name`
+    fn binds(&self, name: &str) -> bool {
+        self.frames
+            .iter()
+            .any(|frame| matches!(frame, Frame::Comprehension { binds } if *binds == name))
+    }
+
+    pub(crate) fn identifier_end(&self, mut i: usize) -> usize {
+        while i < self.bytes.len()
+            && (self.bytes[i].is_ascii_alphanumeric() || self.bytes[i] == b'_')
+        {
+            i += 1;
+        }
+        i
+    }
+
+    /// The offset after any whitespace and comments at `i`
+    pub(crate) fn skip_trivia(&self, mut i: usize) -> usize {
+        loop {
+            while i < self.bytes.len() && self.bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            if !self.bytes[i..].starts_with(b"//") {
+                return i;
+            }
+            while i < self.bytes.len() && self.bytes[i] != b'\n' {
+                i += 1;
+            }
+        }
+    }
+
+    /// The offset after the string or bytes literal starting at `i`, if one does
+    pub(crate) fn string_end(&self, i: usize) -> Option<usize> {
+        let mut start = i;
+        let mut raw = false;
+        while start < self.bytes.len() && start - i < 2 && b"rRbB".contains(&self.bytes[start]) {
+            raw |= matches!(self.bytes[start], b'r' | b'R');
+            start += 1;
+        }
+        let quote = *self.bytes.get(start).filter(|&&b| b == b'\'' || b == b'"')?;
+        let quotes = [quote; 3];
+        let width = if self.bytes[start..].starts_with(&quotes) { 3 } else { 1 };
+        let delimiter = &quotes[..width];
+
+        let mut j = start + delimiter.len();
+        while j < self.bytes.len() {
+            if self.bytes[j..].starts_with(delimiter) {
+                return Some(j + delimiter.len());
+            }
+            j += if self.bytes[j] == b'\\' && !raw { 2 } else { 1 };
+        }
+        Some(self.bytes.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename(expression: &str, old: &str, new: &str) -> String {
+        rename_variable(expression, old, new).unwrap()
+    }
+
+    #[test]
+    fn test_rename_references() {
+        assert_eq!(
+            rename("user.name == 'user' && size(user) > 0 // user check", "user", "account"),
+            "account.name == 'user' && size(account) > 0 // user check"
+        );
+        assert_eq!(rename("a.user + user.user", "user", "u"), "a.user + u.user");
+        assert_eq!(rename("r'user' + user + \"user\"", "user", "u"), "r'user' + u + \"user\"");
+        assert_eq!(rename("Point{x: x}.x + {x: 1}[x]", "x", "y"), "Point{x: y}.x + {y: 1}[y]");
+        assert_eq!(rename("x in {x: 1}", "x", "y"), "y in {y: 1}");
+        assert_eq!(rename("P{a: c ? x : 2, x: x}", "x", "y"), "P{a: c ? y : 2, x: y}");
+        assert_eq!(rename("other + 1", "user", "u"), "other + 1");
+    }
+
+    #[test]
+    fn test_rename_respects_comprehensions() {
+        assert_eq!(
+            rename("xs.all(x, x > min) && x == 1", "x", "item"),
+            "xs.all(x, x > min) && item == 1"
+        );
+        assert_eq!(rename("x.map(x, x + 1)", "x", "y"), "y.map(x, x + 1)");
+        assert_eq!(rename("xs.exists(x, x == limit)", "limit", "max"), "xs.exists(x, x == max)");
+        assert!(matches!(
+            rename_variable("xs.exists(x, x == limit)", "limit", "x"),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_rename_invalid() {
+        assert!(matches!(rename_variable("a +", "a", "b"), Err(Error::Compile(_))));
+        assert!(matches!(rename_variable("a", "a", "in"), Err(Error::InvalidArgument(_))));
+        assert!(matches!(rename_variable("a", "a", "b.c"), Err(Error::InvalidArgument(_))));
+    }
+}
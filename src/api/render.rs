@@ -0,0 +1,193 @@
+//! Text renderings of values for logs and test output
+//!
+//! The debug form is a CEL expression that evaluates to the value, so it keeps every
+//! type apart: `1`, `1u` and `1.0` differ, strings are quoted and bytes are written as
+//! `b"..."` literals. The display form is for people: a value with a natural text form,
+//! such as a string or a timestamp, is written as that text, and lists and maps, whose
+//! elements would run together, fall back to the debug form.
+
+use super::optimize::{literal, unparse};
+use super::value::{
+    bigint_parts, decimal_parts, format_duration, key_value, optional_parts, type_parts,
+};
+use super::Value;
+use chrono::SecondsFormat;
+use std::fmt::Write;
+
+/// `value` written as a CEL expression that evaluates to it, such as
+/// `{"a": [1u, b"\x00"], "b": timestamp("2024-01-01T00:00:00Z")}`
+///
+/// Map entries are ordered by key, so equal maps render the same. Types are written as
+/// their names, as in `int`.
+#[must_use]
+pub fn debug_string(value: &Value) -> String {
+    let mut out = String::new();
+    write_debug(&mut out, value);
+    out
+}
+
+/// `value` as text for people: strings and bytes as their contents, timestamps in
+/// RFC 3339, durations such as `1.5s`, big integers, decimals and uints as their
+/// digits, types as their names and optionals as their contents
+///
+/// Other values, including lists and maps, are written as by `debug_string`.
+#[must_use]
+pub fn display_string(value: &Value) -> String {
+    if let Some(inner) = optional_parts(value) {
+        return inner.map_or_else(|| debug_string(value), display_string);
+    }
+    if let Some(n) = bigint_parts(value) {
+        return n.to_string();
+    }
+    if let Some(decimal) = decimal_parts(value) {
+        return decimal.to_string();
+    }
+    if let Some(name) = type_parts(value) {
+        return name.to_string();
+    }
+    match value {
+        Value::String(s) => s.to_string(),
+        Value::Bytes(b) => String::from_utf8_lossy(b).into_owned(),
+        Value::UInt(u) => u.to_string(),
+        Value::Float(f) if !f.is_finite() => non_finite_name(*f).to_string(),
+        Value::Timestamp(time) => time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        Value::Duration(duration) => format_duration(*duration),
+        value => debug_string(value),
+    }
+}
+
+fn write_debug(out: &mut String, value: &Value) {
+    if let Some(inner) = optional_parts(value) {
+        match inner {
+            Some(inner) => {
+                out.push_str("optional.of(");
+                write_debug(out, inner);
+                out.push(')');
+            }
+            None => out.push_str("optional.none()"),
+        }
+        return;
+    }
+    if let Some(n) = bigint_parts(value) {
+        let _ = write!(out, "bigint(\"{n}\")");
+        return;
+    }
+    if let Some(decimal) = decimal_parts(value) {
+        let _ = write!(out, "decimal(\"{decimal}\")");
+        return;
+    }
+    if let Some(name) = type_parts(value) {
+        out.push_str(name);
+        return;
+    }
+
+    match value {
+        Value::List(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_debug(out, item);
+            }
+            out.push(']');
+        }
+        Value::Map(map) => {
+            let mut entries = map
+                .map
+                .iter()
+                .map(|(key, value)| (debug_string(&key_value(key)), value))
+                .collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(&key);
+                out.push_str(": ");
+                write_debug(out, value);
+            }
+            out.push('}');
+        }
+        Value::Float(f) if !f.is_finite() => {
+            let _ = write!(out, "double(\"{}\")", non_finite_name(*f));
+        }
+        Value::Timestamp(time) => {
+            let text = time.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+            let _ = write!(out, "timestamp(\"{text}\")");
+        }
+        Value::Duration(duration) => {
+            let _ = write!(out, "duration(\"{}\")", format_duration(*duration));
+        }
+        Value::Function(name, _) => out.push_str(name),
+        value => {
+            if let Some(literal) = literal(value) {
+                out.push_str(&unparse(&literal));
+            }
+        }
+    }
+}
+
+/// The name `double()` parses a NaN or infinite double from
+const fn non_finite_name(f: f64) -> &'static str {
+    if f.is_nan() {
+        "NaN"
+    } else if f > 0.0 {
+        "Infinity"
+    } else {
+        "-Infinity"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::value::{bigint_value, optional_value, parse_decimal, parse_timestamp};
+    use crate::api::{Context, Program};
+    use std::sync::Arc;
+
+    fn execute(expression: &str) -> Value {
+        Program::compile(expression).unwrap().execute(&Context::new()).unwrap()
+    }
+
+    #[test]
+    fn test_debug_string() {
+        let value = execute("{'b': [1, 2u, 3.0, 'x\"y', b'\\x00a'], 'a': null, 1: true}");
+        assert_eq!(
+            debug_string(&value),
+            r#"{"a": null, "b": [1, 2u, 3.0, "x\"y", b"\x00a"], 1: true}"#
+        );
+        assert_eq!(
+            debug_string(&execute("[duration('90s'), type(1)]")),
+            r#"[duration("90s"), int]"#
+        );
+        assert_eq!(
+            debug_string(&Value::List(Arc::new(vec![
+                parse_timestamp("2024-01-01T00:00:00Z").unwrap(),
+                optional_value(Some(bigint_value(-5))),
+                optional_value(None),
+                parse_decimal("1.50").unwrap(),
+                Value::Float(f64::NEG_INFINITY),
+            ]))),
+            "[timestamp(\"2024-01-01T00:00:00Z\"), optional.of(bigint(\"-5\")), \
+             optional.none(), decimal(\"1.50\"), double(\"-Infinity\")]"
+        );
+    }
+
+    #[test]
+    fn test_display_string() {
+        assert_eq!(display_string(&execute("'x\"y'")), "x\"y");
+        assert_eq!(display_string(&execute("b'abc'")), "abc");
+        assert_eq!(display_string(&execute("18446744073709551615u")), "18446744073709551615");
+        assert_eq!(display_string(&execute("1.5")), "1.5");
+        assert_eq!(display_string(&execute("duration('1.5s')")), "1.5s");
+        assert_eq!(display_string(&execute("type('')")), "string");
+        assert_eq!(display_string(&execute("['a', 1u]")), r#"["a", 1u]"#);
+        assert_eq!(
+            display_string(&parse_timestamp("2024-01-01T00:00:00.5Z").unwrap()),
+            "2024-01-01T00:00:00.500Z"
+        );
+        assert_eq!(display_string(&optional_value(Some(parse_decimal("2.0").unwrap()))), "2.0");
+    }
+}
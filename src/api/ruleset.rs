@@ -0,0 +1,306 @@
+//! Named rules compiled and evaluated together
+//!
+//! Policies and routing tables are sets of related expressions run against the same
+//! request. Evaluating them as one unit converts the context's variables once for
+//! every rule, instead of once per rule.
+//!
+//! Routing and authorization tables instead want the first rule that matches:
+//! `first_match` evaluates the rules in order and stops there.
+
+use super::intern::intern;
+use super::program::bindings;
+use super::{CompileOptions, Context, Error, Program, Value};
+use std::sync::Arc;
+
+/// A rule's source, and its program once the set is compiled
+#[derive(Debug)]
+struct Rule {
+    name: Arc<str>,
+    expression: String,
+    program: Option<Program>,
+}
+
+/// An ordered set of named expressions
+///
+/// Add rules with `add`, compile them all with `compile`, then evaluate every rule
+/// with `execute_all`. Adding a rule leaves the set uncompiled until the next
+/// `compile`, and a set with a rule that failed to compile cannot run until the rule
+/// is removed or the set compiles.
+#[derive(Debug, Default)]
+pub struct RuleSet {
+    options: CompileOptions,
+    rules: Vec<Rule>,
+    compiled: bool,
+    /// Decides whether a rule's value matches in `first_match`, if not `true`
+    predicate: Option<Program>,
+}
+
+impl RuleSet {
+    /// An empty set whose rules are compiled with `options`
+    #[must_use]
+    pub fn new(options: CompileOptions) -> Self {
+        Self {
+            options,
+            ..Self::default()
+        }
+    }
+
+    /// Add a rule, to be compiled by the next `compile`
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if the set already has a rule named `name`.
+    pub fn add(&mut self, name: &str, expression: impl Into<String>) -> Result<(), Error> {
+        if self.rules.iter().any(|rule| &*rule.name == name) {
+            return Err(Error::InvalidArgument(format!("rule '{name}' is already defined")));
+        }
+        self.rules.push(Rule {
+            name: intern(name),
+            expression: expression.into(),
+            program: None,
+        });
+        self.compiled = false;
+        Ok(())
+    }
+
+    /// Compile every rule not yet compiled
+    ///
+    /// # Errors
+    ///
+    /// Returns the name and error of each rule that failed to compile, in order. The
+    /// other rules stay compiled, but the set cannot run until every rule compiles.
+    pub fn compile(&mut self) -> Result<(), Vec<(Arc<str>, Error)>> {
+        let mut failures = Vec::new();
+        for rule in self.rules.iter_mut().filter(|rule| rule.program.is_none()) {
+            match Program::compile_with_options(&rule.expression, &self.options) {
+                Ok(program) => rule.program = Some(program),
+                Err(e) => failures.push((Arc::clone(&rule.name), e)),
+            }
+        }
+        self.compiled = failures.is_empty();
+        if self.compiled {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+
+    /// Remove the rule named `name`, returning whether there was one
+    pub fn remove(&mut self, name: &str) -> bool {
+        let before = self.rules.len();
+        self.rules.retain(|rule| &*rule.name != name);
+        if self.rules.len() == before {
+            return false;
+        }
+        self.compiled = self.rules.iter().all(|rule| rule.program.is_some());
+        true
+    }
+
+    #[must_use]
+    pub const fn is_compiled(&self) -> bool {
+        self.compiled
+    }
+
+    /// Number of rules in the set
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Rule names, in the order they were added
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.rules.iter().map(|rule| &*rule.name)
+    }
+
+    /// The compiled program of the rule named `name`, or `None` if there is no such
+    /// rule or it is not compiled
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&Program> {
+        self.programs().find(|&(rule, _)| &**rule == name).map(|(_, program)| program)
+    }
+
+    /// Evaluate every rule against `context`, in the order they were added
+    ///
+    /// A rule that fails does not stop the others; its entry holds the error.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Execution` if the set is not compiled, and `Error::Variable`
+    /// if a variable of `context` cannot be converted.
+    pub fn execute_all(
+        &self,
+        context: &Context,
+    ) -> Result<Vec<(Arc<str>, Result<Value, Error>)>, Error> {
+        let bindings = self.shared_bindings(context)?;
+        Ok(self
+            .programs()
+            .map(|(name, program)| (Arc::clone(name), program.execute_with(context, &bindings)))
+            .collect())
+    }
+
+    /// Match rules in `first_match` by evaluating `expression` with the rule's value
+    /// bound to `result`, such as `result == 'deny'`, instead of by the value being
+    /// `true`; `None` restores the default
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Compile` if the expression cannot be compiled, leaving the
+    /// predicate unchanged.
+    pub fn set_predicate(&mut self, expression: Option<&str>) -> Result<(), Error> {
+        self.predicate = expression
+            .map(|expression| Program::compile_with_options(expression, &self.options))
+            .transpose()?;
+        Ok(())
+    }
+
+    /// Evaluate the rules in order, stopping at the first whose value matches,
+    /// and return its name and value, or `None` if no rule matched
+    ///
+    /// A value matches if it is `true`, or if the predicate set with `set_predicate`
+    /// returns `true` for it. Rules after the match are not evaluated.
+    ///
+    /// # Errors
+    ///
+    /// Fails as `execute_all` does, and also stops at the first rule that fails to
+    /// evaluate, or whose value the predicate fails on, naming the rule in the error.
+    /// Skipping a failed rule could let a later rule grant what it would have denied.
+    pub fn first_match(&self, context: &Context) -> Result<Option<(Arc<str>, Value)>, Error> {
+        let bindings = self.shared_bindings(context)?;
+        for (name, program) in self.programs() {
+            let value = program.execute_with(context, &bindings).map_err(|e| in_rule(name, e))?;
+            if self.matches(&value).map_err(|e| in_rule(name, e))? {
+                return Ok(Some((Arc::clone(name), value)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether a rule's value selects it in `first_match`
+    fn matches(&self, value: &Value) -> Result<bool, Error> {
+        let Some(predicate) = &self.predicate else {
+            return Ok(*value == Value::Bool(true));
+        };
+        let mut context = Context::new();
+        context.add_value_variable("result".to_string(), value.clone());
+        match predicate.execute(&context)? {
+            Value::Bool(matched) => Ok(matched),
+            other => Err(Error::Execution(format!("predicate returned {other:?}, not a bool"))),
+        }
+    }
+
+    /// The variables of `context` converted once for every rule, if the set can run
+    fn shared_bindings<'a>(&self, context: &'a Context) -> Result<Vec<(&'a str, Value)>, Error> {
+        if !self.compiled {
+            return Err(Error::Execution("Rule set not compiled".to_string()));
+        }
+        bindings(context)
+    }
+
+    fn programs(&self) -> impl Iterator<Item = (&Arc<str>, &Program)> {
+        self.rules
+            .iter()
+            .filter_map(|rule| rule.program.as_ref().map(|program| (&rule.name, program)))
+    }
+}
+
+/// `error` with the name of the rule it came from
+fn in_rule(name: &str, error: Error) -> Error {
+    match error {
+        Error::Execution(message) => Error::Execution(format!("rule '{name}': {message}")),
+        Error::Variable(message) => Error::Variable(format!("rule '{name}': {message}")),
+        error => error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ruleset_execute_all() {
+        let mut rules = RuleSet::default();
+        rules.add("admin", "'admin' in user.roles").unwrap();
+        rules.add("adult", "user.age >= 18").unwrap();
+        rules.add("broken", "user.missing + 1").unwrap();
+        assert!(matches!(rules.add("admin", "true"), Err(Error::InvalidArgument(_))));
+        assert_eq!(rules.names().collect::<Vec<_>>(), ["admin", "adult", "broken"]);
+
+        let mut context = Context::new();
+        context
+            .add_variable("user".to_string(), serde_json::json!({"roles": ["admin"], "age": 17}));
+        assert!(rules.execute_all(&context).is_err());
+        assert!(rules.get("admin").is_none());
+
+        rules.compile().unwrap();
+        assert!(rules.get("adult").is_some());
+        assert!(rules.get("missing").is_none());
+        let results = rules.execute_all(&context).unwrap();
+        assert_eq!(&*results[0].0, "admin");
+        assert_eq!(results[0].1, Ok(Value::Bool(true)));
+        assert_eq!(results[1].1, Ok(Value::Bool(false)));
+        assert!(results[2].1.is_err());
+    }
+
+    #[test]
+    fn test_ruleset_compile_failures() {
+        let mut rules = RuleSet::default();
+        rules.add("good", "1 + 1").unwrap();
+        rules.add("bad", "1 + + ").unwrap();
+        let failures = rules.compile().unwrap_err();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(&*failures[0].0, "bad");
+        assert!(!rules.is_compiled());
+
+        assert!(rules.remove("bad"));
+        assert!(!rules.remove("bad"));
+        assert!(rules.is_compiled());
+        assert_eq!(rules.execute_all(&Context::new()).unwrap()[0].1, Ok(Value::Int(2)));
+    }
+
+    #[test]
+    fn test_ruleset_first_match() {
+        let mut rules = RuleSet::default();
+        rules.add("blocked", "ip in blocklist").unwrap();
+        rules.add("internal", "ip.startsWith('10.')").unwrap();
+        rules.add("never", "missing").unwrap();
+        rules.compile().unwrap();
+
+        let mut context = Context::new();
+        context.add_variable("ip".to_string(), serde_json::json!("10.0.0.1"));
+        context.add_variable("blocklist".to_string(), serde_json::json!(["1.2.3.4"]));
+        let (name, value) = rules.first_match(&context).unwrap().unwrap();
+        assert_eq!((&*name, value), ("internal", Value::Bool(true)));
+
+        context.add_variable("ip".to_string(), serde_json::json!("192.168.0.1"));
+        let error = rules.first_match(&context).unwrap_err().to_string();
+        assert!(error.contains("rule 'never'"), "{error}");
+        assert!(rules.remove("never"));
+        assert_eq!(rules.first_match(&context).unwrap(), None);
+    }
+
+    #[test]
+    fn test_ruleset_predicate() {
+        let mut rules = RuleSet::default();
+        rules.add("allow_admin", "role == 'admin' ? 'allow' : 'skip'").unwrap();
+        rules.add("deny_rest", "'deny'").unwrap();
+        rules.compile().unwrap();
+        rules.set_predicate(Some("result != 'skip'")).unwrap();
+        assert!(rules.set_predicate(Some("result ==")).is_err());
+
+        let mut context = Context::new();
+        context.add_variable("role".to_string(), serde_json::json!("guest"));
+        let (name, value) = rules.first_match(&context).unwrap().unwrap();
+        assert_eq!(&*name, "deny_rest");
+        assert_eq!(value, Value::String(Arc::new("deny".to_string())));
+
+        rules.set_predicate(Some("result")).unwrap();
+        assert!(rules.first_match(&context).is_err());
+        rules.set_predicate(None).unwrap();
+        assert_eq!(rules.first_match(&context).unwrap(), None);
+    }
+}
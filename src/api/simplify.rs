@@ -0,0 +1,374 @@
+//! Logical simplification and equivalence of expressions
+//!
+//! Rule stores gather duplicates that differ only in operand order, in the names of
+//! comprehension variables or in redundant conditions such as `x && true`.
+//! Equivalence compares canonical syntax trees, so it finds rules that are written
+//! differently but read the same; it does not prove that two different conditions
+//! always agree.
+//!
+//! Simplification treats the operands of `!`, `&&` and `||`, and the conditions of
+//! `?:`, as booleans, as they must be for the expression to evaluate. An expression
+//! keeps the value it had wherever it evaluated, but one that failed, such as
+//! `x && false` with `x` missing, may simplify to one that does not.
+
+use super::ext::Extensions;
+use super::optimize::{fold, unparse};
+use super::options::COMPREHENSIONS;
+use super::{CompileError, Error};
+use cel_parser::{Atom, Expression, Member, RelationOp, UnaryOp};
+use std::sync::Arc;
+
+/// Functions and macros that return booleans
+const BOOLEAN_FUNCTIONS: [&str; 8] = [
+    "all",
+    "contains",
+    "endsWith",
+    "exists",
+    "exists_one",
+    "has",
+    "matches",
+    "startsWith",
+];
+
+/// `expression` with its constants folded and redundant logic removed, such as
+/// `x && true` to `x` and `c ? false : true` to `!c`
+///
+/// # Errors
+///
+/// Returns `Error::Compile` if `expression` cannot be parsed.
+pub fn simplify(expression: &str) -> Result<String, Error> {
+    Ok(unparse(&reduce(&fold(&parse(expression)?, Extensions::default()))))
+}
+
+/// Whether `a` and `b` are the same expression up to layout, comments, quoting,
+/// the order of `&&`, `||` and `==` operands, mirrored comparisons such as `a < b`
+/// and `b > a`, and the names of comprehension variables
+///
+/// With `simplify`, both are first simplified as by [`simplify`], so `x && true`
+/// matches `x` and `1 + 1 == n` matches `n == 2`.
+///
+/// # Errors
+///
+/// Returns `Error::Compile` if either expression cannot be parsed.
+pub fn expressions_equivalent(a: &str, b: &str, simplify: bool) -> Result<bool, Error> {
+    let prepare = |source: &str| {
+        let parsed = parse(source)?;
+        let parsed = if simplify { reduce(&fold(&parsed, Extensions::default())) } else { parsed };
+        Ok::<_, Error>(canonical(&parsed, &[]))
+    };
+    Ok(prepare(a)? == prepare(b)?)
+}
+
+fn parse(expression: &str) -> Result<Expression, Error> {
+    cel_parser::parse(expression)
+        .map_err(|e| Error::Compile(CompileError::from_parse_error(expression, &e)))
+}
+
+/// `expression` with its logic simplified bottom-up
+fn reduce(expression: &Expression) -> Expression {
+    match expression {
+        Expression::And(..) => junction(expression, true),
+        Expression::Or(..) => junction(expression, false),
+        Expression::Unary(UnaryOp::Not, operand) => negate(reduce(operand)),
+        Expression::Unary(UnaryOp::DoubleNot, operand) => reduce(operand),
+        Expression::Ternary(condition, if_true, if_false) => {
+            let condition = reduce(condition);
+            let (if_true, if_false) = (reduce(if_true), reduce(if_false));
+            match condition {
+                Expression::Atom(Atom::Bool(true)) => if_true,
+                Expression::Atom(Atom::Bool(false)) => if_false,
+                _ if same(&if_true, &if_false) => if_true,
+                condition => match (&if_true, &if_false) {
+                    (Expression::Atom(Atom::Bool(true)), Expression::Atom(Atom::Bool(false))) => {
+                        condition
+                    }
+                    (Expression::Atom(Atom::Bool(false)), Expression::Atom(Atom::Bool(true))) => {
+                        negate(condition)
+                    }
+                    _ => match condition {
+                        Expression::Unary(UnaryOp::Not, condition) => {
+                            Expression::Ternary(condition, if_false.into(), if_true.into())
+                        }
+                        condition => {
+                            Expression::Ternary(condition.into(), if_true.into(), if_false.into())
+                        }
+                    },
+                },
+            }
+        }
+        Expression::Relation(left, op @ (RelationOp::Equals | RelationOp::NotEquals), right) => {
+            let (left, right) = (reduce(left), reduce(right));
+            let equals = matches!(op, RelationOp::Equals);
+            // Only a boolean compares to a boolean literal as itself; `1 == true` is false
+            match (&left, &right) {
+                (Expression::Atom(Atom::Bool(b)), other)
+                | (other, Expression::Atom(Atom::Bool(b)))
+                    if is_boolean(other) =>
+                {
+                    if *b == equals {
+                        other.clone()
+                    } else {
+                        negate(other.clone())
+                    }
+                }
+                _ => Expression::Relation(left.into(), op.clone(), right.into()),
+            }
+        }
+        _ => map_children(expression, &reduce),
+    }
+}
+
+/// The simplified `&&` (for `and`) or `||` of the operands of `expression`
+fn junction(expression: &Expression, and: bool) -> Expression {
+    let mut reduced = Vec::new();
+    for operand in operands(expression, and) {
+        reduced.extend(operands(&reduce(operand), and).into_iter().cloned());
+    }
+
+    let mut kept: Vec<Expression> = Vec::new();
+    for operand in reduced {
+        match operand {
+            // `true` in a conjunction, or `false` in a disjunction, changes nothing
+            Expression::Atom(Atom::Bool(b)) if b == and => {}
+            Expression::Atom(Atom::Bool(b)) => return Expression::Atom(Atom::Bool(b)),
+            operand if kept.iter().any(|k| same(k, &operand)) => {}
+            operand => kept.push(operand),
+        }
+    }
+
+    // `x && !x` is false and `x || !x` is true
+    let complemented = kept.iter().any(|a| kept.iter().any(|b| same(&negate(a.clone()), b)));
+    if complemented {
+        return Expression::Atom(Atom::Bool(!and));
+    }
+
+    // `x && (x || y)` and `x || (x && y)` are `x`
+    let absorbed: Vec<bool> = kept
+        .iter()
+        .enumerate()
+        .map(|(i, operand)| {
+            let inner = operands(operand, !and);
+            inner.len() > 1
+                && kept.iter().enumerate().any(|(j, other)| {
+                    i != j && inner.iter().any(|candidate| same(candidate, other))
+                })
+        })
+        .collect();
+    let mut kept = kept.into_iter().zip(absorbed).filter(|(_, a)| !a).map(|(k, _)| k);
+
+    match kept.next() {
+        Some(first) => kept.fold(first, |left, right| {
+            if and {
+                Expression::And(left.into(), right.into())
+            } else {
+                Expression::Or(left.into(), right.into())
+            }
+        }),
+        None => Expression::Atom(Atom::Bool(and)),
+    }
+}
+
+/// The operands of a chain of `&&` (for `and`) or `||`, or `expression` itself
+fn operands(expression: &Expression, and: bool) -> Vec<&Expression> {
+    match (expression, and) {
+        (Expression::And(left, right), true) | (Expression::Or(left, right), false) => {
+            let mut all = operands(left, and);
+            all.extend(operands(right, and));
+            all
+        }
+        _ => vec![expression],
+    }
+}
+
+/// The logical negation of `expression`, without a double negation
+fn negate(expression: Expression) -> Expression {
+    match expression {
+        Expression::Atom(Atom::Bool(b)) => Expression::Atom(Atom::Bool(!b)),
+        Expression::Unary(UnaryOp::Not, operand) => *operand,
+        Expression::Relation(left, RelationOp::Equals, right) => {
+            Expression::Relation(left, RelationOp::NotEquals, right)
+        }
+        Expression::Relation(left, RelationOp::NotEquals, right) => {
+            Expression::Relation(left, RelationOp::Equals, right)
+        }
+        expression => Expression::Unary(UnaryOp::Not, expression.into()),
+    }
+}
+
+/// Whether `expression` can only evaluate to a boolean
+fn is_boolean(expression: &Expression) -> bool {
+    match expression {
+        Expression::Atom(Atom::Bool(_))
+        | Expression::Relation(..)
+        | Expression::And(..)
+        | Expression::Or(..)
+        | Expression::Unary(UnaryOp::Not | UnaryOp::DoubleNot, _) => true,
+        Expression::FunctionCall(function, ..) => {
+            matches!(function.as_ref(), Expression::Ident(name)
+                if BOOLEAN_FUNCTIONS.contains(&name.as_str()))
+        }
+        _ => false,
+    }
+}
+
+/// Whether `a` and `b` are equivalent, so one of them is redundant
+fn same(a: &Expression, b: &Expression) -> bool {
+    a == b || canonical(a, &[]) == canonical(b, &[])
+}
+
+/// `expression` in a canonical form, with `scopes` holding each enclosing
+/// comprehension variable and the name it is canonically given
+fn canonical(expression: &Expression, scopes: &[(Arc<String>, Arc<String>)]) -> Expression {
+    let recurse = |expression: &Expression| canonical(expression, scopes);
+    match expression {
+        Expression::And(..) | Expression::Or(..) => {
+            let and = matches!(expression, Expression::And(..));
+            let mut all: Vec<Expression> =
+                operands(expression, and).into_iter().map(recurse).collect();
+            all.sort_by_cached_key(unparse);
+            all.dedup();
+            let mut all = all.into_iter();
+            let first = all.next().expect("a junction has operands");
+            all.fold(first, |left, right| {
+                if and {
+                    Expression::And(left.into(), right.into())
+                } else {
+                    Expression::Or(left.into(), right.into())
+                }
+            })
+        }
+        Expression::Relation(left, op, right) => {
+            let (left, right) = (recurse(left), recurse(right));
+            let (left, op, right) = match op {
+                RelationOp::GreaterThan => (right, RelationOp::LessThan, left),
+                RelationOp::GreaterThanEq => (right, RelationOp::LessThanEq, left),
+                RelationOp::Equals | RelationOp::NotEquals if unparse(&right) < unparse(&left) => {
+                    (right, op.clone(), left)
+                }
+                op => (left, op.clone(), right),
+            };
+            Expression::Relation(left.into(), op, right.into())
+        }
+        Expression::Ident(name) => match scopes.iter().rev().find(|(bound, _)| bound == name) {
+            Some((_, canonical)) => Expression::Ident(Arc::clone(canonical)),
+            None => expression.clone(),
+        },
+        Expression::FunctionCall(function, Some(target), args) => {
+            let var = match (function.as_ref(), args.first()) {
+                (Expression::Ident(name), Some(Expression::Ident(var)))
+                    if COMPREHENSIONS.contains(&name.as_str()) =>
+                {
+                    var
+                }
+                _ => return map_children(expression, &recurse),
+            };
+            // Not a valid identifier, so it cannot collide with a variable
+            let renamed = Arc::new(format!("@{}", scopes.len()));
+            let mut inner = scopes.to_vec();
+            inner.push((Arc::clone(var), Arc::clone(&renamed)));
+            let body = args[1..].iter().map(|arg| canonical(arg, &inner));
+            Expression::FunctionCall(
+                function.clone(),
+                Some(recurse(target).into()),
+                std::iter::once(Expression::Ident(renamed)).chain(body).collect(),
+            )
+        }
+        Expression::Map(entries) => {
+            let mut entries: Vec<_> =
+                entries.iter().map(|(key, value)| (recurse(key), recurse(value))).collect();
+            entries.sort_by_cached_key(|(key, _)| unparse(key));
+            Expression::Map(entries)
+        }
+        Expression::Member(operand, member) => match member.as_ref() {
+            Member::Fields(fields) => {
+                let mut fields: Vec<_> =
+                    fields.iter().map(|(name, value)| (name.clone(), recurse(value))).collect();
+                fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+                Expression::Member(recurse(operand).into(), Member::Fields(fields).into())
+            }
+            _ => map_children(expression, &recurse),
+        },
+        _ => map_children(expression, &recurse),
+    }
+}
+
+/// `expression` with `f` applied to each of its direct subexpressions
+fn map_children(expression: &Expression, f: &dyn Fn(&Expression) -> Expression) -> Expression {
+    let boxed = |expression: &Expression| Box::new(f(expression));
+    match expression {
+        Expression::Arithmetic(left, op, right) => {
+            Expression::Arithmetic(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Relation(left, op, right) => {
+            Expression::Relation(boxed(left), op.clone(), boxed(right))
+        }
+        Expression::Ternary(condition, if_true, if_false) => {
+            Expression::Ternary(boxed(condition), boxed(if_true), boxed(if_false))
+        }
+        Expression::Or(left, right) => Expression::Or(boxed(left), boxed(right)),
+        Expression::And(left, right) => Expression::And(boxed(left), boxed(right)),
+        Expression::Unary(op, operand) => Expression::Unary(op.clone(), boxed(operand)),
+        Expression::Member(operand, member) => {
+            let member = match member.as_ref() {
+                Member::Attribute(name) => Member::Attribute(name.clone()),
+                Member::Index(index) => Member::Index(boxed(index)),
+                Member::Fields(fields) => Member::Fields(
+                    fields.iter().map(|(name, value)| (name.clone(), f(value))).collect(),
+                ),
+            };
+            Expression::Member(boxed(operand), member.into())
+        }
+        Expression::FunctionCall(function, target, args) => Expression::FunctionCall(
+            function.clone(),
+            target.as_deref().map(boxed),
+            args.iter().map(f).collect(),
+        ),
+        Expression::List(items) => Expression::List(items.iter().map(f).collect()),
+        Expression::Map(entries) => {
+            Expression::Map(entries.iter().map(|(key, value)| (f(key), f(value))).collect())
+        }
+        Expression::Atom(_) | Expression::Ident(_) => expression.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simplified(expression: &str) -> String {
+        simplify(expression).unwrap()
+    }
+
+    #[test]
+    fn test_simplify_logic() {
+        assert_eq!(simplified("x && true"), "x");
+        assert_eq!(simplified("false || x || false"), "x");
+        assert_eq!(simplified("x && (1 > 2)"), "false");
+        assert_eq!(simplified("a && b && a"), "a && b");
+        assert_eq!(simplified("a || !a"), "true");
+        assert_eq!(simplified("a && (a || b)"), "a");
+        assert_eq!(simplified("!!(a == b)"), "a == b");
+        assert_eq!(simplified("!(a == b)"), "a != b");
+        assert_eq!(simplified("c ? false : true"), "!c");
+        assert_eq!(simplified("!c ? x : y"), "c ? y : x");
+        assert_eq!(simplified("(a > 1) == true"), "a > 1");
+        assert_eq!(simplified("a == true"), "a == true");
+        assert_eq!(simplified("xs.all(x, x > 0 && true)"), "xs.all(x, x > 0)");
+    }
+
+    #[test]
+    fn test_expressions_equivalent() {
+        let equivalent = |a, b| expressions_equivalent(a, b, false).unwrap();
+        assert!(equivalent("a && b == 'x' // rule", "\"x\" == b && (a)"));
+        assert!(equivalent("a < b", "b > a"));
+        assert!(equivalent("xs.all(x, x > n)", "xs.all(y, n < y)"));
+        assert!(equivalent("{'a': 1, 'b': 2}", "{'b': 2, 'a': 1}"));
+        assert!(!equivalent("a - b", "b - a"));
+        assert!(!equivalent("xs.all(x, x > n)", "xs.all(n, n > x)"));
+        assert!(!equivalent("x && true", "x"));
+
+        assert!(expressions_equivalent("x && true", "x", true).unwrap());
+        assert!(expressions_equivalent("n == 1 + 1", "2 == n", true).unwrap());
+        assert!(matches!(expressions_equivalent("a +", "a", false), Err(Error::Compile(_))));
+    }
+}
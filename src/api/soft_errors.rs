@@ -0,0 +1,470 @@
+//! Soft errors: failing host functions evaluate to error values instead of failing
+//!
+//! Under `Context::set_soft_errors` a host function that fails returns an error value,
+//! and the program is rewritten so error values behave as CEL specifies. `&&` and `||`
+//! bind their left operand with a one-element `map` and go through functions that
+//! absorb an error when the other operand decides the result, so `error && false` is
+//! false like `false && error`, and `all`, `exists` and the other comprehensions
+//! combine the values of their predicates the same way. Every other operator and
+//! function call checks the operands that can evaluate to an error first, giving the
+//! first error as its value: `f() + 1` becomes
+//! `[f()].map(_soft_v, _soft_error(_soft_v) ? _soft_v : _soft_v + 1)[0]`. An
+//! evaluation whose result is an error fails with its message.
+
+use super::ext::Extensions;
+use super::options::COMPREHENSIONS;
+use super::value::{error_parts, error_value, type_name_of};
+use super::{Error, Value, BUILTIN_FUNCTIONS};
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::{Context as CelContext, ExecutionError};
+use cel_parser::{Atom, Expression, Member};
+use std::sync::Arc;
+
+const BINDING: &str = "_soft_v";
+const IS_ERROR: &str = "_soft_error";
+const IS_FALSE: &str = "_soft_false";
+const IS_TRUE: &str = "_soft_true";
+const AND: &str = "_soft_and";
+const OR: &str = "_soft_or";
+const PICK: &str = "_soft_pick";
+const LIST: &str = "_soft_list";
+const ALL: &str = "_soft_all";
+const ANY: &str = "_soft_any";
+const ONE: &str = "_soft_one";
+const FILTER: &str = "_soft_filter";
+
+/// Add the functions of `with_soft_errors` to `ctx`
+pub(crate) fn register(ctx: &mut CelContext) {
+    ctx.add_function(IS_ERROR, |Arguments(args): Arguments| {
+        single(IS_ERROR, &args).map(|value| Value::Bool(error_parts(value).is_some()))
+    });
+    ctx.add_function(IS_FALSE, |Arguments(args): Arguments| {
+        single(IS_FALSE, &args).map(|value| Value::Bool(*value == Value::Bool(false)))
+    });
+    ctx.add_function(IS_TRUE, |Arguments(args): Arguments| {
+        single(IS_TRUE, &args).map(|value| Value::Bool(*value == Value::Bool(true)))
+    });
+
+    ctx.add_function(AND, |Arguments(args): Arguments| match args.as_slice() {
+        [left, right] => Ok(logical("&&", [left, right], false)),
+        _ => Err(ExecutionError::function_error(AND, "invalid arguments")),
+    });
+    ctx.add_function(OR, |Arguments(args): Arguments| match args.as_slice() {
+        [left, right] => Ok(logical("||", [left, right], true)),
+        _ => Err(ExecutionError::function_error(OR, "invalid arguments")),
+    });
+
+    ctx.add_function(PICK, |Arguments(args): Arguments| {
+        Ok::<_, ExecutionError>(first_error(&args).unwrap_or_else(|| Value::List(args)))
+    });
+    ctx.add_function(LIST, |Arguments(args): Arguments| match args.as_slice() {
+        [Value::List(items)] => Ok(first_error(items).unwrap_or_else(|| args[0].clone())),
+        _ => Err(ExecutionError::function_error(LIST, "invalid arguments")),
+    });
+
+    ctx.add_function(ALL, |Arguments(args): Arguments| {
+        list(ALL, &args).map(|results| logical("all", results, false))
+    });
+    ctx.add_function(ANY, |Arguments(args): Arguments| {
+        list(ANY, &args).map(|results| logical("exists", results, true))
+    });
+    ctx.add_function(ONE, |Arguments(args): Arguments| {
+        list(ONE, &args).map(|results| {
+            let mut count = 0;
+            for result in results {
+                match result {
+                    Value::Bool(true) => count += 1,
+                    Value::Bool(false) => {}
+                    result => return not_bool("exists_one", result),
+                }
+            }
+            Value::Bool(count == 1)
+        })
+    });
+    ctx.add_function(FILTER, |Arguments(args): Arguments| {
+        list(FILTER, &args).and_then(|pairs| {
+            let mut kept = Vec::new();
+            for pair in pairs {
+                let Value::List(pair) = pair else {
+                    return Err(ExecutionError::function_error(FILTER, "invalid arguments"));
+                };
+                match pair.as_slice() {
+                    [item, Value::Bool(true)] => kept.push(item.clone()),
+                    [_, Value::Bool(false)] => {}
+                    [_, result] => return Ok(not_bool("filter", result)),
+                    _ => return Err(ExecutionError::function_error(FILTER, "invalid arguments")),
+                }
+            }
+            Ok(Value::List(Arc::new(kept)))
+        })
+    });
+}
+
+fn single<'a>(function: &str, args: &'a [Value]) -> Result<&'a Value, ExecutionError> {
+    match args {
+        [value] => Ok(value),
+        _ => Err(ExecutionError::function_error(function, "invalid arguments")),
+    }
+}
+
+fn list<'a>(function: &str, args: &'a [Value]) -> Result<&'a [Value], ExecutionError> {
+    match args {
+        [Value::List(items)] => Ok(items.as_slice()),
+        _ => Err(ExecutionError::function_error(function, "invalid arguments")),
+    }
+}
+
+/// The first of `values` that is an error
+fn first_error(values: &[Value]) -> Option<Value> {
+    values.iter().find(|value| error_parts(value).is_some()).cloned()
+}
+
+/// The error of applying the logical operator `op` to a value that is not a bool
+fn not_bool(op: &str, value: &Value) -> Value {
+    if error_parts(value).is_some() {
+        return value.clone();
+    }
+    error_value(format!("{op} expects bool operands, got {}", type_name_of(value)))
+}
+
+/// `operands` combined by `&&` if `absorbing` is false or by `||` if it is true:
+/// `absorbing` if any operand is, else the first error, else the other bool
+fn logical<'a>(
+    op: &str,
+    operands: impl IntoIterator<Item = &'a Value> + Clone,
+    absorbing: bool,
+) -> Value {
+    if operands.clone().into_iter().any(|operand| *operand == Value::Bool(absorbing)) {
+        return Value::Bool(absorbing);
+    }
+    match operands.into_iter().find(|operand| !matches!(operand, Value::Bool(_))) {
+        Some(operand) => not_bool(op, operand),
+        None => Value::Bool(!absorbing),
+    }
+}
+
+/// `result`, failing with the message of the error value it evaluated to, if any
+pub(crate) fn resolve(result: Result<Value, Error>) -> Result<Value, Error> {
+    if let Ok(value) = &result {
+        if let Some(message) = error_parts(value) {
+            return Err(Error::Execution(message.to_string()));
+        }
+    }
+    result
+}
+
+/// Whether `expression` can evaluate to an error value: whether it calls a function
+/// that neither the library nor `extensions` provides, which may be a host function
+fn may_fail(expression: &Expression, extensions: Extensions) -> bool {
+    let fails = |expression: &Expression| may_fail(expression, extensions);
+    match expression {
+        Expression::FunctionCall(function, target, args) => {
+            let host = match function.as_ref() {
+                Expression::Ident(name) => {
+                    !(BUILTIN_FUNCTIONS.contains(&name.as_str()) || extensions.provides(name))
+                }
+                _ => true,
+            };
+            host || target.as_deref().is_some_and(fails) || args.iter().any(fails)
+        }
+        Expression::Arithmetic(left, _, right)
+        | Expression::Relation(left, _, right)
+        | Expression::And(left, right)
+        | Expression::Or(left, right) => fails(left) || fails(right),
+        Expression::Ternary(condition, if_true, if_false) => {
+            fails(condition) || fails(if_true) || fails(if_false)
+        }
+        Expression::Unary(_, operand) => fails(operand),
+        Expression::Member(operand, member) => {
+            fails(operand)
+                || match member.as_ref() {
+                    Member::Attribute(_) => false,
+                    Member::Index(index) => fails(index),
+                    Member::Fields(fields) => fields.iter().any(|(_, value)| fails(value)),
+                }
+        }
+        Expression::List(items) => items.iter().any(fails),
+        Expression::Map(entries) => entries.iter().any(|(key, value)| fails(key) || fails(value)),
+        Expression::Atom(_) | Expression::Ident(_) => false,
+    }
+}
+
+fn ident(name: &str) -> Expression {
+    Expression::Ident(Arc::new(name.to_string()))
+}
+
+fn call(function: &str, args: Vec<Expression>) -> Expression {
+    Expression::FunctionCall(ident(function).into(), None, args)
+}
+
+fn item(list: Expression, index: usize) -> Expression {
+    let index = Expression::Atom(Atom::Int(i64::try_from(index).unwrap_or(i64::MAX)));
+    Expression::Member(list.into(), Member::Index(index.into()).into())
+}
+
+fn comprehension(function: &str, target: Expression, args: Vec<Expression>) -> Expression {
+    Expression::FunctionCall(ident(function).into(), Some(target.into()), args)
+}
+
+/// `body` evaluated once with `value` bound, as `[value].map(_soft_v, body)[0]`
+fn bind(value: Expression, body: impl FnOnce(Expression) -> Expression) -> Expression {
+    let body = body(ident(BINDING));
+    item(
+        comprehension("map", Expression::List(vec![value]), vec![ident(BINDING), body]),
+        0,
+    )
+}
+
+/// `body`, or `value` itself if it is an error
+fn bind_strict(value: Expression, body: impl FnOnce(Expression) -> Expression) -> Expression {
+    bind(value, |bound| {
+        Expression::Ternary(
+            call(IS_ERROR, vec![bound.clone()]).into(),
+            bound.clone().into(),
+            body(bound).into(),
+        )
+    })
+}
+
+/// `rebuild` applied to `operands` rewritten, once those that can evaluate to an error
+/// are checked, with the first error as the value if there is one
+fn strict(
+    operands: &[&Expression],
+    extensions: Extensions,
+    rebuild: impl FnOnce(Vec<Expression>) -> Expression,
+) -> Expression {
+    let mut rewritten: Vec<Expression> =
+        operands.iter().map(|operand| with_soft_errors(operand, extensions)).collect();
+    let fallible: Vec<usize> =
+        (0..operands.len()).filter(|&i| may_fail(operands[i], extensions)).collect();
+    match fallible.as_slice() {
+        [] => rebuild(rewritten),
+        &[i] => {
+            let value = std::mem::replace(&mut rewritten[i], Expression::Atom(Atom::Null));
+            bind_strict(value, |bound| {
+                rewritten[i] = bound;
+                rebuild(rewritten)
+            })
+        }
+        _ => {
+            let values = fallible
+                .iter()
+                .map(|&i| std::mem::replace(&mut rewritten[i], Expression::Atom(Atom::Null)))
+                .collect();
+            bind_strict(call(PICK, values), |bound| {
+                for (n, &i) in fallible.iter().enumerate() {
+                    rewritten[i] = item(bound.clone(), n);
+                }
+                rebuild(rewritten)
+            })
+        }
+    }
+}
+
+/// `left && right` if `absorbing` is false or `left || right` if it is true, with the
+/// right operand evaluated unless the left one is `absorbing`
+fn logical_expression(
+    left: &Expression,
+    right: &Expression,
+    absorbing: bool,
+    extensions: Extensions,
+) -> Expression {
+    let (test, combine) = if absorbing { (IS_TRUE, OR) } else { (IS_FALSE, AND) };
+    let right = with_soft_errors(right, extensions);
+    bind(with_soft_errors(left, extensions), |bound| {
+        Expression::Ternary(
+            call(test, vec![bound.clone()]).into(),
+            Expression::Atom(Atom::Bool(absorbing)).into(),
+            call(combine, vec![bound, right]).into(),
+        )
+    })
+}
+
+/// `expression` with error values propagated and absorbed as CEL specifies, through
+/// the functions `register` adds
+///
+/// Subexpressions that cannot evaluate to an error are left as they are.
+pub(crate) fn with_soft_errors(expression: &Expression, extensions: Extensions) -> Expression {
+    let recurse = |expression: &Expression| with_soft_errors(expression, extensions);
+    if !may_fail(expression, extensions) {
+        return expression.clone();
+    }
+
+    match expression {
+        Expression::And(left, right) => logical_expression(left, right, false, extensions),
+        Expression::Or(left, right) => logical_expression(left, right, true, extensions),
+        Expression::Ternary(condition, if_true, if_false) => {
+            let (if_true, if_false) = (recurse(if_true), recurse(if_false));
+            if !may_fail(condition, extensions) {
+                return Expression::Ternary(condition.clone(), if_true.into(), if_false.into());
+            }
+            bind_strict(recurse(condition), |bound| {
+                Expression::Ternary(bound.into(), if_true.into(), if_false.into())
+            })
+        }
+        Expression::Arithmetic(left, op, right) => {
+            strict(&[&**left, &**right], extensions, |operands| {
+                let [left, right] = pair(operands);
+                Expression::Arithmetic(left.into(), op.clone(), right.into())
+            })
+        }
+        Expression::Relation(left, op, right) => {
+            strict(&[&**left, &**right], extensions, |operands| {
+                let [left, right] = pair(operands);
+                Expression::Relation(left.into(), op.clone(), right.into())
+            })
+        }
+        Expression::Unary(op, operand) => strict(&[&**operand], extensions, |mut operands| {
+            Expression::Unary(op.clone(), operands.remove(0).into())
+        }),
+        Expression::Member(operand, member) => match member.as_ref() {
+            Member::Attribute(_) => strict(&[&**operand], extensions, |mut operands| {
+                Expression::Member(operands.remove(0).into(), member.clone())
+            }),
+            Member::Index(index) => strict(&[&**operand, &**index], extensions, |operands| {
+                let [operand, index] = pair(operands);
+                Expression::Member(operand.into(), Member::Index(index.into()).into())
+            }),
+            Member::Fields(fields) => {
+                let values: Vec<&Expression> = fields.iter().map(|(_, value)| value).collect();
+                strict(&values, extensions, |values| {
+                    let fields = fields.iter().map(|(name, _)| name.clone()).zip(values);
+                    Expression::Member(operand.clone(), Member::Fields(fields.collect()).into())
+                })
+            }
+        },
+        Expression::List(items) => call(PICK, items.iter().map(recurse).collect()),
+        Expression::Map(entries) => {
+            let operands: Vec<&Expression> =
+                entries.iter().flat_map(|(key, value)| [key, value]).collect();
+            strict(&operands, extensions, |operands| {
+                let mut operands = operands.into_iter();
+                let entries = std::iter::from_fn(|| Some((operands.next()?, operands.next()?)));
+                Expression::Map(entries.collect())
+            })
+        }
+        Expression::FunctionCall(function, target, args) => {
+            call_with_soft_errors(expression, function, target.as_deref(), args, extensions)
+        }
+        Expression::Atom(_) | Expression::Ident(_) => expression.clone(),
+    }
+}
+
+fn pair(operands: Vec<Expression>) -> [Expression; 2] {
+    let mut operands = operands.into_iter();
+    let mut next = || operands.next().unwrap_or(Expression::Atom(Atom::Null));
+    [next(), next()]
+}
+
+/// A function call with its operands checked, or a comprehension whose predicate
+/// results are combined by the soft-error functions
+fn call_with_soft_errors(
+    expression: &Expression,
+    function: &Expression,
+    target: Option<&Expression>,
+    args: &[Expression],
+    extensions: Extensions,
+) -> Expression {
+    let name = match function {
+        Expression::Ident(name) => name.as_str(),
+        _ => "",
+    };
+    if name == "has" {
+        return expression.clone();
+    }
+
+    if COMPREHENSIONS.contains(&name) {
+        let (Some(target), [variable @ Expression::Ident(_), body]) = (target, args) else {
+            return expression.clone();
+        };
+        let body_fails = may_fail(body, extensions);
+        let body = with_soft_errors(body, extensions);
+        let variable = variable.clone();
+        return strict(&[target], extensions, |mut operands| {
+            let target = operands.remove(0);
+            if !body_fails {
+                return comprehension(name, target, vec![variable, body]);
+            }
+            let mapped = |body| comprehension("map", target, vec![variable.clone(), body]);
+            match name {
+                "all" => call(ALL, vec![mapped(body)]),
+                "exists" => call(ANY, vec![mapped(body)]),
+                "exists_one" => call(ONE, vec![mapped(body)]),
+                "filter" => {
+                    call(FILTER, vec![mapped(Expression::List(vec![variable.clone(), body]))])
+                }
+                _ => call(LIST, vec![mapped(body)]),
+            }
+        });
+    }
+
+    let operands: Vec<&Expression> = target.into_iter().chain(args).collect();
+    strict(&operands, extensions, |mut operands| {
+        let target = target.map(|_| Box::new(operands.remove(0)));
+        Expression::FunctionCall(function.clone().into(), target, operands)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::optimize::unparse;
+    use crate::api::{Context, Program};
+
+    fn execute(expression: &str, soft: bool) -> Result<Value, Error> {
+        let mut context = Context::new();
+        context.add_variable("xs".to_string(), serde_json::json!([1, 2, 3]));
+        context.add_function("fail", |_| Err("lookup failed".to_string()));
+        context.add_function("id", |args| Ok(args.first().cloned().unwrap_or(Value::Null)));
+        context.set_soft_errors(soft);
+        Program::compile(expression).unwrap().execute(&context)
+    }
+
+    #[test]
+    fn test_soft_errors_rewrite() {
+        let rewrite = |expression: &str| {
+            unparse(&with_soft_errors(
+                &cel_parser::parse(expression).unwrap(),
+                Extensions::default(),
+            ))
+        };
+        assert_eq!(
+            rewrite("a && size(b) > 1"),
+            unparse(&cel_parser::parse("a && size(b) > 1").unwrap())
+        );
+        assert_eq!(
+            rewrite("f() + 1"),
+            unparse(
+                &cel_parser::parse(
+                    "[f()].map(_soft_v, _soft_error(_soft_v) ? _soft_v : _soft_v + 1)[0]"
+                )
+                .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_soft_errors_absorbed() {
+        assert!(matches!(execute("fail() && false", false), Err(Error::Execution(_))));
+
+        assert_eq!(execute("fail() && false", true), Ok(Value::Bool(false)));
+        assert_eq!(execute("false && fail()", true), Ok(Value::Bool(false)));
+        assert_eq!(execute("fail() || true", true), Ok(Value::Bool(true)));
+        assert_eq!(execute("fail() == 1 || id(true)", true), Ok(Value::Bool(true)));
+        assert_eq!(execute("xs.exists(x, fail() || x == 2)", true), Ok(Value::Bool(true)));
+        assert_eq!(execute("xs.all(x, fail() && x > 5)", true), Ok(Value::Bool(false)));
+        assert_eq!(execute("xs.filter(x, id(x) > 1).size()", true), Ok(Value::Int(2)));
+        assert_eq!(execute("id(2) * 3 == 6 && !id(false)", true), Ok(Value::Bool(true)));
+
+        for expression in [
+            "fail() && true",
+            "fail() + 1 > 0 || false",
+            "[fail()].size() == 1",
+        ] {
+            let message = execute(expression, true).unwrap_err().to_string();
+            assert!(message.contains("lookup failed"), "{expression}: {message}");
+        }
+        let message = execute("xs.all(x, id(x))", true).unwrap_err().to_string();
+        assert!(message.contains("all expects bool operands, got int"), "{message}");
+    }
+}
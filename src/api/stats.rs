@@ -0,0 +1,94 @@
+//! Per-program execution counters, built with the `stats` feature
+
+use super::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Counters for the executions of one compiled program
+///
+/// Only `Program::execute` counts, and evaluation time includes binding the
+/// context's variables but not tracing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProgramStats {
+    pub executions: u64,
+    /// Executions that returned an error
+    pub errors: u64,
+    pub total_time: Duration,
+    /// Message of the most recent error
+    pub last_error: Option<String>,
+}
+
+impl ProgramStats {
+    /// Mean time per execution, or zero before the first
+    #[must_use]
+    pub fn average_time(&self) -> Duration {
+        let nanos = self.total_time.as_nanos().checked_div(u128::from(self.executions));
+        nanos.map_or(Duration::ZERO, |nanos| {
+            Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+        })
+    }
+}
+
+/// Counters updated from shared references, since programs execute through `&self`
+#[derive(Debug, Default)]
+pub(crate) struct StatsRecorder {
+    executions: AtomicU64,
+    errors: AtomicU64,
+    total_ns: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+impl StatsRecorder {
+    pub fn record<T>(&self, elapsed: Duration, result: &Result<T, Error>) {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.executions.fetch_add(1, Ordering::Relaxed);
+        self.total_ns.fetch_add(nanos, Ordering::Relaxed);
+        if let Err(e) = result {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            *self.last_error.lock().unwrap_or_else(PoisonError::into_inner) = Some(e.to_string());
+        }
+    }
+
+    pub fn snapshot(&self) -> ProgramStats {
+        ProgramStats {
+            executions: self.executions.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            total_time: Duration::from_nanos(self.total_ns.load(Ordering::Relaxed)),
+            last_error: self.last_error.lock().unwrap_or_else(PoisonError::into_inner).clone(),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.executions.store(0, Ordering::Relaxed);
+        self.errors.store(0, Ordering::Relaxed);
+        self.total_ns.store(0, Ordering::Relaxed);
+        *self.last_error.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Context, Program};
+
+    #[test]
+    fn test_program_stats() {
+        let program = Program::compile("10 / n").unwrap();
+        let mut context = Context::new();
+        assert_eq!(program.stats(), ProgramStats::default());
+
+        context.add_variable("n".to_string(), serde_json::json!(2));
+        program.execute(&context).unwrap();
+        context.add_variable("n".to_string(), serde_json::json!(0));
+        let error = program.execute(&context).unwrap_err().to_string();
+
+        let stats = program.stats();
+        assert_eq!((stats.executions, stats.errors), (2, 1));
+        assert_eq!(stats.last_error, Some(error));
+        assert!(stats.average_time() <= stats.total_time);
+
+        program.reset_stats();
+        assert_eq!(program.stats(), ProgramStats::default());
+    }
+}
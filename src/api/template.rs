@@ -0,0 +1,272 @@
+//! Expression templates with typed parameter substitution
+//!
+//! Building a rule by pasting a host value into expression source lets a value such as
+//! `0 || true` change what the rule means. A template marks where values go with
+//! `{name}` placeholders instead, and `instantiate` writes each parameter as a CEL
+//! literal of its JSON type, so a parameter can only ever be a value.
+
+use super::optimize;
+use super::rename::Scanner;
+use super::value::json_to_cel_value;
+use super::{CompileError, Error};
+use std::ops::Range;
+
+/// An expression with `{name}` placeholders for parameter values
+///
+/// A placeholder is an identifier in braces, outside string literals and comments,
+/// such as `{min_age}` in `user.age > {min_age}`. Map literals always have a `:`
+/// between their braces, so they are never mistaken for one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    /// Byte ranges of the placeholders in `source`, with the parameter each names
+    placeholders: Vec<(Range<usize>, String)>,
+}
+
+impl Template {
+    /// Find the placeholders in `source` and check that it parses with values in them
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Compile` if `source` does not parse, or a placeholder stands
+    /// for something other than a value, such as a field name or a function.
+    pub fn compile(source: &str) -> Result<Self, Error> {
+        let placeholders = placeholders(source)?;
+
+        // Identifiers as long as the placeholders keep error locations in place
+        let mut probe = source.to_string();
+        for (range, _) in &placeholders {
+            probe.replace_range(range.clone(), &"_".repeat(range.len()));
+        }
+        cel_parser::parse(&probe)
+            .map_err(|e| Error::Compile(CompileError::from_parse_error(source, &e)))?;
+
+        Ok(Self {
+            source: source.to_string(),
+            placeholders,
+        })
+    }
+
+    #[must_use]
+    pub const fn source(&self) -> &str {
+        self.source.as_str()
+    }
+
+    /// Names of the parameters, in order of first use
+    pub fn parameters(&self) -> impl Iterator<Item = &str> {
+        self.placeholders
+            .iter()
+            .enumerate()
+            .filter(|(i, (_, name))| !self.placeholders[..*i].iter().any(|(_, n)| n == name))
+            .map(|(_, (_, name))| name.as_str())
+    }
+
+    /// The expression with every placeholder replaced by its parameter as a literal
+    ///
+    /// Strings are quoted and escaped, integers stay `int` (or `uint` above the `int`
+    /// range), numbers with a fraction become `double`, and arrays and objects become
+    /// lists and maps, so `{"min_age": 21}` turns `user.age > {min_age}` into
+    /// `user.age > 21`. The result is parsed before it is returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if a parameter is missing or `params` has one
+    /// the template does not use, and `Error::Compile` if the result does not parse.
+    pub fn instantiate(
+        &self,
+        params: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<String, Error> {
+        if let Some(unknown) = params.keys().find(|name| self.parameters().all(|p| p != *name)) {
+            return Err(Error::InvalidArgument(format!("unknown template parameter '{unknown}'")));
+        }
+
+        let mut expression = String::with_capacity(self.source.len());
+        let mut copied = 0;
+        for (range, name) in &self.placeholders {
+            let Some(value) = params.get(name) else {
+                return Err(Error::InvalidArgument(format!("missing template parameter '{name}'")));
+            };
+            expression.push_str(&self.source[copied..range.start]);
+            expression.push_str(&literal_source(name, value)?);
+            copied = range.end;
+        }
+        expression.push_str(&self.source[copied..]);
+
+        cel_parser::parse(&expression)
+            .map_err(|e| Error::Compile(CompileError::from_parse_error(&expression, &e)))?;
+        Ok(expression)
+    }
+
+    /// `instantiate` with the parameters given as a JSON object
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if `params_json` is not a JSON object, and
+    /// otherwise fails as `instantiate` does.
+    pub fn instantiate_json(&self, params_json: &str) -> Result<String, Error> {
+        match serde_json::from_str(params_json) {
+            Ok(serde_json::Value::Object(params)) => self.instantiate(&params),
+            Ok(_) => Err(Error::InvalidArgument("Parameters must be a JSON object".to_string())),
+            Err(e) => Err(Error::InvalidArgument(format!("Invalid parameters JSON: {e}"))),
+        }
+    }
+}
+
+/// CEL source for the literal of `value`, parenthesized if it starts with a sign
+fn literal_source(name: &str, value: &serde_json::Value) -> Result<String, Error> {
+    let literal = json_to_cel_value(value).ok().as_ref().and_then(optimize::literal);
+    let Some(literal) = literal else {
+        return Err(Error::InvalidArgument(format!(
+            "template parameter '{name}' cannot be written as a literal"
+        )));
+    };
+
+    let source = optimize::unparse(&literal);
+    Ok(if source.starts_with('-') { format!("({source})") } else { source })
+}
+
+/// The placeholders of `source`, skipping string literals and comments
+fn placeholders(source: &str) -> Result<Vec<(Range<usize>, String)>, Error> {
+    let scanner = Scanner::new(source);
+    let bytes = source.as_bytes();
+    let mut found = Vec::new();
+    let mut previous = 0u8;
+
+    let mut i = scanner.skip_trivia(0);
+    while i < bytes.len() {
+        if let Some(end) = scanner.string_end(i) {
+            previous = b'a';
+            i = scanner.skip_trivia(end);
+            continue;
+        }
+        if bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' {
+            previous = b'a';
+            i = scanner.skip_trivia(scanner.identifier_end(i));
+            continue;
+        }
+
+        if let Some((end, name)) = placeholder_at(source, i) {
+            let next = bytes.get(scanner.skip_trivia(end)).copied();
+            if previous == b'.' || next == Some(b'(') {
+                return Err(Error::Compile(CompileError::at(
+                    source,
+                    i,
+                    end - i,
+                    format!("placeholder '{name}' can only stand for a value"),
+                )));
+            }
+            found.push((i..end, name.to_string()));
+            previous = b'a';
+            i = scanner.skip_trivia(end);
+            continue;
+        }
+        previous = bytes[i];
+        i = scanner.skip_trivia(i + 1);
+    }
+
+    Ok(found)
+}
+
+/// The end and name of the placeholder whose `{` is at `open`, if there is one
+fn placeholder_at(source: &str, open: usize) -> Option<(usize, &str)> {
+    let bytes = source.as_bytes();
+    let skip_spaces = |mut i: usize| {
+        while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+            i += 1;
+        }
+        i
+    };
+    if bytes.get(open) != Some(&b'{') {
+        return None;
+    }
+
+    let start = skip_spaces(open + 1);
+    if !bytes.get(start).is_some_and(|&b| b.is_ascii_alphabetic() || b == b'_') {
+        return None;
+    }
+    let mut end = start;
+    while bytes.get(end).is_some_and(|&b| b.is_ascii_alphanumeric() || b == b'_') {
+        end += 1;
+    }
+    let close = skip_spaces(end);
+    (bytes.get(close) == Some(&b'}')).then(|| (close + 1, &source[start..end]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cel_parser::{Atom, Expression};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn instantiate(template: &str, params: &serde_json::Value) -> Result<String, Error> {
+        let params = params.as_object().unwrap();
+        Template::compile(template).unwrap().instantiate(params)
+    }
+
+    #[test]
+    fn test_template_substitutes_literals() {
+        assert_eq!(
+            instantiate("user.age > {min_age}", &json!({"min_age": 21})).unwrap(),
+            "user.age > 21"
+        );
+        assert_eq!(
+            instantiate(
+                "name == { name } && size(name) < {max}",
+                &json!({"name": "a\"b", "max": 2.5})
+            )
+            .unwrap(),
+            "name == \"a\\\"b\" && size(name) < 2.5"
+        );
+        assert_eq!(
+            instantiate("x - {n} in {allowed}", &json!({"n": -1, "allowed": [1, "a", null]}))
+                .unwrap(),
+            "x - (-1) in [1, \"a\", null]"
+        );
+        assert_eq!(
+            instantiate("{m}.a == {u}", &json!({"m": {"b": true, "a": 1}, "u": u64::MAX})).unwrap(),
+            format!("{{\"a\": 1, \"b\": true}}.a == {}u", u64::MAX)
+        );
+    }
+
+    #[test]
+    fn test_template_prevents_injection() {
+        let injected = instantiate("role == {role}", &json!({"role": "x' || true || '"})).unwrap();
+        assert_eq!(injected, "role == \"x' || true || '\"");
+        let Ok(Expression::Relation(_, _, right)) = cel_parser::parse(&injected) else {
+            panic!("expected a comparison");
+        };
+        let value = Arc::new("x' || true || '".to_string());
+        assert_eq!(*right, Expression::Atom(Atom::String(value)));
+    }
+
+    #[test]
+    fn test_template_placeholders() {
+        let template =
+            Template::compile("'{a}' + {b} // {c}\n + {b} + {'k': v}['k'] + {d}").unwrap();
+        assert_eq!(template.parameters().collect::<Vec<_>>(), ["b", "d"]);
+        assert_eq!(template.source(), "'{a}' + {b} // {c}\n + {b} + {'k': v}['k'] + {d}");
+    }
+
+    #[test]
+    fn test_template_errors() {
+        let Err(Error::Compile(error)) = Template::compile("a + + {b}") else {
+            panic!("expected a compile error");
+        };
+        assert_eq!((error.line, error.column), (1, 5));
+        assert!(matches!(Template::compile("a.{field} == 1"), Err(Error::Compile(_))));
+        assert!(matches!(Template::compile("{f}(1)"), Err(Error::Compile(_))));
+
+        let template = Template::compile("a == {b}").unwrap();
+        assert_eq!(
+            template.instantiate_json("{}").unwrap_err().to_string(),
+            "missing template parameter 'b'"
+        );
+        assert_eq!(
+            template.instantiate_json(r#"{"b": 1, "c": 2}"#).unwrap_err().to_string(),
+            "unknown template parameter 'c'"
+        );
+        assert!(matches!(template.instantiate_json("[1]"), Err(Error::InvalidArgument(_))));
+        assert_eq!(template.instantiate_json(r#"{"b": false}"#).unwrap(), "a == false");
+    }
+}
@@ -0,0 +1,565 @@
+//! Time zones for the timestamp accessors, as in `ts.getHours('Europe/Stockholm')`
+//!
+//! Every accessor from `getFullYear` to `getMilliseconds` takes an optional time zone,
+//! written as a target call or with the timestamp first, as in
+//! `getDayOfWeek(ts, 'America/New_York')`. A zone is a fixed offset such as `+05:30`,
+//! `UTC`, or an IANA name looked up in the tz database. The library embeds no tzdata:
+//! zones are read from the compiled `TZif` files of a zoneinfo directory,
+//! `/usr/share/zoneinfo` or `$TZDIR` unless `set_tzdata_dir` names another, or come
+//! from a host that registers them with `add_time_zone`. Instants past the last
+//! transition of a file follow the POSIX TZ rule in its footer, so slim `TZif` files
+//! cover future years too.
+
+use super::value::type_name_of;
+use super::Error;
+use cel_interpreter::extractors::Arguments;
+use cel_interpreter::{Context as CelContext, ExecutionError, FunctionContext, Value};
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, Timelike};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+
+type Accessor = fn(&DateTime<FixedOffset>) -> i64;
+
+/// The timestamp accessors and the field each reads, in the zone it is given
+const ACCESSORS: [(&str, Accessor); 10] = [
+    ("getFullYear", |t| i64::from(t.year())),
+    ("getMonth", |t| i64::from(t.month0())),
+    ("getDayOfYear", |t| i64::from(t.ordinal0())),
+    ("getDayOfMonth", |t| i64::from(t.day0())),
+    ("getDate", |t| i64::from(t.day())),
+    ("getDayOfWeek", |t| i64::from(t.weekday().num_days_from_sunday())),
+    ("getHours", |t| i64::from(t.hour())),
+    ("getMinutes", |t| i64::from(t.minute())),
+    ("getSeconds", |t| i64::from(t.second())),
+    ("getMilliseconds", |t| i64::from(t.timestamp_subsec_millis())),
+];
+
+/// Replace the timestamp accessors of `ctx` with ones that take a time zone
+pub(crate) fn register(ctx: &mut CelContext) {
+    for (name, accessor) in ACCESSORS {
+        ctx.add_function(name, move |ftx: &FunctionContext, Arguments(args): Arguments| {
+            call(name, accessor, ftx.this.as_ref(), &args)
+        });
+    }
+}
+
+fn call(
+    function: &str,
+    accessor: Accessor,
+    this: Option<&Value>,
+    args: &[Value],
+) -> Result<Value, ExecutionError> {
+    let (timestamp, zone) = match (this, args) {
+        (Some(timestamp), []) | (None, [timestamp]) => (timestamp, None),
+        (Some(timestamp), [zone]) | (None, [timestamp, zone]) => (timestamp, Some(zone)),
+        _ => {
+            let message = "expected a timestamp and an optional time zone";
+            return Err(ExecutionError::function_error(function, message));
+        }
+    };
+    let Value::Timestamp(timestamp) = timestamp else {
+        let message = format!("expected a timestamp, got {}", type_name_of(timestamp));
+        return Err(ExecutionError::function_error(function, message));
+    };
+    let local = match zone {
+        None => *timestamp,
+        Some(Value::String(zone)) => {
+            let offset = offset_at(zone, timestamp.timestamp())
+                .map_err(|e| ExecutionError::function_error(function, e))?;
+            timestamp.with_timezone(&offset)
+        }
+        Some(other) => {
+            let message = format!("time zone must be a string, got {}", type_name_of(other));
+            return Err(ExecutionError::function_error(function, message));
+        }
+    };
+    Ok(Value::Int(accessor(&local)))
+}
+
+/// The offset from UTC of the time zone `zone` at `instant`, in Unix seconds
+fn offset_at(zone: &str, instant: i64) -> Result<FixedOffset, String> {
+    if let Some(offset) = fixed_offset(zone) {
+        return Ok(offset);
+    }
+    let seconds = database().zone(zone)?.offset_at(instant);
+    FixedOffset::east_opt(seconds).ok_or_else(|| format!("time zone '{zone}' has no valid offset"))
+}
+
+/// `UTC`, `Z` or an offset written `+HH:MM` or `-HH:MM`
+fn fixed_offset(zone: &str) -> Option<FixedOffset> {
+    if zone == "UTC" || zone == "Z" {
+        return FixedOffset::east_opt(0);
+    }
+    let (sign, rest) = match zone.as_bytes().first()? {
+        b'+' => (1, &zone[1..]),
+        b'-' => (-1, &zone[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = rest.split_once(':')?;
+    if hours.len() != 2 || minutes.len() != 2 {
+        return None;
+    }
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    if minutes >= 60 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Register the time zone `name` from the contents of a compiled `TZif` file, replacing
+/// any zone of that name the tz database directory provides
+///
+/// Hosts without a zoneinfo directory, such as WebAssembly ones, register the zones
+/// their expressions use this way. Zones are shared by every program in the process.
+///
+/// # Errors
+///
+/// Returns `Error::InvalidArgument` if `name` is not a zone name such as
+/// `Europe/Stockholm` or `tzif` is not a `TZif` file.
+pub fn add_time_zone(name: &str, tzif: &[u8]) -> Result<(), Error> {
+    if !is_zone_name(name) {
+        return Err(Error::InvalidArgument(format!("invalid time zone name '{name}'")));
+    }
+    let Some(zone) = Zone::parse(tzif) else {
+        return Err(Error::InvalidArgument(format!("time zone '{name}' is not TZif data")));
+    };
+    database().lock().added.insert(name.to_string(), Arc::new(zone));
+    Ok(())
+}
+
+/// Read zones the host has not registered from the `TZif` files under `dir`, or from
+/// `$TZDIR` or `/usr/share/zoneinfo` for `None`
+///
+/// Zones already read from the previous directory are forgotten.
+pub fn set_tzdata_dir(dir: Option<PathBuf>) {
+    let mut database = database().lock();
+    database.dir = dir;
+    database.loaded.clear();
+}
+
+/// Whether `name` is a tz database name, which is also a safe relative path
+fn is_zone_name(name: &str) -> bool {
+    name.split('/').all(|part| {
+        part.bytes().next().is_some_and(|b| b.is_ascii_alphanumeric())
+            && part.bytes().all(|b| b.is_ascii_alphanumeric() || b"_+-".contains(&b))
+    })
+}
+
+/// Zones registered by the host or read from the zoneinfo directory
+#[derive(Debug, Default)]
+struct Database {
+    dir: Option<PathBuf>,
+    added: HashMap<String, Arc<Zone>>,
+    loaded: HashMap<String, Arc<Zone>>,
+}
+
+struct Shared(Mutex<Database>);
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Database> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The zone `name`, reading it from the zoneinfo directory on first use
+    fn zone(&self, name: &str) -> Result<Arc<Zone>, String> {
+        let mut database = self.lock();
+        if let Some(zone) = database.added.get(name).or_else(|| database.loaded.get(name)) {
+            return Ok(Arc::clone(zone));
+        }
+        if !is_zone_name(name) {
+            return Err(format!("invalid time zone '{name}'"));
+        }
+        let dir = database.dir.clone().unwrap_or_else(|| {
+            std::env::var_os("TZDIR").map_or_else(|| "/usr/share/zoneinfo".into(), PathBuf::from)
+        });
+        let zone = std::fs::read(dir.join(name))
+            .ok()
+            .and_then(|data| Zone::parse(&data))
+            .ok_or_else(|| format!("unknown time zone '{name}'"))?;
+        let zone = Arc::new(zone);
+        database.loaded.insert(name.to_string(), Arc::clone(&zone));
+        Ok(zone)
+    }
+}
+
+fn database() -> &'static Shared {
+    static DATABASE: OnceLock<Shared> = OnceLock::new();
+    DATABASE.get_or_init(|| Shared(Mutex::default()))
+}
+
+/// The offsets of a time zone through time, from a `TZif` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Zone {
+    /// Instants in Unix seconds, ascending, with the offset that starts at each
+    transitions: Vec<(i64, i32)>,
+    /// The offset before the first transition
+    initial: i32,
+    /// The offset after the last transition, if the file has a rule for it
+    rule: Option<Rule>,
+}
+
+impl Zone {
+    /// Seconds east of UTC at `instant`
+    fn offset_at(&self, instant: i64) -> i32 {
+        let after = self.transitions.partition_point(|(at, _)| *at <= instant);
+        match (after, &self.rule) {
+            (after, Some(rule)) if after == self.transitions.len() => rule.offset_at(instant),
+            (0, _) => self.initial,
+            (after, _) => self.transitions[after - 1].1,
+        }
+    }
+
+    /// Parse `TZif` data, using the 64-bit block and the footer rule of version 2 and
+    /// later files
+    fn parse(data: &[u8]) -> Option<Self> {
+        let (version, counts) = header(data)?;
+        if version == 0 {
+            return block(data.get(44..)?, &counts, 4).map(|(zone, _)| zone);
+        }
+        let rest = data.get(44 + counts.block_len(4)..)?;
+        let (_, counts) = header(rest)?;
+        let (mut zone, len) = block(rest.get(44..)?, &counts, 8)?;
+        let footer = rest.get(44 + len..)?.strip_prefix(b"\n")?;
+        let end = footer.iter().position(|&b| b == b'\n')?;
+        let footer = std::str::from_utf8(&footer[..end]).ok()?;
+        // A rule this cannot read leaves the last offset in force
+        zone.rule = Rule::parse(footer);
+        Some(zone)
+    }
+}
+
+/// The counts of a `TZif` header
+struct Counts {
+    isut: usize,
+    isstd: usize,
+    leap: usize,
+    time: usize,
+    types: usize,
+    chars: usize,
+}
+
+impl Counts {
+    /// Bytes of the data block that follows the header, for times of `time_size` bytes
+    const fn block_len(&self, time_size: usize) -> usize {
+        self.time * (time_size + 1)
+            + self.types * 6
+            + self.chars
+            + self.leap * (time_size + 4)
+            + self.isstd
+            + self.isut
+    }
+}
+
+/// The version and counts of the `TZif` header at the start of `data`
+fn header(data: &[u8]) -> Option<(u8, Counts)> {
+    if !data.starts_with(b"TZif") {
+        return None;
+    }
+    let count = |index: usize| -> Option<usize> {
+        let bytes = data.get(20 + index * 4..24 + index * 4)?;
+        // No count can exceed the bytes there are to count
+        let count = usize::try_from(u32::from_be_bytes(bytes.try_into().ok()?)).ok()?;
+        (count <= data.len()).then_some(count)
+    };
+    let version = match *data.get(4)? {
+        0 => 0,
+        version => version.checked_sub(b'0')?,
+    };
+    let counts = Counts {
+        isut: count(0)?,
+        isstd: count(1)?,
+        leap: count(2)?,
+        time: count(3)?,
+        types: count(4)?,
+        chars: count(5)?,
+    };
+    Some((version, counts))
+}
+
+/// The zone of a `TZif` data block, without its rule, and the length of the block
+fn block(data: &[u8], counts: &Counts, time_size: usize) -> Option<(Zone, usize)> {
+    let times = data.get(..counts.time * time_size)?;
+    let indexes = data.get(times.len()..times.len() + counts.time)?;
+    let types = data.get(times.len() + indexes.len()..)?.get(..counts.types * 6)?;
+    let offset = |index: usize| -> Option<i32> {
+        let bytes = types.get(index * 6..index * 6 + 4)?;
+        Some(i32::from_be_bytes(bytes.try_into().ok()?))
+    };
+
+    let mut transitions = Vec::with_capacity(counts.time);
+    for (time, &index) in times.chunks_exact(time_size).zip(indexes) {
+        let at = match time_size {
+            4 => i64::from(i32::from_be_bytes(time.try_into().ok()?)),
+            _ => i64::from_be_bytes(time.try_into().ok()?),
+        };
+        transitions.push((at, offset(usize::from(index))?));
+    }
+    let zone = Zone {
+        transitions,
+        initial: offset(0)?,
+        rule: None,
+    };
+    Some((zone, counts.block_len(time_size)))
+}
+
+/// A POSIX TZ rule such as `CET-1CEST,M3.5.0,M10.5.0/3`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    /// Standard time, in seconds east of UTC
+    standard: i32,
+    daylight: Option<Daylight>,
+}
+
+/// Daylight saving time of a rule and when it starts and ends each year
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Daylight {
+    offset: i32,
+    start: Change,
+    end: Change,
+}
+
+/// A day of the year and a local time on it, in seconds after midnight
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Change {
+    day: Day,
+    time: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Day {
+    /// `Jn`: day 1 to 365, never counting February 29
+    Julian(u32),
+    /// `n`: day 0 to 365, counting February 29 in leap years
+    Ordinal(u32),
+    /// `Mm.w.d`: weekday `d` (0 for Sunday) of week `w` of month `m`, where week 5 is
+    /// the last
+    Weekday { month: u32, week: u32, weekday: u32 },
+}
+
+impl Rule {
+    fn parse(source: &str) -> Option<Self> {
+        let mut cursor = Cursor(source.as_bytes());
+        cursor.name()?;
+        let standard = -cursor.time()?;
+        if cursor.0.is_empty() {
+            return Some(Self {
+                standard: i32::try_from(standard).ok()?,
+                daylight: None,
+            });
+        }
+        cursor.name()?;
+        let offset =
+            if cursor.0.first() == Some(&b',') { standard + 3600 } else { -cursor.time()? };
+        cursor.expect(b',')?;
+        let start = cursor.change()?;
+        cursor.expect(b',')?;
+        let end = cursor.change()?;
+        if !cursor.0.is_empty() {
+            return None;
+        }
+        Some(Self {
+            standard: i32::try_from(standard).ok()?,
+            daylight: Some(Daylight {
+                offset: i32::try_from(offset).ok()?,
+                start,
+                end,
+            }),
+        })
+    }
+
+    fn offset_at(&self, instant: i64) -> i32 {
+        let Some(daylight) = &self.daylight else {
+            return self.standard;
+        };
+        let local = instant + i64::from(self.standard);
+        let Some(year) = DateTime::from_timestamp(local, 0).map(|t| t.year()) else {
+            return self.standard;
+        };
+        let (Some(start), Some(end)) = (
+            daylight.start.instant(year, self.standard),
+            daylight.end.instant(year, daylight.offset),
+        ) else {
+            return self.standard;
+        };
+        // Southern hemisphere rules end daylight saving time before they start it
+        let daylight_saving = if start < end {
+            start <= instant && instant < end
+        } else {
+            !(end <= instant && instant < start)
+        };
+        if daylight_saving {
+            daylight.offset
+        } else {
+            self.standard
+        }
+    }
+}
+
+impl Change {
+    /// The instant of the change in `year`, in Unix seconds, with local time `offset`
+    /// seconds east of UTC
+    fn instant(&self, year: i32, offset: i32) -> Option<i64> {
+        let date = match self.day {
+            Day::Julian(day) => {
+                let leap = NaiveDate::from_ymd_opt(year, 2, 29).is_some();
+                NaiveDate::from_yo_opt(year, day + u32::from(leap && day >= 60))?
+            }
+            Day::Ordinal(day) => NaiveDate::from_yo_opt(year, day + 1)?,
+            Day::Weekday {
+                month,
+                week,
+                weekday,
+            } => {
+                let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+                let first_weekday = first.weekday().num_days_from_sunday();
+                let mut day = 1 + (weekday + 7 - first_weekday) % 7 + (week - 1) * 7;
+                while NaiveDate::from_ymd_opt(year, month, day).is_none() {
+                    day -= 7;
+                }
+                NaiveDate::from_ymd_opt(year, month, day)?
+            }
+        };
+        let midnight = date.and_hms_opt(0, 0, 0)?.and_utc().timestamp();
+        Some(midnight + self.time - i64::from(offset))
+    }
+}
+
+/// The rest of a POSIX TZ rule being parsed
+struct Cursor<'a>(&'a [u8]);
+
+impl Cursor<'_> {
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        self.0 = self.0.strip_prefix(&[byte])?;
+        Some(())
+    }
+
+    /// Skip a zone abbreviation, such as `CET` or `<+0330>`
+    fn name(&mut self) -> Option<()> {
+        let len = if self.0.first() == Some(&b'<') {
+            self.0.iter().position(|&b| b == b'>')? + 1
+        } else {
+            self.0.iter().take_while(|b| b.is_ascii_alphabetic()).count()
+        };
+        if len < 3 {
+            return None;
+        }
+        self.0 = &self.0[len..];
+        Some(())
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let len = self.0.iter().take_while(|b| b.is_ascii_digit()).count();
+        let number = std::str::from_utf8(&self.0[..len]).ok()?.parse().ok()?;
+        self.0 = &self.0[len..];
+        Some(number)
+    }
+
+    /// A time or offset written `[+-]hh[:mm[:ss]]`, in seconds
+    fn time(&mut self) -> Option<i64> {
+        let sign = match self.0.first() {
+            Some(b'-') => -1,
+            Some(b'+') => 1,
+            _ => 0,
+        };
+        if sign != 0 {
+            self.0 = &self.0[1..];
+        }
+        let mut seconds = i64::from(self.number()?) * 3600;
+        for unit in [60, 1] {
+            if self.expect(b':').is_none() {
+                break;
+            }
+            seconds += i64::from(self.number()?) * unit;
+        }
+        Some(if sign < 0 { -seconds } else { seconds })
+    }
+
+    /// A date and optional time of a change, such as `M3.5.0/3`
+    fn change(&mut self) -> Option<Change> {
+        let day = match self.0.first()? {
+            b'J' => {
+                self.0 = &self.0[1..];
+                Day::Julian(self.number().filter(|day| (1..=365).contains(day))?)
+            }
+            b'M' => {
+                self.0 = &self.0[1..];
+                let month = self.number().filter(|month| (1..=12).contains(month))?;
+                self.expect(b'.')?;
+                let week = self.number().filter(|week| (1..=5).contains(week))?;
+                self.expect(b'.')?;
+                let weekday = self.number().filter(|weekday| *weekday <= 6)?;
+                Day::Weekday {
+                    month,
+                    week,
+                    weekday,
+                }
+            }
+            _ => Day::Ordinal(self.number().filter(|day| *day <= 365)?),
+        };
+        let time = if self.expect(b'/').is_some() { self.time()? } else { 2 * 3600 };
+        Some(Change { day, time })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Context, Program};
+
+    /// A version 2 `TZif` file with no transitions, whose offsets all come from `rule`
+    fn tzif(standard: i32, abbreviation: &str, rule: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        for _ in 0..2 {
+            data.extend_from_slice(b"TZif2");
+            data.extend_from_slice(&[0; 15]);
+            let chars = u32::try_from(abbreviation.len() + 1).unwrap();
+            for count in [0, 0, 0, 0, 1, chars] {
+                data.extend_from_slice(&u32::to_be_bytes(count));
+            }
+            data.extend_from_slice(&standard.to_be_bytes());
+            data.extend_from_slice(&[0, 0]);
+            data.extend_from_slice(abbreviation.as_bytes());
+            data.push(0);
+        }
+        data.extend_from_slice(format!("\n{rule}\n").as_bytes());
+        data
+    }
+
+    #[test]
+    fn test_time_zones() {
+        let stockholm = tzif(3600, "CET", "CET-1CEST,M3.5.0,M10.5.0/3");
+        add_time_zone("Test/Stockholm", &stockholm).unwrap();
+        let sydney = tzif(36000, "AEST", "AEST-10AEDT,M10.1.0,M4.1.0/3");
+        add_time_zone("Test/Sydney", &sydney).unwrap();
+
+        let context = Context::new();
+        let eval = |expression: &str| {
+            let program = Program::compile(expression).unwrap();
+            program.execute(&context)
+        };
+        let winter = "timestamp('2024-01-15T23:30:00Z')";
+        let summer = "timestamp('2024-07-15T23:30:00Z')";
+        assert_eq!(eval(&format!("{winter}.getHours()")), Ok(Value::Int(23)));
+        assert_eq!(eval(&format!("{winter}.getHours('Test/Stockholm')")), Ok(Value::Int(0)));
+        assert_eq!(eval(&format!("getHours({summer}, 'Test/Stockholm')")), Ok(Value::Int(1)));
+        assert_eq!(eval(&format!("{winter}.getHours('Test/Sydney')")), Ok(Value::Int(10)));
+        assert_eq!(eval(&format!("{summer}.getHours('Test/Sydney')")), Ok(Value::Int(9)));
+        assert_eq!(eval(&format!("{winter}.getDayOfWeek()")), Ok(Value::Int(1)));
+        assert_eq!(eval(&format!("getDayOfWeek({winter}, '+05:30')")), Ok(Value::Int(2)));
+        assert_eq!(eval(&format!("{winter}.getMinutes('-03:30')")), Ok(Value::Int(0)));
+        assert_eq!(eval(&format!("{winter}.getDate('UTC')")), Ok(Value::Int(15)));
+
+        // The switch to summer time at 01:00 UTC on the last Sunday of March
+        let switch = "timestamp('2024-03-31T01:00:00Z')";
+        assert_eq!(eval(&format!("{switch}.getHours('Test/Stockholm')")), Ok(Value::Int(3)));
+        let before = "timestamp('2024-03-31T00:59:59Z')";
+        assert_eq!(eval(&format!("{before}.getHours('Test/Stockholm')")), Ok(Value::Int(1)));
+
+        let error = eval(&format!("{winter}.getHours('Nowhere/Atlantis')")).unwrap_err();
+        assert!(error.to_string().contains("unknown time zone"), "{error}");
+        assert!(eval(&format!("{winter}.getHours('../passwd')")).is_err());
+        assert!(matches!(add_time_zone("Test/Empty", b"TZif"), Err(Error::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,223 @@
+//! Evaluation traces recording the result of each subexpression
+
+use super::attributes::with_attribute_hooks;
+use super::optimize::{is_literal, unparse};
+use super::options::COMPREHENSIONS;
+use super::OwnedValue;
+use cel_interpreter::{Context as CelContext, Program as CelProgram};
+use cel_parser::{Expression, Member};
+use serde::Serialize;
+
+/// A subexpression evaluated by `Program::execute_traced`
+///
+/// Serializes to JSON with `value` and `error` left out when absent and `children`
+/// when empty.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TraceNode {
+    /// The subexpression as CEL source, with operands parenthesized
+    pub expression: String,
+    /// Its value, absent if evaluation failed or the value has no JSON form
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<OwnedValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Time spent evaluating the subexpression, including its operands; 0 on targets
+    /// without a clock
+    pub duration_ns: u64,
+    /// The operands that were evaluated, in order
+    ///
+    /// Literals are left out, as are the operands that `&&`, `||` and `?:` skip and
+    /// the arguments of macros such as `all`, which depend on the variables they bind.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TraceNode>,
+}
+
+impl TraceNode {
+    /// The trace as a JSON object
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Trace `expression` and the operands evaluating it visits, against `ctx`, asking
+/// the attribute hook about the selections of each if `hooked`
+pub(crate) fn trace(expression: &Expression, ctx: &CelContext, hooked: bool) -> TraceNode {
+    let operand = |expression: &Expression| trace(expression, ctx, hooked);
+    let children = match expression {
+        Expression::And(left, right) => {
+            let left_node = operand(left);
+            match left_node.value {
+                Some(OwnedValue::Bool(false)) => vec![(left.as_ref(), left_node)],
+                _ => vec![(left.as_ref(), left_node), (right.as_ref(), operand(right))],
+            }
+        }
+        Expression::Or(left, right) => {
+            let left_node = operand(left);
+            match left_node.value {
+                Some(OwnedValue::Bool(true)) => vec![(left.as_ref(), left_node)],
+                _ => vec![(left.as_ref(), left_node), (right.as_ref(), operand(right))],
+            }
+        }
+        Expression::Ternary(condition, if_true, if_false) => {
+            let condition_node = operand(condition);
+            let branch = match condition_node.value {
+                Some(OwnedValue::Bool(true)) => Some(if_true.as_ref()),
+                Some(OwnedValue::Bool(false)) => Some(if_false.as_ref()),
+                _ => None,
+            };
+            let mut children = vec![(condition.as_ref(), condition_node)];
+            children.extend(branch.map(|branch| (branch, operand(branch))));
+            children
+        }
+        _ => evaluated_operands(expression).into_iter().map(|e| (e, operand(e))).collect(),
+    };
+
+    let source = unparse(expression);
+    let evaluated =
+        if hooked { unparse(&with_attribute_hooks(expression)) } else { source.clone() };
+    let (value, error, duration_ns) = match CelProgram::compile(&evaluated) {
+        Ok(program) => {
+            let (result, duration_ns) = timed(|| program.execute(ctx));
+            match result {
+                Ok(value) => (OwnedValue::try_from(&value).ok(), None, duration_ns),
+                Err(e) => (None, Some(e.to_string()), duration_ns),
+            }
+        }
+        Err(e) => (None, Some(e.to_string()), 0),
+    };
+
+    TraceNode {
+        expression: source,
+        value,
+        error,
+        duration_ns,
+        children: children
+            .into_iter()
+            .filter(|(expression, _)| !is_literal(expression))
+            .map(|(_, node)| node)
+            .collect(),
+    }
+}
+
+/// The operands of `expression` that evaluating it always visits, in order
+fn evaluated_operands(expression: &Expression) -> Vec<&Expression> {
+    match expression {
+        Expression::Arithmetic(left, _, right) | Expression::Relation(left, _, right) => {
+            vec![left.as_ref(), right.as_ref()]
+        }
+        Expression::Unary(_, operand) => vec![operand.as_ref()],
+        Expression::Member(operand, member) => {
+            let mut operands = vec![operand.as_ref()];
+            match member.as_ref() {
+                Member::Attribute(_) => {}
+                Member::Index(index) => operands.push(index.as_ref()),
+                Member::Fields(fields) => operands.extend(fields.iter().map(|(_, value)| value)),
+            }
+            operands
+        }
+        Expression::FunctionCall(function, target, args) => {
+            let mut operands: Vec<&Expression> = target.as_deref().into_iter().collect();
+            // Macro arguments refer to the variables the macro binds
+            let macro_call = match function.as_ref() {
+                Expression::Ident(name) => {
+                    name.as_str() == "has" || COMPREHENSIONS.contains(&name.as_str())
+                }
+                _ => false,
+            };
+            if !macro_call {
+                operands.extend(args);
+            }
+            operands
+        }
+        Expression::List(items) => items.iter().collect(),
+        Expression::Map(entries) => entries.iter().flat_map(|(key, value)| [key, value]).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// `f`'s result and how long it took in nanoseconds
+#[cfg(not(all(target_family = "wasm", target_os = "unknown")))]
+pub(crate) fn timed<T>(f: impl FnOnce() -> T) -> (T, u64) {
+    let start = std::time::Instant::now();
+    let result = f();
+    (result, u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX))
+}
+
+/// `wasm32-unknown-unknown` has no clock, so durations there are 0
+#[cfg(all(target_family = "wasm", target_os = "unknown"))]
+pub(crate) fn timed<T>(f: impl FnOnce() -> T) -> (T, u64) {
+    (f(), 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Context, Program};
+
+    #[test]
+    fn test_trace_records_clauses() {
+        let program =
+            Program::compile("role == 'admin' || (age >= 18 && region in ['eu'])").unwrap();
+        let mut context = Context::new();
+        context.add_variable("role".to_string(), serde_json::json!("user"));
+        context.add_variable("age".to_string(), serde_json::json!(16));
+        context.add_variable("region".to_string(), serde_json::json!("eu"));
+
+        let (result, trace) = program.execute_traced(&context);
+        assert_eq!(result.unwrap(), crate::api::Value::Bool(false));
+        let trace = trace.unwrap();
+        assert_eq!(trace.value, Some(OwnedValue::Bool(false)));
+
+        let [role, and] = trace.children.as_slice() else {
+            panic!("unexpected children: {trace:?}");
+        };
+        assert_eq!(role.expression, "role == \"admin\"");
+        assert_eq!(role.children.len(), 1);
+        // `age >= 18` is false, so `region in ['eu']` is never evaluated
+        let [age] = and.children.as_slice() else {
+            panic!("unexpected children: {and:?}");
+        };
+        assert_eq!(age.expression, "age >= 18");
+        assert_eq!(age.value, Some(OwnedValue::Bool(false)));
+    }
+
+    #[test]
+    fn test_trace_errors_and_json() {
+        let program = Program::compile("[1, 2].all(x, x > 0) && 1 / zero > 0").unwrap();
+        let mut context = Context::new();
+        context.add_variable("zero".to_string(), serde_json::json!(0));
+
+        let (result, trace) = program.execute_traced(&context);
+        assert!(result.is_err());
+        let trace = trace.unwrap();
+        assert!(trace.error.is_some());
+        assert_eq!(trace.children[0].expression, "[1, 2].all(x, x > 0)");
+        assert!(trace.children[0].children.is_empty());
+
+        let json: serde_json::Value = serde_json::from_str(&trace.to_json()).unwrap();
+        assert_eq!(json["children"][0]["value"], serde_json::json!(true));
+        assert!(json["children"][1]["error"].is_string());
+        assert!(json.get("value").is_none());
+    }
+
+    #[test]
+    fn test_trace_asks_attribute_hook() {
+        let program = Program::compile("user.name == 'a' && user['ssn'] == '123'").unwrap();
+        let mut context = Context::new();
+        context.add_variable("user".to_string(), serde_json::json!({"name": "a", "ssn": "123"}));
+        context.set_attribute_hook(|path| match path {
+            "user.ssn" => crate::api::AttributeAccess::Deny("restricted".to_string()),
+            _ => crate::api::AttributeAccess::Allow,
+        });
+
+        let (result, trace) = program.execute_traced(&context);
+        assert!(result.is_err());
+        let trace = trace.unwrap();
+        assert_eq!(trace.children[0].value, Some(OwnedValue::Bool(true)));
+        let denied = &trace.children[1].children[0];
+        assert_eq!(denied.expression, "user[\"ssn\"]");
+        assert_eq!(denied.value, None);
+        assert!(denied.error.as_ref().unwrap().contains("restricted"));
+    }
+}
@@ -0,0 +1,91 @@
+//! Yes or no answers from results, for hosts using expressions as gates
+//!
+//! Strict truthiness accepts only bools, so a rule returning anything else is a bug the
+//! host hears about. Lenient truthiness follows the usual scripting conventions, so a
+//! rule can return the value it checks: null, numbers equal to zero, NaN, empty
+//! strings, bytes, lists and maps, empty optionals and zero durations are false, and
+//! every other value is true.
+
+use super::decimal::{as_decimal, Decimal};
+use super::value::{optional_parts, type_name_of};
+use super::{Error, Value};
+use std::cmp::Ordering;
+
+/// How a result turns into a yes or no
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Truthiness {
+    /// Only bools are accepted; any other result is an `Error::ResultType`
+    #[default]
+    Strict,
+    /// Null, zero and empty values are false, and other values true
+    Lenient,
+}
+
+impl Truthiness {
+    /// Whether `value` counts as true
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::ResultType` if `value` is not a bool under `Truthiness::Strict`.
+    pub fn truthy(self, value: &Value) -> Result<bool, Error> {
+        match (self, value) {
+            (_, Value::Bool(b)) => Ok(*b),
+            (Self::Strict, _) => Err(Error::ResultType {
+                expected: "bool",
+                actual: type_name_of(value),
+            }),
+            (Self::Lenient, _) => Ok(lenient(value)),
+        }
+    }
+}
+
+fn lenient(value: &Value) -> bool {
+    if let Some(inner) = optional_parts(value) {
+        return inner.is_some();
+    }
+    if let Some(number) = as_decimal(value) {
+        return number.compare(Decimal::integer(0)) != Ordering::Equal;
+    }
+    match value {
+        Value::Null => false,
+        // False for NaN as well as zero
+        Value::Float(f) => f.abs() > 0.0,
+        Value::String(s) => !s.is_empty(),
+        Value::Bytes(bytes) => !bytes.is_empty(),
+        Value::List(items) => !items.is_empty(),
+        Value::Map(map) => !map.map.is_empty(),
+        Value::Duration(duration) => !duration.is_zero(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Context, Program};
+
+    #[test]
+    fn test_truthiness() {
+        let context = Context::new();
+        let truthy = |expression: &str, truthiness: Truthiness| {
+            Program::compile(expression).unwrap().execute_bool(&context, truthiness)
+        };
+
+        assert_eq!(truthy("1 < 2", Truthiness::Strict), Ok(true));
+        assert_eq!(
+            truthy("'yes'", Truthiness::Strict),
+            Err(Error::ResultType {
+                expected: "bool",
+                actual: "string"
+            })
+        );
+
+        for falsy in ["null", "0", "0u", "0.0", "''", "[]", "{}", "duration('0s')"] {
+            assert_eq!(truthy(falsy, Truthiness::Lenient), Ok(false), "{falsy}");
+        }
+        for truthy_value in ["'no'", "-1", "0.5", "[false]", "{'a': 0}", "duration('1s')"] {
+            assert_eq!(truthy(truthy_value, Truthiness::Lenient), Ok(true), "{truthy_value}");
+        }
+        assert!(truthy("1 / 0", Truthiness::Lenient).is_err());
+    }
+}
@@ -0,0 +1,960 @@
+use super::decimal::Decimal;
+use super::Error;
+use crate::ffi::CelValueType;
+use cel_interpreter::objects::{Key, Map};
+use chrono::{DateTime, SecondsFormat, TimeDelta};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// A CEL value, as produced by evaluation and accepted by `Context::add_value_variable`
+pub use cel_interpreter::Value;
+
+/// Name tagging the function values that represent optionals
+const OPTIONAL_TAG: &str = "optional";
+
+/// Wrap `value` as a CEL optional, empty for `None`
+///
+/// The interpreter has no optional type, so optionals are carried as function values
+/// with a reserved name, which no expression or host conversion can produce.
+pub(crate) fn optional_value(value: Option<Value>) -> Value {
+    Value::Function(Arc::new(OPTIONAL_TAG.to_string()), value.map(Box::new))
+}
+
+/// The contents of an optional, or `None` if `value` is not an optional
+pub(crate) fn optional_parts(value: &Value) -> Option<Option<&Value>> {
+    match value {
+        Value::Function(name, inner) if name.as_str() == OPTIONAL_TAG => Some(inner.as_deref()),
+        _ => None,
+    }
+}
+
+/// Name tagging the function values that represent big integers
+const BIGINT_TAG: &str = "bigint";
+
+/// `n` as a big integer, a 128-bit signed integer beyond the range of `int` and `uint`
+///
+/// Like optionals, big integers are carried as function values with a reserved name,
+/// holding their decimal digits.
+pub(crate) fn bigint_value(n: i128) -> Value {
+    let digits = Value::String(Arc::new(n.to_string()));
+    Value::Function(Arc::new(BIGINT_TAG.to_string()), Some(Box::new(digits)))
+}
+
+/// The number a big integer holds, or `None` if `value` is not a big integer
+pub(crate) fn bigint_parts(value: &Value) -> Option<i128> {
+    match value {
+        Value::Function(name, Some(inner)) if name.as_str() == BIGINT_TAG => match inner.as_ref() {
+            Value::String(digits) => digits.parse().ok(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Parse optionally signed decimal digits into a big integer, as `bigint()` does
+///
+/// # Errors
+///
+/// Returns `Error::InvalidArgument` if `text` is not an integer or falls outside the
+/// 128-bit range.
+pub(crate) fn parse_bigint(text: &str) -> Result<Value, Error> {
+    text.trim()
+        .parse::<i128>()
+        .map(bigint_value)
+        .map_err(|e| Error::InvalidArgument(format!("invalid big integer '{text}': {e}")))
+}
+
+/// Name tagging the function values that represent decimals
+const DECIMAL_TAG: &str = "decimal";
+
+/// `decimal` as a value, carried like a big integer as a tagged function value holding
+/// its text
+pub(crate) fn decimal_value(decimal: Decimal) -> Value {
+    let text = Value::String(Arc::new(decimal.to_string()));
+    Value::Function(Arc::new(DECIMAL_TAG.to_string()), Some(Box::new(text)))
+}
+
+/// The number a decimal holds, or `None` if `value` is not a decimal
+pub(crate) fn decimal_parts(value: &Value) -> Option<Decimal> {
+    match value {
+        Value::Function(name, Some(inner)) if name.as_str() == DECIMAL_TAG => {
+            match inner.as_ref() {
+                Value::String(text) => Decimal::parse(text).ok(),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Parse an optionally signed number in plain decimal notation, such as `-12.50`, into
+/// a decimal, as `decimal()` does
+///
+/// # Errors
+///
+/// Returns `Error::InvalidArgument` if `text` is not a number, has more than 28
+/// decimal places or does not fit in 128 bits without its point.
+pub(crate) fn parse_decimal(text: &str) -> Result<Value, Error> {
+    Decimal::parse(text).map(decimal_value).map_err(Error::InvalidArgument)
+}
+
+/// Name tagging the function values that represent types
+const TYPE_TAG: &str = "type";
+
+/// The type named `name`, as `type()` returns it
+///
+/// The interpreter has no type values, so types are carried like big integers as
+/// tagged function values, holding the name CEL gives the type, such as `int` or
+/// `google.protobuf.Timestamp`.
+pub(crate) fn type_value(name: &str) -> Value {
+    let name = Value::String(Arc::new(name.to_string()));
+    Value::Function(Arc::new(TYPE_TAG.to_string()), Some(Box::new(name)))
+}
+
+/// The name of a type, or `None` if `value` is not a type
+pub(crate) fn type_parts(value: &Value) -> Option<&str> {
+    match value {
+        Value::Function(name, Some(inner)) if name.as_str() == TYPE_TAG => match inner.as_ref() {
+            Value::String(type_name) => Some(type_name.as_str()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Read a type name, such as `int` or `my.pkg.Message`, into a type
+///
+/// # Errors
+///
+/// Returns `Error::InvalidArgument` if `text` is empty or contains whitespace.
+pub(crate) fn parse_type(text: &str) -> Result<Value, Error> {
+    if text.is_empty() || text.contains(char::is_whitespace) {
+        return Err(Error::InvalidArgument(format!("invalid type name '{text}'")));
+    }
+    Ok(type_value(text))
+}
+
+/// Name tagging the function values that represent errors
+const ERROR_TAG: &str = "error";
+
+/// An error with `message` as a value, which a failing host function evaluates to
+/// under `Context::set_soft_errors`
+///
+/// Errors are carried like big integers as tagged function values, holding their
+/// message. They only exist during an evaluation, which fails if it results in one.
+pub(crate) fn error_value(message: String) -> Value {
+    let message = Value::String(Arc::new(message));
+    Value::Function(Arc::new(ERROR_TAG.to_string()), Some(Box::new(message)))
+}
+
+/// The message of an error, or `None` if `value` is not an error
+pub(crate) fn error_parts(value: &Value) -> Option<&str> {
+    match value {
+        Value::Function(name, Some(inner)) if name.as_str() == ERROR_TAG => match inner.as_ref() {
+            Value::String(message) => Some(message.as_str()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The name CEL gives the type of `value`, as `type()` returns it
+pub(crate) fn type_name_of(value: &Value) -> &'static str {
+    if optional_parts(value).is_some() {
+        return "optional_type";
+    }
+    if bigint_parts(value).is_some() {
+        return "bigint";
+    }
+    if decimal_parts(value).is_some() {
+        return "decimal";
+    }
+    if type_parts(value).is_some() {
+        return "type";
+    }
+    match value {
+        Value::Null => "null_type",
+        Value::Bool(_) => "bool",
+        Value::Int(_) => "int",
+        Value::UInt(_) => "uint",
+        Value::Float(_) => "double",
+        Value::String(_) => "string",
+        Value::Bytes(_) => "bytes",
+        Value::List(_) => "list",
+        Value::Map(_) => "map",
+        Value::Timestamp(_) => "google.protobuf.Timestamp",
+        Value::Duration(_) => "google.protobuf.Duration",
+        _ => "function",
+    }
+}
+
+/// The value a map key holds
+pub(crate) fn key_value(key: &Key) -> Value {
+    match key {
+        Key::Int(i) => Value::Int(*i),
+        Key::Uint(u) => Value::UInt(*u),
+        Key::Bool(b) => Value::Bool(*b),
+        Key::String(s) => Value::String(s.clone()),
+    }
+}
+
+/// The entries of `map` in a fixed order: booleans, then ints, then uints, then
+/// strings, each ascending
+///
+/// Maps are hash maps whose iteration order changes from run to run, so anything that
+/// walks a map for the host goes through this to give the same order every time.
+pub(crate) fn sorted_entries(map: &Map) -> Vec<(&Key, &Value)> {
+    let mut entries: Vec<_> = map.map.iter().collect();
+    entries.sort_unstable_by_key(|&(key, _)| key_order(key));
+    entries
+}
+
+fn key_order(key: &Key) -> (u8, i128, &str) {
+    match key {
+        Key::Bool(b) => (0, i128::from(*b), ""),
+        Key::Int(i) => (1, i128::from(*i), ""),
+        Key::Uint(u) => (2, i128::from(*u), ""),
+        Key::String(s) => (3, 0, s),
+    }
+}
+
+/// The CEL name of the type values of `value_type` have, as `type_name_of` gives it
+pub(crate) const fn value_type_name(value_type: CelValueType) -> &'static str {
+    match value_type {
+        CelValueType::Null => "null_type",
+        CelValueType::Bool => "bool",
+        CelValueType::Int => "int",
+        CelValueType::Uint => "uint",
+        CelValueType::Double => "double",
+        CelValueType::String => "string",
+        CelValueType::Bytes => "bytes",
+        CelValueType::List => "list",
+        CelValueType::Map => "map",
+        CelValueType::Type => "type",
+        CelValueType::Optional => "optional_type",
+        CelValueType::Timestamp => "google.protobuf.Timestamp",
+        CelValueType::Duration => "google.protobuf.Duration",
+        CelValueType::BigInt => "bigint",
+        CelValueType::Decimal => "decimal",
+    }
+}
+
+/// The `CelValueType` a value converts to, or `Type` for values with no C representation
+pub(crate) fn value_type_of(value: &Value) -> CelValueType {
+    if optional_parts(value).is_some() {
+        return CelValueType::Optional;
+    }
+    if bigint_parts(value).is_some() {
+        return CelValueType::BigInt;
+    }
+    if decimal_parts(value).is_some() {
+        return CelValueType::Decimal;
+    }
+    match value {
+        Value::Null => CelValueType::Null,
+        Value::Bool(_) => CelValueType::Bool,
+        Value::Int(_) => CelValueType::Int,
+        Value::UInt(_) => CelValueType::Uint,
+        Value::Float(_) => CelValueType::Double,
+        Value::String(_) => CelValueType::String,
+        Value::Bytes(_) => CelValueType::Bytes,
+        Value::List(_) => CelValueType::List,
+        Value::Map(_) => CelValueType::Map,
+        Value::Timestamp(_) => CelValueType::Timestamp,
+        Value::Duration(_) => CelValueType::Duration,
+        _ => CelValueType::Type,
+    }
+}
+
+pub(crate) fn json_to_cel_value(value: &serde_json::Value) -> Result<Value, String> {
+    match value {
+        serde_json::Value::Null => Ok(Value::Null),
+        serde_json::Value::Bool(b) => Ok(Value::Bool(*b)),
+        serde_json::Value::Number(n) => n.as_i64().map_or_else(
+            || {
+                n.as_u64().map_or_else(
+                    || {
+                        n.as_f64().map_or_else(
+                            || Err("Invalid number format".to_string()),
+                            |f| Ok(Value::Float(f)),
+                        )
+                    },
+                    |u| Ok(Value::UInt(u)),
+                )
+            },
+            |i| Ok(Value::Int(i)),
+        ),
+        serde_json::Value::String(s) => Ok(Value::String(s.clone().into())),
+        serde_json::Value::Array(arr) => {
+            let cel_list: Result<Vec<_>, _> = arr.iter().map(json_to_cel_value).collect();
+            Ok(Value::List(cel_list?.into()))
+        }
+        serde_json::Value::Object(obj) => {
+            let mut cel_map = std::collections::HashMap::new();
+            for (k, v) in obj {
+                cel_map.insert(Key::String(k.clone().into()), json_to_cel_value(v)?);
+            }
+            Ok(Value::Map(Map {
+                map: cel_map.into(),
+            }))
+        }
+    }
+}
+
+const NANOS_PER_SECOND: u32 = 1_000_000_000;
+/// 0001-01-01T00:00:00Z
+pub(crate) const MIN_TIMESTAMP_SECONDS: i64 = -62_135_596_800;
+/// 9999-12-31T23:59:59Z
+pub(crate) const MAX_TIMESTAMP_SECONDS: i64 = 253_402_300_799;
+/// Roughly 10000 years, the longest duration CEL represents
+pub(crate) const MAX_DURATION_SECONDS: u64 = 315_576_000_000;
+
+/// The timestamp `nanos` nanoseconds after `seconds` since the Unix epoch, in UTC
+///
+/// # Errors
+///
+/// Returns `Error::InvalidArgument` if `nanos` is a second or more, or the instant
+/// falls outside the years 0001 to 9999 that CEL timestamps cover.
+pub(crate) fn timestamp_value(seconds: i64, nanos: u32) -> Result<Value, Error> {
+    if nanos >= NANOS_PER_SECOND {
+        return Err(Error::InvalidArgument(format!(
+            "nanos must be less than {NANOS_PER_SECOND}, got {nanos}"
+        )));
+    }
+    let time = (MIN_TIMESTAMP_SECONDS..=MAX_TIMESTAMP_SECONDS)
+        .contains(&seconds)
+        .then(|| DateTime::from_timestamp(seconds, nanos))
+        .flatten()
+        .ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "timestamp {seconds} is outside the range of CEL timestamps"
+            ))
+        })?;
+    Ok(Value::Timestamp(time.fixed_offset()))
+}
+
+/// The duration of `seconds` and `nanos`, where `nanos` has the sign of `seconds`
+/// as in `google.protobuf.Duration`
+///
+/// # Errors
+///
+/// Returns `Error::InvalidArgument` if `nanos` is a second or more, its sign differs
+/// from that of `seconds`, or the duration is longer than about 10000 years.
+pub(crate) fn duration_value(seconds: i64, nanos: i32) -> Result<Value, Error> {
+    if nanos.unsigned_abs() >= NANOS_PER_SECOND
+        || (seconds < 0 && nanos > 0)
+        || (seconds > 0 && nanos < 0)
+    {
+        return Err(Error::InvalidArgument(format!(
+            "nanos must be less than a second with the sign of seconds, got {nanos}"
+        )));
+    }
+    let duration = (seconds.unsigned_abs() <= MAX_DURATION_SECONDS)
+        .then(|| TimeDelta::try_seconds(seconds))
+        .flatten()
+        .map(|duration| duration + TimeDelta::nanoseconds(nanos.into()))
+        .ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "duration of {seconds}s is outside the range of CEL durations"
+            ))
+        })?;
+    Ok(Value::Duration(duration))
+}
+
+/// Parse an RFC 3339 timestamp such as `2024-01-01T12:00:00Z`, as `timestamp()` does
+///
+/// # Errors
+///
+/// Returns `Error::InvalidArgument` if `text` is not RFC 3339 or falls outside the
+/// years 0001 to 9999.
+pub(crate) fn parse_timestamp(text: &str) -> Result<Value, Error> {
+    let time = DateTime::parse_from_rfc3339(text)
+        .map_err(|e| Error::InvalidArgument(format!("invalid timestamp '{text}': {e}")))?;
+    timestamp_value(time.timestamp(), time.timestamp_subsec_nanos())
+}
+
+/// Parse a duration such as `1h30m`, `-1.5s` or `250ms`, as `duration()` does
+///
+/// The text is an optional sign followed by decimal numbers, each with one of the
+/// units `h`, `m`, `s`, `ms`, `us` (or `µs`) and `ns`; a bare `0` is also accepted.
+///
+/// # Errors
+///
+/// Returns `Error::InvalidArgument` if `text` is malformed or the duration is longer
+/// than about 10000 years.
+pub(crate) fn parse_duration(text: &str) -> Result<Value, Error> {
+    let invalid = || Error::InvalidArgument(format!("invalid duration '{text}'"));
+    let (negative, mut rest) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    if rest == "0" {
+        return Ok(Value::Duration(TimeDelta::zero()));
+    }
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut total_nanos: i128 = 0;
+    while !rest.is_empty() {
+        let number_len = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        let (number, after) = rest.split_at(number_len);
+        let unit_len = after.find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(after.len());
+        let (unit, after) = after.split_at(unit_len);
+        rest = after;
+
+        let unit_nanos: i128 = match unit {
+            "ns" => 1,
+            "us" | "µs" => 1_000,
+            "ms" => 1_000_000,
+            "s" => 1_000_000_000,
+            "m" => 60_000_000_000,
+            "h" => 3_600_000_000_000,
+            _ => return Err(invalid()),
+        };
+        let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(invalid());
+        }
+        let whole =
+            if whole.is_empty() { 0 } else { whole.parse::<i128>().map_err(|_| invalid())? };
+        let mut scale = unit_nanos;
+        let mut fraction_nanos = 0;
+        for digit in fraction.bytes() {
+            if !digit.is_ascii_digit() {
+                return Err(invalid());
+            }
+            scale /= 10;
+            fraction_nanos += i128::from(digit - b'0') * scale;
+        }
+
+        total_nanos = whole
+            .checked_mul(unit_nanos)
+            .and_then(|nanos| nanos.checked_add(fraction_nanos))
+            .and_then(|nanos| total_nanos.checked_add(nanos))
+            .ok_or_else(invalid)?;
+    }
+
+    if negative {
+        total_nanos = -total_nanos;
+    }
+    let seconds =
+        i64::try_from(total_nanos / i128::from(NANOS_PER_SECOND)).map_err(|_| invalid())?;
+    let nanos = i32::try_from(total_nanos % i128::from(NANOS_PER_SECOND)).map_err(|_| invalid())?;
+    duration_value(seconds, nanos)
+}
+
+/// The type-preserving JSON form of `value`, as written by `Context::to_json`
+///
+/// Each value is an object with one member named for its type, holding the value as
+/// plain JSON. Non-finite doubles use the names of `OwnedValue`, bytes are base64,
+/// timestamps are RFC 3339, durations are seconds such as `-1.5s`, big integers and
+/// decimals are strings of digits, and types are their names. Lists hold
+/// tagged items, maps hold `[key, value]` pairs of tagged values ordered by key, and
+/// optionals hold a tagged value or `null` when empty.
+///
+/// # Errors
+///
+/// Returns an error for function values, which have no JSON form.
+pub(crate) fn value_to_tagged_json(value: &Value) -> Result<serde_json::Value, String> {
+    use serde_json::json;
+
+    if let Some(inner) = optional_parts(value) {
+        return Ok(json!({ "optional": inner.map(value_to_tagged_json).transpose()? }));
+    }
+    if let Some(n) = bigint_parts(value) {
+        return Ok(json!({ "bigint": n.to_string() }));
+    }
+    if let Some(decimal) = decimal_parts(value) {
+        return Ok(json!({ "decimal": decimal.to_string() }));
+    }
+    if let Some(name) = type_parts(value) {
+        return Ok(json!({ "type": name }));
+    }
+    Ok(match value {
+        Value::Null => json!({ "null": null }),
+        Value::Bool(b) => json!({ "bool": b }),
+        Value::Int(i) => json!({ "int": i }),
+        Value::UInt(u) => json!({ "uint": u }),
+        Value::Float(f) if f.is_nan() => json!({ "double": "NaN" }),
+        Value::Float(f) if f.is_infinite() => {
+            json!({ "double": if *f > 0.0 { "Infinity" } else { "-Infinity" } })
+        }
+        Value::Float(f) => json!({ "double": f }),
+        Value::String(s) => json!({ "string": s.as_str() }),
+        Value::Bytes(b) => json!({ "bytes": base64_encode(b) }),
+        Value::List(items) => json!({
+            "list": items.iter().map(value_to_tagged_json).collect::<Result<Vec<_>, _>>()?
+        }),
+        Value::Map(map) => {
+            let mut entries = map
+                .map
+                .iter()
+                .map(|(key, value)| Ok((key_to_tagged_json(key), value_to_tagged_json(value)?)))
+                .collect::<Result<Vec<_>, String>>()?;
+            entries.sort_by_cached_key(|(key, _)| key.to_string());
+            let entries = entries.into_iter().map(|(key, value)| json!([key, value]));
+            json!({ "map": entries.collect::<Vec<_>>() })
+        }
+        Value::Timestamp(time) => {
+            json!({ "timestamp": time.to_rfc3339_opts(SecondsFormat::AutoSi, true) })
+        }
+        Value::Duration(duration) => json!({ "duration": format_duration(*duration) }),
+        _ => return Err("Unsupported value type".to_string()),
+    })
+}
+
+/// Read a value written by `value_to_tagged_json`
+///
+/// # Errors
+///
+/// Returns an error if `json` is not a tagged value or its contents are out of range.
+pub(crate) fn value_from_tagged_json(json: &serde_json::Value) -> Result<Value, String> {
+    let invalid = || format!("Invalid tagged value: {json}");
+    let Some((tag, payload)) =
+        json.as_object().filter(|o| o.len() == 1).and_then(|o| o.iter().next())
+    else {
+        return Err(invalid());
+    };
+
+    let value = match tag.as_str() {
+        "null" => payload.is_null().then_some(Value::Null),
+        "bool" => payload.as_bool().map(Value::Bool),
+        "int" => payload.as_i64().map(Value::Int),
+        "uint" => payload.as_u64().map(Value::UInt),
+        "double" => match payload.as_str() {
+            Some("NaN") => Some(f64::NAN),
+            Some("Infinity") => Some(f64::INFINITY),
+            Some("-Infinity") => Some(f64::NEG_INFINITY),
+            Some(_) => None,
+            None => payload.as_f64(),
+        }
+        .map(Value::Float),
+        "string" => payload.as_str().map(|s| Value::String(Arc::new(s.to_string()))),
+        "bytes" => payload.as_str().and_then(base64_decode).map(|b| Value::Bytes(Arc::new(b))),
+        "list" => {
+            let items = payload.as_array().ok_or_else(invalid)?;
+            let items = items.iter().map(value_from_tagged_json).collect::<Result<Vec<_>, _>>()?;
+            Some(Value::List(Arc::new(items)))
+        }
+        "map" => {
+            let entries = payload.as_array().ok_or_else(invalid)?;
+            let map = entries
+                .iter()
+                .map(|entry| match entry.as_array().map(Vec::as_slice) {
+                    Some([key, value]) => {
+                        Ok((key_from_tagged_json(key)?, value_from_tagged_json(value)?))
+                    }
+                    _ => Err(invalid()),
+                })
+                .collect::<Result<std::collections::HashMap<_, _>, String>>()?;
+            Some(Value::Map(Map { map: Arc::new(map) }))
+        }
+        "timestamp" => {
+            let text = payload.as_str().ok_or_else(invalid)?;
+            // Checks the range; the parsed time keeps the offset it was written with
+            parse_timestamp(text).map_err(|e| e.to_string())?;
+            DateTime::parse_from_rfc3339(text).ok().map(Value::Timestamp)
+        }
+        "duration" => {
+            let text = payload.as_str().ok_or_else(invalid)?;
+            Some(parse_duration(text).map_err(|e| e.to_string())?)
+        }
+        "bigint" => {
+            let text = payload.as_str().ok_or_else(invalid)?;
+            Some(parse_bigint(text).map_err(|e| e.to_string())?)
+        }
+        "decimal" => {
+            let text = payload.as_str().ok_or_else(invalid)?;
+            Some(parse_decimal(text).map_err(|e| e.to_string())?)
+        }
+        "type" => {
+            let text = payload.as_str().ok_or_else(invalid)?;
+            Some(parse_type(text).map_err(|e| e.to_string())?)
+        }
+        "optional" if payload.is_null() => Some(optional_value(None)),
+        "optional" => Some(optional_value(Some(value_from_tagged_json(payload)?))),
+        _ => None,
+    };
+    value.ok_or_else(invalid)
+}
+
+fn key_to_tagged_json(key: &Key) -> serde_json::Value {
+    use serde_json::json;
+
+    match key {
+        Key::Int(i) => json!({ "int": i }),
+        Key::Uint(u) => json!({ "uint": u }),
+        Key::Bool(b) => json!({ "bool": b }),
+        Key::String(s) => json!({ "string": s.as_str() }),
+    }
+}
+
+fn key_from_tagged_json(json: &serde_json::Value) -> Result<Key, String> {
+    match value_from_tagged_json(json)? {
+        Value::Int(i) => Ok(Key::Int(i)),
+        Value::UInt(u) => Ok(Key::Uint(u)),
+        Value::Bool(b) => Ok(Key::Bool(b)),
+        Value::String(s) => Ok(Key::String(s)),
+        _ => Err(format!("Invalid map key: {json}")),
+    }
+}
+
+/// `duration` in seconds with up to nine decimal places, as `parse_duration` reads it
+pub(crate) fn format_duration(duration: TimeDelta) -> String {
+    let sign = if duration < TimeDelta::zero() { "-" } else { "" };
+    let seconds = duration.num_seconds().unsigned_abs();
+    let nanos = duration.subsec_nanos().unsigned_abs();
+    if nanos == 0 {
+        return format!("{sign}{seconds}s");
+    }
+    let fraction = format!("{nanos:09}");
+    format!("{sign}{seconds}.{}s", fraction.trim_end_matches('0'))
+}
+
+/// An evaluation result that owns its data and serializes to plain JSON
+///
+/// Lists become arrays and maps become objects with their keys rendered as strings,
+/// ordered by key. Bytes serialize as standard padded base64 and non-finite doubles as
+/// `"NaN"`, `"Infinity"` and `"-Infinity"`, following the protobuf JSON mapping. JSON
+/// has no bytes type, so deserializing always produces `String` for string data. An
+/// optional becomes its value, or `Null` when empty, and big integers and decimals
+/// become their digits as a `String`, since JSON numbers would lose precision.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OwnedValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Uint(u64),
+    Double(#[serde(serialize_with = "serialize_double")] f64),
+    String(String),
+    #[serde(skip_deserializing, serialize_with = "serialize_bytes")]
+    Bytes(Vec<u8>),
+    List(Vec<OwnedValue>),
+    Map(BTreeMap<String, OwnedValue>),
+}
+
+impl TryFrom<&Value> for OwnedValue {
+    type Error = String;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        if let Some(inner) = optional_parts(value) {
+            return inner.map_or(Ok(Self::Null), Self::try_from);
+        }
+        if let Some(n) = bigint_parts(value) {
+            return Ok(Self::String(n.to_string()));
+        }
+        if let Some(decimal) = decimal_parts(value) {
+            return Ok(Self::String(decimal.to_string()));
+        }
+        if let Some(name) = type_parts(value) {
+            return Ok(Self::String(name.to_string()));
+        }
+        Ok(match value {
+            Value::Null => Self::Null,
+            Value::Bool(b) => Self::Bool(*b),
+            Value::Int(i) => Self::Int(*i),
+            Value::UInt(u) => Self::Uint(*u),
+            Value::Float(f) => Self::Double(*f),
+            Value::String(s) => Self::String(s.to_string()),
+            Value::Bytes(b) => Self::Bytes(b.to_vec()),
+            Value::List(items) => {
+                Self::List(items.iter().map(Self::try_from).collect::<Result<_, _>>()?)
+            }
+            Value::Map(map) => Self::Map(
+                map.map
+                    .iter()
+                    .map(|(key, value)| Ok((key_to_string(key), Self::try_from(value)?)))
+                    .collect::<Result<_, String>>()?,
+            ),
+            _ => return Err("Unsupported return value type".to_string()),
+        })
+    }
+}
+
+impl From<OwnedValue> for Value {
+    fn from(value: OwnedValue) -> Self {
+        match value {
+            OwnedValue::Null => Self::Null,
+            OwnedValue::Bool(b) => Self::Bool(b),
+            OwnedValue::Int(i) => Self::Int(i),
+            OwnedValue::Uint(u) => Self::UInt(u),
+            OwnedValue::Double(f) => Self::Float(f),
+            OwnedValue::String(s) => Self::String(s.into()),
+            OwnedValue::Bytes(b) => Self::Bytes(b.into()),
+            OwnedValue::List(items) => {
+                Self::List(items.into_iter().map(Self::from).collect::<Vec<_>>().into())
+            }
+            OwnedValue::Map(map) => Self::Map(Map {
+                map: map
+                    .into_iter()
+                    .map(|(key, value)| (Key::String(key.into()), Self::from(value)))
+                    .collect::<std::collections::HashMap<_, _>>()
+                    .into(),
+            }),
+        }
+    }
+}
+
+fn key_to_string(key: &Key) -> String {
+    match key {
+        Key::Int(i) => i.to_string(),
+        Key::Uint(u) => u.to_string(),
+        Key::Bool(b) => b.to_string(),
+        Key::String(s) => s.to_string(),
+    }
+}
+
+fn serialize_double<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    if value.is_nan() {
+        serializer.serialize_str("NaN")
+    } else if value.is_infinite() {
+        serializer.serialize_str(if *value > 0.0 { "Infinity" } else { "-Infinity" })
+    } else {
+        serializer.serialize_f64(*value)
+    }
+}
+
+fn serialize_bytes<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&base64_encode(value))
+}
+
+pub(crate) fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |group, (i, byte)| group | (u32::from(*byte) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(ALPHABET[(group >> (18 - 6 * i)) as usize & 0x3f]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Decode standard padded base64, as written by `base64_encode`
+pub(crate) fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 4 != 0 {
+        return None;
+    }
+    let sextet = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+
+    let mut decoded = Vec::with_capacity(text.len() / 4 * 3);
+    let chunks = text.as_bytes().chunks(4);
+    let last = chunks.len().saturating_sub(1);
+    for (index, chunk) in chunks.enumerate() {
+        let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && index != last) {
+            return None;
+        }
+        let mut group = 0u32;
+        for (i, c) in chunk.iter().take(4 - padding).enumerate() {
+            group |= u32::from(sextet(*c)?) << (18 - 6 * i);
+        }
+        let bytes = group.to_be_bytes();
+        decoded.extend_from_slice(bytes.get(1..4 - padding)?);
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_owned_value_serializes_to_json() {
+        let value = Value::List(
+            vec![
+                Value::Int(-1),
+                Value::UInt(u64::MAX),
+                Value::Float(1.5),
+                Value::Float(f64::NAN),
+                Value::Bytes(b"hello".to_vec().into()),
+                Value::Null,
+            ]
+            .into(),
+        );
+
+        let owned = OwnedValue::try_from(&value).unwrap();
+        assert_eq!(
+            serde_json::to_string(&owned).unwrap(),
+            r#"[-1,18446744073709551615,1.5,"NaN","aGVsbG8=",null]"#
+        );
+    }
+
+    #[test]
+    fn test_owned_value_map_keys_are_strings() {
+        let map: std::collections::HashMap<Key, Value> = [
+            (Key::Int(2), Value::Bool(true)),
+            (Key::String("a".to_string().into()), Value::String("x".to_string().into())),
+        ]
+        .into_iter()
+        .collect();
+        let value = Value::Map(Map { map: map.into() });
+
+        let owned = OwnedValue::try_from(&value).unwrap();
+        assert_eq!(serde_json::to_string(&owned).unwrap(), r#"{"2":true,"a":"x"}"#);
+    }
+
+    #[test]
+    fn test_owned_value_round_trips_through_json() {
+        let json = r#"{"items":[1,18446744073709551615,2.5,"s",null,false]}"#;
+        let owned: OwnedValue = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            owned,
+            OwnedValue::Map(BTreeMap::from([(
+                "items".to_string(),
+                OwnedValue::List(vec![
+                    OwnedValue::Int(1),
+                    OwnedValue::Uint(u64::MAX),
+                    OwnedValue::Double(2.5),
+                    OwnedValue::String("s".to_string()),
+                    OwnedValue::Null,
+                    OwnedValue::Bool(false),
+                ])
+            )]))
+        );
+        assert_eq!(serde_json::to_string(&owned).unwrap(), json);
+        assert!(matches!(Value::from(owned), Value::Map(_)));
+    }
+
+    #[test]
+    fn test_base64_padding() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(&[0xfb, 0xff]), "+/8=");
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let value = parse_timestamp("2024-01-01T01:00:00.25+01:00").unwrap();
+        assert_eq!(value, timestamp_value(1_704_067_200, 250_000_000).unwrap());
+        assert_eq!(value_type_of(&value), CelValueType::Timestamp);
+
+        assert!(parse_timestamp("2024-01-01").is_err());
+        assert!(parse_timestamp("0000-12-31T23:59:59Z").is_err());
+        assert!(timestamp_value(0, 1_000_000_000).is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        let seconds = |text: &str| match parse_duration(text).unwrap() {
+            Value::Duration(duration) => (duration.num_seconds(), duration.subsec_nanos()),
+            other => panic!("unexpected value: {other:?}"),
+        };
+        assert_eq!(seconds("1h30m"), (5400, 0));
+        assert_eq!(seconds("-1.5s"), (-1, -500_000_000));
+        assert_eq!(seconds("2m3.25s"), (123, 250_000_000));
+        assert_eq!(seconds("250ms1us5ns"), (0, 250_001_005));
+        assert_eq!(seconds(".5µs"), (0, 500));
+        assert_eq!(seconds("0"), (0, 0));
+
+        for text in [
+            "",
+            "-",
+            "1",
+            "1d",
+            "h",
+            "1.2.3s",
+            "1e3s",
+            "99999999999999999h",
+        ] {
+            assert!(parse_duration(text).is_err(), "{text}");
+        }
+        assert!(duration_value(1, -1).is_err());
+    }
+
+    #[test]
+    fn test_parse_bigint() {
+        let value = parse_bigint(" 170141183460469231731687303715884105727").unwrap();
+        assert_eq!(bigint_parts(&value), Some(i128::MAX));
+        assert_eq!(value_type_of(&value), CelValueType::BigInt);
+        assert_eq!(bigint_parts(&Value::Int(5)), None);
+
+        assert!(parse_bigint("170141183460469231731687303715884105728").is_err());
+        assert!(parse_bigint("1.5").is_err());
+    }
+
+    #[test]
+    fn test_tagged_json_round_trip() {
+        let map = Value::Map(Map {
+            map: Arc::new(
+                [
+                    (Key::Int(-1), Value::Bytes(Arc::new(vec![0, 0xff, b'a']))),
+                    (Key::Uint(2), Value::Float(f64::NEG_INFINITY)),
+                    (Key::Bool(true), optional_value(None)),
+                    (Key::String(Arc::new("k".to_string())), Value::Null),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+        });
+        let value = Value::List(Arc::new(vec![
+            map,
+            Value::UInt(u64::MAX),
+            Value::Float(1.0),
+            optional_value(Some(Value::String(Arc::new("s".to_string())))),
+            parse_timestamp("2024-01-01T12:00:00.5+02:00").unwrap(),
+            parse_duration("-1.25s").unwrap(),
+            bigint_value(i128::MIN),
+            parse_decimal("-0.10").unwrap(),
+            type_value("google.protobuf.Duration"),
+        ]));
+
+        let json = value_to_tagged_json(&value).unwrap();
+        assert_eq!(json["list"][1], serde_json::json!({ "uint": u64::MAX }));
+        assert_eq!(json["list"][2], serde_json::json!({ "double": 1.0 }));
+        assert_eq!(json["list"][4], serde_json::json!({ "timestamp": "2024-01-01T10:00:00.500Z" }));
+        assert_eq!(json["list"][5], serde_json::json!({ "duration": "-1.25s" }));
+        assert_eq!(json["list"][6], serde_json::json!({ "bigint": i128::MIN.to_string() }));
+        assert_eq!(json["list"][7], serde_json::json!({ "decimal": "-0.10" }));
+        assert_eq!(json["list"][8], serde_json::json!({ "type": "google.protobuf.Duration" }));
+
+        let text = json.to_string();
+        let decoded = value_from_tagged_json(&serde_json::from_str(&text).unwrap()).unwrap();
+        assert_eq!(value_to_tagged_json(&decoded).unwrap(), json);
+
+        for invalid in [
+            serde_json::json!(1),
+            serde_json::json!({ "int": "1" }),
+            serde_json::json!({ "int": 1, "uint": 1 }),
+            serde_json::json!({ "bytes": "Zg=" }),
+            serde_json::json!({ "map": [[{ "double": 1.0 }, { "null": null }]] }),
+            serde_json::json!({ "timestamp": "10000-01-01T00:00:00Z" }),
+            serde_json::json!({ "bigint": "1e3" }),
+            serde_json::json!({ "decimal": 1.5 }),
+            serde_json::json!({ "type": "" }),
+        ] {
+            assert!(value_from_tagged_json(&invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_base64_decode() {
+        for bytes in [&b""[..], b"f", b"fo", b"foo", b"foob", &[0xfb, 0xff]] {
+            assert_eq!(base64_decode(&base64_encode(bytes)).as_deref(), Some(bytes));
+        }
+        for text in ["Zg", "Z===", "Zg==Zg==", "Zm9*"] {
+            assert_eq!(base64_decode(text), None, "{text}");
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use super::error::contain;
+use super::{
+    CelBytesValue, CelErrorCode, CelExecuteOptions, CelListValue, CelLogLevel, CelMapValue,
+    CelStringValue, CelTimeValue, CelValue, CelValueData, CelValueType,
+};
+use crate::api::capabilities;
+use std::ffi::{c_char, CString};
+use std::mem::{align_of, offset_of, size_of};
+use std::sync::OnceLock;
+
+/// Version of the C ABI exposed by this library
+///
+/// Bump it whenever an exported signature or `#[repr(C)]` layout changes, then
+/// regenerate `include/cel_lua.h` with `make header` and update `CEL_ABI_VERSION`
+/// in `lib/cel/cdefs.lua`.
+pub const CEL_ABI_VERSION: u32 = 2;
+
+/// ABI version the library was compiled with, for load-time checks by bindings
+#[no_mangle]
+pub const extern "C" fn cel_abi_version() -> u32 {
+    CEL_ABI_VERSION
+}
+
+/// JSON object describing this build, for feature detection by bindings
+///
+/// It holds the library `version`, `abi_version`, the optional `features` compiled in,
+/// the `extensions` programs can enable, the supported `value_types`, the built-in
+/// `functions` and `macros`, and fixed `limits`. The string is null-terminated, owned
+/// by the library and valid until it is unloaded; do not free it.
+#[no_mangle]
+pub extern "C" fn cel_capabilities_json() -> *const c_char {
+    contain(std::ptr::null(), || {
+        static CAPABILITIES: OnceLock<CString> = OnceLock::new();
+        CAPABILITIES
+            .get_or_init(|| CString::new(capabilities().to_json()).unwrap_or_default())
+            .as_ptr()
+    })
+}
+
+const WORD: usize = size_of::<usize>();
+
+// Layout guarantees the generated header and the LuaJIT cdefs rely on
+const _: () = {
+    assert!(size_of::<CelValueType>() == size_of::<u32>());
+    assert!(size_of::<CelErrorCode>() == size_of::<u32>());
+    assert!(size_of::<CelLogLevel>() == size_of::<u32>());
+
+    assert!(size_of::<CelStringValue>() == 2 * WORD);
+    assert!(offset_of!(CelStringValue, len) == WORD);
+    assert!(size_of::<CelBytesValue>() == size_of::<CelStringValue>());
+    assert!(size_of::<CelListValue>() == 2 * WORD);
+    assert!(offset_of!(CelListValue, len) == WORD);
+    assert!(size_of::<CelMapValue>() == 3 * WORD);
+    assert!(offset_of!(CelMapValue, values) == WORD);
+    assert!(offset_of!(CelMapValue, len) == 2 * WORD);
+    assert!(size_of::<CelTimeValue>() == 16);
+    assert!(offset_of!(CelTimeValue, nanos) == 8);
+
+    assert!(align_of::<CelValueData>() == align_of::<u64>());
+    assert!(size_of::<CelValueData>() >= size_of::<CelMapValue>());
+    assert!(offset_of!(CelValue, value_type) == 0);
+    assert!(offset_of!(CelValue, data) == align_of::<CelValueData>());
+    assert!(size_of::<CelValue>() == offset_of!(CelValue, data) + size_of::<CelValueData>());
+
+    assert!(size_of::<CelExecuteOptions>() == WORD);
+};
+
+#[cfg(target_pointer_width = "64")]
+const _: () = {
+    assert!(size_of::<CelValueData>() == 24);
+    assert!(size_of::<CelValue>() == 32);
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_abi_version() {
+        assert_eq!(cel_abi_version(), CEL_ABI_VERSION);
+    }
+
+    #[test]
+    fn test_capabilities_json() {
+        let json = unsafe { std::ffi::CStr::from_ptr(cel_capabilities_json()) };
+        let json: serde_json::Value = serde_json::from_str(json.to_str().unwrap()).unwrap();
+        assert_eq!(json["version"], serde_json::json!(env!("CARGO_PKG_VERSION")));
+        assert!(json["value_types"].as_array().unwrap().contains(&serde_json::json!("bytes")));
+        assert_eq!(cel_capabilities_json(), cel_capabilities_json());
+    }
+
+    #[test]
+    fn test_abi_version_matches_bindings() {
+        let header = include_str!("../../include/cel_lua.h");
+        assert!(header.contains(&format!("#define CEL_ABI_VERSION {CEL_ABI_VERSION}\n")));
+
+        let cdefs = include_str!("../../lib/cel/cdefs.lua");
+        assert!(cdefs.contains(&format!("local CEL_ABI_VERSION = {CEL_ABI_VERSION}\n")));
+
+        let flat = include_str!("../../lib/cel/flat.lua");
+        assert!(flat.contains(&format!("local CEL_ABI_VERSION = {CEL_ABI_VERSION}\n")));
+    }
+}
@@ -0,0 +1,284 @@
+//! Routing of the library's heap allocations through host-supplied functions, built
+//! with the `allocator` feature
+//!
+//! The feature installs a global allocator, so everything the library allocates goes
+//! through it: values, programs and contexts as well as the strings handed to the host.
+//! Every block starts with a header recording which functions allocated it, so a block
+//! allocated before `cel_set_allocator` (or under earlier functions) is still freed by
+//! the allocator it came from.
+
+use super::error::{contain, report};
+use super::CelErrorCode;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::ffi::c_void;
+use std::ptr::{self, null_mut};
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/// Allocate `size` bytes aligned like `malloc` does, or return null
+pub type CelMallocFn = unsafe extern "C" fn(user_data: *mut c_void, size: usize) -> *mut c_void;
+
+/// Free a block returned by the matching `CelMallocFn` or `CelReallocFn`
+pub type CelFreeFn = unsafe extern "C" fn(user_data: *mut c_void, ptr: *mut c_void);
+
+/// Resize a block to `size` bytes, moving it if needed, or return null and leave it as is
+pub type CelReallocFn =
+    unsafe extern "C" fn(user_data: *mut c_void, ptr: *mut c_void, size: usize) -> *mut c_void;
+
+/// Host allocation functions, as set by one call to `cel_set_allocator`
+///
+/// They are leaked rather than freed when replaced, since blocks they allocated keep
+/// pointing at them.
+#[derive(Debug)]
+struct Hooks {
+    malloc: CelMallocFn,
+    free: CelFreeFn,
+    realloc: Option<CelReallocFn>,
+    user_data: *mut c_void,
+}
+
+/// The functions new blocks are allocated with, or null for the system allocator
+static HOOKS: AtomicPtr<Hooks> = AtomicPtr::new(null_mut());
+
+/// Bytes in front of every block: its base address, then the `Hooks` that allocated it
+const HEADER: usize = 16;
+
+/// The alignment `malloc` guarantees, which host functions must match
+const MIN_ALIGN: usize = 16;
+
+/// The global allocator of builds with the `allocator` feature
+#[derive(Debug)]
+struct HookedAllocator;
+
+#[global_allocator]
+static ALLOCATOR: HookedAllocator = HookedAllocator;
+
+/// Room in front of the caller's bytes, a multiple of the alignment they need
+const fn header_size(layout: Layout) -> usize {
+    if layout.align() > HEADER {
+        layout.align()
+    } else {
+        HEADER
+    }
+}
+
+/// The layout of a whole block from the system allocator
+unsafe fn system_layout(layout: Layout) -> Layout {
+    Layout::from_size_align_unchecked(
+        layout.size() + header_size(layout),
+        layout.align().max(MIN_ALIGN),
+    )
+}
+
+/// Record the block's base address and allocator in front of `ptr`
+// Blocks and the caller's bytes are at least `MIN_ALIGN`-aligned, and so is the header
+#[allow(clippy::cast_ptr_alignment)]
+const unsafe fn write_header(ptr: *mut u8, base: *mut u8, hooks: *mut Hooks) {
+    ptr.sub(HEADER).cast::<*mut u8>().write(base);
+    ptr.sub(HEADER / 2).cast::<*mut Hooks>().write(hooks);
+}
+
+#[allow(clippy::cast_ptr_alignment)]
+const unsafe fn read_header(ptr: *mut u8) -> (*mut u8, *mut Hooks) {
+    (
+        ptr.sub(HEADER).cast::<*mut u8>().read(),
+        ptr.sub(HEADER / 2).cast::<*mut Hooks>().read(),
+    )
+}
+
+unsafe impl GlobalAlloc for HookedAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let hooks = HOOKS.load(Ordering::Acquire);
+        let Some(current) = hooks.as_ref() else {
+            let base = System.alloc(system_layout(layout));
+            if base.is_null() {
+                return base;
+            }
+            let ptr = base.add(header_size(layout));
+            write_header(ptr, base, hooks);
+            return ptr;
+        };
+
+        // `malloc` only aligns to `MIN_ALIGN`, so stricter alignments need slack
+        let slack = if layout.align() > MIN_ALIGN { layout.align() } else { HEADER };
+        let base = (current.malloc)(current.user_data, layout.size() + slack).cast::<u8>();
+        if base.is_null() {
+            return base;
+        }
+        let start = (base.addr() + HEADER).next_multiple_of(layout.align());
+        let ptr = base.add(start - base.addr());
+        write_header(ptr, base, hooks);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (base, hooks) = read_header(ptr);
+        match hooks.as_ref() {
+            None => System.dealloc(base, system_layout(layout)),
+            Some(hooks) => (hooks.free)(hooks.user_data, base.cast()),
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let (base, hooks) = read_header(ptr);
+        // Blocks stay with the allocator they came from. Only blocks whose caller bytes
+        // sit right after the header keep their offset when the allocator moves them.
+        let resized = match hooks.as_ref() {
+            None if layout.align() <= MIN_ALIGN => {
+                System.realloc(base, system_layout(layout), new_size + HEADER)
+            }
+            Some(Hooks {
+                realloc: Some(realloc),
+                user_data,
+                ..
+            }) if layout.align() <= MIN_ALIGN => {
+                realloc(*user_data, base.cast(), new_size + HEADER).cast()
+            }
+            _ => {
+                let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+                let new_ptr = self.alloc(new_layout);
+                if !new_ptr.is_null() {
+                    ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                    self.dealloc(ptr, layout);
+                }
+                return new_ptr;
+            }
+        };
+        if resized.is_null() {
+            return resized;
+        }
+        let new_ptr = resized.add(HEADER);
+        write_header(new_ptr, resized, hooks);
+        new_ptr
+    }
+}
+
+/// Allocate everything from now on with `malloc_fn`, `free_fn` and `realloc_fn`
+///
+/// Each function receives `user_data` first, so a host can keep per-library memory
+/// accounting. `malloc_fn` must return memory aligned to 16 bytes, as `malloc` does;
+/// the library over-allocates for anything that needs more. `realloc_fn` may be null,
+/// in which case blocks are resized by allocating, copying and freeing. Blocks carry a
+/// 16-byte header recording which functions allocated them, so blocks from before the
+/// call, including those from the system allocator, are still freed correctly.
+///
+/// Passing a null `malloc_fn` and `free_fn` goes back to the system allocator. Returns
+/// false with `cel_last_error` set to `NullArgument`, and changes nothing, if only one
+/// of them is null. Each call keeps a few bytes that are never freed, so set the
+/// allocator once at startup rather than switching back and forth.
+///
+/// # Safety
+/// Until the library is unloaded, the functions must be safe to call with `user_data`
+/// from any thread that calls into the library, and must not call back into it
+#[no_mangle]
+pub unsafe extern "C" fn cel_set_allocator(
+    malloc_fn: Option<CelMallocFn>,
+    free_fn: Option<CelFreeFn>,
+    realloc_fn: Option<CelReallocFn>,
+    user_data: *mut c_void,
+) -> bool {
+    contain(false, || {
+        let hooks = match (malloc_fn, free_fn) {
+            (Some(malloc), Some(free)) => Box::into_raw(Box::new(Hooks {
+                malloc,
+                free,
+                realloc: realloc_fn,
+                user_data,
+            })),
+            (None, None) => null_mut(),
+            _ => return report(CelErrorCode::NullArgument),
+        };
+        HOOKS.store(hooks, Ordering::Release);
+        report(CelErrorCode::Ok)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Calls made to the test functions, through their `user_data`
+    struct Counts {
+        mallocs: AtomicUsize,
+        frees: AtomicUsize,
+        reallocs: AtomicUsize,
+    }
+
+    static COUNTS: Counts = Counts {
+        mallocs: AtomicUsize::new(0),
+        frees: AtomicUsize::new(0),
+        reallocs: AtomicUsize::new(0),
+    };
+
+    const unsafe fn counts<'a>(user_data: *mut c_void) -> &'a Counts {
+        &*user_data.cast::<Counts>()
+    }
+
+    // A malloc over the system allocator that keeps each block's size in front of it
+    const unsafe fn layout(size: usize) -> Layout {
+        Layout::from_size_align_unchecked(size + MIN_ALIGN, MIN_ALIGN)
+    }
+
+    #[allow(clippy::cast_ptr_alignment)]
+    unsafe extern "C" fn test_malloc(user_data: *mut c_void, size: usize) -> *mut c_void {
+        counts(user_data).mallocs.fetch_add(1, Ordering::Relaxed);
+        let block = System.alloc(layout(size));
+        block.cast::<usize>().write(size);
+        block.add(MIN_ALIGN).cast()
+    }
+
+    #[allow(clippy::cast_ptr_alignment)]
+    unsafe extern "C" fn test_free(user_data: *mut c_void, ptr: *mut c_void) {
+        counts(user_data).frees.fetch_add(1, Ordering::Relaxed);
+        let block = ptr.cast::<u8>().sub(MIN_ALIGN);
+        System.dealloc(block, layout(block.cast::<usize>().read()));
+    }
+
+    #[allow(clippy::cast_ptr_alignment)]
+    unsafe extern "C" fn test_realloc(
+        user_data: *mut c_void,
+        ptr: *mut c_void,
+        size: usize,
+    ) -> *mut c_void {
+        counts(user_data).reallocs.fetch_add(1, Ordering::Relaxed);
+        let block = ptr.cast::<u8>().sub(MIN_ALIGN);
+        let block = System.realloc(block, layout(block.cast::<usize>().read()), size + MIN_ALIGN);
+        block.cast::<usize>().write(size);
+        block.add(MIN_ALIGN).cast()
+    }
+
+    #[repr(align(64))]
+    struct Aligned([u8; 64]);
+
+    #[test]
+    fn test_allocations_go_through_the_host() {
+        let user_data = ptr::from_ref(&COUNTS).cast_mut().cast();
+        let before = vec![1u8; 100];
+        unsafe {
+            assert!(!cel_set_allocator(Some(test_malloc), None, None, user_data));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+            assert!(cel_set_allocator(
+                Some(test_malloc),
+                Some(test_free),
+                Some(test_realloc),
+                user_data
+            ));
+        }
+
+        let mallocs = COUNTS.mallocs.load(Ordering::Relaxed);
+        let mut during = vec![2u8; 100];
+        assert!(COUNTS.mallocs.load(Ordering::Relaxed) > mallocs);
+        during.resize(10_000, 3);
+        assert!(COUNTS.reallocs.load(Ordering::Relaxed) > 0);
+        let aligned = Box::new(Aligned([4; 64]));
+        assert_eq!(ptr::from_ref(aligned.as_ref()).addr() % 64, 0);
+        assert_eq!(aligned.0[63], 4);
+
+        unsafe { assert!(cel_set_allocator(None, None, None, null_mut())) };
+        let frees = COUNTS.frees.load(Ordering::Relaxed);
+        drop(during);
+        drop(aligned);
+        assert!(COUNTS.frees.load(Ordering::Relaxed) >= frees + 2);
+        assert_eq!(before, [1; 100]);
+    }
+}
@@ -0,0 +1,203 @@
+//! Arenas that hold the strings and arrays of evaluation results
+//!
+//! By default every string, bytes value, list and map in a result is a separate heap
+//! allocation, and every string also takes an entry in the library's string table.
+//! An execution given an arena through `CelExecuteOptions` bump-allocates them in the
+//! arena instead, and the next execution that finds no result still using the arena
+//! reuses its memory from the start. Evaluation itself allocates as it always does;
+//! only the conversion of its value into the result moves to the arena.
+
+use super::error::{contain, report};
+use super::result::CelEvalResult;
+use super::{CelErrorCode, Context, Program};
+use bumpalo::Bump;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A reusable arena for evaluation results
+///
+/// Results built in the arena keep its memory alive, so freeing the arena while they
+/// are still in use is safe; the memory goes with the last of them.
+#[derive(Debug, Default)]
+pub struct CelArena {
+    bump: Arc<Mutex<Bump>>,
+}
+
+/// Options for `program_execute_with_options`
+///
+/// The arena only holds the result handed back to the caller. The values the
+/// interpreter builds while evaluating still come from the global allocator, since
+/// cel-interpreter takes no allocator to build them with.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CelExecuteOptions {
+    /// Arena to build the result in, or null to allocate it as `program_execute_alloc`
+    /// does
+    pub arena: *mut CelArena,
+}
+
+/// Create an empty arena
+///
+/// # Ownership
+/// The caller owns the arena and must release it with `cel_arena_free`.
+#[no_mangle]
+pub extern "C" fn cel_arena_new() -> *mut CelArena {
+    contain(std::ptr::null_mut(), || Box::into_raw(Box::default()))
+}
+
+/// Free an arena
+///
+/// Results built in it stay valid until they are freed.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `arena` is either null or a valid pointer returned by `cel_arena_new`
+/// - `arena` has not been previously freed
+#[no_mangle]
+pub unsafe extern "C" fn cel_arena_free(arena: *mut CelArena) {
+    contain((), || {
+        if !arena.is_null() {
+            drop(Box::from_raw(arena));
+        }
+    });
+}
+
+/// Bytes the arena has taken from the allocator, kept across executions for reuse;
+/// 0 for a null arena
+///
+/// # Safety
+/// `arena` must be either null or a valid pointer returned by `cel_arena_new`
+#[no_mangle]
+pub unsafe extern "C" fn cel_arena_allocated_bytes(arena: *const CelArena) -> usize {
+    contain(0, || {
+        arena.as_ref().map_or(0, |arena| {
+            arena.bump.lock().unwrap_or_else(PoisonError::into_inner).allocated_bytes()
+        })
+    })
+}
+
+/// Execute the compiled expression into a newly allocated result, as chosen by
+/// `options`
+///
+/// A null `options`, or a null `options.arena`, behaves as `program_execute_alloc`.
+/// With an arena, the result's strings and arrays are built in it, starting over
+/// from the beginning of the arena if no earlier result built in it is still alive.
+/// Free the result with `cel_result_free` either way.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `options` is either null or a valid pointer to a `CelExecuteOptions`
+/// - `options.arena` is either null or a valid pointer returned by `cel_arena_new`
+#[no_mangle]
+pub unsafe extern "C" fn program_execute_with_options(
+    program: *const Program,
+    context: *const Context,
+    options: *const CelExecuteOptions,
+) -> *mut CelEvalResult {
+    contain(std::ptr::null_mut(), || {
+        let arena = options.as_ref().and_then(|options| options.arena.as_ref());
+        let result = match (program.as_ref(), context.as_ref()) {
+            (Some(program), Some(context)) => {
+                let outcome = program.execute_cancellable(context, None);
+                match arena {
+                    Some(arena) => CelEvalResult::from_cancellable_in(outcome, &arena.bump),
+                    None => CelEvalResult::from_cancellable(outcome),
+                }
+            }
+            (None, _) => CelEvalResult::from_error(CelErrorCode::NullArgument, "Program is null"),
+            (_, None) => CelEvalResult::from_error(CelErrorCode::NullArgument, "Context is null"),
+        };
+        report(result.code());
+        result.into_raw()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cel_result_free, cel_result_value, CelValueType};
+
+    fn execute(program: &Program, context: &Context, arena: *mut CelArena) -> *mut CelEvalResult {
+        let options = CelExecuteOptions { arena };
+        unsafe { program_execute_with_options(program, context, &raw const options) }
+    }
+
+    unsafe fn list_strings(result: *const CelEvalResult) -> Vec<String> {
+        let value = &*cel_result_value(result);
+        assert_eq!(value.value_type, CelValueType::List);
+        let list = &value.data.list_val;
+        std::slice::from_raw_parts(list.items, list.len)
+            .iter()
+            .map(|item| {
+                let string_val = &item.data.string_val;
+                let bytes = std::slice::from_raw_parts(string_val.ptr, string_val.len);
+                assert_eq!(*string_val.ptr.add(string_val.len), 0);
+                String::from_utf8(bytes.to_vec()).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_execute_in_arena() {
+        let mut program = Program::new();
+        program.compile("[name, name + '!', {'k': name}.k]").unwrap();
+        let mut context = Context::new();
+        context.add_variable("name".to_string(), serde_json::json!("ada"));
+        let arena = cel_arena_new();
+
+        unsafe {
+            let first = execute(&program, &context, arena);
+            assert_eq!(list_strings(first), ["ada", "ada!", "ada"]);
+            assert!(cel_arena_allocated_bytes(arena) > 0);
+
+            // A live result keeps its values while later ones are built after them
+            let second = execute(&program, &context, arena);
+            assert_eq!(list_strings(first), ["ada", "ada!", "ada"]);
+            assert_eq!(list_strings(second), ["ada", "ada!", "ada"]);
+            cel_result_free(first);
+            cel_result_free(second);
+
+            // With every result freed, each execution reuses the arena's memory
+            cel_result_free(execute(&program, &context, arena));
+            let used = cel_arena_allocated_bytes(arena);
+            for _ in 0..100 {
+                let result = execute(&program, &context, arena);
+                assert_eq!(list_strings(result), ["ada", "ada!", "ada"]);
+                cel_result_free(result);
+            }
+            assert_eq!(cel_arena_allocated_bytes(arena), used);
+
+            // Freeing the arena leaves a live result intact
+            let result = execute(&program, &context, arena);
+            cel_arena_free(arena);
+            assert_eq!(list_strings(result), ["ada", "ada!", "ada"]);
+            cel_result_free(result);
+        }
+    }
+
+    #[test]
+    fn test_execute_with_options_without_arena() {
+        let mut program = Program::new();
+        program.compile("missing + 1").unwrap();
+        let context = Context::new();
+
+        let result = execute(&program, &context, std::ptr::null_mut());
+        assert_eq!(unsafe { crate::cel_result_error_code(result) }, CelErrorCode::ExecutionError);
+        unsafe { cel_result_free(result) };
+
+        let arena = cel_arena_new();
+        let result = execute(&program, &context, arena);
+        assert_eq!(unsafe { crate::cel_result_error_code(result) }, CelErrorCode::ExecutionError);
+        unsafe {
+            cel_result_free(result);
+            cel_arena_free(arena);
+        }
+
+        let result =
+            unsafe { program_execute_with_options(&program, std::ptr::null(), std::ptr::null()) };
+        assert_eq!(unsafe { crate::cel_result_error_code(result) }, CelErrorCode::NullArgument);
+        unsafe { cel_result_free(result) };
+        assert_eq!(unsafe { cel_arena_allocated_bytes(std::ptr::null()) }, 0);
+    }
+}
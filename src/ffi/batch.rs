@@ -0,0 +1,190 @@
+//! Executing one program against many contexts
+
+use super::error::{contain, report};
+use super::result::CelEvalResult;
+use super::ruleset::entry;
+use super::{CelErrorCode, Context, Program};
+use crate::api::Value as CelRustValue;
+use std::sync::Arc;
+
+/// Execute a program against `count` contexts into a newly allocated list result
+///
+/// Each element is a map with the context's `value`, or with an `error` string if
+/// evaluation failed, so one failing context does not hide the others. The result
+/// fails with `ExecutionError` if nothing is compiled, or with `NullArgument` if
+/// `program` or an entry of `contexts` is null, and is released with `cel_result_free`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `contexts` is either null or points to `count` pointers, each either null or
+///   returned by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn program_execute_batch(
+    program: *const Program,
+    contexts: *const *const Context,
+    count: usize,
+) -> *mut CelEvalResult {
+    contain(std::ptr::null_mut(), || {
+        execute(program, contexts, count, |program, contexts| program.execute_batch(contexts))
+    })
+}
+
+/// Execute like `program_execute_batch`, with the contexts sharded across threads
+///
+/// Uses `threads` threads, or one per CPU for 0, and is built with the `parallel`
+/// feature. Pools are started on first use and kept for later batches with the same
+/// thread count. Resolvers and log callbacks may be called from the pool's threads,
+/// so they must be safe to call concurrently. The result also fails with
+/// `ExecutionError` if the threads cannot be started.
+///
+/// # Safety
+/// As for `program_execute_batch`; the contexts must not be modified until it returns
+#[cfg(feature = "parallel")]
+#[no_mangle]
+pub unsafe extern "C" fn program_execute_batch_parallel(
+    program: *const Program,
+    contexts: *const *const Context,
+    count: usize,
+    threads: usize,
+) -> *mut CelEvalResult {
+    contain(std::ptr::null_mut(), || {
+        execute(program, contexts, count, |program, contexts| {
+            program.execute_batch_parallel(contexts, threads)
+        })
+    })
+}
+
+type Outcomes = Result<Vec<Result<CelRustValue, crate::api::Error>>, String>;
+
+/// Check the arguments, run `batch` over the contexts and box its outcomes as a list
+unsafe fn execute(
+    program: *const Program,
+    contexts: *const *const Context,
+    count: usize,
+    batch: impl FnOnce(&Program, &[&Context]) -> Outcomes,
+) -> *mut CelEvalResult {
+    let result = match (program.as_ref(), contexts_from_raw(contexts, count)) {
+        (Some(program), Some(contexts)) => {
+            CelEvalResult::from_execution(batch(program, &contexts).map(|outcomes| {
+                CelRustValue::List(Arc::new(outcomes.into_iter().map(entry).collect()))
+            }))
+        }
+        (None, _) => CelEvalResult::from_error(CelErrorCode::NullArgument, "Program is null"),
+        (_, None) => CelEvalResult::from_error(CelErrorCode::NullArgument, "Context is null"),
+    };
+    report(result.code());
+    result.into_raw()
+}
+
+/// The `count` contexts at `contexts`, or `None` if the array or an entry is null
+unsafe fn contexts_from_raw<'a>(
+    contexts: *const *const Context,
+    count: usize,
+) -> Option<Vec<&'a Context>> {
+    if count == 0 {
+        return Some(Vec::new());
+    }
+    if contexts.is_null() {
+        return None;
+    }
+    std::slice::from_raw_parts(contexts, count)
+        .iter()
+        .map(|context| context.as_ref())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cel_last_error, cel_result_free, cel_result_is_ok, cel_result_value};
+    use crate::{context_free, context_new, program_free, program_new, CelValueType};
+
+    unsafe fn compiled(expression: &str) -> *mut Program {
+        let program = program_new();
+        (*program).compile(expression).unwrap();
+        program
+    }
+
+    unsafe fn contexts() -> Vec<*mut Context> {
+        (0..5)
+            .map(|n| {
+                let context = context_new();
+                if n != 2 {
+                    (*context).add_variable("n".to_string(), serde_json::json!(n));
+                }
+                context
+            })
+            .collect()
+    }
+
+    /// Length of a batch result's list, after checking each element is a one-entry map
+    unsafe fn outcomes(result: *mut CelEvalResult) -> usize {
+        assert!(cel_result_is_ok(result));
+        let value = &*cel_result_value(result);
+        assert_eq!(value.value_type, CelValueType::List);
+        let len = value.data.list_val.len;
+        let items = std::slice::from_raw_parts(value.data.list_val.items, len);
+        for item in items {
+            assert_eq!(item.value_type, CelValueType::Map);
+            assert_eq!(item.data.map_val.len, 1);
+        }
+        cel_result_free(result);
+        len
+    }
+
+    #[test]
+    fn test_program_execute_batch() {
+        unsafe {
+            let program = compiled("n + 1");
+            let contexts = contexts();
+            let raw: Vec<*const Context> = contexts.iter().map(|c| c.cast_const()).collect();
+
+            assert_eq!(outcomes(program_execute_batch(program, raw.as_ptr(), raw.len())), 5);
+            assert_eq!(cel_last_error(), CelErrorCode::Ok);
+            assert_eq!(outcomes(program_execute_batch(program, std::ptr::null(), 0)), 0);
+
+            let result = program_execute_batch(std::ptr::null(), raw.as_ptr(), raw.len());
+            assert!(!cel_result_is_ok(result));
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+            cel_result_free(result);
+
+            let gap = [raw[0], std::ptr::null()];
+            let result = program_execute_batch(program, gap.as_ptr(), gap.len());
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+            cel_result_free(result);
+
+            let empty = program_new();
+            let result = program_execute_batch(empty, raw.as_ptr(), raw.len());
+            assert_eq!(cel_last_error(), CelErrorCode::ExecutionError);
+            cel_result_free(result);
+
+            program_free(empty);
+            program_free(program);
+            for context in contexts {
+                context_free(context);
+            }
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_program_execute_batch_parallel() {
+        unsafe {
+            let program = compiled("n + 1");
+            let contexts = contexts();
+            let raw: Vec<*const Context> = contexts.iter().map(|c| c.cast_const()).collect();
+
+            for threads in [0, 2] {
+                let result =
+                    program_execute_batch_parallel(program, raw.as_ptr(), raw.len(), threads);
+                assert_eq!(outcomes(result), 5);
+            }
+
+            program_free(program);
+            for context in contexts {
+                context_free(context);
+            }
+        }
+    }
+}
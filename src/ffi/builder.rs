@@ -0,0 +1,514 @@
+//! Lists and maps assembled one element at a time, for collections too large to
+//! pass through JSON
+
+use super::context::{rust_value, variable_target};
+use super::error::{contain, contain_with_error, report};
+use super::program::fail;
+use super::{CelErrorCode, CelValue, CelValueType, Context};
+use crate::api::Value as CelRustValue;
+use cel_interpreter::objects::{Key, Map};
+use std::collections::HashMap;
+use std::ffi::c_char;
+use std::sync::Arc;
+
+/// A list under construction, bound to a context by `cel_list_builder_finish`
+#[derive(Debug, Default)]
+pub struct CelListBuilder {
+    items: Vec<CelRustValue>,
+}
+
+/// A map under construction, bound to a context by `cel_map_builder_finish`
+#[derive(Debug, Default)]
+pub struct CelMapBuilder {
+    entries: HashMap<Key, CelRustValue>,
+}
+
+impl CelListBuilder {
+    fn into_value(self) -> CelRustValue {
+        CelRustValue::List(Arc::new(self.items))
+    }
+}
+
+impl CelMapBuilder {
+    fn into_value(self) -> CelRustValue {
+        CelRustValue::Map(Map {
+            map: Arc::new(self.entries),
+        })
+    }
+}
+
+/// The map key a `CelValue` stands for
+unsafe fn map_key(key: &CelValue) -> Result<Key, String> {
+    match (key.value_type, rust_value(key)?) {
+        (CelValueType::Int, CelRustValue::Int(i)) => Ok(Key::Int(i)),
+        (CelValueType::Uint, CelRustValue::UInt(u)) => Ok(Key::Uint(u)),
+        (CelValueType::Bool, CelRustValue::Bool(b)) => Ok(Key::Bool(b)),
+        (CelValueType::String, CelRustValue::String(s)) => Ok(Key::String(s)),
+        _ => Err(format!("{:?} values cannot be map keys", key.value_type)),
+    }
+}
+
+/// Bind a finished collection, whose builder has already been consumed
+unsafe fn finish(
+    value: Option<CelRustValue>,
+    context: *mut Context,
+    name: *const c_char,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    let Some(errbuf_len) = errbuf_len.as_mut() else {
+        return report(CelErrorCode::NullArgument);
+    };
+    let Some(value) = value else {
+        return fail(CelErrorCode::NullArgument, "Builder is null", errbuf, errbuf_len);
+    };
+    match variable_target(context, name) {
+        Ok((context, name)) => {
+            context.add_value_variable(name, value);
+            report(CelErrorCode::Ok)
+        }
+        Err((code, e)) => fail(code, &e, errbuf, errbuf_len),
+    }
+}
+
+/// Insert a value, `None` if it was null, or fail with the reason it could not be converted
+unsafe fn insert(
+    builder: *mut CelMapBuilder,
+    key: *const CelValue,
+    value: Option<Result<CelRustValue, String>>,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    let Some(errbuf_len) = errbuf_len.as_mut() else {
+        return report(CelErrorCode::NullArgument);
+    };
+    let (Some(builder), Some(key), Some(value)) = (builder.as_mut(), key.as_ref(), value) else {
+        return fail(
+            CelErrorCode::NullArgument,
+            "Builder, key or value is null",
+            errbuf,
+            errbuf_len,
+        );
+    };
+
+    match value.and_then(|value| Ok((map_key(key)?, value))) {
+        Ok((key, value)) => {
+            builder.entries.insert(key, value);
+            report(CelErrorCode::Ok)
+        }
+        Err(e) => fail(CelErrorCode::InvalidArgument, &e, errbuf, errbuf_len),
+    }
+}
+
+/// Start an empty list
+///
+/// Add items with the `cel_list_builder_push*` functions, then bind it with
+/// `cel_list_builder_finish` or discard it with `cel_list_builder_free`.
+#[no_mangle]
+pub extern "C" fn cel_list_builder_new() -> *mut CelListBuilder {
+    contain(std::ptr::null_mut(), || Box::into_raw(Box::default()))
+}
+
+/// Append a copy of a scalar, string, bytes, timestamp or duration value
+///
+/// Optionals are appended as their value, or null when empty. Returns false with
+/// `cel_last_error` set to `InvalidArgument` if the value cannot be converted, or to
+/// `NullArgument` if `builder`, `value` or `errbuf_len` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `builder` is either null or a valid pointer returned by `cel_list_builder_new`
+/// - `value` is either null or a valid pointer to a `CelValue`
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn cel_list_builder_push(
+    builder: *mut CelListBuilder,
+    value: *const CelValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let (Some(builder), Some(value)) = (builder.as_mut(), value.as_ref()) else {
+            return fail(
+                CelErrorCode::NullArgument,
+                "Builder or value is null",
+                errbuf,
+                errbuf_len,
+            );
+        };
+
+        match rust_value(value) {
+            Ok(value) => {
+                builder.items.push(value);
+                report(CelErrorCode::Ok)
+            }
+            Err(e) => fail(CelErrorCode::InvalidArgument, &e, errbuf, errbuf_len),
+        }
+    })
+}
+
+/// Append a finished list, consuming `child`
+///
+/// Returns false with `cel_last_error` set to `NullArgument` if either builder is
+/// null; a non-null `child` is consumed even then.
+///
+/// # Safety
+/// The caller must ensure that `builder` and `child` are either null or valid
+/// pointers returned by `cel_list_builder_new`, and are different builders
+#[no_mangle]
+pub unsafe extern "C" fn cel_list_builder_push_list(
+    builder: *mut CelListBuilder,
+    child: *mut CelListBuilder,
+) -> bool {
+    contain(false, || {
+        let child = (!child.is_null()).then(|| Box::from_raw(child));
+        let (Some(builder), Some(child)) = (builder.as_mut(), child) else {
+            return report(CelErrorCode::NullArgument);
+        };
+        builder.items.push(child.into_value());
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Append a finished map, consuming `child`
+///
+/// Returns false with `cel_last_error` set to `NullArgument` if either builder is
+/// null; a non-null `child` is consumed even then.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `builder` is either null or a valid pointer returned by `cel_list_builder_new`
+/// - `child` is either null or a valid pointer returned by `cel_map_builder_new`
+#[no_mangle]
+pub unsafe extern "C" fn cel_list_builder_push_map(
+    builder: *mut CelListBuilder,
+    child: *mut CelMapBuilder,
+) -> bool {
+    contain(false, || {
+        let child = (!child.is_null()).then(|| Box::from_raw(child));
+        let (Some(builder), Some(child)) = (builder.as_mut(), child) else {
+            return report(CelErrorCode::NullArgument);
+        };
+        builder.items.push(child.into_value());
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Bind the list to `name` in the context, consuming the builder
+///
+/// The builder is freed even when binding fails. Returns false with `cel_last_error`
+/// set to `NullArgument` if `context`, `name` or `errbuf_len` is null, to
+/// `InvalidArgument` if `name` is not UTF-8, or to `Frozen` if the context is frozen.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `builder` is either null or a valid pointer returned by `cel_list_builder_new`
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `name` is either null or a valid null-terminated C string
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn cel_list_builder_finish(
+    builder: *mut CelListBuilder,
+    context: *mut Context,
+    name: *const c_char,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let value = (!builder.is_null()).then(|| Box::from_raw(builder).into_value());
+        finish(value, context, name, errbuf, errbuf_len)
+    })
+}
+
+/// Discard an unfinished list
+///
+/// # Safety
+/// The caller must ensure that `builder` is either null or a valid pointer returned
+/// by `cel_list_builder_new` that has not been consumed or freed
+#[no_mangle]
+pub unsafe extern "C" fn cel_list_builder_free(builder: *mut CelListBuilder) {
+    contain((), || {
+        if !builder.is_null() {
+            drop(Box::from_raw(builder));
+        }
+    })
+}
+
+/// Start an empty map
+///
+/// Add entries with the `cel_map_builder_insert*` functions, then bind it with
+/// `cel_map_builder_finish` or discard it with `cel_map_builder_free`.
+#[no_mangle]
+pub extern "C" fn cel_map_builder_new() -> *mut CelMapBuilder {
+    contain(std::ptr::null_mut(), || Box::into_raw(Box::default()))
+}
+
+/// Insert a copy of a value under a copy of `key`, replacing any previous entry
+///
+/// Keys must be `Int`, `Uint`, `Bool` or `String`, and values are converted as for
+/// `cel_list_builder_push`. Returns false with `cel_last_error` set to
+/// `InvalidArgument` if either cannot be converted, or to `NullArgument` if
+/// `builder`, `key`, `value` or `errbuf_len` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `builder` is either null or a valid pointer returned by `cel_map_builder_new`
+/// - `key` and `value` are either null or valid pointers to `CelValue`s
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn cel_map_builder_insert(
+    builder: *mut CelMapBuilder,
+    key: *const CelValue,
+    value: *const CelValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let value = value.as_ref().map(|value| rust_value(value));
+        insert(builder, key, value, errbuf, errbuf_len)
+    })
+}
+
+/// Insert a finished list under a copy of `key`, consuming `child`
+///
+/// Returns false as `cel_map_builder_insert` does; a non-null `child` is consumed
+/// even then.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `builder` is either null or a valid pointer returned by `cel_map_builder_new`
+/// - `key` is either null or a valid pointer to a `CelValue`
+/// - `child` is either null or a valid pointer returned by `cel_list_builder_new`
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn cel_map_builder_insert_list(
+    builder: *mut CelMapBuilder,
+    key: *const CelValue,
+    child: *mut CelListBuilder,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let value = (!child.is_null()).then(|| Ok(Box::from_raw(child).into_value()));
+        insert(builder, key, value, errbuf, errbuf_len)
+    })
+}
+
+/// Insert a finished map under a copy of `key`, consuming `child`
+///
+/// Returns false as `cel_map_builder_insert` does; a non-null `child` is consumed
+/// even then.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `builder` and `child` are either null or valid pointers returned by
+///   `cel_map_builder_new`, and are different builders
+/// - `key` is either null or a valid pointer to a `CelValue`
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn cel_map_builder_insert_map(
+    builder: *mut CelMapBuilder,
+    key: *const CelValue,
+    child: *mut CelMapBuilder,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let value = (!child.is_null()).then(|| Ok(Box::from_raw(child).into_value()));
+        insert(builder, key, value, errbuf, errbuf_len)
+    })
+}
+
+/// Bind the map to `name` in the context, consuming the builder
+///
+/// Behaves as `cel_list_builder_finish` does.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `builder` is either null or a valid pointer returned by `cel_map_builder_new`
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `name` is either null or a valid null-terminated C string
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn cel_map_builder_finish(
+    builder: *mut CelMapBuilder,
+    context: *mut Context,
+    name: *const c_char,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let value = (!builder.is_null()).then(|| Box::from_raw(builder).into_value());
+        finish(value, context, name, errbuf, errbuf_len)
+    })
+}
+
+/// Discard an unfinished map
+///
+/// # Safety
+/// The caller must ensure that `builder` is either null or a valid pointer returned
+/// by `cel_map_builder_new` that has not been consumed or freed
+#[no_mangle]
+pub unsafe extern "C" fn cel_map_builder_free(builder: *mut CelMapBuilder) {
+    contain((), || {
+        if !builder.is_null() {
+            drop(Box::from_raw(builder));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context_free, context_freeze, context_new, CelStringValue, CelValueData};
+    use std::ffi::CString;
+    use std::mem::ManuallyDrop;
+
+    fn int(i: i64) -> CelValue {
+        CelValue {
+            value_type: CelValueType::Int,
+            data: CelValueData { int_val: i },
+        }
+    }
+
+    fn string(s: &str) -> CelValue {
+        CelValue {
+            value_type: CelValueType::String,
+            data: CelValueData {
+                string_val: ManuallyDrop::new(CelStringValue {
+                    ptr: s.as_ptr(),
+                    len: s.len(),
+                }),
+            },
+        }
+    }
+
+    #[test]
+    fn test_builders_bind_nested_collections() {
+        let context = context_new();
+        let name = CString::new("data").unwrap();
+        let mut error_buf = [0u8; 64];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            let items = cel_list_builder_new();
+            for i in 0..1000 {
+                assert!(cel_list_builder_push(
+                    items,
+                    &int(i),
+                    error_buf.as_mut_ptr(),
+                    &mut error_len,
+                ));
+            }
+            let nested = cel_list_builder_new();
+            assert!(cel_list_builder_push(
+                nested,
+                &string("x"),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert!(cel_list_builder_push_list(items, nested));
+
+            let map = cel_map_builder_new();
+            assert!(cel_map_builder_insert_list(
+                map,
+                &string("items"),
+                items,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert!(cel_map_builder_insert(
+                map,
+                &int(7),
+                &string("seven"),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert!(cel_map_builder_insert_map(
+                map,
+                &string("empty"),
+                cel_map_builder_new(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert!(cel_map_builder_finish(
+                map,
+                context,
+                name.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+
+            let mut program = crate::Program::new();
+            program
+                .compile(
+                    "size(data.items) == 1001 && data.items[999] == 998 \
+                     && data.items[1000][0] == 'x' && data[7] == 'seven' && size(data.empty) == 0",
+                )
+                .unwrap();
+            assert_eq!(program.execute(&*context).unwrap(), CelRustValue::Bool(true));
+
+            context_free(context);
+        }
+    }
+
+    #[test]
+    fn test_builder_errors() {
+        let context = context_new();
+        let name = CString::new("data").unwrap();
+        let mut error_buf = [0u8; 64];
+        let mut error_len = error_buf.len();
+        let key = CelValue {
+            value_type: CelValueType::Double,
+            data: CelValueData { double_val: 1.5 },
+        };
+
+        unsafe {
+            let map = cel_map_builder_new();
+            assert!(!cel_map_builder_insert(
+                map,
+                &key,
+                &int(1),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+            assert_eq!(error_buf.get(..error_len).unwrap(), b"Double values cannot be map keys");
+            cel_map_builder_free(map);
+
+            error_len = error_buf.len();
+            assert!(!cel_list_builder_push(
+                std::ptr::null_mut(),
+                &int(1),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+            assert!(!cel_list_builder_push_list(std::ptr::null_mut(), cel_list_builder_new(),));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+
+            context_freeze(context);
+            error_len = error_buf.len();
+            assert!(!cel_list_builder_finish(
+                cel_list_builder_new(),
+                context,
+                name.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::Frozen);
+            assert_eq!(error_buf.get(..error_len).unwrap(), b"Context is frozen");
+
+            context_free(context);
+        }
+    }
+}
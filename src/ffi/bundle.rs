@@ -0,0 +1,575 @@
+//! Rule bundles loaded from a manifest file, a directory or memory
+
+use super::context::raw_bytes;
+use super::error::{contain, contain_with_error, report};
+#[cfg(not(target_family = "wasm"))]
+use super::flat::failure;
+use super::program::{compile_options, fail, owned_library_string};
+use super::result::CelEvalResult;
+use super::ruleset::{execute_all, first_match};
+use super::{CelCompileOptions, CelErrorCode, CelStringValue, Context, RuleSet};
+pub use crate::api::Bundle;
+#[cfg(not(target_family = "wasm"))]
+use crate::api::{self, BundleSource};
+use std::ffi::{c_char, CStr};
+use std::path::Path;
+#[cfg(not(target_family = "wasm"))]
+use std::thread::JoinHandle;
+
+/// Load and compile a bundle from a manifest file or a directory of `.cel` files
+///
+/// A manifest is a JSON object of rule names to expressions, a JSON array of
+/// `{"name", "expression"}` objects, or an archive in which each rule starts with a
+/// `=== name` line. A null `options` gives the defaults. Rules that fail to compile
+/// are left out and listed by `cel_bundle_errors_json`. Returns null with
+/// `cel_last_error` set to `InvalidArgument` if the path cannot be read, the manifest
+/// is malformed or two rules share a name, or to `NullArgument` if `path` or
+/// `errbuf_len` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `path` is either null or a valid null-terminated C string
+/// - `options` is either null or a valid pointer to a `CelCompileOptions`
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn cel_bundle_load(
+    path: *const c_char,
+    options: *const CelCompileOptions,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> *mut Bundle {
+    contain_with_error(std::ptr::null_mut(), errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            report(CelErrorCode::NullArgument);
+            return std::ptr::null_mut();
+        };
+        if path.is_null() {
+            fail(CelErrorCode::NullArgument, "Path is null", errbuf, errbuf_len);
+            return std::ptr::null_mut();
+        }
+        let Ok(path) = CStr::from_ptr(path).to_str() else {
+            fail(CelErrorCode::InvalidArgument, "Invalid UTF-8 in path", errbuf, errbuf_len);
+            return std::ptr::null_mut();
+        };
+
+        load(options, errbuf, errbuf_len, |options| Bundle::load(Path::new(path), options))
+    })
+}
+
+/// Load and compile a bundle from a manifest of `len` bytes held in memory
+///
+/// The manifest is JSON if it starts with `{` or `[`, and an archive otherwise.
+/// Fails like `cel_bundle_load`, with `NullArgument` for a null `data` of nonzero
+/// length.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `data` is either null or points to `len` readable bytes
+/// - `options`, `errbuf` and `errbuf_len` are as for `cel_bundle_load`
+#[no_mangle]
+pub unsafe extern "C" fn cel_bundle_load_bytes(
+    data: *const u8,
+    len: usize,
+    options: *const CelCompileOptions,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> *mut Bundle {
+    contain_with_error(std::ptr::null_mut(), errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            report(CelErrorCode::NullArgument);
+            return std::ptr::null_mut();
+        };
+        if data.is_null() && len > 0 {
+            fail(CelErrorCode::NullArgument, "Bundle data is null", errbuf, errbuf_len);
+            return std::ptr::null_mut();
+        }
+        let data = raw_bytes(data, len);
+
+        load(options, errbuf, errbuf_len, |options| Bundle::from_bytes(data, options))
+    })
+}
+
+/// Free a bundle
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `bundle` is either null or a valid pointer returned by `cel_bundle_load` or
+///   `cel_bundle_load_bytes`
+/// - `bundle` has not been previously freed
+#[no_mangle]
+pub unsafe extern "C" fn cel_bundle_free(bundle: *mut Bundle) {
+    contain((), || {
+        if !bundle.is_null() {
+            drop(Box::from_raw(bundle));
+        }
+    })
+}
+
+/// Execute every rule of the bundle into a result as `ruleset_execute_all` does
+///
+/// The result fails with `NullArgument` if `bundle` or `context` is null, and is
+/// released with `cel_result_free`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `bundle` is either null or a valid pointer returned by `cel_bundle_load` or
+///   `cel_bundle_load_bytes`
+/// - `context` is either null or a valid pointer returned by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn cel_bundle_execute_all(
+    bundle: *const Bundle,
+    context: *const Context,
+) -> *mut CelEvalResult {
+    contain(std::ptr::null_mut(), || {
+        execute_rules(bundle, context, |rules, context| execute_all(rules, context))
+    })
+}
+
+/// Evaluate the bundle's rules into a result as `ruleset_first_match` does
+///
+/// The result fails with `NullArgument` if `bundle` or `context` is null, and is
+/// released with `cel_result_free`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `bundle` is either null or a valid pointer returned by `cel_bundle_load` or
+///   `cel_bundle_load_bytes`
+/// - `context` is either null or a valid pointer returned by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn cel_bundle_first_match(
+    bundle: *const Bundle,
+    context: *const Context,
+) -> *mut CelEvalResult {
+    contain(std::ptr::null_mut(), || {
+        execute_rules(bundle, context, |rules, context| first_match(rules, context))
+    })
+}
+
+/// Number of the bundle's current rules: 1 as loaded, counting up with each reload
+///
+/// Returns 0 for a null bundle.
+///
+/// # Safety
+/// The caller must ensure that `bundle` is either null or a valid pointer returned by
+/// `cel_bundle_load` or `cel_bundle_load_bytes`
+#[no_mangle]
+pub unsafe extern "C" fn cel_bundle_version(bundle: *const Bundle) -> u64 {
+    contain(0, || bundle.as_ref().map_or(0, |bundle| bundle.current().number()))
+}
+
+/// The rules that failed to compile, as a JSON object mapping names to errors
+///
+/// The object is empty if every rule compiled, as it always is after a reload.
+/// Returns false with `cel_last_error` set to `NullArgument` if `bundle` or
+/// `out_json` is null.
+///
+/// # Ownership
+/// `out_json.ptr` is a caller-owned library string; drop it with `cel_string_release`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `bundle` is either null or a valid pointer returned by `cel_bundle_load` or
+///   `cel_bundle_load_bytes`
+/// - `out_json` is either null or a valid pointer to a `CelStringValue` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn cel_bundle_errors_json(
+    bundle: *const Bundle,
+    out_json: *mut CelStringValue,
+) -> bool {
+    contain(false, || {
+        let (Some(bundle), Some(out_json)) = (bundle.as_ref(), out_json.as_mut()) else {
+            return report(CelErrorCode::NullArgument);
+        };
+
+        let errors: serde_json::Map<String, serde_json::Value> = bundle
+            .current()
+            .errors()
+            .iter()
+            .map(|(name, e)| (name.to_string(), serde_json::Value::String(e.to_string())))
+            .collect();
+        *out_json = owned_library_string(&serde_json::Value::Object(errors).to_string());
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Execute the bundle's rule named `name` into a newly allocated result
+///
+/// The result fails with `InvalidArgument` if the bundle has no such rule or it
+/// failed to compile, with `ExecutionError` if evaluation fails, or with
+/// `NullArgument` if an argument is null, and is released with `cel_result_free`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `bundle` is either null or a valid pointer returned by `cel_bundle_load` or
+///   `cel_bundle_load_bytes`
+/// - `name` is either null or a valid null-terminated C string
+/// - `context` is either null or a valid pointer returned by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn cel_bundle_execute(
+    bundle: *const Bundle,
+    name: *const c_char,
+    context: *const Context,
+) -> *mut CelEvalResult {
+    contain(std::ptr::null_mut(), || {
+        let result = match (bundle.as_ref(), name.is_null(), context.as_ref()) {
+            (Some(bundle), false, Some(context)) => match CStr::from_ptr(name).to_str() {
+                Ok(name) => match bundle.execute(name, context) {
+                    Ok(value) => CelEvalResult::from_execution(Ok(value)),
+                    Err(crate::api::Error::InvalidArgument(e)) => {
+                        CelEvalResult::from_error(CelErrorCode::InvalidArgument, &e)
+                    }
+                    Err(e) => CelEvalResult::from_execution(Err(e.to_string())),
+                },
+                Err(_) => CelEvalResult::from_error(
+                    CelErrorCode::InvalidArgument,
+                    "Invalid UTF-8 in name",
+                ),
+            },
+            (None, _, _) => CelEvalResult::from_error(CelErrorCode::NullArgument, "Bundle is null"),
+            (_, true, _) => CelEvalResult::from_error(CelErrorCode::NullArgument, "Name is null"),
+            (_, _, None) => {
+                CelEvalResult::from_error(CelErrorCode::NullArgument, "Context is null")
+            }
+        };
+        report(result.code());
+        result.into_raw()
+    })
+}
+
+/// A reload started by `cel_bundle_reload`, collected by `cel_bundle_reload_finish`
+#[cfg(not(target_family = "wasm"))]
+#[derive(Debug)]
+pub struct CelBundleReload {
+    thread: JoinHandle<Result<u64, api::Error>>,
+}
+
+/// Reload the bundle from a manifest file or directory on a background thread
+///
+/// Evaluations keep using the current rules while the new ones compile, and those
+/// already running when they are swapped in finish against the rules they started
+/// with. If any rule fails to compile, the bundle keeps its current rules. Poll
+/// `cel_bundle_reload_is_done`, then collect the outcome with
+/// `cel_bundle_reload_finish`. The bundle may be freed before the reload finishes.
+/// Returns null with `cel_last_error` set to `NullArgument` if `bundle` or `path` is
+/// null, to `InvalidArgument` if `path` is not UTF-8 or the options are invalid, or
+/// to `Internal` if no thread could be started.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `bundle` is either null or a valid pointer returned by `cel_bundle_load` or
+///   `cel_bundle_load_bytes`
+/// - `path` is either null or a valid null-terminated C string
+/// - `options` is either null or a valid pointer to a `CelCompileOptions`
+#[cfg(not(target_family = "wasm"))]
+#[no_mangle]
+pub unsafe extern "C" fn cel_bundle_reload(
+    bundle: *const Bundle,
+    path: *const c_char,
+    options: *const CelCompileOptions,
+) -> *mut CelBundleReload {
+    contain(std::ptr::null_mut(), || {
+        if path.is_null() {
+            failure(CelErrorCode::NullArgument, "Path is null");
+            return std::ptr::null_mut();
+        }
+        let Ok(path) = CStr::from_ptr(path).to_str() else {
+            failure(CelErrorCode::InvalidArgument, "Invalid UTF-8 in path");
+            return std::ptr::null_mut();
+        };
+        start_reload(bundle, BundleSource::Path(path.into()), options)
+    })
+}
+
+/// Reload the bundle from a manifest of `len` bytes on a background thread
+///
+/// The bytes are copied before this returns. Otherwise behaves like
+/// `cel_bundle_reload`, failing with `NullArgument` for a null `data` of nonzero
+/// length.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `bundle` and `options` are as for `cel_bundle_reload`
+/// - `data` is either null or points to `len` readable bytes
+#[cfg(not(target_family = "wasm"))]
+#[no_mangle]
+pub unsafe extern "C" fn cel_bundle_reload_bytes(
+    bundle: *const Bundle,
+    data: *const u8,
+    len: usize,
+    options: *const CelCompileOptions,
+) -> *mut CelBundleReload {
+    contain(std::ptr::null_mut(), || {
+        if data.is_null() && len > 0 {
+            failure(CelErrorCode::NullArgument, "Bundle data is null");
+            return std::ptr::null_mut();
+        }
+        start_reload(bundle, BundleSource::Bytes(raw_bytes(data, len).to_vec()), options)
+    })
+}
+
+/// Whether the reload has finished, false for a null reload
+///
+/// # Safety
+/// The caller must ensure that `reload` is either null or a valid pointer returned by
+/// `cel_bundle_reload` or `cel_bundle_reload_bytes` that has not been finished
+#[cfg(not(target_family = "wasm"))]
+#[no_mangle]
+pub unsafe extern "C" fn cel_bundle_reload_is_done(reload: *const CelBundleReload) -> bool {
+    contain(false, || reload.as_ref().is_some_and(|reload| reload.thread.is_finished()))
+}
+
+/// Wait for the reload to finish and report whether it swapped in new rules
+///
+/// Consumes `reload`. Returns false with `cel_last_error` set to `InvalidArgument`
+/// if the manifest could not be read or a rule failed to compile, describing each
+/// failing rule on its own line as `rule 'name': error`, or to `NullArgument` if
+/// `reload` or `errbuf_len` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `reload` is either null or a valid pointer returned by `cel_bundle_reload` or
+///   `cel_bundle_reload_bytes` that has not been finished
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[cfg(not(target_family = "wasm"))]
+#[no_mangle]
+pub unsafe extern "C" fn cel_bundle_reload_finish(
+    reload: *mut CelBundleReload,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        if reload.is_null() {
+            return report(CelErrorCode::NullArgument);
+        }
+        let outcome = Box::from_raw(reload).thread.join();
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        match outcome {
+            Ok(Ok(_)) => report(CelErrorCode::Ok),
+            Ok(Err(e)) => fail(CelErrorCode::InvalidArgument, &e.to_string(), errbuf, errbuf_len),
+            Err(_) => fail(
+                CelErrorCode::Internal,
+                "Internal error: the reload thread panicked",
+                errbuf,
+                errbuf_len,
+            ),
+        }
+    })
+}
+
+/// Start reloading `bundle` from `source` on a new thread
+#[cfg(not(target_family = "wasm"))]
+unsafe fn start_reload(
+    bundle: *const Bundle,
+    source: BundleSource,
+    options: *const CelCompileOptions,
+) -> *mut CelBundleReload {
+    let Some(bundle) = bundle.as_ref() else {
+        failure(CelErrorCode::NullArgument, "Bundle is null");
+        return std::ptr::null_mut();
+    };
+    let options = match compile_options(options) {
+        Ok(options) => options,
+        Err((code, e)) => {
+            failure(code, &e);
+            return std::ptr::null_mut();
+        }
+    };
+    match bundle.reload_in_background(source, options) {
+        Ok(thread) => {
+            report(CelErrorCode::Ok);
+            Box::into_raw(Box::new(CelBundleReload { thread }))
+        }
+        Err(e) => {
+            failure(CelErrorCode::Internal, &format!("Cannot start the reload thread: {e}"));
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// The current rules of `bundle` evaluated against `context` by `execute`
+unsafe fn execute_rules(
+    bundle: *const Bundle,
+    context: *const Context,
+    execute: impl FnOnce(&RuleSet, &Context) -> CelEvalResult,
+) -> *mut CelEvalResult {
+    let result = match (bundle.as_ref(), context.as_ref()) {
+        (Some(bundle), Some(context)) => execute(bundle.current().rules(), context),
+        (None, _) => CelEvalResult::from_error(CelErrorCode::NullArgument, "Bundle is null"),
+        (_, None) => CelEvalResult::from_error(CelErrorCode::NullArgument, "Context is null"),
+    };
+    report(result.code());
+    result.into_raw()
+}
+
+/// Resolve the options and run `load`, reporting its failure in `errbuf`
+unsafe fn load(
+    options: *const CelCompileOptions,
+    errbuf: *mut u8,
+    errbuf_len: &mut usize,
+    load: impl FnOnce(crate::api::CompileOptions) -> Result<Bundle, crate::api::Error>,
+) -> *mut Bundle {
+    let bundle = compile_options(options).and_then(|options| {
+        load(options).map_err(|e| (CelErrorCode::InvalidArgument, e.to_string()))
+    });
+    match bundle {
+        Ok(bundle) => {
+            report(CelErrorCode::Ok);
+            Box::into_raw(Box::new(bundle))
+        }
+        Err((code, e)) => {
+            fail(code, &e, errbuf, errbuf_len);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cel_last_error, cel_result_free, cel_result_is_ok, cel_result_value};
+    use crate::{cel_string_release, context_free, context_new, CelValueType};
+    use std::ffi::CString;
+
+    const MANIFEST: &str = r#"[
+        {"name": "sum", "expression": "1 + 2"},
+        {"name": "broken", "expression": "1 + + "}
+    ]"#;
+
+    unsafe fn load_bytes(manifest: &str) -> *mut Bundle {
+        let mut errbuf = [0u8; 256];
+        let mut errbuf_len = errbuf.len();
+        cel_bundle_load_bytes(
+            manifest.as_ptr(),
+            manifest.len(),
+            std::ptr::null(),
+            errbuf.as_mut_ptr(),
+            &mut errbuf_len,
+        )
+    }
+
+    #[test]
+    fn test_bundle_load_bytes() {
+        unsafe {
+            let bundle = load_bytes(MANIFEST);
+            assert!(!bundle.is_null());
+            let context = context_new();
+
+            let mut json = CelStringValue {
+                ptr: std::ptr::null(),
+                len: 0,
+            };
+            assert!(cel_bundle_errors_json(bundle, &mut json));
+            let errors = std::str::from_utf8(std::slice::from_raw_parts(json.ptr, json.len));
+            assert!(errors.unwrap().starts_with(r#"{"broken":"Compilation error"#));
+            cel_string_release(json.ptr);
+
+            let sum = CString::new("sum").unwrap();
+            let result = cel_bundle_execute(bundle, sum.as_ptr(), context);
+            assert!(cel_result_is_ok(result));
+            assert_eq!((*cel_result_value(result)).data.int_val, 3);
+            cel_result_free(result);
+
+            let broken = CString::new("broken").unwrap();
+            let result = cel_bundle_execute(bundle, broken.as_ptr(), context);
+            assert_eq!(cel_last_error(), CelErrorCode::InvalidArgument);
+            cel_result_free(result);
+            let result = cel_bundle_execute(bundle, std::ptr::null(), context);
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+            cel_result_free(result);
+
+            let result = cel_bundle_execute_all(bundle, context);
+            assert!(cel_result_is_ok(result));
+            let value = &*cel_result_value(result);
+            assert_eq!(value.value_type, CelValueType::Map);
+            assert_eq!(value.data.map_val.len, 1);
+            cel_result_free(result);
+
+            cel_bundle_free(bundle);
+            context_free(context);
+        }
+    }
+
+    #[test]
+    fn test_bundle_load_failures() {
+        unsafe {
+            assert!(load_bytes("{").is_null());
+            assert_eq!(cel_last_error(), CelErrorCode::InvalidArgument);
+
+            let mut errbuf = [0u8; 256];
+            let mut errbuf_len = errbuf.len();
+            let path = CString::new("/nonexistent/bundle.json").unwrap();
+            let bundle = cel_bundle_load(
+                path.as_ptr(),
+                std::ptr::null(),
+                errbuf.as_mut_ptr(),
+                &mut errbuf_len,
+            );
+            assert!(bundle.is_null());
+            assert_eq!(cel_last_error(), CelErrorCode::InvalidArgument);
+            assert!(std::str::from_utf8(&errbuf[..errbuf_len]).unwrap().starts_with("cannot read"));
+
+            let mut errbuf_len = errbuf.len();
+            let bundle = cel_bundle_load(
+                std::ptr::null(),
+                std::ptr::null(),
+                errbuf.as_mut_ptr(),
+                &mut errbuf_len,
+            );
+            assert!(bundle.is_null());
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+            assert_eq!(cel_bundle_version(std::ptr::null()), 0);
+        }
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[test]
+    fn test_bundle_reload() {
+        unsafe {
+            let bundle = load_bytes("=== route\n'old'");
+            let context = context_new();
+            assert_eq!(cel_bundle_version(bundle), 1);
+            let mut errbuf = [0u8; 256];
+
+            let update = "=== route\n'new'\n=== fallback\n'cdn'";
+            let reload =
+                cel_bundle_reload_bytes(bundle, update.as_ptr(), update.len(), std::ptr::null());
+            assert!(!reload.is_null());
+            while !cel_bundle_reload_is_done(reload) {
+                std::thread::yield_now();
+            }
+            let mut errbuf_len = errbuf.len();
+            assert!(cel_bundle_reload_finish(reload, errbuf.as_mut_ptr(), &mut errbuf_len));
+            assert_eq!(cel_bundle_version(bundle), 2);
+
+            let result = cel_bundle_first_match(bundle, context);
+            assert!(cel_result_is_ok(result));
+            assert_eq!((*cel_result_value(result)).value_type, CelValueType::Null);
+            cel_result_free(result);
+
+            let broken = "=== route\n1 +";
+            let reload =
+                cel_bundle_reload_bytes(bundle, broken.as_ptr(), broken.len(), std::ptr::null());
+            let mut errbuf_len = errbuf.len();
+            assert!(!cel_bundle_reload_finish(reload, errbuf.as_mut_ptr(), &mut errbuf_len));
+            assert_eq!(cel_last_error(), CelErrorCode::InvalidArgument);
+            assert!(std::str::from_utf8(&errbuf[..errbuf_len])
+                .unwrap()
+                .starts_with("rule 'route'"));
+            assert_eq!(cel_bundle_version(bundle), 2);
+
+            let path = CString::new("/nonexistent/bundle.json").unwrap();
+            let reload = cel_bundle_reload(bundle, path.as_ptr(), std::ptr::null());
+            cel_bundle_free(bundle);
+            let mut errbuf_len = errbuf.len();
+            assert!(!cel_bundle_reload_finish(reload, errbuf.as_mut_ptr(), &mut errbuf_len));
+            assert!(cel_bundle_reload(std::ptr::null(), path.as_ptr(), std::ptr::null()).is_null());
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+
+            context_free(context);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Tokens for cancelling evaluations from another thread, such as when a worker
+//! pool shuts down
+
+use super::error::{contain, record_error_message, report};
+use super::program::Program;
+use super::result::CelEvalResult;
+use super::{CelErrorCode, Context};
+use crate::api::CancelToken;
+use std::time::Duration;
+
+/// A token cancelling the evaluations it is passed to, created by `cel_cancel_token_new`
+#[derive(Debug, Default)]
+pub struct CelCancelToken {
+    token: CancelToken,
+}
+
+impl CelCancelToken {
+    /// The shared flag, for evaluations that outlive the caller's borrow
+    pub(crate) fn token(&self) -> CancelToken {
+        self.token.clone()
+    }
+}
+
+/// Create a token that has not been cancelled
+///
+/// Pass it to `program_execute_cancellable` or `program_execute_start`, then call
+/// `cel_cancel` from any thread to stop those evaluations. Release it with
+/// `cel_cancel_token_free`.
+#[no_mangle]
+pub extern "C" fn cel_cancel_token_new() -> *mut CelCancelToken {
+    contain(std::ptr::null_mut(), || Box::into_raw(Box::default()))
+}
+
+/// Cancel every evaluation using `token`, including those started later
+///
+/// Evaluations stop at their next check, before evaluation starts or on the next
+/// comprehension iteration, and fail with `Cancelled`. Safe to call from any thread
+/// while the token is in use. Returns false with `cel_last_error` set to `NullArgument`
+/// for a null token.
+///
+/// # Safety
+/// The caller must ensure that `token` is either null or a valid pointer returned by
+/// `cel_cancel_token_new` that has not been freed
+#[no_mangle]
+pub unsafe extern "C" fn cel_cancel(token: *const CelCancelToken) -> bool {
+    contain(false, || match token.as_ref() {
+        Some(token) => {
+            token.token.cancel();
+            report(CelErrorCode::Ok)
+        }
+        None => report(CelErrorCode::NullArgument),
+    })
+}
+
+/// Cancel `token` once `timeout_ms` milliseconds have passed
+///
+/// Replaces any earlier deadline. Evaluations notice the deadline at the same checks
+/// as `cel_cancel` and fail with `Cancelled`. Returns false with `cel_last_error` set
+/// to `NullArgument` for a null token, or to `InvalidArgument` on targets without a
+/// clock, such as `wasm32-unknown-unknown`.
+///
+/// # Safety
+/// The caller must ensure that `token` is either null or a valid pointer returned by
+/// `cel_cancel_token_new` that has not been freed
+#[no_mangle]
+pub unsafe extern "C" fn cel_cancel_after(token: *const CelCancelToken, timeout_ms: u64) -> bool {
+    contain(false, || match token.as_ref() {
+        Some(_) if cfg!(all(target_family = "wasm", target_os = "unknown")) => {
+            record_error_message("Deadlines need a clock, which this target does not have");
+            report(CelErrorCode::InvalidArgument)
+        }
+        Some(token) => {
+            token.token.cancel_after(Duration::from_millis(timeout_ms));
+            report(CelErrorCode::Ok)
+        }
+        None => report(CelErrorCode::NullArgument),
+    })
+}
+
+/// Limit the evaluations using `token` to `cost` checks in total
+///
+/// Each evaluation of a comprehension's predicate or transform costs one. Once the
+/// budget is spent, evaluations fail with `ExecutionError`. The budget is shared by
+/// every evaluation using the token. Returns false with `cel_last_error` set to
+/// `NullArgument` for a null token.
+///
+/// # Safety
+/// The caller must ensure that `token` is either null or a valid pointer returned by
+/// `cel_cancel_token_new` that has not been freed
+#[no_mangle]
+pub unsafe extern "C" fn cel_cancel_token_set_budget(
+    token: *const CelCancelToken,
+    cost: u64,
+) -> bool {
+    contain(false, || match token.as_ref() {
+        Some(token) => {
+            token.token.set_budget(cost);
+            report(CelErrorCode::Ok)
+        }
+        None => report(CelErrorCode::NullArgument),
+    })
+}
+
+/// Release a token
+///
+/// Executions started by `program_execute_start` keep their own reference, so the
+/// token may be freed before they finish.
+///
+/// # Safety
+/// The caller must ensure that `token` is either null or a valid pointer returned by
+/// `cel_cancel_token_new` that has not been freed, and that no other thread is using it
+#[no_mangle]
+pub unsafe extern "C" fn cel_cancel_token_free(token: *mut CelCancelToken) {
+    contain((), || {
+        if !token.is_null() {
+            drop(Box::from_raw(token));
+        }
+    })
+}
+
+/// Execute into a newly allocated result, stopping early once `token` is cancelled
+///
+/// The result behaves as for `program_execute_alloc`, with the `Cancelled` code if the
+/// token was cancelled before evaluation finished. The token is checked before
+/// evaluation and on every comprehension iteration. With a null token this is
+/// `program_execute_alloc`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `token` is either null or a valid pointer returned by `cel_cancel_token_new`
+#[no_mangle]
+pub unsafe extern "C" fn program_execute_cancellable(
+    program: *const Program,
+    context: *const Context,
+    token: *const CelCancelToken,
+) -> *mut CelEvalResult {
+    contain(std::ptr::null_mut(), || {
+        let result = match (program.as_ref(), context.as_ref()) {
+            (Some(program), Some(context)) => {
+                let token = token.as_ref().map(|token| &token.token);
+                CelEvalResult::from_cancellable(program.execute_cancellable(context, token))
+            }
+            (None, _) => CelEvalResult::from_error(CelErrorCode::NullArgument, "Program is null"),
+            (_, None) => CelEvalResult::from_error(CelErrorCode::NullArgument, "Context is null"),
+        };
+        report(result.code());
+        result.into_raw()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cel_result_free, cel_result_is_ok, context_free, context_new};
+    use crate::{program_free, program_new};
+
+    #[test]
+    fn test_cancel_token() {
+        let program = program_new();
+        let context = context_new();
+        let token = cel_cancel_token_new();
+
+        unsafe {
+            (*program).compile("[1, 2, 3].all(x, x > 0)").unwrap();
+            let result = program_execute_cancellable(program, context, token);
+            assert!(cel_result_is_ok(result));
+            cel_result_free(result);
+
+            assert!(cel_cancel(token));
+            let result = program_execute_cancellable(program, context, token);
+            assert!(!cel_result_is_ok(result));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::Cancelled);
+            cel_result_free(result);
+
+            let result = program_execute_cancellable(program, context, std::ptr::null());
+            assert!(cel_result_is_ok(result));
+            cel_result_free(result);
+            assert!(!cel_cancel(std::ptr::null()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+
+            cel_cancel_token_free(token);
+            program_free(program);
+            context_free(context);
+        }
+    }
+
+    #[test]
+    fn test_cancel_token_limits() {
+        let program = program_new();
+        let context = context_new();
+
+        unsafe {
+            (*program).compile("[1, 2, 3].all(x, x > 0)").unwrap();
+            let token = cel_cancel_token_new();
+            assert!(cel_cancel_token_set_budget(token, 2));
+            let result = program_execute_cancellable(program, context, token);
+            assert!(!cel_result_is_ok(result));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::ExecutionError);
+            cel_result_free(result);
+            cel_cancel_token_free(token);
+
+            let token = cel_cancel_token_new();
+            assert!(cel_cancel_after(token, 0));
+            let result = program_execute_cancellable(program, context, token);
+            assert_eq!(crate::cel_last_error(), CelErrorCode::Cancelled);
+            cel_result_free(result);
+            cel_cancel_token_free(token);
+
+            assert!(!cel_cancel_after(std::ptr::null(), 10));
+            assert!(!cel_cancel_token_set_budget(std::ptr::null(), 10));
+            program_free(program);
+            context_free(context);
+        }
+    }
+}
@@ -1,39 +1,235 @@
-use super::{CelValue, CelValueType};
-use std::collections::HashMap;
-use std::ffi::{c_char, CStr};
+use super::error::{contain, contain_with_error, report};
+use super::log::{log, CelLogLevel};
+use super::memory::{track, untrack, CelAllocationKind};
+use super::program::{copy_error_to_buffer, fail, owned_library_string};
+use super::value::{exact_rust_value, free_all, raw_values, tagged_rust_value, time_rust_value};
+use super::{to_owned_c_value, CelErrorCode, CelStringValue, CelValue, CelValueData, CelValueType};
+use crate::api::{
+    json_to_cel_value, Arity, AttributeAccess, Error, MissingKeys, Value as CelRustValue,
+};
+pub use crate::api::{Context, ContextSnapshot};
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::mem::size_of;
+use std::sync::Arc;
+
+/// Callback resolving a variable that the context does not bind
+///
+/// It receives the `user_data` it was registered with and the variable name, and
+/// returns true after writing the value to `out_value`, or false to leave the
+/// variable unbound. Memory referenced by `out_value` only has to stay valid until
+/// the callback returns, since the library copies the value.
+pub type CelVariableResolver = unsafe extern "C" fn(
+    user_data: *mut c_void,
+    name: *const c_char,
+    out_value: *mut CelValue,
+) -> bool;
 
-/// Context for storing variables for CEL evaluation
-#[derive(Debug, Default)]
-pub struct Context {
-    variables: HashMap<String, serde_json::Value>,
+#[derive(Debug, Clone, Copy)]
+struct Resolver {
+    callback: CelVariableResolver,
+    user_data: *mut c_void,
 }
 
-impl Context {
-    #[must_use]
-    pub fn new() -> Self {
-        Self {
-            variables: HashMap::new(),
+// SAFETY: `context_set_resolver` requires the callback and its user data to be
+// usable from whichever thread executes a program against the context.
+unsafe impl Send for Resolver {}
+unsafe impl Sync for Resolver {}
+
+impl Resolver {
+    /// Call the callback, copying the value it writes
+    fn resolve(self, name: &str) -> Result<Option<CelRustValue>, String> {
+        let Ok(c_name) = CString::new(name) else {
+            log(CelLogLevel::Warn, || {
+                format!(
+                    "variable '{}' contains a NUL byte and cannot be resolved",
+                    name.escape_debug()
+                )
+            });
+            return Ok(None);
+        };
+
+        let mut value = CelValue {
+            value_type: CelValueType::Null,
+            data: CelValueData { int_val: 0 },
+        };
+        if !unsafe { (self.callback)(self.user_data, c_name.as_ptr(), &mut value) } {
+            return Ok(None);
         }
+
+        unsafe { rust_value(&value) }.map(Some)
     }
+}
+
+/// What a `CelAttributeHook` decides about a field selection
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CelAttributeAccess {
+    /// The field is selected as usual
+    AttributeAllow,
+    /// Evaluation fails with `ExecutionError`
+    AttributeDeny,
+    /// The selection evaluates to the value the hook wrote to `out_value`
+    AttributeReplace,
+}
+
+/// Callback deciding each field selection an expression makes
+///
+/// It receives the `user_data` it was registered with and the selection as written,
+/// such as `request.user.email`, and returns whether to allow it, deny it, or replace
+/// its value with the one it writes to `out_value`. Memory referenced by `out_value`
+/// only has to stay valid until the callback returns, since the library copies the
+/// value.
+pub type CelAttributeHook = unsafe extern "C" fn(
+    user_data: *mut c_void,
+    path: *const c_char,
+    out_value: *mut CelValue,
+) -> CelAttributeAccess;
+
+#[derive(Debug, Clone, Copy)]
+struct Hook {
+    callback: CelAttributeHook,
+    user_data: *mut c_void,
+}
+
+// SAFETY: `context_set_attribute_hook` requires the callback and its user data to be
+// usable from whichever thread executes a program against the context.
+unsafe impl Send for Hook {}
+unsafe impl Sync for Hook {}
+
+impl Hook {
+    /// Call the callback, copying the value it writes to replace the selection
+    fn decide(self, path: &str) -> AttributeAccess {
+        let denied = || AttributeAccess::Deny(format!("access to '{path}' is denied"));
+        // Selections are identifiers, so only a quoted key could hold a NUL byte
+        let Ok(c_path) = CString::new(path) else {
+            return denied();
+        };
 
-    pub fn add_variable(&mut self, name: String, value: serde_json::Value) {
-        self.variables.insert(name, value);
+        let mut value = CelValue {
+            value_type: CelValueType::Null,
+            data: CelValueData { int_val: 0 },
+        };
+        match unsafe { (self.callback)(self.user_data, c_path.as_ptr(), &mut value) } {
+            CelAttributeAccess::AttributeAllow => AttributeAccess::Allow,
+            CelAttributeAccess::AttributeDeny => denied(),
+            CelAttributeAccess::AttributeReplace => match unsafe { rust_value(&value) } {
+                Ok(value) => AttributeAccess::Replace(value),
+                Err(e) => AttributeAccess::Deny(format!("replacement for '{path}': {e}")),
+            },
+        }
     }
+}
+
+/// Callback implementing a function that expressions call
+///
+/// It receives the `user_data` it was registered with and the call's `args_len`
+/// arguments, which the library owns and frees once the callback returns. It returns
+/// true after writing the result to `out_value`, or false to fail the evaluation,
+/// optionally writing a message of at most `*errbuf_len` bytes to `errbuf` and its
+/// length to `errbuf_len`. Memory referenced by `out_value` only has to stay valid
+/// until the callback returns, since the library copies the value.
+pub type CelFunctionCallback = unsafe extern "C" fn(
+    user_data: *mut c_void,
+    args: *const CelValue,
+    args_len: usize,
+    out_value: *mut CelValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool;
+
+/// Room for the message of a failing function callback
+const FUNCTION_ERROR_MAX_LEN: usize = 256;
+
+#[derive(Debug, Clone, Copy)]
+struct Function {
+    callback: CelFunctionCallback,
+    user_data: *mut c_void,
+}
+
+// SAFETY: `context_add_function` requires the callback and its user data to be
+// usable from whichever thread executes a program against the context.
+unsafe impl Send for Function {}
+unsafe impl Sync for Function {}
+
+impl Function {
+    /// Call the callback with copies of `args`, copying the value it writes
+    fn call(self, args: &[CelRustValue]) -> Result<CelRustValue, String> {
+        let mut c_args = Vec::with_capacity(args.len());
+        for arg in args {
+            match to_owned_c_value(arg) {
+                Ok(value) => c_args.push(value),
+                Err(e) => {
+                    free_all(c_args);
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut value = CelValue {
+            value_type: CelValueType::Null,
+            data: CelValueData { int_val: 0 },
+        };
+        let mut errbuf = [0_u8; FUNCTION_ERROR_MAX_LEN];
+        let mut errbuf_len = errbuf.len();
+        let succeeded = unsafe {
+            (self.callback)(
+                self.user_data,
+                c_args.as_ptr(),
+                c_args.len(),
+                &mut value,
+                errbuf.as_mut_ptr(),
+                &mut errbuf_len,
+            )
+        };
+        free_all(c_args);
+        if !succeeded {
+            let message = String::from_utf8_lossy(&errbuf[..errbuf_len.min(errbuf.len())]);
+            if message.is_empty() {
+                return Err("function failed".to_string());
+            }
+            return Err(message.into_owned());
+        }
 
-    #[must_use]
-    pub const fn get_variables(&self) -> &HashMap<String, serde_json::Value> {
-        &self.variables
+        unsafe { exact_rust_value(&value) }
     }
+}
 
-    pub fn reset(&mut self) {
-        self.variables.clear();
+/// Copy a `CelValue`, unwrapping optionals, with lists and maps copied element by
+/// element
+///
+/// Values are converted directly rather than through JSON, so a `Uint` stays a `uint`
+/// across its whole range wherever it is nested.
+///
+/// # Safety
+/// Any pointers in `value`, however deeply nested, must be valid for their lengths
+pub(crate) unsafe fn rust_value(value: &CelValue) -> Result<CelRustValue, String> {
+    if value.value_type == CelValueType::Bytes {
+        let bytes_val = &*value.data.bytes_val;
+        if bytes_val.ptr.is_null() && bytes_val.len > 0 {
+            return Err("Bytes pointer is null".to_string());
+        }
+        let bytes = raw_bytes(bytes_val.ptr, bytes_val.len);
+        return Ok(CelRustValue::Bytes(Arc::new(bytes.to_vec())));
+    }
+    if value.value_type == CelValueType::Optional {
+        let optional = &*value.data.optional_val;
+        if optional.has_value && !optional.value.is_null() {
+            return rust_value(&*optional.value);
+        }
+        return Ok(CelRustValue::Null);
     }
+
+    exact_rust_value(value)
 }
 
 /// Create a new context instance
 #[no_mangle]
 pub extern "C" fn context_new() -> *mut Context {
-    Box::into_raw(Box::new(Context::new()))
+    contain(std::ptr::null_mut(), || {
+        let context = Box::into_raw(Box::new(Context::new()));
+        track(CelAllocationKind::AllocatedContext, context, size_of::<Context>());
+        context
+    })
 }
 
 /// Free a context instance
@@ -45,191 +241,1699 @@ pub extern "C" fn context_new() -> *mut Context {
 /// - No other references to the context exist
 #[no_mangle]
 pub unsafe extern "C" fn context_free(context: *mut Context) {
-    if !context.is_null() {
-        drop(Box::from_raw(context));
+    contain((), || {
+        if !context.is_null() {
+            untrack(CelAllocationKind::AllocatedContext, context);
+            drop(Box::from_raw(context));
+        }
+    })
+}
+
+/// Validate the context and name arguments shared by the `context_add_variable*` functions
+pub(crate) unsafe fn variable_target<'a>(
+    context: *mut Context,
+    name: *const c_char,
+) -> Result<(&'a mut Context, String), (CelErrorCode, String)> {
+    Ok((writable_context(context)?, variable_name(name)?))
+}
+
+/// The context a mutation function changes, unless it is null or frozen
+pub(crate) unsafe fn writable_context<'a>(
+    context: *mut Context,
+) -> Result<&'a mut Context, (CelErrorCode, String)> {
+    match context.as_mut() {
+        None => Err((CelErrorCode::NullArgument, "Context is null".to_string())),
+        Some(context) if context.is_frozen() => {
+            Err((CelErrorCode::Frozen, "Context is frozen".to_string()))
+        }
+        Some(context) => Ok(context),
+    }
+}
+
+unsafe fn variable_name(name: *const c_char) -> Result<String, (CelErrorCode, String)> {
+    if name.is_null() {
+        return Err((CelErrorCode::NullArgument, "Variable name is null".to_string()));
+    }
+
+    match CStr::from_ptr(name).to_str() {
+        Ok(s) => Ok(s.to_string()),
+        Err(e) => Err((CelErrorCode::InvalidArgument, format!("Invalid variable name: {e}"))),
     }
 }
 
 /// Add a variable to the context
 ///
+/// A `Uint` stays a CEL `uint` across its whole range, including values that would
+/// also fit an `int`, and so do those nested in a `List` or `Map`. A `Double` may be NaN or infinite unless the context was set to
+/// reject those with `context_set_reject_non_finite`, in which case adding one fails
+/// with `InvalidArgument`. Returns false with `cel_last_error` set to `NullArgument`
+/// if `context`, `name`, `value` or `errbuf_len` is null.
+///
 /// # Safety
 /// The caller must ensure that:
-/// - `context` is a valid mutable reference to a Context
-/// - `name` is a valid null-terminated C string
-/// - `value` is a valid reference to a `CelValue`
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `name` is either null or a valid null-terminated C string
+/// - `value` is either null or a valid pointer to a `CelValue`
 /// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
-/// - `errbuf_len` is a valid mutable reference to the buffer size
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
 #[no_mangle]
 pub unsafe extern "C" fn context_add_variable(
-    context: &mut Context,
+    context: *mut Context,
     name: *const c_char,
-    value: &CelValue,
+    value: *const CelValue,
     errbuf: *mut u8,
-    errbuf_len: &mut usize,
+    errbuf_len: *mut usize,
 ) -> bool {
-    let name_str = match CStr::from_ptr(name).to_str() {
-        Ok(s) => s.to_string(),
-        Err(e) => {
-            let error_msg = format!("Invalid variable name: {e}");
-            copy_error_to_buffer(&error_msg, errbuf, errbuf_len);
-            return false;
-        }
-    };
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let (context, name_str) = match variable_target(context, name) {
+            Ok(target) => target,
+            Err((code, e)) => {
+                copy_error_to_buffer(&e, errbuf, errbuf_len);
+                return report(code);
+            }
+        };
+        let Some(value) = value.as_ref() else {
+            copy_error_to_buffer("Variable value is null", errbuf, errbuf_len);
+            return report(CelErrorCode::NullArgument);
+        };
 
-    let json_value = match cel_value_to_json(value) {
-        Ok(v) => v,
-        Err(e) => {
-            copy_error_to_buffer(&e, errbuf, errbuf_len);
-            return false;
+        if value.value_type == CelValueType::Bytes {
+            let bytes_val = &*value.data.bytes_val;
+            if bytes_val.ptr.is_null() && bytes_val.len > 0 {
+                copy_error_to_buffer("Bytes pointer is null", errbuf, errbuf_len);
+                return report(CelErrorCode::NullArgument);
+            }
+            context.add_bytes_variable(name_str, raw_bytes(bytes_val.ptr, bytes_val.len).to_vec());
+            return report(CelErrorCode::Ok);
         }
-    };
+        if let Some(converted) = time_rust_value(value).or_else(|| tagged_rust_value(value)) {
+            return match converted {
+                Ok(converted) => {
+                    context.add_value_variable(name_str, converted);
+                    report(CelErrorCode::Ok)
+                }
+                Err(e) => {
+                    copy_error_to_buffer(&e, errbuf, errbuf_len);
+                    report(CelErrorCode::InvalidArgument)
+                }
+            };
+        }
+        // A JSON number that fits an int reads back as one, so uints skip JSON
+        if value.value_type == CelValueType::Uint {
+            context.add_value_variable(name_str, CelRustValue::UInt(value.data.uint_val));
+            return report(CelErrorCode::Ok);
+        }
+        // JSON has no NaN or infinities, so doubles skip it too
+        if value.value_type == CelValueType::Double {
+            let double_val = value.data.double_val;
+            if !double_val.is_finite() && context.reject_non_finite() {
+                let e = format!("Variable '{name_str}' is {double_val}, which the context rejects");
+                copy_error_to_buffer(&e, errbuf, errbuf_len);
+                return report(CelErrorCode::InvalidArgument);
+            }
+            context.add_value_variable(name_str, CelRustValue::Float(double_val));
+            return report(CelErrorCode::Ok);
+        }
+        // Lists and maps are copied element by element, so nested uints keep their type
+        if matches!(value.value_type, CelValueType::List | CelValueType::Map) {
+            return match rust_value(value) {
+                Ok(converted) => {
+                    context.add_value_variable(name_str, converted);
+                    report(CelErrorCode::Ok)
+                }
+                Err(e) => {
+                    copy_error_to_buffer(&e, errbuf, errbuf_len);
+                    report(CelErrorCode::InvalidArgument)
+                }
+            };
+        }
+
+        let json_value = match cel_value_to_json(value) {
+            Ok(v) => v,
+            Err(e) => {
+                copy_error_to_buffer(&e, errbuf, errbuf_len);
+                return report(CelErrorCode::InvalidArgument);
+            }
+        };
 
-    context.add_variable(name_str, json_value);
-    true
+        context.add_variable(name_str, json_value);
+        report(CelErrorCode::Ok)
+    })
 }
 
-/// Reset the context, clearing all variables
+/// Add a variable at a dotted path, building nested maps as needed
+///
+/// `context_add_variable_path(ctx, "request.headers.authorization", value, ...)` binds
+/// `request` to a map (created or extended) so the expression can reference the full
+/// dotted name. A path without dots behaves like `context_add_variable`. Returns false
+/// with `cel_last_error` set to `InvalidArgument` if a prefix of the path is bound to
+/// a non-map value.
+///
+/// A `Uint`, or a `List` or `Map`, is bound as a converted value rather than as JSON,
+/// so uints keep their type across their whole range, including those that would also
+/// fit an `int`.
 ///
 /// # Safety
-/// The caller must ensure that `context` is a valid mutable reference to a Context
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `path` is either null or a valid null-terminated C string
+/// - `value` is either null or a valid pointer to a `CelValue`
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
 #[no_mangle]
-pub unsafe extern "C" fn context_reset(context: &mut Context) {
-    context.reset();
-}
-
-fn cel_value_to_json(value: &CelValue) -> Result<serde_json::Value, String> {
-    match value.value_type {
-        CelValueType::Null => Ok(serde_json::Value::Null),
-        CelValueType::Bool => Ok(serde_json::Value::Bool(unsafe { value.data.bool_val })),
-        CelValueType::Int => Ok(serde_json::Value::Number(serde_json::Number::from(unsafe {
-            value.data.int_val
-        }))),
-        CelValueType::Uint => Ok(serde_json::Value::Number(serde_json::Number::from(unsafe {
-            value.data.uint_val
-        }))),
-        CelValueType::Double => {
-            let float_val = unsafe { value.data.double_val };
+pub unsafe extern "C" fn context_add_variable_path(
+    context: *mut Context,
+    path: *const c_char,
+    value: *const CelValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let (context, path_str) = match variable_target(context, path) {
+            Ok(target) => target,
+            Err((code, e)) => {
+                copy_error_to_buffer(&e, errbuf, errbuf_len);
+                return report(code);
+            }
+        };
+        let Some(cel_value) = value.as_ref() else {
+            copy_error_to_buffer("Variable value is null", errbuf, errbuf_len);
+            return report(CelErrorCode::NullArgument);
+        };
 
-            serde_json::Number::from_f64(float_val).map_or_else(
-                || Err(format!("Invalid float value: {float_val}")),
-                |num| Ok(serde_json::Value::Number(num)),
-            )
+        if !path_str.contains('.') {
+            return context_add_variable(context, path, value, errbuf, errbuf_len);
         }
-        CelValueType::String => {
-            let string_val = unsafe { &*value.data.string_val };
-            let slice = unsafe { std::slice::from_raw_parts(string_val.ptr, string_val.len) };
-            match std::str::from_utf8(slice) {
-                Ok(s) => Ok(serde_json::Value::String(s.to_string())),
-                Err(e) => Err(format!("Invalid UTF-8 string: {e}")),
+        if matches!(
+            cel_value.value_type,
+            CelValueType::Bytes
+                | CelValueType::Timestamp
+                | CelValueType::Duration
+                | CelValueType::BigInt
+                | CelValueType::Decimal
+                | CelValueType::Type
+        ) {
+            let error_msg = format!(
+                "{:?} values cannot be nested in variable path '{path_str}'",
+                cel_value.value_type
+            );
+            copy_error_to_buffer(&error_msg, errbuf, errbuf_len);
+            return report(CelErrorCode::InvalidArgument);
+        }
+
+        // JSON reads a uint that fits an int back as an int, so uints and the
+        // collections that may hold them are bound as values
+        let result = if matches!(
+            cel_value.value_type,
+            CelValueType::Uint | CelValueType::List | CelValueType::Map | CelValueType::Optional
+        ) {
+            rust_value(cel_value).and_then(|converted| {
+                context.add_value_variable_path(&path_str, converted).map_err(|e| e.to_string())
+            })
+        } else {
+            cel_value_to_json(cel_value).and_then(|json_value| {
+                context.add_variable_path(&path_str, json_value).map_err(|e| e.to_string())
+            })
+        };
+        match result {
+            Ok(()) => report(CelErrorCode::Ok),
+            Err(e) => {
+                copy_error_to_buffer(&e, errbuf, errbuf_len);
+                report(CelErrorCode::InvalidArgument)
             }
         }
-        _ => Err("Unsupported value type".to_string()),
-    }
+    })
 }
 
-/// Copy an error message into a provided buffer, truncating if necessary
-fn copy_error_to_buffer(error: &str, errbuf: *mut u8, errbuf_len: &mut usize) {
-    if errbuf.is_null() {
-        return;
-    }
-
-    let error_bytes = error.as_bytes();
-    let copy_len = std::cmp::min(error_bytes.len(), errbuf_len.saturating_sub(1));
-
-    unsafe {
-        std::ptr::copy_nonoverlapping(error_bytes.as_ptr(), errbuf, copy_len);
-        *errbuf.add(copy_len) = 0; // null terminator
-    }
+/// Add a string variable given as a pointer and length
+///
+/// Unlike `context_add_variable`, the data does not need to be null-terminated and
+/// may contain interior NUL bytes. Valid UTF-8 is added as a CEL `string`; any other
+/// data is added as CEL `bytes` rather than rejected. `ptr` may be null when `len` is 0.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `name` is either null or a valid null-terminated C string
+/// - `ptr` is either null or points to at least `len` readable bytes
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn context_add_variable_string_n(
+    context: *mut Context,
+    name: *const c_char,
+    ptr: *const u8,
+    len: usize,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let (context, name_str) = match variable_target(context, name) {
+            Ok(target) => target,
+            Err((code, e)) => {
+                copy_error_to_buffer(&e, errbuf, errbuf_len);
+                return report(code);
+            }
+        };
+        if ptr.is_null() && len > 0 {
+            copy_error_to_buffer("String pointer is null", errbuf, errbuf_len);
+            return report(CelErrorCode::NullArgument);
+        }
 
-    *errbuf_len = copy_len;
+        let bytes = raw_bytes(ptr, len);
+        match std::str::from_utf8(bytes) {
+            Ok(s) => context.add_variable(name_str, serde_json::Value::String(s.to_string())),
+            Err(e) => {
+                log(CelLogLevel::Info, || {
+                    format!("variable '{name_str}' is not valid UTF-8 ({e}), adding it as bytes")
+                });
+                context.add_bytes_variable(name_str, bytes.to_vec());
+            }
+        }
+        report(CelErrorCode::Ok)
+    })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{CelStringValue, CelValueData};
-    use std::mem::ManuallyDrop;
-
-    #[test]
-    fn test_context_new_and_default() {
-        let ctx1 = Context::new();
-        let ctx2 = Context::default();
+/// Add a string variable that refers to the caller's memory instead of copying it
+///
+/// The context keeps only `ptr` and `len`, so large strings such as request bodies are
+/// not held twice. Executing a program still copies the string into the evaluation,
+/// because the interpreter owns every value it works on, but that copy is freed when
+/// the execution ends. Call `context_invalidate_borrowed` with the same
+/// `lifetime_token` before the memory is freed or changed; until then it must stay
+/// valid even if the variable is replaced or removed, since restoring a snapshot can
+/// bind it again. Unlike `context_add_variable_string_n`, data that is not UTF-8 is
+/// rejected with `InvalidArgument` rather than copied as bytes.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `name` is either null or a valid null-terminated C string
+/// - `ptr` is either null or points to at least `len` bytes that stay readable and
+///   unchanged until `context_invalidate_borrowed(context, lifetime_token)` is called
+///   or the context is freed
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn context_add_variable_string_borrowed(
+    context: *mut Context,
+    name: *const c_char,
+    ptr: *const u8,
+    len: usize,
+    lifetime_token: u64,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let (context, name_str) = match variable_target(context, name) {
+            Ok(target) => target,
+            Err((code, e)) => return fail(code, &e, errbuf, errbuf_len),
+        };
+        if ptr.is_null() && len > 0 {
+            return fail(CelErrorCode::NullArgument, "String pointer is null", errbuf, errbuf_len);
+        }
 
-        assert_eq!(ctx1.variables.len(), 0);
-        assert_eq!(ctx2.variables.len(), 0);
-    }
+        match std::str::from_utf8(raw_bytes(ptr, len)) {
+            Ok(value) => {
+                context.add_borrowed_string_variable(name_str, value, lifetime_token);
+                report(CelErrorCode::Ok)
+            }
+            Err(e) => fail(
+                CelErrorCode::InvalidArgument,
+                &format!("Borrowed string '{name_str}' is not valid UTF-8: {e}"),
+                errbuf,
+                errbuf_len,
+            ),
+        }
+    })
+}
 
-    #[test]
-    fn test_context_add_variable_direct() {
-        let mut context = Context::new();
-        assert_eq!(context.variables.len(), 0);
+/// Unbind the variables borrowed under `lifetime_token`, returning how many there were
+///
+/// Afterwards the library no longer reads their memory, and restoring a snapshot
+/// leaves them unbound. Returns 0 with `cel_last_error` set to `NullArgument` for a
+/// null context, or to `Frozen` for a frozen one, whose borrowed strings must
+/// outlive it.
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_invalidate_borrowed(
+    context: *mut Context,
+    lifetime_token: u64,
+) -> usize {
+    contain(0, || match writable_context(context) {
+        Ok(context) => {
+            let unbound = context.invalidate_borrowed(lifetime_token);
+            report(CelErrorCode::Ok);
+            unbound
+        }
+        Err((code, _)) => {
+            report(code);
+            0
+        }
+    })
+}
 
-        context.add_variable("test".to_string(), serde_json::Value::Bool(true));
-        assert_eq!(context.variables.len(), 1);
-        assert_eq!(context.variables.get("test"), Some(&serde_json::Value::Bool(true)));
-    }
+/// Add a string variable given as UTF-16 code units
+///
+/// `len` counts code units, not bytes. Unpaired surrogates are rejected with
+/// `InvalidArgument`. `ptr` may be null when `len` is 0.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `name` is either null or a valid null-terminated C string
+/// - `ptr` is either null or points to at least `len` readable, aligned `u16` values
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn context_add_variable_utf16(
+    context: *mut Context,
+    name: *const c_char,
+    ptr: *const u16,
+    len: usize,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let (context, name_str) = match variable_target(context, name) {
+            Ok(target) => target,
+            Err((code, e)) => {
+                copy_error_to_buffer(&e, errbuf, errbuf_len);
+                return report(code);
+            }
+        };
+        if ptr.is_null() && len > 0 {
+            copy_error_to_buffer("String pointer is null", errbuf, errbuf_len);
+            return report(CelErrorCode::NullArgument);
+        }
 
-    #[test]
-    fn test_context_reset_direct() {
-        let mut context = Context::new();
-        context.add_variable("test1".to_string(), serde_json::Value::Bool(true));
-        context.add_variable("test2".to_string(), serde_json::Value::String("hello".to_string()));
-        assert_eq!(context.variables.len(), 2);
+        let units = if len == 0 { &[][..] } else { std::slice::from_raw_parts(ptr, len) };
+        match String::from_utf16(units) {
+            Ok(s) => {
+                context.add_variable(name_str, serde_json::Value::String(s));
+                report(CelErrorCode::Ok)
+            }
+            Err(e) => {
+                copy_error_to_buffer(&format!("Invalid UTF-16 string: {e}"), errbuf, errbuf_len);
+                report(CelErrorCode::InvalidArgument)
+            }
+        }
+    })
+}
 
-        context.reset();
-        assert_eq!(context.variables.len(), 0);
+/// Borrow `len` bytes at `ptr`, treating an empty range as valid even when `ptr` is null
+pub(crate) unsafe fn raw_bytes<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(ptr, len)
     }
+}
 
-    #[test]
-    fn test_context_get_variables() {
-        let mut context = Context::new();
-        context
-            .add_variable("x".to_string(), serde_json::Value::Number(serde_json::Number::from(42)));
-
-        let vars = context.get_variables();
-        assert_eq!(vars.len(), 1);
-        assert!(vars.contains_key("x"));
-    }
+/// Reset the context, clearing all variables
+///
+/// A null `context` is ignored and sets `cel_last_error` to `NullArgument`.
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_reset(context: *mut Context) {
+    contain((), || match writable_context(context) {
+        Ok(context) => {
+            context.reset();
+            report(CelErrorCode::Ok);
+        }
+        Err((code, _)) => {
+            report(code);
+        }
+    })
+}
 
-    #[test]
-    fn test_cel_value_to_json_bool() {
-        let cel_value = CelValue {
-            value_type: CelValueType::Bool,
-            data: CelValueData { bool_val: true },
+/// Take a snapshot of the context's variables for `context_restore`
+///
+/// No variables are copied: while the snapshot is alive the context journals what
+/// each change replaces, so restoring only undoes the changes made since. Returns
+/// null with `cel_last_error` set to `NullArgument` if `context` is null. The
+/// snapshot must be released with `context_snapshot_free`.
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_snapshot(context: *mut Context) -> *mut ContextSnapshot {
+    contain(std::ptr::null_mut(), || {
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, _)) => {
+                report(code);
+                return std::ptr::null_mut();
+            }
         };
 
-        let result = cel_value_to_json(&cel_value).unwrap();
-        assert_eq!(result, serde_json::Value::Bool(true));
-    }
+        report(CelErrorCode::Ok);
+        Box::into_raw(Box::new(context.snapshot()))
+    })
+}
 
-    #[test]
-    fn test_cel_value_to_json_int() {
-        let cel_value = CelValue {
-            value_type: CelValueType::Int,
-            data: CelValueData { int_val: -123 },
+/// Roll the context's variables back to a snapshot
+///
+/// The snapshot stays valid, so a host can add per-evaluation variables, execute and
+/// restore repeatedly. Restoring an earlier snapshot invalidates later ones, and the
+/// resolver is not affected. Returns false with `cel_last_error` set to
+/// `InvalidArgument` if the snapshot was taken from another context or has been
+/// invalidated, or to `NullArgument` if `context` or `snapshot` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `snapshot` is either null or a valid pointer returned by `context_snapshot`
+#[no_mangle]
+pub unsafe extern "C" fn context_restore(
+    context: *mut Context,
+    snapshot: *const ContextSnapshot,
+) -> bool {
+    contain(false, || {
+        let Some(snapshot) = snapshot.as_ref() else {
+            return report(CelErrorCode::NullArgument);
         };
-
-        let result = cel_value_to_json(&cel_value).unwrap();
-        assert_eq!(result, serde_json::Value::Number(serde_json::Number::from(-123)));
-    }
-
-    #[test]
-    fn test_cel_value_to_json_uint() {
-        let cel_value = CelValue {
-            value_type: CelValueType::Uint,
-            data: CelValueData { uint_val: 456 },
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, _)) => return report(code),
         };
 
-        let result = cel_value_to_json(&cel_value).unwrap();
-        assert_eq!(result, serde_json::Value::Number(serde_json::Number::from(456)));
-    }
+        match context.restore(snapshot) {
+            Ok(()) => report(CelErrorCode::Ok),
+            Err(_) => report(CelErrorCode::InvalidArgument),
+        }
+    })
+}
 
-    #[test]
-    #[allow(clippy::unreachable)]
-    fn test_cel_value_to_json_double() {
-        let cel_value = CelValue {
-            value_type: CelValueType::Double,
-            data: CelValueData {
+/// Free a snapshot returned by `context_snapshot`
+///
+/// Snapshots may be freed in any order and after their context.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `snapshot` is either null or a valid pointer returned by `context_snapshot`
+/// - `snapshot` has not been previously freed
+#[no_mangle]
+pub unsafe extern "C" fn context_snapshot_free(snapshot: *mut ContextSnapshot) {
+    contain((), || {
+        if !snapshot.is_null() {
+            drop(Box::from_raw(snapshot));
+        }
+    })
+}
+
+/// Serialize the context's variables as JSON, as described for `api::Context::to_json`
+///
+/// Returns false with `cel_last_error` set to `InvalidArgument` if a variable has no
+/// JSON form, or to `NullArgument` if `context`, `out_json` or `errbuf_len` is null.
+///
+/// # Ownership
+/// `out_json.ptr` is a caller-owned library string; drop it with `cel_string_release`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `out_json` is either null or a valid pointer to a `CelStringValue` that can be written to
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn context_to_json(
+    context: *const Context,
+    out_json: *mut CelStringValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(context) = context.as_ref() else {
+            return fail(CelErrorCode::NullArgument, "Context is null", errbuf, errbuf_len);
+        };
+        let Some(out_json) = out_json.as_mut() else {
+            return fail(CelErrorCode::NullArgument, "Output pointer is null", errbuf, errbuf_len);
+        };
+
+        match context.to_json() {
+            Ok(json) => {
+                *out_json = owned_library_string(&json);
+                report(CelErrorCode::Ok)
+            }
+            Err(e) => fail(CelErrorCode::InvalidArgument, &e.to_string(), errbuf, errbuf_len),
+        }
+    })
+}
+
+/// Remove the context's audit records and return them as a JSON array, oldest first
+///
+/// Each record is an object such as `{"expression_hash":"692607cc7f502f92",
+/// "variables":["n"],"result":5,"duration_ns":1200}`, with `error` in place of
+/// `result` for a failed execution; `result` is also left out when the value has no
+/// JSON form. `expression_hash` is the fingerprint `program_hash_hex` returns.
+/// Returns false with `cel_last_error` set to `NullArgument` if `context` or
+/// `out_json` is null.
+///
+/// # Ownership
+/// `out_json.ptr` is a caller-owned library string; drop it with `cel_string_release`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `out_json` is either null or a valid pointer to a `CelStringValue` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn context_take_audit_log(
+    context: *const Context,
+    out_json: *mut CelStringValue,
+) -> bool {
+    contain(false, || {
+        let (Some(context), Some(out_json)) = (context.as_ref(), out_json.as_mut()) else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let json = serde_json::to_string(&context.take_audit_records()).unwrap_or_default();
+        *out_json = owned_library_string(&json);
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Create a context from JSON written by `context_to_json`
+///
+/// Returns null with `cel_last_error` set to `InvalidArgument` and the reason in
+/// `errbuf` if `json` is not UTF-8 or not a serialized context, or to `NullArgument`
+/// if `json` or `errbuf_len` is null. Free the context with `context_free`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `json` is either null or a valid null-terminated C string
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn context_from_json(
+    json: *const c_char,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> *mut Context {
+    contain_with_error(std::ptr::null_mut(), errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            report(CelErrorCode::NullArgument);
+            return std::ptr::null_mut();
+        };
+        if json.is_null() {
+            fail(CelErrorCode::NullArgument, "JSON is null", errbuf, errbuf_len);
+            return std::ptr::null_mut();
+        }
+
+        let context = CStr::from_ptr(json)
+            .to_str()
+            .map_err(|e| format!("Invalid JSON string: {e}"))
+            .and_then(|json| Context::from_json(json).map_err(|e| e.to_string()));
+        match context {
+            Ok(context) => {
+                report(CelErrorCode::Ok);
+                let context = Box::into_raw(Box::new(context));
+                track(CelAllocationKind::AllocatedContext, context, size_of::<Context>());
+                context
+            }
+            Err(e) => {
+                fail(CelErrorCode::InvalidArgument, &e, errbuf, errbuf_len);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Make the context read-only so threads can share it as a base environment
+///
+/// Returns the same context as a `const` pointer for `program_execute` and the other
+/// functions that only read it. From then on every function that would change it,
+/// such as `context_add_variable`, `context_reset`, `context_restore` or
+/// `context_set_max_depth`, fails with `cel_last_error` set to `Frozen` and leaves
+/// it untouched, so concurrent executions need no locking. A frozen context cannot
+/// be unfrozen; free it with `context_free` once no thread uses it. Returns null with
+/// `cel_last_error` set to `NullArgument` if `context` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - no other thread uses the context while it is being frozen
+#[no_mangle]
+pub unsafe extern "C" fn context_freeze(context: *mut Context) -> *const Context {
+    contain(std::ptr::null(), || {
+        let Some(frozen) = context.as_mut() else {
+            report(CelErrorCode::NullArgument);
+            return std::ptr::null();
+        };
+
+        frozen.freeze();
+        report(CelErrorCode::Ok);
+        context.cast_const()
+    })
+}
+
+/// Whether `context_freeze` has made the context read-only, false for a null context
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_is_frozen(context: *const Context) -> bool {
+    contain(false, || context.as_ref().is_some_and(Context::is_frozen))
+}
+
+/// Remove a single variable from the context
+///
+/// Returns true if the variable was bound. A null `context` or `name` returns false
+/// with `cel_last_error` set to `NullArgument`; an unknown name leaves it `Ok`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `name` is either null or a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn context_remove_variable(
+    context: *mut Context,
+    name: *const c_char,
+) -> bool {
+    contain(false, || match variable_target(context, name) {
+        Ok((context, name)) => {
+            let removed = context.remove_variable(&name);
+            report(CelErrorCode::Ok);
+            removed
+        }
+        Err((code, _)) => report(code),
+    })
+}
+
+/// Whether a variable is bound in the context
+///
+/// When it is and `value_type` is not null, the type the variable evaluates to is
+/// written there. A null `context` or `name` returns false with `cel_last_error` set
+/// to `NullArgument`; an unknown name leaves it `Ok`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `name` is either null or a valid null-terminated C string
+/// - `value_type` is either null or a valid pointer to a `CelValueType` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn context_has_variable(
+    context: *const Context,
+    name: *const c_char,
+    value_type: *mut CelValueType,
+) -> bool {
+    contain(false, || {
+        let Some(context) = context.as_ref() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let name = match variable_name(name) {
+            Ok(name) => name,
+            Err((code, _)) => return report(code),
+        };
+        report(CelErrorCode::Ok);
+
+        match context.variable_type(&name) {
+            Some(bound_type) => {
+                if let Some(value_type) = value_type.as_mut() {
+                    *value_type = bound_type;
+                }
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// Copy a variable's value out of the context
+///
+/// The copy is owned by the caller: strings, bytes and nested list/map arrays are
+/// separate allocations that must be released with `cel_value_free`. Returns false
+/// with `cel_last_error` set to `InvalidArgument` if the name is not bound, or to
+/// `NullArgument` if `context`, `name`, `out_value` or `errbuf_len` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `name` is either null or a valid null-terminated C string
+/// - `out_value` is either null or a valid pointer to a `CelValue` that can be written to
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn context_get_variable(
+    context: *const Context,
+    name: *const c_char,
+    out_value: *mut CelValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(context) = context.as_ref() else {
+            copy_error_to_buffer("Context is null", errbuf, errbuf_len);
+            return report(CelErrorCode::NullArgument);
+        };
+        let name = match variable_name(name) {
+            Ok(name) => name,
+            Err((code, e)) => {
+                copy_error_to_buffer(&e, errbuf, errbuf_len);
+                return report(code);
+            }
+        };
+        if out_value.is_null() {
+            copy_error_to_buffer("Output value pointer is null", errbuf, errbuf_len);
+            return report(CelErrorCode::NullArgument);
+        }
+
+        let value = if let Some(bytes) = context.get_bytes_variables().get(name.as_str()) {
+            Ok(CelRustValue::Bytes(Arc::new(bytes.clone())))
+        } else if let Some(value) = context.get_value_variables().get(name.as_str()) {
+            Ok(value.clone())
+        } else if let Some(value) = context.get_borrowed_variable(&name) {
+            Ok(CelRustValue::String(Arc::new(value.to_string())))
+        } else if let Some(value) = context.get_variables().get(name.as_str()) {
+            json_to_cel_value(value)
+        } else {
+            Err(format!("Variable '{name}' is not bound"))
+        };
+
+        match value.and_then(|value| to_owned_c_value(&value)) {
+            Ok(value) => {
+                out_value.write(value);
+                report(CelErrorCode::Ok)
+            }
+            Err(e) => {
+                copy_error_to_buffer(&e, errbuf, errbuf_len);
+                report(CelErrorCode::InvalidArgument)
+            }
+        }
+    })
+}
+
+/// Register a resolver for variables the context does not bind
+///
+/// When a program executes, every variable it references that is not bound in the
+/// context is passed to `resolver`, so hosts with a large variable universe only
+/// produce the few values an expression actually uses. Variables that comprehensions
+/// bind, such as `x` in `xs.all(x, x > 0)`, are not passed. Resolved values are not
+/// cached in the context. Passing a null `resolver` removes it. Returns false with
+/// `cel_last_error` set to `NullArgument` if `context` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - until it is replaced or the context is freed, `resolver` is safe to call with
+///   `user_data` from any thread that executes a program against the context
+#[no_mangle]
+pub unsafe extern "C" fn context_set_resolver(
+    context: *mut Context,
+    resolver: Option<CelVariableResolver>,
+    user_data: *mut c_void,
+) -> bool {
+    contain(false, || {
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, _)) => return report(code),
+        };
+
+        match resolver {
+            Some(callback) => {
+                let resolver = Resolver {
+                    callback,
+                    user_data,
+                };
+                context.set_resolver(move |name| resolver.resolve(name));
+            }
+            None => context.clear_resolver(),
+        }
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Register a hook that decides every field selection and index expressions make
+///
+/// Before `a.b.c` selects `c`, and likewise for `a.b`, the path of the selection is
+/// passed to `hook`, which can allow it, deny it to fail the execution with
+/// `ExecutionError`, or replace the value it evaluates to. That lets hosts enforce
+/// row-level security and record which data a rule actually read. Selections inside
+/// `has()` are passed too. Indexes are passed once the key is evaluated, with string
+/// keys as the selection they stand for, so `a["b"]` and `a[k]` holding `"b"` are
+/// both passed as `a.b`, and other keys as in `a[1]`. Passing a null `hook` removes
+/// it. Returns false with `cel_last_error` set to `NullArgument` if `context`
+/// is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - until it is replaced or the context is freed, `hook` is safe to call with
+///   `user_data` from any thread that executes a program against the context
+#[no_mangle]
+pub unsafe extern "C" fn context_set_attribute_hook(
+    context: *mut Context,
+    hook: Option<CelAttributeHook>,
+    user_data: *mut c_void,
+) -> bool {
+    contain(false, || {
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, _)) => return report(code),
+        };
+
+        match hook {
+            Some(callback) => {
+                let hook = Hook {
+                    callback,
+                    user_data,
+                };
+                context.set_attribute_hook(move |path| hook.decide(path));
+            }
+            None => context.clear_attribute_hook(),
+        }
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Register a function that expressions executed against the context can call
+///
+/// Calls to `name` pass their arguments to `callback`, which replaces any function
+/// registered under that name before. A built-in function or one of an enabled
+/// extension is never replaced, so registering `size` has no effect. Functions are
+/// kept by `context_reset` and are not part of snapshots. Returns false with
+/// `cel_last_error` set to `NullArgument` if `context`, `name` or `callback` is null,
+/// `InvalidArgument` if `name` is not UTF-8, and `Frozen` if the context is frozen.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `name` is either null or a valid null-terminated C string
+/// - until it is removed or replaced or the context is freed, `callback` is safe to
+///   call with `user_data` from any thread that executes a program against the context
+#[no_mangle]
+pub unsafe extern "C" fn context_add_function(
+    context: *mut Context,
+    name: *const c_char,
+    callback: Option<CelFunctionCallback>,
+    user_data: *mut c_void,
+) -> bool {
+    contain(false, || {
+        register_function(context, name, callback, user_data, |context, name, function| {
+            context.add_function(name, move |args| function.call(args));
+        })
+    })
+}
+
+/// Register an overload of `name` for arguments of the `arg_types_len` types in
+/// `arg_types`
+///
+/// A call whose arguments have exactly these types goes to `callback`, so the
+/// library dispatches between the overloads of a name instead of one callback
+/// switching on types. The overload replaces one registered for the same types, and
+/// a call fails if it returns a value of a type other than `result_type`. Calls no
+/// overload matches go to the function `context_add_function` registered, or fail.
+/// Returns false with `cel_last_error` set like `context_add_function`, and to
+/// `NullArgument` if `arg_types` is null while `arg_types_len` is not 0.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `name` is either null or a valid null-terminated C string
+/// - `arg_types` is either null or points to `arg_types_len` valid `CelValueType`s
+/// - until it is removed or replaced or the context is freed, `callback` is safe to
+///   call with `user_data` from any thread that executes a program against the context
+#[no_mangle]
+pub unsafe extern "C" fn context_add_overload(
+    context: *mut Context,
+    name: *const c_char,
+    arg_types: *const CelValueType,
+    arg_types_len: usize,
+    result_type: CelValueType,
+    callback: Option<CelFunctionCallback>,
+    user_data: *mut c_void,
+) -> bool {
+    contain(false, || {
+        let Some(params) = value_types(arg_types, arg_types_len) else {
+            return report(CelErrorCode::NullArgument);
+        };
+        register_function(context, name, callback, user_data, |context, name, function| {
+            context.add_overload(name, params, result_type, move |args| function.call(args));
+        })
+    })
+}
+
+/// Register a function that expressions call on a receiver, as in
+/// `user.hasRole('admin')`
+///
+/// `callback` gets the receiver followed by the arguments. Member functions are kept
+/// apart from the functions `context_add_function` registers under the same name,
+/// which only global calls such as `hasRole(user, 'admin')` reach. Returns false with
+/// `cel_last_error` set like `context_add_function`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `name` and `callback` are as for `context_add_function`
+#[no_mangle]
+pub unsafe extern "C" fn context_add_member_function(
+    context: *mut Context,
+    name: *const c_char,
+    callback: Option<CelFunctionCallback>,
+    user_data: *mut c_void,
+) -> bool {
+    contain(false, || {
+        register_function(context, name, callback, user_data, |context, name, function| {
+            context.add_member_function(name, move |args| function.call(args));
+        })
+    })
+}
+
+/// Register an overload of the member function `name` for a receiver and arguments
+/// of the types in `arg_types`, the receiver's first
+///
+/// Member calls no overload matches go to the function `context_add_member_function`
+/// registered, or fail. Returns false with `cel_last_error` set like
+/// `context_add_overload`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `name`, `arg_types` and `callback` are as for `context_add_overload`
+#[no_mangle]
+pub unsafe extern "C" fn context_add_member_overload(
+    context: *mut Context,
+    name: *const c_char,
+    arg_types: *const CelValueType,
+    arg_types_len: usize,
+    result_type: CelValueType,
+    callback: Option<CelFunctionCallback>,
+    user_data: *mut c_void,
+) -> bool {
+    contain(false, || {
+        let Some(params) = value_types(arg_types, arg_types_len) else {
+            return report(CelErrorCode::NullArgument);
+        };
+        register_function(context, name, callback, user_data, |context, name, function| {
+            context.add_member_overload(name, params, result_type, move |args| function.call(args));
+        })
+    })
+}
+
+/// How many arguments a function registered with `context_add_variadic_function`
+/// takes, as described for `api::Arity`
+///
+/// A call passes between `min_args` and `max_args` arguments, or any number from
+/// `min_args` on if `max_args` is `SIZE_MAX`. `defaults` points to `defaults_len`
+/// values filling in the arguments after the first `min_args` that a call leaves out,
+/// and may be null when the length is 0; they are copied.
+#[repr(C)]
+#[derive(Debug)]
+pub struct CelArity {
+    pub min_args: usize,
+    pub max_args: usize,
+    pub defaults: *const CelValue,
+    pub defaults_len: usize,
+}
+
+/// Copy a caller-provided arity
+unsafe fn copy_arity(arity: &CelArity) -> Result<Arity, (CelErrorCode, String)> {
+    let defaults = raw_values(arity.defaults, arity.defaults_len)
+        .map_err(|e| (CelErrorCode::NullArgument, e))?
+        .iter()
+        .map(|value| exact_rust_value(value))
+        .collect::<Result<_, _>>()
+        .map_err(|e| (CelErrorCode::InvalidArgument, e))?;
+    Ok(Arity {
+        min: arity.min_args,
+        max: (arity.max_args != usize::MAX).then_some(arity.max_args),
+        defaults,
+    })
+}
+
+/// Check the arguments of a variadic function registration and hand the function to
+/// `add`, describing a failure in `errbuf`
+unsafe fn register_variadic_function(
+    context: *mut Context,
+    name: *const c_char,
+    arity: *const CelArity,
+    callback: Option<CelFunctionCallback>,
+    user_data: *mut c_void,
+    errbuf: *mut u8,
+    errbuf_len: &mut usize,
+    add: impl FnOnce(&mut Context, &str, Arity, Function) -> Result<(), Error>,
+) -> bool {
+    let (context, name) = match variable_target(context, name) {
+        Ok(target) => target,
+        Err((code, e)) => return fail(code, &e, errbuf, errbuf_len),
+    };
+    let Some(callback) = callback else {
+        return fail(CelErrorCode::NullArgument, "Function callback is null", errbuf, errbuf_len);
+    };
+    let Some(arity) = arity.as_ref() else {
+        return fail(CelErrorCode::NullArgument, "Arity is null", errbuf, errbuf_len);
+    };
+    let arity = match copy_arity(arity) {
+        Ok(arity) => arity,
+        Err((code, e)) => return fail(code, &e, errbuf, errbuf_len),
+    };
+
+    let function = Function {
+        callback,
+        user_data,
+    };
+    match add(context, &name, arity, function) {
+        Ok(()) => report(CelErrorCode::Ok),
+        Err(e) => fail(CelErrorCode::InvalidArgument, &e.to_string(), errbuf, errbuf_len),
+    }
+}
+
+/// Register a function taking any arguments, as many as `arity` allows
+///
+/// Calls passing too few or too many arguments fail the evaluation, and `callback`
+/// gets copies of the defaults of `arity` in place of the optional arguments a call
+/// leaves out, so a helper such as `coalesce(a, b, ...)` is registered once for all
+/// its arities. Returns false with `cel_last_error` set to `NullArgument` for a null
+/// pointer and to `InvalidArgument` for a default that cannot be converted, a
+/// maximum below the minimum, or more defaults than optional arguments, with the
+/// reason in `errbuf`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `name` and `callback` are as for `context_add_function`
+/// - `arity` is either null or points to a valid `CelArity` whose defaults are valid
+///   values
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn context_add_variadic_function(
+    context: *mut Context,
+    name: *const c_char,
+    arity: *const CelArity,
+    callback: Option<CelFunctionCallback>,
+    user_data: *mut c_void,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        register_variadic_function(
+            context,
+            name,
+            arity,
+            callback,
+            user_data,
+            errbuf,
+            errbuf_len,
+            |context, name, arity, function| {
+                context.add_variadic_function(name, arity, move |args| function.call(args))
+            },
+        )
+    })
+}
+
+/// Register a member function taking as many arguments as `arity` allows, counting
+/// the receiver
+///
+/// Returns false with `cel_last_error` set like `context_add_variadic_function`.
+///
+/// # Safety
+/// The caller must ensure that the arguments are as for
+/// `context_add_variadic_function`.
+#[no_mangle]
+pub unsafe extern "C" fn context_add_variadic_member_function(
+    context: *mut Context,
+    name: *const c_char,
+    arity: *const CelArity,
+    callback: Option<CelFunctionCallback>,
+    user_data: *mut c_void,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        register_variadic_function(
+            context,
+            name,
+            arity,
+            callback,
+            user_data,
+            errbuf,
+            errbuf_len,
+            |context, name, arity, function| {
+                context.add_variadic_member_function(name, arity, move |args| function.call(args))
+            },
+        )
+    })
+}
+
+/// Check the arguments of a function registration and hand the function to `add`
+unsafe fn register_function(
+    context: *mut Context,
+    name: *const c_char,
+    callback: Option<CelFunctionCallback>,
+    user_data: *mut c_void,
+    add: impl FnOnce(&mut Context, &str, Function),
+) -> bool {
+    let (context, name) = match variable_target(context, name) {
+        Ok(target) => target,
+        Err((code, _)) => return report(code),
+    };
+    let Some(callback) = callback else {
+        return report(CelErrorCode::NullArgument);
+    };
+
+    add(
+        context,
+        &name,
+        Function {
+            callback,
+            user_data,
+        },
+    );
+    report(CelErrorCode::Ok)
+}
+
+/// The `len` types at `ptr`, or `None` if `ptr` is null although `len` is not 0
+unsafe fn value_types<'a>(ptr: *const CelValueType, len: usize) -> Option<&'a [CelValueType]> {
+    match (ptr.is_null(), len) {
+        (_, 0) => Some(&[]),
+        (true, _) => None,
+        (false, len) => Some(std::slice::from_raw_parts(ptr, len)),
+    }
+}
+
+/// Unregister the functions, member functions and overloads registered under `name`
+///
+/// Returns true if a function was registered under `name`. A null `context` or `name`
+/// returns false with `cel_last_error` set to `NullArgument`; an unknown name leaves
+/// it `Ok`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `name` is either null or a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn context_remove_function(
+    context: *mut Context,
+    name: *const c_char,
+) -> bool {
+    contain(false, || match variable_target(context, name) {
+        Ok((context, name)) => {
+            let removed = context.remove_function(&name);
+            report(CelErrorCode::Ok);
+            removed
+        }
+        Err((code, _)) => report(code),
+    })
+}
+
+/// Limit the nesting depth of expressions executed against the context
+///
+/// Deeper expressions fail with `ExecutionError` and an "expression too deep" message
+/// instead of overflowing the stack. New contexts allow a depth of 256, and 0 removes
+/// the limit. Returns false with `cel_last_error` set to `NullArgument` if
+/// `context` is null.
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_set_max_depth(context: *mut Context, max_depth: usize) -> bool {
+    contain(false, || {
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, _)) => return report(code),
+        };
+
+        context.set_max_depth(max_depth);
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Limit the bytes one execution against the context may allocate
+///
+/// Strings, bytes, lists and maps an expression builds are charged as they are made,
+/// and an execution that passes `max_bytes` fails with `MemoryLimit`. Values bound
+/// to the context are not charged. New contexts have no limit, and 0 removes it.
+/// Returns false with `cel_last_error` set to `NullArgument` if `context` is null.
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_set_max_memory(context: *mut Context, max_bytes: usize) -> bool {
+    contain(false, || {
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, _)) => return report(code),
+        };
+
+        context.set_max_memory(max_bytes);
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Keep an audit record of each of the last `capacity` executions against the context
+///
+/// Every execution of a program against the context then records the program's
+/// fingerprint, the names of the variables bound, the result or error and the
+/// duration, so a decision can be reconstructed later; `context_take_audit_log`
+/// retrieves the records. Once `capacity` records are kept the oldest is dropped for
+/// each new one. New contexts keep none, and 0 stops recording; any call discards the
+/// records kept. Returns false with `cel_last_error` set to `NullArgument` if
+/// `context` is null.
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_set_audit_log(context: *mut Context, capacity: usize) -> bool {
+    contain(false, || {
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, _)) => return report(code),
+        };
+
+        context.set_audit_log(capacity);
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Make executions against the context reproducible across runs and platforms
+///
+/// `now()` then fails with `ExecutionError` unless `context_set_now` injected the
+/// instant, and comprehensions over a map visit its keys in sorted order rather than
+/// the map's hash order. The rest of evaluation is deterministic already: the library
+/// has no source of randomness, doubles format the same way everywhere, and maps in
+/// results list their entries sorted by key. Host functions and resolvers have to be
+/// deterministic themselves. Returns false with `cel_last_error` set to `NullArgument`
+/// if `context` is null.
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_set_deterministic(context: *mut Context, enabled: bool) -> bool {
+    contain(false, || {
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, _)) => return report(code),
+        };
+
+        context.set_deterministic(enabled);
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Limit the comprehension iterations one execution against the context may run
+///
+/// Iterations are counted across every comprehension the execution runs, nested ones
+/// included, and an execution that passes `max_iterations` fails with
+/// `IterationLimit`. Comprehensions that stop early, such as an `exists` that finds a
+/// match, only count the iterations they run. New contexts have no limit, and 0
+/// removes it. Returns false with `cel_last_error` set to `NullArgument` if `context`
+/// is null.
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_set_max_iterations(
+    context: *mut Context,
+    max_iterations: usize,
+) -> bool {
+    contain(false, || {
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, _)) => return report(code),
+        };
+
+        context.set_max_iterations(max_iterations);
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// What selecting a key a map lacks evaluates to, set by `context_set_missing_keys`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CelMissingKeys {
+    /// Evaluation fails with `ExecutionError`
+    MissingKeyError,
+    /// The selection is null
+    MissingKeyNull,
+    /// Evaluation fails with `Unknown`, naming the missing keys
+    MissingKeyUnknown,
+}
+
+impl From<CelMissingKeys> for MissingKeys {
+    fn from(policy: CelMissingKeys) -> Self {
+        match policy {
+            CelMissingKeys::MissingKeyError => Self::Error,
+            CelMissingKeys::MissingKeyNull => Self::Null,
+            CelMissingKeys::MissingKeyUnknown => Self::Unknown,
+        }
+    }
+}
+
+/// Choose what selecting a key that a map lacks evaluates to
+///
+/// Under `MissingKeyError`, the default, `user.age` fails with `ExecutionError` when
+/// `user` has no `age` key and `has(user.age)` is false. Under `MissingKeyNull` the
+/// selection is null instead. Under `MissingKeyUnknown` the selection and `has()` on
+/// it are unknown: an execution that needs the key fails with `Unknown` and a message
+/// naming every missing key it reached, while one whose result does not depend on it,
+/// such as `true || user.age > 1`, succeeds. Returns false with `cel_last_error` set
+/// to `NullArgument` if `context` is null.
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_set_missing_keys(
+    context: *mut Context,
+    policy: CelMissingKeys,
+) -> bool {
+    contain(false, || {
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, _)) => return report(code),
+        };
+
+        context.set_missing_keys(policy.into());
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Make operators applied to null yield null instead of failing, as in SQL
+///
+/// With `enabled`, arithmetic, negation, `!`, ordering comparisons, field selection
+/// and indexing with a null operand evaluate to null in expressions executed against
+/// the context, so `row.price * row.qty` is null rather than an `ExecutionError` for
+/// a row whose price is null. Equality, `in` and the logical operators are unchanged.
+/// Returns false with `cel_last_error` set to `NullArgument` if `context` is null.
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_set_null_propagation(
+    context: *mut Context,
+    enabled: bool,
+) -> bool {
+    contain(false, || {
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, _)) => return report(code),
+        };
+
+        context.set_null_propagation(enabled);
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Let arithmetic, comparisons and equality mix int, uint and double operands
+///
+/// With `enabled`, expressions executed against the context promote mixed numeric
+/// operands instead of failing with `ExecutionError`: comparisons between int and
+/// uint are exact and those with a double operand are done in double, so
+/// `count < 1.5` works for an int `count`; arithmetic with a double operand is done in
+/// double, and between int and uint in int, failing if a value falls outside the int
+/// range. Returns false with `cel_last_error` set to `NullArgument` if `context` is
+/// null.
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_set_numeric_promotion(
+    context: *mut Context,
+    enabled: bool,
+) -> bool {
+    contain(false, || {
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, _)) => return report(code),
+        };
+
+        context.set_numeric_promotion(enabled);
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Let host functions that fail evaluate to error values, which `&&`, `||` and
+/// comprehensions can absorb
+///
+/// With `enabled`, a call whose callback fails in an expression executed against the
+/// context no longer fails the execution at once: `lookup(id) && false` is false and
+/// `lookup(id) || true` is true, as CEL specifies, while an execution whose result
+/// depends on the failed call fails with its message. Returns false with
+/// `cel_last_error` set to `NullArgument` if `context` is null.
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_set_soft_errors(context: *mut Context, enabled: bool) -> bool {
+    contain(false, || {
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, _)) => return report(code),
+        };
+
+        context.set_soft_errors(enabled);
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Make `context_add_variable` reject NaN and infinite doubles
+///
+/// By default they are bound like other doubles: NaN equals nothing, not even itself,
+/// and ordering comparisons with it are false, as CEL specifies. With `enabled`, adding
+/// one fails with `InvalidArgument` instead, for hosts that treat a non-finite input
+/// as a bug. Variables already bound are kept. Returns false with `cel_last_error` set
+/// to `NullArgument` if `context` is null.
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_set_reject_non_finite(
+    context: *mut Context,
+    enabled: bool,
+) -> bool {
+    contain(false, || {
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, _)) => return report(code),
+        };
+
+        context.set_reject_non_finite(enabled);
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Fix the instant `now()` returns in expressions executed against the context
+///
+/// `epoch_seconds` and `nanos` count from 1970-01-01T00:00:00Z, and every call to
+/// `now()` within one execution sees the same instant. Returns false with
+/// `cel_last_error` set to `NullArgument` if `context` is null, or to
+/// `InvalidArgument` if `nanos` is 1e9 or more or the instant falls outside the years
+/// 0001 to 9999.
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_set_now(
+    context: *mut Context,
+    epoch_seconds: i64,
+    nanos: u32,
+) -> bool {
+    contain(false, || {
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, _)) => return report(code),
+        };
+
+        match context.set_now(epoch_seconds, nanos) {
+            Ok(()) => report(CelErrorCode::Ok),
+            Err(_) => report(CelErrorCode::InvalidArgument),
+        }
+    })
+}
+
+/// Make `now()` read the system clock again, once per execution
+///
+/// Returns false with `cel_last_error` set to `NullArgument` if `context` is null.
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_clear_now(context: *mut Context) -> bool {
+    contain(false, || {
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, _)) => return report(code),
+        };
+
+        context.clear_now();
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Number of variables bound in the context, or 0 for a null context
+///
+/// # Safety
+/// The caller must ensure that `context` is either null or a valid pointer returned
+/// by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn context_variable_count(context: *const Context) -> usize {
+    contain(0, || context.as_ref().map_or(0, Context::variable_count))
+}
+
+/// Name of the variable at `index`, for enumerating with `context_variable_count`
+///
+/// The name is borrowed from the context: it is not null-terminated and stays valid
+/// only until the context is next modified, as does the order of the indices.
+/// Returns false with `cel_last_error` set to `InvalidArgument` if `index` is out of
+/// range, or to `NullArgument` if `context` or `out_name` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `out_name` is either null or a valid pointer to a `CelStringValue` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn context_variable_name_at(
+    context: *const Context,
+    index: usize,
+    out_name: *mut CelStringValue,
+) -> bool {
+    contain(false, || {
+        let (Some(context), Some(out_name)) = (context.as_ref(), out_name.as_mut()) else {
+            return report(CelErrorCode::NullArgument);
+        };
+
+        match context.variable_names().nth(index) {
+            Some(name) => {
+                *out_name = CelStringValue {
+                    ptr: name.as_ptr(),
+                    len: name.len(),
+                };
+                report(CelErrorCode::Ok)
+            }
+            None => report(CelErrorCode::InvalidArgument),
+        }
+    })
+}
+
+fn cel_value_to_json(value: &CelValue) -> Result<serde_json::Value, String> {
+    match value.value_type {
+        CelValueType::Null => Ok(serde_json::Value::Null),
+        CelValueType::Bool => Ok(serde_json::Value::Bool(unsafe { value.data.bool_val })),
+        CelValueType::Int => Ok(serde_json::Value::Number(serde_json::Number::from(unsafe {
+            value.data.int_val
+        }))),
+        CelValueType::Uint => Ok(serde_json::Value::Number(serde_json::Number::from(unsafe {
+            value.data.uint_val
+        }))),
+        CelValueType::Double => {
+            let float_val = unsafe { value.data.double_val };
+
+            serde_json::Number::from_f64(float_val).map_or_else(
+                || Err(format!("Invalid float value: {float_val}")),
+                |num| Ok(serde_json::Value::Number(num)),
+            )
+        }
+        CelValueType::String => {
+            let string_val = unsafe { &*value.data.string_val };
+            let slice = unsafe { std::slice::from_raw_parts(string_val.ptr, string_val.len) };
+            match std::str::from_utf8(slice) {
+                Ok(s) => Ok(serde_json::Value::String(s.to_string())),
+                Err(e) => Err(format!("Invalid UTF-8 string: {e}")),
+            }
+        }
+        CelValueType::Optional => {
+            let optional = unsafe { &*value.data.optional_val };
+            if optional.has_value && !optional.value.is_null() {
+                cel_value_to_json(unsafe { &*optional.value })
+            } else {
+                Ok(serde_json::Value::Null)
+            }
+        }
+        _ => Err("Unsupported value type".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CelListValue, CelMapValue, CelStringValue};
+    use std::mem::ManuallyDrop;
+
+    #[test]
+    fn test_cel_value_to_json_bool() {
+        let cel_value = CelValue {
+            value_type: CelValueType::Bool,
+            data: CelValueData { bool_val: true },
+        };
+
+        let result = cel_value_to_json(&cel_value).unwrap();
+        assert_eq!(result, serde_json::Value::Bool(true));
+    }
+
+    #[test]
+    fn test_cel_value_to_json_int() {
+        let cel_value = CelValue {
+            value_type: CelValueType::Int,
+            data: CelValueData { int_val: -123 },
+        };
+
+        let result = cel_value_to_json(&cel_value).unwrap();
+        assert_eq!(result, serde_json::Value::Number(serde_json::Number::from(-123)));
+    }
+
+    #[test]
+    fn test_cel_value_to_json_uint() {
+        let cel_value = CelValue {
+            value_type: CelValueType::Uint,
+            data: CelValueData { uint_val: 456 },
+        };
+
+        let result = cel_value_to_json(&cel_value).unwrap();
+        assert_eq!(result, serde_json::Value::Number(serde_json::Number::from(456)));
+    }
+
+    #[test]
+    #[allow(clippy::unreachable)]
+    fn test_cel_value_to_json_double() {
+        let cel_value = CelValue {
+            value_type: CelValueType::Double,
+            data: CelValueData {
                 double_val: std::f64::consts::PI,
             },
         };
@@ -253,396 +1957,1516 @@ mod tests {
     }
 
     #[test]
-    fn test_cel_value_to_json_invalid_double() {
-        let cel_value = CelValue {
+    fn test_cel_value_to_json_invalid_double() {
+        let cel_value = CelValue {
+            value_type: CelValueType::Double,
+            data: CelValueData {
+                double_val: f64::NAN,
+            },
+        };
+
+        let result = cel_value_to_json(&cel_value);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().contains("Invalid float value"));
+    }
+
+    #[test]
+    fn test_copy_error_to_buffer() {
+        let error_msg = "Test error message";
+        let mut buffer = [0u8; 20];
+        let mut buffer_len = buffer.len();
+
+        copy_error_to_buffer(error_msg, buffer.as_mut_ptr(), &mut buffer_len);
+
+        assert_eq!(buffer_len, error_msg.len());
+        assert_eq!(*buffer.get(buffer_len).unwrap(), 0); // null terminator
+
+        let result_str = std::str::from_utf8(buffer.get(..buffer_len).unwrap()).unwrap();
+        assert_eq!(result_str, error_msg);
+    }
+
+    #[test]
+    fn test_copy_error_to_buffer_truncation() {
+        let error_msg = "This is a very long error message that should be truncated";
+        let mut buffer = [0u8; 10];
+        let mut buffer_len = buffer.len();
+
+        copy_error_to_buffer(error_msg, buffer.as_mut_ptr(), &mut buffer_len);
+
+        assert_eq!(buffer_len, 9); // 10 - 1 for null terminator
+        assert_eq!(*buffer.get(buffer_len).unwrap(), 0); // null terminator
+
+        let result_str = std::str::from_utf8(buffer.get(..buffer_len).unwrap()).unwrap();
+        assert_eq!(result_str, "This is a");
+    }
+
+    #[test]
+    fn test_copy_error_to_buffer_null_ptr() {
+        let error_msg = "Test error";
+        let mut buffer_len = 100usize;
+
+        // Should not crash with null pointer
+        copy_error_to_buffer(error_msg, std::ptr::null_mut(), &mut buffer_len);
+
+        // buffer_len should remain unchanged
+        assert_eq!(buffer_len, 100);
+    }
+
+    #[test]
+    fn test_cel_value_to_json_string() {
+        let test_string = "Test string with special chars: áéíóú, 你好, 🚀";
+        let string_ptr = crate::store_string_in_pool(test_string);
+
+        let cel_value = super::CelValue {
+            value_type: super::CelValueType::String,
+            data: CelValueData {
+                string_val: ManuallyDrop::new(CelStringValue {
+                    ptr: string_ptr,
+                    len: test_string.len(),
+                }),
+            },
+        };
+
+        let result = cel_value_to_json(&cel_value);
+        assert!(result.is_ok());
+
+        let json_value = result.unwrap();
+        assert!(json_value.is_string());
+        assert_eq!(json_value.as_str().unwrap(), test_string);
+
+        crate::release_string_from_pool(string_ptr);
+    }
+
+    #[test]
+    fn test_cel_value_to_json_edge_cases() {
+        // Test special floating point values
+        let nan_value = super::CelValue {
+            value_type: super::CelValueType::Double,
+            data: CelValueData {
+                double_val: f64::NAN,
+            },
+        };
+
+        let infinity_value = super::CelValue {
+            value_type: super::CelValueType::Double,
+            data: CelValueData {
+                double_val: f64::INFINITY,
+            },
+        };
+
+        let neg_infinity_value = super::CelValue {
+            value_type: super::CelValueType::Double,
+            data: CelValueData {
+                double_val: f64::NEG_INFINITY,
+            },
+        };
+
+        // NaN should fail conversion
+        assert!(cel_value_to_json(&nan_value).is_err());
+
+        // Infinity values should also fail for JSON compatibility
+        assert!(cel_value_to_json(&infinity_value).is_err());
+        assert!(cel_value_to_json(&neg_infinity_value).is_err());
+    }
+
+    #[test]
+    fn test_copy_error_to_buffer_exact_fit() {
+        let error_msg = "exact"; // 5 characters
+        let mut buffer = [0u8; 6]; // Exactly enough space including null terminator
+        let mut buffer_len = buffer.len();
+
+        copy_error_to_buffer(error_msg, buffer.as_mut_ptr(), &mut buffer_len);
+
+        assert_eq!(buffer_len, 5);
+        assert_eq!(buffer[5], 0); // null terminator
+
+        let result_str = std::str::from_utf8(buffer.get(..buffer_len).unwrap()).unwrap();
+        assert_eq!(result_str, error_msg);
+    }
+
+    #[test]
+    fn test_copy_error_to_buffer_single_char() {
+        let error_msg = "x";
+        let mut buffer = [0u8; 2];
+        let mut buffer_len = buffer.len();
+
+        copy_error_to_buffer(error_msg, buffer.as_mut_ptr(), &mut buffer_len);
+
+        assert_eq!(buffer_len, 1);
+        assert_eq!(buffer[1], 0);
+
+        let result_str = std::str::from_utf8(buffer.get(..buffer_len).unwrap()).unwrap();
+        assert_eq!(result_str, "x");
+    }
+
+    #[test]
+    fn test_copy_error_to_buffer_empty_message() {
+        let error_msg = "";
+        let mut buffer = [0u8; 10];
+        let mut buffer_len = buffer.len();
+
+        copy_error_to_buffer(error_msg, buffer.as_mut_ptr(), &mut buffer_len);
+
+        assert_eq!(buffer_len, 0);
+        assert_eq!(buffer[0], 0); // null terminator at start
+    }
+
+    #[test]
+    fn test_copy_error_to_buffer_minimal_space() {
+        let error_msg = "Error";
+        let mut buffer = [0u8; 1]; // Only space for null terminator
+        let mut buffer_len = buffer.len();
+
+        copy_error_to_buffer(error_msg, buffer.as_mut_ptr(), &mut buffer_len);
+
+        assert_eq!(buffer_len, 0); // No space for characters
+        assert_eq!(buffer[0], 0); // null terminator
+    }
+
+    #[test]
+    fn test_context_add_variable_string_n_interior_nul() {
+        let mut context = Context::new();
+        let name = std::ffi::CString::new("s").unwrap();
+        let data = b"before\0after";
+        let mut error_buf = [0u8; 128];
+        let mut error_len = error_buf.len();
+
+        let ok = unsafe {
+            context_add_variable_string_n(
+                &mut context,
+                name.as_ptr(),
+                data.as_ptr(),
+                data.len(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            )
+        };
+
+        assert!(ok);
+        assert_eq!(
+            context.get_variables().get("s"),
+            Some(&serde_json::Value::String("before\0after".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_context_add_variable_string_n_non_utf8_becomes_bytes() {
+        let mut context = Context::new();
+        let name = std::ffi::CString::new("b").unwrap();
+        let data = [0xff, 0x00, 0xfe];
+        let mut error_len = 0;
+
+        let ok = unsafe {
+            context_add_variable_string_n(
+                &mut context,
+                name.as_ptr(),
+                data.as_ptr(),
+                data.len(),
+                std::ptr::null_mut(),
+                &mut error_len,
+            )
+        };
+
+        assert!(ok);
+        assert!(context.get_variables().get("b").is_none());
+        assert_eq!(context.get_bytes_variables().get("b"), Some(&data.to_vec()));
+
+        // Re-adding the name as a string replaces the bytes value
+        context.add_variable("b".to_string(), serde_json::Value::Bool(true));
+        assert!(context.get_bytes_variables().get("b").is_none());
+    }
+
+    #[test]
+    fn test_context_add_variable_string_n_empty_and_null() {
+        let mut context = Context::new();
+        let name = std::ffi::CString::new("empty").unwrap();
+        let mut error_len = 0;
+
+        unsafe {
+            assert!(context_add_variable_string_n(
+                &mut context,
+                name.as_ptr(),
+                std::ptr::null(),
+                0,
+                std::ptr::null_mut(),
+                &mut error_len,
+            ));
+            assert!(!context_add_variable_string_n(
+                &mut context,
+                name.as_ptr(),
+                std::ptr::null(),
+                4,
+                std::ptr::null_mut(),
+                &mut error_len,
+            ));
+        }
+
+        assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        assert_eq!(
+            context.get_variables().get("empty"),
+            Some(&serde_json::Value::String(String::new()))
+        );
+    }
+
+    #[test]
+    fn test_context_add_variable_utf16() {
+        let mut context = Context::new();
+        let name = std::ffi::CString::new("u").unwrap();
+        let units: Vec<u16> = "héllo 🌍".encode_utf16().collect();
+        let mut error_buf = [0u8; 128];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            assert!(context_add_variable_utf16(
+                &mut context,
+                name.as_ptr(),
+                units.as_ptr(),
+                units.len(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(
+                context.get_variables().get("u"),
+                Some(&serde_json::Value::String("héllo 🌍".to_string()))
+            );
+
+            // Lone high surrogate
+            let invalid = [0xd83c_u16];
+            assert!(!context_add_variable_utf16(
+                &mut context,
+                name.as_ptr(),
+                invalid.as_ptr(),
+                invalid.len(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+        }
+
+        assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+        let message = std::str::from_utf8(error_buf.get(..error_len).unwrap()).unwrap();
+        assert!(message.contains("Invalid UTF-16 string"));
+    }
+
+    #[test]
+    fn test_context_add_variable_bytes_value() {
+        let mut context = Context::new();
+        let name = std::ffi::CString::new("raw").unwrap();
+        let data = [1u8, 2, 3];
+        let value = CelValue {
+            value_type: CelValueType::Bytes,
+            data: CelValueData {
+                bytes_val: ManuallyDrop::new(crate::CelBytesValue {
+                    ptr: data.as_ptr(),
+                    len: data.len(),
+                }),
+            },
+        };
+        let mut error_len = 0;
+
+        let ok = unsafe {
+            context_add_variable(
+                &mut context,
+                name.as_ptr(),
+                &value,
+                std::ptr::null_mut(),
+                &mut error_len,
+            )
+        };
+
+        assert!(ok);
+        assert_eq!(context.get_bytes_variables().get("raw"), Some(&data.to_vec()));
+
+        context.reset();
+        assert!(context.get_bytes_variables().is_empty());
+    }
+
+    #[test]
+    fn test_uint_variable_keeps_type_and_range() {
+        let mut context = Context::new();
+        let mut error_len = 0;
+        for (name, uint_val) in [("small", 1), ("max", u64::MAX)] {
+            let name = std::ffi::CString::new(name).unwrap();
+            let value = CelValue {
+                value_type: CelValueType::Uint,
+                data: CelValueData { uint_val },
+            };
+            assert!(unsafe {
+                context_add_variable(
+                    &mut context,
+                    name.as_ptr(),
+                    &value,
+                    std::ptr::null_mut(),
+                    &mut error_len,
+                )
+            });
+        }
+        assert_eq!(context.variable_type("small"), Some(CelValueType::Uint));
+
+        let mut program = crate::Program::new();
+        program
+            .compile("small == 1u && type(small) == uint && max == 18446744073709551615u")
+            .unwrap();
+        assert_eq!(program.execute(&context).unwrap(), cel_interpreter::Value::Bool(true));
+    }
+
+    #[test]
+    fn test_nested_uint_variables_keep_type_and_range() {
+        let uint = |uint_val| CelValue {
+            value_type: CelValueType::Uint,
+            data: CelValueData { uint_val },
+        };
+        let items = [uint(u64::MAX), uint(1)];
+        let list = CelValue {
+            value_type: CelValueType::List,
+            data: CelValueData {
+                list_val: ManuallyDrop::new(CelListValue {
+                    items: items.as_ptr(),
+                    len: items.len(),
+                }),
+            },
+        };
+        let key = b"max";
+        let keys = [CelValue {
+            value_type: CelValueType::String,
+            data: CelValueData {
+                string_val: ManuallyDrop::new(CelStringValue {
+                    ptr: key.as_ptr(),
+                    len: key.len(),
+                }),
+            },
+        }];
+        let values = [uint(u64::MAX)];
+        let map = CelValue {
+            value_type: CelValueType::Map,
+            data: CelValueData {
+                map_val: ManuallyDrop::new(CelMapValue {
+                    keys: keys.as_ptr(),
+                    values: values.as_ptr(),
+                    len: 1,
+                }),
+            },
+        };
+        let name = CelValue {
+            value_type: CelValueType::String,
+            data: CelValueData {
+                string_val: ManuallyDrop::new(CelStringValue {
+                    ptr: key.as_ptr(),
+                    len: key.len(),
+                }),
+            },
+        };
+
+        type Binder = unsafe extern "C" fn(
+            *mut Context,
+            *const c_char,
+            *const CelValue,
+            *mut u8,
+            *mut usize,
+        ) -> bool;
+        let mut context = Context::new();
+        let mut error_len = 0;
+        let bindings = [
+            ("list", context_add_variable as Binder, &list),
+            ("map", context_add_variable, &map),
+            ("limits.name", context_add_variable_path, &name),
+            ("limits.max", context_add_variable_path, &values[0]),
+            ("limits.one", context_add_variable_path, &items[1]),
+        ];
+        for (target, bind, value) in bindings {
+            let target = std::ffi::CString::new(target).unwrap();
+            assert!(unsafe {
+                bind(&mut context, target.as_ptr(), value, std::ptr::null_mut(), &mut error_len)
+            });
+        }
+        assert_eq!(context.variable_type("list"), Some(CelValueType::List));
+        assert_eq!(context.variable_type("limits"), Some(CelValueType::Map));
+
+        let mut program = crate::Program::new();
+        program
+            .compile(
+                "list == [18446744073709551615u, 1u] && type(list[1]) == uint \
+                 && map.max == 18446744073709551615u \
+                 && limits.max == 18446744073709551615u && type(limits.one) == uint \
+                 && limits.name == 'max'",
+            )
+            .unwrap();
+        assert_eq!(program.execute(&context).unwrap(), cel_interpreter::Value::Bool(true));
+    }
+
+    #[test]
+    fn test_non_finite_double_variables() {
+        let mut context = Context::new();
+        let name = std::ffi::CString::new("x").unwrap();
+        let nan = CelValue {
             value_type: CelValueType::Double,
             data: CelValueData {
                 double_val: f64::NAN,
             },
         };
-
-        let result = cel_value_to_json(&cel_value);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Invalid float value"));
+        let mut error_buf = [0u8; 128];
+        let mut error_len = error_buf.len();
+        let mut program = crate::Program::new();
+        program.compile("x != x && !(x < 1.0) && !(x >= 1.0)").unwrap();
+
+        unsafe {
+            assert!(context_add_variable(
+                &mut context,
+                name.as_ptr(),
+                &nan,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(program.execute(&context).unwrap(), cel_interpreter::Value::Bool(true));
+
+            assert!(context_set_reject_non_finite(&mut context, true));
+            assert!(!context_add_variable(
+                &mut context,
+                name.as_ptr(),
+                &nan,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+            assert!(!context_set_reject_non_finite(std::ptr::null_mut(), true));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
     }
 
     #[test]
-    fn test_copy_error_to_buffer() {
-        let error_msg = "Test error message";
-        let mut buffer = [0u8; 20];
-        let mut buffer_len = buffer.len();
-
-        copy_error_to_buffer(error_msg, buffer.as_mut_ptr(), &mut buffer_len);
+    fn test_bytes_variable_reaches_program() {
+        let mut context = Context::new();
+        context.add_bytes_variable("data".to_string(), vec![0xff, 0x00, 0x61]);
 
-        assert_eq!(buffer_len, error_msg.len());
-        assert_eq!(*buffer.get(buffer_len).unwrap(), 0); // null terminator
+        let mut program = crate::Program::new();
+        program.compile("size(data) == 3 && data == b'\\xff\\x00a'").unwrap();
 
-        let result_str = std::str::from_utf8(buffer.get(..buffer_len).unwrap()).unwrap();
-        assert_eq!(result_str, error_msg);
+        assert_eq!(program.execute(&context).unwrap(), cel_interpreter::Value::Bool(true));
     }
 
     #[test]
-    fn test_copy_error_to_buffer_truncation() {
-        let error_msg = "This is a very long error message that should be truncated";
-        let mut buffer = [0u8; 10];
-        let mut buffer_len = buffer.len();
-
-        copy_error_to_buffer(error_msg, buffer.as_mut_ptr(), &mut buffer_len);
-
-        assert_eq!(buffer_len, 9); // 10 - 1 for null terminator
-        assert_eq!(*buffer.get(buffer_len).unwrap(), 0); // null terminator
+    fn test_context_remove_variable() {
+        let mut context = Context::new();
+        context.add_variable("a".to_string(), serde_json::json!(1));
+        context.add_bytes_variable("b".to_string(), vec![1, 2]);
+        let a = std::ffi::CString::new("a").unwrap();
+        let b = std::ffi::CString::new("b").unwrap();
+
+        unsafe {
+            assert!(context_remove_variable(&mut context, a.as_ptr()));
+            assert!(!context_remove_variable(&mut context, a.as_ptr()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::Ok);
+            assert!(context_remove_variable(&mut context, b.as_ptr()));
+
+            assert!(!context_remove_variable(std::ptr::null_mut(), a.as_ptr()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+            assert!(!context_remove_variable(&mut context, std::ptr::null()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
 
-        let result_str = std::str::from_utf8(buffer.get(..buffer_len).unwrap()).unwrap();
-        assert_eq!(result_str, "This is a");
+        assert!(context.get_variables().is_empty());
+        assert!(context.get_bytes_variables().is_empty());
     }
 
     #[test]
-    fn test_copy_error_to_buffer_null_ptr() {
-        let error_msg = "Test error";
-        let mut buffer_len = 100usize;
-
-        // Should not crash with null pointer
-        copy_error_to_buffer(error_msg, std::ptr::null_mut(), &mut buffer_len);
+    fn test_context_has_variable_reports_type() {
+        let mut context = Context::new();
+        context.add_variable("i".to_string(), serde_json::json!(-1));
+        context.add_variable("u".to_string(), serde_json::json!(u64::MAX));
+        context.add_variable("d".to_string(), serde_json::json!(1.5));
+        context.add_variable("s".to_string(), serde_json::json!("x"));
+        context.add_variable("l".to_string(), serde_json::json!([1]));
+        context.add_variable("m".to_string(), serde_json::json!({"k": 1}));
+        context.add_variable("n".to_string(), serde_json::Value::Null);
+        context.add_bytes_variable("b".to_string(), vec![0xff]);
+
+        for (name, expected) in [
+            ("i", CelValueType::Int),
+            ("u", CelValueType::Uint),
+            ("d", CelValueType::Double),
+            ("s", CelValueType::String),
+            ("l", CelValueType::List),
+            ("m", CelValueType::Map),
+            ("n", CelValueType::Null),
+            ("b", CelValueType::Bytes),
+        ] {
+            let c_name = std::ffi::CString::new(name).unwrap();
+            let mut value_type = CelValueType::Type;
+            assert!(unsafe { context_has_variable(&context, c_name.as_ptr(), &mut value_type) });
+            assert_eq!(value_type, expected, "variable {name}");
+        }
 
-        // buffer_len should remain unchanged
-        assert_eq!(buffer_len, 100);
+        let missing = std::ffi::CString::new("missing").unwrap();
+        unsafe {
+            assert!(!context_has_variable(&context, missing.as_ptr(), std::ptr::null_mut()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::Ok);
+            assert!(!context_has_variable(
+                std::ptr::null_mut(),
+                missing.as_ptr(),
+                std::ptr::null_mut()
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
     }
 
     #[test]
-    fn test_context_add_variable_string() {
+    fn test_context_get_variable_roundtrip() {
         let mut context = Context::new();
-        let test_string = "Hello, CEL!";
-
-        // Test adding string variable
-        let json_value = serde_json::Value::String(test_string.to_string());
-        context.add_variable("greeting".to_string(), json_value);
+        context.add_variable("user".to_string(), serde_json::json!({"name": "ann", "tags": ["a"]}));
+        context.add_bytes_variable("raw".to_string(), vec![0, 0xff]);
+        let user = std::ffi::CString::new("user").unwrap();
+        let raw = std::ffi::CString::new("raw").unwrap();
+        let mut value = CelValue {
+            value_type: CelValueType::Null,
+            data: CelValueData { int_val: 0 },
+        };
+        let mut error_buf = [0u8; 128];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            assert!(context_get_variable(
+                &context,
+                user.as_ptr(),
+                &mut value,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(value.value_type, CelValueType::Map);
+            assert_eq!(value.data.map_val.len, 2);
+            crate::cel_value_free(&mut value);
+
+            assert!(context_get_variable(
+                &context,
+                raw.as_ptr(),
+                &mut value,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(value.value_type, CelValueType::Bytes);
+            let bytes = &value.data.bytes_val;
+            assert_eq!(std::slice::from_raw_parts(bytes.ptr, bytes.len), &[0, 0xff]);
+            crate::cel_value_free(&mut value);
+        }
 
-        let variables = context.get_variables();
-        assert_eq!(variables.len(), 1);
-        assert!(variables.contains_key("greeting"));
+        // The context keeps its own copy
+        assert!(context.get_variables().contains_key("user"));
+    }
 
-        if let Some(value) = variables.get("greeting") {
-            assert_eq!(value.as_str().unwrap(), test_string);
+    #[test]
+    fn test_context_get_variable_errors() {
+        let context = Context::new();
+        let missing = std::ffi::CString::new("missing").unwrap();
+        let mut value = CelValue {
+            value_type: CelValueType::Null,
+            data: CelValueData { int_val: 0 },
+        };
+        let mut error_buf = [0u8; 128];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            assert!(!context_get_variable(
+                &context,
+                missing.as_ptr(),
+                &mut value,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+            let message = std::str::from_utf8(error_buf.get(..error_len).unwrap()).unwrap();
+            assert_eq!(message, "Variable 'missing' is not bound");
+
+            error_len = error_buf.len();
+            assert!(!context_get_variable(
+                &context,
+                missing.as_ptr(),
+                std::ptr::null_mut(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
         }
     }
 
     #[test]
-    #[allow(clippy::indexing_slicing)]
-    fn test_context_add_variable_array() {
+    fn test_context_enumerate_variables() {
         let mut context = Context::new();
-        let array_value = serde_json::json!([1, 2, 3, "four", true]);
-
-        context.add_variable("items".to_string(), array_value);
-
-        let variables = context.get_variables();
-        assert_eq!(variables.len(), 1);
-        assert!(variables.contains_key("items"));
-
-        if let Some(value) = variables.get("items") {
-            assert!(value.is_array());
-            let arr = value.as_array().unwrap();
-            assert_eq!(arr.len(), 5);
-            assert_eq!(arr[0], 1);
-            assert_eq!(arr[3], "four");
+        context.add_variable("a".to_string(), serde_json::json!(1));
+        context.add_variable("bb".to_string(), serde_json::json!(2));
+        context.add_bytes_variable("ccc".to_string(), vec![3]);
+
+        let count = unsafe { context_variable_count(&context) };
+        assert_eq!(count, 3);
+
+        let mut names = (0..count)
+            .map(|index| {
+                let mut name = CelStringValue {
+                    ptr: std::ptr::null(),
+                    len: 0,
+                };
+                assert!(unsafe { context_variable_name_at(&context, index, &mut name) });
+                let bytes = unsafe { std::slice::from_raw_parts(name.ptr, name.len) };
+                String::from_utf8(bytes.to_vec()).unwrap()
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a", "bb", "ccc"]);
+
+        let mut name = CelStringValue {
+            ptr: std::ptr::null(),
+            len: 0,
+        };
+        unsafe {
+            assert!(!context_variable_name_at(&context, count, &mut name));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+            assert!(!context_variable_name_at(std::ptr::null(), 0, &mut name));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+            assert_eq!(context_variable_count(std::ptr::null()), 0);
         }
     }
 
     #[test]
-    fn test_context_add_variable_object() {
+    fn test_context_add_variable_path_ffi() {
         let mut context = Context::new();
-        let object_value = serde_json::json!({
-            "name": "John Doe",
-            "age": 30,
-            "active": true,
-            "score": 95.5
-        });
-
-        context.add_variable("user".to_string(), object_value);
+        let path = std::ffi::CString::new("a.b").unwrap();
+        let value = CelValue {
+            value_type: CelValueType::Int,
+            data: CelValueData { int_val: 5 },
+        };
+        let mut error_buf = [0u8; 128];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            assert!(context_add_variable_path(
+                &mut context,
+                path.as_ptr(),
+                &value,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+
+            let data = [1u8];
+            let bytes = CelValue {
+                value_type: CelValueType::Bytes,
+                data: CelValueData {
+                    bytes_val: ManuallyDrop::new(crate::CelBytesValue {
+                        ptr: data.as_ptr(),
+                        len: data.len(),
+                    }),
+                },
+            };
+            assert!(!context_add_variable_path(
+                &mut context,
+                path.as_ptr(),
+                &bytes,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+        }
 
-        let variables = context.get_variables();
-        assert_eq!(variables.len(), 1);
-        assert!(variables.contains_key("user"));
+        assert_eq!(context.get_variables().get("a"), Some(&serde_json::json!({"b": 5})));
+    }
 
-        if let Some(value) = variables.get("user") {
-            assert!(value.is_object());
-            let obj = value.as_object().unwrap();
-            assert_eq!(obj["name"], "John Doe");
-            assert_eq!(obj["age"], 30);
-            assert_eq!(obj["active"], true);
-            assert_eq!(obj["score"], 95.5);
+    unsafe extern "C" fn counting_resolver(
+        user_data: *mut c_void,
+        name: *const c_char,
+        out_value: *mut CelValue,
+    ) -> bool {
+        *user_data.cast::<usize>() += 1;
+
+        match CStr::from_ptr(name).to_str().unwrap() {
+            "lazy" => {
+                (*out_value).value_type = CelValueType::Int;
+                (*out_value).data.int_val = 40;
+                true
+            }
+            "label" => {
+                // Only has to outlive the callback
+                let label = b"resolved";
+                (*out_value).value_type = CelValueType::String;
+                (*out_value).data.string_val = ManuallyDrop::new(CelStringValue {
+                    ptr: label.as_ptr(),
+                    len: label.len(),
+                });
+                true
+            }
+            _ => false,
         }
     }
 
     #[test]
-    fn test_context_add_variable_nested_object() {
+    fn test_context_resolver_supplies_unbound_variables() {
+        let mut calls = 0usize;
         let mut context = Context::new();
-        let nested_object = serde_json::json!({
-            "user": {
-                "profile": {
-                    "name": "Alice",
-                    "preferences": {
-                        "theme": "dark",
-                        "notifications": true
-                    }
-                },
-                "permissions": ["read", "write"]
-            }
-        });
-
-        context.add_variable("data".to_string(), nested_object);
+        context.add_variable("bound".to_string(), serde_json::json!(2));
+        unsafe {
+            assert!(context_set_resolver(
+                &mut context,
+                Some(counting_resolver),
+                (&raw mut calls).cast()
+            ));
+        }
 
-        let variables = context.get_variables();
-        assert_eq!(variables.len(), 1);
+        let mut program = crate::Program::new();
+        program.compile("label + ':' + string(lazy + bound)").unwrap();
+        assert_eq!(
+            program.execute(&context).unwrap(),
+            cel_interpreter::Value::String(Arc::new("resolved:42".to_string()))
+        );
+        // Only the two unbound references reached the resolver
+        assert_eq!(calls, 2);
+
+        program.compile("unknown + 1").unwrap();
+        assert!(program.execute(&context).is_err());
+        assert_eq!(calls, 3);
+
+        // Comprehension variables are bound by the comprehension, not resolved
+        program.compile("[1, 2].all(x, x < lazy) && [bound].exists(y, y == 2)").unwrap();
+        assert_eq!(program.execute(&context).unwrap(), cel_interpreter::Value::Bool(true));
+        assert_eq!(calls, 4);
+
+        // Removing the resolver leaves the variable unbound again
+        context.clear_resolver();
+        program.compile("lazy").unwrap();
+        assert!(program.execute(&context).is_err());
+        assert_eq!(calls, 3);
+
+        unsafe {
+            assert!(!context_set_resolver(std::ptr::null_mut(), None, std::ptr::null_mut()));
+        }
+        assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+    }
 
-        if let Some(value) = variables.get("data") {
-            assert!(
-                value.get("user").and_then(|u| u.get("profile")).and_then(|p| p.get("name"))
-                    == Some(&serde_json::Value::String("Alice".to_string()))
-            );
-            assert!(
-                value
-                    .get("user")
-                    .and_then(|u| u.get("profile"))
-                    .and_then(|p| p.get("preferences"))
-                    .and_then(|pref| pref.get("theme"))
-                    == Some(&serde_json::Value::String("dark".to_string()))
-            );
-            assert!(value
-                .get("user")
-                .and_then(|u| u.get("permissions"))
-                .is_some_and(serde_json::Value::is_array));
+    unsafe extern "C" fn restricting_hook(
+        user_data: *mut c_void,
+        path: *const c_char,
+        out_value: *mut CelValue,
+    ) -> CelAttributeAccess {
+        (*user_data.cast::<Vec<String>>()).push(CStr::from_ptr(path).to_str().unwrap().into());
+
+        match CStr::from_ptr(path).to_str().unwrap() {
+            "user.ssn" => CelAttributeAccess::AttributeDeny,
+            "user.region" => {
+                (*out_value).value_type = CelValueType::Int;
+                (*out_value).data.int_val = 7;
+                CelAttributeAccess::AttributeReplace
+            }
+            _ => CelAttributeAccess::AttributeAllow,
         }
     }
 
     #[test]
-    fn test_context_variable_types_edge_cases() {
-        let mut context = Context::new(); // Test various numeric edge cases
-        context.add_variable(
-            "max_i64".to_string(),
-            serde_json::Value::Number(serde_json::Number::from(i64::MAX)),
-        );
-        context.add_variable(
-            "min_i64".to_string(),
-            serde_json::Value::Number(serde_json::Number::from(i64::MIN)),
-        );
-        context.add_variable(
-            "zero".to_string(),
-            serde_json::Value::Number(serde_json::Number::from(0)),
+    fn test_context_attribute_hook_decides_selections() {
+        let mut read = Vec::<String>::new();
+        let mut context = Context::new();
+        context.add_variable("user".to_string(), serde_json::json!({"name": "a", "ssn": "1"}));
+        unsafe {
+            assert!(context_set_attribute_hook(
+                &mut context,
+                Some(restricting_hook),
+                (&raw mut read).cast()
+            ));
+        }
+
+        let mut program = crate::Program::new();
+        program.compile("user.name + string(user.region)").unwrap();
+        assert_eq!(
+            program.execute(&context).unwrap(),
+            cel_interpreter::Value::String(Arc::new("a7".to_string()))
         );
-        context.add_variable("float_val".to_string(), serde_json::json!(f64::MAX)); // Close to f64::MAX
-        context.add_variable(
-            "small_float".to_string(),
-            serde_json::json!(2.225_073_858_507_201_4e-308),
-        ); // Close to f64::MIN_POSITIVE
+        program.compile("user.ssn == '1'").unwrap();
+        assert!(program
+            .execute(&context)
+            .unwrap_err()
+            .contains("access to 'user.ssn' is denied"));
+        assert_eq!(read, ["user.name", "user.region", "user.ssn"]);
+
+        unsafe {
+            assert!(context_set_attribute_hook(&mut context, None, std::ptr::null_mut()));
+        }
+        assert_eq!(program.execute(&context).unwrap(), cel_interpreter::Value::Bool(true));
+        assert_eq!(read.len(), 3);
+
+        unsafe {
+            assert!(!context_set_attribute_hook(std::ptr::null_mut(), None, std::ptr::null_mut()));
+        }
+        assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+    }
 
-        // Test empty collections
-        context.add_variable("empty_array".to_string(), serde_json::json!([]));
-        context.add_variable("empty_object".to_string(), serde_json::json!({}));
+    unsafe extern "C" fn lookup_function(
+        user_data: *mut c_void,
+        args: *const CelValue,
+        args_len: usize,
+        out_value: *mut CelValue,
+        errbuf: *mut u8,
+        errbuf_len: *mut usize,
+    ) -> bool {
+        *user_data.cast::<usize>() += 1;
+
+        let args = std::slice::from_raw_parts(args, args_len);
+        if let [arg] = args {
+            let id = &*arg.data.string_val;
+            if arg.value_type == CelValueType::String && raw_bytes(id.ptr, id.len) == b"42" {
+                // Only has to outlive the callback
+                let name = b"answer";
+                (*out_value).value_type = CelValueType::String;
+                (*out_value).data.string_val = ManuallyDrop::new(CelStringValue {
+                    ptr: name.as_ptr(),
+                    len: name.len(),
+                });
+                return true;
+            }
+        }
 
-        // Test null value
-        context.add_variable("null_value".to_string(), serde_json::Value::Null);
+        let message = b"no such id";
+        let len = message.len().min(*errbuf_len);
+        std::ptr::copy_nonoverlapping(message.as_ptr(), errbuf, len);
+        *errbuf_len = len;
+        false
+    }
 
-        let variables = context.get_variables();
-        assert_eq!(variables.len(), 8);
+    #[test]
+    fn test_context_functions_call_back() {
+        let mut calls = 0usize;
+        let name = CString::new("lookup").unwrap();
+        let mut context = Context::new();
+        unsafe {
+            assert!(context_add_function(
+                &mut context,
+                name.as_ptr(),
+                Some(lookup_function),
+                (&raw mut calls).cast()
+            ));
+        }
 
+        let mut program = crate::Program::new();
+        program.compile("lookup(string(40 + 2)) + '!'").unwrap();
         assert_eq!(
-            variables.get("max_i64").unwrap(),
-            &serde_json::Value::Number(serde_json::Number::from(i64::MAX))
+            program.execute(&context).unwrap(),
+            cel_interpreter::Value::String(Arc::new("answer!".to_string()))
         );
+        assert_eq!(calls, 1);
+
+        program.compile("lookup('7')").unwrap();
+        let error = program.execute(&context).unwrap_err();
+        assert!(error.contains("no such id"), "{error}");
+        assert_eq!(calls, 2);
+
+        unsafe {
+            assert!(context_remove_function(&mut context, name.as_ptr()));
+            assert!(!context_remove_function(&mut context, name.as_ptr()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::Ok);
+            assert!(!context_add_function(&mut context, name.as_ptr(), None, std::ptr::null_mut()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
+        assert!(program.execute(&context).is_err());
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn test_context_overloads_dispatch_by_type() {
+        let mut calls = 0usize;
+        let name = CString::new("lookup").unwrap();
+        let string = [CelValueType::String];
+        let mut context = Context::new();
+        unsafe {
+            assert!(context_add_overload(
+                &mut context,
+                name.as_ptr(),
+                string.as_ptr(),
+                string.len(),
+                CelValueType::String,
+                Some(lookup_function),
+                (&raw mut calls).cast()
+            ));
+            assert!(context_add_overload(
+                &mut context,
+                name.as_ptr(),
+                [CelValueType::Bool].as_ptr(),
+                1,
+                CelValueType::Int,
+                Some(lookup_function),
+                (&raw mut calls).cast()
+            ));
+        }
+
+        let mut program = crate::Program::new();
+        program.compile("lookup('42')").unwrap();
         assert_eq!(
-            variables.get("min_i64").unwrap(),
-            &serde_json::Value::Number(serde_json::Number::from(i64::MIN))
+            program.execute(&context).unwrap(),
+            cel_interpreter::Value::String(Arc::new("answer".to_string()))
         );
+        assert_eq!(calls, 1);
+
+        // No overload takes an int, so the callback is not reached
+        program.compile("lookup(42)").unwrap();
+        let error = program.execute(&context).unwrap_err();
+        assert!(error.contains("no overload takes (int)"), "{error}");
+        assert_eq!(calls, 1);
+
+        unsafe {
+            assert!(!context_add_overload(
+                &mut context,
+                name.as_ptr(),
+                std::ptr::null(),
+                1,
+                CelValueType::Int,
+                Some(lookup_function),
+                std::ptr::null_mut()
+            ));
+        }
+        assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+    }
+
+    #[test]
+    fn test_context_member_functions_take_receiver() {
+        let mut calls = 0usize;
+        let name = CString::new("lookup").unwrap();
+        let mut context = Context::new();
+        unsafe {
+            assert!(context_add_member_function(
+                &mut context,
+                name.as_ptr(),
+                Some(lookup_function),
+                (&raw mut calls).cast()
+            ));
+        }
+
+        let mut program = crate::Program::new();
+        program.compile("'42'.lookup()").unwrap();
         assert_eq!(
-            variables.get("zero").unwrap(),
-            &serde_json::Value::Number(serde_json::Number::from(0))
+            program.execute(&context).unwrap(),
+            cel_interpreter::Value::String(Arc::new("answer".to_string()))
         );
-        assert!(variables.get("empty_array").unwrap().as_array().unwrap().is_empty());
-        assert!(variables.get("empty_object").unwrap().as_object().unwrap().is_empty());
-        assert!(variables.get("null_value").unwrap().is_null());
+        assert_eq!(calls, 1);
+
+        program.compile("lookup('42')").unwrap();
+        let error = program.execute(&context).unwrap_err();
+        assert!(error.contains("can only be called as a method"), "{error}");
+        assert_eq!(calls, 1);
     }
 
     #[test]
-    fn test_cel_value_to_json_string() {
-        let test_string = "Test string with special chars: áéíóú, 你好, 🚀";
-        let string_ptr = crate::store_string_in_pool(test_string);
-
-        let cel_value = super::CelValue {
-            value_type: super::CelValueType::String,
+    fn test_context_variadic_functions_fill_in_defaults() {
+        let mut calls = 0usize;
+        let name = CString::new("lookup").unwrap();
+        let id = b"42";
+        let defaults = [CelValue {
+            value_type: CelValueType::String,
             data: CelValueData {
                 string_val: ManuallyDrop::new(CelStringValue {
-                    ptr: string_ptr,
-                    len: test_string.len(),
+                    ptr: id.as_ptr(),
+                    len: id.len(),
                 }),
             },
+        }];
+        let arity = CelArity {
+            min_args: 0,
+            max_args: 1,
+            defaults: defaults.as_ptr(),
+            defaults_len: 1,
         };
+        let mut errbuf = [0u8; 64];
+        let mut errbuf_len = errbuf.len();
+        let mut context = Context::new();
+        unsafe {
+            assert!(context_add_variadic_function(
+                &mut context,
+                name.as_ptr(),
+                &arity,
+                Some(lookup_function),
+                (&raw mut calls).cast(),
+                errbuf.as_mut_ptr(),
+                &mut errbuf_len
+            ));
+        }
 
-        let result = cel_value_to_json(&cel_value);
-        assert!(result.is_ok());
+        let mut program = crate::Program::new();
+        program.compile("lookup() + lookup('42')").unwrap();
+        assert_eq!(
+            program.execute(&context).unwrap(),
+            cel_interpreter::Value::String(Arc::new("answeranswer".to_string()))
+        );
+        assert_eq!(calls, 2);
 
-        let json_value = result.unwrap();
-        assert!(json_value.is_string());
-        assert_eq!(json_value.as_str().unwrap(), test_string);
+        program.compile("lookup('42', '42')").unwrap();
+        let error = program.execute(&context).unwrap_err();
+        assert!(error.contains("takes 0 to 1 arguments, got 2"), "{error}");
+        assert_eq!(calls, 2);
 
-        crate::release_string_from_pool(string_ptr);
+        let arity = CelArity {
+            min_args: 1,
+            ..arity
+        };
+        let mut errbuf_len = errbuf.len();
+        unsafe {
+            assert!(!context_add_variadic_function(
+                &mut context,
+                name.as_ptr(),
+                &arity,
+                Some(lookup_function),
+                std::ptr::null_mut(),
+                errbuf.as_mut_ptr(),
+                &mut errbuf_len
+            ));
+        }
+        assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+        assert_eq!(&errbuf[..errbuf_len], b"more defaults (1) than optional arguments (0)");
     }
 
     #[test]
-    fn test_cel_value_to_json_edge_cases() {
-        // Test special floating point values
-        let nan_value = super::CelValue {
-            value_type: super::CelValueType::Double,
-            data: CelValueData {
-                double_val: f64::NAN,
-            },
-        };
+    fn test_context_snapshot_ffi() {
+        let mut context = Context::new();
+        context.add_variable("x".to_string(), serde_json::json!(1));
+
+        unsafe {
+            let snapshot = context_snapshot(&mut context);
+            assert!(!snapshot.is_null());
+
+            context.add_variable("x".to_string(), serde_json::json!(2));
+            assert!(context_restore(&mut context, snapshot));
+            assert_eq!(context.get_variables().get("x"), Some(&serde_json::json!(1)));
+
+            let mut other = Context::new();
+            assert!(!context_restore(&mut other, snapshot));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+            assert!(!context_restore(&mut context, std::ptr::null()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+            assert!(context_snapshot(std::ptr::null_mut()).is_null());
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+
+            context_snapshot_free(snapshot);
+            context_snapshot_free(std::ptr::null_mut());
+        }
+    }
 
-        let infinity_value = super::CelValue {
-            value_type: super::CelValueType::Double,
-            data: CelValueData {
-                double_val: f64::INFINITY,
-            },
-        };
+    #[test]
+    fn test_context_set_max_depth() {
+        let mut context = Context::new();
+        let mut program = crate::Program::new();
+        program.compile("[[[1]]]").unwrap();
 
-        let neg_infinity_value = super::CelValue {
-            value_type: super::CelValueType::Double,
-            data: CelValueData {
-                double_val: f64::NEG_INFINITY,
-            },
+        unsafe {
+            assert!(context_set_max_depth(&mut context, 3));
+            let error = program.execute(&context).unwrap_err();
+            assert!(error.contains("expression too deep"), "{error}");
+
+            assert!(context_set_max_depth(&mut context, 4));
+            assert!(program.execute(&context).is_ok());
+
+            assert!(!context_set_max_depth(std::ptr::null_mut(), 4));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
+    }
+
+    #[test]
+    fn test_context_set_missing_keys() {
+        let mut context = Context::new();
+        context.add_variable("user".to_string(), serde_json::json!({"name": "a"}));
+        let mut program = crate::Program::new();
+        program.compile("user.age").unwrap();
+        let mut value = CelValue {
+            value_type: CelValueType::Null,
+            data: CelValueData { int_val: 0 },
         };
+        let mut errbuf = [0u8; 256];
+        let mut errbuf_len = errbuf.len();
+
+        unsafe {
+            assert!(context_set_missing_keys(&mut context, CelMissingKeys::MissingKeyNull));
+            assert_eq!(program.execute(&context).unwrap(), CelRustValue::Null);
+
+            assert!(context_set_missing_keys(&mut context, CelMissingKeys::MissingKeyUnknown));
+            assert!(!crate::program_execute(
+                &program,
+                &context,
+                &mut value,
+                errbuf.as_mut_ptr(),
+                &mut errbuf_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::Unknown);
+            let error = std::str::from_utf8(&errbuf[..errbuf_len]).unwrap();
+            assert_eq!(error, "Result is unknown: missing user.age");
+
+            assert!(!context_set_missing_keys(
+                std::ptr::null_mut(),
+                CelMissingKeys::MissingKeyError
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
+    }
 
-        // NaN should fail conversion
-        assert!(cel_value_to_json(&nan_value).is_err());
+    #[test]
+    fn test_context_set_null_propagation() {
+        let mut context = Context::new();
+        context.add_variable("price".to_string(), serde_json::Value::Null);
+        let mut program = crate::Program::new();
+        program.compile("price * 2 > 10").unwrap();
 
-        // Infinity values should also fail for JSON compatibility
-        assert!(cel_value_to_json(&infinity_value).is_err());
-        assert!(cel_value_to_json(&neg_infinity_value).is_err());
+        unsafe {
+            assert!(program.execute(&context).is_err());
+            assert!(context_set_null_propagation(&mut context, true));
+            assert_eq!(program.execute(&context).unwrap(), CelRustValue::Null);
+
+            assert!(!context_set_null_propagation(std::ptr::null_mut(), true));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
     }
 
     #[test]
-    fn test_copy_error_to_buffer_exact_fit() {
-        let error_msg = "exact"; // 5 characters
-        let mut buffer = [0u8; 6]; // Exactly enough space including null terminator
-        let mut buffer_len = buffer.len();
+    fn test_context_set_numeric_promotion() {
+        let mut context = Context::new();
+        context.add_variable("count".to_string(), serde_json::json!(1));
+        let mut program = crate::Program::new();
+        program.compile("count < 1.5 && count + 0.5 == 1.5").unwrap();
 
-        copy_error_to_buffer(error_msg, buffer.as_mut_ptr(), &mut buffer_len);
+        unsafe {
+            assert!(context_set_numeric_promotion(&mut context, true));
+            assert_eq!(program.execute(&context).unwrap(), CelRustValue::Bool(true));
 
-        assert_eq!(buffer_len, 5);
-        assert_eq!(buffer[5], 0); // null terminator
+            assert!(!context_set_numeric_promotion(std::ptr::null_mut(), true));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
+    }
 
-        let result_str = std::str::from_utf8(buffer.get(..buffer_len).unwrap()).unwrap();
-        assert_eq!(result_str, error_msg);
+    #[test]
+    fn test_context_set_soft_errors() {
+        let name = CString::new("lookup").unwrap();
+        let mut calls = 0usize;
+        let mut context = Context::new();
+        let mut program = crate::Program::new();
+        program.compile("lookup('7') && false").unwrap();
+
+        unsafe {
+            assert!(context_add_function(
+                &mut context,
+                name.as_ptr(),
+                Some(lookup_function),
+                (&raw mut calls).cast()
+            ));
+            let error = program.execute(&context).unwrap_err();
+            assert!(error.contains("no such id"), "{error}");
+
+            assert!(context_set_soft_errors(&mut context, true));
+            assert_eq!(program.execute(&context).unwrap(), CelRustValue::Bool(false));
+            program.compile("lookup('7') || false").unwrap();
+            let error = program.execute(&context).unwrap_err();
+            assert!(error.contains("no such id"), "{error}");
+
+            assert!(!context_set_soft_errors(std::ptr::null_mut(), true));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
     }
 
     #[test]
-    fn test_copy_error_to_buffer_single_char() {
-        let error_msg = "x";
-        let mut buffer = [0u8; 2];
-        let mut buffer_len = buffer.len();
+    fn test_context_set_max_memory() {
+        let mut context = Context::new();
+        context.add_variable("s".to_string(), serde_json::json!("x".repeat(100)));
+        let mut program = crate::Program::new();
+        program.compile("size(s + s + s)").unwrap();
+
+        unsafe {
+            assert!(context_set_max_memory(&mut context, 1_000));
+            assert_eq!(program.execute(&context).unwrap(), CelRustValue::Int(300));
+
+            assert!(context_set_max_memory(&mut context, 400));
+            assert!(matches!(
+                program.execute_cancellable(&context, None),
+                Err(crate::api::Error::MemoryLimit {
+                    limit: 400,
+                    allocated: 500
+                })
+            ));
+
+            assert!(!context_set_max_memory(std::ptr::null_mut(), 0));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
+    }
 
-        copy_error_to_buffer(error_msg, buffer.as_mut_ptr(), &mut buffer_len);
+    #[test]
+    fn test_context_set_max_iterations() {
+        let mut context = Context::new();
+        context.add_variable("xs".to_string(), serde_json::json!([1, 2, 3]));
+        let mut program = crate::Program::new();
+        program.compile("xs.all(x, xs.exists(y, y >= x))").unwrap();
 
-        assert_eq!(buffer_len, 1);
-        assert_eq!(buffer[1], 0);
+        unsafe {
+            assert!(context_set_max_iterations(&mut context, 9));
+            assert_eq!(program.execute(&context).unwrap(), CelRustValue::Bool(true));
 
-        let result_str = std::str::from_utf8(buffer.get(..buffer_len).unwrap()).unwrap();
-        assert_eq!(result_str, "x");
+            assert!(context_set_max_iterations(&mut context, 5));
+            let result = crate::program_execute_alloc(&program, &context);
+            assert_eq!(crate::cel_result_error_code(result), CelErrorCode::IterationLimit);
+            crate::cel_result_free(result);
+
+            assert!(!context_set_max_iterations(std::ptr::null_mut(), 0));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
     }
 
     #[test]
-    fn test_copy_error_to_buffer_empty_message() {
-        let error_msg = "";
-        let mut buffer = [0u8; 10];
-        let mut buffer_len = buffer.len();
+    fn test_context_audit_log() {
+        let mut context = Context::new();
+        context.add_variable("n".to_string(), serde_json::json!(0));
+        let mut program = crate::Program::new();
+        program.compile("10 / n").unwrap();
+        let mut out = CelStringValue {
+            ptr: std::ptr::null(),
+            len: 0,
+        };
 
-        copy_error_to_buffer(error_msg, buffer.as_mut_ptr(), &mut buffer_len);
+        unsafe {
+            assert!(context_set_audit_log(&mut context, 4));
+            assert!(program.execute(&context).is_err());
+            context.add_variable("n".to_string(), serde_json::json!(5));
+            assert!(program.execute(&context).is_ok());
+
+            assert!(context_take_audit_log(&context, &mut out));
+            let json = std::slice::from_raw_parts(out.ptr, out.len);
+            let records: serde_json::Value = serde_json::from_slice(json).unwrap();
+            crate::cel_string_release(out.ptr);
+            let [failed, succeeded] = records.as_array().unwrap().as_slice() else {
+                panic!("unexpected records: {records}");
+            };
+            assert!(failed["error"].is_string() && failed.get("result").is_none());
+            assert_eq!(succeeded["result"], 2);
+            assert_eq!(succeeded["variables"], serde_json::json!(["n"]));
+            assert!(succeeded["expression_hash"].as_str().is_some_and(|hash| hash.len() == 16));
+
+            assert!(context_take_audit_log(&context, &mut out));
+            assert_eq!(std::slice::from_raw_parts(out.ptr, out.len), b"[]");
+            crate::cel_string_release(out.ptr);
+
+            assert!(!context_take_audit_log(&context, std::ptr::null_mut()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+            assert!(!context_set_audit_log(std::ptr::null_mut(), 1));
+        }
+    }
 
-        assert_eq!(buffer_len, 0);
-        assert_eq!(buffer[0], 0); // null terminator at start
+    #[test]
+    fn test_context_set_deterministic() {
+        let mut context = Context::new();
+        context.add_variable("m".to_string(), serde_json::json!({"b": 2, "a": 1, "c": 3}));
+        let mut program = crate::Program::new();
+        program
+            .compile("m.map(k, k) == ['a', 'b', 'c'] && now() == timestamp('1970-01-01T00:00:00Z')")
+            .unwrap();
+
+        unsafe {
+            assert!(context_set_deterministic(&mut context, true));
+            assert!(program.execute(&context).unwrap_err().contains("set the time"));
+            assert!(context_set_now(&mut context, 0, 0));
+            assert_eq!(program.execute(&context).unwrap(), CelRustValue::Bool(true));
+
+            assert!(!context_set_deterministic(std::ptr::null_mut(), true));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
     }
 
     #[test]
-    fn test_copy_error_to_buffer_minimal_space() {
-        let error_msg = "Error";
-        let mut buffer = [0u8; 1]; // Only space for null terminator
-        let mut buffer_len = buffer.len();
+    fn test_context_time_variables() {
+        let mut context = Context::new();
+        let mut error_len = 0;
+        let mut created = CelValue::null();
+        let mut ttl = CelValue::null();
+        let created_text = CString::new("2024-01-01T00:00:00Z").unwrap();
+        let ttl_text = CString::new("1h30m").unwrap();
+
+        unsafe {
+            assert!(crate::cel_value_timestamp_from_rfc3339(
+                created_text.as_ptr(),
+                &mut created,
+                std::ptr::null_mut(),
+                &mut error_len,
+            ));
+            assert!(crate::cel_value_duration_from_string(
+                ttl_text.as_ptr(),
+                &mut ttl,
+                std::ptr::null_mut(),
+                &mut error_len,
+            ));
+            for (name, value) in [("created", &created), ("ttl", &ttl)] {
+                let name = CString::new(name).unwrap();
+                assert!(context_add_variable(
+                    &mut context,
+                    name.as_ptr(),
+                    value,
+                    std::ptr::null_mut(),
+                    &mut error_len,
+                ));
+            }
 
-        copy_error_to_buffer(error_msg, buffer.as_mut_ptr(), &mut buffer_len);
+            let path = CString::new("request.created").unwrap();
+            assert!(!context_add_variable_path(
+                &mut context,
+                path.as_ptr(),
+                &created,
+                std::ptr::null_mut(),
+                &mut error_len,
+            ));
+        }
 
-        assert_eq!(buffer_len, 0); // No space for characters
-        assert_eq!(buffer[0], 0); // null terminator
+        assert_eq!(context.variable_type("created"), Some(CelValueType::Timestamp));
+        let mut program = crate::Program::new();
+        program
+            .compile("created + ttl == timestamp('2024-01-01T01:30:00Z') && ttl > duration('1h')")
+            .unwrap();
+        assert_eq!(program.execute(&context).unwrap(), cel_interpreter::Value::Bool(true));
     }
 
     #[test]
-    fn test_context_unicode_variable_names() {
+    fn test_context_set_now() {
         let mut context = Context::new();
+        let mut program = crate::Program::new();
+        program.compile("now() == timestamp('1970-01-02T00:00:00Z')").unwrap();
 
-        // Test Unicode variable names
-        context.add_variable("变量".to_string(), serde_json::json!(42));
-        context.add_variable("переменная".to_string(), serde_json::json!("hello"));
-        context.add_variable("変数".to_string(), serde_json::json!(true));
-        context.add_variable("متغير".to_string(), serde_json::json!(std::f64::consts::PI));
+        unsafe {
+            assert!(context_set_now(&mut context, 86_400, 0));
+            assert_eq!(program.execute(&context).unwrap(), cel_interpreter::Value::Bool(true));
 
-        let variables = context.get_variables();
-        assert_eq!(variables.len(), 4);
+            assert!(!context_set_now(&mut context, 0, 1_000_000_000));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+            assert_eq!(context.now(), Some((86_400, 0)));
 
-        assert_eq!(
-            variables.get("变量").unwrap(),
-            &serde_json::Value::Number(serde_json::Number::from(42))
-        );
-        assert_eq!(
-            variables.get("переменная").unwrap(),
-            &serde_json::Value::String("hello".to_string())
-        );
-        assert_eq!(variables.get("変数").unwrap(), &serde_json::Value::Bool(true));
-        assert_eq!(
-            variables.get("متغير").unwrap(),
-            &serde_json::Value::Number(serde_json::Number::from_f64(std::f64::consts::PI).unwrap())
-        );
+            assert!(context_clear_now(&mut context));
+            assert_eq!(program.execute(&context).unwrap(), cel_interpreter::Value::Bool(false));
+
+            assert!(!context_set_now(std::ptr::null_mut(), 0, 0));
+            assert!(!context_clear_now(std::ptr::null_mut()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
     }
 
     #[test]
-    fn test_context_large_number_of_variables() {
+    fn test_context_json_round_trip() {
         let mut context = Context::new();
+        context.add_variable("n".to_string(), serde_json::json!(1));
+        context.add_bytes_variable("raw".to_string(), b"\xff".to_vec());
+        let mut json = CelStringValue {
+            ptr: std::ptr::null(),
+            len: 0,
+        };
+        let mut error_buf = [0u8; 128];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            assert!(context_to_json(&context, &mut json, error_buf.as_mut_ptr(), &mut error_len));
+            let text = CString::new(raw_bytes(json.ptr, json.len)).unwrap();
+            assert!(crate::cel_string_release(json.ptr));
+
+            let restored = context_from_json(text.as_ptr(), error_buf.as_mut_ptr(), &mut error_len);
+            assert!(!restored.is_null());
+            assert_eq!((*restored).variable_type("raw"), Some(CelValueType::Bytes));
+            assert_eq!((*restored).variable_type("n"), Some(CelValueType::Int));
+            context_free(restored);
+
+            let invalid = CString::new("{\"values\": {\"n\": 1}}").unwrap();
+            error_len = error_buf.len();
+            assert!(context_from_json(invalid.as_ptr(), error_buf.as_mut_ptr(), &mut error_len)
+                .is_null());
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+            let error = std::str::from_utf8(error_buf.get(..error_len).unwrap()).unwrap();
+            assert!(error.starts_with("Variable 'n': Invalid tagged value"), "{error}");
+        }
+    }
 
-        // Add many variables
-        for i in 0..1000 {
-            let name = format!("var_{i}");
-            let value = serde_json::json!({
-                "index": i,
-                "even": i % 2 == 0,
-                "name": format!("Variable number {}", i)
+    #[test]
+    fn test_context_freeze() {
+        let context = context_new();
+        let name = CString::new("limit").unwrap();
+        let value = CelValue {
+            value_type: CelValueType::Int,
+            data: CelValueData { int_val: 10 },
+        };
+        let mut error_buf = [0u8; 64];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            assert!(context_add_variable(
+                context,
+                name.as_ptr(),
+                &value,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert!(!context_is_frozen(context));
+            let frozen = context_freeze(context);
+            assert_eq!(frozen, context.cast_const());
+            assert!(context_is_frozen(frozen));
+
+            assert!(!context_add_variable(
+                context,
+                name.as_ptr(),
+                &value,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::Frozen);
+            assert_eq!(error_buf.get(..error_len).unwrap(), b"Context is frozen");
+
+            context_reset(context);
+            assert_eq!(crate::cel_last_error(), CelErrorCode::Frozen);
+            assert!(!context_remove_variable(context, name.as_ptr()));
+            assert!(!context_set_max_depth(context, 1));
+            assert!(context_snapshot(context).is_null());
+            assert_eq!(crate::cel_last_error(), CelErrorCode::Frozen);
+            assert!(context_has_variable(frozen, name.as_ptr(), std::ptr::null_mut()));
+
+            let shared = &*frozen;
+            std::thread::scope(|scope| {
+                for _ in 0..4 {
+                    scope.spawn(|| {
+                        let mut program = crate::Program::new();
+                        program.compile("limit * 2").unwrap();
+                        assert_eq!(program.execute(shared).unwrap(), CelRustValue::Int(20));
+                    });
+                }
             });
-            context.add_variable(name, value);
-        }
 
-        let variables = context.get_variables();
-        assert_eq!(variables.len(), 1000);
+            context_free(context);
+        }
+    }
 
-        // Verify some random variables
-        assert_eq!(
-            variables.get("var_0").unwrap().get("index").unwrap(),
-            &serde_json::Value::Number(serde_json::Number::from(0))
-        );
-        assert_eq!(
-            variables.get("var_0").unwrap().get("even").unwrap(),
-            &serde_json::Value::Bool(true)
-        );
-        assert_eq!(
-            variables.get("var_500").unwrap().get("index").unwrap(),
-            &serde_json::Value::Number(serde_json::Number::from(500))
-        );
-        assert_eq!(
-            variables.get("var_500").unwrap().get("even").unwrap(),
-            &serde_json::Value::Bool(true)
-        );
-        assert_eq!(
-            variables.get("var_999").unwrap().get("index").unwrap(),
-            &serde_json::Value::Number(serde_json::Number::from(999))
-        );
-        assert_eq!(
-            variables.get("var_999").unwrap().get("even").unwrap(),
-            &serde_json::Value::Bool(false)
-        );
+    #[test]
+    fn test_context_add_variable_string_borrowed() {
+        let context = context_new();
+        let name = CString::new("body").unwrap();
+        let body = b"GET /index.html".to_vec();
+        let mut error_buf = [0u8; 128];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            assert!(context_add_variable_string_borrowed(
+                context,
+                name.as_ptr(),
+                body.as_ptr(),
+                body.len(),
+                42,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            let mut program = crate::Program::new();
+            program.compile("body.startsWith('GET ')").unwrap();
+            assert_eq!(program.execute(&*context).unwrap(), CelRustValue::Bool(true));
+
+            assert!(!context_add_variable_string_borrowed(
+                context,
+                name.as_ptr(),
+                [0xffu8].as_ptr(),
+                1,
+                42,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+            assert!(context_has_variable(context, name.as_ptr(), std::ptr::null_mut()));
+
+            assert_eq!(context_invalidate_borrowed(context, 7), 0);
+            assert_eq!(context_invalidate_borrowed(context, 42), 1);
+            assert!(!context_has_variable(context, name.as_ptr(), std::ptr::null_mut()));
+            assert_eq!(context_invalidate_borrowed(std::ptr::null_mut(), 42), 0);
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+
+            context_free(context);
+        }
     }
 }
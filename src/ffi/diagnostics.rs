@@ -0,0 +1,138 @@
+//! Validation reporting every error and warning for an expression
+
+use super::error::{contain, report};
+use super::program::compile_options;
+use super::result::CelEvalResult;
+use super::{CelCompileOptions, CelErrorCode};
+use crate::api::{self, Value as CelRustValue};
+use cel_interpreter::objects::{Key, Map};
+use std::collections::HashMap;
+use std::ffi::{c_char, CStr};
+use std::sync::Arc;
+
+/// Validate a CEL expression into a newly allocated list of diagnostics
+///
+/// Unlike `program_validate_with_options`, which stops at the first error, this
+/// reports the compile error, if any, followed by warnings such as comparisons that
+/// are always true, calls to deprecated functions and shadowed comprehension
+/// variables. Each element is a map with `severity` (`"error"` or `"warning"`),
+/// `message`, `line`, `column`, `offset` and `length`, located as in
+/// `program_compile_error`; an empty list means the expression is clean. The result
+/// fails with `InvalidArgument` if `options` names an unknown macro or `expression` is
+/// not valid UTF-8, or with `NullArgument` if `expression` is null, and is released
+/// with `cel_result_free`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `expression` is either null or a valid null-terminated C string
+/// - `options` is either null or a valid pointer to a `CelCompileOptions`
+#[no_mangle]
+pub unsafe extern "C" fn program_validate_full(
+    expression: *const c_char,
+    options: *const CelCompileOptions,
+) -> *mut CelEvalResult {
+    contain(std::ptr::null_mut(), || {
+        let result = validate_full(expression, options);
+        report(result.code());
+        result.into_raw()
+    })
+}
+
+unsafe fn validate_full(
+    expression: *const c_char,
+    options: *const CelCompileOptions,
+) -> CelEvalResult {
+    if expression.is_null() {
+        return CelEvalResult::from_error(CelErrorCode::NullArgument, "Expression is null");
+    }
+    let Ok(expression) = CStr::from_ptr(expression).to_str() else {
+        let error = "Expression must be valid UTF-8";
+        return CelEvalResult::from_error(CelErrorCode::InvalidArgument, error);
+    };
+    let options = match compile_options(options) {
+        Ok(options) => options,
+        Err((code, e)) => return CelEvalResult::from_error(code, &e),
+    };
+
+    let diagnostics = api::validate_full(expression, &options);
+    CelEvalResult::from_execution(Ok(CelRustValue::List(Arc::new(
+        diagnostics.iter().map(diagnostic_value).collect(),
+    ))))
+}
+
+/// A diagnostic as a map from its field names to their values
+fn diagnostic_value(diagnostic: &api::Diagnostic) -> CelRustValue {
+    let int = |n: usize| CelRustValue::Int(i64::try_from(n).unwrap_or(i64::MAX));
+    let fields = [
+        (
+            "severity",
+            CelRustValue::String(Arc::new(diagnostic.severity.as_str().to_string())),
+        ),
+        ("message", CelRustValue::String(Arc::new(diagnostic.message.clone()))),
+        ("line", int(diagnostic.line)),
+        ("column", int(diagnostic.column)),
+        ("offset", int(diagnostic.offset)),
+        ("length", int(diagnostic.length)),
+    ];
+    let map: HashMap<Key, CelRustValue> = fields
+        .into_iter()
+        .map(|(name, value)| (Key::String(Arc::new(name.to_string())), value))
+        .collect();
+    CelRustValue::Map(Map { map: Arc::new(map) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CelValueType;
+    use crate::{cel_last_error, cel_result_free, cel_result_is_ok, cel_result_value};
+    use std::ffi::CString;
+
+    #[test]
+    fn test_program_validate_full() {
+        let expression = CString::new("x == x && max(a, 1) > 0").unwrap();
+        let invalid = CString::new("1 + + 2").unwrap();
+
+        unsafe {
+            let result = program_validate_full(expression.as_ptr(), std::ptr::null());
+            assert!(cel_result_is_ok(result));
+            let value = &*cel_result_value(result);
+            assert_eq!(value.value_type, CelValueType::List);
+            assert_eq!(value.data.list_val.len, 2);
+            cel_result_free(result);
+
+            let result = program_validate_full(invalid.as_ptr(), std::ptr::null());
+            assert!(cel_result_is_ok(result));
+            assert_eq!((*cel_result_value(result)).data.list_val.len, 1);
+            cel_result_free(result);
+
+            let result = program_validate_full(std::ptr::null(), std::ptr::null());
+            assert!(!cel_result_is_ok(result));
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+            cel_result_free(result);
+
+            let unknown = CString::new("nope").unwrap();
+            let names = [unknown.as_ptr()];
+            let options = CelCompileOptions {
+                max_expression_length: 0,
+                max_nesting_depth: 0,
+                max_comprehensions: 0,
+                disabled_macros: names.as_ptr(),
+                disabled_macros_len: 1,
+                disable_regex: false,
+                max_regex_length: 0,
+                max_regex_size: 0,
+                string_ext: false,
+                math_ext: false,
+                list_ext: false,
+                optional_ext: false,
+                unicode_ext: false,
+                encoders_ext: false,
+                json_ext: false,
+            };
+            let result = program_validate_full(expression.as_ptr(), &options);
+            assert_eq!(cel_last_error(), CelErrorCode::InvalidArgument);
+            cel_result_free(result);
+        }
+    }
+}
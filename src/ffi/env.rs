@@ -0,0 +1,635 @@
+use super::error::{contain, contain_with_error, report};
+use super::program::{compile_options, fail};
+use super::value::exact_rust_value;
+use super::{CelCompileOptions, CelErrorCode, CelValue, Program};
+pub use crate::api::Env;
+use std::ffi::{c_char, CStr};
+
+/// Create a compilation environment with the given options
+///
+/// A null `options` gives the defaults: no limits and no extensions. Returns null
+/// with `cel_last_error` set to `InvalidArgument` if the options name an unknown
+/// macro, or to `NullArgument` if `errbuf_len` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `options` is either null or a valid pointer to a `CelCompileOptions`
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn cel_env_new(
+    options: *const CelCompileOptions,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> *mut Env {
+    contain_with_error(std::ptr::null_mut(), errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            report(CelErrorCode::NullArgument);
+            return std::ptr::null_mut();
+        };
+
+        match compile_options(options) {
+            Ok(options) => {
+                report(CelErrorCode::Ok);
+                Box::into_raw(Box::new(Env::new(options)))
+            }
+            Err((code, e)) => {
+                fail(code, &e, errbuf, errbuf_len);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Free an environment
+///
+/// Programs compiled in it stay valid.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `env` is either null or a valid pointer returned by `cel_env_new`
+/// - `env` has not been previously freed
+#[no_mangle]
+pub unsafe extern "C" fn cel_env_free(env: *mut Env) {
+    contain((), || {
+        if !env.is_null() {
+            drop(Box::from_raw(env));
+        }
+    })
+}
+
+/// Declare a variable that contexts will bind
+///
+/// Returns false with `cel_last_error` set to `InvalidArgument` if `name` is not
+/// valid UTF-8, or to `NullArgument` if `env` or `name` is null. Declaring a name
+/// twice is not an error.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `env` is either null or a valid pointer returned by `cel_env_new`
+/// - `name` is either null or a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn env_declare_variable(env: *mut Env, name: *const c_char) -> bool {
+    contain(false, || declare(env, name, |env, name| env.declare_variable(name)))
+}
+
+/// Define a constant that programs compiled in `env` afterwards see in place of a variable
+///
+/// References to `name` are replaced by the value as a program compiles and the subexpressions
+/// that become constant are folded, so `amount > threshold * 2` compiles to
+/// `amount > 200` for a threshold of 100. Contexts cannot override a constant, and
+/// constants count as declared under strict variables. Adding a constant again
+/// replaces its value for programs compiled afterwards. Returns false with
+/// `cel_last_error` set to `InvalidArgument` if `name` is not an identifier or
+/// `value` cannot be written as a literal, such as a timestamp or NaN, or to
+/// `NullArgument` if `env`, `name`, `value` or `errbuf_len` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `env` is either null or a valid pointer returned by `cel_env_new`
+/// - `name` is either null or a valid null-terminated C string
+/// - `value` is either null or a valid pointer to a `CelValue`
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn env_add_constant(
+    env: *mut Env,
+    name: *const c_char,
+    value: *const CelValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(env) = env.as_mut() else {
+            return fail(CelErrorCode::NullArgument, "Env is null", errbuf, errbuf_len);
+        };
+        if name.is_null() {
+            return fail(CelErrorCode::NullArgument, "Constant name is null", errbuf, errbuf_len);
+        }
+        let Some(value) = value.as_ref() else {
+            return fail(CelErrorCode::NullArgument, "Constant value is null", errbuf, errbuf_len);
+        };
+
+        let added = CStr::from_ptr(name)
+            .to_str()
+            .map_err(|e| format!("Invalid constant name: {e}"))
+            .and_then(|name| Ok((name, exact_rust_value(value)?)))
+            .and_then(|(name, value)| env.add_constant(name, &value).map_err(|e| e.to_string()));
+        match added {
+            Ok(()) => report(CelErrorCode::Ok),
+            Err(e) => fail(CelErrorCode::InvalidArgument, &e, errbuf, errbuf_len),
+        }
+    })
+}
+
+/// Register an enum whose members programs compiled in `env` afterwards write as `Name.MEMBER`
+///
+/// `members` and `values` point to `len` member names and their ints, and may be
+/// null when `len` is 0. A member compiles to its int, so `level >= Level.INFO`
+/// compiles to `level >= 1`, and selecting a member the enum lacks fails to compile.
+/// The enum replaces any earlier enum or constant named `name`; pass `name` to
+/// `program_set_result_enum` to get member names back from a program. Returns false
+/// with `cel_last_error` set to `InvalidArgument` if `name` or a member name is not
+/// an identifier, or to `NullArgument` if `env`, `name`, a member name or an array
+/// is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `env` is either null or a valid pointer returned by `cel_env_new`
+/// - `name` is either null or a valid null-terminated C string
+/// - `members` is either null or points to `len` pointers that are each null or a
+///   valid null-terminated C string
+/// - `values` is either null or points to `len` ints
+#[no_mangle]
+pub unsafe extern "C" fn env_register_enum(
+    env: *mut Env,
+    name: *const c_char,
+    members: *const *const c_char,
+    values: *const i64,
+    len: usize,
+) -> bool {
+    contain(false, || {
+        let Some(env) = env.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        if name.is_null() || (len > 0 && (members.is_null() || values.is_null())) {
+            return report(CelErrorCode::NullArgument);
+        }
+        let (names, values) = if len == 0 {
+            (&[][..], &[][..])
+        } else {
+            (
+                std::slice::from_raw_parts(members, len),
+                std::slice::from_raw_parts(values, len),
+            )
+        };
+        if names.iter().any(|member| member.is_null()) {
+            return report(CelErrorCode::NullArgument);
+        }
+
+        let Ok(name) = CStr::from_ptr(name).to_str() else {
+            return report(CelErrorCode::InvalidArgument);
+        };
+        let Ok(members) = names
+            .iter()
+            .zip(values)
+            .map(|(&member, &value)| Ok((CStr::from_ptr(member).to_str()?.to_string(), value)))
+            .collect::<Result<Vec<_>, std::str::Utf8Error>>()
+        else {
+            return report(CelErrorCode::InvalidArgument);
+        };
+        match env.register_enum(name, members) {
+            Ok(()) => report(CelErrorCode::Ok),
+            Err(_) => report(CelErrorCode::InvalidArgument),
+        }
+    })
+}
+
+/// Declare a function the host provides, so programs compiled in `env` may call it
+///
+/// Returns false with `cel_last_error` set as for `env_declare_variable`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `env` is either null or a valid pointer returned by `cel_env_new`
+/// - `name` is either null or a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn env_declare_function(env: *mut Env, name: *const c_char) -> bool {
+    contain(false, || declare(env, name, |env, name| env.declare_function(name)))
+}
+
+/// Allow programs compiled in `env` to call the function or macro `name`
+///
+/// The first call restricts `env` to the functions allowed this way, built-in ones
+/// such as `size` included; calls to any other fail to compile. Returns false with
+/// `cel_last_error` set as for `env_declare_variable`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `env` is either null or a valid pointer returned by `cel_env_new`
+/// - `name` is either null or a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn env_allow_function(env: *mut Env, name: *const c_char) -> bool {
+    contain(false, || declare(env, name, |env, name| env.allow_function(name)))
+}
+
+/// Reject expressions that reference variables not declared in `env`
+///
+/// Returns false with `cel_last_error` set to `NullArgument` if `env` is null.
+///
+/// # Safety
+/// The caller must ensure that `env` is either null or a valid pointer returned by
+/// `cel_env_new`
+#[no_mangle]
+pub unsafe extern "C" fn env_set_strict_variables(env: *mut Env, strict: bool) -> bool {
+    contain(false, || {
+        let Some(env) = env.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+
+        env.set_strict_variables(strict);
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Accept calls to functions not declared in `env`, resolving them when programs run
+///
+/// Calls are resolved against the functions registered with `context_add_function`
+/// on the context each program executes with, and executing a call that context does
+/// not register fails with `UnresolvedFunction`. Returns false with `cel_last_error`
+/// set to `NullArgument` if `env` is null.
+///
+/// # Safety
+/// The caller must ensure that `env` is either null or a valid pointer returned by
+/// `cel_env_new`
+#[no_mangle]
+pub unsafe extern "C" fn env_set_late_bound_functions(env: *mut Env, late_bound: bool) -> bool {
+    contain(false, || {
+        let Some(env) = env.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+
+        env.set_late_bound_functions(late_bound);
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Reject expressions that reference the variable `pattern`
+///
+/// A pattern ending in `*` reserves every name starting with the rest of it, such as
+/// `__*` for internal variables. Compiling an expression that references a reserved
+/// name fails with `CompileError`, pointing at the reference. Returns false with
+/// `cel_last_error` set as for `env_declare_variable`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `env` is either null or a valid pointer returned by `cel_env_new`
+/// - `pattern` is either null or a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn env_reserve_name(env: *mut Env, pattern: *const c_char) -> bool {
+    contain(false, || declare(env, pattern, |env, pattern| env.reserve_name(pattern)))
+}
+
+/// Reject expressions with an identifier longer than `max` bytes, or none for 0
+///
+/// Variables, fields, functions and comprehension variables all count. Returns false
+/// with `cel_last_error` set to `NullArgument` if `env` is null.
+///
+/// # Safety
+/// The caller must ensure that `env` is either null or a valid pointer returned by
+/// `cel_env_new`
+#[no_mangle]
+pub unsafe extern "C" fn env_set_max_identifier_length(env: *mut Env, max: usize) -> bool {
+    contain(false, || {
+        let Some(env) = env.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+
+        env.set_max_identifier_length(max);
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Reject expressions compiled in `env` that use the macro `name`
+///
+/// `name` is one of `has`, `all`, `exists`, `exists_one`, `map` and `filter`, as for
+/// `CelCompileOptions::disabled_macros`, and adds to the macros the environment's
+/// options disable; disabling all five comprehension macros rules out comprehensions
+/// entirely. Returns false with `cel_last_error` set to `InvalidArgument` if `name` is
+/// not such a macro, or otherwise as for `env_declare_variable`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `env` is either null or a valid pointer returned by `cel_env_new`
+/// - `name` is either null or a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn env_disable_macro(env: *mut Env, name: *const c_char) -> bool {
+    contain(false, || {
+        let unknown = |name: &str| !crate::api::MACROS.contains(&name);
+        if !env.is_null() && !name.is_null() && CStr::from_ptr(name).to_str().is_ok_and(unknown) {
+            return report(CelErrorCode::InvalidArgument);
+        }
+        declare(env, name, |env, name| env.disable_macro(name))
+    })
+}
+
+/// Fail executions once a comprehension ranges over more than `max` elements
+///
+/// Applies to programs compiled in `env` from now on, and 0 removes the cap. The size
+/// of the list or map is checked before the first iteration, so an oversized range
+/// fails the execution with `ExecutionError` without being iterated, and each nested
+/// comprehension is checked on its own range. Returns false with `cel_last_error` set
+/// to `NullArgument` if `env` is null.
+///
+/// # Safety
+/// The caller must ensure that `env` is either null or a valid pointer returned by
+/// `cel_env_new`
+#[no_mangle]
+pub unsafe extern "C" fn env_set_max_comprehension_iterations(env: *mut Env, max: usize) -> bool {
+    contain(false, || {
+        let Some(env) = env.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+
+        env.set_max_comprehension_iterations(max);
+        report(CelErrorCode::Ok)
+    })
+}
+
+unsafe fn declare(
+    env: *mut Env,
+    name: *const c_char,
+    add: impl FnOnce(&mut Env, &str) -> bool,
+) -> bool {
+    let Some(env) = env.as_mut() else {
+        return report(CelErrorCode::NullArgument);
+    };
+    if name.is_null() {
+        return report(CelErrorCode::NullArgument);
+    }
+    let Ok(name) = CStr::from_ptr(name).to_str() else {
+        return report(CelErrorCode::InvalidArgument);
+    };
+
+    add(env, name);
+    report(CelErrorCode::Ok)
+}
+
+/// Compile a CEL expression into `program` within the environment
+///
+/// Fails like `program_compile_with_options` with the environment's options, and also
+/// when the expression calls a function that is neither built in, provided by an
+/// enabled extension nor declared with `env_declare_function`, references a name
+/// reserved with `env_reserve_name`, has an identifier longer than the limit set with
+/// `env_set_max_identifier_length`, or, with strict variables, references a variable
+/// not declared with `env_declare_variable`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `env` is either null or a valid pointer returned by `cel_env_new`
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `expression` is either null or a valid null-terminated C string
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn env_compile(
+    env: *const Env,
+    program: *mut Program,
+    expression: *const c_char,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(env) = env.as_ref() else {
+            return fail(CelErrorCode::NullArgument, "Env is null", errbuf, errbuf_len);
+        };
+        let Some(program) = program.as_mut() else {
+            return fail(CelErrorCode::NullArgument, "Program is null", errbuf, errbuf_len);
+        };
+        if expression.is_null() {
+            return fail(CelErrorCode::NullArgument, "Expression is null", errbuf, errbuf_len);
+        }
+
+        let expr_str = match CStr::from_ptr(expression).to_str() {
+            Ok(s) => s,
+            Err(e) => {
+                let error_msg = format!("Invalid expression string: {e}");
+                return fail(CelErrorCode::InvalidArgument, &error_msg, errbuf, errbuf_len);
+            }
+        };
+
+        match program.compile_with_env(expr_str, env) {
+            Ok(()) => report(CelErrorCode::Ok),
+            Err(e) => fail(CelErrorCode::CompileError, &e, errbuf, errbuf_len),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cel_last_error;
+    use std::ffi::CString;
+
+    #[test]
+    fn test_env_compile() {
+        let lookup = CString::new("lookup").unwrap();
+        let expression = CString::new("lookup('id').lowerAscii()").unwrap();
+        let options = CelCompileOptions {
+            max_expression_length: 0,
+            max_nesting_depth: 0,
+            max_comprehensions: 0,
+            disabled_macros: std::ptr::null(),
+            disabled_macros_len: 0,
+            disable_regex: false,
+            max_regex_length: 0,
+            max_regex_size: 0,
+            string_ext: true,
+            math_ext: false,
+            list_ext: false,
+            optional_ext: false,
+            unicode_ext: false,
+            encoders_ext: false,
+            json_ext: false,
+        };
+        let mut program = Program::new();
+        let mut error_buf = [0u8; 256];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            let env = cel_env_new(&options, error_buf.as_mut_ptr(), &mut error_len);
+            assert!(!env.is_null());
+
+            assert!(!env_compile(
+                env,
+                &mut program,
+                expression.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(cel_last_error(), CelErrorCode::CompileError);
+            let error = std::str::from_utf8(error_buf.get(..error_len).unwrap()).unwrap();
+            assert_eq!(error, "Compilation error: function 'lookup' is not declared");
+
+            assert!(env_declare_function(env, lookup.as_ptr()));
+            error_len = error_buf.len();
+            assert!(env_compile(
+                env,
+                &mut program,
+                expression.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+
+            let strict = CString::new("lookup(id)").unwrap();
+            assert!(env_set_strict_variables(env, true));
+            error_len = error_buf.len();
+            assert!(!env_compile(
+                env,
+                &mut program,
+                strict.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            let error = std::str::from_utf8(error_buf.get(..error_len).unwrap()).unwrap();
+            assert_eq!(
+                error,
+                "Compilation error: undeclared reference to 'id' at line 1, column 8"
+            );
+
+            let reserved = CString::new("__*").unwrap();
+            let internal = CString::new("lookup(__id)").unwrap();
+            assert!(env_reserve_name(env, reserved.as_ptr()));
+            error_len = error_buf.len();
+            assert!(!env_compile(
+                env,
+                &mut program,
+                internal.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            let error = std::str::from_utf8(error_buf.get(..error_len).unwrap()).unwrap();
+            assert_eq!(
+                error,
+                "Compilation error: reference to reserved variable '__id' at line 1, column 8"
+            );
+
+            let late = CString::new("fetch(1)").unwrap();
+            assert!(env_set_late_bound_functions(env, true));
+            error_len = error_buf.len();
+            assert!(env_compile(
+                env,
+                &mut program,
+                late.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            let unresolved = program.execute_cancellable(&crate::Context::new(), None);
+            assert_eq!(unresolved, Err(crate::api::Error::UnresolvedFunction("fetch".into())));
+
+            assert!(env_set_max_identifier_length(env, 4));
+            error_len = error_buf.len();
+            assert!(!env_compile(
+                env,
+                &mut program,
+                expression.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(cel_last_error(), CelErrorCode::CompileError);
+
+            assert!(!env_declare_variable(std::ptr::null_mut(), lookup.as_ptr()));
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+            cel_env_free(env);
+        }
+    }
+
+    #[test]
+    fn test_env_comprehension_limits() {
+        let map = CString::new("map").unwrap();
+        let unknown = CString::new("size").unwrap();
+        let mapped = CString::new("[1, 2].map(x, x)").unwrap();
+        let exists = CString::new("[1, 2, 3].exists(x, x > 2)").unwrap();
+        let mut program = Program::new();
+        let mut error_buf = [0u8; 256];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            let env = cel_env_new(std::ptr::null(), error_buf.as_mut_ptr(), &mut error_len);
+            assert!(env_disable_macro(env, map.as_ptr()));
+            assert!(!env_disable_macro(env, unknown.as_ptr()));
+            assert_eq!(cel_last_error(), CelErrorCode::InvalidArgument);
+            error_len = error_buf.len();
+            assert!(!env_compile(
+                env,
+                &mut program,
+                mapped.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            let error = std::str::from_utf8(error_buf.get(..error_len).unwrap()).unwrap();
+            assert_eq!(error, "Compilation error: macro 'map' is disabled");
+
+            assert!(env_set_max_comprehension_iterations(env, 2));
+            error_len = error_buf.len();
+            assert!(env_compile(
+                env,
+                &mut program,
+                exists.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            let result = program.execute_cancellable(&crate::Context::new(), None);
+            assert!(matches!(result, Err(crate::api::Error::Execution(_))));
+
+            assert!(!env_set_max_comprehension_iterations(std::ptr::null_mut(), 2));
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+            cel_env_free(env);
+        }
+    }
+
+    #[test]
+    fn test_env_add_constant() {
+        let threshold = CString::new("threshold").unwrap();
+        let expression = CString::new("amount > threshold * 2").unwrap();
+        let value = CelValue {
+            value_type: crate::CelValueType::Int,
+            data: crate::CelValueData { int_val: 100 },
+        };
+        let nan = CelValue {
+            value_type: crate::CelValueType::Double,
+            data: crate::CelValueData {
+                double_val: f64::NAN,
+            },
+        };
+        let mut program = Program::new();
+        let mut error_buf = [0u8; 256];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            let env = cel_env_new(std::ptr::null(), error_buf.as_mut_ptr(), &mut error_len);
+            assert!(env_add_constant(
+                env,
+                threshold.as_ptr(),
+                &value,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert!(env_compile(
+                env,
+                &mut program,
+                expression.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            let mut context = crate::Context::new();
+            context.add_variable("amount".to_string(), serde_json::json!(201));
+            assert_eq!(program.execute(&context), Ok(crate::api::Value::Bool(true)));
+
+            assert!(!env_add_constant(
+                env,
+                threshold.as_ptr(),
+                &nan,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(cel_last_error(), CelErrorCode::InvalidArgument);
+            error_len = error_buf.len();
+            assert!(!env_add_constant(
+                env,
+                threshold.as_ptr(),
+                std::ptr::null(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+            cel_env_free(env);
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use super::log::{log, CelLogLevel};
+use super::program::copy_error_to_buffer;
+use crate::api;
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+
+/// Status codes returned by FFI entry points that report more than success/failure
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CelErrorCode {
+    Ok,
+    InvalidHandle,
+    InvalidArgument,
+    CompileError,
+    ExecutionError,
+    NullArgument,
+    /// The library panicked and the call was abandoned partway through
+    Internal,
+    /// The context was frozen by `context_freeze` and cannot be modified
+    Frozen,
+    /// The evaluation was cancelled through its `CelCancelToken`
+    Cancelled,
+    /// The evaluation needed keys the data lacks, under the `MissingKeyUnknown` policy
+    Unknown,
+    /// The evaluation allocated more than the limit set by `context_set_max_memory`
+    MemoryLimit,
+    /// The evaluation called a late-bound function the context does not register
+    UnresolvedFunction,
+    /// The string pointer is not a live library string: it was already freed, or the
+    /// library never handed it out
+    InvalidPointer,
+    /// The evaluation ran more comprehension iterations than the limit set by
+    /// `context_set_max_iterations`
+    IterationLimit,
+    /// The evaluation returned a value of another type than the one set by
+    /// `program_expect_type`
+    ResultType,
+}
+
+impl CelErrorCode {
+    #[must_use]
+    pub const fn is_ok(self) -> bool {
+        matches!(self, Self::Ok)
+    }
+}
+
+/// The code reporting a failed execution
+pub(crate) const fn execution_code(error: &api::Error) -> CelErrorCode {
+    match error {
+        api::Error::Cancelled => CelErrorCode::Cancelled,
+        api::Error::Unknown(_) => CelErrorCode::Unknown,
+        api::Error::MemoryLimit { .. } => CelErrorCode::MemoryLimit,
+        api::Error::IterationLimit { .. } => CelErrorCode::IterationLimit,
+        api::Error::UnresolvedFunction(_) => CelErrorCode::UnresolvedFunction,
+        api::Error::ResultType { .. } => CelErrorCode::ResultType,
+        _ => CelErrorCode::ExecutionError,
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: Cell<CelErrorCode> = const { Cell::new(CelErrorCode::Ok) };
+    static LAST_ERROR_MESSAGE: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Record `code` as the calling thread's last error and return whether it is `Ok`
+///
+/// Entry points that keep a `bool` return for compatibility report through this so
+/// callers can still tell, for example, a null argument apart from a compile error.
+pub(crate) fn report(code: CelErrorCode) -> bool {
+    LAST_ERROR.with(|last| last.set(code));
+    code.is_ok()
+}
+
+/// Keep the full text of an error written to a caller's buffer for `cel_last_error_message`
+pub(crate) fn record_error_message(message: &str) {
+    LAST_ERROR_MESSAGE.with_borrow_mut(|last| {
+        last.clear();
+        last.push_str(message);
+    });
+}
+
+/// The message `cel_last_error_message` would copy out
+pub(crate) fn last_error_message() -> String {
+    LAST_ERROR_MESSAGE.with_borrow(Clone::clone)
+}
+
+/// Run the body of an entry point, containing any panic
+///
+/// Unwinding out of an `extern "C"` function is undefined behavior, so every entry
+/// point that can panic runs its body through this. A panic is logged, recorded as
+/// `Internal` for `cel_last_error` and turned into `on_panic`.
+pub(crate) fn contain<T>(on_panic: T, body: impl FnOnce() -> T) -> T {
+    catch(body).unwrap_or(on_panic)
+}
+
+/// Like `contain`, also describing a panic in the caller's error buffer
+///
+/// # Safety
+/// `errbuf` and `errbuf_len` must satisfy the entry point's own requirements.
+pub(crate) unsafe fn contain_with_error<T>(
+    on_panic: T,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+    body: impl FnOnce() -> T,
+) -> T {
+    catch(body).unwrap_or_else(|message| {
+        if let Some(errbuf_len) = errbuf_len.as_mut() {
+            copy_error_to_buffer(&format!("Internal error: {message}"), errbuf, errbuf_len);
+        }
+        on_panic
+    })
+}
+
+/// Run `body`, returning the message of any panic after logging and reporting it
+fn catch<T>(body: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(body)).map_err(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic")
+            .to_string();
+        log(CelLogLevel::Error, || format!("internal error: {message}"));
+        report(CelErrorCode::Internal);
+        message
+    })
+}
+
+/// Status of the most recent `bool`-returning call made on this thread
+///
+/// Every such entry point overwrites it, including with `Ok` on success.
+#[no_mangle]
+pub extern "C" fn cel_last_error() -> CelErrorCode {
+    LAST_ERROR.with(Cell::get)
+}
+
+/// Copy the full message of the calling thread's last error into `buf`
+///
+/// Unlike the `errbuf` arguments of other functions, `*buf_len` is always set to the
+/// message's length in bytes, so a message that does not fit still reports the size
+/// needed: pass a null `buf` or a `*buf_len` of 0 to query it, then call again with
+/// a buffer of at least that length plus one for the terminator. The message is the
+/// one last written to an error buffer on this thread, kept even if that buffer was
+/// too small or null and until the next error replaces it, so only read it after a
+/// call fails. Returns true if the whole message and its terminator were written.
+/// Does not change `cel_last_error` unless `buf_len` is null, which reports
+/// `NullArgument`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `buf` is either null or points to a valid buffer of at least `*buf_len` bytes
+/// - `buf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn cel_last_error_message(buf: *mut u8, buf_len: *mut usize) -> bool {
+    contain(false, || {
+        let Some(buf_len) = buf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        LAST_ERROR_MESSAGE.with_borrow(|message| {
+            let capacity = if buf.is_null() { 0 } else { *buf_len };
+            *buf_len = message.len();
+            if capacity == 0 {
+                return false;
+            }
+            let copy_len = message.len().min(capacity - 1);
+            std::ptr::copy_nonoverlapping(message.as_ptr(), buf, copy_len);
+            *buf.add(copy_len) = 0;
+            copy_len == message.len()
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_code_values() {
+        // Ok must stay zero so C callers can test `if (rc) { ... }`
+        assert_eq!(CelErrorCode::Ok as i32, 0);
+        assert!(CelErrorCode::Ok.is_ok());
+        assert!(!CelErrorCode::InvalidHandle.is_ok());
+        assert_ne!(CelErrorCode::CompileError, CelErrorCode::ExecutionError);
+    }
+
+    #[test]
+    fn test_last_error_is_per_call() {
+        assert!(!report(CelErrorCode::NullArgument));
+        assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+
+        assert!(report(CelErrorCode::Ok));
+        assert_eq!(cel_last_error(), CelErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_contain_panic() {
+        report(CelErrorCode::Ok);
+        assert_eq!(contain(1, || 2), 2);
+        assert_eq!(cel_last_error(), CelErrorCode::Ok);
+
+        let result = contain(false, || -> bool { panic!("boom") });
+        assert!(!result);
+        assert_eq!(cel_last_error(), CelErrorCode::Internal);
+    }
+
+    #[test]
+    fn test_last_error_message_reports_required_length() {
+        let message = "a message longer than the buffer";
+        record_error_message(message);
+        let mut buf = [0xffu8; 8];
+
+        unsafe {
+            let mut len = 0;
+            assert!(!cel_last_error_message(std::ptr::null_mut(), &mut len));
+            assert_eq!(len, message.len());
+
+            len = 0;
+            assert!(!cel_last_error_message(buf.as_mut_ptr(), &mut len));
+            assert_eq!(len, message.len());
+            assert_eq!(buf[0], 0xff, "a zero-length buffer is not written");
+
+            len = buf.len();
+            assert!(!cel_last_error_message(buf.as_mut_ptr(), &mut len));
+            assert_eq!(len, message.len());
+            assert_eq!(&buf, b"a messa\0");
+
+            let mut full = vec![0u8; len + 1];
+            len = full.len();
+            assert!(cel_last_error_message(full.as_mut_ptr(), &mut len));
+            assert_eq!(&full[..len], message.as_bytes());
+            assert_eq!(full[len], 0);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Executions that run on a background thread, so hosts with cooperative schedulers
+//! can keep serving other work while a long evaluation runs
+
+use super::cancel::CelCancelToken;
+use super::error::{contain, report};
+use super::program::Program;
+use super::result::CelEvalResult;
+use super::{CelErrorCode, Context};
+use crate::api::{self, Value as CelRustValue};
+use std::thread::JoinHandle;
+
+/// An execution started by `program_execute_start`, collected by `cel_execution_finish`
+#[derive(Debug)]
+pub struct CelExecution {
+    thread: JoinHandle<Result<CelRustValue, api::Error>>,
+}
+
+/// The program and context an execution thread borrows, and the token it checks
+struct Borrowed {
+    program: *const Program,
+    context: *const Context,
+    token: Option<api::CancelToken>,
+}
+
+// SAFETY: programs and contexts are only read while executing, and
+// `program_execute_start` requires both to outlive the execution unchanged
+unsafe impl Send for Borrowed {}
+
+impl Borrowed {
+    unsafe fn execute(self) -> Result<CelRustValue, api::Error> {
+        (*self.program).execute_cancellable(&*self.context, self.token.as_ref())
+    }
+}
+
+/// Start executing the compiled expression on a background thread
+///
+/// Poll `cel_execution_is_done` and yield to the host's scheduler in between, then
+/// collect the result with `cel_execution_finish`. Starting a thread costs far more
+/// than a short evaluation, so this is meant for expressions over large inputs.
+/// If `token` is not null, cancelling it stops the evaluation, which then finishes
+/// with `Cancelled`. Returns null with `cel_last_error` set to `NullArgument` if
+/// `program` or `context` is null, or to `Internal` if no thread could be started.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `token` is either null or a valid pointer returned by `cel_cancel_token_new`
+/// - neither `program` nor `context` is modified or freed until `cel_execution_finish` returns
+#[no_mangle]
+pub unsafe extern "C" fn program_execute_start(
+    program: *const Program,
+    context: *const Context,
+    token: *const CelCancelToken,
+) -> *mut CelExecution {
+    contain(std::ptr::null_mut(), || {
+        if program.is_null() || context.is_null() {
+            report(CelErrorCode::NullArgument);
+            return std::ptr::null_mut();
+        }
+
+        let token = token.as_ref().map(CelCancelToken::token);
+        let borrowed = Borrowed {
+            program,
+            context,
+            token,
+        };
+        let spawned = std::thread::Builder::new()
+            .name("cel-execution".to_string())
+            .spawn(move || borrowed.execute());
+        match spawned {
+            Ok(thread) => {
+                report(CelErrorCode::Ok);
+                Box::into_raw(Box::new(CelExecution { thread }))
+            }
+            Err(_) => {
+                report(CelErrorCode::Internal);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Whether the execution has finished, false for a null execution
+///
+/// Once it has, `cel_execution_finish` returns without blocking.
+///
+/// # Safety
+/// The caller must ensure that `execution` is either null or a valid pointer
+/// returned by `program_execute_start` that has not been finished
+#[no_mangle]
+pub unsafe extern "C" fn cel_execution_is_done(execution: *const CelExecution) -> bool {
+    contain(false, || {
+        execution.as_ref().is_some_and(|execution| execution.thread.is_finished())
+    })
+}
+
+/// Wait for the execution to finish and return its result, consuming `execution`
+///
+/// The result behaves as for `program_execute_alloc` and is released with
+/// `cel_result_free`. Every started execution must be finished, even if its result
+/// is not wanted, since until then it reads the program and context. A null
+/// execution gives a `NullArgument` result.
+///
+/// # Safety
+/// The caller must ensure that `execution` is either null or a valid pointer
+/// returned by `program_execute_start` that has not been finished
+#[no_mangle]
+pub unsafe extern "C" fn cel_execution_finish(execution: *mut CelExecution) -> *mut CelEvalResult {
+    contain(std::ptr::null_mut(), || {
+        let result = if execution.is_null() {
+            CelEvalResult::from_error(CelErrorCode::NullArgument, "Execution is null")
+        } else {
+            match Box::from_raw(execution).thread.join() {
+                Ok(outcome) => CelEvalResult::from_cancellable(outcome),
+                Err(_) => CelEvalResult::from_error(
+                    CelErrorCode::Internal,
+                    "Internal error: the execution thread panicked",
+                ),
+            }
+        };
+        report(result.code());
+        result.into_raw()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cel_result_free, cel_result_is_ok, cel_result_value, context_free, context_new};
+    use crate::{program_free, program_new, CelValueType};
+
+    #[test]
+    fn test_execute_in_background() {
+        let program = program_new();
+        let context = context_new();
+
+        unsafe {
+            (*program).compile("[1, 2, 3].map(x, x * x).exists(x, x == 9)").unwrap();
+            let execution = program_execute_start(program, context, std::ptr::null());
+            assert!(!execution.is_null());
+            while !cel_execution_is_done(execution) {
+                std::thread::yield_now();
+            }
+
+            let result = cel_execution_finish(execution);
+            assert!(cel_result_is_ok(result));
+            let value = &*cel_result_value(result);
+            assert_eq!(value.value_type, CelValueType::Bool);
+            assert!(value.data.bool_val);
+            cel_result_free(result);
+
+            let token = crate::cel_cancel_token_new();
+            crate::cel_cancel(token);
+            let execution = program_execute_start(program, context, token);
+            crate::cel_cancel_token_free(token);
+            let result = cel_execution_finish(execution);
+            assert_eq!(crate::cel_last_error(), CelErrorCode::Cancelled);
+            cel_result_free(result);
+
+            assert!(program_execute_start(program, std::ptr::null(), std::ptr::null()).is_null());
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+            let result = cel_execution_finish(std::ptr::null_mut());
+            assert!(!cel_result_is_ok(result));
+            cel_result_free(result);
+
+            program_free(program);
+            context_free(context);
+        }
+    }
+}
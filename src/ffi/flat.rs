@@ -0,0 +1,658 @@
+//! A flat C API for hosts that call the library through the LuaJIT FFI, such as
+//! OpenResty
+//!
+//! Every function takes and returns only scalars, C strings and the `u64` handles
+//! of `program_handle_new` and `context_handle_new`, or of their `_in` variants for a
+//! per-state registry from `cel_state_new`, with no structs, unions or
+//! out-parameters, so `lib/cel/flat.lua` declares all a host needs. A failure
+//! returns an error code, a zero handle or a zero value, sets `cel_last_error` and
+//! leaves its message for `cel_flat_error_message`.
+
+use super::context::raw_bytes;
+use super::error::{contain, last_error_message, record_error_message, report};
+use super::handle::{lookup, release, HandleRegistry, CONTEXTS, PROGRAMS, STATES};
+use super::program::value_to_json;
+use super::{CelErrorCode, CelValueType};
+use crate::api::value_type_of;
+use cel_interpreter::Value as CelRustValue;
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+
+/// A finished evaluation, read through the `cel_flat_result_*` functions
+#[derive(Debug)]
+struct FlatResult {
+    value: CelRustValue,
+    /// The value as null-terminated JSON, serialized on first request
+    json: OnceLock<Result<CString, String>>,
+}
+
+static RESULTS: Mutex<HandleRegistry<FlatResult>> = Mutex::new(HandleRegistry::new());
+
+thread_local! {
+    static ERROR_MESSAGE: RefCell<CString> = RefCell::new(CString::default());
+}
+
+/// A variable value, bound by `bind`
+pub(super) enum Variable {
+    Value(CelRustValue),
+    Json(serde_json::Value),
+}
+
+/// Record `message` as the calling thread's last error and return `code`
+pub(super) fn failure(code: CelErrorCode, message: &str) -> CelErrorCode {
+    record_error_message(message);
+    report(code);
+    code
+}
+
+/// `ptr` as a string, failing for null or invalid UTF-8
+unsafe fn text<'a>(ptr: *const c_char, what: &str) -> Result<&'a str, CelErrorCode> {
+    if ptr.is_null() {
+        return Err(failure(CelErrorCode::NullArgument, &format!("{what} is null")));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|e| failure(CelErrorCode::InvalidArgument, &format!("Invalid {what} string: {e}")))
+}
+
+/// Bind `name` in the context referred to by `context` to what `value` returns
+unsafe fn set_variable(
+    context: u64,
+    name: *const c_char,
+    value: impl FnOnce() -> Result<Variable, (CelErrorCode, String)>,
+) -> CelErrorCode {
+    bind(context, || text(name, "Variable name"), value)
+}
+
+/// Bind the name `name` returns in the context referred to by `context` to what
+/// `value` returns, checking the handle first
+pub(super) fn bind<'a>(
+    context: u64,
+    name: impl FnOnce() -> Result<&'a str, CelErrorCode>,
+    value: impl FnOnce() -> Result<Variable, (CelErrorCode, String)>,
+) -> CelErrorCode {
+    contain(CelErrorCode::Internal, || {
+        let Some(context) = lookup(&CONTEXTS, context) else {
+            return failure(CelErrorCode::InvalidHandle, "Invalid context handle");
+        };
+        let name = match name() {
+            Ok(name) => name.to_string(),
+            Err(code) => return code,
+        };
+        let value = match value() {
+            Ok(value) => value,
+            Err((code, message)) => return failure(code, &message),
+        };
+
+        let mut context = context.write().unwrap_or_else(PoisonError::into_inner);
+        if context.is_frozen() {
+            return failure(CelErrorCode::Frozen, "Context is frozen");
+        }
+        match value {
+            Variable::Value(value) => context.add_value_variable(name, value),
+            Variable::Json(value) => context.add_variable(name, value),
+        }
+        report(CelErrorCode::Ok);
+        CelErrorCode::Ok
+    })
+}
+
+/// Read the result referred to by `result` with `get`, or return `default` if the
+/// handle is unknown or `get` finds no value, which must be `what`
+fn read_result<T>(
+    result: u64,
+    default: T,
+    what: &str,
+    get: impl FnOnce(&FlatResult) -> Option<T>,
+) -> T {
+    contain(None, || {
+        let Some(result) = lookup(&RESULTS, result) else {
+            failure(CelErrorCode::InvalidHandle, "Invalid result handle");
+            return None;
+        };
+        let result = result.read().unwrap_or_else(PoisonError::into_inner);
+        let value = get(&result);
+        if value.is_some() {
+            report(CelErrorCode::Ok);
+        } else {
+            failure(CelErrorCode::InvalidArgument, &format!("Result is not {what}"));
+        }
+        value
+    })
+    .unwrap_or(default)
+}
+
+/// Compile `expression` into the program referred to by `program`
+///
+/// # Safety
+/// The caller must ensure that `expression` is either null or a valid null-terminated
+/// C string
+#[no_mangle]
+pub unsafe extern "C" fn cel_flat_compile(program: u64, expression: *const c_char) -> CelErrorCode {
+    contain(CelErrorCode::Internal, || {
+        let Some(program) = lookup(&PROGRAMS, program) else {
+            return failure(CelErrorCode::InvalidHandle, "Invalid program handle");
+        };
+        let expression = match text(expression, "Expression") {
+            Ok(expression) => expression,
+            Err(code) => return code,
+        };
+
+        let mut program = program.write().unwrap_or_else(PoisonError::into_inner);
+        match program.compile(expression) {
+            Ok(()) => {
+                report(CelErrorCode::Ok);
+                CelErrorCode::Ok
+            }
+            Err(e) => failure(CelErrorCode::CompileError, &e),
+        }
+    })
+}
+
+/// Bind `name` to null in the context referred to by `context`
+///
+/// # Safety
+/// The caller must ensure that `name` is either null or a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn cel_flat_set_null(context: u64, name: *const c_char) -> CelErrorCode {
+    set_variable(context, name, || Ok(Variable::Value(CelRustValue::Null)))
+}
+
+/// Bind `name` to a bool in the context referred to by `context`
+///
+/// # Safety
+/// The caller must ensure that `name` is either null or a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn cel_flat_set_bool(
+    context: u64,
+    name: *const c_char,
+    value: bool,
+) -> CelErrorCode {
+    set_variable(context, name, || Ok(Variable::Value(CelRustValue::Bool(value))))
+}
+
+/// Bind `name` to an int in the context referred to by `context`
+///
+/// # Safety
+/// The caller must ensure that `name` is either null or a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn cel_flat_set_int(
+    context: u64,
+    name: *const c_char,
+    value: i64,
+) -> CelErrorCode {
+    set_variable(context, name, || Ok(Variable::Value(CelRustValue::Int(value))))
+}
+
+/// Bind `name` to a uint in the context referred to by `context`
+///
+/// # Safety
+/// The caller must ensure that `name` is either null or a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn cel_flat_set_uint(
+    context: u64,
+    name: *const c_char,
+    value: u64,
+) -> CelErrorCode {
+    set_variable(context, name, || Ok(Variable::Value(CelRustValue::UInt(value))))
+}
+
+/// Bind `name` to a double in the context referred to by `context`
+///
+/// # Safety
+/// The caller must ensure that `name` is either null or a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn cel_flat_set_double(
+    context: u64,
+    name: *const c_char,
+    value: f64,
+) -> CelErrorCode {
+    set_variable(context, name, || Ok(Variable::Value(CelRustValue::Float(value))))
+}
+
+/// Bind `name` to the UTF-8 string of `len` bytes at `value`
+///
+/// The bytes are copied, and may hold interior NULs. Returns `InvalidArgument` if
+/// they are not UTF-8.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `name` is either null or a valid null-terminated C string
+/// - `value` is either null or points to at least `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn cel_flat_set_string(
+    context: u64,
+    name: *const c_char,
+    value: *const c_char,
+    len: usize,
+) -> CelErrorCode {
+    set_variable(context, name, || {
+        if value.is_null() {
+            return Err((CelErrorCode::NullArgument, "Value is null".to_string()));
+        }
+        let text = std::str::from_utf8(raw_bytes(value.cast(), len))
+            .map_err(|e| (CelErrorCode::InvalidArgument, format!("Invalid string value: {e}")))?;
+        Ok(Variable::Value(CelRustValue::String(Arc::new(text.to_string()))))
+    })
+}
+
+/// Bind `name` to the `len` bytes at `value`, as a CEL bytes value
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `name` is either null or a valid null-terminated C string
+/// - `value` is either null or points to at least `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn cel_flat_set_bytes(
+    context: u64,
+    name: *const c_char,
+    value: *const c_char,
+    len: usize,
+) -> CelErrorCode {
+    set_variable(context, name, || {
+        if value.is_null() {
+            return Err((CelErrorCode::NullArgument, "Value is null".to_string()));
+        }
+        let bytes = raw_bytes(value.cast(), len).to_vec();
+        Ok(Variable::Value(CelRustValue::Bytes(Arc::new(bytes))))
+    })
+}
+
+/// Bind `name` to the value of a JSON document, such as an object or a list
+///
+/// Returns `InvalidArgument` if `json` is not valid JSON.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `name` is either null or a valid null-terminated C string
+/// - `json` is either null or a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn cel_flat_set_json(
+    context: u64,
+    name: *const c_char,
+    json: *const c_char,
+) -> CelErrorCode {
+    set_variable(context, name, || {
+        if json.is_null() {
+            return Err((CelErrorCode::NullArgument, "JSON is null".to_string()));
+        }
+        let json = CStr::from_ptr(json)
+            .to_str()
+            .map_err(|e| (CelErrorCode::InvalidArgument, format!("Invalid JSON string: {e}")))?;
+        serde_json::from_str(json)
+            .map(Variable::Json)
+            .map_err(|e| (CelErrorCode::InvalidArgument, format!("Invalid JSON: {e}")))
+    })
+}
+
+/// Execute a program against a context, returning a handle to the result
+///
+/// Returns 0 if either handle is unknown, with `cel_last_error` set to
+/// `InvalidHandle`, or if evaluation fails, with it set to `ExecutionError`. Read the
+/// result with the `cel_flat_result_*` functions and release it with
+/// `cel_flat_result_free`.
+#[no_mangle]
+pub extern "C" fn cel_flat_execute(program: u64, context: u64) -> u64 {
+    contain(0, || {
+        let found = {
+            let programs = PROGRAMS.lock().unwrap_or_else(PoisonError::into_inner);
+            programs.get(program).zip(programs.owner(program))
+        };
+        let Some((program, owner)) = found else {
+            failure(CelErrorCode::InvalidHandle, "Invalid program handle");
+            return 0;
+        };
+        let Some(context) = lookup(&CONTEXTS, context) else {
+            failure(CelErrorCode::InvalidHandle, "Invalid context handle");
+            return 0;
+        };
+
+        let value = {
+            let program = program.read().unwrap_or_else(PoisonError::into_inner);
+            let context = context.read().unwrap_or_else(PoisonError::into_inner);
+            program.execute(&context)
+        };
+        match value {
+            Ok(value) => {
+                // The result belongs to the program's state, unless that was freed
+                // during the evaluation
+                let states = STATES.lock().unwrap_or_else(PoisonError::into_inner);
+                if owner != 0 && states.get(owner).is_none() {
+                    failure(CelErrorCode::InvalidHandle, "Invalid program handle");
+                    return 0;
+                }
+                report(CelErrorCode::Ok);
+                RESULTS.lock().unwrap_or_else(PoisonError::into_inner).insert_owned(
+                    FlatResult {
+                        value,
+                        json: OnceLock::new(),
+                    },
+                    owner,
+                )
+            }
+            Err(e) => {
+                failure(CelErrorCode::ExecutionError, &e);
+                0
+            }
+        }
+    })
+}
+
+/// Type of a result's value, or `Null` for an unknown handle
+#[no_mangle]
+pub extern "C" fn cel_flat_result_type(result: u64) -> CelValueType {
+    read_result(result, CelValueType::Null, "a value", |result| {
+        Some(value_type_of(&result.value))
+    })
+}
+
+/// A bool result's value, or false with `cel_last_error` set if it is not a bool
+#[no_mangle]
+pub extern "C" fn cel_flat_result_bool(result: u64) -> bool {
+    read_result(result, false, "a bool", |result| match result.value {
+        CelRustValue::Bool(b) => Some(b),
+        _ => None,
+    })
+}
+
+/// An int result's value, or 0 with `cel_last_error` set if it is not an int
+#[no_mangle]
+pub extern "C" fn cel_flat_result_int(result: u64) -> i64 {
+    read_result(result, 0, "an int", |result| match result.value {
+        CelRustValue::Int(i) => Some(i),
+        _ => None,
+    })
+}
+
+/// A uint result's value, or 0 with `cel_last_error` set if it is not a uint
+#[no_mangle]
+pub extern "C" fn cel_flat_result_uint(result: u64) -> u64 {
+    read_result(result, 0, "a uint", |result| match result.value {
+        CelRustValue::UInt(u) => Some(u),
+        _ => None,
+    })
+}
+
+/// A double result's value, or 0 with `cel_last_error` set if it is not a double
+#[no_mangle]
+pub extern "C" fn cel_flat_result_double(result: u64) -> f64 {
+    read_result(result, 0.0, "a double", |result| match result.value {
+        CelRustValue::Float(f) => Some(f),
+        _ => None,
+    })
+}
+
+/// The bytes of a string or bytes result, `cel_flat_result_len` long
+///
+/// The data is not null-terminated and stays valid until the result is freed.
+/// Returns null with `cel_last_error` set for other types.
+#[no_mangle]
+pub extern "C" fn cel_flat_result_string(result: u64) -> *const u8 {
+    read_result(result, std::ptr::null(), "a string or bytes", |result| match &result.value {
+        CelRustValue::String(s) => Some(s.as_ptr()),
+        CelRustValue::Bytes(b) => Some(b.as_ptr()),
+        _ => None,
+    })
+}
+
+/// Length in bytes of a string or bytes result, or the element count of a list or map
+///
+/// Returns 0 with `cel_last_error` set for other types.
+#[no_mangle]
+pub extern "C" fn cel_flat_result_len(result: u64) -> usize {
+    read_result(result, 0, "a string, bytes, list or map", |result| match &result.value {
+        CelRustValue::String(s) => Some(s.len()),
+        CelRustValue::Bytes(b) => Some(b.len()),
+        CelRustValue::List(items) => Some(items.len()),
+        CelRustValue::Map(map) => Some(map.map.len()),
+        _ => None,
+    })
+}
+
+/// A result serialized as null-terminated JSON, as for `program_execute_json`
+///
+/// The string stays valid until the result is freed. Returns null with
+/// `cel_last_error` set to `ExecutionError` if the value has no JSON form.
+#[no_mangle]
+pub extern "C" fn cel_flat_result_json(result: u64) -> *const c_char {
+    let mut error = None;
+    let json = read_result(result, std::ptr::null(), "serializable", |result| {
+        let json = result.json.get_or_init(|| {
+            value_to_json(&result.value)
+                .and_then(|json| CString::new(json).map_err(|e| e.to_string()))
+        });
+        match json {
+            Ok(json) => Some(json.as_ptr()),
+            Err(e) => {
+                error = Some(e.clone());
+                None
+            }
+        }
+    });
+    if let Some(e) = error {
+        failure(CelErrorCode::ExecutionError, &e);
+    }
+    json
+}
+
+/// Release a result handle
+///
+/// Returns `InvalidHandle` if the handle is unknown or was already freed.
+#[no_mangle]
+pub extern "C" fn cel_flat_result_free(result: u64) -> CelErrorCode {
+    contain(CelErrorCode::Internal, || release(&RESULTS, result))
+}
+
+/// Open a state owning the handles created for one host state, such as a Lua state
+///
+/// Programs and contexts created with `program_handle_new_in` and
+/// `context_handle_new_in`, and the results of executing those programs, belong to
+/// the state, so freeing it frees whatever the host has not.
+#[no_mangle]
+pub extern "C" fn cel_state_new() -> u64 {
+    contain(0, || STATES.lock().unwrap_or_else(PoisonError::into_inner).insert(()))
+}
+
+/// Free a state and every program, context and result handle it still owns
+///
+/// Evaluations already running keep their program and context until they finish.
+/// Returns `InvalidHandle` if the state is unknown or was already freed.
+#[no_mangle]
+pub extern "C" fn cel_state_free(state: u64) -> CelErrorCode {
+    contain(CelErrorCode::Internal, || {
+        let mut states = STATES.lock().unwrap_or_else(PoisonError::into_inner);
+        if states.remove(state).is_none() {
+            return CelErrorCode::InvalidHandle;
+        }
+        PROGRAMS.lock().unwrap_or_else(PoisonError::into_inner).remove_owned(state);
+        CONTEXTS.lock().unwrap_or_else(PoisonError::into_inner).remove_owned(state);
+        RESULTS.lock().unwrap_or_else(PoisonError::into_inner).remove_owned(state);
+        CelErrorCode::Ok
+    })
+}
+
+/// Message of the calling thread's last error, as for `cel_last_error_message`
+///
+/// The string is owned by the library and valid until the next call to this
+/// function on the same thread.
+#[no_mangle]
+pub extern "C" fn cel_flat_error_message() -> *const c_char {
+    contain(std::ptr::null(), || {
+        let message = CString::new(last_error_message().replace('\0', "")).unwrap_or_default();
+        ERROR_MESSAGE.with_borrow_mut(|slot| {
+            *slot = message;
+            slot.as_ptr()
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context_handle_free, context_handle_new, context_handle_new_in, program_handle_free,
+        program_handle_new, program_handle_new_in,
+    };
+
+    fn message() -> String {
+        unsafe { CStr::from_ptr(cel_flat_error_message()) }
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_flat_execute() {
+        let program = program_handle_new();
+        let context = context_handle_new();
+        let expression =
+            CString::new("{'greeting': greeting + ', ' + user.name, 'n': n * 2}").unwrap();
+        let [greeting, user, n] = ["greeting", "user", "n"].map(|s| CString::new(s).unwrap());
+        let hello = CString::new("Hello").unwrap();
+        let json = CString::new(r#"{"name": "Ada"}"#).unwrap();
+
+        unsafe {
+            assert_eq!(cel_flat_compile(program, expression.as_ptr()), CelErrorCode::Ok);
+            assert_eq!(
+                cel_flat_set_string(context, greeting.as_ptr(), hello.as_ptr(), 5),
+                CelErrorCode::Ok
+            );
+            assert_eq!(cel_flat_set_json(context, user.as_ptr(), json.as_ptr()), CelErrorCode::Ok);
+            assert_eq!(cel_flat_set_int(context, n.as_ptr(), 21), CelErrorCode::Ok);
+        }
+
+        let result = cel_flat_execute(program, context);
+        assert_ne!(result, 0);
+        assert_eq!(cel_flat_result_type(result), CelValueType::Map);
+        assert_eq!(cel_flat_result_len(result), 2);
+        assert_eq!(cel_flat_result_int(result), 0);
+        assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+        let json = unsafe { CStr::from_ptr(cel_flat_result_json(result)) };
+        assert_eq!(json.to_str().unwrap(), r#"{"greeting":"Hello, Ada","n":42}"#);
+        assert_eq!(cel_flat_result_free(result), CelErrorCode::Ok);
+        assert_eq!(cel_flat_result_free(result), CelErrorCode::InvalidHandle);
+
+        assert_eq!(program_handle_free(program), CelErrorCode::Ok);
+        assert_eq!(context_handle_free(context), CelErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_flat_scalars_and_errors() {
+        let program = program_handle_new();
+        let context = context_handle_new();
+        let [bad, missing, good] =
+            ["1 +", "missing", "'abc'"].map(|expression| CString::new(expression).unwrap());
+
+        unsafe {
+            assert_eq!(cel_flat_compile(program, bad.as_ptr()), CelErrorCode::CompileError);
+            assert!(!message().is_empty());
+
+            assert_eq!(cel_flat_compile(program, missing.as_ptr()), CelErrorCode::Ok);
+            assert_eq!(cel_flat_execute(program, context), 0);
+            assert_eq!(crate::cel_last_error(), CelErrorCode::ExecutionError);
+            assert!(message().contains("missing"), "{}", message());
+
+            assert_eq!(cel_flat_compile(program, good.as_ptr()), CelErrorCode::Ok);
+        }
+        let result = cel_flat_execute(program, context);
+        assert_eq!(cel_flat_result_type(result), CelValueType::String);
+        let text = unsafe {
+            std::slice::from_raw_parts(cel_flat_result_string(result), cel_flat_result_len(result))
+        };
+        assert_eq!(text, b"abc");
+        assert_eq!(cel_flat_result_free(result), CelErrorCode::Ok);
+
+        assert_eq!(cel_flat_execute(0, context), 0);
+        assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidHandle);
+        assert_eq!(cel_flat_result_type(result), CelValueType::Null);
+        assert_eq!(program_handle_free(program), CelErrorCode::Ok);
+        assert_eq!(context_handle_free(context), CelErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_flat_concurrent_hosts() {
+        // Like several Lua states on their own threads, each with its own handles
+        let expression = CString::new("n * 2").unwrap();
+        let name = CString::new("n").unwrap();
+        std::thread::scope(|scope| {
+            for worker in 0..8i64 {
+                let (expression, name) = (&expression, &name);
+                scope.spawn(move || {
+                    for i in 0..50 {
+                        let program = program_handle_new();
+                        let context = context_handle_new();
+                        let n = worker * 1000 + i;
+                        unsafe {
+                            assert_eq!(
+                                cel_flat_compile(program, expression.as_ptr()),
+                                CelErrorCode::Ok
+                            );
+                            assert_eq!(
+                                cel_flat_set_int(context, name.as_ptr(), n),
+                                CelErrorCode::Ok
+                            );
+                        }
+                        let result = cel_flat_execute(program, context);
+                        assert_eq!(cel_flat_result_int(result), n * 2);
+                        assert_eq!(cel_flat_result_free(result), CelErrorCode::Ok);
+                        assert_eq!(program_handle_free(program), CelErrorCode::Ok);
+                        assert_eq!(context_handle_free(context), CelErrorCode::Ok);
+                        assert_eq!(cel_flat_execute(program, context), 0);
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_flat_states() {
+        // Each thread plays a Lua state with its own registry, freed with its handles
+        let expression = CString::new("n + 1").unwrap();
+        let name = CString::new("n").unwrap();
+        std::thread::scope(|scope| {
+            for worker in 0..4i64 {
+                let (expression, name) = (&expression, &name);
+                scope.spawn(move || {
+                    let state = cel_state_new();
+                    assert_ne!(state, 0);
+                    let program = program_handle_new_in(state);
+                    let mut results = Vec::new();
+                    for i in 0..20 {
+                        let context = context_handle_new_in(state);
+                        unsafe {
+                            assert_eq!(
+                                cel_flat_compile(program, expression.as_ptr()),
+                                CelErrorCode::Ok
+                            );
+                            assert_eq!(
+                                cel_flat_set_int(context, name.as_ptr(), worker * 100 + i),
+                                CelErrorCode::Ok
+                            );
+                        }
+                        let result = cel_flat_execute(program, context);
+                        assert_eq!(cel_flat_result_int(result), worker * 100 + i + 1);
+                        results.push((context, result));
+                    }
+
+                    assert_eq!(cel_state_free(state), CelErrorCode::Ok);
+                    assert_eq!(cel_state_free(state), CelErrorCode::InvalidHandle);
+                    assert_eq!(program_handle_free(program), CelErrorCode::InvalidHandle);
+                    for (context, result) in results {
+                        assert_eq!(context_handle_free(context), CelErrorCode::InvalidHandle);
+                        assert_eq!(cel_flat_result_free(result), CelErrorCode::InvalidHandle);
+                    }
+                    assert_eq!(program_handle_new_in(state), 0);
+                    assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidHandle);
+                });
+            }
+        });
+
+        // Handles outside any state are untouched by freeing one
+        let state = cel_state_new();
+        let program = program_handle_new();
+        assert_eq!(cel_state_free(state), CelErrorCode::Ok);
+        assert_eq!(program_handle_free(program), CelErrorCode::Ok);
+        assert_eq!(context_handle_new_in(0), 0);
+    }
+}
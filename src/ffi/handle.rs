@@ -0,0 +1,588 @@
+use super::error::{cel_last_error, contain, contain_with_error, report, CelErrorCode};
+use super::flat::failure;
+use super::program::copy_error_to_buffer;
+use super::{
+    context_add_variable, context_reset, program_compile, program_execute, CelValue, Context,
+    Program,
+};
+use std::ffi::c_char;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+
+/// A registry slot, the generation its current value was issued under and the state
+/// that owns it, or 0
+#[derive(Debug)]
+struct Slot<T> {
+    generation: u32,
+    owner: u64,
+    value: Option<Arc<RwLock<T>>>,
+}
+
+/// Slot map handing out generation-checked `u64` handles
+///
+/// A handle packs the slot index into its low 32 bits and the slot generation into
+/// its high 32 bits. Removing a value bumps the generation, so a stale handle to a
+/// reused slot is rejected instead of aliasing the new value. Generations start at 1,
+/// which keeps `0` free to mean "no handle".
+#[derive(Debug)]
+pub struct HandleRegistry<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+}
+
+impl<T> HandleRegistry<T> {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    /// Store a value and return the handle that refers to it
+    ///
+    /// # Panics
+    ///
+    /// Will panic if more than `u32::MAX` slots are in use at once
+    pub fn insert(&mut self, value: T) -> u64 {
+        self.insert_owned(value, 0)
+    }
+
+    /// Store a value owned by the state `owner`, which `remove_owned` removes together
+    ///
+    /// # Panics
+    ///
+    /// Will panic if more than `u32::MAX` slots are in use at once
+    pub fn insert_owned(&mut self, value: T, owner: u64) -> u64 {
+        let value = Some(Arc::new(RwLock::new(value)));
+
+        if let Some(index) = self.free.pop() {
+            if let Some(slot) = self.slots.get_mut(index as usize) {
+                slot.owner = owner;
+                slot.value = value;
+                return encode_handle(index, slot.generation);
+            }
+        }
+
+        let index = u32::try_from(self.slots.len()).expect("handle registry exhausted");
+        self.slots.push(Slot {
+            generation: 1,
+            owner,
+            value,
+        });
+        encode_handle(index, 1)
+    }
+
+    /// Look up a live value, returning `None` for unknown or stale handles
+    #[must_use]
+    pub fn get(&self, handle: u64) -> Option<Arc<RwLock<T>>> {
+        let (index, generation) = decode_handle(handle);
+        self.slots
+            .get(index as usize)
+            .filter(|slot| slot.generation == generation)
+            .and_then(|slot| slot.value.clone())
+    }
+
+    /// The state owning a live value, 0 if none does, or `None` for unknown or stale
+    /// handles
+    #[must_use]
+    pub fn owner(&self, handle: u64) -> Option<u64> {
+        let (index, generation) = decode_handle(handle);
+        self.slots
+            .get(index as usize)
+            .filter(|slot| slot.generation == generation && slot.value.is_some())
+            .map(|slot| slot.owner)
+    }
+
+    /// Remove a value, invalidating its handle
+    ///
+    /// In-flight users that already cloned the value keep it alive until they finish.
+    pub fn remove(&mut self, handle: u64) -> Option<Arc<RwLock<T>>> {
+        let (index, generation) = decode_handle(handle);
+        let slot = self
+            .slots
+            .get_mut(index as usize)
+            .filter(|slot| slot.generation == generation)?;
+        let value = slot.value.take()?;
+
+        slot.generation = slot.generation.wrapping_add(1).max(1);
+        slot.owner = 0;
+        self.free.push(index);
+        Some(value)
+    }
+
+    /// Remove every value the state `owner` owns, returning how many there were
+    pub fn remove_owned(&mut self, owner: u64) -> usize {
+        if owner == 0 {
+            return 0;
+        }
+
+        let mut removed = 0;
+        for (index, slot) in (0..).zip(&mut self.slots) {
+            if slot.owner == owner && slot.value.take().is_some() {
+                slot.generation = slot.generation.wrapping_add(1).max(1);
+                slot.owner = 0;
+                self.free.push(index);
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Number of live values in the registry
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.value.is_some()).count()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every live value in the registry, in slot order
+    pub fn values(&self) -> impl Iterator<Item = &Arc<RwLock<T>>> {
+        self.slots.iter().filter_map(|slot| slot.value.as_ref())
+    }
+}
+
+impl<T> Default for HandleRegistry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const fn encode_handle(index: u32, generation: u32) -> u64 {
+    ((generation as u64) << 32) | index as u64
+}
+
+#[allow(clippy::cast_possible_truncation)]
+const fn decode_handle(handle: u64) -> (u32, u32) {
+    (handle as u32, (handle >> 32) as u32)
+}
+
+pub(crate) static PROGRAMS: Mutex<HandleRegistry<Program>> = Mutex::new(HandleRegistry::new());
+pub(crate) static CONTEXTS: Mutex<HandleRegistry<Context>> = Mutex::new(HandleRegistry::new());
+/// Host states, such as Lua states, each owning the handles created for it
+///
+/// Lock it before `PROGRAMS`, `CONTEXTS` or the flat results, so a handle is never
+/// created for a state that is being freed.
+pub(crate) static STATES: Mutex<HandleRegistry<()>> = Mutex::new(HandleRegistry::new());
+
+pub(crate) fn lookup<T>(
+    registry: &Mutex<HandleRegistry<T>>,
+    handle: u64,
+) -> Option<Arc<RwLock<T>>> {
+    registry.lock().unwrap_or_else(PoisonError::into_inner).get(handle)
+}
+
+pub(crate) fn release<T>(registry: &Mutex<HandleRegistry<T>>, handle: u64) -> CelErrorCode {
+    match registry.lock().unwrap_or_else(PoisonError::into_inner).remove(handle) {
+        Some(_) => CelErrorCode::Ok,
+        None => CelErrorCode::InvalidHandle,
+    }
+}
+
+/// Create a new program and return its handle
+#[no_mangle]
+pub extern "C" fn program_handle_new() -> u64 {
+    contain(0, || {
+        PROGRAMS.lock().unwrap_or_else(PoisonError::into_inner).insert(Program::new())
+    })
+}
+
+/// Insert `value` into `registry`, owned by `state`, or return 0 with `cel_last_error`
+/// set to `InvalidHandle` if the state is unknown or was freed
+fn insert_in_state<T>(registry: &Mutex<HandleRegistry<T>>, state: u64, value: T) -> u64 {
+    let states = STATES.lock().unwrap_or_else(PoisonError::into_inner);
+    if state == 0 || states.get(state).is_none() {
+        failure(CelErrorCode::InvalidHandle, "Invalid state handle");
+        return 0;
+    }
+    let handle = registry
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert_owned(value, state);
+    report(CelErrorCode::Ok);
+    handle
+}
+
+/// Create a new program owned by a state from `cel_state_new` and return its handle
+///
+/// `cel_state_free` frees it with the state, if `program_handle_free` has not. Returns
+/// 0 with `cel_last_error` set to `InvalidHandle` if the state is unknown or was freed.
+#[no_mangle]
+pub extern "C" fn program_handle_new_in(state: u64) -> u64 {
+    contain(0, || insert_in_state(&PROGRAMS, state, Program::new()))
+}
+
+/// Free a program handle
+///
+/// Returns `InvalidHandle` if the handle is unknown or was already freed.
+#[no_mangle]
+pub extern "C" fn program_handle_free(program: u64) -> CelErrorCode {
+    contain(CelErrorCode::Internal, || release(&PROGRAMS, program))
+}
+
+/// Compile a CEL expression into the program referred to by a handle
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `expression` is either null or a valid null-terminated C string
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn program_handle_compile(
+    program: u64,
+    expression: *const c_char,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> CelErrorCode {
+    contain_with_error(CelErrorCode::Internal, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return CelErrorCode::NullArgument;
+        };
+        let Some(program) = lookup(&PROGRAMS, program) else {
+            copy_error_to_buffer("Invalid program handle", errbuf, errbuf_len);
+            return CelErrorCode::InvalidHandle;
+        };
+
+        let mut program = program.write().unwrap_or_else(PoisonError::into_inner);
+        program_compile(&mut *program, expression, errbuf, errbuf_len);
+        cel_last_error()
+    })
+}
+
+/// Execute the program referred to by a handle against a context handle
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `result` is either null or a valid pointer to a `CelValue` struct that can be written to
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn program_handle_execute(
+    program: u64,
+    context: u64,
+    result: *mut CelValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> CelErrorCode {
+    contain_with_error(CelErrorCode::Internal, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return CelErrorCode::NullArgument;
+        };
+        let Some(program) = lookup(&PROGRAMS, program) else {
+            copy_error_to_buffer("Invalid program handle", errbuf, errbuf_len);
+            return CelErrorCode::InvalidHandle;
+        };
+        let Some(context) = lookup(&CONTEXTS, context) else {
+            copy_error_to_buffer("Invalid context handle", errbuf, errbuf_len);
+            return CelErrorCode::InvalidHandle;
+        };
+
+        let program = program.read().unwrap_or_else(PoisonError::into_inner);
+        let context = context.read().unwrap_or_else(PoisonError::into_inner);
+        program_execute(&*program, &*context, result, errbuf, errbuf_len);
+        cel_last_error()
+    })
+}
+
+/// Create a new context and return its handle
+#[no_mangle]
+pub extern "C" fn context_handle_new() -> u64 {
+    contain(0, || {
+        CONTEXTS.lock().unwrap_or_else(PoisonError::into_inner).insert(Context::new())
+    })
+}
+
+/// Create a new context owned by a state from `cel_state_new` and return its handle
+///
+/// `cel_state_free` frees it with the state, if `context_handle_free` has not. Returns
+/// 0 with `cel_last_error` set to `InvalidHandle` if the state is unknown or was freed.
+#[no_mangle]
+pub extern "C" fn context_handle_new_in(state: u64) -> u64 {
+    contain(0, || insert_in_state(&CONTEXTS, state, Context::new()))
+}
+
+/// Free a context handle
+///
+/// Returns `InvalidHandle` if the handle is unknown or was already freed.
+#[no_mangle]
+pub extern "C" fn context_handle_free(context: u64) -> CelErrorCode {
+    contain(CelErrorCode::Internal, || release(&CONTEXTS, context))
+}
+
+/// Add a variable to the context referred to by a handle
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `name` is either null or a valid null-terminated C string
+/// - `value` is either null or a valid pointer to a `CelValue`
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn context_handle_add_variable(
+    context: u64,
+    name: *const c_char,
+    value: *const CelValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> CelErrorCode {
+    contain_with_error(CelErrorCode::Internal, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return CelErrorCode::NullArgument;
+        };
+        let Some(context) = lookup(&CONTEXTS, context) else {
+            copy_error_to_buffer("Invalid context handle", errbuf, errbuf_len);
+            return CelErrorCode::InvalidHandle;
+        };
+
+        let mut context = context.write().unwrap_or_else(PoisonError::into_inner);
+        context_add_variable(&mut *context, name, value, errbuf, errbuf_len);
+        cel_last_error()
+    })
+}
+
+/// Reset the context referred to by a handle, clearing all variables
+#[no_mangle]
+pub extern "C" fn context_handle_reset(context: u64) -> CelErrorCode {
+    contain(CelErrorCode::Internal, || {
+        let Some(context) = lookup(&CONTEXTS, context) else {
+            return CelErrorCode::InvalidHandle;
+        };
+
+        let mut context = context.write().unwrap_or_else(PoisonError::into_inner);
+        unsafe { context_reset(&mut *context) };
+        CelErrorCode::Ok
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CelValueData, CelValueType};
+    use std::ffi::CString;
+
+    #[test]
+    fn test_registry_insert_and_get() {
+        let mut registry = HandleRegistry::new();
+        let handle = registry.insert(42);
+
+        assert_ne!(handle, 0);
+        assert_eq!(registry.len(), 1);
+        assert_eq!(*registry.get(handle).unwrap().read().unwrap(), 42);
+    }
+
+    #[test]
+    fn test_registry_rejects_stale_handle() {
+        let mut registry = HandleRegistry::new();
+        let first = registry.insert("first");
+        assert!(registry.remove(first).is_some());
+
+        // The slot is reused, but under a new generation
+        let second = registry.insert("second");
+        assert_ne!(first, second);
+        assert!(registry.get(first).is_none());
+        assert!(registry.remove(first).is_none());
+        assert_eq!(*registry.get(second).unwrap().read().unwrap(), "second");
+    }
+
+    #[test]
+    fn test_registry_rejects_unknown_handles() {
+        let mut registry: HandleRegistry<i32> = HandleRegistry::default();
+        assert!(registry.is_empty());
+        assert!(registry.get(0).is_none());
+        assert!(registry.get(u64::MAX).is_none());
+        assert!(registry.remove(12345).is_none());
+    }
+
+    #[test]
+    fn test_registry_double_remove() {
+        let mut registry = HandleRegistry::new();
+        let handle = registry.insert(1);
+
+        assert!(registry.remove(handle).is_some());
+        assert!(registry.remove(handle).is_none());
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn test_registry_remove_owned() {
+        let mut registry = HandleRegistry::new();
+        let [first, second] = [1, 2].map(|owner| registry.insert_owned(owner, owner));
+        let unowned = registry.insert(0);
+        let freed = registry.insert_owned(1, 1);
+        assert!(registry.remove(freed).is_some());
+
+        assert_eq!(registry.owner(first), Some(1));
+        assert_eq!(registry.owner(unowned), Some(0));
+        assert_eq!(registry.owner(freed), None);
+        assert_eq!(registry.remove_owned(0), 0);
+        assert_eq!(registry.remove_owned(1), 1);
+        assert!(registry.get(first).is_none());
+        assert!(registry.get(second).is_some());
+        assert!(registry.get(unowned).is_some());
+        assert_eq!(registry.remove_owned(1), 0);
+    }
+
+    #[test]
+    fn test_handle_encoding_roundtrip() {
+        let handle = encode_handle(7, 3);
+        assert_eq!(decode_handle(handle), (7, 3));
+    }
+
+    #[test]
+    fn test_program_handle_lifecycle() {
+        let program = program_handle_new();
+        assert_ne!(program, 0);
+        assert_eq!(program_handle_free(program), CelErrorCode::Ok);
+        assert_eq!(program_handle_free(program), CelErrorCode::InvalidHandle);
+    }
+
+    #[test]
+    fn test_context_handle_lifecycle() {
+        let context = context_handle_new();
+        assert_eq!(context_handle_reset(context), CelErrorCode::Ok);
+        assert_eq!(context_handle_free(context), CelErrorCode::Ok);
+        assert_eq!(context_handle_reset(context), CelErrorCode::InvalidHandle);
+    }
+
+    #[test]
+    fn test_handle_execute() {
+        let program = program_handle_new();
+        let context = context_handle_new();
+        let mut error_buf = [0u8; 256];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            let name = CString::new("x").unwrap();
+            let value = CelValue {
+                value_type: CelValueType::Int,
+                data: CelValueData { int_val: 20 },
+            };
+            assert_eq!(
+                context_handle_add_variable(
+                    context,
+                    name.as_ptr(),
+                    &raw const value,
+                    error_buf.as_mut_ptr(),
+                    &mut error_len,
+                ),
+                CelErrorCode::Ok
+            );
+
+            let expression = CString::new("x * 2 + 2").unwrap();
+            error_len = error_buf.len();
+            assert_eq!(
+                program_handle_compile(
+                    program,
+                    expression.as_ptr(),
+                    error_buf.as_mut_ptr(),
+                    &mut error_len,
+                ),
+                CelErrorCode::Ok
+            );
+
+            let mut result = CelValue {
+                value_type: CelValueType::Null,
+                data: CelValueData { int_val: 0 },
+            };
+            error_len = error_buf.len();
+            assert_eq!(
+                program_handle_execute(
+                    program,
+                    context,
+                    &raw mut result,
+                    error_buf.as_mut_ptr(),
+                    &mut error_len,
+                ),
+                CelErrorCode::Ok
+            );
+            assert_eq!(result.value_type, CelValueType::Int);
+            assert_eq!(result.data.int_val, 42);
+        }
+
+        assert_eq!(program_handle_free(program), CelErrorCode::Ok);
+        assert_eq!(context_handle_free(context), CelErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_handle_use_after_free_is_rejected() {
+        let program = program_handle_new();
+        let context = context_handle_new();
+        assert_eq!(program_handle_free(program), CelErrorCode::Ok);
+
+        let mut error_buf = [0u8; 256];
+        let mut error_len = error_buf.len();
+        let expression = CString::new("1 + 1").unwrap();
+
+        unsafe {
+            let rc = program_handle_compile(
+                program,
+                expression.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            );
+            assert_eq!(rc, CelErrorCode::InvalidHandle);
+            let message = std::str::from_utf8(error_buf.get(..error_len).unwrap()).unwrap();
+            assert!(message.contains("Invalid program handle"));
+
+            let mut result = CelValue {
+                value_type: CelValueType::Null,
+                data: CelValueData { int_val: 0 },
+            };
+            error_len = error_buf.len();
+            let rc = program_handle_execute(
+                program,
+                context,
+                &raw mut result,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            );
+            assert_eq!(rc, CelErrorCode::InvalidHandle);
+        }
+
+        assert_eq!(context_handle_free(context), CelErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_handle_compile_error_code() {
+        let program = program_handle_new();
+        let mut error_buf = [0u8; 256];
+        let mut error_len = error_buf.len();
+        let expression = CString::new("1 + +").unwrap();
+
+        let rc = unsafe {
+            program_handle_compile(
+                program,
+                expression.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            )
+        };
+
+        assert_eq!(rc, CelErrorCode::CompileError);
+        assert!(error_len > 0);
+        assert_eq!(program_handle_free(program), CelErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_handle_compile_panic_code() {
+        let program = program_handle_new();
+        let mut error_buf = [0u8; 256];
+        let mut error_len = error_buf.len();
+        let expression = CString::new("99999999999999999999").unwrap();
+
+        let rc = unsafe {
+            program_handle_compile(
+                program,
+                expression.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            )
+        };
+
+        assert_eq!(rc, CelErrorCode::Internal);
+        assert_eq!(program_handle_free(program), CelErrorCode::Ok);
+    }
+}
@@ -0,0 +1,107 @@
+//! Routing of the library's internal warnings to a host-supplied callback
+
+use super::error::contain;
+use std::ffi::{c_char, c_void, CString};
+use std::sync::{PoisonError, RwLock};
+
+/// Severity of a log message, from most to least severe
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CelLogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+/// Callback receiving the library's log messages
+///
+/// It receives the `user_data` it was registered with, the message's level and the
+/// message, which is only valid for the duration of the call.
+pub type CelLogCallback =
+    unsafe extern "C" fn(user_data: *mut c_void, level: CelLogLevel, message: *const c_char);
+
+#[derive(Debug, Clone, Copy)]
+struct Logger {
+    callback: CelLogCallback,
+    user_data: *mut c_void,
+    level: CelLogLevel,
+}
+
+// SAFETY: `cel_set_log_callback` requires the callback and its user data to be usable
+// from any thread that calls into the library.
+unsafe impl Send for Logger {}
+unsafe impl Sync for Logger {}
+
+static LOGGER: RwLock<Option<Logger>> = RwLock::new(None);
+
+/// Send the library's messages at `level` or more severe to `callback`
+///
+/// Without a callback, warnings such as error messages truncated to fit `errbuf`,
+/// conversions that lose information and cache evictions are dropped. Passing a null
+/// `callback` stops logging. The callback may call back into the library, including
+/// to replace itself.
+///
+/// # Safety
+/// Until it is replaced, `callback` must be safe to call with `user_data` from any
+/// thread that calls into the library
+#[no_mangle]
+pub unsafe extern "C" fn cel_set_log_callback(
+    callback: Option<CelLogCallback>,
+    user_data: *mut c_void,
+    level: CelLogLevel,
+) {
+    contain((), || {
+        *LOGGER.write().unwrap_or_else(PoisonError::into_inner) = callback.map(|callback| Logger {
+            callback,
+            user_data,
+            level,
+        });
+    })
+}
+
+/// Pass a message to the host's callback, building it only if it will be delivered
+pub(crate) fn log(level: CelLogLevel, message: impl FnOnce() -> String) {
+    // Copied out so that the callback runs without the lock held
+    let logger = *LOGGER.read().unwrap_or_else(PoisonError::into_inner);
+    let Some(logger) = logger.filter(|logger| level <= logger.level) else {
+        return;
+    };
+    let Ok(message) = CString::new(message()) else {
+        return;
+    };
+    unsafe { (logger.callback)(logger.user_data, level, message.as_ptr()) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::sync::Mutex;
+
+    static MESSAGES: Mutex<Vec<(CelLogLevel, String)>> = Mutex::new(Vec::new());
+
+    unsafe extern "C" fn record(_: *mut c_void, level: CelLogLevel, message: *const c_char) {
+        let message = CStr::from_ptr(message).to_string_lossy().into_owned();
+        MESSAGES.lock().unwrap().push((level, message));
+    }
+
+    #[test]
+    fn test_log_callback() {
+        unsafe { cel_set_log_callback(Some(record), std::ptr::null_mut(), CelLogLevel::Warn) };
+
+        let mut buf = [0u8; 8];
+        let mut len = buf.len();
+        crate::ffi::copy_error_to_buffer("a long error message", buf.as_mut_ptr(), &mut len);
+        log(CelLogLevel::Debug, || "test debug message".to_string());
+
+        unsafe { cel_set_log_callback(None, std::ptr::null_mut(), CelLogLevel::Debug) };
+        log(CelLogLevel::Error, || "test after removal".to_string());
+
+        let messages = MESSAGES.lock().unwrap();
+        assert!(messages.iter().any(|(level, message)| {
+            *level == CelLogLevel::Warn && message.contains("a long error message")
+        }));
+        assert!(!messages.iter().any(|(_, message)| message.starts_with("test ")));
+    }
+}
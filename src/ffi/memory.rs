@@ -0,0 +1,272 @@
+//! Counts of the objects the library has handed to the host and not had back, for
+//! hunting leaks in bindings
+
+use super::error::contain;
+use super::handle::{CONTEXTS, PROGRAMS};
+use super::CelOwnedString;
+#[cfg(debug_assertions)]
+use super::{Context, Program};
+#[cfg(debug_assertions)]
+use std::collections::BTreeMap;
+use std::ffi::c_void;
+#[cfg(debug_assertions)]
+use std::mem::size_of;
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(debug_assertions)]
+use std::sync::Mutex;
+use std::sync::PoisonError;
+
+/// Kind of an object the library allocated for the host
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CelAllocationKind {
+    /// A string or bytes value, caller-owned or held by another library object
+    AllocatedString,
+    /// A list, map or optional array nested in a value that `cel_value_free` releases
+    AllocatedValues,
+    /// An evaluation result, released with `cel_result_free`
+    AllocatedResult,
+    /// A program, from `program_new` or `program_handle_new`
+    AllocatedProgram,
+    /// A context, from `context_new`, `context_from_json` or `context_handle_new`
+    AllocatedContext,
+}
+
+/// Library objects allocated and not yet freed, as counted by `cel_memory_stats`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CelMemoryStats {
+    pub strings: usize,
+    /// Bytes held by those strings, not counting their NUL terminators
+    pub string_bytes: usize,
+    pub values: usize,
+    pub results: usize,
+    pub programs: usize,
+    pub contexts: usize,
+}
+
+/// Live objects of each kind, indexed by `CelAllocationKind`
+///
+/// Strings are counted by the string table instead, and handles by their registries.
+static LIVE: [AtomicUsize; 5] = [const { AtomicUsize::new(0) }; 5];
+
+/// Kind and size of every live object in `LIVE`, by address, for `cel_dump_leaks`
+#[cfg(debug_assertions)]
+static ALLOCATIONS: Mutex<BTreeMap<usize, (CelAllocationKind, usize)>> =
+    Mutex::new(BTreeMap::new());
+
+/// Record that `ptr`, an object of `size` bytes, was handed to the host
+pub(crate) fn track<T>(kind: CelAllocationKind, ptr: *const T, size: usize) {
+    LIVE[kind as usize].fetch_add(1, Ordering::Relaxed);
+    #[cfg(debug_assertions)]
+    ALLOCATIONS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(ptr.addr(), (kind, size));
+    #[cfg(not(debug_assertions))]
+    let _ = (ptr, size);
+}
+
+/// Record that the host gave back `ptr`, which `track` recorded as `kind`
+pub(crate) fn untrack<T>(kind: CelAllocationKind, ptr: *const T) {
+    let _ = LIVE[kind as usize]
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |live| live.checked_sub(1));
+    #[cfg(debug_assertions)]
+    ALLOCATIONS.lock().unwrap_or_else(PoisonError::into_inner).remove(&ptr.addr());
+    #[cfg(not(debug_assertions))]
+    let _ = ptr;
+}
+
+fn live(kind: CelAllocationKind) -> usize {
+    LIVE[kind as usize].load(Ordering::Relaxed)
+}
+
+/// Count the strings, value arrays, results, programs and contexts the library has
+/// allocated and not yet freed
+///
+/// Programs and contexts behind handles are counted with those from `program_new`,
+/// `context_new` and `context_from_json`. The counts are kept in every build; a host
+/// whose counts keep growing while its workload stays the same is leaking, and a
+/// debug build of the library can list the objects with `cel_dump_leaks`.
+#[no_mangle]
+pub extern "C" fn cel_memory_stats() -> CelMemoryStats {
+    contain(CelMemoryStats::default(), || {
+        let strings = CelOwnedString::live();
+        let programs = PROGRAMS.lock().unwrap_or_else(PoisonError::into_inner).len();
+        let contexts = CONTEXTS.lock().unwrap_or_else(PoisonError::into_inner).len();
+        CelMemoryStats {
+            strings: strings.len(),
+            string_bytes: strings.iter().map(|&(_, len)| len).sum(),
+            values: live(CelAllocationKind::AllocatedValues),
+            results: live(CelAllocationKind::AllocatedResult),
+            programs: live(CelAllocationKind::AllocatedProgram) + programs,
+            contexts: live(CelAllocationKind::AllocatedContext) + contexts,
+        }
+    })
+}
+
+/// Callback receiving one live object from `cel_dump_leaks`
+///
+/// It receives the `user_data` passed to `cel_dump_leaks`, the object's kind, its
+/// address and its size in bytes. The address is only for identifying the object and
+/// must not be dereferenced.
+pub type CelLeakCallback = unsafe extern "C" fn(
+    user_data: *mut c_void,
+    kind: CelAllocationKind,
+    ptr: *const c_void,
+    size: usize,
+);
+
+/// List every library object allocated and not yet freed, in debug builds
+///
+/// Passes each object to `callback`, if it is not null, and returns how many there
+/// were. Call it once the host believes it has freed everything: whatever is listed
+/// was never given back. Programs and contexts behind handles are listed under the
+/// address of the object the handle refers to. Only debug builds of the library
+/// record addresses, so release builds do not export this; `cel_memory_stats` counts
+/// the same objects in every build.
+///
+/// The objects are collected before the first call, so the callback may call back into
+/// the library, and sees nothing allocated or freed after `cel_dump_leaks` began.
+///
+/// # Safety
+/// `callback` must be safe to call with `user_data`
+#[cfg(debug_assertions)]
+#[no_mangle]
+pub unsafe extern "C" fn cel_dump_leaks(
+    callback: Option<CelLeakCallback>,
+    user_data: *mut c_void,
+) -> usize {
+    contain(0, || {
+        let mut leaks: Vec<_> = CelOwnedString::live()
+            .into_iter()
+            .map(|(addr, len)| (addr, CelAllocationKind::AllocatedString, len))
+            .collect();
+        leaks.extend(
+            ALLOCATIONS
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .iter()
+                .map(|(&addr, &(kind, size))| (addr, kind, size)),
+        );
+        for program in PROGRAMS.lock().unwrap_or_else(PoisonError::into_inner).values() {
+            let addr = std::sync::Arc::as_ptr(program).addr();
+            leaks.push((addr, CelAllocationKind::AllocatedProgram, size_of::<Program>()));
+        }
+        for context in CONTEXTS.lock().unwrap_or_else(PoisonError::into_inner).values() {
+            let addr = std::sync::Arc::as_ptr(context).addr();
+            leaks.push((addr, CelAllocationKind::AllocatedContext, size_of::<Context>()));
+        }
+
+        if let Some(callback) = callback {
+            for &(addr, kind, size) in &leaks {
+                callback(user_data, kind, std::ptr::without_provenance(addr), size);
+            }
+        }
+        leaks.len()
+    })
+}
+
+// Listing objects needs the addresses only debug builds record
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::*;
+    use crate::{
+        cel_result_free, cel_string_release, cel_value_clone, cel_value_free, context_free,
+        context_new, program_execute_alloc, program_free, program_new, store_string_in_pool,
+        CelListValue, CelValue, CelValueData, CelValueType,
+    };
+    use std::mem::ManuallyDrop;
+
+    type Leak = (CelAllocationKind, usize, usize);
+
+    unsafe extern "C" fn collect(
+        user_data: *mut c_void,
+        kind: CelAllocationKind,
+        ptr: *const c_void,
+        size: usize,
+    ) {
+        let leaks = user_data.cast::<Vec<Leak>>().as_mut().unwrap();
+        leaks.push((kind, ptr.addr(), size));
+    }
+
+    /// Every live object, as `cel_dump_leaks` lists it
+    fn leaks() -> Vec<Leak> {
+        let mut leaks = Vec::new();
+        let count = unsafe { cel_dump_leaks(Some(collect), (&raw mut leaks).cast()) };
+        assert_eq!(count, leaks.len());
+        leaks
+    }
+
+    fn listed<T>(kind: CelAllocationKind, ptr: *const T) -> bool {
+        leaks().iter().any(|&(k, addr, _)| k == kind && addr == ptr.addr())
+    }
+
+    #[test]
+    fn test_memory_stats_count_live_objects() {
+        let program = program_new();
+        let context = context_new();
+        let string = store_string_in_pool("leak");
+
+        let stats = cel_memory_stats();
+        assert!(stats.programs >= 1 && stats.contexts >= 1);
+        assert!(stats.strings >= 1 && stats.string_bytes >= 4);
+
+        unsafe {
+            cel_string_release(string);
+            context_free(context);
+            program_free(program);
+        }
+    }
+
+    #[test]
+    fn test_dump_leaks_lists_live_objects() {
+        let program = program_new();
+        let context = context_new();
+        let string = store_string_in_pool("leak");
+        assert!(listed(CelAllocationKind::AllocatedProgram, program));
+        assert!(listed(CelAllocationKind::AllocatedContext, context));
+        assert!(leaks().contains(&(CelAllocationKind::AllocatedString, string.addr(), 4)));
+
+        unsafe {
+            (*program).compile("1").unwrap();
+            let result = program_execute_alloc(program, context);
+            assert!(listed(CelAllocationKind::AllocatedResult, result));
+            cel_result_free(result);
+
+            let items = [CelValue::int(1), CelValue::int(2)];
+            let list = CelValue {
+                value_type: CelValueType::List,
+                data: CelValueData {
+                    list_val: ManuallyDrop::new(CelListValue {
+                        items: items.as_ptr(),
+                        len: items.len(),
+                    }),
+                },
+            };
+            let mut copy = CelValue::null();
+            assert!(cel_value_clone(&list, &mut copy));
+            let copied = copy.data.list_val.items;
+            assert!(leaks().contains(&(
+                CelAllocationKind::AllocatedValues,
+                copied.addr(),
+                2 * size_of::<CelValue>()
+            )));
+            cel_value_free(&mut copy);
+
+            cel_string_release(string);
+            context_free(context);
+            program_free(program);
+        }
+    }
+
+    #[test]
+    fn test_freed_objects_are_not_listed() {
+        // A stack address, which no other test can be handed while this one runs
+        let object = 0u64;
+        track(CelAllocationKind::AllocatedResult, &raw const object, 8);
+        assert!(listed(CelAllocationKind::AllocatedResult, &raw const object));
+        untrack(CelAllocationKind::AllocatedResult, &raw const object);
+        assert!(!listed(CelAllocationKind::AllocatedResult, &raw const object));
+    }
+}
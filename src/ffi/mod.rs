@@ -1,11 +1,70 @@
+use std::collections::BTreeMap;
 use std::ffi::{c_char, CStr};
 use std::mem::ManuallyDrop;
-
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+pub mod abi;
+#[cfg(feature = "allocator")]
+pub mod allocator;
+pub mod arena;
+pub mod batch;
+pub mod builder;
+pub mod bundle;
+pub mod cancel;
 pub mod context;
+pub mod diagnostics;
+pub mod env;
+pub mod error;
+#[cfg(not(target_family = "wasm"))]
+pub mod execution;
+pub mod flat;
+pub mod handle;
+pub mod log;
+pub mod memory;
 pub mod program;
-
+#[cfg(feature = "proto")]
+pub mod proto;
+pub mod result;
+pub mod ruleset;
+#[cfg(feature = "stats")]
+pub mod stats;
+pub mod template;
+pub mod value;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use abi::*;
+#[cfg(feature = "allocator")]
+pub use allocator::*;
+pub use arena::*;
+pub use batch::*;
+pub use builder::*;
+pub use bundle::*;
+pub use cancel::*;
 pub use context::*;
+pub use diagnostics::*;
+pub use env::*;
+pub use error::*;
+#[cfg(not(target_family = "wasm"))]
+pub use execution::*;
+pub use flat::*;
+pub use handle::*;
+pub use log::*;
+pub use memory::*;
 pub use program::*;
+#[cfg(feature = "proto")]
+pub use proto::*;
+pub use result::*;
+pub use ruleset::*;
+#[cfg(feature = "stats")]
+pub use stats::*;
+pub use template::*;
+pub use value::*;
+#[cfg(feature = "wasm")]
+pub use wasm::*;
+
+use error::{contain, record_error_message, report};
+use log::log;
 
 // Simple memory management without global state
 // We'll use a simpler approach that doesn't require a global HashMap
@@ -23,7 +82,15 @@ pub enum CelValueType {
     Bytes,
     List,
     Map,
+    /// A type, as `type()` returns it, carried as its name such as `int` in `string_val`
     Type,
+    Optional,
+    Timestamp,
+    Duration,
+    /// A 128-bit signed integer, carried as decimal digits in `string_val`
+    BigInt,
+    /// An exact decimal number, carried as text such as `-12.50` in `string_val`
+    Decimal,
 }
 
 /// String value representation for CEL
@@ -42,6 +109,45 @@ pub struct CelBytesValue {
     pub len: usize,
 }
 
+/// List value representation for CEL
+#[repr(C)]
+#[derive(Debug)]
+pub struct CelListValue {
+    pub items: *const CelValue,
+    pub len: usize,
+}
+
+/// Map value representation for CEL, as parallel key and value arrays
+#[repr(C)]
+#[derive(Debug)]
+pub struct CelMapValue {
+    pub keys: *const CelValue,
+    pub values: *const CelValue,
+    pub len: usize,
+}
+
+/// Optional value representation for CEL
+///
+/// `value` points to the contained value when `has_value` is set and is null otherwise.
+#[repr(C)]
+#[derive(Debug)]
+pub struct CelOptionalValue {
+    pub has_value: bool,
+    pub value: *const CelValue,
+}
+
+/// Timestamp or duration representation for CEL, in whole seconds and nanoseconds
+///
+/// A timestamp counts from 1970-01-01T00:00:00Z with `nanos` in `0..1_000_000_000`.
+/// A duration's `nanos` has the same sign as its `seconds`, as in
+/// `google.protobuf.Duration`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CelTimeValue {
+    pub seconds: i64,
+    pub nanos: i32,
+}
+
 /// Union for CEL value data
 #[repr(C)]
 pub union CelValueData {
@@ -51,6 +157,11 @@ pub union CelValueData {
     pub double_val: f64,
     pub string_val: ManuallyDrop<CelStringValue>,
     pub bytes_val: ManuallyDrop<CelBytesValue>,
+    pub list_val: ManuallyDrop<CelListValue>,
+    pub map_val: ManuallyDrop<CelMapValue>,
+    pub optional_val: ManuallyDrop<CelOptionalValue>,
+    pub timestamp_val: CelTimeValue,
+    pub duration_val: CelTimeValue,
 }
 
 /// CEL value structure
@@ -60,20 +171,90 @@ pub struct CelValue {
     pub data: CelValueData,
 }
 
-/// Clear all strings from the pool (useful for cleanup)
-/// Note: With the new memory management approach, this is a no-op
-/// Individual strings are freed when `release_string_from_pool` is called
+/// Kept for compatibility; does nothing
+///
+/// Strings are freed one by one as their last reference is released, since clearing
+/// them all would leave callers holding freed pointers.
+#[no_mangle]
+pub const extern "C" fn cel_string_pool_clear() {}
+
+/// Number of library strings allocated and not yet freed, whether caller-owned or
+/// held by another library object, for leak checks
+#[no_mangle]
+pub extern "C" fn cel_string_pool_size() -> usize {
+    contain(0, || CelOwnedString::table().len())
+}
+
+/// Number of distinct identifiers in the table shared by all programs and contexts
+#[no_mangle]
+pub extern "C" fn cel_intern_count() -> usize {
+    contain(0, crate::api::interned_count)
+}
+
+/// Drop the shared identifiers nothing uses any more, returning how many were dropped
+///
+/// Identifiers outlive the programs and contexts that used them until this is called.
+#[no_mangle]
+pub extern "C" fn cel_intern_purge() -> usize {
+    contain(0, crate::api::purge_interned)
+}
+
+/// Register a time zone for the timestamp accessors from the bytes of a compiled `TZif` file
+///
+/// `data` points to `len` bytes, such as the contents of
+/// `/usr/share/zoneinfo/Europe/Stockholm`, and the zone is then available as `name`
+/// to expressions like `ts.getHours('Europe/Stockholm')` in every program. Hosts
+/// without a zoneinfo directory register the zones their rules use this way. Returns
+/// false with `cel_last_error` set to `InvalidArgument` if `name` is not a zone name
+/// or `data` is not `TZif` data, or to `NullArgument` if `name` or `data` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `name` is either null or a valid null-terminated C string
+/// - `data` is either null or points to `len` readable bytes
 #[no_mangle]
-pub const extern "C" fn cel_string_pool_clear() {
-    // No-op with the new approach - memory is managed per-string
+pub unsafe extern "C" fn cel_add_time_zone(
+    name: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> bool {
+    contain(false, || {
+        if name.is_null() || data.is_null() {
+            return report(CelErrorCode::NullArgument);
+        }
+        let Ok(name) = CStr::from_ptr(name).to_str() else {
+            return report(CelErrorCode::InvalidArgument);
+        };
+        match crate::api::add_time_zone(name, std::slice::from_raw_parts(data, len)) {
+            Ok(()) => report(CelErrorCode::Ok),
+            Err(_) => report(CelErrorCode::InvalidArgument),
+        }
+    })
 }
 
-/// Get the number of strings currently in the pool
-/// Note: With the new memory management approach, we can't track count
-/// Returns 0 as we don't maintain a global pool anymore
+/// Read time zones nobody registered from the `TZif` files under the directory `dir`
+///
+/// Zones are looked up by name as files under `dir` on first use, so
+/// `Europe/Stockholm` reads `dir/Europe/Stockholm`. A null `dir` restores the default,
+/// `$TZDIR` if it is set and `/usr/share/zoneinfo` otherwise. Returns false with
+/// `cel_last_error` set to `InvalidArgument` if `dir` is not UTF-8.
+///
+/// # Safety
+/// The caller must ensure that `dir` is either null or a valid null-terminated C string
 #[no_mangle]
-pub const extern "C" fn cel_string_pool_size() -> usize {
-    0 // No global pool anymore
+pub unsafe extern "C" fn cel_set_tzdata_dir(dir: *const c_char) -> bool {
+    contain(false, || {
+        let dir = if dir.is_null() {
+            None
+        } else {
+            let Ok(dir) = CStr::from_ptr(dir).to_str() else {
+                return report(CelErrorCode::InvalidArgument);
+            };
+            Some(dir.into())
+        };
+        crate::api::set_tzdata_dir(dir);
+        report(CelErrorCode::Ok)
+    })
 }
 
 // Helper function to convert C string to Rust string
@@ -86,55 +267,235 @@ pub unsafe fn c_str_to_string(ptr: *const c_char) -> Result<String, std::str::Ut
     Ok(c_str.to_str()?.to_string())
 }
 
-// String memory management functions - simplified without global state
-/// # Panics
+/// A string handed out by the library, as recorded in the allocation table
 ///
-/// Will panic if unwrap fails
+/// String pointers returned in `CelStringValue` point at `len` bytes followed by a NUL
+/// terminator, and every live one has an entry keyed by its address. When `owned` is
+/// set the caller owns one reference and must drop it with `cel_string_release`; the
+/// bytes are freed when the last reference goes away. Strings without the flag belong
+/// to another library object (such as an evaluation result) and ignore
+/// retain/release.
+///
+/// Functions taking a string pointer look it up before touching it, so a pointer the
+/// library never handed out, or already freed, is reported instead of being read or
+/// freed again. Once freed, an address may be handed out again for a new string, so
+/// a stale pointer is only caught until then.
+#[derive(Debug)]
+pub struct CelOwnedString {
+    pub owned: bool,
+    refcount: usize,
+    pub len: usize,
+}
+
+/// Every live library string, by the address of its first byte
+static STRINGS: Mutex<BTreeMap<usize, CelOwnedString>> = Mutex::new(BTreeMap::new());
+
+impl CelOwnedString {
+    fn table() -> MutexGuard<'static, BTreeMap<usize, Self>> {
+        STRINGS.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn allocate(bytes: &[u8], owned: bool) -> *const u8 {
+        // The string bytes and the NUL terminator in one allocation
+        let mut buffer = Vec::with_capacity(bytes.len() + 1);
+        buffer.extend_from_slice(bytes);
+        buffer.push(0);
+        let ptr = Box::into_raw(buffer.into_boxed_slice()).cast::<u8>();
+        let string = Self {
+            owned,
+            refcount: 1,
+            len: bytes.len(),
+        };
+        Self::table().insert(ptr.addr(), string);
+        ptr
+    }
+
+    /// Free the bytes of a string whose table entry was removed
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by [`CelOwnedString::allocate`] for `len` bytes
+    unsafe fn deallocate(ptr: *const u8, len: usize) {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr.cast_mut(), len + 1)));
+    }
+
+    /// The address and length of every live library string
+    pub(crate) fn live() -> Vec<(usize, usize)> {
+        Self::table().iter().map(|(&addr, string)| (addr, string.len)).collect()
+    }
+
+    /// Whether `ptr` is a live library string, and whether the caller owns it
+    fn owned(ptr: *const u8) -> Option<bool> {
+        Self::table().get(&ptr.addr()).map(|string| string.owned)
+    }
+
+    /// The references held on a live library string
+    fn refcount(ptr: *const u8) -> Option<usize> {
+        Self::table().get(&ptr.addr()).map(|string| string.refcount)
+    }
+
+    /// Add a reference to a caller-owned string
+    fn retain(ptr: *const u8) -> bool {
+        if ptr.is_null() {
+            return false;
+        }
+        let mut table = Self::table();
+        match table.get_mut(&ptr.addr()) {
+            Some(string) if string.owned => {
+                string.refcount += 1;
+                true
+            }
+            Some(_) => false,
+            None => {
+                drop(table);
+                unknown_string(ptr, "retained")
+            }
+        }
+    }
+
+    /// Drop one reference, freeing the allocation when it was the last one
+    fn release(ptr: *const u8) -> bool {
+        if ptr.is_null() {
+            return false;
+        }
+        let mut table = Self::table();
+        let len = match table.get_mut(&ptr.addr()) {
+            Some(string) if string.owned => {
+                string.refcount -= 1;
+                if string.refcount > 0 {
+                    return true;
+                }
+                string.len
+            }
+            Some(_) => return false,
+            None => {
+                drop(table);
+                return unknown_string(ptr, "released");
+            }
+        };
+        table.remove(&ptr.addr());
+        drop(table);
+        // SAFETY: the entry was live, so `ptr` came from `allocate` and is freed once
+        unsafe { Self::deallocate(ptr, len) };
+        true
+    }
+}
+
+/// Report a string pointer that is not in the allocation table, because the library
+/// never handed it out or it was already freed
+fn unknown_string(ptr: *const u8, action: &str) -> bool {
+    let message = format!("{ptr:p} cannot be {action}: it is not a live string from this library");
+    log(CelLogLevel::Warn, || message.clone());
+    record_error_message(&message);
+    report(CelErrorCode::InvalidPointer)
+}
+
+/// Copy a string into a new caller-owned library allocation with a reference count of 1
 #[must_use]
 pub fn store_string_in_pool(s: &str) -> *const u8 {
-    // Create a CString and convert it to a raw pointer
-    // This ensures null-termination and proper memory layout
-    let c_string = std::ffi::CString::new(s).unwrap();
-    let bytes = c_string.into_bytes_with_nul();
-    let boxed_bytes = bytes.into_boxed_slice();
-    let ptr = boxed_bytes.as_ptr();
+    CelOwnedString::allocate(s.as_bytes(), true)
+}
 
-    // Leak the box so the memory stays allocated
-    Box::leak(boxed_bytes);
+/// Copy arbitrary bytes into a new caller-owned library allocation with a reference count of 1
+pub(crate) fn store_owned_bytes(bytes: &[u8]) -> *const u8 {
+    CelOwnedString::allocate(bytes, true)
+}
 
-    ptr
+/// Copy bytes into a library-owned string that callers must not release
+///
+/// The owning library object frees it with [`free_library_string`].
+pub(crate) fn store_library_string(bytes: &[u8]) -> *const u8 {
+    CelOwnedString::allocate(bytes, false)
+}
+
+/// Free a string created by [`store_library_string`]
+///
+/// Null pointers and pointers that are not live library-owned strings are ignored.
+pub(crate) fn free_library_string(ptr: *const u8) {
+    let mut table = CelOwnedString::table();
+    let Some(len) = table.get(&ptr.addr()).filter(|string| !string.owned).map(|string| string.len)
+    else {
+        return;
+    };
+    table.remove(&ptr.addr());
+    drop(table);
+    // SAFETY: the entry was live, so `ptr` came from `allocate` and is freed once
+    unsafe { CelOwnedString::deallocate(ptr, len) };
 }
 
+/// Drop the caller's reference to a string returned by `store_string_in_pool`
+///
+/// Pointers that are not live library strings, such as ones already released, are
+/// reported with `InvalidPointer` and otherwise ignored.
 pub fn release_string_from_pool(ptr: *const u8) {
-    if !ptr.is_null() {
-        unsafe {
-            // SAFETY: This function should only be called with pointers that were
-            // returned by store_string_in_pool. We cannot validate the pointer
-            // without additional bookkeeping, so we trust the caller.
-            // If an invalid pointer is passed, this will cause undefined behavior.
-            let c_str = std::ffi::CStr::from_ptr(ptr.cast::<i8>());
-            let len = c_str.to_bytes_with_nul().len();
+    CelOwnedString::release(ptr);
+}
 
-            // Reconstruct the Box and let it drop to free the memory
-            let slice_ptr = std::ptr::slice_from_raw_parts_mut(ptr.cast_mut(), len);
-            let _boxed_slice = Box::from_raw(slice_ptr);
-            // Box automatically drops and frees the memory
-        }
-    }
+/// Add a reference to a caller-owned library string
+///
+/// Returns false if `ptr` is null or not caller-owned. A pointer that is not a live
+/// library string, such as one already fully released, also returns false, with
+/// `cel_last_error` set to `InvalidPointer`.
+///
+/// # Safety
+/// `ptr` may be any pointer; it is looked up, never read, unless it is a live library
+/// string
+#[no_mangle]
+pub unsafe extern "C" fn cel_string_retain(ptr: *const u8) -> bool {
+    contain(false, || CelOwnedString::retain(ptr))
+}
+
+/// Drop a reference to a caller-owned library string, freeing it on the last release
+///
+/// Returns false if `ptr` is null or not owned by the caller, in which case nothing
+/// is freed. A pointer that is not a live library string, such as one released
+/// before, is a double free: nothing is freed, the call returns false with
+/// `cel_last_error` set to `InvalidPointer`, and a warning is logged.
+///
+/// # Safety
+/// `ptr` may be any pointer; it is looked up, never read, unless it is a live library
+/// string
+#[no_mangle]
+pub unsafe extern "C" fn cel_string_release(ptr: *const u8) -> bool {
+    contain(false, || CelOwnedString::release(ptr))
+}
+
+/// Whether the caller owns (and must release) a library string
+///
+/// False for null pointers and pointers that are not live library strings.
+///
+/// # Safety
+/// `ptr` may be any pointer; it is only looked up
+#[no_mangle]
+pub unsafe extern "C" fn cel_string_is_owned(ptr: *const u8) -> bool {
+    contain(false, || CelOwnedString::owned(ptr).unwrap_or(false))
+}
+
+/// Current reference count of a library string, or 0 for null pointers and pointers
+/// that are not live library strings
+///
+/// # Safety
+/// `ptr` may be any pointer; it is only looked up
+#[no_mangle]
+pub unsafe extern "C" fn cel_string_refcount(ptr: *const u8) -> usize {
+    contain(0, || CelOwnedString::refcount(ptr).unwrap_or(0))
 }
 
 /// Free a string that was allocated by the library
 ///
+/// Equivalent to `cel_string_release`: the string is only freed once every
+/// reference taken with `cel_string_retain` has been released as well, and freeing
+/// it again is reported with `InvalidPointer` instead of freeing anything.
+///
 /// # Safety
-/// The caller must ensure that:
-/// - `ptr` is either null or a valid pointer returned by a CEL library function
-/// - `ptr` has not been previously freed
-/// - No other references to the string exist
+/// `ptr` may be any pointer; it is looked up, never read, unless it is a live library
+/// string
 #[no_mangle]
 pub unsafe extern "C" fn cel_string_free(ptr: *const u8) {
-    if !ptr.is_null() {
-        release_string_from_pool(ptr);
-    }
+    contain((), || {
+        if !ptr.is_null() {
+            release_string_from_pool(ptr);
+        }
+    })
 }
 
 #[cfg(test)]
@@ -270,15 +631,15 @@ mod tests {
         let ptr1 = store_string_in_pool("String 1");
         let ptr2 = store_string_in_pool("String 2");
 
-        // With the new approach, we manually free the strings
-        release_string_from_pool(ptr1);
-        release_string_from_pool(ptr2);
-
-        // Clear the pool (no-op with new approach)
+        // Clearing is a no-op: strings stay live until released
         cel_string_pool_clear();
+        unsafe {
+            assert_eq!(cel_string_refcount(ptr1), 1);
+            assert_eq!(cel_string_refcount(ptr2), 1);
+        }
 
-        // Pool size is always 0 with new approach
-        assert_eq!(cel_string_pool_size(), 0);
+        release_string_from_pool(ptr1);
+        release_string_from_pool(ptr2);
     }
 
     #[test]
@@ -622,10 +983,11 @@ mod tests {
             ptrs.push(store_string_in_pool(s));
         }
 
-        // All pointers should be valid
+        // All pointers should be valid, and tracked until released
         for ptr in &ptrs {
             assert!(!ptr.is_null());
         }
+        assert!(cel_string_pool_size() >= ptrs.len());
 
         // Free half the strings
         for ptr in &ptrs[..25] {
@@ -636,13 +998,6 @@ mod tests {
         for ptr in &ptrs[25..] {
             release_string_from_pool(*ptr);
         }
-
-        // Clear function is a no-op now
-        cel_string_pool_clear();
-
-        // Pool size is always 0 with new approach
-        let final_size = cel_string_pool_size();
-        assert_eq!(final_size, 0);
     }
 
     #[test]
@@ -702,6 +1057,103 @@ mod tests {
         // In real usage, this would be managed by the string pool
     }
 
+    #[test]
+    fn test_owned_string_refcounting() {
+        let ptr = store_string_in_pool("refcounted");
+
+        unsafe {
+            assert!(cel_string_is_owned(ptr));
+            assert_eq!(cel_string_refcount(ptr), 1);
+
+            assert!(cel_string_retain(ptr));
+            assert_eq!(cel_string_refcount(ptr), 2);
+
+            // First release keeps the bytes alive for the second holder
+            assert!(cel_string_release(ptr));
+            assert_eq!(cel_string_refcount(ptr), 1);
+            let bytes = std::slice::from_raw_parts(ptr, "refcounted".len());
+            assert_eq!(bytes, b"refcounted");
+
+            assert!(cel_string_release(ptr));
+        }
+    }
+
+    #[test]
+    fn test_owned_string_null_handling() {
+        unsafe {
+            assert!(!cel_string_retain(std::ptr::null()));
+            assert!(!cel_string_release(std::ptr::null()));
+            assert!(!cel_string_is_owned(std::ptr::null()));
+            assert_eq!(cel_string_refcount(std::ptr::null()), 0);
+        }
+    }
+
+    #[test]
+    fn test_borrowed_string_ignores_release() {
+        let ptr = store_library_string(b"library owned");
+
+        unsafe {
+            assert!(!cel_string_is_owned(ptr));
+            assert!(!cel_string_retain(ptr));
+            assert!(!cel_string_release(ptr));
+            assert_eq!(cel_string_refcount(ptr), 1);
+
+            // Free it the way the owning library object would
+            free_library_string(ptr);
+        }
+    }
+
+    #[test]
+    fn test_string_pool_interior_nul() {
+        // Previously CString::new(...).unwrap() panicked on embedded NUL bytes
+        let text = "before\0after";
+        let ptr = store_string_in_pool(text);
+
+        unsafe {
+            let bytes = std::slice::from_raw_parts(ptr, text.len() + 1);
+            assert_eq!(bytes.get(..text.len()).unwrap(), text.as_bytes());
+            assert_eq!(*bytes.get(text.len()).unwrap(), 0);
+            assert!(cel_string_release(ptr));
+        }
+    }
+
+    #[test]
+    fn test_string_double_free_is_reported() {
+        let ptr = store_string_in_pool("freed twice");
+
+        unsafe {
+            assert!(cel_string_release(ptr));
+            assert_eq!(cel_string_refcount(ptr), 0);
+            assert!(!cel_string_is_owned(ptr));
+
+            assert!(!cel_string_release(ptr));
+            assert_eq!(cel_last_error(), CelErrorCode::InvalidPointer);
+            assert!(!cel_string_retain(ptr));
+            assert_eq!(cel_last_error(), CelErrorCode::InvalidPointer);
+            cel_string_free(ptr);
+        }
+    }
+
+    #[test]
+    fn test_foreign_string_is_rejected() {
+        // Memory the library did not allocate is never read or freed
+        let text = String::from("not from the library");
+        let ptr = text.as_ptr();
+
+        unsafe {
+            assert!(!cel_string_is_owned(ptr));
+            assert_eq!(cel_string_refcount(ptr), 0);
+            assert!(!cel_string_release(ptr));
+            assert_eq!(cel_last_error(), CelErrorCode::InvalidPointer);
+            // Nor is a pointer into a library string
+            let string = store_string_in_pool("inner");
+            assert!(!cel_string_release(string.add(1)));
+            assert_eq!(cel_string_refcount(string), 1);
+            assert!(cel_string_release(string));
+        }
+        assert_eq!(text, "not from the library");
+    }
+
     // Add a cleanup test that runs last to clean up global state
     #[test]
     fn zzz_test_cleanup() {
@@ -709,6 +1161,29 @@ mod tests {
         // and cleans up any remaining string pool memory
         test_cleanup();
     } // This test should run last to attempt cleanup of global resources
+
+    #[test]
+    fn test_time_zones() {
+        let name = CString::new("Test/Invalid").unwrap();
+        let data = b"not tzif";
+        unsafe {
+            assert!(!cel_add_time_zone(name.as_ptr(), data.as_ptr(), data.len()));
+            assert_eq!(cel_last_error(), CelErrorCode::InvalidArgument);
+            assert!(!cel_add_time_zone(std::ptr::null(), data.as_ptr(), data.len()));
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+
+            let dir = CString::new("/nonexistent/zoneinfo").unwrap();
+            assert!(cel_set_tzdata_dir(dir.as_ptr()));
+            let mut program = Program::new();
+            program
+                .compile("timestamp('2024-01-01T00:00:00Z').getHours('Europe/Oslo')")
+                .unwrap();
+            let error = program.execute(&Context::new()).unwrap_err();
+            assert!(error.contains("unknown time zone 'Europe/Oslo'"), "{error}");
+            assert!(cel_set_tzdata_dir(std::ptr::null()));
+        }
+    }
+
     #[test]
     fn zzz_final_test_cleanup() {
         cel_string_pool_clear();
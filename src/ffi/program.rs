@@ -1,13 +1,19 @@
-use super::Context;
-use cel_interpreter::{Context as CelContext, Program as CelProgram, Value as CelRustValue};
+use super::context::raw_bytes;
+use super::error::{contain, contain_with_error, execution_code, record_error_message, report};
+use super::log::{log, CelLogLevel};
+use super::memory::{track, untrack, CelAllocationKind};
+use super::value::tagged_text;
+use super::{CelErrorCode, CelStringValue, CelValueType, Context, Env};
+use crate::api::{self, Value as CelRustValue};
 use std::ffi::{c_char, CStr};
-use std::mem::ManuallyDrop;
+use std::mem::{size_of, ManuallyDrop};
 
 /// CEL Program for compiling and executing expressions
 #[derive(Debug)]
 pub struct Program {
-    program: Option<CelProgram>,
+    program: Option<api::Program>,
     variables: Vec<String>,
+    compile_error: Option<api::CompileError>,
 }
 
 impl Program {
@@ -16,6 +22,7 @@ impl Program {
         Self {
             program: None,
             variables: Vec::new(),
+            compile_error: None,
         }
     }
 
@@ -23,15 +30,48 @@ impl Program {
     ///
     /// Returns an error if the expression cannot be compiled.
     pub fn compile(&mut self, expression: &str) -> Result<(), String> {
-        match CelProgram::compile(expression) {
-            Ok(program) => {
-                // Extract variables from the expression
-                self.variables = extract_variables(expression);
-                self.program = Some(program);
-                Ok(())
+        self.compile_with_options(expression, &api::CompileOptions::default())
+    }
+
+    /// # Errors
+    ///
+    /// Returns an error if the expression exceeds a limit in `options` or cannot be
+    /// compiled.
+    pub fn compile_with_options(
+        &mut self,
+        expression: &str,
+        options: &api::CompileOptions,
+    ) -> Result<(), String> {
+        self.store(expression, api::Program::compile_with_options(expression, options))
+    }
+
+    /// # Errors
+    ///
+    /// Returns an error if the expression exceeds a limit of `env`, cannot be compiled
+    /// or calls a function `env` does not provide.
+    pub fn compile_with_env(&mut self, expression: &str, env: &api::Env) -> Result<(), String> {
+        self.store(expression, env.compile(expression))
+    }
+
+    fn store(
+        &mut self,
+        expression: &str,
+        compiled: Result<api::Program, api::Error>,
+    ) -> Result<(), String> {
+        let program = match compiled {
+            Ok(program) => program,
+            Err(e) => {
+                if let api::Error::Compile(compile_error) = &e {
+                    self.compile_error = Some(compile_error.clone());
+                }
+                return Err(e.to_string());
             }
-            Err(e) => Err(format!("Compilation error: {e}")),
-        }
+        };
+        self.compile_error = None;
+        // Extract variables from the expression
+        self.variables = extract_variables(expression);
+        self.program = Some(program);
+        Ok(())
     }
 
     /// # Errors
@@ -39,23 +79,150 @@ impl Program {
     /// Returns an error if the program has not been compiled or if execution fails.
     pub fn execute(&self, context: &Context) -> Result<CelRustValue, String> {
         let program = self.program.as_ref().ok_or("No expression compiled")?;
+        program.execute(context).map_err(|e| e.to_string())
+    }
 
-        // Create CEL context from our context
-        let mut cel_ctx = CelContext::default();
-
-        for (name, value) in context.get_variables() {
-            let cel_value = json_to_cel_value(value)
-                .map_err(|e| format!("Error converting variable '{name}': {e}"))?;
-            cel_ctx.add_variable_from_value(name, cel_value);
+    /// Execute like `execute`, stopping early once `token`, if any, is cancelled
+    ///
+    /// # Errors
+    ///
+    /// Returns `api::Error::Cancelled` if `token` is cancelled before evaluation
+    /// finishes, or another error if nothing is compiled or evaluation fails.
+    pub fn execute_cancellable(
+        &self,
+        context: &Context,
+        token: Option<&api::CancelToken>,
+    ) -> Result<CelRustValue, api::Error> {
+        let program = self
+            .program
+            .as_ref()
+            .ok_or_else(|| api::Error::InvalidArgument("No expression compiled".to_string()))?;
+        match token {
+            Some(token) => program.execute_cancellable(context, token),
+            None => program.execute(context),
         }
+    }
+
+    /// Execute like `execute`, also returning the trace of evaluated subexpressions,
+    /// which is `None` if evaluation did not start
+    #[must_use]
+    pub fn execute_traced(
+        &self,
+        context: &Context,
+    ) -> (Result<CelRustValue, String>, Option<api::TraceNode>) {
+        let Some(program) = self.program.as_ref() else {
+            return (Err("No expression compiled".to_string()), None);
+        };
+        let (result, trace) = program.execute_traced(context);
+        (result.map_err(|e| e.to_string()), trace)
+    }
 
-        program.execute(&cel_ctx).map_err(|e| format!("Execution error: {e}"))
+    /// Execute against each context in turn, with one outcome per context
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the program has not been compiled.
+    pub fn execute_batch(
+        &self,
+        contexts: &[&Context],
+    ) -> Result<Vec<Result<CelRustValue, api::Error>>, String> {
+        let program = self.program.as_ref().ok_or("No expression compiled")?;
+        Ok(program.execute_batch(contexts))
+    }
+
+    /// Execute like `execute_batch`, sharding the contexts across `threads` threads
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the program has not been compiled or the threads cannot
+    /// be started.
+    #[cfg(feature = "parallel")]
+    pub fn execute_batch_parallel(
+        &self,
+        contexts: &[&Context],
+        threads: usize,
+    ) -> Result<Vec<Result<CelRustValue, api::Error>>, String> {
+        let program = self.program.as_ref().ok_or("No expression compiled")?;
+        program.execute_batch_parallel(contexts, threads).map_err(|e| e.to_string())
+    }
+
+    /// # Errors
+    ///
+    /// Returns an error if the program has not been compiled or if the optimized
+    /// expression cannot be compiled.
+    pub fn optimize(&mut self) -> Result<api::Optimization, String> {
+        let program = self.program.as_mut().ok_or("No expression compiled")?;
+        program.optimize().map_err(|e| e.to_string())
+    }
+
+    /// Require executions to return a value of `value_type`, returning false if
+    /// nothing is compiled
+    pub fn expect_type(&mut self, value_type: CelValueType) -> bool {
+        let Some(program) = self.program.as_mut() else {
+            return false;
+        };
+        program.expect_type(Some(value_type));
+        true
+    }
+
+    /// Return member names of `result_enum` for int results, returning false if
+    /// nothing is compiled
+    pub fn set_result_enum(&mut self, result_enum: Option<api::EnumType>) -> bool {
+        let Some(program) = self.program.as_mut() else {
+            return false;
+        };
+        program.set_result_enum(result_enum);
+        true
+    }
+
+    /// Execution counters of the compiled program, or `None` if nothing is compiled
+    #[cfg(feature = "stats")]
+    #[must_use]
+    pub fn stats(&self) -> Option<api::ProgramStats> {
+        self.program.as_ref().map(api::Program::stats)
+    }
+
+    /// Reset the execution counters, returning false if nothing is compiled
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&self) -> bool {
+        self.program.as_ref().map(api::Program::reset_stats).is_some()
+    }
+
+    /// Fingerprint of the compiled expression, or `None` if nothing is compiled
+    #[must_use]
+    pub fn fingerprint(&self) -> Option<u64> {
+        self.program.as_ref().map(api::Program::fingerprint)
+    }
+
+    /// Size and complexity of the compiled expression, or `None` if nothing is compiled
+    #[must_use]
+    pub fn metrics(&self) -> Option<api::ProgramMetrics> {
+        self.program.as_ref().map(api::Program::metrics)
+    }
+
+    /// Field paths the compiled expression reads, or `None` if nothing is compiled
+    #[must_use]
+    pub fn dependencies(&self) -> Option<Vec<String>> {
+        self.program.as_ref().map(api::Program::dependencies)
+    }
+
+    /// The compiled expression as macro-expanded AST JSON, or `None` if nothing is
+    /// compiled
+    #[must_use]
+    pub fn expanded_ast_json(&self) -> Option<String> {
+        self.program.as_ref().map(api::Program::expanded_ast_json)
     }
 
     #[must_use]
     pub fn get_variables(&self) -> &[String] {
         &self.variables
     }
+
+    /// Details of the last `compile` call, if it failed to parse the expression
+    #[must_use]
+    pub const fn compile_error(&self) -> Option<&api::CompileError> {
+        self.compile_error.as_ref()
+    }
 }
 
 impl Default for Program {
@@ -64,133 +231,1179 @@ impl Default for Program {
     }
 }
 
-/// Create a new program instance
+/// Location and details of a failed compilation
+///
+/// `line` and `column` are 1-based, with the column counted in characters, and are 0
+/// when the parser reported no location. `offset` and `length` are byte positions of
+/// the offending `token` within `source`. The strings are not null-terminated.
+#[repr(C)]
+#[derive(Debug)]
+pub struct CelCompileError {
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
+    pub length: usize,
+    pub message: CelStringValue,
+    pub token: CelStringValue,
+    pub source: CelStringValue,
+}
+
+/// Expression node counts before and after `program_optimize`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CelOptimization {
+    pub nodes_before: usize,
+    pub nodes_after: usize,
+}
+
+/// Size and complexity of a compiled expression, from `program_metrics`
+///
+/// `cost` estimates the nodes one evaluation visits, counting those inside each
+/// comprehension 100 times per enclosing comprehension.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CelProgramMetrics {
+    pub nodes: usize,
+    pub depth: usize,
+    pub comprehensions: usize,
+    pub calls: usize,
+    pub cost: u64,
+}
+
+/// Create a new program instance
+#[no_mangle]
+pub extern "C" fn program_new() -> *mut Program {
+    contain(std::ptr::null_mut(), || {
+        let program = Box::into_raw(Box::new(Program::new()));
+        track(CelAllocationKind::AllocatedProgram, program, size_of::<Program>());
+        program
+    })
+}
+
+/// Free a program instance
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `program` has not been previously freed
+/// - No other references to the program exist
+#[no_mangle]
+pub unsafe extern "C" fn program_free(program: *mut Program) {
+    contain((), || {
+        if !program.is_null() {
+            untrack(CelAllocationKind::AllocatedProgram, program);
+            drop(Box::from_raw(program));
+        }
+    })
+}
+
+/// Compile a CEL expression
+///
+/// Returns false with `cel_last_error` set to `NullArgument` if `program`,
+/// `expression` or `errbuf_len` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `expression` is either null or a valid null-terminated C string
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn program_compile(
+    program: *mut Program,
+    expression: *const c_char,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        program_compile_with_options(program, expression, std::ptr::null(), errbuf, errbuf_len)
+    })
+}
+
+/// Limits for compiling untrusted expressions, as described for `api::CompileOptions`
+///
+/// A limit of 0 disables that check. `disabled_macros` points to
+/// `disabled_macros_len` null-terminated names from `has`, `all`, `exists`,
+/// `exists_one`, `map` and `filter`, and may be null when the length is 0.
+/// `disable_regex` rejects expressions that call `matches()`, and the regex limits
+/// bound the patterns it accepts. `string_ext`, `math_ext` and `list_ext` enable the strings,
+/// math and lists extensions, `optional_ext` enables optional values, `unicode_ext`
+/// the case-insensitive, normalizing and collating string functions, `encoders_ext`
+/// the `base64`, `hex` and `url` encoding functions, and `json_ext` `json.parse` and
+/// `json.stringify`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct CelCompileOptions {
+    pub max_expression_length: usize,
+    pub max_nesting_depth: usize,
+    pub max_comprehensions: usize,
+    pub disabled_macros: *const *const c_char,
+    pub disabled_macros_len: usize,
+    pub disable_regex: bool,
+    pub max_regex_length: usize,
+    pub max_regex_size: usize,
+    pub string_ext: bool,
+    pub math_ext: bool,
+    pub list_ext: bool,
+    pub optional_ext: bool,
+    pub unicode_ext: bool,
+    pub encoders_ext: bool,
+    pub json_ext: bool,
+}
+
+/// Read caller-provided options, with null meaning the defaults
+///
+/// # Safety
+/// `options` must be null or point to a valid `CelCompileOptions` whose macro names
+/// are valid null-terminated C strings
+pub(crate) unsafe fn compile_options(
+    options: *const CelCompileOptions,
+) -> Result<api::CompileOptions, (CelErrorCode, String)> {
+    let Some(options) = options.as_ref() else {
+        return Ok(api::CompileOptions::default());
+    };
+    if options.disabled_macros.is_null() && options.disabled_macros_len > 0 {
+        return Err((CelErrorCode::NullArgument, "Disabled macros pointer is null".to_string()));
+    }
+
+    let names = if options.disabled_macros_len == 0 {
+        &[][..]
+    } else {
+        std::slice::from_raw_parts(options.disabled_macros, options.disabled_macros_len)
+    };
+    let mut disabled_macros = Vec::with_capacity(names.len());
+    for &name in names {
+        if name.is_null() {
+            return Err((CelErrorCode::NullArgument, "Macro name is null".to_string()));
+        }
+        let name = CStr::from_ptr(name)
+            .to_str()
+            .map_err(|e| (CelErrorCode::InvalidArgument, format!("Invalid macro name: {e}")))?;
+        if !api::MACROS.contains(&name) {
+            return Err((CelErrorCode::InvalidArgument, format!("Unknown macro '{name}'")));
+        }
+        disabled_macros.push(name.to_string());
+    }
+
+    Ok(api::CompileOptions {
+        max_expression_length: options.max_expression_length,
+        max_nesting_depth: options.max_nesting_depth,
+        max_comprehensions: options.max_comprehensions,
+        disabled_macros,
+        disable_regex: options.disable_regex,
+        max_regex_length: options.max_regex_length,
+        max_regex_size: options.max_regex_size,
+        string_ext: options.string_ext,
+        math_ext: options.math_ext,
+        list_ext: options.list_ext,
+        optional_ext: options.optional_ext,
+        unicode_ext: options.unicode_ext,
+        encoders_ext: options.encoders_ext,
+        json_ext: options.json_ext,
+    })
+}
+
+/// Compile a CEL expression after checking it against `options`
+///
+/// A null `options` applies no limits, as `program_compile` does. An expression that
+/// exceeds a limit fails like one that cannot be parsed, with `cel_last_error` set to
+/// `CompileError` and the details available from `program_compile_error`. Returns
+/// false with `cel_last_error` set to `InvalidArgument` if `options` names an unknown
+/// macro, or to `NullArgument` if `program`, `expression` or `errbuf_len` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `expression` is either null or a valid null-terminated C string
+/// - `options` is either null or a valid pointer to a `CelCompileOptions`
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn program_compile_with_options(
+    program: *mut Program,
+    expression: *const c_char,
+    options: *const CelCompileOptions,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(program) = program.as_mut() else {
+            return fail(CelErrorCode::NullArgument, "Program is null", errbuf, errbuf_len);
+        };
+        if expression.is_null() {
+            return fail(CelErrorCode::NullArgument, "Expression is null", errbuf, errbuf_len);
+        }
+
+        let expr_str = match CStr::from_ptr(expression).to_str() {
+            Ok(s) => s,
+            Err(e) => {
+                let error_msg = format!("Invalid expression string: {e}");
+                return fail(CelErrorCode::InvalidArgument, &error_msg, errbuf, errbuf_len);
+            }
+        };
+        let options = match compile_options(options) {
+            Ok(options) => options,
+            Err((code, e)) => return fail(code, &e, errbuf, errbuf_len),
+        };
+
+        match program.compile_with_options(expr_str, &options) {
+            Ok(()) => report(CelErrorCode::Ok),
+            Err(e) => fail(CelErrorCode::CompileError, &e, errbuf, errbuf_len),
+        }
+    })
+}
+
+/// Details of the program's last compilation failure
+///
+/// The strings in `out_error` are borrowed from the program and stay valid until it
+/// is next compiled or freed. Returns false with `cel_last_error` set to
+/// `InvalidArgument` if the last `program_compile` did not fail to parse, or to
+/// `NullArgument` if `program` or `out_error` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `out_error` is either null or a valid pointer to a `CelCompileError` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn program_compile_error(
+    program: *const Program,
+    out_error: *mut CelCompileError,
+) -> bool {
+    contain(false, || {
+        let (Some(program), Some(out_error)) = (program.as_ref(), out_error.as_mut()) else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(error) = program.compile_error() else {
+            return report(CelErrorCode::InvalidArgument);
+        };
+
+        let borrowed = |s: &str| CelStringValue {
+            ptr: s.as_ptr(),
+            len: s.len(),
+        };
+        *out_error = CelCompileError {
+            line: error.line,
+            column: error.column,
+            offset: error.offset,
+            length: error.length,
+            message: borrowed(&error.message),
+            token: borrowed(&error.token),
+            source: borrowed(&error.source),
+        };
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Render a compilation error as its message and the source line with a caret
+/// underline
+///
+/// The text has the form
+///
+/// ```text
+/// ERROR: <input>:1:5: unrecognized token: '+'
+///  | 1 + + 2
+///  | ....^
+/// ```
+///
+/// and is written to `out` as a caller-owned library string; drop it with
+/// `cel_string_release`. Returns false with `cel_last_error` set to `InvalidArgument`
+/// if a string in `error` is not UTF-8, or to `NullArgument` if `error` or `out` is
+/// null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `error` is either null or a valid pointer to a `CelCompileError`, such as one
+///   filled in by `program_compile_error`, whose strings are valid for their lengths
+/// - `out` is either null or a valid pointer to a `CelStringValue` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn cel_error_render(
+    error: *const CelCompileError,
+    out: *mut CelStringValue,
+) -> bool {
+    contain(false, || {
+        let (Some(error), Some(out)) = (error.as_ref(), out.as_mut()) else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let text =
+            |s: &CelStringValue| std::str::from_utf8(raw_bytes(s.ptr, s.len)).map(str::to_string);
+        let (Ok(message), Ok(token), Ok(source)) =
+            (text(&error.message), text(&error.token), text(&error.source))
+        else {
+            return report(CelErrorCode::InvalidArgument);
+        };
+
+        let rendered = api::CompileError {
+            message,
+            line: error.line,
+            column: error.column,
+            offset: error.offset,
+            length: error.length,
+            token,
+            expected: Vec::new(),
+            source,
+        }
+        .render();
+        *out = owned_library_string(&rendered);
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Execute the compiled expression
+///
+/// Returns false with `cel_last_error` set to `NullArgument` if `program`,
+/// `context`, `result` or `errbuf_len` is null.
+///
+/// # Ownership
+/// When the result is a string, `result.data.string_val.ptr` is a caller-owned
+/// library string with a reference count of 1 (`cel_string_is_owned` returns true).
+/// The caller must drop it with `cel_string_release` once done; further holders may
+/// take extra references with `cel_string_retain`. All other result types carry no
+/// allocations.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `result` is either null or a valid pointer to a `CelValue` struct that can be written to
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn program_execute(
+    program: *const Program,
+    context: *const Context,
+    result: *mut super::CelValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(program) = program.as_ref() else {
+            return fail(CelErrorCode::NullArgument, "Program is null", errbuf, errbuf_len);
+        };
+        let Some(context) = context.as_ref() else {
+            return fail(CelErrorCode::NullArgument, "Context is null", errbuf, errbuf_len);
+        };
+        if result.is_null() {
+            return fail(CelErrorCode::NullArgument, "Result pointer is null", errbuf, errbuf_len);
+        }
+
+        match program.execute_cancellable(context, None) {
+            Ok(cel_value) => match cel_value_to_c_value(&cel_value, result) {
+                Ok(()) => report(CelErrorCode::Ok),
+                Err(e) => fail(CelErrorCode::ExecutionError, &e, errbuf, errbuf_len),
+            },
+            Err(e) => fail(execution_code(&e), &e.to_string(), errbuf, errbuf_len),
+        }
+    })
+}
+
+/// Execute the compiled expression and serialize the result as JSON
+///
+/// The result is converted as described for `api::OwnedValue`: bytes become base64
+/// strings and map keys become strings. Returns false with `cel_last_error` set to
+/// `ExecutionError` if evaluation fails or the result has no JSON form, or to
+/// `NullArgument` if `program`, `context`, `out_json` or `errbuf_len` is null.
+///
+/// # Ownership
+/// `out_json.ptr` is a caller-owned library string; drop it with `cel_string_release`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `out_json` is either null or a valid pointer to a `CelStringValue` that can be written to
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn program_execute_json(
+    program: *const Program,
+    context: *const Context,
+    out_json: *mut CelStringValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(program) = program.as_ref() else {
+            return fail(CelErrorCode::NullArgument, "Program is null", errbuf, errbuf_len);
+        };
+        let Some(context) = context.as_ref() else {
+            return fail(CelErrorCode::NullArgument, "Context is null", errbuf, errbuf_len);
+        };
+        let Some(out_json) = out_json.as_mut() else {
+            return fail(CelErrorCode::NullArgument, "Output pointer is null", errbuf, errbuf_len);
+        };
+
+        match program.execute(context).and_then(|value| value_to_json(&value)) {
+            Ok(json) => {
+                *out_json = owned_library_string(&json);
+                report(CelErrorCode::Ok)
+            }
+            Err(e) => fail(CelErrorCode::ExecutionError, &e, errbuf, errbuf_len),
+        }
+    })
+}
+
+/// How `program_execute_bool` turns a result into a yes or no
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CelTruthiness {
+    /// Only bools are accepted
+    TruthinessStrict,
+    /// Null, zero and empty values are false, and other values true
+    TruthinessLenient,
+}
+
+impl From<CelTruthiness> for api::Truthiness {
+    fn from(truthiness: CelTruthiness) -> Self {
+        match truthiness {
+            CelTruthiness::TruthinessStrict => Self::Strict,
+            CelTruthiness::TruthinessLenient => Self::Lenient,
+        }
+    }
+}
+
+/// Execute the compiled expression and write whether its result counts as true
+///
+/// Under `TruthinessStrict` the result has to be a bool, and any other result fails
+/// with `ResultType`. Under `TruthinessLenient` null, numbers equal to zero, NaN,
+/// empty strings, bytes, lists and maps, empty optionals and zero durations are
+/// false, and every other value is true. Returns false with `cel_last_error` set as
+/// for `program_execute` if execution fails, or to `NullArgument` if `program`,
+/// `context`, `out_bool` or `errbuf_len` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `out_bool` is either null or a valid pointer to a `bool` that can be written to
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn program_execute_bool(
+    program: *const Program,
+    context: *const Context,
+    out_bool: *mut bool,
+    truthiness: CelTruthiness,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(program) = program.as_ref() else {
+            return fail(CelErrorCode::NullArgument, "Program is null", errbuf, errbuf_len);
+        };
+        let Some(context) = context.as_ref() else {
+            return fail(CelErrorCode::NullArgument, "Context is null", errbuf, errbuf_len);
+        };
+        let Some(out_bool) = out_bool.as_mut() else {
+            return fail(CelErrorCode::NullArgument, "Output pointer is null", errbuf, errbuf_len);
+        };
+
+        let truthiness = api::Truthiness::from(truthiness);
+        match program.execute_cancellable(context, None).and_then(|v| truthiness.truthy(&v)) {
+            Ok(answer) => {
+                *out_bool = answer;
+                report(CelErrorCode::Ok)
+            }
+            Err(e) => fail(execution_code(&e), &e.to_string(), errbuf, errbuf_len),
+        }
+    })
+}
+
+/// Compile and execute an expression against JSON variables, returning the result as JSON
+///
+/// `variables_json` is a JSON object whose members are bound as variables, or null for
+/// none. The result is serialized as for `program_execute_json`. Returns false with
+/// `cel_last_error` set to `CompileError` or `ExecutionError` when the expression
+/// fails, to `InvalidArgument` if `expression` is not UTF-8 or `variables_json` is
+/// not a JSON object, or to `NullArgument` if `expression`, `out_json` or
+/// `errbuf_len` is null.
+///
+/// # Ownership
+/// `out_json.ptr` is a caller-owned library string; drop it with `cel_string_release`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `expression` is either null or a valid null-terminated C string
+/// - `variables_json` is either null or a valid null-terminated C string
+/// - `out_json` is either null or a valid pointer to a `CelStringValue` that can be written to
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn cel_eval_json(
+    expression: *const c_char,
+    variables_json: *const c_char,
+    out_json: *mut CelStringValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        if expression.is_null() {
+            return fail(CelErrorCode::NullArgument, "Expression is null", errbuf, errbuf_len);
+        }
+        let Some(out_json) = out_json.as_mut() else {
+            return fail(CelErrorCode::NullArgument, "Output pointer is null", errbuf, errbuf_len);
+        };
+
+        let expr_str = match CStr::from_ptr(expression).to_str() {
+            Ok(s) => s,
+            Err(e) => {
+                let error_msg = format!("Invalid expression string: {e}");
+                return fail(CelErrorCode::InvalidArgument, &error_msg, errbuf, errbuf_len);
+            }
+        };
+        let mut context = Context::new();
+        if !variables_json.is_null() {
+            let variables = match parse_variables_json(CStr::from_ptr(variables_json)) {
+                Ok(variables) => variables,
+                Err(e) => return fail(CelErrorCode::InvalidArgument, &e, errbuf, errbuf_len),
+            };
+            for (name, value) in variables {
+                context.add_variable(name, value);
+            }
+        }
+
+        let program = match api::Program::compile(expr_str) {
+            Ok(program) => program,
+            Err(e) => return fail(CelErrorCode::CompileError, &e.to_string(), errbuf, errbuf_len),
+        };
+        let json = program
+            .execute(&context)
+            .map_err(|e| e.to_string())
+            .and_then(|value| value_to_json(&value));
+        match json {
+            Ok(json) => {
+                *out_json = owned_library_string(&json);
+                report(CelErrorCode::Ok)
+            }
+            Err(e) => fail(CelErrorCode::ExecutionError, &e, errbuf, errbuf_len),
+        }
+    })
+}
+
+fn parse_variables_json(
+    variables_json: &CStr,
+) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+    let variables_str =
+        variables_json.to_str().map_err(|e| format!("Invalid variables string: {e}"))?;
+    match serde_json::from_str(variables_str) {
+        Ok(serde_json::Value::Object(variables)) => Ok(variables),
+        Ok(_) => Err("Variables must be a JSON object".to_string()),
+        Err(e) => Err(format!("Invalid variables JSON: {e}")),
+    }
+}
+
+pub(crate) fn value_to_json(value: &CelRustValue) -> Result<String, String> {
+    let owned = api::OwnedValue::try_from(value)?;
+    serde_json::to_string(&owned).map_err(|e| format!("Error serializing result: {e}"))
+}
+
+pub(crate) fn owned_library_string(text: &str) -> CelStringValue {
+    CelStringValue {
+        ptr: super::store_owned_bytes(text.as_bytes()),
+        len: text.len(),
+    }
+}
+
+/// Stable 64-bit fingerprint of the compiled expression's syntax tree
+///
+/// Expressions that differ only in whitespace, comments, quoting or redundant
+/// parentheses have the same fingerprint, which stays the same across releases and
+/// platforms and is unaffected by `program_optimize`. Returns false with
+/// `cel_last_error` set to `InvalidArgument` if the program has not been compiled,
+/// or to `NullArgument` if `program` or `out_hash` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `out_hash` is either null or a valid pointer to a `uint64_t` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn program_hash(program: *const Program, out_hash: *mut u64) -> bool {
+    contain(false, || {
+        let (Some(program), Some(out_hash)) = (program.as_ref(), out_hash.as_mut()) else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(fingerprint) = program.fingerprint() else {
+            return report(CelErrorCode::InvalidArgument);
+        };
+
+        *out_hash = fingerprint;
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Measure the size and complexity of the compiled expression
+///
+/// Writes its node count, deepest nesting, number of comprehensions and of function
+/// calls, and an estimate of the nodes one evaluation visits, so admission control can
+/// reject pathologically complex rules before they run. A comprehension is assumed to
+/// iterate 100 times, since its range is only known when it runs. Returns false with
+/// `cel_last_error` set to `InvalidArgument` if the program has not been compiled, or
+/// to `NullArgument` if `program` or `out` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `out` is either null or a valid pointer to a `CelProgramMetrics` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn program_metrics(
+    program: *const Program,
+    out: *mut CelProgramMetrics,
+) -> bool {
+    contain(false, || {
+        let (Some(program), Some(out)) = (program.as_ref(), out.as_mut()) else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(metrics) = program.metrics() else {
+            return report(CelErrorCode::InvalidArgument);
+        };
+
+        *out = CelProgramMetrics {
+            nodes: metrics.nodes,
+            depth: metrics.depth,
+            comprehensions: metrics.comprehensions,
+            calls: metrics.calls,
+            cost: metrics.cost,
+        };
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// The program's fingerprint as 16 lowercase hex digits
+///
+/// Fails like `program_hash`.
+///
+/// # Ownership
+/// `out_hex.ptr` is a caller-owned library string; drop it with `cel_string_release`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `out_hex` is either null or a valid pointer to a `CelStringValue` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn program_hash_hex(
+    program: *const Program,
+    out_hex: *mut CelStringValue,
+) -> bool {
+    contain(false, || {
+        let mut fingerprint = 0;
+        if !program_hash(program, &mut fingerprint) {
+            return false;
+        }
+        let Some(out_hex) = out_hex.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+
+        *out_hex = owned_library_string(&format!("{fingerprint:016x}"));
+        true
+    })
+}
+
+/// The field paths the compiled expression reads, as JSON grouped by variable
+///
+/// The object maps each variable the expression references to the paths it reads
+/// through it, such as `{"request": ["request.headers.authorization"], "user":
+/// ["user.roles[*]"]}`. Keys that are not identifiers appear in brackets, as in
+/// `request.headers["x-id"]`, and dynamic indexes and comprehension elements as
+/// `[*]`. Fails like `program_hash`.
+///
+/// # Ownership
+/// `out_json.ptr` is a caller-owned library string; drop it with `cel_string_release`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `out_json` is either null or a valid pointer to a `CelStringValue` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn program_dependencies_json(
+    program: *const Program,
+    out_json: *mut CelStringValue,
+) -> bool {
+    contain(false, || {
+        let (Some(program), Some(out_json)) = (program.as_ref(), out_json.as_mut()) else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(paths) = program.dependencies() else {
+            return report(CelErrorCode::InvalidArgument);
+        };
+
+        let mut grouped = serde_json::Map::new();
+        for path in paths {
+            let root = path.split(['.', '[']).next().unwrap_or_default().to_string();
+            let entry = grouped.entry(root).or_insert_with(|| serde_json::json!([]));
+            if let serde_json::Value::Array(paths) = entry {
+                paths.push(serde_json::Value::String(path));
+            }
+        }
+        *out_json = owned_library_string(&serde_json::Value::Object(grouped).to_string());
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// The parsed expression as JSON, with its macros expanded into comprehensions
+///
+/// The JSON is CEL's `google.api.expr.v1alpha1.Expr` in the protobuf JSON mapping, so
+/// tools that read parsed expressions from other CEL implementations can read it. Each
+/// node has a unique `id`; operators are calls to their CEL names, such as `_+_` and
+/// `@in`; `has(a.b)` is a `testOnly` select; and `all`, `exists`, `exists_one`, `map`
+/// and `filter` are comprehensions over the accumulator `__result__`. Fails like
+/// `program_hash`.
+///
+/// # Ownership
+/// `out_json.ptr` is a caller-owned library string; drop it with `cel_string_release`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `out_json` is either null or a valid pointer to a `CelStringValue` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn program_expanded_ast_json(
+    program: *const Program,
+    out_json: *mut CelStringValue,
+) -> bool {
+    contain(false, || {
+        let (Some(program), Some(out_json)) = (program.as_ref(), out_json.as_mut()) else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(json) = program.expanded_ast_json() else {
+            return report(CelErrorCode::InvalidArgument);
+        };
+        *out_json = owned_library_string(&json);
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Fold constant subexpressions and prune dead conditional branches
+///
+/// `1 + 2`, `'a' + 'b'` and `size('xyz')` are replaced by their values, and
+/// conditionals with a constant condition by the branch taken, so the program
+/// evaluates fewer nodes each time it runs. Results are unchanged, including
+/// errors. `out` receives the node counts and may be null. Returns false with
+/// `cel_last_error` set to `InvalidArgument` if the program has not been compiled,
+/// or to `NullArgument` if `program` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `out` is either null or a valid pointer to a `CelOptimization` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn program_optimize(
+    program: *mut Program,
+    out: *mut CelOptimization,
+) -> bool {
+    contain(false, || {
+        let Some(program) = program.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        if program.program.is_none() {
+            return report(CelErrorCode::InvalidArgument);
+        }
+
+        match program.optimize() {
+            Ok(optimization) => {
+                if let Some(out) = out.as_mut() {
+                    *out = CelOptimization {
+                        nodes_before: optimization.nodes_before,
+                        nodes_after: optimization.nodes_after,
+                    };
+                }
+                report(CelErrorCode::Ok)
+            }
+            Err(_) => report(CelErrorCode::CompileError),
+        }
+    })
+}
+
+/// Require executions of the program to return a value of `value_type`
+///
+/// An execution whose result has another type, such as a string where a `Bool` policy
+/// verdict was expected, fails with `ResultType` and a message naming both types.
+/// Ints, uints and doubles are distinct types, and null matches only `Null`.
+/// Compiling another expression into the program removes the requirement. Returns
+/// false with `cel_last_error` set to `InvalidArgument` if the program has not been
+/// compiled, or to `NullArgument` if `program` is null.
+///
+/// # Safety
+/// The caller must ensure that `program` is either null or a valid pointer returned
+/// by `program_new`
+#[no_mangle]
+pub unsafe extern "C" fn program_expect_type(
+    program: *mut Program,
+    value_type: CelValueType,
+) -> bool {
+    contain(false, || {
+        let Some(program) = program.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        if !program.expect_type(value_type) {
+            return report(CelErrorCode::InvalidArgument);
+        }
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Infer the type every successful execution of the program returns
+///
+/// The library has no type checker, since variables carry no declared types, so the
+/// inference is conservative: literals, comparisons, `&&` and `||`, operators on
+/// operands of one inferred type, conditionals whose branches agree and built-in
+/// functions with a fixed result type such as `size` are inferred, and anything
+/// depending on a variable or a host function is not. Writes whether a type was
+/// inferred to `out_known` and, if it was, the type to `out_type`; an unknown type is
+/// not an error. A program passed to `program_set_result_enum` infers no int type.
+/// Returns false with `cel_last_error` set to `InvalidArgument` if the program has
+/// not been compiled, or to `NullArgument` if any pointer is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `out_known` is either null or a valid pointer to a `bool` that can be written to
+/// - `out_type` is either null or a valid pointer to a `CelValueType` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn program_inferred_type(
+    program: *const Program,
+    out_known: *mut bool,
+    out_type: *mut CelValueType,
+) -> bool {
+    contain(false, || {
+        let (Some(program), Some(out_known), Some(out_type)) =
+            (program.as_ref(), out_known.as_mut(), out_type.as_mut())
+        else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(compiled) = program.program.as_ref() else {
+            return report(CelErrorCode::InvalidArgument);
+        };
+
+        let inferred = compiled.inferred_type();
+        *out_known = inferred.is_some();
+        if let Some(value_type) = inferred {
+            *out_type = value_type;
+        }
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Return member names of the enum `enum_name` in `env` for the int results it names
+///
+/// Results are strings such as `"INFO"` where the program would return `1`, and ints
+/// the enum does not name are returned unchanged. Names replace results before
+/// `program_expect_type` checks them. A null `enum_name` returns ints again, and
+/// compiling another expression into the program does too. Returns false with
+/// `cel_last_error` set to `InvalidArgument` if the program has not been compiled or
+/// `env` has no enum `enum_name`, or to `NullArgument` if `program` or `env` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `env` is either null or a valid pointer returned by `cel_env_new`
+/// - `enum_name` is either null or a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn program_set_result_enum(
+    program: *mut Program,
+    env: *const Env,
+    enum_name: *const c_char,
+) -> bool {
+    contain(false, || {
+        let (Some(program), Some(env)) = (program.as_mut(), env.as_ref()) else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let result_enum = if enum_name.is_null() {
+            None
+        } else {
+            let enum_type =
+                CStr::from_ptr(enum_name).to_str().ok().and_then(|name| env.enum_type(name));
+            let Some(enum_type) = enum_type else {
+                return report(CelErrorCode::InvalidArgument);
+            };
+            Some(enum_type.clone())
+        };
+        if !program.set_result_enum(result_enum) {
+            return report(CelErrorCode::InvalidArgument);
+        }
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Validate a CEL expression and return variables
+///
+/// Returns false with `cel_last_error` set to `NullArgument` if `expression` or
+/// `errbuf_len` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `expression` is either null or a valid null-terminated C string
+/// - `_variables` is either null or a valid pointer to receive variable names (not currently implemented)
+/// - `variables_len` is either null or a valid pointer to receive the variable count
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
 #[no_mangle]
-pub extern "C" fn program_new() -> *mut Program {
-    Box::into_raw(Box::new(Program::new()))
+pub unsafe extern "C" fn program_validate(
+    expression: *const c_char,
+    _variables: *mut *const u8,
+    variables_len: *mut usize,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        program_validate_with_options(
+            expression,
+            std::ptr::null(),
+            variables_len,
+            errbuf,
+            errbuf_len,
+        )
+    })
 }
 
-/// Free a program instance
+/// Validate a CEL expression against `options` and return the variable count
+///
+/// Limits apply as for `program_compile_with_options`, and a null `options` applies
+/// none. Returns false with `cel_last_error` set to `CompileError` if the expression
+/// exceeds a limit or cannot be parsed, to `InvalidArgument` if `options` names an
+/// unknown macro, or to `NullArgument` if `expression` or `errbuf_len` is null.
 ///
 /// # Safety
 /// The caller must ensure that:
-/// - `program` is either null or a valid pointer returned by `program_new`
-/// - `program` has not been previously freed
-/// - No other references to the program exist
+/// - `expression` is either null or a valid null-terminated C string
+/// - `options` is either null or a valid pointer to a `CelCompileOptions`
+/// - `variables_len` is either null or a valid pointer to receive the variable count
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
 #[no_mangle]
-pub unsafe extern "C" fn program_free(program: *mut Program) {
-    if !program.is_null() {
-        drop(Box::from_raw(program));
-    }
+pub unsafe extern "C" fn program_validate_with_options(
+    expression: *const c_char,
+    options: *const CelCompileOptions,
+    variables_len: *mut usize,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        if expression.is_null() {
+            return fail(CelErrorCode::NullArgument, "Expression is null", errbuf, errbuf_len);
+        }
+
+        let expr_str = match CStr::from_ptr(expression).to_str() {
+            Ok(s) => s,
+            Err(e) => {
+                let error_msg = format!("Invalid expression string: {e}");
+                return fail(CelErrorCode::InvalidArgument, &error_msg, errbuf, errbuf_len);
+            }
+        };
+        let options = match compile_options(options) {
+            Ok(options) => options,
+            Err((code, e)) => return fail(code, &e, errbuf, errbuf_len),
+        };
+
+        match api::Program::compile_with_options(expr_str, &options) {
+            Ok(_) => {
+                let vars = extract_variables(expr_str);
+                // For simplicity, we'll just return the count for now
+                // In a full implementation, you'd need to handle the variable names
+                if !variables_len.is_null() {
+                    *variables_len = vars.len();
+                }
+                report(CelErrorCode::Ok)
+            }
+            Err(api::Error::Compile(e)) => {
+                let error_msg = format!("Validation error: {e}");
+                fail(CelErrorCode::CompileError, &error_msg, errbuf, errbuf_len)
+            }
+            Err(e) => fail(CelErrorCode::CompileError, &e.to_string(), errbuf, errbuf_len),
+        }
+    })
 }
 
-/// Compile a CEL expression
+/// Rename every reference to the variable `old_name` in `expression` to `new_name`
+///
+/// The rest of the source, including strings, comments, field selections and
+/// comprehension variables that shadow `old_name`, is kept as written. An expression
+/// that does not reference `old_name` is returned unchanged. Returns false with
+/// `cel_last_error` set to `CompileError` if the expression cannot be parsed, to
+/// `InvalidArgument` if a name is not a valid variable name or a comprehension
+/// variable named `new_name` would capture a reference, or to `NullArgument` if an
+/// argument or `out_expression` is null.
+///
+/// # Ownership
+/// `out_expression.ptr` is a caller-owned library string; drop it with
+/// `cel_string_release`.
 ///
 /// # Safety
 /// The caller must ensure that:
-/// - `program` is a valid mutable reference to a Program
-/// - `expression` is a valid null-terminated C string
+/// - `expression`, `old_name` and `new_name` are either null or valid null-terminated C
+///   strings
+/// - `out_expression` is either null or a valid pointer to a `CelStringValue` that can be
+///   written to
 /// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
-/// - `errbuf_len` is a valid mutable reference to the buffer size
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
 #[no_mangle]
-pub unsafe extern "C" fn program_compile(
-    program: &mut Program,
+pub unsafe extern "C" fn program_rename_variable(
     expression: *const c_char,
+    old_name: *const c_char,
+    new_name: *const c_char,
+    out_expression: *mut CelStringValue,
     errbuf: *mut u8,
-    errbuf_len: &mut usize,
+    errbuf_len: *mut usize,
 ) -> bool {
-    let expr_str = match CStr::from_ptr(expression).to_str() {
-        Ok(s) => s,
-        Err(e) => {
-            let error_msg = format!("Invalid expression string: {e}");
-            copy_error_to_buffer(&error_msg, errbuf, errbuf_len);
-            return false;
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        if expression.is_null() || old_name.is_null() || new_name.is_null() {
+            let error_msg = "Expression or variable name is null";
+            return fail(CelErrorCode::NullArgument, error_msg, errbuf, errbuf_len);
         }
-    };
+        let Some(out_expression) = out_expression.as_mut() else {
+            return fail(CelErrorCode::NullArgument, "Output pointer is null", errbuf, errbuf_len);
+        };
 
-    match program.compile(expr_str) {
-        Ok(()) => true,
-        Err(e) => {
-            copy_error_to_buffer(&e, errbuf, errbuf_len);
-            false
+        let strings = [expression, old_name, new_name].map(|s| CStr::from_ptr(s).to_str());
+        let [Ok(expr_str), Ok(old_name), Ok(new_name)] = strings else {
+            let error_msg = "Expression and variable names must be UTF-8";
+            return fail(CelErrorCode::InvalidArgument, error_msg, errbuf, errbuf_len);
+        };
+
+        match api::rename_variable(expr_str, old_name, new_name) {
+            Ok(renamed) => {
+                *out_expression = owned_library_string(&renamed);
+                report(CelErrorCode::Ok)
+            }
+            Err(api::Error::Compile(e)) => {
+                fail(CelErrorCode::CompileError, &e.to_string(), errbuf, errbuf_len)
+            }
+            Err(e) => fail(CelErrorCode::InvalidArgument, &e.to_string(), errbuf, errbuf_len),
         }
-    }
+    })
 }
 
-/// Execute the compiled expression
+/// Simplify an expression, folding constants and removing redundant logic
+///
+/// Rewrites include `x && true` to `x`, `a || !a` to `true` and `c ? false : true`
+/// to `!c`. The operands of logical operators are taken to be booleans, so an
+/// expression keeps its value wherever it evaluated. The output is canonical CEL
+/// source, parenthesized for the parser. Returns false with `cel_last_error` set to
+/// `CompileError` if the expression cannot be parsed, to `InvalidArgument` if it is
+/// not UTF-8, or to `NullArgument` if `expression`, `out_expression` or `errbuf_len`
+/// is null.
+///
+/// # Ownership
+/// `out_expression.ptr` is a caller-owned library string; drop it with
+/// `cel_string_release`.
 ///
 /// # Safety
 /// The caller must ensure that:
-/// - `program` is a valid reference to a Program with a compiled expression
-/// - `context` is a valid reference to a Context
-/// - `result` is a valid pointer to a `CelValue` struct that can be written to
+/// - `expression` is either null or a valid null-terminated C string
+/// - `out_expression` is either null or a valid pointer to a `CelStringValue` that can be
+///   written to
 /// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
-/// - `errbuf_len` is a valid mutable reference to the buffer size
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
 #[no_mangle]
-pub unsafe extern "C" fn program_execute(
-    program: &Program,
-    context: &Context,
-    result: *mut super::CelValue,
+pub unsafe extern "C" fn cel_simplify(
+    expression: *const c_char,
+    out_expression: *mut CelStringValue,
     errbuf: *mut u8,
-    errbuf_len: &mut usize,
+    errbuf_len: *mut usize,
 ) -> bool {
-    match program.execute(context) {
-        Ok(cel_value) => match cel_value_to_c_value(&cel_value, result) {
-            Ok(()) => true,
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        if expression.is_null() {
+            return fail(CelErrorCode::NullArgument, "Expression is null", errbuf, errbuf_len);
+        }
+        let Some(out_expression) = out_expression.as_mut() else {
+            return fail(CelErrorCode::NullArgument, "Output pointer is null", errbuf, errbuf_len);
+        };
+
+        let expr_str = match CStr::from_ptr(expression).to_str() {
+            Ok(s) => s,
             Err(e) => {
-                copy_error_to_buffer(&e, errbuf, errbuf_len);
-                false
+                let error_msg = format!("Invalid expression string: {e}");
+                return fail(CelErrorCode::InvalidArgument, &error_msg, errbuf, errbuf_len);
             }
-        },
-        Err(e) => {
-            copy_error_to_buffer(&e, errbuf, errbuf_len);
-            false
+        };
+        match api::simplify(expr_str) {
+            Ok(simplified) => {
+                *out_expression = owned_library_string(&simplified);
+                report(CelErrorCode::Ok)
+            }
+            Err(e) => fail(CelErrorCode::CompileError, &e.to_string(), errbuf, errbuf_len),
         }
-    }
+    })
 }
 
-/// Validate a CEL expression and return variables
+/// Check whether two expressions are the same rule written differently
+///
+/// Layout, comments, quoting, the order of `&&`, `||` and `==` operands, mirrored
+/// comparisons such as `a < b` and `b > a`, and comprehension variable names are
+/// ignored. With `simplify`, both expressions are first simplified as by
+/// `cel_simplify`, so `x && true` matches `x`. Writes the answer to
+/// `out_equivalent`. Returns false with `cel_last_error` set to `CompileError` if
+/// either expression cannot be parsed, to `InvalidArgument` if one is not UTF-8, or
+/// to `NullArgument` if an expression, `out_equivalent` or `errbuf_len` is null.
 ///
 /// # Safety
 /// The caller must ensure that:
-/// - `expression` is a valid null-terminated C string
-/// - `_variables` is either null or a valid pointer to receive variable names (not currently implemented)
-/// - `variables_len` is either null or a valid pointer to receive the variable count
+/// - `a` and `b` are either null or valid null-terminated C strings
+/// - `out_equivalent` is either null or a valid pointer to a `bool` that can be written to
 /// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
-/// - `errbuf_len` is a valid mutable reference to the buffer size
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
 #[no_mangle]
-pub unsafe extern "C" fn program_validate(
-    expression: *const c_char,
-    _variables: *mut *const u8,
-    variables_len: *mut usize,
+pub unsafe extern "C" fn cel_expressions_equivalent(
+    a: *const c_char,
+    b: *const c_char,
+    simplify: bool,
+    out_equivalent: *mut bool,
     errbuf: *mut u8,
-    errbuf_len: &mut usize,
+    errbuf_len: *mut usize,
 ) -> bool {
-    let expr_str = match CStr::from_ptr(expression).to_str() {
-        Ok(s) => s,
-        Err(e) => {
-            let error_msg = format!("Invalid expression string: {e}");
-            copy_error_to_buffer(&error_msg, errbuf, errbuf_len);
-            return false;
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        if a.is_null() || b.is_null() {
+            return fail(CelErrorCode::NullArgument, "Expression is null", errbuf, errbuf_len);
         }
-    };
+        let Some(out_equivalent) = out_equivalent.as_mut() else {
+            return fail(CelErrorCode::NullArgument, "Output pointer is null", errbuf, errbuf_len);
+        };
 
-    match CelProgram::compile(expr_str) {
-        Ok(_) => {
-            let vars = extract_variables(expr_str);
-            // For simplicity, we'll just return the count for now
-            // In a full implementation, you'd need to handle the variable names
-            if !variables_len.is_null() {
-                *variables_len = vars.len();
+        let (Ok(a), Ok(b)) = (CStr::from_ptr(a).to_str(), CStr::from_ptr(b).to_str()) else {
+            let error_msg = "Expressions must be UTF-8";
+            return fail(CelErrorCode::InvalidArgument, error_msg, errbuf, errbuf_len);
+        };
+        match api::expressions_equivalent(a, b, simplify) {
+            Ok(equivalent) => {
+                *out_equivalent = equivalent;
+                report(CelErrorCode::Ok)
             }
-            true
-        }
-        Err(e) => {
-            let error_msg = format!("Validation error: {e}");
-            copy_error_to_buffer(&error_msg, errbuf, errbuf_len);
-            false
+            Err(e) => fail(CelErrorCode::CompileError, &e.to_string(), errbuf, errbuf_len),
         }
-    }
+    })
+}
+
+/// The identifiers `program_validate` counts as variables, for the fuzz targets
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+#[must_use]
+pub fn fuzz_extract_variables(expression: &str) -> Vec<String> {
+    extract_variables(expression)
 }
 
 fn extract_variables(expression: &str) -> Vec<String> {
@@ -359,49 +1572,22 @@ fn is_builtin_function(word: &str) -> bool {
     )
 }
 
-fn json_to_cel_value(value: &serde_json::Value) -> Result<CelRustValue, String> {
-    match value {
-        serde_json::Value::Null => Ok(CelRustValue::Null),
-        serde_json::Value::Bool(b) => Ok(CelRustValue::Bool(*b)),
-        serde_json::Value::Number(n) => n.as_i64().map_or_else(
-            || {
-                n.as_u64().map_or_else(
-                    || {
-                        n.as_f64().map_or_else(
-                            || Err("Invalid number format".to_string()),
-                            |f| Ok(CelRustValue::Float(f)),
-                        )
-                    },
-                    |u| Ok(CelRustValue::UInt(u)),
-                )
-            },
-            |i| Ok(CelRustValue::Int(i)),
-        ),
-        serde_json::Value::String(s) => Ok(CelRustValue::String(s.clone().into())),
-        serde_json::Value::Array(arr) => {
-            let cel_list: Result<Vec<_>, _> = arr.iter().map(json_to_cel_value).collect();
-            Ok(CelRustValue::List(cel_list?.into()))
-        }
-        serde_json::Value::Object(obj) => {
-            let mut cel_map = std::collections::HashMap::new();
-            for (k, v) in obj {
-                cel_map.insert(
-                    cel_interpreter::objects::Key::String(k.clone().into()),
-                    json_to_cel_value(v)?,
-                );
-            }
-            Ok(CelRustValue::Map(cel_interpreter::objects::Map {
-                map: cel_map.into(),
-            }))
-        }
-    }
-}
-
 fn cel_value_to_c_value(value: &CelRustValue, result: *mut super::CelValue) -> Result<(), String> {
     if result.is_null() {
         return Err("Result pointer is null".to_string());
     }
 
+    if let Some((value_type, text)) = tagged_text(value) {
+        unsafe {
+            (*result).value_type = value_type;
+            (*result).data.string_val = ManuallyDrop::new(CelStringValue {
+                ptr: super::store_string_in_pool(&text),
+                len: text.len(),
+            });
+        }
+        return Ok(());
+    }
+
     unsafe {
         match value {
             CelRustValue::Null => {
@@ -429,7 +1615,7 @@ fn cel_value_to_c_value(value: &CelRustValue, result: *mut super::CelValue) -> R
                 let ptr = super::store_string_in_pool(s);
                 let len = s.len();
 
-                (*result).data.string_val = ManuallyDrop::new(super::CelStringValue { ptr, len });
+                (*result).data.string_val = ManuallyDrop::new(CelStringValue { ptr, len });
             }
             CelRustValue::List(_) => {
                 return Err("List return values not yet supported".to_string());
@@ -444,7 +1630,19 @@ fn cel_value_to_c_value(value: &CelRustValue, result: *mut super::CelValue) -> R
     Ok(())
 }
 
-fn copy_error_to_buffer(error: &str, errbuf: *mut u8, errbuf_len: &mut usize) {
+/// Copy `error` into the caller's buffer and record `code` as the last error
+pub(crate) fn fail(
+    code: CelErrorCode,
+    error: &str,
+    errbuf: *mut u8,
+    errbuf_len: &mut usize,
+) -> bool {
+    copy_error_to_buffer(error, errbuf, errbuf_len);
+    report(code)
+}
+
+pub(crate) fn copy_error_to_buffer(error: &str, errbuf: *mut u8, errbuf_len: &mut usize) {
+    record_error_message(error);
     if errbuf.is_null() {
         return;
     }
@@ -452,18 +1650,27 @@ fn copy_error_to_buffer(error: &str, errbuf: *mut u8, errbuf_len: &mut usize) {
     let error_bytes = error.as_bytes();
     let copy_len = std::cmp::min(error_bytes.len(), errbuf_len.saturating_sub(1));
 
-    unsafe {
-        std::ptr::copy_nonoverlapping(error_bytes.as_ptr(), errbuf, copy_len);
-        *errbuf.add(copy_len) = 0; // null terminator
+    // A zero-length buffer has no room even for the terminator
+    if *errbuf_len > 0 {
+        unsafe {
+            std::ptr::copy_nonoverlapping(error_bytes.as_ptr(), errbuf, copy_len);
+            *errbuf.add(copy_len) = 0; // null terminator
+        }
     }
 
     *errbuf_len = copy_len;
+    if copy_len < error_bytes.len() {
+        log(CelLogLevel::Warn, || {
+            format!("error truncated to {copy_len} of {} bytes: {error}", error_bytes.len())
+        });
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::super::release_string_from_pool;
     use super::*;
+    use crate::api::json_to_cel_value;
 
     #[test]
     fn test_program_new_and_default() {
@@ -497,6 +1704,312 @@ mod tests {
         assert!(program.variables.contains(&"y".to_string()));
     }
 
+    #[test]
+    fn test_program_hash() {
+        let mut program = Program::new();
+        let mut hash = 0;
+        let mut hex = CelStringValue {
+            ptr: std::ptr::null(),
+            len: 0,
+        };
+
+        unsafe {
+            assert!(!program_hash(&program, &mut hash));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+
+            program.compile("a+b == 'x'").unwrap();
+            assert!(program_hash(&program, &mut hash));
+            assert_eq!(hash, 0x6926_07cc_7f50_2f92);
+
+            assert!(program_hash_hex(&program, &mut hex));
+            let text = std::slice::from_raw_parts(hex.ptr, hex.len);
+            assert_eq!(text, b"692607cc7f502f92");
+            crate::cel_string_release(hex.ptr);
+
+            assert!(!program_hash_hex(&program, std::ptr::null_mut()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
+    }
+
+    #[test]
+    fn test_program_dependencies_json() {
+        let mut program = Program::new();
+        let mut json = CelStringValue {
+            ptr: std::ptr::null(),
+            len: 0,
+        };
+
+        unsafe {
+            assert!(!program_dependencies_json(&program, &mut json));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+
+            program
+                .compile("request.headers.authorization != '' && user.roles.all(r, r)")
+                .unwrap();
+            assert!(program_dependencies_json(&program, &mut json));
+            let text = std::slice::from_raw_parts(json.ptr, json.len);
+            assert_eq!(
+                std::str::from_utf8(text).unwrap(),
+                r#"{"request":["request.headers.authorization"],"user":["user.roles[*]"]}"#
+            );
+            crate::cel_string_release(json.ptr);
+        }
+    }
+
+    #[test]
+    fn test_program_expanded_ast_json() {
+        let mut program = Program::new();
+        let mut json = CelStringValue {
+            ptr: std::ptr::null(),
+            len: 0,
+        };
+
+        unsafe {
+            assert!(!program_expanded_ast_json(&program, &mut json));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+
+            program.compile("!x").unwrap();
+            assert!(program_expanded_ast_json(&program, &mut json));
+            let text = std::slice::from_raw_parts(json.ptr, json.len);
+            assert_eq!(
+                std::str::from_utf8(text).unwrap(),
+                r#"{"callExpr":{"args":[{"id":2,"identExpr":{"name":"x"}}],"function":"!_"},"id":1}"#
+            );
+            crate::cel_string_release(json.ptr);
+        }
+    }
+
+    #[test]
+    fn test_program_rename_variable() {
+        let mut out = CelStringValue {
+            ptr: std::ptr::null(),
+            len: 0,
+        };
+        let mut errbuf = [0u8; 256];
+        let mut errbuf_len = errbuf.len();
+        let expression =
+            std::ffi::CString::new("user.name == 'user' && xs.all(user, user > 0)").unwrap();
+        let [old_name, new_name, invalid] =
+            ["user", "account", "not valid"].map(|name| std::ffi::CString::new(name).unwrap());
+
+        unsafe {
+            assert!(program_rename_variable(
+                expression.as_ptr(),
+                old_name.as_ptr(),
+                new_name.as_ptr(),
+                &mut out,
+                errbuf.as_mut_ptr(),
+                &mut errbuf_len,
+            ));
+            let text = std::slice::from_raw_parts(out.ptr, out.len);
+            assert_eq!(
+                std::str::from_utf8(text).unwrap(),
+                "account.name == 'user' && xs.all(user, user > 0)"
+            );
+            crate::cel_string_release(out.ptr);
+
+            assert!(!program_rename_variable(
+                expression.as_ptr(),
+                old_name.as_ptr(),
+                invalid.as_ptr(),
+                &mut out,
+                errbuf.as_mut_ptr(),
+                &mut errbuf_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+        }
+    }
+
+    #[test]
+    fn test_cel_simplify_and_equivalence() {
+        let mut out = CelStringValue {
+            ptr: std::ptr::null(),
+            len: 0,
+        };
+        let mut equivalent = false;
+        let mut errbuf = [0u8; 256];
+        let mut errbuf_len = errbuf.len();
+        let [a, b, invalid] = ["x && (y || false)", "(y && x) // duplicate", "x &&"]
+            .map(|expression| std::ffi::CString::new(expression).unwrap());
+
+        unsafe {
+            assert!(cel_simplify(a.as_ptr(), &mut out, errbuf.as_mut_ptr(), &mut errbuf_len));
+            let text = std::slice::from_raw_parts(out.ptr, out.len);
+            assert_eq!(std::str::from_utf8(text).unwrap(), "x && y");
+            crate::cel_string_release(out.ptr);
+
+            let mut check = |a: &std::ffi::CString, simplify| {
+                errbuf_len = errbuf.len();
+                let ok = cel_expressions_equivalent(
+                    a.as_ptr(),
+                    b.as_ptr(),
+                    simplify,
+                    &mut equivalent,
+                    errbuf.as_mut_ptr(),
+                    &mut errbuf_len,
+                );
+                ok.then_some(equivalent)
+            };
+            assert_eq!(check(&a, false), Some(false));
+            assert_eq!(check(&a, true), Some(true));
+            assert_eq!(check(&invalid, false), None);
+            assert_eq!(crate::cel_last_error(), CelErrorCode::CompileError);
+        }
+    }
+
+    #[test]
+    fn test_program_optimize() {
+        let mut program = Program::new();
+        let mut out = CelOptimization::default();
+
+        unsafe {
+            assert!(!program_optimize(&mut program, &mut out));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+
+            program.compile("x > 1 + 2 ? 'a' + 'b' : 'c'").unwrap();
+            assert!(program_optimize(&mut program, &mut out));
+            assert_eq!((out.nodes_before, out.nodes_after), (10, 6));
+            assert!(program_optimize(&mut program, std::ptr::null_mut()));
+
+            assert!(!program_optimize(std::ptr::null_mut(), &mut out));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
+
+        let mut context = Context::new();
+        context.add_variable("x".to_string(), serde_json::json!(4));
+        let result = program.execute(&context).unwrap();
+        assert_eq!(result, CelRustValue::String("ab".to_string().into()));
+    }
+
+    #[test]
+    fn test_program_expect_type() {
+        let mut program = Program::new();
+        let mut context = Context::new();
+        context.add_variable("verdict".to_string(), serde_json::json!("allow"));
+
+        unsafe {
+            assert!(!program_expect_type(&mut program, CelValueType::Bool));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+
+            program.compile("verdict").unwrap();
+            assert!(program_expect_type(&mut program, CelValueType::Bool));
+            let result = crate::program_execute_alloc(&program, &context);
+            assert_eq!(crate::cel_result_error_code(result), CelErrorCode::ResultType);
+            crate::cel_result_free(result);
+            assert_eq!(
+                program.execute(&context),
+                Err("Execution error: result has type string, expected bool".to_string())
+            );
+
+            program.compile("verdict == 'allow'").unwrap();
+            assert!(program_expect_type(&mut program, CelValueType::Bool));
+            assert_eq!(program.execute(&context), Ok(CelRustValue::Bool(true)));
+
+            assert!(!program_expect_type(std::ptr::null_mut(), CelValueType::Bool));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
+    }
+
+    #[test]
+    fn test_program_inferred_type() {
+        let mut program = Program::new();
+        let mut known = true;
+        let mut value_type = CelValueType::Null;
+
+        unsafe {
+            assert!(!program_inferred_type(&program, &mut known, &mut value_type));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+
+            program.compile("size(name) > 3 || admin").unwrap();
+            assert!(program_inferred_type(&program, &mut known, &mut value_type));
+            assert!(known);
+            assert_eq!(value_type, CelValueType::Bool);
+
+            program.compile("name").unwrap();
+            assert!(program_inferred_type(&program, &mut known, &mut value_type));
+            assert!(!known);
+
+            assert!(!program_inferred_type(&program, std::ptr::null_mut(), &mut value_type));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
+    }
+
+    #[test]
+    fn test_program_set_result_enum() {
+        let [level, debug, info, invalid, unknown] =
+            ["Level", "DEBUG", "INFO", "not valid", "Severity"]
+                .map(|name| std::ffi::CString::new(name).unwrap());
+        let members = [debug.as_ptr(), info.as_ptr()];
+        let values = [0i64, 1];
+        let mut program = Program::new();
+        let mut context = Context::new();
+        context.add_variable("level".to_string(), serde_json::json!(1));
+        let mut error_buf = [0u8; 256];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            let env = crate::cel_env_new(std::ptr::null(), error_buf.as_mut_ptr(), &mut error_len);
+            let registered =
+                crate::env_register_enum(env, level.as_ptr(), members.as_ptr(), values.as_ptr(), 2);
+            assert!(registered);
+            assert!(!program_set_result_enum(&mut program, env, level.as_ptr()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+
+            program
+                .compile_with_env("level == Level.INFO ? level : Level.DEBUG", &*env)
+                .unwrap();
+            assert!(program_set_result_enum(&mut program, env, level.as_ptr()));
+            assert_eq!(
+                program.execute(&context),
+                Ok(CelRustValue::String("INFO".to_string().into()))
+            );
+            assert!(program_set_result_enum(&mut program, env, std::ptr::null()));
+            assert_eq!(program.execute(&context), Ok(CelRustValue::Int(1)));
+
+            assert!(!program_set_result_enum(&mut program, env, unknown.as_ptr()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+            assert!(!program_set_result_enum(&mut program, std::ptr::null(), level.as_ptr()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+
+            let invalid = [invalid.as_ptr()];
+            assert!(!crate::env_register_enum(
+                env,
+                level.as_ptr(),
+                invalid.as_ptr(),
+                values.as_ptr(),
+                1
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+            crate::cel_env_free(env);
+        }
+    }
+
+    #[test]
+    fn test_program_metrics() {
+        let mut program = Program::new();
+        let mut out = CelProgramMetrics::default();
+
+        unsafe {
+            assert!(!program_metrics(&program, &mut out));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+
+            program.compile("x > 1 + 2 ? 'a' + 'b' : 'c'").unwrap();
+            assert!(program_metrics(&program, &mut out));
+            assert_eq!((out.nodes, out.depth, out.calls, out.cost), (10, 4, 0, 10));
+            program.optimize().unwrap();
+            assert!(program_metrics(&program, &mut out));
+            assert_eq!(out.nodes, 6);
+
+            program.compile("items.exists(i, i.price > limit)").unwrap();
+            assert!(program_metrics(&program, &mut out));
+            assert_eq!(out.comprehensions, 1);
+            assert!(out.cost > 100);
+
+            assert!(!program_metrics(&program, std::ptr::null_mut()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
+    }
+
     #[test]
     fn test_program_compile_error() {
         let mut program = Program::new();
@@ -673,6 +2186,21 @@ mod tests {
         assert_eq!(result_str, error_msg);
     }
 
+    #[test]
+    fn test_copy_error_to_zero_length_buffer() {
+        let mut buffer = [0xffu8; 4];
+        let mut buffer_len = 0;
+
+        copy_error_to_buffer("Test error", buffer.as_mut_ptr(), &mut buffer_len);
+
+        assert_eq!(buffer_len, 0);
+        assert_eq!(buffer, [0xff; 4]);
+
+        let mut len = 0;
+        unsafe { crate::cel_last_error_message(std::ptr::null_mut(), &mut len) };
+        assert_eq!(len, "Test error".len());
+    }
+
     #[test]
     fn test_cel_value_to_c_value_null() {
         let cel_value = CelRustValue::Null;
@@ -1173,4 +2701,310 @@ mod tests {
         assert!(new_variables.contains(&"y".to_string()));
         assert!(new_variables.contains(&"z".to_string()));
     }
+
+    #[test]
+    fn test_program_execute_json() {
+        let mut program = Program::new();
+        let mut context = Context::new();
+        context.add_variable("name".to_string(), serde_json::json!("cel"));
+        program.compile("{'name': name, 'tags': [1, 2.5, b'hi', null]}").unwrap();
+
+        let mut out = CelStringValue {
+            ptr: std::ptr::null(),
+            len: 0,
+        };
+        let mut error_buf = [0u8; 256];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            assert!(program_execute_json(
+                &program,
+                &context,
+                &mut out,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            let json = std::str::from_utf8(std::slice::from_raw_parts(out.ptr, out.len)).unwrap();
+            assert_eq!(json, r#"{"name":"cel","tags":[1,2.5,"aGk=",null]}"#);
+            assert!(super::super::cel_string_release(out.ptr));
+
+            program.compile("missing + 1").unwrap();
+            assert!(!program_execute_json(
+                &program,
+                &context,
+                &mut out,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::ExecutionError);
+
+            error_len = error_buf.len();
+            assert!(!program_execute_json(
+                &program,
+                &context,
+                std::ptr::null_mut(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
+    }
+
+    #[test]
+    fn test_program_execute_bool() {
+        let mut program = Program::new();
+        let mut context = Context::new();
+        context.add_variable("roles".to_string(), serde_json::json!([]));
+        program.compile("roles").unwrap();
+
+        let mut answer = true;
+        let mut error_buf = [0u8; 256];
+        let mut error_len = error_buf.len();
+        let mut execute = |program: &Program, truthiness: CelTruthiness, answer: &mut bool| {
+            error_len = error_buf.len();
+            unsafe {
+                program_execute_bool(
+                    program,
+                    &context,
+                    answer,
+                    truthiness,
+                    error_buf.as_mut_ptr(),
+                    &mut error_len,
+                )
+            }
+        };
+
+        assert!(execute(&program, CelTruthiness::TruthinessLenient, &mut answer));
+        assert!(!answer);
+        assert!(!execute(&program, CelTruthiness::TruthinessStrict, &mut answer));
+        assert_eq!(crate::cel_last_error(), CelErrorCode::ResultType);
+
+        program.compile("size(roles) == 0").unwrap();
+        assert!(execute(&program, CelTruthiness::TruthinessStrict, &mut answer));
+        assert!(answer);
+
+        program.compile("roles[0]").unwrap();
+        assert!(!execute(&program, CelTruthiness::TruthinessLenient, &mut answer));
+        assert_eq!(crate::cel_last_error(), CelErrorCode::ExecutionError);
+
+        let out = std::ptr::null_mut();
+        assert!(!unsafe {
+            program_execute_bool(
+                &program,
+                &context,
+                out,
+                CelTruthiness::TruthinessStrict,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            )
+        });
+        assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+    }
+
+    fn eval_json(expression: &str, variables: Option<&str>) -> Result<String, CelErrorCode> {
+        let expression = std::ffi::CString::new(expression).unwrap();
+        let variables = variables.map(|v| std::ffi::CString::new(v).unwrap());
+        let mut out = CelStringValue {
+            ptr: std::ptr::null(),
+            len: 0,
+        };
+        let mut error_buf = [0u8; 256];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            if !cel_eval_json(
+                expression.as_ptr(),
+                variables.as_ref().map_or(std::ptr::null(), |v| v.as_ptr()),
+                &mut out,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ) {
+                return Err(crate::cel_last_error());
+            }
+            let json = std::slice::from_raw_parts(out.ptr, out.len).to_vec();
+            assert!(super::super::cel_string_release(out.ptr));
+            Ok(String::from_utf8(json).unwrap())
+        }
+    }
+
+    #[test]
+    fn test_cel_eval_json() {
+        assert_eq!(
+            eval_json(
+                "user.age >= 18 ? [user.name] : []",
+                Some(r#"{"user": {"name": "ada", "age": 36}}"#)
+            ),
+            Ok(r#"["ada"]"#.to_string())
+        );
+        assert_eq!(eval_json("1 + 2", None), Ok("3".to_string()));
+
+        assert_eq!(eval_json("1 + + 2", None), Err(CelErrorCode::CompileError));
+        assert_eq!(eval_json("x", Some("[1]")), Err(CelErrorCode::InvalidArgument));
+        assert_eq!(eval_json("x", Some("{")), Err(CelErrorCode::InvalidArgument));
+        assert_eq!(eval_json("x + 1", Some("{}")), Err(CelErrorCode::ExecutionError));
+    }
+
+    #[test]
+    fn test_program_compile_error_details() {
+        let mut program = Program::new();
+        let mut details = CelCompileError {
+            line: 0,
+            column: 0,
+            offset: 0,
+            length: 0,
+            message: CelStringValue {
+                ptr: std::ptr::null(),
+                len: 0,
+            },
+            token: CelStringValue {
+                ptr: std::ptr::null(),
+                len: 0,
+            },
+            source: CelStringValue {
+                ptr: std::ptr::null(),
+                len: 0,
+            },
+        };
+        let mut rendered = CelStringValue {
+            ptr: std::ptr::null(),
+            len: 0,
+        };
+
+        unsafe {
+            assert!(!program_compile_error(&program, &mut details));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+
+            assert!(program.compile("1 + + 2").is_err());
+            assert!(program_compile_error(&program, &mut details));
+            assert_eq!(
+                (details.line, details.column, details.offset, details.length),
+                (1, 5, 4, 1)
+            );
+            assert_eq!(raw_bytes(details.token.ptr, details.token.len), b"+");
+
+            assert!(cel_error_render(&details, &mut rendered));
+            assert_eq!(
+                raw_bytes(rendered.ptr, rendered.len),
+                b"ERROR: <input>:1:5: unrecognized token: '+'\n | 1 + + 2\n | ....^"
+            );
+            assert!(super::super::cel_string_release(rendered.ptr));
+
+            assert!(program.compile("1 + 2").is_ok());
+            assert!(!program_compile_error(&program, &mut details));
+            assert!(!cel_error_render(std::ptr::null(), &mut rendered));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
+    }
+
+    #[test]
+    fn test_program_compile_with_options() {
+        let mut program = Program::new();
+        let expression = std::ffi::CString::new("[1].all(x, [2].exists(y, has(y.z)))").unwrap();
+        let has = std::ffi::CString::new("has").unwrap();
+        let unknown = std::ffi::CString::new("sort").unwrap();
+        let mut names = [has.as_ptr()];
+        let mut options = CelCompileOptions {
+            max_expression_length: 0,
+            max_nesting_depth: 0,
+            max_comprehensions: 0,
+            disabled_macros: std::ptr::null(),
+            disabled_macros_len: 0,
+            disable_regex: false,
+            max_regex_length: 0,
+            max_regex_size: 0,
+            string_ext: false,
+            math_ext: false,
+            list_ext: false,
+            optional_ext: false,
+            unicode_ext: false,
+            encoders_ext: false,
+            json_ext: false,
+        };
+        let mut error_buf = [0u8; 256];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            assert!(program_compile_with_options(
+                &mut program,
+                expression.as_ptr(),
+                &options,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+
+            options.disabled_macros = names.as_ptr();
+            options.disabled_macros_len = names.len();
+            assert!(!program_compile_with_options(
+                &mut program,
+                expression.as_ptr(),
+                &options,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::CompileError);
+            let error = std::str::from_utf8(error_buf.get(..error_len).unwrap()).unwrap();
+            assert_eq!(error, "Compilation error: macro 'has' is disabled");
+            assert_eq!(program.compile_error().unwrap().message, "macro 'has' is disabled");
+
+            names[0] = unknown.as_ptr();
+            error_len = error_buf.len();
+            assert!(!program_validate_with_options(
+                expression.as_ptr(),
+                &options,
+                std::ptr::null_mut(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+
+            options.disabled_macros_len = 0;
+            options.max_comprehensions = 1;
+            error_len = error_buf.len();
+            assert!(!program_validate_with_options(
+                expression.as_ptr(),
+                &options,
+                std::ptr::null_mut(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            let error = std::str::from_utf8(error_buf.get(..error_len).unwrap()).unwrap();
+            assert_eq!(error, "Validation error: expression uses more than 1 comprehensions");
+        }
+    }
+
+    #[test]
+    fn test_program_compile_contains_parser_panics() {
+        // cel-parser unwraps out-of-range literals and invalid escapes
+        for source in ["99999999999999999999", "'\\q'", "0xffffffffffffffffff"] {
+            let expression = std::ffi::CString::new(source).unwrap();
+            let mut program = Program::new();
+            let mut error_buf = [0u8; 64];
+            let mut error_len = error_buf.len();
+
+            unsafe {
+                assert!(!program_compile(
+                    &mut program,
+                    expression.as_ptr(),
+                    error_buf.as_mut_ptr(),
+                    &mut error_len,
+                ));
+                assert_eq!(crate::cel_last_error(), CelErrorCode::Internal, "{source}");
+                let error = std::str::from_utf8(error_buf.get(..error_len).unwrap()).unwrap();
+                assert!(error.starts_with("Internal error: "), "{error}");
+
+                error_len = error_buf.len();
+                assert!(!program_validate(
+                    expression.as_ptr(),
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    error_buf.as_mut_ptr(),
+                    &mut error_len,
+                ));
+                assert_eq!(crate::cel_last_error(), CelErrorCode::Internal, "{source}");
+            }
+
+            // The program is still usable afterwards
+            assert!(program.compile("1 + 2").is_ok());
+        }
+    }
 }
@@ -0,0 +1,298 @@
+use super::context::{raw_bytes, variable_target, writable_context};
+use super::error::{contain_with_error, report};
+use super::program::copy_error_to_buffer;
+use super::{CelErrorCode, Context};
+use crate::api::Error;
+use std::ffi::{c_char, CStr};
+
+/// Register the message types in a serialized `google.protobuf.FileDescriptorSet`
+///
+/// Types are registered with the context, so `context_add_variable_proto` can decode
+/// messages of any type in the set or in sets added before it. Returns false with
+/// `cel_last_error` set to `InvalidArgument` if the set cannot be decoded or refers
+/// to unknown types, or to `NullArgument` if `context` or `errbuf_len` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `ptr` is either null or points to at least `len` readable bytes
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn context_add_proto_descriptors(
+    context: *mut Context,
+    ptr: *const u8,
+    len: usize,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let context = match writable_context(context) {
+            Ok(context) => context,
+            Err((code, e)) => {
+                copy_error_to_buffer(&e, errbuf, errbuf_len);
+                return report(code);
+            }
+        };
+        if ptr.is_null() && len > 0 {
+            copy_error_to_buffer("Descriptor set pointer is null", errbuf, errbuf_len);
+            return report(CelErrorCode::NullArgument);
+        }
+
+        match context.add_proto_descriptors(raw_bytes(ptr, len)) {
+            Ok(()) => report(CelErrorCode::Ok),
+            Err(e) => {
+                copy_error_to_buffer(&e.to_string(), errbuf, errbuf_len);
+                report(CelErrorCode::InvalidArgument)
+            }
+        }
+    })
+}
+
+/// Add a variable holding a serialized protobuf message
+///
+/// `type_name` is the fully qualified message name, such as `acme.Request`, and must
+/// have been registered with `context_add_proto_descriptors`. The message is exposed
+/// as described for `api::message_to_cel_value`, and the enum values it can reference are
+/// bound at their fully qualified names unless that would replace another variable.
+/// Returns false with `cel_last_error` set to `InvalidArgument` if the type is
+/// unknown or the message cannot be decoded, or to `NullArgument` if `context`,
+/// `name`, `type_name` or `errbuf_len` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `name` and `type_name` are either null or valid null-terminated C strings
+/// - `ptr` is either null or points to at least `len` readable bytes
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn context_add_variable_proto(
+    context: *mut Context,
+    name: *const c_char,
+    ptr: *const u8,
+    len: usize,
+    type_name: *const c_char,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let (context, name_str) = match variable_target(context, name) {
+            Ok(target) => target,
+            Err((code, e)) => {
+                copy_error_to_buffer(&e, errbuf, errbuf_len);
+                return report(code);
+            }
+        };
+        if type_name.is_null() {
+            copy_error_to_buffer("Message type name is null", errbuf, errbuf_len);
+            return report(CelErrorCode::NullArgument);
+        }
+        if ptr.is_null() && len > 0 {
+            copy_error_to_buffer("Message pointer is null", errbuf, errbuf_len);
+            return report(CelErrorCode::NullArgument);
+        }
+
+        let result = CStr::from_ptr(type_name)
+            .to_str()
+            .map_err(|e| Error::InvalidArgument(format!("Invalid message type name: {e}")))
+            .and_then(|type_name| {
+                context.add_proto_variable(name_str, raw_bytes(ptr, len), type_name)
+            });
+        match result {
+            Ok(()) => report(CelErrorCode::Ok),
+            Err(e) => {
+                copy_error_to_buffer(&e.to_string(), errbuf, errbuf_len);
+                report(CelErrorCode::InvalidArgument)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{enum_constants, Value as CelRustValue};
+    use prost::Message;
+    use prost_reflect::{DescriptorPool, DynamicMessage, Value as ProtoValue};
+    use prost_types::field_descriptor_proto::{Label, Type};
+    use prost_types::{
+        DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+        FileDescriptorProto, FileDescriptorSet,
+    };
+    use std::ffi::CString;
+
+    fn field(name: &str, number: i32, kind: Type, type_name: Option<&str>) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            label: Some(Label::Optional as i32),
+            r#type: Some(kind as i32),
+            type_name: type_name.map(str::to_string),
+            ..FieldDescriptorProto::default()
+        }
+    }
+
+    /// `acme.Request { uint64 id; bytes payload; Status status; repeated string labels;
+    /// Request parent; }` with `enum Status { STATUS_UNKNOWN = 0; ACTIVE = 1; }`
+    fn descriptor_set() -> Vec<u8> {
+        let mut labels = field("labels", 4, Type::String, None);
+        labels.label = Some(Label::Repeated as i32);
+
+        let file = FileDescriptorProto {
+            name: Some("acme.proto".to_string()),
+            package: Some("acme".to_string()),
+            syntax: Some("proto3".to_string()),
+            message_type: vec![DescriptorProto {
+                name: Some("Request".to_string()),
+                field: vec![
+                    field("id", 1, Type::Uint64, None),
+                    field("payload", 2, Type::Bytes, None),
+                    field("status", 3, Type::Enum, Some(".acme.Status")),
+                    labels,
+                    field("parent", 5, Type::Message, Some(".acme.Request")),
+                ],
+                ..DescriptorProto::default()
+            }],
+            enum_type: vec![EnumDescriptorProto {
+                name: Some("Status".to_string()),
+                value: ["STATUS_UNKNOWN", "ACTIVE"]
+                    .iter()
+                    .zip(0..)
+                    .map(|(name, number)| EnumValueDescriptorProto {
+                        name: Some((*name).to_string()),
+                        number: Some(number),
+                        ..EnumValueDescriptorProto::default()
+                    })
+                    .collect(),
+                ..EnumDescriptorProto::default()
+            }],
+            ..FileDescriptorProto::default()
+        };
+
+        FileDescriptorSet { file: vec![file] }.encode_to_vec()
+    }
+
+    fn request_message() -> Vec<u8> {
+        let pool = DescriptorPool::decode(descriptor_set().as_slice()).unwrap();
+        let descriptor = pool.get_message_by_name("acme.Request").unwrap();
+        let mut message = DynamicMessage::new(descriptor);
+        message.set_field_by_name("id", ProtoValue::U64(7));
+        message.set_field_by_name("payload", ProtoValue::Bytes(vec![0xff, 0].into()));
+        message.set_field_by_name("status", ProtoValue::EnumNumber(1));
+        message.set_field_by_name(
+            "labels",
+            ProtoValue::List(vec![ProtoValue::String("beta".to_string())]),
+        );
+        message.encode_to_vec()
+    }
+
+    #[test]
+    fn test_proto_variable_field_access() {
+        let mut context = Context::new();
+        let set = descriptor_set();
+        let message = request_message();
+        let name = CString::new("req").unwrap();
+        let type_name = CString::new("acme.Request").unwrap();
+        let mut error_buf = [0u8; 256];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            assert!(context_add_proto_descriptors(
+                &mut context,
+                set.as_ptr(),
+                set.len(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert!(context_add_variable_proto(
+                &mut context,
+                name.as_ptr(),
+                message.as_ptr(),
+                message.len(),
+                type_name.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+        }
+
+        let mut program = crate::Program::new();
+        program
+            .compile(
+                "req.id == 7u && req.payload == b'\\xff\\x00' && req.status == acme.Status.ACTIVE \
+                 && req.labels == ['beta'] && has(req.id) && !has(req.parent)",
+            )
+            .unwrap();
+        assert_eq!(program.execute(&context).unwrap(), CelRustValue::Bool(true));
+    }
+
+    #[test]
+    fn test_proto_variable_errors() {
+        let mut context = Context::new();
+        let message = request_message();
+        let name = CString::new("req").unwrap();
+        let type_name = CString::new("acme.Request").unwrap();
+        let mut error_buf = [0u8; 256];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            assert!(!context_add_variable_proto(
+                &mut context,
+                name.as_ptr(),
+                message.as_ptr(),
+                message.len(),
+                type_name.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+            let error = std::str::from_utf8(error_buf.get(..error_len).unwrap()).unwrap();
+            assert_eq!(error, "Unknown message type 'acme.Request'");
+
+            let garbage = [0xffu8; 4];
+            error_len = error_buf.len();
+            assert!(!context_add_proto_descriptors(
+                &mut context,
+                garbage.as_ptr(),
+                garbage.len(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+
+            error_len = error_buf.len();
+            assert!(!context_add_variable_proto(
+                &mut context,
+                name.as_ptr(),
+                message.as_ptr(),
+                message.len(),
+                std::ptr::null(),
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
+
+        assert_eq!(context.variable_count(), 0);
+    }
+
+    #[test]
+    fn test_enum_constants() {
+        let pool = DescriptorPool::decode(descriptor_set().as_slice()).unwrap();
+        let descriptor = pool.get_message_by_name("acme.Request").unwrap();
+
+        assert_eq!(
+            enum_constants(&descriptor),
+            [
+                ("acme.Status.ACTIVE".to_string(), 1),
+                ("acme.Status.STATUS_UNKNOWN".to_string(), 0)
+            ]
+        );
+    }
+}
@@ -0,0 +1,674 @@
+use super::error::{contain, execution_code, report};
+use super::memory::{track, untrack, CelAllocationKind};
+use super::value::{tagged_text, time_c_value};
+use super::{
+    free_library_string, store_library_string, store_owned_bytes, CelBytesValue, CelErrorCode,
+    CelListValue, CelMapValue, CelOptionalValue, CelStringValue, CelValue, CelValueData,
+    CelValueType, Context, Program,
+};
+use crate::api::{self, optional_parts, sorted_entries};
+use bumpalo::Bump;
+use cel_interpreter::objects::Key;
+use cel_interpreter::Value as CelRustValue;
+use std::mem::{size_of, ManuallyDrop};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Evaluation result that owns every allocation reachable from its value
+///
+/// Strings, bytes, lists and maps nested in the value are library-owned and stay
+/// valid until `cel_result_free` is called, so bindings have a single free point
+/// instead of per-field memory bookkeeping.
+pub struct CelEvalResult {
+    value: CelValue,
+    code: CelErrorCode,
+    error: Option<CelStringValue>,
+    strings: Vec<*const u8>,
+    arrays: Vec<Box<[CelValue]>>,
+    /// The arena strings and arrays go to instead, only set while converting into it
+    bump: *const Bump,
+    /// The arena holding the value's strings and arrays, kept alive with the result
+    arena: Option<Arc<Mutex<Bump>>>,
+}
+
+impl CelEvalResult {
+    const fn new() -> Self {
+        Self {
+            value: CelValue {
+                value_type: CelValueType::Null,
+                data: CelValueData { int_val: 0 },
+            },
+            code: CelErrorCode::Ok,
+            error: None,
+            strings: Vec::new(),
+            arrays: Vec::new(),
+            bump: std::ptr::null(),
+            arena: None,
+        }
+    }
+
+    /// Build a result from the outcome of `Program::execute`
+    #[must_use]
+    pub fn from_execution(outcome: Result<CelRustValue, String>) -> Self {
+        let mut result = Self::new();
+
+        match outcome.and_then(|value| result.convert(&value)) {
+            Ok(value) => result.value = value,
+            Err(e) => result.set_error(CelErrorCode::ExecutionError, &e),
+        }
+
+        result
+    }
+
+    /// Like `from_execution`, giving a cancelled evaluation the `Cancelled` code, one
+    /// that reached missing keys under `MissingKeys::Unknown` the `Unknown` code, one
+    /// that passed its memory or iteration limit the `MemoryLimit` or `IterationLimit`
+    /// code, one that called an unregistered late-bound function the
+    /// `UnresolvedFunction` code and one that returned an unexpected type the
+    /// `ResultType` code
+    pub(crate) fn from_cancellable(outcome: Result<CelRustValue, api::Error>) -> Self {
+        match outcome {
+            Err(
+                e @ (api::Error::Cancelled
+                | api::Error::Unknown(_)
+                | api::Error::MemoryLimit { .. }
+                | api::Error::IterationLimit { .. }
+                | api::Error::UnresolvedFunction(_)
+                | api::Error::ResultType { .. }),
+            ) => Self::from_error(execution_code(&e), &e.to_string()),
+            outcome => Self::from_execution(outcome.map_err(|e| e.to_string())),
+        }
+    }
+
+    /// Like `from_cancellable`, building the value's strings and arrays in `arena`
+    ///
+    /// The arena starts over first if no other result holds it, since then nothing
+    /// points into it.
+    pub(crate) fn from_cancellable_in(
+        outcome: Result<CelRustValue, api::Error>,
+        arena: &Arc<Mutex<Bump>>,
+    ) -> Self {
+        let value = match outcome {
+            Ok(value) => value,
+            Err(e) => return Self::from_cancellable(Err(e)),
+        };
+
+        let mut bump = arena.lock().unwrap_or_else(PoisonError::into_inner);
+        if Arc::strong_count(arena) == 1 {
+            bump.reset();
+        }
+        // Taken while locked, so no other execution can reset the arena under it
+        let mut result = Self::new();
+        result.arena = Some(Arc::clone(arena));
+        result.bump = std::ptr::from_ref(&*bump);
+        let converted = result.convert(&value);
+        result.bump = std::ptr::null();
+        drop(bump);
+        match converted {
+            Ok(value) => result.value = value,
+            Err(e) => result.set_error(CelErrorCode::ExecutionError, &e),
+        }
+        result
+    }
+
+    pub(crate) fn from_error(code: CelErrorCode, message: &str) -> Self {
+        let mut result = Self::new();
+        result.set_error(code, message);
+        result
+    }
+
+    /// Move the result to the heap for the caller, who frees it with `cel_result_free`
+    pub(crate) fn into_raw(self) -> *mut Self {
+        let result = Box::into_raw(Box::new(self));
+        track(CelAllocationKind::AllocatedResult, result, size_of::<Self>());
+        result
+    }
+
+    pub(crate) const fn code(&self) -> CelErrorCode {
+        self.code
+    }
+
+    fn set_error(&mut self, code: CelErrorCode, message: &str) {
+        let error = self.store_string(message.as_bytes());
+        self.code = code;
+        self.error = Some(error);
+    }
+
+    /// The converted value, or `None` if evaluation failed
+    #[must_use]
+    pub const fn value(&self) -> Option<&CelValue> {
+        if self.error.is_some() {
+            None
+        } else {
+            Some(&self.value)
+        }
+    }
+
+    /// The error message, or `None` if evaluation succeeded
+    #[must_use]
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_ref().map(|error| unsafe {
+            std::str::from_utf8_unchecked(std::slice::from_raw_parts(error.ptr, error.len))
+        })
+    }
+
+    fn store_string(&mut self, bytes: &[u8]) -> CelStringValue {
+        // SAFETY: `bump` is only set while its arena is locked for this conversion
+        if let Some(bump) = unsafe { self.bump.as_ref() } {
+            // NUL-terminated, as library strings are
+            let stored =
+                bump.alloc_slice_fill_with(bytes.len() + 1, |i| bytes.get(i).copied().unwrap_or(0));
+            return CelStringValue {
+                ptr: stored.as_ptr(),
+                len: bytes.len(),
+            };
+        }
+
+        let ptr = store_library_string(bytes);
+        self.strings.push(ptr);
+        CelStringValue {
+            ptr,
+            len: bytes.len(),
+        }
+    }
+
+    fn store_array(&mut self, items: Vec<CelValue>) -> (*const CelValue, usize) {
+        // SAFETY: as in `store_string`
+        if let Some(bump) = unsafe { self.bump.as_ref() } {
+            let len = items.len();
+            return (bump.alloc_slice_fill_iter(items).as_ptr(), len);
+        }
+
+        let items = items.into_boxed_slice();
+        let ptr = items.as_ptr();
+        let len = items.len();
+        self.arrays.push(items);
+        (ptr, len)
+    }
+
+    fn convert(&mut self, value: &CelRustValue) -> Result<CelValue, String> {
+        if let Some(inner) = optional_parts(value) {
+            let items =
+                inner.map(|inner| self.convert(inner)).into_iter().collect::<Result<_, _>>()?;
+            let (items, len) = self.store_array(items);
+            return Ok(CelValue {
+                value_type: CelValueType::Optional,
+                data: CelValueData {
+                    optional_val: ManuallyDrop::new(CelOptionalValue {
+                        has_value: len > 0,
+                        value: if len > 0 { items } else { std::ptr::null() },
+                    }),
+                },
+            });
+        }
+        if let Some((value_type, text)) = tagged_text(value) {
+            let text = self.store_string(text.as_bytes());
+            return Ok(CelValue {
+                value_type,
+                data: CelValueData {
+                    string_val: ManuallyDrop::new(text),
+                },
+            });
+        }
+
+        let (value_type, data) = match value {
+            CelRustValue::Null => (CelValueType::Null, CelValueData { int_val: 0 }),
+            CelRustValue::Bool(b) => (CelValueType::Bool, CelValueData { bool_val: *b }),
+            CelRustValue::Int(i) => (CelValueType::Int, CelValueData { int_val: *i }),
+            CelRustValue::UInt(u) => (CelValueType::Uint, CelValueData { uint_val: *u }),
+            CelRustValue::Float(f) => (CelValueType::Double, CelValueData { double_val: *f }),
+            CelRustValue::String(s) => (
+                CelValueType::String,
+                CelValueData {
+                    string_val: ManuallyDrop::new(self.store_string(s.as_bytes())),
+                },
+            ),
+            CelRustValue::Bytes(b) => {
+                let stored = self.store_string(b);
+                (
+                    CelValueType::Bytes,
+                    CelValueData {
+                        bytes_val: ManuallyDrop::new(CelBytesValue {
+                            ptr: stored.ptr,
+                            len: stored.len,
+                        }),
+                    },
+                )
+            }
+            CelRustValue::List(items) => {
+                let items =
+                    items.iter().map(|item| self.convert(item)).collect::<Result<Vec<_>, _>>()?;
+                let (items, len) = self.store_array(items);
+                (
+                    CelValueType::List,
+                    CelValueData {
+                        list_val: ManuallyDrop::new(CelListValue { items, len }),
+                    },
+                )
+            }
+            CelRustValue::Map(map) => {
+                let mut keys = Vec::with_capacity(map.map.len());
+                let mut values = Vec::with_capacity(map.map.len());
+                for (key, value) in sorted_entries(map) {
+                    keys.push(self.convert_key(key));
+                    values.push(self.convert(value)?);
+                }
+                let (keys, len) = self.store_array(keys);
+                let (values, _) = self.store_array(values);
+                (
+                    CelValueType::Map,
+                    CelValueData {
+                        map_val: ManuallyDrop::new(CelMapValue { keys, values, len }),
+                    },
+                )
+            }
+            _ => {
+                return time_c_value(value)
+                    .ok_or_else(|| "Unsupported return value type".to_string())
+            }
+        };
+
+        Ok(CelValue { value_type, data })
+    }
+
+    fn convert_key(&mut self, key: &Key) -> CelValue {
+        let (value_type, data) = match key {
+            Key::Int(i) => (CelValueType::Int, CelValueData { int_val: *i }),
+            Key::Uint(u) => (CelValueType::Uint, CelValueData { uint_val: *u }),
+            Key::Bool(b) => (CelValueType::Bool, CelValueData { bool_val: *b }),
+            Key::String(s) => (
+                CelValueType::String,
+                CelValueData {
+                    string_val: ManuallyDrop::new(self.store_string(s.as_bytes())),
+                },
+            ),
+        };
+
+        CelValue { value_type, data }
+    }
+}
+
+impl Drop for CelEvalResult {
+    fn drop(&mut self) {
+        for ptr in self.strings.drain(..) {
+            free_library_string(ptr);
+        }
+    }
+}
+
+/// Execute the compiled expression into a newly allocated result
+///
+/// Failures, including null arguments, are reported through `cel_result_error`
+/// rather than a null return. Everything reachable from the result value stays
+/// valid until the result is released with `cel_result_free`; none of its strings
+/// may be released on their own.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `context` is either null or a valid pointer returned by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn program_execute_alloc(
+    program: *const Program,
+    context: *const Context,
+) -> *mut CelEvalResult {
+    contain(std::ptr::null_mut(), || {
+        let result = match (program.as_ref(), context.as_ref()) {
+            (Some(program), Some(context)) => {
+                CelEvalResult::from_cancellable(program.execute_cancellable(context, None))
+            }
+            (None, _) => CelEvalResult::from_error(CelErrorCode::NullArgument, "Program is null"),
+            (_, None) => CelEvalResult::from_error(CelErrorCode::NullArgument, "Context is null"),
+        };
+        report(result.code);
+        result.into_raw()
+    })
+}
+
+/// Execute the compiled expression into a newly allocated result, with a trace
+///
+/// The trace records each subexpression evaluated, and the result behaves as for
+/// `program_execute_alloc`. If `out_trace_json` is not
+/// null it receives the trace as a JSON object with the subexpression's
+/// `expression`, its `value` or `error`, `duration_ns` and the `children` evaluated
+/// to compute it, leaving out literals and the branches `&&`, `||` and `?:` skip.
+/// It receives an empty string if evaluation did not start, for example because the
+/// program is not compiled.
+///
+/// # Ownership
+/// A non-null `out_trace_json.ptr` is a caller-owned library string; drop it with
+/// `cel_string_release`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `context` is either null or a valid pointer returned by `context_new`
+/// - `out_trace_json` is either null or a valid pointer to a `CelStringValue` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn program_execute_traced(
+    program: *const Program,
+    context: *const Context,
+    out_trace_json: *mut CelStringValue,
+) -> *mut CelEvalResult {
+    contain(std::ptr::null_mut(), || {
+        let (result, trace) = match (program.as_ref(), context.as_ref()) {
+            (Some(program), Some(context)) => {
+                let (outcome, trace) = program.execute_traced(context);
+                (CelEvalResult::from_execution(outcome), trace)
+            }
+            (None, _) => {
+                (CelEvalResult::from_error(CelErrorCode::NullArgument, "Program is null"), None)
+            }
+            (_, None) => {
+                (CelEvalResult::from_error(CelErrorCode::NullArgument, "Context is null"), None)
+            }
+        };
+
+        if let Some(out_trace_json) = out_trace_json.as_mut() {
+            *out_trace_json = match trace.map(|trace| trace.to_json()) {
+                Some(json) => CelStringValue {
+                    ptr: store_owned_bytes(json.as_bytes()),
+                    len: json.len(),
+                },
+                None => CelStringValue {
+                    ptr: std::ptr::null(),
+                    len: 0,
+                },
+            };
+        }
+        report(result.code);
+        result.into_raw()
+    })
+}
+
+/// Whether the evaluation succeeded; false for a null result
+///
+/// # Safety
+/// `result` must be either null or a valid pointer returned by `program_execute_alloc`
+#[no_mangle]
+pub unsafe extern "C" fn cel_result_is_ok(result: *const CelEvalResult) -> bool {
+    contain(false, || result.as_ref().is_some_and(|result| result.error.is_none()))
+}
+
+/// Status code of the evaluation; `NullArgument` for a null result
+///
+/// # Safety
+/// `result` must be either null or a valid pointer returned by `program_execute_alloc`
+#[no_mangle]
+pub unsafe extern "C" fn cel_result_error_code(result: *const CelEvalResult) -> CelErrorCode {
+    contain(CelErrorCode::Internal, || {
+        result.as_ref().map_or(CelErrorCode::NullArgument, |result| result.code)
+    })
+}
+
+/// Pointer to the result value, or null if the evaluation failed or `result` is null
+///
+/// # Safety
+/// `result` must be either null or a valid pointer returned by `program_execute_alloc`
+#[no_mangle]
+pub unsafe extern "C" fn cel_result_value(result: *const CelEvalResult) -> *const CelValue {
+    contain(std::ptr::null(), || match result.as_ref() {
+        Some(result) if result.error.is_none() => &raw const result.value,
+        _ => std::ptr::null(),
+    })
+}
+
+/// Pointer to the error message, or null if the evaluation succeeded or `result` is null
+///
+/// # Safety
+/// `result` must be either null or a valid pointer returned by `program_execute_alloc`
+#[no_mangle]
+pub unsafe extern "C" fn cel_result_error(result: *const CelEvalResult) -> *const CelStringValue {
+    contain(std::ptr::null(), || {
+        match result.as_ref().and_then(|result| result.error.as_ref()) {
+            Some(error) => std::ptr::from_ref(error),
+            None => std::ptr::null(),
+        }
+    })
+}
+
+/// Free an evaluation result together with everything it owns
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `result` is either null or a valid pointer returned by `program_execute_alloc`
+/// - `result` has not been previously freed
+/// - No pointers into the result are used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn cel_result_free(result: *mut CelEvalResult) {
+    contain((), || {
+        if !result.is_null() {
+            untrack(CelAllocationKind::AllocatedResult, result);
+            drop(Box::from_raw(result));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cel_string_is_owned;
+
+    fn evaluate(expression: &str) -> CelEvalResult {
+        let mut program = Program::new();
+        program.compile(expression).unwrap();
+        CelEvalResult::from_execution(program.execute(&Context::new()))
+    }
+
+    unsafe fn as_str(value: &CelValue) -> &str {
+        let string_val = &value.data.string_val;
+        std::str::from_utf8(std::slice::from_raw_parts(string_val.ptr, string_val.len)).unwrap()
+    }
+
+    #[test]
+    fn test_result_scalar() {
+        let result = evaluate("40 + 2");
+        let value = result.value().unwrap();
+
+        assert_eq!(value.value_type, CelValueType::Int);
+        assert_eq!(unsafe { value.data.int_val }, 42);
+        assert!(result.error().is_none());
+    }
+
+    #[test]
+    fn test_result_string_is_library_owned() {
+        let result = evaluate("'hello' + ' ' + 'world'");
+        let value = result.value().unwrap();
+
+        assert_eq!(value.value_type, CelValueType::String);
+        unsafe {
+            assert_eq!(as_str(value), "hello world");
+            assert!(!cel_string_is_owned(value.data.string_val.ptr));
+        }
+    }
+
+    #[test]
+    #[allow(clippy::indexing_slicing)]
+    fn test_result_list() {
+        let result = evaluate("[1, 'two', [3]]");
+        let value = result.value().unwrap();
+        assert_eq!(value.value_type, CelValueType::List);
+
+        unsafe {
+            let list = &value.data.list_val;
+            assert_eq!(list.len, 3);
+            let items = std::slice::from_raw_parts(list.items, list.len);
+
+            assert_eq!(items[0].value_type, CelValueType::Int);
+            assert_eq!(items[0].data.int_val, 1);
+            assert_eq!(as_str(&items[1]), "two");
+
+            let nested = &items[2].data.list_val;
+            assert_eq!(items[2].value_type, CelValueType::List);
+            assert_eq!(nested.len, 1);
+            assert_eq!((*nested.items).data.int_val, 3);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::indexing_slicing)]
+    fn test_result_map() {
+        let result = evaluate("{'name': 'cel', 'size': 3}");
+        let value = result.value().unwrap();
+        assert_eq!(value.value_type, CelValueType::Map);
+
+        unsafe {
+            let map = &value.data.map_val;
+            assert_eq!(map.len, 2);
+            let keys = std::slice::from_raw_parts(map.keys, map.len);
+            let values = std::slice::from_raw_parts(map.values, map.len);
+
+            for (key, value) in keys.iter().zip(values) {
+                match as_str(key) {
+                    "name" => assert_eq!(as_str(value), "cel"),
+                    "size" => assert_eq!(value.data.int_val, 3),
+                    other => panic!("unexpected key {other}"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_result_bytes() {
+        let result = evaluate("b'\\x00\\xff'");
+        let value = result.value().unwrap();
+        assert_eq!(value.value_type, CelValueType::Bytes);
+
+        unsafe {
+            let bytes = &value.data.bytes_val;
+            assert_eq!(std::slice::from_raw_parts(bytes.ptr, bytes.len), &[0x00, 0xff]);
+        }
+    }
+
+    #[test]
+    fn test_result_time_values() {
+        let result = evaluate("[timestamp('2024-01-01T00:00:00Z'), duration('1.5s')]");
+        let value = result.value().unwrap();
+
+        unsafe {
+            let items = std::slice::from_raw_parts(value.data.list_val.items, 2);
+            assert_eq!(items[0].value_type, CelValueType::Timestamp);
+            assert_eq!(items[0].data.timestamp_val.seconds, 1_704_067_200);
+            assert_eq!(items[1].value_type, CelValueType::Duration);
+            assert_eq!(items[1].data.duration_val.nanos, 500_000_000);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::indexing_slicing)]
+    fn test_result_optional() {
+        let options = crate::api::CompileOptions {
+            optional_ext: true,
+            ..crate::api::CompileOptions::default()
+        };
+        let mut program = Program::new();
+        program
+            .compile_with_options("[optional.of('x'), optional.none()]", &options)
+            .unwrap();
+        let result = CelEvalResult::from_execution(program.execute(&Context::new()));
+        let value = result.value().unwrap();
+
+        unsafe {
+            let items = std::slice::from_raw_parts(value.data.list_val.items, 2);
+            let (present, empty) = (&items[0], &items[1]);
+            assert_eq!(present.value_type, CelValueType::Optional);
+            assert!(present.data.optional_val.has_value);
+            assert_eq!(as_str(&*present.data.optional_val.value), "x");
+
+            assert_eq!(empty.value_type, CelValueType::Optional);
+            assert!(!empty.data.optional_val.has_value);
+            assert!(empty.data.optional_val.value.is_null());
+        }
+    }
+
+    #[test]
+    fn test_result_error() {
+        let result = evaluate("missing_variable + 1");
+
+        assert!(result.value().is_none());
+        assert!(result.error().unwrap().contains("Execution error"));
+        unsafe {
+            assert!(!cel_result_is_ok(&result));
+            assert_eq!(cel_result_error_code(&result), CelErrorCode::ExecutionError);
+            assert!(cel_result_value(&result).is_null());
+            assert!(!cel_result_error(&result).is_null());
+        }
+    }
+
+    #[test]
+    fn test_result_null_arguments() {
+        let program = Program::new();
+        let context = Context::new();
+
+        unsafe {
+            let result = program_execute_alloc(std::ptr::null(), &context);
+            assert_eq!(cel_result_error_code(result), CelErrorCode::NullArgument);
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+            assert!(cel_result_value(result).is_null());
+            cel_result_free(result);
+
+            let result = program_execute_alloc(&program, std::ptr::null());
+            assert_eq!(cel_result_error_code(result), CelErrorCode::NullArgument);
+            assert!((*result).error().unwrap().contains("Context is null"));
+            cel_result_free(result);
+
+            assert!(!cel_result_is_ok(std::ptr::null()));
+            assert_eq!(cel_result_error_code(std::ptr::null()), CelErrorCode::NullArgument);
+            assert!(cel_result_value(std::ptr::null()).is_null());
+            assert!(cel_result_error(std::ptr::null()).is_null());
+        }
+    }
+
+    #[test]
+    fn test_program_execute_alloc_lifecycle() {
+        let mut program = Program::new();
+        program.compile("[x, x * 2]").unwrap();
+        let mut context = Context::new();
+        context.add_variable("x".to_string(), serde_json::json!(21));
+
+        unsafe {
+            let result = program_execute_alloc(&program, &context);
+            assert!(!result.is_null());
+            assert!(cel_result_is_ok(result));
+            assert_eq!(cel_result_error_code(result), CelErrorCode::Ok);
+            assert!(cel_result_error(result).is_null());
+
+            let value = &*cel_result_value(result);
+            assert_eq!(value.value_type, CelValueType::List);
+            assert_eq!(value.data.list_val.len, 2);
+
+            cel_result_free(result);
+            cel_result_free(std::ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_program_execute_traced() {
+        let mut program = Program::new();
+        program.compile("x > 1 && x < 10").unwrap();
+        let mut context = Context::new();
+        context.add_variable("x".to_string(), serde_json::json!(0));
+        let mut trace = CelStringValue {
+            ptr: std::ptr::null(),
+            len: 0,
+        };
+
+        unsafe {
+            let result = program_execute_traced(&program, &context, &mut trace);
+            assert!(cel_result_is_ok(result));
+            assert!(!(*cel_result_value(result)).data.bool_val);
+            cel_result_free(result);
+
+            let json = std::slice::from_raw_parts(trace.ptr, trace.len);
+            let json: serde_json::Value = serde_json::from_slice(json).unwrap();
+            assert_eq!(json["value"], serde_json::json!(false));
+            assert_eq!(json["children"].as_array().unwrap().len(), 1);
+            assert_eq!(json["children"][0]["expression"], "x > 1");
+            crate::cel_string_release(trace.ptr);
+
+            let result = program_execute_traced(&Program::new(), &context, &mut trace);
+            assert_eq!(cel_result_error_code(result), CelErrorCode::ExecutionError);
+            assert!(trace.ptr.is_null());
+            cel_result_free(result);
+        }
+    }
+}
@@ -0,0 +1,349 @@
+//! Rule sets: named expressions compiled and evaluated as one unit
+
+use super::error::{contain, contain_with_error, report};
+use super::program::fail;
+use super::result::CelEvalResult;
+use super::{CelErrorCode, Context};
+pub use crate::api::RuleSet;
+use crate::api::Value as CelRustValue;
+use cel_interpreter::objects::{Key, Map};
+use std::collections::HashMap;
+use std::ffi::{c_char, CStr};
+use std::sync::Arc;
+
+/// Create an empty rule set, compiled with the default options
+#[no_mangle]
+pub extern "C" fn ruleset_new() -> *mut RuleSet {
+    contain(std::ptr::null_mut(), || Box::into_raw(Box::default()))
+}
+
+/// Free a rule set
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `ruleset` is either null or a valid pointer returned by `ruleset_new`
+/// - `ruleset` has not been previously freed
+#[no_mangle]
+pub unsafe extern "C" fn ruleset_free(ruleset: *mut RuleSet) {
+    contain((), || {
+        if !ruleset.is_null() {
+            drop(Box::from_raw(ruleset));
+        }
+    })
+}
+
+/// Add a rule named `name`, to be compiled by the next `ruleset_compile`
+///
+/// Returns false with `cel_last_error` set to `InvalidArgument` if the set already has
+/// a rule named `name` or either string is not valid UTF-8, or to `NullArgument` if
+/// any pointer is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `ruleset` is either null or a valid pointer returned by `ruleset_new`
+/// - `name` and `expression` are either null or valid null-terminated C strings
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn ruleset_add(
+    ruleset: *mut RuleSet,
+    name: *const c_char,
+    expression: *const c_char,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(ruleset) = ruleset.as_mut() else {
+            return fail(CelErrorCode::NullArgument, "Rule set is null", errbuf, errbuf_len);
+        };
+        if name.is_null() || expression.is_null() {
+            let error = "Rule name or expression is null";
+            return fail(CelErrorCode::NullArgument, error, errbuf, errbuf_len);
+        }
+
+        let (Ok(name), Ok(expression)) =
+            (CStr::from_ptr(name).to_str(), CStr::from_ptr(expression).to_str())
+        else {
+            let error = "Rule name and expression must be valid UTF-8";
+            return fail(CelErrorCode::InvalidArgument, error, errbuf, errbuf_len);
+        };
+        match ruleset.add(name, expression) {
+            Ok(()) => report(CelErrorCode::Ok),
+            Err(e) => fail(CelErrorCode::InvalidArgument, &e.to_string(), errbuf, errbuf_len),
+        }
+    })
+}
+
+/// Compile every rule added since the last compilation
+///
+/// Returns false with `cel_last_error` set to `CompileError` if any rule fails to
+/// compile, describing each failure on its own line as `rule 'name': error`, or to
+/// `NullArgument` if `ruleset` is null. The set cannot be executed until every rule
+/// compiles.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `ruleset` is either null or a valid pointer returned by `ruleset_new`
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn ruleset_compile(
+    ruleset: *mut RuleSet,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(ruleset) = ruleset.as_mut() else {
+            return fail(CelErrorCode::NullArgument, "Rule set is null", errbuf, errbuf_len);
+        };
+
+        match ruleset.compile() {
+            Ok(()) => report(CelErrorCode::Ok),
+            Err(failures) => {
+                let error = failures
+                    .iter()
+                    .map(|(name, e)| format!("rule '{name}': {e}"))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                fail(CelErrorCode::CompileError, &error, errbuf, errbuf_len)
+            }
+        }
+    })
+}
+
+/// Execute every rule into a newly allocated result mapping rule names to outcomes
+///
+/// Each outcome is a map with the rule's `value`, or with an `error` string if the
+/// rule failed, so one failing rule does not hide the others. The variables of
+/// `context` are converted once for all rules. The result fails with
+/// `ExecutionError` if the set is not compiled or a variable cannot be converted, and
+/// is released with `cel_result_free`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `ruleset` is either null or a valid pointer returned by `ruleset_new`
+/// - `context` is either null or a valid pointer returned by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn ruleset_execute_all(
+    ruleset: *const RuleSet,
+    context: *const Context,
+) -> *mut CelEvalResult {
+    contain(std::ptr::null_mut(), || {
+        let result = match (ruleset.as_ref(), context.as_ref()) {
+            (Some(ruleset), Some(context)) => execute_all(ruleset, context),
+            (None, _) => CelEvalResult::from_error(CelErrorCode::NullArgument, "Rule set is null"),
+            (_, None) => CelEvalResult::from_error(CelErrorCode::NullArgument, "Context is null"),
+        };
+        report(result.code());
+        result.into_raw()
+    })
+}
+
+/// Match rules in `ruleset_first_match` with a CEL predicate over the rule's `result`
+///
+/// With the predicate `result == 'deny'`, for example, the first rule returning
+/// `'deny'` matches. A null `expression` restores the default, matching rules that
+/// return `true`. Returns false with `cel_last_error` set to `CompileError` if the
+/// predicate cannot be compiled, leaving the previous one in place, to
+/// `InvalidArgument` if it is not valid UTF-8, or to `NullArgument` if `ruleset` is
+/// null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `ruleset` is either null or a valid pointer returned by `ruleset_new`
+/// - `expression` is either null or a valid null-terminated C string
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn ruleset_set_predicate(
+    ruleset: *mut RuleSet,
+    expression: *const c_char,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(ruleset) = ruleset.as_mut() else {
+            return fail(CelErrorCode::NullArgument, "Rule set is null", errbuf, errbuf_len);
+        };
+        let expression = if expression.is_null() {
+            None
+        } else if let Ok(expression) = CStr::from_ptr(expression).to_str() {
+            Some(expression)
+        } else {
+            let error = "Predicate must be valid UTF-8";
+            return fail(CelErrorCode::InvalidArgument, error, errbuf, errbuf_len);
+        };
+
+        match ruleset.set_predicate(expression) {
+            Ok(()) => report(CelErrorCode::Ok),
+            Err(e) => fail(CelErrorCode::CompileError, &e.to_string(), errbuf, errbuf_len),
+        }
+    })
+}
+
+/// Evaluate the rules in order into a result holding the name of the first that matches
+///
+/// A rule matches when it returns `true`, or when the predicate set with
+/// `ruleset_set_predicate` returns `true` for its value, and the rules after it are
+/// not evaluated. The value is null if no rule matched. The result fails with
+/// `ExecutionError` if the set is not compiled, or if a rule or the predicate fails
+/// before a match, naming the rule. It is released with `cel_result_free`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `ruleset` is either null or a valid pointer returned by `ruleset_new`
+/// - `context` is either null or a valid pointer returned by `context_new`
+#[no_mangle]
+pub unsafe extern "C" fn ruleset_first_match(
+    ruleset: *const RuleSet,
+    context: *const Context,
+) -> *mut CelEvalResult {
+    contain(std::ptr::null_mut(), || {
+        let result = match (ruleset.as_ref(), context.as_ref()) {
+            (Some(ruleset), Some(context)) => first_match(ruleset, context),
+            (None, _) => CelEvalResult::from_error(CelErrorCode::NullArgument, "Rule set is null"),
+            (_, None) => CelEvalResult::from_error(CelErrorCode::NullArgument, "Context is null"),
+        };
+        report(result.code());
+        result.into_raw()
+    })
+}
+
+/// The outcomes of `ruleset_execute_all`, as a map from rule names to `entry` maps
+pub(super) fn execute_all(ruleset: &RuleSet, context: &Context) -> CelEvalResult {
+    let outcomes = ruleset.execute_all(context).map_err(|e| e.to_string());
+    CelEvalResult::from_execution(outcomes.map(|outcomes| {
+        let entries = outcomes
+            .into_iter()
+            .map(|(name, outcome)| (Key::String(Arc::new(name.to_string())), entry(outcome)))
+            .collect();
+        CelRustValue::Map(Map {
+            map: Arc::new(entries),
+        })
+    }))
+}
+
+/// The outcome of `ruleset_first_match`: the matching rule's name, or null
+pub(super) fn first_match(ruleset: &RuleSet, context: &Context) -> CelEvalResult {
+    let matched = ruleset.first_match(context).map_err(|e| e.to_string());
+    CelEvalResult::from_execution(matched.map(|matched| match matched {
+        Some((name, _)) => CelRustValue::String(Arc::new(name.to_string())),
+        None => CelRustValue::Null,
+    }))
+}
+
+/// A rule's outcome as a map with its `value` or `error`
+pub(super) fn entry(outcome: Result<CelRustValue, crate::api::Error>) -> CelRustValue {
+    let (key, value) = match outcome {
+        Ok(value) => ("value", value),
+        Err(e) => ("error", CelRustValue::String(Arc::new(e.to_string()))),
+    };
+    let map = HashMap::from([(Key::String(Arc::new(key.to_string())), value)]);
+    CelRustValue::Map(Map { map: Arc::new(map) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cel_last_error, CelValueType};
+    use crate::{cel_result_free, cel_result_is_ok, cel_result_value, context_free, context_new};
+    use std::ffi::CString;
+
+    unsafe fn add(ruleset: *mut RuleSet, name: &str, expression: &str) -> bool {
+        let name = CString::new(name).unwrap();
+        let expression = CString::new(expression).unwrap();
+        let mut errbuf = [0u8; 256];
+        let mut errbuf_len = errbuf.len();
+        ruleset_add(
+            ruleset,
+            name.as_ptr(),
+            expression.as_ptr(),
+            errbuf.as_mut_ptr(),
+            &mut errbuf_len,
+        )
+    }
+
+    #[test]
+    fn test_ruleset_execute_all() {
+        let ruleset = ruleset_new();
+        let context = context_new();
+        let mut errbuf = [0u8; 256];
+
+        unsafe {
+            assert!(add(ruleset, "sum", "1 + 2"));
+            assert!(add(ruleset, "broken", "missing + 1"));
+            assert!(!add(ruleset, "sum", "3"));
+            assert_eq!(cel_last_error(), CelErrorCode::InvalidArgument);
+
+            let result = ruleset_execute_all(ruleset, context);
+            assert!(!cel_result_is_ok(result));
+            cel_result_free(result);
+
+            let mut errbuf_len = errbuf.len();
+            assert!(ruleset_compile(ruleset, errbuf.as_mut_ptr(), &mut errbuf_len));
+            let result = ruleset_execute_all(ruleset, context);
+            assert!(cel_result_is_ok(result));
+            let value = &*cel_result_value(result);
+            assert_eq!(value.value_type, CelValueType::Map);
+            assert_eq!(value.data.map_val.len, 2);
+            cel_result_free(result);
+
+            assert!(add(ruleset, "bad", "1 + + "));
+            let mut errbuf_len = errbuf.len();
+            assert!(!ruleset_compile(ruleset, errbuf.as_mut_ptr(), &mut errbuf_len));
+            assert_eq!(cel_last_error(), CelErrorCode::CompileError);
+            assert!(std::str::from_utf8(&errbuf[..errbuf_len]).unwrap().starts_with("rule 'bad'"));
+
+            ruleset_free(ruleset);
+            context_free(context);
+        }
+    }
+
+    #[test]
+    fn test_ruleset_first_match() {
+        let ruleset = ruleset_new();
+        let context = context_new();
+        let predicate = CString::new("result == 'deny'").unwrap();
+        let mut errbuf = [0u8; 256];
+
+        unsafe {
+            assert!(add(ruleset, "allow", "'allow'"));
+            assert!(add(ruleset, "deny", "'deny'"));
+            let mut errbuf_len = errbuf.len();
+            assert!(ruleset_compile(ruleset, errbuf.as_mut_ptr(), &mut errbuf_len));
+
+            let result = ruleset_first_match(ruleset, context);
+            assert!(cel_result_is_ok(result));
+            assert_eq!((*cel_result_value(result)).value_type, CelValueType::Null);
+            cel_result_free(result);
+
+            let mut errbuf_len = errbuf.len();
+            let set = ruleset_set_predicate(
+                ruleset,
+                predicate.as_ptr(),
+                errbuf.as_mut_ptr(),
+                &mut errbuf_len,
+            );
+            assert!(set);
+            let result = ruleset_first_match(ruleset, context);
+            let value = &*cel_result_value(result);
+            assert_eq!(value.value_type, CelValueType::String);
+            let name =
+                std::slice::from_raw_parts(value.data.string_val.ptr, value.data.string_val.len);
+            assert_eq!(name, b"deny");
+            cel_result_free(result);
+
+            ruleset_free(ruleset);
+            context_free(context);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use super::error::{contain, report};
+use super::{store_owned_bytes, CelErrorCode, CelStringValue, Program};
+
+/// Execution counters of a program, built with the `stats` feature
+///
+/// `last_error` is empty until an execution fails. Counters start from zero each
+/// time the program is compiled.
+#[repr(C)]
+#[derive(Debug)]
+pub struct CelProgramStats {
+    pub executions: u64,
+    pub errors: u64,
+    pub total_time_ns: u64,
+    pub last_error: CelStringValue,
+}
+
+/// Read the execution counters of a program
+///
+/// Returns false with `cel_last_error` set to `InvalidArgument` if the program has
+/// not been compiled, or to `NullArgument` if `program` or `out_stats` is null.
+///
+/// # Ownership
+/// A non-null `out_stats.last_error.ptr` is a caller-owned library string; drop it
+/// with `cel_string_release`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `program` is either null or a valid pointer returned by `program_new`
+/// - `out_stats` is either null or a valid pointer to a `CelProgramStats` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn program_stats(
+    program: *const Program,
+    out_stats: *mut CelProgramStats,
+) -> bool {
+    contain(false, || {
+        let (Some(program), Some(out_stats)) = (program.as_ref(), out_stats.as_mut()) else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(stats) = program.stats() else {
+            return report(CelErrorCode::InvalidArgument);
+        };
+
+        let last_error = stats.last_error.as_deref().map_or(
+            CelStringValue {
+                ptr: std::ptr::null(),
+                len: 0,
+            },
+            |error| CelStringValue {
+                ptr: store_owned_bytes(error.as_bytes()),
+                len: error.len(),
+            },
+        );
+        *out_stats = CelProgramStats {
+            executions: stats.executions,
+            errors: stats.errors,
+            total_time_ns: u64::try_from(stats.total_time.as_nanos()).unwrap_or(u64::MAX),
+            last_error,
+        };
+        report(CelErrorCode::Ok)
+    })
+}
+
+/// Set the execution counters of a program back to zero
+///
+/// Returns false with `cel_last_error` set to `InvalidArgument` if the program has
+/// not been compiled, or to `NullArgument` if `program` is null.
+///
+/// # Safety
+/// The caller must ensure that `program` is either null or a valid pointer returned
+/// by `program_new`
+#[no_mangle]
+pub unsafe extern "C" fn program_reset_stats(program: *const Program) -> bool {
+    contain(false, || {
+        let Some(program) = program.as_ref() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        if program.reset_stats() {
+            report(CelErrorCode::Ok)
+        } else {
+            report(CelErrorCode::InvalidArgument)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cel_last_error, cel_string_release, Context};
+
+    #[test]
+    fn test_program_stats() {
+        let mut program = Program::new();
+        let mut stats = CelProgramStats {
+            executions: 0,
+            errors: 0,
+            total_time_ns: 0,
+            last_error: CelStringValue {
+                ptr: std::ptr::null(),
+                len: 0,
+            },
+        };
+
+        unsafe {
+            assert!(!program_stats(&program, &mut stats));
+            assert_eq!(cel_last_error(), CelErrorCode::InvalidArgument);
+
+            program.compile("missing").unwrap();
+            assert!(program.execute(&Context::new()).is_err());
+            assert!(program_stats(&program, &mut stats));
+            assert_eq!((stats.executions, stats.errors), (1, 1));
+            let error = std::slice::from_raw_parts(stats.last_error.ptr, stats.last_error.len);
+            assert!(std::str::from_utf8(error).unwrap().contains("missing"));
+            cel_string_release(stats.last_error.ptr);
+
+            assert!(program_reset_stats(&program));
+            assert!(program_stats(&program, &mut stats));
+            assert_eq!(stats.executions, 0);
+            assert!(stats.last_error.ptr.is_null());
+
+            assert!(!program_stats(std::ptr::null(), &mut stats));
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+        }
+    }
+}
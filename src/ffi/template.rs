@@ -0,0 +1,190 @@
+//! Expression templates: `{name}` placeholders filled with typed literals
+
+use super::error::{contain, contain_with_error, report};
+use super::program::{fail, owned_library_string};
+use super::{CelErrorCode, CelStringValue};
+use crate::api;
+pub use crate::api::Template;
+use std::ffi::{c_char, CStr};
+
+/// Compile a template such as `user.age > {min_age}` for `template_instantiate`
+///
+/// Placeholders are identifiers in braces outside string literals and comments.
+/// Returns null with `cel_last_error` set to `CompileError` if the template does not
+/// parse or a placeholder stands for something other than a value, such as a field
+/// name, to `InvalidArgument` if it is not valid UTF-8, or to `NullArgument` if
+/// `source` or `errbuf_len` is null.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `source` is either null or a valid null-terminated C string
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn cel_template_compile(
+    source: *const c_char,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> *mut Template {
+    contain_with_error(std::ptr::null_mut(), errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            report(CelErrorCode::NullArgument);
+            return std::ptr::null_mut();
+        };
+        if source.is_null() {
+            fail(CelErrorCode::NullArgument, "Template is null", errbuf, errbuf_len);
+            return std::ptr::null_mut();
+        }
+        let Ok(source) = CStr::from_ptr(source).to_str() else {
+            let error = "Template must be valid UTF-8";
+            fail(CelErrorCode::InvalidArgument, error, errbuf, errbuf_len);
+            return std::ptr::null_mut();
+        };
+
+        match Template::compile(source) {
+            Ok(template) => {
+                report(CelErrorCode::Ok);
+                Box::into_raw(Box::new(template))
+            }
+            Err(e) => {
+                fail(CelErrorCode::CompileError, &e.to_string(), errbuf, errbuf_len);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Free a template
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `template` is either null or a valid pointer returned by `cel_template_compile`
+/// - `template` has not been previously freed
+#[no_mangle]
+pub unsafe extern "C" fn cel_template_free(template: *mut Template) {
+    contain((), || {
+        if !template.is_null() {
+            drop(Box::from_raw(template));
+        }
+    })
+}
+
+/// Fill the template's placeholders from a JSON object of parameters
+///
+/// Each parameter is written as a CEL literal of its JSON type: strings are quoted and
+/// escaped, so a value can never change the structure of the expression. The result
+/// is parsed before it is returned. Returns false with `cel_last_error` set to
+/// `InvalidArgument` if `params_json` is not a JSON object, lacks a parameter or has
+/// one the template does not use, to `CompileError` if the result does not parse, or
+/// to `NullArgument` if an argument is null.
+///
+/// # Ownership
+/// `out_expression.ptr` is a caller-owned library string; drop it with
+/// `cel_string_release`.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `template` is either null or a valid pointer returned by `cel_template_compile`
+/// - `params_json` is either null or a valid null-terminated C string
+/// - `out_expression` is either null or a valid pointer to a `CelStringValue` that can be
+///   written to
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn template_instantiate(
+    template: *const Template,
+    params_json: *const c_char,
+    out_expression: *mut CelStringValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        let Some(errbuf_len) = errbuf_len.as_mut() else {
+            return report(CelErrorCode::NullArgument);
+        };
+        let Some(template) = template.as_ref() else {
+            return fail(CelErrorCode::NullArgument, "Template is null", errbuf, errbuf_len);
+        };
+        if params_json.is_null() {
+            return fail(CelErrorCode::NullArgument, "Parameters are null", errbuf, errbuf_len);
+        }
+        let Some(out_expression) = out_expression.as_mut() else {
+            return fail(CelErrorCode::NullArgument, "Output pointer is null", errbuf, errbuf_len);
+        };
+        let Ok(params_json) = CStr::from_ptr(params_json).to_str() else {
+            let error = "Parameters must be valid UTF-8";
+            return fail(CelErrorCode::InvalidArgument, error, errbuf, errbuf_len);
+        };
+
+        match template.instantiate_json(params_json) {
+            Ok(expression) => {
+                *out_expression = owned_library_string(&expression);
+                report(CelErrorCode::Ok)
+            }
+            Err(api::Error::Compile(e)) => {
+                fail(CelErrorCode::CompileError, &e.to_string(), errbuf, errbuf_len)
+            }
+            Err(e) => fail(CelErrorCode::InvalidArgument, &e.to_string(), errbuf, errbuf_len),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cel_last_error;
+    use std::ffi::CString;
+
+    #[test]
+    fn test_template_instantiate() {
+        let source = CString::new("user.age > {min_age} && user.name != {name}").unwrap();
+        let params = CString::new(r#"{"min_age": 21, "name": "x' || true || '"}"#).unwrap();
+        let missing = CString::new(r#"{"min_age": 21}"#).unwrap();
+        let mut out = CelStringValue {
+            ptr: std::ptr::null(),
+            len: 0,
+        };
+        let mut errbuf = [0u8; 256];
+        let mut errbuf_len = errbuf.len();
+
+        unsafe {
+            let template =
+                cel_template_compile(source.as_ptr(), errbuf.as_mut_ptr(), &mut errbuf_len);
+            assert!(!template.is_null());
+
+            assert!(template_instantiate(
+                template,
+                params.as_ptr(),
+                &mut out,
+                errbuf.as_mut_ptr(),
+                &mut errbuf_len,
+            ));
+            let text = std::slice::from_raw_parts(out.ptr, out.len);
+            assert_eq!(
+                std::str::from_utf8(text).unwrap(),
+                "user.age > 21 && user.name != \"x' || true || '\""
+            );
+            crate::cel_string_release(out.ptr);
+
+            errbuf_len = errbuf.len();
+            assert!(!template_instantiate(
+                template,
+                missing.as_ptr(),
+                &mut out,
+                errbuf.as_mut_ptr(),
+                &mut errbuf_len,
+            ));
+            assert_eq!(cel_last_error(), CelErrorCode::InvalidArgument);
+            let error = std::str::from_utf8(&errbuf[..errbuf_len]).unwrap();
+            assert_eq!(error, "missing template parameter 'name'");
+            cel_template_free(template);
+
+            let invalid = CString::new("a.{b}").unwrap();
+            errbuf_len = errbuf.len();
+            let template =
+                cel_template_compile(invalid.as_ptr(), errbuf.as_mut_ptr(), &mut errbuf_len);
+            assert!(template.is_null());
+            assert_eq!(cel_last_error(), CelErrorCode::CompileError);
+        }
+    }
+}
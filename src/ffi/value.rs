@@ -0,0 +1,1481 @@
+use super::context::raw_bytes;
+use super::error::{contain, contain_with_error, record_error_message, report};
+use super::log::{log, CelLogLevel};
+use super::memory::{track, untrack, CelAllocationKind};
+use super::program::{fail, owned_library_string};
+use super::{
+    cel_string_release, store_owned_bytes, CelBytesValue, CelErrorCode, CelListValue, CelMapValue,
+    CelOptionalValue, CelStringValue, CelTimeValue, CelValue, CelValueData, CelValueType,
+};
+use crate::api::{
+    bigint_parts, debug_string, decimal_parts, display_string, duration_value, optional_parts,
+    optional_value, parse_bigint, parse_decimal, parse_duration, parse_timestamp, parse_type,
+    sorted_entries, timestamp_value, type_parts, value_hash, values_equal, Error,
+};
+use cel_interpreter::objects::{Key, Map};
+use cel_interpreter::Value as CelRustValue;
+use std::collections::HashMap;
+use std::ffi::{c_char, CStr};
+use std::mem::{size_of, ManuallyDrop};
+use std::sync::Arc;
+
+/// Convert a value into a standalone `CelValue` the caller owns
+///
+/// Every string and bytes value is a caller-owned library string, and list and map
+/// arrays are separate allocations; all of it is released by `cel_value_free`.
+///
+/// # Errors
+///
+/// Returns an error for value types that have no C representation.
+pub fn to_owned_c_value(value: &CelRustValue) -> Result<CelValue, String> {
+    if let Some(inner) = optional_parts(value) {
+        let value = match inner {
+            Some(inner) => into_raw_array(vec![to_owned_c_value(inner)?]),
+            None => std::ptr::null(),
+        };
+        return Ok(CelValue {
+            value_type: CelValueType::Optional,
+            data: CelValueData {
+                optional_val: ManuallyDrop::new(CelOptionalValue {
+                    has_value: !value.is_null(),
+                    value,
+                }),
+            },
+        });
+    }
+    if let Some((value_type, text)) = tagged_text(value) {
+        let (_, data) = owned_string(text.as_bytes());
+        return Ok(CelValue { value_type, data });
+    }
+    if let Some(value) = time_c_value(value) {
+        return Ok(value);
+    }
+
+    let (value_type, data) = match value {
+        CelRustValue::Null => (CelValueType::Null, CelValueData { int_val: 0 }),
+        CelRustValue::Bool(b) => (CelValueType::Bool, CelValueData { bool_val: *b }),
+        CelRustValue::Int(i) => (CelValueType::Int, CelValueData { int_val: *i }),
+        CelRustValue::UInt(u) => (CelValueType::Uint, CelValueData { uint_val: *u }),
+        CelRustValue::Float(f) => (CelValueType::Double, CelValueData { double_val: *f }),
+        CelRustValue::String(s) => owned_string(s.as_bytes()),
+        CelRustValue::Bytes(b) => (
+            CelValueType::Bytes,
+            CelValueData {
+                bytes_val: ManuallyDrop::new(CelBytesValue {
+                    ptr: store_owned_bytes(b),
+                    len: b.len(),
+                }),
+            },
+        ),
+        CelRustValue::List(items) => {
+            let items = convert_all(items.iter().map(to_owned_c_value))?;
+            let len = items.len();
+            (
+                CelValueType::List,
+                CelValueData {
+                    list_val: ManuallyDrop::new(CelListValue {
+                        items: into_raw_array(items),
+                        len,
+                    }),
+                },
+            )
+        }
+        CelRustValue::Map(map) => {
+            let entries = sorted_entries(map);
+            let keys = entries.iter().map(|&(key, _)| owned_key(key)).collect::<Vec<_>>();
+            let values =
+                match convert_all(entries.iter().map(|&(_, value)| to_owned_c_value(value))) {
+                    Ok(values) => values,
+                    Err(e) => {
+                        free_all(keys);
+                        return Err(e);
+                    }
+                };
+            let len = keys.len();
+            (
+                CelValueType::Map,
+                CelValueData {
+                    map_val: ManuallyDrop::new(CelMapValue {
+                        keys: into_raw_array(keys),
+                        values: into_raw_array(values),
+                        len,
+                    }),
+                },
+            )
+        }
+        _ => return Err("Unsupported value type".to_string()),
+    };
+
+    Ok(CelValue { value_type, data })
+}
+
+/// The `CelValue` of a timestamp or duration, or `None` for other values
+///
+/// Timestamps are converted to UTC, since `CelTimeValue` carries no offset.
+pub(crate) fn time_c_value(value: &CelRustValue) -> Option<CelValue> {
+    match value {
+        CelRustValue::Timestamp(time) => {
+            if time.offset().local_minus_utc() != 0 {
+                log(CelLogLevel::Debug, || format!("timestamp {time} converted to UTC"));
+            }
+            Some(CelValue {
+                value_type: CelValueType::Timestamp,
+                data: CelValueData {
+                    timestamp_val: CelTimeValue {
+                        seconds: time.timestamp(),
+                        nanos: i32::try_from(time.timestamp_subsec_nanos()).unwrap_or_default(),
+                    },
+                },
+            })
+        }
+        CelRustValue::Duration(duration) => Some(CelValue {
+            value_type: CelValueType::Duration,
+            data: CelValueData {
+                duration_val: CelTimeValue {
+                    seconds: duration.num_seconds(),
+                    nanos: duration.subsec_nanos(),
+                },
+            },
+        }),
+        _ => None,
+    }
+}
+
+/// The CEL value of a `Timestamp` or `Duration` `CelValue`, or `None` for other types
+pub(crate) fn time_rust_value(value: &CelValue) -> Option<Result<CelRustValue, String>> {
+    let result = match value.value_type {
+        CelValueType::Timestamp => {
+            let time = unsafe { value.data.timestamp_val };
+            u32::try_from(time.nanos)
+                .map_err(|_| Error::InvalidArgument(format!("Invalid nanos: {}", time.nanos)))
+                .and_then(|nanos| timestamp_value(time.seconds, nanos))
+        }
+        CelValueType::Duration => {
+            let duration = unsafe { value.data.duration_val };
+            duration_value(duration.seconds, duration.nanos)
+        }
+        _ => return None,
+    };
+    Some(result.map_err(|e| e.to_string()))
+}
+
+/// The type and text of a big integer, decimal or type, or `None` for other values
+pub(crate) fn tagged_text(value: &CelRustValue) -> Option<(CelValueType, String)> {
+    bigint_parts(value)
+        .map(|n| (CelValueType::BigInt, n.to_string()))
+        .or_else(|| decimal_parts(value).map(|d| (CelValueType::Decimal, d.to_string())))
+        .or_else(|| type_parts(value).map(|name| (CelValueType::Type, name.to_string())))
+}
+
+/// The CEL value of a `BigInt`, `Decimal` or `Type` `CelValue`, or `None` for other
+/// types
+///
+/// # Safety
+/// The text pointer must be valid for its length
+pub(crate) unsafe fn tagged_rust_value(value: &CelValue) -> Option<Result<CelRustValue, String>> {
+    let parse = match value.value_type {
+        CelValueType::BigInt => parse_bigint,
+        CelValueType::Decimal => parse_decimal,
+        CelValueType::Type => parse_type,
+        _ => return None,
+    };
+    let text = &*value.data.string_val;
+    if text.ptr.is_null() {
+        return Some(Err(format!("{:?} text is null", value.value_type)));
+    }
+    Some(
+        std::str::from_utf8(raw_bytes(text.ptr, text.len))
+            .map_err(|e| format!("Invalid UTF-8 string: {e}"))
+            .and_then(|text| parse(text).map_err(|e| e.to_string())),
+    )
+}
+
+/// Convert a `CelValue` into a value, the inverse of `to_owned_c_value`
+///
+/// Every type is kept, however deeply nested, so converting the result back with
+/// `to_owned_c_value` gives an equal `CelValue`. Timestamps are in UTC.
+///
+/// # Safety
+/// Every pointer in `value`, however deeply nested, must be valid for its length
+///
+/// # Errors
+///
+/// Returns an error for null pointers with a nonzero length, strings that are not
+/// UTF-8, out-of-range timestamps and durations, malformed big integer, decimal or
+/// type text, and map keys that are not ints, uints, bools or strings.
+pub unsafe fn from_c_value(value: &CelValue) -> Result<CelRustValue, String> {
+    exact_rust_value(value)
+}
+
+/// The CEL value of any `CelValue`, lists and maps included
+///
+/// Unlike `rust_value` this keeps an optional at the top level as an optional rather
+/// than unwrapping it.
+///
+/// # Safety
+/// Every pointer in `value`, however deeply nested, must be valid for its length
+pub(crate) unsafe fn exact_rust_value(value: &CelValue) -> Result<CelRustValue, String> {
+    if let Some(converted) = time_rust_value(value).or_else(|| tagged_rust_value(value)) {
+        return converted;
+    }
+
+    Ok(match value.value_type {
+        CelValueType::Null => CelRustValue::Null,
+        CelValueType::Bool => CelRustValue::Bool(value.data.bool_val),
+        CelValueType::Int => CelRustValue::Int(value.data.int_val),
+        CelValueType::Uint => CelRustValue::UInt(value.data.uint_val),
+        CelValueType::Double => CelRustValue::Float(value.data.double_val),
+        CelValueType::String => {
+            let text = &*value.data.string_val;
+            let text = std::str::from_utf8(checked_bytes(text.ptr, text.len, "String")?)
+                .map_err(|e| format!("Invalid UTF-8 string: {e}"))?;
+            CelRustValue::String(Arc::new(text.to_string()))
+        }
+        CelValueType::Bytes => {
+            let bytes = &*value.data.bytes_val;
+            CelRustValue::Bytes(Arc::new(checked_bytes(bytes.ptr, bytes.len, "Bytes")?.to_vec()))
+        }
+        CelValueType::List => {
+            let list = &*value.data.list_val;
+            let items = raw_values(list.items, list.len)?
+                .iter()
+                .map(|item| exact_rust_value(item))
+                .collect::<Result<Vec<_>, _>>()?;
+            CelRustValue::List(Arc::new(items))
+        }
+        CelValueType::Map => {
+            let map = &*value.data.map_val;
+            let keys = raw_values(map.keys, map.len)?;
+            let values = raw_values(map.values, map.len)?;
+            let mut entries = HashMap::with_capacity(map.len);
+            for (key, value) in keys.iter().zip(values) {
+                entries.insert(exact_key(key)?, exact_rust_value(value)?);
+            }
+            CelRustValue::Map(Map {
+                map: Arc::new(entries),
+            })
+        }
+        CelValueType::Optional => {
+            let optional = &*value.data.optional_val;
+            if optional.has_value && !optional.value.is_null() {
+                optional_value(Some(exact_rust_value(&*optional.value)?))
+            } else {
+                optional_value(None)
+            }
+        }
+        value_type => return Err(format!("Unsupported value type: {value_type:?}")),
+    })
+}
+
+unsafe fn exact_key(key: &CelValue) -> Result<Key, String> {
+    match exact_rust_value(key)? {
+        CelRustValue::Int(i) => Ok(Key::Int(i)),
+        CelRustValue::UInt(u) => Ok(Key::Uint(u)),
+        CelRustValue::Bool(b) => Ok(Key::Bool(b)),
+        CelRustValue::String(s) => Ok(Key::String(s)),
+        _ => Err(format!("Unsupported map key type: {:?}", key.value_type)),
+    }
+}
+
+/// A copy of `value` the caller owns, with every string, bytes value and array in it
+/// duplicated, in the same order
+///
+/// # Safety
+/// Every pointer in `value`, however deeply nested, must be valid for its length
+unsafe fn clone_c_value(value: &CelValue) -> Result<CelValue, String> {
+    let data = match value.value_type {
+        CelValueType::Null => CelValueData { int_val: 0 },
+        CelValueType::Bool => CelValueData {
+            bool_val: value.data.bool_val,
+        },
+        CelValueType::Int => CelValueData {
+            int_val: value.data.int_val,
+        },
+        CelValueType::Uint => CelValueData {
+            uint_val: value.data.uint_val,
+        },
+        CelValueType::Double => CelValueData {
+            double_val: value.data.double_val,
+        },
+        CelValueType::Timestamp => CelValueData {
+            timestamp_val: value.data.timestamp_val,
+        },
+        CelValueType::Duration => CelValueData {
+            duration_val: value.data.duration_val,
+        },
+        CelValueType::String
+        | CelValueType::BigInt
+        | CelValueType::Decimal
+        | CelValueType::Type => {
+            let text = &*value.data.string_val;
+            owned_string(checked_bytes(text.ptr, text.len, "String")?).1
+        }
+        CelValueType::Bytes => {
+            let bytes = checked_bytes(value.data.bytes_val.ptr, value.data.bytes_val.len, "Bytes")?;
+            CelValueData {
+                bytes_val: ManuallyDrop::new(CelBytesValue {
+                    ptr: store_owned_bytes(bytes),
+                    len: bytes.len(),
+                }),
+            }
+        }
+        CelValueType::List => {
+            let list = &*value.data.list_val;
+            let items = convert_all(
+                raw_values(list.items, list.len)?.iter().map(|item| clone_c_value(item)),
+            )?;
+            CelValueData {
+                list_val: ManuallyDrop::new(CelListValue {
+                    len: items.len(),
+                    items: into_raw_array(items),
+                }),
+            }
+        }
+        CelValueType::Map => {
+            let map = &*value.data.map_val;
+            let keys =
+                convert_all(raw_values(map.keys, map.len)?.iter().map(|key| clone_c_value(key)))?;
+            let values = match raw_values(map.values, map.len)
+                .and_then(|values| convert_all(values.iter().map(|value| clone_c_value(value))))
+            {
+                Ok(values) => values,
+                Err(e) => {
+                    free_all(keys);
+                    return Err(e);
+                }
+            };
+            CelValueData {
+                map_val: ManuallyDrop::new(CelMapValue {
+                    len: keys.len(),
+                    keys: into_raw_array(keys),
+                    values: into_raw_array(values),
+                }),
+            }
+        }
+        CelValueType::Optional => {
+            let optional = &*value.data.optional_val;
+            let value = if optional.has_value && !optional.value.is_null() {
+                into_raw_array(vec![clone_c_value(&*optional.value)?])
+            } else {
+                std::ptr::null()
+            };
+            CelValueData {
+                optional_val: ManuallyDrop::new(CelOptionalValue {
+                    has_value: !value.is_null(),
+                    value,
+                }),
+            }
+        }
+    };
+
+    Ok(CelValue {
+        value_type: value.value_type,
+        data,
+    })
+}
+
+/// Borrow `len` bytes at `ptr`, failing if `ptr` is null for a non-empty range
+unsafe fn checked_bytes<'a>(ptr: *const u8, len: usize, what: &str) -> Result<&'a [u8], String> {
+    if ptr.is_null() && len > 0 {
+        return Err(format!("{what} pointer is null"));
+    }
+    Ok(raw_bytes(ptr, len))
+}
+
+pub(crate) unsafe fn raw_values<'a>(
+    ptr: *const CelValue,
+    len: usize,
+) -> Result<&'a [CelValue], String> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if ptr.is_null() {
+        return Err("Value array pointer is null".to_string());
+    }
+    Ok(std::slice::from_raw_parts(ptr, len))
+}
+
+fn owned_string(bytes: &[u8]) -> (CelValueType, CelValueData) {
+    (
+        CelValueType::String,
+        CelValueData {
+            string_val: ManuallyDrop::new(CelStringValue {
+                ptr: store_owned_bytes(bytes),
+                len: bytes.len(),
+            }),
+        },
+    )
+}
+
+fn owned_key(key: &Key) -> CelValue {
+    let (value_type, data) = match key {
+        Key::Int(i) => (CelValueType::Int, CelValueData { int_val: *i }),
+        Key::Uint(u) => (CelValueType::Uint, CelValueData { uint_val: *u }),
+        Key::Bool(b) => (CelValueType::Bool, CelValueData { bool_val: *b }),
+        Key::String(s) => owned_string(s.as_bytes()),
+    };
+
+    CelValue { value_type, data }
+}
+
+/// Collect converted values, freeing the ones already built if any conversion fails
+fn convert_all(
+    values: impl Iterator<Item = Result<CelValue, String>>,
+) -> Result<Vec<CelValue>, String> {
+    let mut converted = Vec::new();
+    for value in values {
+        match value {
+            Ok(value) => converted.push(value),
+            Err(e) => {
+                free_all(converted);
+                return Err(e);
+            }
+        }
+    }
+    Ok(converted)
+}
+
+pub(crate) fn free_all(values: Vec<CelValue>) {
+    for mut value in values {
+        unsafe { cel_value_free(&mut value) };
+    }
+}
+
+fn into_raw_array(values: Vec<CelValue>) -> *const CelValue {
+    let len = values.len();
+    let ptr = Box::into_raw(values.into_boxed_slice()).cast::<CelValue>().cast_const();
+    // Empty arrays allocate nothing and all share one dangling address
+    if len > 0 {
+        track(CelAllocationKind::AllocatedValues, ptr, len * size_of::<CelValue>());
+    }
+    ptr
+}
+
+unsafe fn from_raw_array(ptr: *const CelValue, len: usize) -> Box<[CelValue]> {
+    if len > 0 {
+        untrack(CelAllocationKind::AllocatedValues, ptr);
+    }
+    Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr.cast_mut(), len))
+}
+
+/// Constructors for values that borrow their strings and bytes
+///
+/// The pointers in a value built from a `&str` or `&[u8]` are only valid while the
+/// borrowed data is, which is all `context_add_variable` needs since it copies.
+impl CelValue {
+    #[must_use]
+    pub const fn null() -> Self {
+        Self {
+            value_type: CelValueType::Null,
+            data: CelValueData { int_val: 0 },
+        }
+    }
+
+    #[must_use]
+    pub const fn bool(value: bool) -> Self {
+        Self {
+            value_type: CelValueType::Bool,
+            data: CelValueData { bool_val: value },
+        }
+    }
+
+    #[must_use]
+    pub const fn int(value: i64) -> Self {
+        Self {
+            value_type: CelValueType::Int,
+            data: CelValueData { int_val: value },
+        }
+    }
+
+    #[must_use]
+    pub const fn uint(value: u64) -> Self {
+        Self {
+            value_type: CelValueType::Uint,
+            data: CelValueData { uint_val: value },
+        }
+    }
+
+    #[must_use]
+    pub const fn double(value: f64) -> Self {
+        Self {
+            value_type: CelValueType::Double,
+            data: CelValueData { double_val: value },
+        }
+    }
+
+    #[must_use]
+    pub const fn string(value: &str) -> Self {
+        Self {
+            value_type: CelValueType::String,
+            data: CelValueData {
+                string_val: ManuallyDrop::new(CelStringValue {
+                    ptr: value.as_ptr(),
+                    len: value.len(),
+                }),
+            },
+        }
+    }
+
+    #[must_use]
+    pub const fn bytes(value: &[u8]) -> Self {
+        Self {
+            value_type: CelValueType::Bytes,
+            data: CelValueData {
+                bytes_val: ManuallyDrop::new(CelBytesValue {
+                    ptr: value.as_ptr(),
+                    len: value.len(),
+                }),
+            },
+        }
+    }
+}
+
+impl From<bool> for CelValue {
+    fn from(value: bool) -> Self {
+        Self::bool(value)
+    }
+}
+
+impl From<i64> for CelValue {
+    fn from(value: i64) -> Self {
+        Self::int(value)
+    }
+}
+
+impl From<u64> for CelValue {
+    fn from(value: u64) -> Self {
+        Self::uint(value)
+    }
+}
+
+impl From<f64> for CelValue {
+    fn from(value: f64) -> Self {
+        Self::double(value)
+    }
+}
+
+impl From<&str> for CelValue {
+    fn from(value: &str) -> Self {
+        Self::string(value)
+    }
+}
+
+impl From<&[u8]> for CelValue {
+    fn from(value: &[u8]) -> Self {
+        Self::bytes(value)
+    }
+}
+
+fn type_mismatch(expected: &str, actual: CelValueType) -> String {
+    format!("Expected {expected} value, got {actual:?}")
+}
+
+impl TryFrom<&CelValue> for bool {
+    type Error = String;
+
+    fn try_from(value: &CelValue) -> Result<Self, Self::Error> {
+        match value.value_type {
+            CelValueType::Bool => Ok(unsafe { value.data.bool_val }),
+            other => Err(type_mismatch("bool", other)),
+        }
+    }
+}
+
+impl TryFrom<&CelValue> for i64 {
+    type Error = String;
+
+    fn try_from(value: &CelValue) -> Result<Self, Self::Error> {
+        match value.value_type {
+            CelValueType::Int => Ok(unsafe { value.data.int_val }),
+            other => Err(type_mismatch("int", other)),
+        }
+    }
+}
+
+impl TryFrom<&CelValue> for u64 {
+    type Error = String;
+
+    fn try_from(value: &CelValue) -> Result<Self, Self::Error> {
+        match value.value_type {
+            CelValueType::Uint => Ok(unsafe { value.data.uint_val }),
+            other => Err(type_mismatch("uint", other)),
+        }
+    }
+}
+
+impl TryFrom<&CelValue> for f64 {
+    type Error = String;
+
+    fn try_from(value: &CelValue) -> Result<Self, Self::Error> {
+        match value.value_type {
+            CelValueType::Double => Ok(unsafe { value.data.double_val }),
+            other => Err(type_mismatch("double", other)),
+        }
+    }
+}
+
+/// Copies the string, which must be valid UTF-8; its pointer must be valid for `len`
+/// bytes, as for every `CelValue` passed to the library
+impl TryFrom<&CelValue> for String {
+    type Error = String;
+
+    fn try_from(value: &CelValue) -> Result<Self, Self::Error> {
+        if value.value_type != CelValueType::String {
+            return Err(type_mismatch("string", value.value_type));
+        }
+
+        let string_val = unsafe { &*value.data.string_val };
+        let bytes = unsafe { raw_bytes(string_val.ptr, string_val.len) };
+        std::str::from_utf8(bytes)
+            .map(str::to_string)
+            .map_err(|e| format!("Invalid UTF-8 string: {e}"))
+    }
+}
+
+/// Copies the bytes; the pointer must be valid for `len` bytes, as for every
+/// `CelValue` passed to the library
+impl TryFrom<&CelValue> for Vec<u8> {
+    type Error = String;
+
+    fn try_from(value: &CelValue) -> Result<Self, Self::Error> {
+        if value.value_type != CelValueType::Bytes {
+            return Err(type_mismatch("bytes", value.value_type));
+        }
+
+        let bytes_val = unsafe { &*value.data.bytes_val };
+        Ok(unsafe { raw_bytes(bytes_val.ptr, bytes_val.len) }.to_vec())
+    }
+}
+
+/// Build a timestamp value from RFC 3339 text such as `2024-01-01T12:00:00Z`
+///
+/// The value is written to `out_value` in UTC and holds no allocations, so it needs no
+/// `cel_value_free`. Returns false with `cel_last_error` set to `NullArgument` if
+/// `text`, `out_value` or `errbuf_len` is null, or to `InvalidArgument` with the reason
+/// in `errbuf` if `text` is not a timestamp between the years 0001 and 9999.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `text` is either null or a valid null-terminated C string
+/// - `out_value` is either null or a valid pointer to a `CelValue`
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn cel_value_timestamp_from_rfc3339(
+    text: *const c_char,
+    out_value: *mut CelValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        parse_time_value(text, out_value, errbuf, errbuf_len, parse_timestamp)
+    })
+}
+
+/// Build a duration value from text such as `1h30m`, `-1.5s` or `250ms`
+///
+/// Accepts the units `h`, `m`, `s`, `ms`, `us` and `ns`, as CEL's `duration()` does.
+/// The value is written to `out_value` and holds no allocations, so it needs no
+/// `cel_value_free`. Returns false with `cel_last_error` set to `NullArgument` if
+/// `text`, `out_value` or `errbuf_len` is null, or to `InvalidArgument` with the reason
+/// in `errbuf` if `text` is not a valid duration.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `text` is either null or a valid null-terminated C string
+/// - `out_value` is either null or a valid pointer to a `CelValue`
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn cel_value_duration_from_string(
+    text: *const c_char,
+    out_value: *mut CelValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        parse_time_value(text, out_value, errbuf, errbuf_len, parse_duration)
+    })
+}
+
+unsafe fn parse_time_value(
+    text: *const c_char,
+    out_value: *mut CelValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+    parse: fn(&str) -> Result<CelRustValue, Error>,
+) -> bool {
+    let Some(errbuf_len) = errbuf_len.as_mut() else {
+        return report(CelErrorCode::NullArgument);
+    };
+    let Some(out_value) = out_value.as_mut() else {
+        return fail(CelErrorCode::NullArgument, "Output value is null", errbuf, errbuf_len);
+    };
+    if text.is_null() {
+        return fail(CelErrorCode::NullArgument, "Text is null", errbuf, errbuf_len);
+    }
+
+    let parsed = CStr::from_ptr(text)
+        .to_str()
+        .map_err(|e| format!("Invalid UTF-8 string: {e}"))
+        .and_then(|text| parse(text).map_err(|e| e.to_string()));
+    match parsed.map(|value| time_c_value(&value)) {
+        Ok(Some(value)) => {
+            *out_value = value;
+            report(CelErrorCode::Ok)
+        }
+        Ok(None) => fail(CelErrorCode::InvalidArgument, "Not a time value", errbuf, errbuf_len),
+        Err(e) => fail(CelErrorCode::InvalidArgument, &e, errbuf, errbuf_len),
+    }
+}
+
+/// Build a big integer value from optionally signed decimal digits
+///
+/// The value is written to `out_value` holding its digits as a caller-owned string,
+/// released by `cel_value_free`. Returns false with `cel_last_error` set to
+/// `NullArgument` if `text`, `out_value` or `errbuf_len` is null, or to
+/// `InvalidArgument` with the reason in `errbuf` if `text` is not an integer within
+/// the signed 128-bit range.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `text` is either null or a valid null-terminated C string
+/// - `out_value` is either null or a valid pointer to a `CelValue`
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn cel_value_bigint_from_string(
+    text: *const c_char,
+    out_value: *mut CelValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        parse_text_value(text, out_value, errbuf, errbuf_len, parse_bigint)
+    })
+}
+
+/// Build an exact decimal value from text such as `19.99` or `-0.005`
+///
+/// Accepts plain decimal notation with up to 28 places; the value keeps the places it
+/// was written with. It is written to `out_value` holding its text as a caller-owned
+/// string, released by `cel_value_free`. Returns false with `cel_last_error` set to
+/// `NullArgument` if `text`, `out_value` or `errbuf_len` is null, or to
+/// `InvalidArgument` with the reason in `errbuf` if `text` is not such a number.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `text` is either null or a valid null-terminated C string
+/// - `out_value` is either null or a valid pointer to a `CelValue`
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn cel_value_decimal_from_string(
+    text: *const c_char,
+    out_value: *mut CelValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        parse_text_value(text, out_value, errbuf, errbuf_len, parse_decimal)
+    })
+}
+
+/// Build a type value from its name, such as `int` or `google.protobuf.Timestamp`
+///
+/// Type values compare equal to what `type()` returns for values of that type, so
+/// hosts can bind them as variables. The value holds the name as a caller-owned
+/// string, released by `cel_value_free`. Returns false with `cel_last_error` set to
+/// `NullArgument` if `name`, `out_value` or `errbuf_len` is null, or to
+/// `InvalidArgument` if `name` is empty or contains whitespace.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `name` is either null or a valid null-terminated C string
+/// - `out_value` is either null or a valid pointer to a `CelValue`
+/// - `errbuf` is either null or points to a valid buffer of at least `*errbuf_len` bytes
+/// - `errbuf_len` is either null or a valid pointer to the buffer size
+#[no_mangle]
+pub unsafe extern "C" fn cel_value_type_from_name(
+    name: *const c_char,
+    out_value: *mut CelValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+) -> bool {
+    contain_with_error(false, errbuf, errbuf_len, || {
+        parse_text_value(name, out_value, errbuf, errbuf_len, parse_type)
+    })
+}
+
+unsafe fn parse_text_value(
+    text: *const c_char,
+    out_value: *mut CelValue,
+    errbuf: *mut u8,
+    errbuf_len: *mut usize,
+    parse: fn(&str) -> Result<CelRustValue, Error>,
+) -> bool {
+    let Some(errbuf_len) = errbuf_len.as_mut() else {
+        return report(CelErrorCode::NullArgument);
+    };
+    let Some(out_value) = out_value.as_mut() else {
+        return fail(CelErrorCode::NullArgument, "Output value is null", errbuf, errbuf_len);
+    };
+    if text.is_null() {
+        return fail(CelErrorCode::NullArgument, "Text is null", errbuf, errbuf_len);
+    }
+
+    let parsed = CStr::from_ptr(text)
+        .to_str()
+        .map_err(|e| format!("Invalid UTF-8 string: {e}"))
+        .and_then(|text| parse(text).map_err(|e| e.to_string()))
+        .and_then(|value| to_owned_c_value(&value));
+    match parsed {
+        Ok(value) => {
+            *out_value = value;
+            report(CelErrorCode::Ok)
+        }
+        Err(e) => fail(CelErrorCode::InvalidArgument, &e, errbuf, errbuf_len),
+    }
+}
+
+/// Free a value the caller owns, including everything nested in it
+///
+/// Use this for values returned by `context_get_variable` or copied by
+/// `cel_value_clone`. Afterwards the value is
+/// reset to null, so freeing it twice is harmless. Strings the caller retained with
+/// `cel_string_retain` stay valid until their own release.
+///
+/// # Safety
+/// The caller must ensure that `value` is either null or a valid pointer to a value
+/// whose strings and arrays were allocated by the library for the caller, such as one
+/// filled in by `context_get_variable`
+#[no_mangle]
+pub unsafe extern "C" fn cel_value_free(value: *mut CelValue) {
+    contain((), || {
+        let Some(value) = value.as_mut() else {
+            return;
+        };
+
+        match value.value_type {
+            CelValueType::String
+            | CelValueType::BigInt
+            | CelValueType::Decimal
+            | CelValueType::Type => {
+                cel_string_release(value.data.string_val.ptr);
+            }
+            CelValueType::Bytes => {
+                cel_string_release(value.data.bytes_val.ptr);
+            }
+            CelValueType::List => {
+                let list = &value.data.list_val;
+                free_all(from_raw_array(list.items, list.len).into_vec());
+            }
+            CelValueType::Map => {
+                let map = &value.data.map_val;
+                free_all(from_raw_array(map.keys, map.len).into_vec());
+                free_all(from_raw_array(map.values, map.len).into_vec());
+            }
+            CelValueType::Optional => {
+                let optional = &value.data.optional_val;
+                if optional.has_value && !optional.value.is_null() {
+                    free_all(from_raw_array(optional.value, 1).into_vec());
+                }
+            }
+            _ => {}
+        }
+
+        value.value_type = CelValueType::Null;
+        value.data = CelValueData { int_val: 0 };
+    })
+}
+
+/// Copy a value, including everything nested in it, into `out_value`
+///
+/// The copy shares nothing with `value`: every string, bytes value and list, map and
+/// optional array is duplicated, in the same order, so the caller owns it outright
+/// and frees it with `cel_value_free` whether `value` was built by the host, filled in
+/// by `context_get_variable` or returned by an execution. Returns false with
+/// `cel_last_error` set to `NullArgument` if `value` or `out_value` is null, or to
+/// `InvalidArgument` if `value` holds a null pointer for a non-empty string or array,
+/// in which case nothing is allocated.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `value` is either null or a valid pointer to a `CelValue` whose strings and arrays,
+///   however deeply nested, are valid for their lengths
+/// - `out_value` is either null or a valid pointer to a `CelValue` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn cel_value_clone(value: *const CelValue, out_value: *mut CelValue) -> bool {
+    contain(false, || {
+        let (Some(value), Some(out_value)) = (value.as_ref(), out_value.as_mut()) else {
+            return report(CelErrorCode::NullArgument);
+        };
+        match clone_c_value(value) {
+            Ok(copy) => {
+                *out_value = copy;
+                report(CelErrorCode::Ok)
+            }
+            Err(e) => {
+                record_error_message(&e);
+                report(CelErrorCode::InvalidArgument)
+            }
+        }
+    })
+}
+
+/// Render a value as a CEL expression that evaluates to it
+///
+/// Lists, maps and optionals are written out in full, such as
+/// `{"a": [1u, b"\x00"], "b": optional.none()}`, with map entries ordered by key, so
+/// `1`, `1u` and `1.0` stay apart. The text is written to `out` as a caller-owned
+/// library string; drop it with `cel_string_release`. Returns false with
+/// `cel_last_error` set to `NullArgument` if `value` or `out` is null, or to
+/// `InvalidArgument` if `value` holds a string that is not UTF-8, a null array or an
+/// unsupported map key.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `value` is either null or a valid pointer to a `CelValue` whose strings and arrays,
+///   however deeply nested, are valid for their lengths
+/// - `out` is either null or a valid pointer to a `CelStringValue` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn cel_value_debug_string(
+    value: *const CelValue,
+    out: *mut CelStringValue,
+) -> bool {
+    contain(false, || render_value(value, out, debug_string))
+}
+
+/// Render a value as text for people
+///
+/// Strings and bytes are written as their contents, timestamps in RFC 3339, durations
+/// such as `1.5s`, big integers, decimals and uints as their digits, types as their
+/// names and optionals as their contents; lists, maps and other values are written as
+/// by `cel_value_debug_string`. The text is written to `out` as a caller-owned library
+/// string; drop it with `cel_string_release`. Fails as `cel_value_debug_string` does.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `value` is either null or a valid pointer to a `CelValue` whose strings and arrays,
+///   however deeply nested, are valid for their lengths
+/// - `out` is either null or a valid pointer to a `CelStringValue` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn cel_value_display_string(
+    value: *const CelValue,
+    out: *mut CelStringValue,
+) -> bool {
+    contain(false, || render_value(value, out, display_string))
+}
+
+/// Compare two values under CEL equality, writing the answer to `out_equal`
+///
+/// Numbers are equal when they are the same number whatever their types, so `1`, `1u`
+/// and `1.0` are equal, NaN equals nothing, and lists, maps and optionals are compared
+/// by their contents. Returns false with `cel_last_error` set to `NullArgument` if an
+/// argument is null, or to `InvalidArgument` if a value holds a string that is not
+/// UTF-8, a null array or an unsupported map key.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `a` and `b` are either null or valid pointers to `CelValue`s whose strings and
+///   arrays, however deeply nested, are valid for their lengths
+/// - `out_equal` is either null or a valid pointer to a `bool` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn cel_value_equals(
+    a: *const CelValue,
+    b: *const CelValue,
+    out_equal: *mut bool,
+) -> bool {
+    contain(false, || {
+        let (Some(a), Some(b), Some(out_equal)) = (a.as_ref(), b.as_ref(), out_equal.as_mut())
+        else {
+            return report(CelErrorCode::NullArgument);
+        };
+        match exact_rust_value(a).and_then(|a| Ok((a, exact_rust_value(b)?))) {
+            Ok((a, b)) => {
+                *out_equal = values_equal(&a, &b);
+                report(CelErrorCode::Ok)
+            }
+            Err(e) => {
+                record_error_message(&e);
+                report(CelErrorCode::InvalidArgument)
+            }
+        }
+    })
+}
+
+/// Hash a value consistently with `cel_value_equals`, writing it to `out_hash`
+///
+/// Values that `cel_value_equals` finds equal have the same hash, which stays the same
+/// across releases and platforms. Fails as `cel_value_equals` does.
+///
+/// # Safety
+/// The caller must ensure that:
+/// - `value` is either null or a valid pointer to a `CelValue` whose strings and arrays,
+///   however deeply nested, are valid for their lengths
+/// - `out_hash` is either null or a valid pointer to a `uint64_t` that can be written to
+#[no_mangle]
+pub unsafe extern "C" fn cel_value_hash(value: *const CelValue, out_hash: *mut u64) -> bool {
+    contain(false, || {
+        let (Some(value), Some(out_hash)) = (value.as_ref(), out_hash.as_mut()) else {
+            return report(CelErrorCode::NullArgument);
+        };
+        match exact_rust_value(value) {
+            Ok(value) => {
+                *out_hash = value_hash(&value);
+                report(CelErrorCode::Ok)
+            }
+            Err(e) => {
+                record_error_message(&e);
+                report(CelErrorCode::InvalidArgument)
+            }
+        }
+    })
+}
+
+unsafe fn render_value(
+    value: *const CelValue,
+    out: *mut CelStringValue,
+    render: fn(&CelRustValue) -> String,
+) -> bool {
+    let (Some(value), Some(out)) = (value.as_ref(), out.as_mut()) else {
+        return report(CelErrorCode::NullArgument);
+    };
+    match exact_rust_value(value) {
+        Ok(value) => {
+            *out = owned_library_string(&render(&value));
+            report(CelErrorCode::Ok)
+        }
+        Err(e) => {
+            record_error_message(&e);
+            report(CelErrorCode::InvalidArgument)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cel_string_is_owned, cel_string_refcount, cel_string_retain};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_owned_scalar_and_string() {
+        let mut value = to_owned_c_value(&CelRustValue::Int(7)).unwrap();
+        assert_eq!(value.value_type, CelValueType::Int);
+        assert_eq!(unsafe { value.data.int_val }, 7);
+        unsafe { cel_value_free(&mut value) };
+
+        let mut value =
+            to_owned_c_value(&CelRustValue::String(Arc::new("hello".to_string()))).unwrap();
+        unsafe {
+            let string_val = &value.data.string_val;
+            assert!(cel_string_is_owned(string_val.ptr));
+            assert_eq!(std::slice::from_raw_parts(string_val.ptr, string_val.len), b"hello");
+
+            cel_value_free(&mut value);
+            assert_eq!(value.value_type, CelValueType::Null);
+            // Second free is a no-op on the reset value
+            cel_value_free(&mut value);
+            cel_value_free(std::ptr::null_mut());
+        }
+    }
+
+    #[test]
+    #[allow(clippy::indexing_slicing)]
+    fn test_owned_nested_list() {
+        let list = CelRustValue::List(Arc::new(vec![
+            CelRustValue::String(Arc::new("a".to_string())),
+            CelRustValue::List(Arc::new(vec![CelRustValue::Bytes(Arc::new(vec![1, 2]))])),
+            CelRustValue::List(Arc::new(Vec::new())),
+        ]));
+        let mut value = to_owned_c_value(&list).unwrap();
+
+        unsafe {
+            let items = std::slice::from_raw_parts(value.data.list_val.items, 3);
+            let nested = &items[1].data.list_val;
+            assert_eq!(nested.len, 1);
+            assert_eq!((*nested.items).value_type, CelValueType::Bytes);
+            assert_eq!(items[2].data.list_val.len, 0);
+
+            // A retained string outlives the value it came from
+            let kept = items[0].data.string_val.ptr;
+            assert!(cel_string_retain(kept));
+            cel_value_free(&mut value);
+            assert_eq!(cel_string_refcount(kept), 1);
+            assert!(crate::cel_string_release(kept));
+        }
+    }
+
+    #[test]
+    fn test_owned_map() {
+        let mut program = crate::Program::new();
+        program.compile("{'k': [1, 2], 3: 'v'}").unwrap();
+        let map = program.execute(&crate::Context::new()).unwrap();
+
+        let mut value = to_owned_c_value(&map).unwrap();
+        unsafe {
+            assert_eq!(value.value_type, CelValueType::Map);
+            assert_eq!(value.data.map_val.len, 2);
+            cel_value_free(&mut value);
+        }
+    }
+
+    #[test]
+    fn test_value_constructors_and_conversions() {
+        assert_eq!(i64::try_from(&CelValue::from(-3_i64)), Ok(-3));
+        assert_eq!(u64::try_from(&CelValue::uint(u64::MAX)), Ok(u64::MAX));
+        assert_eq!(f64::try_from(&CelValue::from(1.5)), Ok(1.5));
+        assert_eq!(bool::try_from(&CelValue::bool(true)), Ok(true));
+        assert_eq!(String::try_from(&CelValue::from("héllo")), Ok("héllo".to_string()));
+        assert_eq!(Vec::<u8>::try_from(&CelValue::bytes(&[0, 0xff])), Ok(vec![0, 0xff]));
+        assert_eq!(String::try_from(&CelValue::string("")), Ok(String::new()));
+
+        assert_eq!(
+            i64::try_from(&CelValue::double(1.0)),
+            Err("Expected int value, got Double".to_string())
+        );
+        assert!(String::try_from(&CelValue::bytes(&[0xff])).is_err());
+        assert!(String::try_from(&CelValue::from(&[0xff_u8][..])).is_err());
+        assert_eq!(CelValue::null().value_type, CelValueType::Null);
+    }
+
+    #[test]
+    fn test_value_constructors_bind_variables() {
+        let mut context = crate::Context::new();
+        let name = std::ffi::CString::new("greeting").unwrap();
+        let mut error_len = 0;
+
+        let ok = unsafe {
+            crate::context_add_variable(
+                &mut context,
+                name.as_ptr(),
+                &CelValue::string("hi"),
+                std::ptr::null_mut(),
+                &mut error_len,
+            )
+        };
+
+        assert!(ok);
+        assert_eq!(context.get_variables().get("greeting"), Some(&serde_json::json!("hi")));
+    }
+
+    #[test]
+    fn test_time_value_constructors() {
+        let text = std::ffi::CString::new("2024-01-01T01:00:00.5+01:00").unwrap();
+        let mut value = CelValue::null();
+        let mut error_buf = [0u8; 128];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            assert!(cel_value_timestamp_from_rfc3339(
+                text.as_ptr(),
+                &mut value,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(value.value_type, CelValueType::Timestamp);
+            assert_eq!(
+                value.data.timestamp_val,
+                CelTimeValue {
+                    seconds: 1_704_067_200,
+                    nanos: 500_000_000
+                }
+            );
+            let round_trip = time_rust_value(&value).unwrap().unwrap();
+            assert_eq!(
+                time_c_value(&round_trip).unwrap().data.timestamp_val.seconds,
+                1_704_067_200
+            );
+
+            let text = std::ffi::CString::new("-1h30m").unwrap();
+            assert!(cel_value_duration_from_string(
+                text.as_ptr(),
+                &mut value,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(value.value_type, CelValueType::Duration);
+            assert_eq!(
+                value.data.duration_val,
+                CelTimeValue {
+                    seconds: -5400,
+                    nanos: 0
+                }
+            );
+
+            let text = std::ffi::CString::new("90 minutes").unwrap();
+            assert!(!cel_value_duration_from_string(
+                text.as_ptr(),
+                &mut value,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+            let error = std::str::from_utf8(&error_buf[..error_len]).unwrap();
+            assert!(error.contains("invalid duration"), "{error}");
+
+            assert!(!cel_value_duration_from_string(
+                std::ptr::null(),
+                &mut value,
+                std::ptr::null_mut(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
+    }
+
+    #[test]
+    fn test_bigint_value_constructor() {
+        let text = std::ffi::CString::new("-170141183460469231731687303715884105728").unwrap();
+        let mut value = CelValue::null();
+        let mut error_buf = [0u8; 128];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            assert!(cel_value_bigint_from_string(
+                text.as_ptr(),
+                &mut value,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(value.value_type, CelValueType::BigInt);
+            let digits = &value.data.string_val;
+            assert_eq!(std::slice::from_raw_parts(digits.ptr, digits.len), text.as_bytes());
+            let round_trip = tagged_rust_value(&value).unwrap().unwrap();
+            assert_eq!(bigint_parts(&round_trip), Some(i128::MIN));
+            cel_value_free(&mut value);
+
+            let text = std::ffi::CString::new("1e40").unwrap();
+            assert!(!cel_value_bigint_from_string(
+                text.as_ptr(),
+                &mut value,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+            assert_eq!(value.value_type, CelValueType::Null);
+        }
+    }
+
+    #[test]
+    fn test_decimal_value_constructor() {
+        let text = std::ffi::CString::new("-19.990").unwrap();
+        let mut value = CelValue::null();
+        let mut error_buf = [0u8; 128];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            assert!(cel_value_decimal_from_string(
+                text.as_ptr(),
+                &mut value,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(value.value_type, CelValueType::Decimal);
+            let round_trip = tagged_rust_value(&value).unwrap().unwrap();
+            assert_eq!(tagged_text(&round_trip), Some((CelValueType::Decimal, "-19.990".into())));
+            cel_value_free(&mut value);
+
+            let text = std::ffi::CString::new("1,5").unwrap();
+            assert!(!cel_value_decimal_from_string(
+                text.as_ptr(),
+                &mut value,
+                error_buf.as_mut_ptr(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+            let error = std::str::from_utf8(&error_buf[..error_len]).unwrap();
+            assert!(error.contains("invalid decimal"), "{error}");
+        }
+    }
+
+    #[test]
+    fn test_type_value_constructor() {
+        let name = std::ffi::CString::new("google.protobuf.Timestamp").unwrap();
+        let mut value = CelValue::null();
+        let mut error_len = 0;
+
+        unsafe {
+            assert!(cel_value_type_from_name(
+                name.as_ptr(),
+                &mut value,
+                std::ptr::null_mut(),
+                &mut error_len,
+            ));
+            assert_eq!(value.value_type, CelValueType::Type);
+            let round_trip = tagged_rust_value(&value).unwrap().unwrap();
+            assert_eq!(round_trip, crate::api::type_value("google.protobuf.Timestamp"));
+            cel_value_free(&mut value);
+
+            let name = std::ffi::CString::new("").unwrap();
+            assert!(!cel_value_type_from_name(
+                name.as_ptr(),
+                &mut value,
+                std::ptr::null_mut(),
+                &mut error_len,
+            ));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+        }
+    }
+
+    #[test]
+    fn test_value_debug_and_display_string() {
+        let map = CelRustValue::Map(Map {
+            map: Arc::new(HashMap::from([
+                (
+                    Key::String(Arc::new("b".to_string())),
+                    CelRustValue::List(Arc::new(vec![
+                        CelRustValue::UInt(1),
+                        CelRustValue::Bytes(Arc::new(vec![0])),
+                    ])),
+                ),
+                (
+                    Key::String(Arc::new("a".to_string())),
+                    optional_value(Some(CelRustValue::Float(2.5))),
+                ),
+            ])),
+        });
+        let mut value = to_owned_c_value(&map).unwrap();
+        let mut out = CelStringValue {
+            ptr: std::ptr::null(),
+            len: 0,
+        };
+
+        unsafe {
+            assert!(cel_value_debug_string(&value, &mut out));
+            assert_eq!(
+                std::slice::from_raw_parts(out.ptr, out.len),
+                br#"{"a": optional.of(2.5), "b": [1u, b"\x00"]}"#
+            );
+            assert!(cel_string_release(out.ptr));
+            cel_value_free(&mut value);
+
+            assert!(cel_value_display_string(&CelValue::string("héllo"), &mut out));
+            assert_eq!(std::slice::from_raw_parts(out.ptr, out.len), "héllo".as_bytes());
+            assert!(cel_string_release(out.ptr));
+
+            let keys = [CelValue::double(1.0)];
+            let values = [CelValue::null()];
+            let map = CelValue {
+                value_type: CelValueType::Map,
+                data: CelValueData {
+                    map_val: ManuallyDrop::new(CelMapValue {
+                        keys: keys.as_ptr(),
+                        values: values.as_ptr(),
+                        len: 1,
+                    }),
+                },
+            };
+            assert!(!cel_value_debug_string(&map, &mut out));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+            assert!(!cel_value_display_string(std::ptr::null(), &mut out));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
+    }
+
+    #[test]
+    fn test_value_equals_and_hash() {
+        let items = [CelValue::uint(1), CelValue::string("a")];
+        let list = CelValue {
+            value_type: CelValueType::List,
+            data: CelValueData {
+                list_val: ManuallyDrop::new(CelListValue {
+                    items: items.as_ptr(),
+                    len: items.len(),
+                }),
+            },
+        };
+        let mut program = crate::Program::new();
+        program.compile("[1.0, 'a']").unwrap();
+        let mut result =
+            to_owned_c_value(&program.execute(&crate::Context::new()).unwrap()).unwrap();
+        let (mut equal, mut list_hash, mut result_hash) = (false, 0, 0);
+
+        unsafe {
+            assert!(cel_value_equals(&list, &result, &mut equal));
+            assert!(equal);
+            assert!(cel_value_hash(&list, &mut list_hash));
+            assert!(cel_value_hash(&result, &mut result_hash));
+            assert_eq!(list_hash, result_hash);
+            cel_value_free(&mut result);
+
+            assert!(cel_value_equals(&CelValue::string("a"), &CelValue::bytes(b"a"), &mut equal));
+            assert!(!equal);
+            assert!(!cel_value_equals(&CelValue::bytes(&[0xff]), &list, std::ptr::null_mut()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+            let bad = [0xff_u8];
+            let invalid = CelValue {
+                value_type: CelValueType::String,
+                data: CelValueData {
+                    string_val: ManuallyDrop::new(CelStringValue {
+                        ptr: bad.as_ptr(),
+                        len: 1,
+                    }),
+                },
+            };
+            assert!(!cel_value_hash(&invalid, &mut list_hash));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+        }
+    }
+
+    #[test]
+    fn test_value_clone() {
+        let map = CelRustValue::Map(Map {
+            map: Arc::new(HashMap::from([(
+                Key::String(Arc::new("k".to_string())),
+                CelRustValue::List(Arc::new(vec![
+                    CelRustValue::String(Arc::new("v".to_string())),
+                    optional_value(Some(CelRustValue::Bytes(Arc::new(vec![1, 2])))),
+                    crate::api::bigint_value(-7),
+                ])),
+            )])),
+        });
+        let mut original = to_owned_c_value(&map).unwrap();
+        let mut copy = CelValue::null();
+
+        unsafe {
+            assert!(cel_value_clone(&original, &mut copy));
+            let (mut equal, original_map) = (false, &original.data.map_val);
+            assert!(cel_value_equals(&original, &copy, &mut equal));
+            assert!(equal);
+            assert_ne!(original_map.keys, copy.data.map_val.keys);
+            let key = &*copy.data.map_val.keys;
+            assert_ne!(key.data.string_val.ptr, (*original_map.keys).data.string_val.ptr);
+            assert!(cel_string_is_owned(key.data.string_val.ptr));
+
+            // The copy outlives the original
+            cel_value_free(&mut original);
+            assert_eq!(exact_rust_value(&copy), Ok(map));
+            cel_value_free(&mut copy);
+
+            let items = [CelValue::int(1)];
+            let list = CelValue {
+                value_type: CelValueType::List,
+                data: CelValueData {
+                    list_val: ManuallyDrop::new(CelListValue {
+                        items: items.as_ptr(),
+                        len: 1,
+                    }),
+                },
+            };
+            assert!(cel_value_clone(&list, &mut copy));
+            assert_eq!((*copy.data.list_val.items).data.int_val, 1);
+            cel_value_free(&mut copy);
+
+            let broken = CelValue {
+                value_type: CelValueType::Bytes,
+                data: CelValueData {
+                    bytes_val: ManuallyDrop::new(CelBytesValue {
+                        ptr: std::ptr::null(),
+                        len: 3,
+                    }),
+                },
+            };
+            assert!(!cel_value_clone(&broken, &mut copy));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidArgument);
+            assert!(!cel_value_clone(&list, std::ptr::null_mut()));
+            assert_eq!(crate::cel_last_error(), CelErrorCode::NullArgument);
+        }
+    }
+}
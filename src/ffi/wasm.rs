@@ -0,0 +1,275 @@
+//! A WebAssembly API over linear memory, built with the `wasm` feature
+//!
+//! A host such as an edge runtime copies its strings into memory from
+//! `cel_wasm_alloc` and passes them as a pointer and a length, with no terminating
+//! null. Strings coming back are buffers holding a little-endian `u32` length followed
+//! by that many bytes, released with `cel_wasm_buffer_free`. Programs and contexts
+//! are the `u64` handles of `program_handle_new` and `context_handle_new`, and failures
+//! are reported as by the flat API: an error code or a null buffer, with the message
+//! in `cel_wasm_error_message`.
+
+use super::error::{contain, last_error_message, report};
+use super::flat::{bind, failure, Variable};
+use super::handle::{lookup, CONTEXTS, PROGRAMS};
+use super::program::value_to_json;
+use super::CelErrorCode;
+use std::sync::PoisonError;
+
+/// Bytes of the length before the contents of a returned buffer
+const LENGTH_PREFIX: usize = std::mem::size_of::<u32>();
+
+/// `len` bytes of memory for passing a string in, released with `cel_wasm_free`
+///
+/// Returns null if the memory cannot be allocated.
+#[no_mangle]
+pub extern "C" fn cel_wasm_alloc(len: usize) -> *mut u8 {
+    contain(std::ptr::null_mut(), || {
+        Box::into_raw(vec![0u8; len].into_boxed_slice()).cast::<u8>()
+    })
+}
+
+/// Release memory from `cel_wasm_alloc`
+///
+/// # Safety
+/// The caller must ensure that `ptr` is either null or a pointer returned by
+/// `cel_wasm_alloc(len)` that has not been freed
+#[no_mangle]
+pub unsafe extern "C" fn cel_wasm_free(ptr: *mut u8, len: usize) {
+    contain((), || {
+        if !ptr.is_null() {
+            drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+        }
+    })
+}
+
+/// Release a buffer returned by the library
+///
+/// # Safety
+/// The caller must ensure that `buffer` is either null or a buffer returned by a
+/// `cel_wasm_*` function that has not been freed
+#[no_mangle]
+pub unsafe extern "C" fn cel_wasm_buffer_free(buffer: *mut u8) {
+    contain((), || {
+        if !buffer.is_null() {
+            let mut prefix = [0u8; LENGTH_PREFIX];
+            std::ptr::copy_nonoverlapping(buffer, prefix.as_mut_ptr(), LENGTH_PREFIX);
+            let len = LENGTH_PREFIX + u32::from_le_bytes(prefix) as usize;
+            drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(buffer, len)));
+        }
+    })
+}
+
+/// Compile the `len` bytes at `expression` into the program referred to by `program`
+///
+/// # Safety
+/// The caller must ensure that `expression` is either null or points to `len`
+/// readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn cel_wasm_compile(
+    program: u64,
+    expression: *const u8,
+    len: usize,
+) -> CelErrorCode {
+    contain(CelErrorCode::Internal, || {
+        let Some(program) = lookup(&PROGRAMS, program) else {
+            return failure(CelErrorCode::InvalidHandle, "Invalid program handle");
+        };
+        let expression = match text(expression, len, "Expression") {
+            Ok(expression) => expression,
+            Err(code) => return code,
+        };
+
+        let mut program = program.write().unwrap_or_else(PoisonError::into_inner);
+        match program.compile(expression) {
+            Ok(()) => {
+                report(CelErrorCode::Ok);
+                CelErrorCode::Ok
+            }
+            Err(e) => failure(CelErrorCode::CompileError, &e),
+        }
+    })
+}
+
+/// Bind a variable to a JSON value in the context referred to by `context`
+///
+/// Returns `InvalidArgument` if the name is not UTF-8 or the value is not JSON.
+///
+/// # Safety
+/// The caller must ensure that `name` and `json` are each either null or point to
+/// `name_len` and `json_len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn cel_wasm_set_json(
+    context: u64,
+    name: *const u8,
+    name_len: usize,
+    json: *const u8,
+    json_len: usize,
+) -> CelErrorCode {
+    bind(
+        context,
+        || text(name, name_len, "Variable name"),
+        || {
+            let json = text(json, json_len, "JSON").map_err(|code| (code, last_error_message()))?;
+            serde_json::from_str(json)
+                .map(Variable::Json)
+                .map_err(|e| (CelErrorCode::InvalidArgument, format!("Invalid JSON: {e}")))
+        },
+    )
+}
+
+/// Execute a program against a context, returning the result as a JSON buffer
+///
+/// The result is serialized as for `program_execute_json`. Returns null if either
+/// handle is unknown, with `cel_last_error` set to `InvalidHandle`, or if evaluation
+/// fails or the result has no JSON form, with it set to `ExecutionError`.
+///
+/// # Ownership
+/// Release the buffer with `cel_wasm_buffer_free`.
+#[no_mangle]
+pub extern "C" fn cel_wasm_execute_json(program: u64, context: u64) -> *mut u8 {
+    contain(std::ptr::null_mut(), || {
+        let Some(program) = lookup(&PROGRAMS, program) else {
+            failure(CelErrorCode::InvalidHandle, "Invalid program handle");
+            return std::ptr::null_mut();
+        };
+        let Some(context) = lookup(&CONTEXTS, context) else {
+            failure(CelErrorCode::InvalidHandle, "Invalid context handle");
+            return std::ptr::null_mut();
+        };
+
+        let json = {
+            let program = program.read().unwrap_or_else(PoisonError::into_inner);
+            let context = context.read().unwrap_or_else(PoisonError::into_inner);
+            program.execute(&context).and_then(|value| value_to_json(&value))
+        };
+        match json {
+            Ok(json) => {
+                report(CelErrorCode::Ok);
+                buffer(json.as_bytes())
+            }
+            Err(e) => {
+                failure(CelErrorCode::ExecutionError, &e);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Message of the calling thread's last error, as for `cel_last_error_message`
+///
+/// # Ownership
+/// Release the buffer with `cel_wasm_buffer_free`.
+#[no_mangle]
+pub extern "C" fn cel_wasm_error_message() -> *mut u8 {
+    contain(std::ptr::null_mut(), || buffer(last_error_message().as_bytes()))
+}
+
+/// `bytes` behind a little-endian `u32` length, in memory `cel_wasm_buffer_free` releases
+fn buffer(bytes: &[u8]) -> *mut u8 {
+    // Linear memory is 32-bit, so only native builds can have more, and they truncate
+    let len = u32::try_from(bytes.len()).unwrap_or(u32::MAX);
+    let mut buffer = Vec::with_capacity(LENGTH_PREFIX + len as usize);
+    buffer.extend_from_slice(&len.to_le_bytes());
+    buffer.extend_from_slice(&bytes[..len as usize]);
+    Box::into_raw(buffer.into_boxed_slice()).cast::<u8>()
+}
+
+/// The `len` bytes at `ptr` as a string, failing for null or invalid UTF-8
+unsafe fn text<'a>(ptr: *const u8, len: usize, what: &str) -> Result<&'a str, CelErrorCode> {
+    if ptr.is_null() {
+        return Err(failure(CelErrorCode::NullArgument, &format!("{what} is null")));
+    }
+    std::str::from_utf8(std::slice::from_raw_parts(ptr, len))
+        .map_err(|e| failure(CelErrorCode::InvalidArgument, &format!("Invalid {what} string: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context_handle_free, context_handle_new, program_handle_free, program_handle_new};
+
+    /// The contents of a returned buffer, which is then released
+    fn take(buffer: *mut u8) -> String {
+        assert!(!buffer.is_null());
+        let text = unsafe {
+            let len = u32::from_le_bytes(*buffer.cast::<[u8; LENGTH_PREFIX]>()) as usize;
+            let bytes = std::slice::from_raw_parts(buffer.add(LENGTH_PREFIX), len);
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+        unsafe { cel_wasm_buffer_free(buffer) };
+        text
+    }
+
+    /// `text` copied into memory from `cel_wasm_alloc`, as a host would pass it
+    fn input(text: &str) -> (*mut u8, usize) {
+        let ptr = cel_wasm_alloc(text.len());
+        unsafe { std::ptr::copy_nonoverlapping(text.as_ptr(), ptr, text.len()) };
+        (ptr, text.len())
+    }
+
+    #[test]
+    fn test_wasm_execute() {
+        let program = program_handle_new();
+        let context = context_handle_new();
+        let (expression, expression_len) = input("user.name + ' is ' + string(age)");
+        let (user, user_len) = input("user");
+        let (ada, ada_len) = input(r#"{"name": "Ada"}"#);
+        let (age, age_len) = input("age");
+        let (years, years_len) = input("36");
+
+        unsafe {
+            assert_eq!(cel_wasm_compile(program, expression, expression_len), CelErrorCode::Ok);
+            assert_eq!(cel_wasm_set_json(context, user, user_len, ada, ada_len), CelErrorCode::Ok);
+            assert_eq!(
+                cel_wasm_set_json(context, age, age_len, years, years_len),
+                CelErrorCode::Ok
+            );
+        }
+        assert_eq!(take(cel_wasm_execute_json(program, context)), r#""Ada is 36""#);
+
+        unsafe {
+            assert_eq!(cel_wasm_set_json(context, user, user_len, years, 1), CelErrorCode::Ok);
+            assert_eq!(
+                cel_wasm_set_json(context, user, user_len, ada, 3),
+                CelErrorCode::InvalidArgument
+            );
+            assert!(take(cel_wasm_error_message()).starts_with("Invalid JSON"));
+            assert_eq!(cel_wasm_compile(program, expression, 9), CelErrorCode::Ok);
+        }
+        assert!(cel_wasm_execute_json(program, context).is_null());
+        assert_eq!(crate::cel_last_error(), CelErrorCode::ExecutionError);
+        assert!(cel_wasm_execute_json(0, context).is_null());
+        assert_eq!(crate::cel_last_error(), CelErrorCode::InvalidHandle);
+
+        unsafe {
+            for (ptr, len) in [
+                (expression, expression_len),
+                (user, user_len),
+                (ada, ada_len),
+                (age, age_len),
+                (years, years_len),
+            ] {
+                cel_wasm_free(ptr, len);
+            }
+        }
+        assert_eq!(program_handle_free(program), CelErrorCode::Ok);
+        assert_eq!(context_handle_free(context), CelErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_wasm_compile_errors() {
+        let program = program_handle_new();
+        let (bad, bad_len) = input("1 +");
+
+        unsafe {
+            assert_eq!(cel_wasm_compile(program, bad, bad_len), CelErrorCode::CompileError);
+            assert!(!take(cel_wasm_error_message()).is_empty());
+            assert_eq!(cel_wasm_compile(program, std::ptr::null(), 0), CelErrorCode::NullArgument);
+            assert_eq!(cel_wasm_compile(0, bad, bad_len), CelErrorCode::InvalidHandle);
+            cel_wasm_free(bad, bad_len);
+            cel_wasm_free(std::ptr::null_mut(), 0);
+            cel_wasm_buffer_free(std::ptr::null_mut());
+        }
+        assert_eq!(program_handle_free(program), CelErrorCode::Ok);
+    }
+}
@@ -1,3 +1,4 @@
+pub mod api;
 pub mod ffi;
 
 pub use ffi::*;
@@ -993,4 +994,204 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_null_arguments_are_rejected() {
+        let program = program_new();
+        let context = context_new();
+        let expression = CString::new("1 + 1").unwrap();
+        let name = CString::new("x").unwrap();
+        let value = CelValue {
+            value_type: CelValueType::Int,
+            data: CelValueData { int_val: 1 },
+        };
+        let mut result = CelValue {
+            value_type: CelValueType::Null,
+            data: CelValueData { int_val: 0 },
+        };
+        let mut error_buf = [0u8; 256];
+        let mut error_len = error_buf.len();
+
+        unsafe {
+            assert!(!program_compile(
+                ptr::null_mut(),
+                expression.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len
+            ));
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+            let message = std::str::from_utf8(error_buf.get(..error_len).unwrap()).unwrap();
+            assert_eq!(message, "Program is null");
+
+            error_len = error_buf.len();
+            assert!(!program_compile(program, ptr::null(), error_buf.as_mut_ptr(), &mut error_len));
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+
+            assert!(!program_compile(
+                program,
+                expression.as_ptr(),
+                error_buf.as_mut_ptr(),
+                ptr::null_mut()
+            ));
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+
+            error_len = error_buf.len();
+            assert!(program_compile(
+                program,
+                expression.as_ptr(),
+                error_buf.as_mut_ptr(),
+                &mut error_len
+            ));
+            assert_eq!(cel_last_error(), CelErrorCode::Ok);
+
+            for (program, context, result) in [
+                (ptr::null(), context.cast_const(), &raw mut result),
+                (program.cast_const(), ptr::null(), &raw mut result),
+                (program.cast_const(), context.cast_const(), ptr::null_mut()),
+            ] {
+                error_len = error_buf.len();
+                assert!(!program_execute(
+                    program,
+                    context,
+                    result,
+                    error_buf.as_mut_ptr(),
+                    &mut error_len
+                ));
+                assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+            }
+            assert!(!program_execute(
+                program,
+                context,
+                &raw mut result,
+                error_buf.as_mut_ptr(),
+                ptr::null_mut()
+            ));
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+
+            error_len = error_buf.len();
+            assert!(!program_validate(
+                ptr::null(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                error_buf.as_mut_ptr(),
+                &mut error_len
+            ));
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+            assert!(!program_validate(
+                expression.as_ptr(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                error_buf.as_mut_ptr(),
+                ptr::null_mut()
+            ));
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+
+            for (context, name, value) in [
+                (ptr::null_mut(), name.as_ptr(), &raw const value),
+                (context, ptr::null(), &raw const value),
+                (context, name.as_ptr(), ptr::null()),
+            ] {
+                error_len = error_buf.len();
+                assert!(!context_add_variable(
+                    context,
+                    name,
+                    value,
+                    error_buf.as_mut_ptr(),
+                    &mut error_len
+                ));
+                assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+            }
+            assert!(!context_add_variable(
+                context,
+                name.as_ptr(),
+                &raw const value,
+                error_buf.as_mut_ptr(),
+                ptr::null_mut()
+            ));
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+
+            context_reset(ptr::null_mut());
+            assert_eq!(cel_last_error(), CelErrorCode::NullArgument);
+            context_reset(context);
+            assert_eq!(cel_last_error(), CelErrorCode::Ok);
+
+            let eval = program_execute_alloc(ptr::null(), context);
+            assert_eq!(cel_result_error_code(eval), CelErrorCode::NullArgument);
+            cel_result_free(eval);
+            let eval = program_execute_alloc(program, ptr::null());
+            assert_eq!(cel_result_error_code(eval), CelErrorCode::NullArgument);
+            cel_result_free(eval);
+            assert!(!cel_result_is_ok(ptr::null()));
+            assert!(cel_result_value(ptr::null()).is_null());
+            assert!(cel_result_error(ptr::null()).is_null());
+            cel_result_free(ptr::null_mut());
+
+            assert!(!cel_string_retain(ptr::null()));
+            assert!(!cel_string_release(ptr::null()));
+            assert!(!cel_string_is_owned(ptr::null()));
+            assert_eq!(cel_string_refcount(ptr::null()), 0);
+            cel_string_free(ptr::null());
+
+            let program_handle = program_handle_new();
+            let context_handle = context_handle_new();
+            error_len = error_buf.len();
+            assert_eq!(
+                program_handle_compile(
+                    program_handle,
+                    ptr::null(),
+                    error_buf.as_mut_ptr(),
+                    &mut error_len
+                ),
+                CelErrorCode::NullArgument
+            );
+            assert_eq!(
+                program_handle_compile(
+                    program_handle,
+                    expression.as_ptr(),
+                    error_buf.as_mut_ptr(),
+                    ptr::null_mut()
+                ),
+                CelErrorCode::NullArgument
+            );
+            error_len = error_buf.len();
+            assert_eq!(
+                program_handle_execute(
+                    program_handle,
+                    context_handle,
+                    ptr::null_mut(),
+                    error_buf.as_mut_ptr(),
+                    &mut error_len
+                ),
+                CelErrorCode::NullArgument
+            );
+            error_len = error_buf.len();
+            assert_eq!(
+                context_handle_add_variable(
+                    context_handle,
+                    ptr::null(),
+                    &raw const value,
+                    error_buf.as_mut_ptr(),
+                    &mut error_len
+                ),
+                CelErrorCode::NullArgument
+            );
+            assert_eq!(
+                context_handle_add_variable(
+                    context_handle,
+                    name.as_ptr(),
+                    ptr::null(),
+                    error_buf.as_mut_ptr(),
+                    ptr::null_mut()
+                ),
+                CelErrorCode::NullArgument
+            );
+            assert_eq!(program_handle_free(program_handle), CelErrorCode::Ok);
+            assert_eq!(context_handle_free(context_handle), CelErrorCode::Ok);
+
+            program_free(ptr::null_mut());
+            context_free(ptr::null_mut());
+            program_free(program);
+            context_free(context);
+        }
+    }
 }
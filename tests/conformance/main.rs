@@ -0,0 +1,380 @@
+//! CEL spec conformance: runs the `SimpleTestFile` textproto cases of the official
+//! suite through the C API and reports how many pass in each section
+//!
+//! The bundled files in `testdata` always run and must pass. Point
+//! `CEL_SPEC_TESTDATA` at a checkout's `tests/simple/testdata` to also run the
+//! official files; those are only reported, since the binding does not support every
+//! spec area:
+//!
+//! ```sh
+//! CEL_SPEC_TESTDATA=../cel-spec/tests/simple/testdata \
+//!     cargo test --features conformance --test conformance -- --nocapture
+//! ```
+//!
+//! A case passes if executing its expression gives the expected value, compared under
+//! CEL equality and with the same type, or fails when an error is expected, whether
+//! at compilation or at execution. Cases whose bindings or expected value use a type
+//! with no C representation, such as messages, or that expect unknowns, are skipped.
+
+mod textproto;
+
+use cel_interpreter::objects::{Key, Map};
+use cel_lua::api::{Context, Value};
+use cel_lua::{
+    cel_result_error, cel_result_free, cel_result_is_ok, cel_result_value, cel_value_equals,
+    cel_value_free, context_add_variable, context_free, context_new, program_compile,
+    program_execute_alloc, program_free, program_new, to_owned_c_value, CelValue, CelValueType,
+};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use textproto::{Field, Message};
+
+/// How one case went
+#[derive(Debug, Clone, PartialEq)]
+enum Outcome {
+    Pass,
+    Fail(String),
+    Skip(String),
+}
+
+/// What a case expects its expression to evaluate to
+enum Expected {
+    Value(Value),
+    Error,
+}
+
+/// The outcomes of the cases of one section of a test file
+#[derive(Debug, Default)]
+struct SectionReport {
+    file: String,
+    section: String,
+    passed: usize,
+    /// The names of the failed cases, with the reason
+    failed: Vec<(String, String)>,
+    /// The names of the skipped cases, with the reason
+    skipped: Vec<(String, String)>,
+}
+
+impl fmt::Display for SectionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}/{}: {} passed, {} failed, {} skipped",
+            self.file,
+            self.section,
+            self.passed,
+            self.failed.len(),
+            self.skipped.len()
+        )?;
+        for (name, reason) in &self.failed {
+            writeln!(f, "  FAIL {name}: {reason}")?;
+        }
+        for (name, reason) in &self.skipped {
+            writeln!(f, "  SKIP {name}: {reason}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The textproto files in `dir`, by name
+fn test_files(dir: &Path) -> Vec<std::path::PathBuf> {
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("cannot read {}: {e}", dir.display()))
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "textproto"))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Run every case of the test files in `dir`, one report per section
+fn run_dir(dir: &Path) -> Vec<SectionReport> {
+    let mut reports = Vec::new();
+    for path in test_files(dir) {
+        let text = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("cannot read {}: {e}", path.display()));
+        let file = textproto::parse(&text)
+            .unwrap_or_else(|e| panic!("cannot parse {}: {e}", path.display()));
+        let file_name = file.string("name").unwrap_or_else(|| path.display().to_string());
+
+        for section in file.messages("section") {
+            let mut report = SectionReport {
+                file: file_name.clone(),
+                section: section.string("name").unwrap_or_default(),
+                ..SectionReport::default()
+            };
+            for test in section.messages("test") {
+                let name = test.string("name").unwrap_or_default();
+                match run(test) {
+                    Outcome::Pass => report.passed += 1,
+                    Outcome::Fail(reason) => report.failed.push((name, reason)),
+                    Outcome::Skip(reason) => report.skipped.push((name, reason)),
+                }
+            }
+            reports.push(report);
+        }
+    }
+    reports
+}
+
+/// Run one case through the C API
+fn run(test: &Message) -> Outcome {
+    let Some(expr) = test.string("expr") else {
+        return Outcome::Skip("no expression".to_string());
+    };
+    let expected = match expected(test) {
+        Ok(expected) => expected,
+        Err(reason) => return Outcome::Skip(reason),
+    };
+    let mut bindings = Vec::new();
+    for binding in test.messages("bindings") {
+        let name = binding.string("key").unwrap_or_default();
+        let Some(value) = binding.message("value").and_then(|value| value.message("value")) else {
+            return Outcome::Skip(format!("binding {name} is not a value"));
+        };
+        match value_of(value) {
+            Ok(value) => bindings.push((name, value)),
+            Err(reason) => return Outcome::Skip(reason),
+        }
+    }
+    let Ok(expr) = CString::new(expr) else {
+        return Outcome::Skip("expression contains a NUL byte".to_string());
+    };
+
+    unsafe { execute(&expr, &bindings, &expected) }
+}
+
+/// Compile and execute `expr` with `bindings` bound, and check the result
+unsafe fn execute(expr: &CString, bindings: &[(String, Value)], expected: &Expected) -> Outcome {
+    let mut errbuf = [0u8; 512];
+    let context = context_new();
+    for (name, value) in bindings {
+        let outcome = bind(context, name, value, &mut errbuf);
+        if outcome != Outcome::Pass {
+            context_free(context);
+            return outcome;
+        }
+    }
+
+    let program = program_new();
+    let mut errbuf_len = errbuf.len();
+    let outcome = if program_compile(program, expr.as_ptr(), errbuf.as_mut_ptr(), &mut errbuf_len) {
+        let result = program_execute_alloc(program, context);
+        let outcome = if cel_result_is_ok(result) {
+            compare(&*cel_result_value(result), expected)
+        } else if matches!(expected, Expected::Error) {
+            Outcome::Pass
+        } else {
+            let error = &*cel_result_error(result);
+            let error = std::slice::from_raw_parts(error.ptr, error.len);
+            Outcome::Fail(format!("execution failed: {}", String::from_utf8_lossy(error)))
+        };
+        cel_result_free(result);
+        outcome
+    } else if matches!(expected, Expected::Error) {
+        Outcome::Pass
+    } else {
+        let error = String::from_utf8_lossy(&errbuf[..errbuf_len]);
+        Outcome::Fail(format!("compilation failed: {}", error.trim_end_matches('\0')))
+    };
+
+    program_free(program);
+    context_free(context);
+    outcome
+}
+
+/// Bind `value` to `name` in `context`, with `Pass` meaning it was bound
+unsafe fn bind(context: *mut Context, name: &str, value: &Value, errbuf: &mut [u8]) -> Outcome {
+    let Ok(c_name) = CString::new(name) else {
+        return Outcome::Skip(format!("binding name {name:?} contains a NUL byte"));
+    };
+    let mut c_value = match to_owned_c_value(value) {
+        Ok(c_value) => c_value,
+        Err(e) => return Outcome::Skip(format!("binding {name}: {e}")),
+    };
+    let mut errbuf_len = errbuf.len();
+    let added = context_add_variable(
+        context,
+        c_name.as_ptr(),
+        &c_value,
+        errbuf.as_mut_ptr(),
+        &mut errbuf_len,
+    );
+    cel_value_free(&mut c_value);
+    if added {
+        Outcome::Pass
+    } else {
+        let error = String::from_utf8_lossy(&errbuf[..errbuf_len]);
+        Outcome::Fail(format!("cannot bind {name}: {}", error.trim_end_matches('\0')))
+    }
+}
+
+/// Whether `actual` is the expected value, of the same type
+unsafe fn compare(actual: &CelValue, expected: &Expected) -> Outcome {
+    let Expected::Value(expected) = expected else {
+        return Outcome::Fail("expected an error, got a value".to_string());
+    };
+    if let Value::Float(f) = expected {
+        if f.is_nan() {
+            let nan = matches!(actual.value_type, CelValueType::Double)
+                && actual.data.double_val.is_nan();
+            return if nan { Outcome::Pass } else { Outcome::Fail("expected NaN".to_string()) };
+        }
+    }
+
+    let mut c_expected = match to_owned_c_value(expected) {
+        Ok(c_expected) => c_expected,
+        Err(e) => return Outcome::Skip(format!("expected value: {e}")),
+    };
+    let mut equal = false;
+    let compared = cel_value_equals(actual, &c_expected, &mut equal);
+    let same_type = actual.value_type == c_expected.value_type;
+    let expected_type = c_expected.value_type;
+    cel_value_free(&mut c_expected);
+
+    if compared && equal && same_type {
+        Outcome::Pass
+    } else if !same_type {
+        Outcome::Fail(format!("expected {expected_type:?}, got {:?}", actual.value_type))
+    } else {
+        Outcome::Fail(format!("expected {expected:?}, got a different value"))
+    }
+}
+
+/// The result a case expects, or why it is skipped
+fn expected(test: &Message) -> Result<Expected, String> {
+    if let Some(value) = test.message("value") {
+        return value_of(value).map(Expected::Value);
+    }
+    if test.get("eval_error").is_some() || test.get("any_eval_errors").is_some() {
+        return Ok(Expected::Error);
+    }
+    if test.get("unknown").is_some() || test.get("any_unknowns").is_some() {
+        return Err("unknowns are not supported".to_string());
+    }
+    if test.get("typed_result").is_some() || test.get("check_only").is_some() {
+        return Err("type-checked results are not supported".to_string());
+    }
+    // A case with no expectation expects true
+    Ok(Expected::Value(Value::Bool(true)))
+}
+
+/// The value a `google.api.expr.v1alpha1.Value` message holds
+fn value_of(message: &Message) -> Result<Value, String> {
+    let Some((kind, field)) = message.0.last() else {
+        return Err("empty value".to_string());
+    };
+    let word = || match field {
+        Field::Word(word) => Ok(word.as_str()),
+        _ => Err(format!("{kind} is not a scalar")),
+    };
+    let bytes = || match field {
+        Field::Bytes(bytes) => Ok(bytes.clone()),
+        _ => Err(format!("{kind} is not a string")),
+    };
+    let invalid = |e: &dyn fmt::Display| format!("invalid {kind}: {e}");
+
+    Ok(match kind.as_str() {
+        "null_value" => Value::Null,
+        "bool_value" => Value::Bool(word()? == "true"),
+        "int64_value" => Value::Int(parse_int(word()?).map_err(|e| invalid(&e))?),
+        "uint64_value" => Value::UInt(parse_uint(word()?).map_err(|e| invalid(&e))?),
+        "double_value" => Value::Float(parse_double(word()?).map_err(|e| invalid(&e))?),
+        "string_value" => {
+            Value::String(Arc::new(String::from_utf8(bytes()?).map_err(|e| invalid(&e))?))
+        }
+        "bytes_value" => Value::Bytes(Arc::new(bytes()?)),
+        "list_value" => {
+            let Field::Message(list) = field else {
+                return Err("list_value is not a message".to_string());
+            };
+            let items: Result<Vec<_>, _> = list.messages("values").map(value_of).collect();
+            Value::List(Arc::new(items?))
+        }
+        "map_value" => {
+            let Field::Message(map) = field else {
+                return Err("map_value is not a message".to_string());
+            };
+            let mut entries = HashMap::new();
+            for entry in map.messages("entries") {
+                let key = entry.message("key").ok_or("map entry without a key")?;
+                let value = entry.message("value").ok_or("map entry without a value")?;
+                entries.insert(map_key(value_of(key)?)?, value_of(value)?);
+            }
+            Value::Map(Map {
+                map: Arc::new(entries),
+            })
+        }
+        kind => return Err(format!("{kind} values are not supported")),
+    })
+}
+
+fn map_key(value: Value) -> Result<Key, String> {
+    match value {
+        Value::Int(i) => Ok(Key::Int(i)),
+        Value::UInt(u) => Ok(Key::Uint(u)),
+        Value::Bool(b) => Ok(Key::Bool(b)),
+        Value::String(s) => Ok(Key::String(s)),
+        value => Err(format!("{value:?} cannot be a map key")),
+    }
+}
+
+fn parse_int(word: &str) -> Result<i64, std::num::ParseIntError> {
+    match word.strip_prefix("-0x") {
+        Some(hex) => i64::from_str_radix(&format!("-{hex}"), 16),
+        None => match word.strip_prefix("0x") {
+            Some(hex) => i64::from_str_radix(hex, 16),
+            None => word.parse(),
+        },
+    }
+}
+
+fn parse_uint(word: &str) -> Result<u64, std::num::ParseIntError> {
+    match word.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => word.parse(),
+    }
+}
+
+fn parse_double(word: &str) -> Result<f64, std::num::ParseFloatError> {
+    match word.to_ascii_lowercase().trim_end_matches('f') {
+        "inf" | "infinity" => Ok(f64::INFINITY),
+        "-inf" | "-infinity" => Ok(f64::NEG_INFINITY),
+        "nan" | "-nan" => Ok(f64::NAN),
+        word => word.parse(),
+    }
+}
+
+#[test]
+fn test_conformance_bundled() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/testdata");
+    let reports = run_dir(&dir);
+    for report in &reports {
+        print!("{report}");
+    }
+    let failed: Vec<_> = reports.iter().filter(|report| !report.failed.is_empty()).collect();
+    assert!(failed.is_empty(), "bundled conformance cases failed: {failed:#?}");
+    assert!(reports.iter().map(|report| report.passed).sum::<usize>() > 0);
+}
+
+/// Report on the official suite when `CEL_SPEC_TESTDATA` points to it
+#[test]
+fn test_conformance_spec() {
+    let Some(dir) = std::env::var_os("CEL_SPEC_TESTDATA") else {
+        println!("CEL_SPEC_TESTDATA is not set; skipping the official conformance suite");
+        return;
+    };
+    let reports = run_dir(Path::new(&dir));
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for report in &reports {
+        print!("{report}");
+        passed += report.passed;
+        failed += report.failed.len();
+        skipped += report.skipped.len();
+    }
+    println!("total: {passed} passed, {failed} failed, {skipped} skipped");
+}
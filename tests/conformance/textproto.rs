@@ -0,0 +1,323 @@
+//! A reader for the protobuf text format the CEL conformance tests are written in
+//!
+//! Only what the test files use is supported: nested messages in braces or angle
+//! brackets, repeated fields written out or as `[...]` lists, quoted strings with their
+//! escapes, and bare numbers and identifiers. Messages are kept untyped, as lists of
+//! named fields.
+
+/// A field value: a scalar, or a message of its own
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    /// A quoted string, as the bytes its escapes stand for
+    Bytes(Vec<u8>),
+    /// A number, enum value or other bare word, such as `-1.5`, `inf` or `NULL_VALUE`
+    Word(String),
+    Message(Message),
+}
+
+/// A message, as its fields in the order they appear
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Message(pub Vec<(String, Field)>);
+
+impl Message {
+    /// Every value of the field `name`
+    pub fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Field> + 'a {
+        self.0.iter().filter(move |(field, _)| field == name).map(|(_, value)| value)
+    }
+
+    /// The last value of the field `name`
+    pub fn get(&self, name: &str) -> Option<&Field> {
+        self.all(name).last()
+    }
+
+    /// Every message value of the field `name`
+    pub fn messages<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Message> + 'a {
+        self.all(name).filter_map(|value| match value {
+            Field::Message(message) => Some(message),
+            _ => None,
+        })
+    }
+
+    pub fn message(&self, name: &str) -> Option<&Message> {
+        self.messages(name).last()
+    }
+
+    /// The field `name` as UTF-8 text
+    pub fn string(&self, name: &str) -> Option<String> {
+        match self.get(name)? {
+            Field::Bytes(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+            Field::Word(word) => Some(word.clone()),
+            Field::Message(_) => None,
+        }
+    }
+}
+
+/// Read a message written in the text format
+pub fn parse(text: &str) -> Result<Message, String> {
+    let mut parser = Parser {
+        text: text.as_bytes(),
+        pos: 0,
+    };
+    let message = parser.message(None)?;
+    parser.skip_space();
+    match parser.peek() {
+        None => Ok(message),
+        Some(c) => Err(parser.error(&format!("unexpected '{}'", char::from(c)))),
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        let line = self.text[..self.pos].iter().filter(|&&c| c == b'\n').count() + 1;
+        format!("line {line}: {message}")
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    /// Skip whitespace and `#` comments
+    fn skip_space(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == b'#' {
+                while self.peek().is_some_and(|c| c != b'\n') {
+                    self.pos += 1;
+                }
+            } else if c.is_ascii_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        self.skip_space();
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    /// Fields up to `close`, or up to the end of the text
+    fn message(&mut self, close: Option<u8>) -> Result<Message, String> {
+        let mut fields = Vec::new();
+        loop {
+            self.skip_space();
+            match (self.peek(), close) {
+                (None, None) => return Ok(Message(fields)),
+                (None, Some(close)) => {
+                    return Err(self.error(&format!("missing '{}'", char::from(close))))
+                }
+                (Some(c), Some(close)) if c == close => {
+                    self.pos += 1;
+                    return Ok(Message(fields));
+                }
+                _ => {}
+            }
+
+            let name = self.name()?;
+            let colon = self.eat(b':');
+            if self.eat(b'[') {
+                if !self.eat(b']') {
+                    loop {
+                        fields.push((name.clone(), self.value(colon)?));
+                        if self.eat(b']') {
+                            break;
+                        }
+                        if !self.eat(b',') {
+                            return Err(self.error("expected ',' or ']'"));
+                        }
+                    }
+                }
+            } else {
+                fields.push((name, self.value(colon)?));
+            }
+            // Fields may be separated by commas or semicolons
+            let _ = self.eat(b',') || self.eat(b';');
+        }
+    }
+
+    /// A field name, including the brackets of an extension or `Any` type URL
+    fn name(&mut self) -> Result<String, String> {
+        self.skip_space();
+        let start = self.pos;
+        if self.peek() == Some(b'[') {
+            while self.peek().is_some_and(|c| c != b']') {
+                self.pos += 1;
+            }
+            self.pos += 1;
+        } else {
+            while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_') {
+                self.pos += 1;
+            }
+        }
+        if self.pos == start || self.pos > self.text.len() {
+            return Err(self.error("expected a field name"));
+        }
+        Ok(String::from_utf8_lossy(&self.text[start..self.pos]).into_owned())
+    }
+
+    fn value(&mut self, colon: bool) -> Result<Field, String> {
+        if self.eat(b'{') {
+            return self.message(Some(b'}')).map(Field::Message);
+        }
+        if self.eat(b'<') {
+            return self.message(Some(b'>')).map(Field::Message);
+        }
+        if !colon {
+            return Err(self.error("expected ':' or a message"));
+        }
+
+        self.skip_space();
+        match self.peek() {
+            Some(b'"' | b'\'') => {
+                // Adjacent strings are concatenated
+                let mut bytes = Vec::new();
+                while matches!(self.peek(), Some(b'"' | b'\'')) {
+                    bytes.extend(self.string()?);
+                    self.skip_space();
+                }
+                Ok(Field::Bytes(bytes))
+            }
+            _ => {
+                let start = self.pos;
+                if self.peek() == Some(b'-') {
+                    self.pos += 1;
+                    self.skip_space();
+                }
+                let word_start = self.pos;
+                while self
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, b'_' | b'.' | b'+'))
+                {
+                    // A sign is only part of a word after an exponent
+                    if self.peek() == Some(b'+') && !self.text[..self.pos].ends_with(b"e") {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                if self.pos == word_start {
+                    return Err(self.error("expected a value"));
+                }
+                let sign = if start == word_start { "" } else { "-" };
+                let word = String::from_utf8_lossy(&self.text[word_start..self.pos]);
+                Ok(Field::Word(format!("{sign}{word}")))
+            }
+        }
+    }
+
+    /// A quoted string, with its escapes resolved
+    fn string(&mut self) -> Result<Vec<u8>, String> {
+        let Some(quote) = self.peek() else {
+            return Err(self.error("expected a string"));
+        };
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(self.error("unterminated string"));
+            };
+            self.pos += 1;
+            match c {
+                c if c == quote => return Ok(bytes),
+                b'\\' => self.escape(&mut bytes)?,
+                c => bytes.push(c),
+            }
+        }
+    }
+
+    fn escape(&mut self, bytes: &mut Vec<u8>) -> Result<(), String> {
+        let Some(c) = self.peek() else {
+            return Err(self.error("unterminated escape"));
+        };
+        self.pos += 1;
+        match c {
+            b'n' => bytes.push(b'\n'),
+            b'r' => bytes.push(b'\r'),
+            b't' => bytes.push(b'\t'),
+            b'a' => bytes.push(0x07),
+            b'b' => bytes.push(0x08),
+            b'f' => bytes.push(0x0c),
+            b'v' => bytes.push(0x0b),
+            b'0'..=b'7' => {
+                let mut value = u32::from(c - b'0');
+                for _ in 0..2 {
+                    match self.peek() {
+                        Some(d @ b'0'..=b'7') => {
+                            value = value * 8 + u32::from(d - b'0');
+                            self.pos += 1;
+                        }
+                        _ => break,
+                    }
+                }
+                bytes.push(u8::try_from(value).map_err(|_| self.error("octal escape too large"))?);
+            }
+            b'x' => {
+                let value = self.hex(2)?;
+                bytes.push(u8::try_from(value).map_err(|_| self.error("invalid hex escape"))?);
+            }
+            b'u' | b'U' => {
+                let value = self.hex(if c == b'u' { 4 } else { 8 })?;
+                let c = char::from_u32(value).ok_or_else(|| self.error("invalid code point"))?;
+                bytes.extend(c.to_string().bytes());
+            }
+            c => bytes.push(c),
+        }
+        Ok(())
+    }
+
+    /// Up to `digits` hex digits, at least one
+    fn hex(&mut self, digits: usize) -> Result<u32, String> {
+        let start = self.pos;
+        while self.pos - start < digits && self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.text[start..self.pos]).unwrap_or_default();
+        u32::from_str_radix(text, 16).map_err(|_| self.error("invalid hex escape"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_messages_lists_and_escapes() {
+        let message = parse(
+            "# comment\n\
+             name: \"basic\"\n\
+             section { name: 'a' test < expr: \"x\" 'y'\n\
+             value: { bytes_value: \"\\001\\xff\" } > }\n\
+             values: [1, -2.5e+3, inf]; key: -inf\n\
+             [type.googleapis.com/google.protobuf.Int32Value] { value: 1 }",
+        )
+        .unwrap();
+
+        assert_eq!(message.string("name").as_deref(), Some("basic"));
+        let test = message.message("section").unwrap().message("test").unwrap();
+        assert_eq!(test.string("expr").as_deref(), Some("xy"));
+        assert_eq!(
+            test.message("value").unwrap().get("bytes_value"),
+            Some(&Field::Bytes(vec![1, 0xff]))
+        );
+        let values: Vec<_> = message.all("values").collect();
+        assert_eq!(
+            values,
+            [
+                &Field::Word("1".to_string()),
+                &Field::Word("-2.5e+3".to_string()),
+                &Field::Word("inf".to_string())
+            ]
+        );
+        assert_eq!(message.string("key").as_deref(), Some("-inf"));
+        assert!(message.message("[type.googleapis.com/google.protobuf.Int32Value]").is_some());
+        assert!(parse("section {").is_err());
+    }
+}
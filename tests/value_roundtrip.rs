@@ -0,0 +1,377 @@
+//! Property tests: a `CelValue` converted with `from_c_value` and back with
+//! `to_owned_c_value` keeps its type and content, for every type the C API carries
+
+use cel_lua::{
+    cel_value_free, from_c_value, to_owned_c_value, CelBytesValue, CelListValue, CelMapValue,
+    CelOptionalValue, CelStringValue, CelTimeValue, CelValue, CelValueData, CelValueType,
+};
+use proptest::collection::{btree_map, vec};
+use proptest::prelude::*;
+use std::collections::BTreeMap;
+use std::mem::ManuallyDrop;
+
+/// 0001-01-01T00:00:00Z and 9999-12-31T23:59:59Z
+const TIMESTAMP_SECONDS: std::ops::RangeInclusive<i64> = -62_135_596_800..=253_402_300_799;
+const MAX_DURATION_SECONDS: i64 = 315_576_000_000;
+
+/// A value to build as a `CelValue`
+#[derive(Debug, Clone)]
+enum Shape {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Uint(u64),
+    Double(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Timestamp(i64, i32),
+    Duration(i64, i32),
+    BigInt(i128),
+    /// Mantissa and scale
+    Decimal(i64, u32),
+    Type(String),
+    List(Vec<Shape>),
+    Map(BTreeMap<KeyShape, Shape>),
+    Optional(Option<Box<Shape>>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum KeyShape {
+    Int(i64),
+    Uint(u64),
+    Bool(bool),
+    String(String),
+}
+
+fn double() -> impl Strategy<Value = f64> {
+    prop_oneof![
+        any::<f64>(),
+        Just(0.0),
+        Just(-0.0),
+        Just(f64::INFINITY),
+        Just(f64::NEG_INFINITY),
+        Just(f64::NAN),
+        Just(f64::MIN),
+        Just(f64::MAX),
+        Just(f64::MIN_POSITIVE),
+        Just(f64::EPSILON),
+        // The smallest subnormal
+        Just(f64::from_bits(1)),
+    ]
+}
+
+fn uint() -> impl Strategy<Value = u64> {
+    prop_oneof![
+        any::<u64>(),
+        (i64::MAX.unsigned_abs() + 1)..=u64::MAX,
+        Just(u64::MAX)
+    ]
+}
+
+fn string() -> impl Strategy<Value = String> {
+    prop_oneof![
+        any::<String>(),
+        "[a-z]{0,8}",
+        "[äöü€ß日本語🦀\\x{0}\\x{feff}]{1,8}"
+    ]
+}
+
+fn key() -> impl Strategy<Value = KeyShape> {
+    prop_oneof![
+        any::<i64>().prop_map(KeyShape::Int),
+        uint().prop_map(KeyShape::Uint),
+        any::<bool>().prop_map(KeyShape::Bool),
+        string().prop_map(KeyShape::String),
+    ]
+}
+
+fn duration() -> impl Strategy<Value = (i64, i32)> {
+    (-MAX_DURATION_SECONDS..=MAX_DURATION_SECONDS, 0..1_000_000_000i32).prop_map(
+        |(seconds, nanos)| {
+            // Nanos take the sign of seconds
+            if seconds < 0 {
+                (seconds, -nanos)
+            } else {
+                (seconds, nanos)
+            }
+        },
+    )
+}
+
+fn shape() -> impl Strategy<Value = Shape> {
+    let leaf = prop_oneof![
+        Just(Shape::Null),
+        any::<bool>().prop_map(Shape::Bool),
+        prop_oneof![any::<i64>(), Just(i64::MIN), Just(i64::MAX)].prop_map(Shape::Int),
+        uint().prop_map(Shape::Uint),
+        double().prop_map(Shape::Double),
+        string().prop_map(Shape::String),
+        vec(any::<u8>(), 0..16).prop_map(Shape::Bytes),
+        (TIMESTAMP_SECONDS, 0..1_000_000_000i32)
+            .prop_map(|(seconds, nanos)| Shape::Timestamp(seconds, nanos)),
+        duration().prop_map(|(seconds, nanos)| Shape::Duration(seconds, nanos)),
+        any::<i128>().prop_map(Shape::BigInt),
+        (any::<i64>(), 0..=28u32).prop_map(|(mantissa, scale)| Shape::Decimal(mantissa, scale)),
+        "[a-z][a-z0-9_.]{0,12}".prop_map(Shape::Type),
+    ];
+    leaf.prop_recursive(4, 32, 4, |inner| {
+        prop_oneof![
+            vec(inner.clone(), 0..4).prop_map(Shape::List),
+            btree_map(key(), inner.clone(), 0..4).prop_map(Shape::Map),
+            proptest::option::of(inner.prop_map(Box::new)).prop_map(Shape::Optional),
+        ]
+    })
+}
+
+/// The text `Display` gives a decimal with `mantissa` and `scale`
+fn decimal_text(mantissa: i64, scale: u32) -> String {
+    let sign = if mantissa < 0 { "-" } else { "" };
+    let magnitude = u128::from(mantissa.unsigned_abs());
+    if scale == 0 {
+        return format!("{sign}{magnitude}");
+    }
+    let unit = 10_u128.pow(scale);
+    let places = scale as usize;
+    format!("{sign}{}.{:0places$}", magnitude / unit, magnitude % unit)
+}
+
+fn string_data(text: &[u8]) -> CelValueData {
+    let (ptr, len) = (text.as_ptr(), text.len());
+    CelValueData {
+        string_val: ManuallyDrop::new(CelStringValue { ptr, len }),
+    }
+}
+
+fn bytes_data(bytes: &[u8]) -> CelValueData {
+    let (ptr, len) = (bytes.as_ptr(), bytes.len());
+    CelValueData {
+        bytes_val: ManuallyDrop::new(CelBytesValue { ptr, len }),
+    }
+}
+
+fn list_data(items: *const CelValue, len: usize) -> CelValueData {
+    CelValueData {
+        list_val: ManuallyDrop::new(CelListValue { items, len }),
+    }
+}
+
+fn map_data(keys: *const CelValue, values: *const CelValue, len: usize) -> CelValueData {
+    CelValueData {
+        map_val: ManuallyDrop::new(CelMapValue { keys, values, len }),
+    }
+}
+
+fn optional_data(value: *const CelValue) -> CelValueData {
+    let optional = CelOptionalValue {
+        has_value: !value.is_null(),
+        value,
+    };
+    CelValueData {
+        optional_val: ManuallyDrop::new(optional),
+    }
+}
+
+const fn time(seconds: i64, nanos: i32) -> CelTimeValue {
+    CelTimeValue { seconds, nanos }
+}
+
+/// The buffers built values point into, kept alive while they are used
+#[derive(Default)]
+struct Arena {
+    bytes: Vec<Vec<u8>>,
+    values: Vec<Vec<CelValue>>,
+}
+
+impl Arena {
+    fn text(&mut self, text: &[u8]) -> CelValueData {
+        let text = text.to_vec();
+        let data = string_data(&text);
+        self.bytes.push(text);
+        data
+    }
+
+    fn array(&mut self, values: Vec<CelValue>) -> (*const CelValue, usize) {
+        let array = (values.as_ptr(), values.len());
+        self.values.push(values);
+        array
+    }
+
+    fn key(&mut self, key: &KeyShape) -> CelValue {
+        match key {
+            KeyShape::Int(i) => self.build(&Shape::Int(*i)),
+            KeyShape::Uint(u) => self.build(&Shape::Uint(*u)),
+            KeyShape::Bool(b) => self.build(&Shape::Bool(*b)),
+            KeyShape::String(s) => self.build(&Shape::String(s.clone())),
+        }
+    }
+
+    fn build(&mut self, shape: &Shape) -> CelValue {
+        let (value_type, data) = match shape {
+            Shape::Null => (CelValueType::Null, CelValueData { int_val: 0 }),
+            Shape::Bool(b) => (CelValueType::Bool, CelValueData { bool_val: *b }),
+            Shape::Int(i) => (CelValueType::Int, CelValueData { int_val: *i }),
+            Shape::Uint(u) => (CelValueType::Uint, CelValueData { uint_val: *u }),
+            Shape::Double(f) => (CelValueType::Double, CelValueData { double_val: *f }),
+            Shape::String(s) => (CelValueType::String, self.text(s.as_bytes())),
+            Shape::Type(name) => (CelValueType::Type, self.text(name.as_bytes())),
+            Shape::BigInt(n) => (CelValueType::BigInt, self.text(n.to_string().as_bytes())),
+            Shape::Decimal(mantissa, scale) => {
+                let text = decimal_text(*mantissa, *scale);
+                (CelValueType::Decimal, self.text(text.as_bytes()))
+            }
+            Shape::Bytes(b) => {
+                let bytes = b.clone();
+                let data = bytes_data(&bytes);
+                self.bytes.push(bytes);
+                (CelValueType::Bytes, data)
+            }
+            Shape::Timestamp(seconds, nanos) => {
+                let timestamp_val = time(*seconds, *nanos);
+                (CelValueType::Timestamp, CelValueData { timestamp_val })
+            }
+            Shape::Duration(seconds, nanos) => {
+                let duration_val = time(*seconds, *nanos);
+                (CelValueType::Duration, CelValueData { duration_val })
+            }
+            Shape::List(items) => {
+                let items = items.iter().map(|item| self.build(item)).collect();
+                let (items, len) = self.array(items);
+                (CelValueType::List, list_data(items, len))
+            }
+            Shape::Map(entries) => {
+                let keys = entries.keys().map(|key| self.key(key)).collect();
+                let values = entries.values().map(|value| self.build(value)).collect();
+                let (keys, len) = self.array(keys);
+                let (values, _) = self.array(values);
+                (CelValueType::Map, map_data(keys, values, len))
+            }
+            Shape::Optional(inner) => {
+                let value = match inner {
+                    Some(inner) => {
+                        let inner = self.build(inner);
+                        self.array(vec![inner]).0
+                    }
+                    None => std::ptr::null(),
+                };
+                (CelValueType::Optional, optional_data(value))
+            }
+        };
+        CelValue { value_type, data }
+    }
+}
+
+unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(ptr, len)
+    }
+}
+
+unsafe fn values<'a>(ptr: *const CelValue, len: usize) -> &'a [CelValue] {
+    if len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(ptr, len)
+    }
+}
+
+/// Whether `a` and `b` have the same type and content, with map entries in any order
+/// and every NaN the same
+unsafe fn same(a: &CelValue, b: &CelValue) -> bool {
+    if a.value_type != b.value_type {
+        return false;
+    }
+    match a.value_type {
+        CelValueType::Null => true,
+        CelValueType::Bool => a.data.bool_val == b.data.bool_val,
+        CelValueType::Int => a.data.int_val == b.data.int_val,
+        CelValueType::Uint => a.data.uint_val == b.data.uint_val,
+        CelValueType::Double => {
+            let (a, b) = (a.data.double_val, b.data.double_val);
+            (a.is_nan() && b.is_nan()) || a.to_bits() == b.to_bits()
+        }
+        CelValueType::String
+        | CelValueType::Type
+        | CelValueType::BigInt
+        | CelValueType::Decimal => {
+            let (a, b) = (&*a.data.string_val, &*b.data.string_val);
+            bytes(a.ptr, a.len) == bytes(b.ptr, b.len)
+        }
+        CelValueType::Bytes => {
+            let (a, b) = (&*a.data.bytes_val, &*b.data.bytes_val);
+            bytes(a.ptr, a.len) == bytes(b.ptr, b.len)
+        }
+        CelValueType::Timestamp => a.data.timestamp_val == b.data.timestamp_val,
+        CelValueType::Duration => a.data.duration_val == b.data.duration_val,
+        CelValueType::List => {
+            let (a, b) = (&*a.data.list_val, &*b.data.list_val);
+            let (a, b) = (values(a.items, a.len), values(b.items, b.len));
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b))
+        }
+        CelValueType::Map => {
+            let (a, b) = (&*a.data.map_val, &*b.data.map_val);
+            let (a_keys, a_values) = (values(a.keys, a.len), values(a.values, a.len));
+            let (b_keys, b_values) = (values(b.keys, b.len), values(b.values, b.len));
+            a.len == b.len
+                && a_keys.iter().zip(a_values).all(|(key, value)| {
+                    b_keys
+                        .iter()
+                        .position(|other| same(key, other))
+                        .is_some_and(|i| same(value, &b_values[i]))
+                })
+        }
+        CelValueType::Optional => {
+            let (a, b) = (&*a.data.optional_val, &*b.data.optional_val);
+            a.has_value == b.has_value && (!a.has_value || same(&*a.value, &*b.value))
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn test_c_value_round_trip(shape in shape()) {
+        let mut arena = Arena::default();
+        let value = arena.build(&shape);
+
+        unsafe {
+            let converted = from_c_value(&value);
+            prop_assert!(converted.is_ok(), "{shape:?} did not convert: {converted:?}");
+            let back = to_owned_c_value(&converted.unwrap());
+            prop_assert!(back.is_ok(), "{shape:?} did not convert back: {:?}", back.err());
+            let mut back = back.unwrap();
+            let kept = same(&value, &back);
+            cel_value_free(&mut back);
+            prop_assert!(kept, "{shape:?} changed in the round trip");
+        }
+    }
+}
+
+#[test]
+fn test_c_value_round_trip_edges() {
+    let shapes = [
+        Shape::Uint(u64::MAX),
+        Shape::Uint(i64::MAX.unsigned_abs() + 1),
+        Shape::Double(-0.0),
+        Shape::Double(f64::NAN),
+        Shape::Double(f64::NEG_INFINITY),
+        Shape::String("naïve 日本語 🦀".to_string()),
+        Shape::List(vec![Shape::Uint(u64::MAX), Shape::Double(f64::INFINITY)]),
+        Shape::Map(BTreeMap::from([(KeyShape::Uint(u64::MAX), Shape::String("ü".to_string()))])),
+        Shape::Timestamp(*TIMESTAMP_SECONDS.start(), 0),
+        Shape::Timestamp(*TIMESTAMP_SECONDS.end(), 999_999_999),
+        Shape::Duration(-MAX_DURATION_SECONDS, -999_999_999),
+        Shape::BigInt(i128::MIN),
+        Shape::Decimal(-1, 28),
+        Shape::Optional(Some(Box::new(Shape::Optional(None)))),
+    ];
+    for shape in shapes {
+        let mut arena = Arena::default();
+        let value = arena.build(&shape);
+        unsafe {
+            let mut back = to_owned_c_value(&from_c_value(&value).unwrap()).unwrap();
+            assert!(same(&value, &back), "{shape:?} changed in the round trip");
+            cel_value_free(&mut back);
+        }
+    }
+}